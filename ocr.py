@@ -735,6 +735,9 @@ class OCRFactory:
             }
             env_var = env_vars[provider_name]
             api_key = os.environ.get(env_var)
+            if not api_key and provider_name == "mathpix":
+                env_var = "MATHPIX_APP_KEY"
+                api_key = os.environ.get(env_var)
             
             if not api_key and provider_name != "google":
                 raise ValueError(
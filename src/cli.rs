@@ -1,9 +1,16 @@
 use clap::{Parser, Subcommand};
 use log::{error, info, warn};
-use std::collections::BTreeSet;
+use std::path::Path;
 
 use crate::config::Config;
-use crate::services::{FileService, MistralOcrProvider, OcrProvider};
+use crate::services::cache_bundle::{export_book, import_bundle, BUNDLE_EXTENSION};
+use crate::services::database::Database;
+use crate::services::ocr_confidence::provider_confidence;
+use crate::services::markdown_import::{import_markdown, MarkdownImportRequest};
+use crate::services::solution_cleanup::{plan_cleanup, ArchiveReason, DEFAULT_SIMILARITY_THRESHOLD};
+use crate::services::{default_ocr_provider, migrate_artifact_names, migrate_previews, FileService, NO_OCR_PROVIDER};
+use crate::utils::page_range::parse_page_ranges;
+use crate::utils::slug::book_slug;
 
 #[derive(Parser)]
 #[command(name = "booker")]
@@ -22,7 +29,7 @@ pub enum Commands {
     OcrMarkdown {
         /// PDF filename
         file: String,
-        /// Page number or range (e.g., "1", "1-5", "1,3,5", "1-e" for all)
+        /// Page number or range (e.g., "1", "1-5", "1,3,5", "1-e" for all, "odd", "10-e/even")
         page: String,
     },
 
@@ -30,7 +37,7 @@ pub enum Commands {
     OcrRun {
         /// PDF filename
         file: String,
-        /// Page number or range (e.g., "1", "1-5", "1,3,5", "1-e" for all)
+        /// Page number or range (e.g., "1", "1-5", "1,3,5", "1-e" for all, "odd", "10-e/even")
         page: String,
     },
 
@@ -39,32 +46,93 @@ pub enum Commands {
         /// PDF filename
         file: String,
     },
+
+    /// Import problems from a markdown file (## Задача 5, а) ...)
+    ImportMd {
+        /// Markdown file path
+        file: String,
+        /// Target book id
+        #[arg(long)]
+        book: String,
+        /// Target chapter number
+        #[arg(long)]
+        chapter: u32,
+        /// Chapter title (defaults to the first `#` heading)
+        #[arg(long)]
+        title: Option<String>,
+    },
+
+    /// Rename previews, OCR caches and book ids created before slugs to their slugged names
+    MigrateSlugs {
+        /// Only print what would be renamed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Rename previews from every historical naming scheme to the canonical one
+    /// and list the ones that belong to no book
+    MigratePreviews {
+        /// Only print what would be renamed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Archive redundant solutions (provider re-runs, near-identical content),
+    /// keeping the best of each; only reports unless --apply is given
+    CleanupSolutions {
+        /// Limit to one book id
+        #[arg(long)]
+        book: Option<String>,
+        /// Content similarity (0.5-1.0) above which two solutions are duplicates
+        #[arg(long, default_value_t = DEFAULT_SIMILARITY_THRESHOLD)]
+        threshold: f64,
+        /// Archive the redundant solutions instead of only listing them
+        #[arg(long)]
+        apply: bool,
+    },
+
+    /// Move OCR results between machines as a bundle of a book's OCR cache,
+    /// page rows and previews
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Write a book's OCR cache, page rows and previews to a bundle
+    Export {
+        /// Book id or file name
+        book: String,
+        /// Bundle path (defaults to `{book}.bookers-cache.tar.zst`)
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+
+    /// Verify a bundle and add its files and page rows to this machine
+    Import {
+        /// Bundle path
+        file: String,
+    },
 }
 
 pub fn handle_ocr_markdown(file: &str, page: &str) {
     let config = Config::new();
-    let file_service = FileService::new(
-        config.resources_dir.clone(),
-        config.preview_dir.clone(),
-        config.ocr_cache_dir.clone(),
-    );
+    let file_service = FileService::from_config(&config);
 
-    let total_pages = file_service
-        .get_pdf_metadata(file)
-        .ok()
-        .and_then(|meta| meta.get("Pages").and_then(|v| v.parse::<u32>().ok()))
-        .unwrap_or(1);
+    let total_pages = file_service.get_pdf_page_count(file).unwrap_or(0);
 
-    let page_range = parse_page_ranges(page, total_pages);
+    let page_range = match parse_page_ranges(page, total_pages) {
+        Ok(pages) => pages,
+        Err(e) => {
+            eprintln!("Invalid page selection: {}", e);
+            return;
+        }
+    };
 
     for p in page_range {
-        let cache_path = config.ocr_cache_dir.join(format!(
-            "{}_{}.ocr_cache",
-            file.replace('/', "_"),
-            p
-        ));
-
-        if !cache_path.exists() {
+        let Some(data) = file_service.get_ocr_cache(file, p) else {
             warn!("No OCR cache for file {} page {}. Running OCR...", file, p);
             match run_ocr_for_file_page(file, p, &config) {
                 Ok(result) => {
@@ -76,10 +144,9 @@ pub fn handle_ocr_markdown(file: &str, page: &str) {
                 }
             }
             continue;
-        }
+        };
 
         info!("Found OCR cache for file {} page {}", file, p);
-        let data = std::fs::read_to_string(&cache_path).expect("Failed to read ocr_cache file");
         let json: serde_json::Value = serde_json::from_str(&data).expect("Invalid JSON");
 
         if let Some(entry) = json.as_array().and_then(|arr| arr.first()) {
@@ -98,19 +165,17 @@ pub fn handle_ocr_markdown(file: &str, page: &str) {
 
 pub fn handle_ocr_run(file: &str, page: &str) {
     let config = Config::new();
-    let file_service = FileService::new(
-        config.resources_dir.clone(),
-        config.preview_dir.clone(),
-        config.ocr_cache_dir.clone(),
-    );
+    let file_service = FileService::from_config(&config);
 
-    let total_pages = file_service
-        .get_pdf_metadata(file)
-        .ok()
-        .and_then(|meta| meta.get("Pages").and_then(|v| v.parse::<u32>().ok()))
-        .unwrap_or(1);
+    let total_pages = file_service.get_pdf_page_count(file).unwrap_or(0);
 
-    let page_range = parse_page_ranges(page, total_pages);
+    let page_range = match parse_page_ranges(page, total_pages) {
+        Ok(pages) => pages,
+        Err(e) => {
+            eprintln!("Invalid page selection: {}", e);
+            return;
+        }
+    };
 
     for p in page_range {
         match run_ocr_for_file_page(file, p, &config) {
@@ -127,18 +192,34 @@ pub fn handle_ocr_run(file: &str, page: &str) {
 
 pub fn handle_pdf_info(file: &str) {
     let config = Config::new();
-    let file_service = FileService::new(
-        config.resources_dir.clone(),
-        config.preview_dir.clone(),
-        config.ocr_cache_dir.clone(),
-    );
+    let file_service = FileService::from_config(&config);
 
     match file_service.get_pdf_metadata(file) {
         Ok(metadata) => {
             println!("PDF metadata for '{}':", file);
-            for (k, v) in &metadata {
-                println!("{:20}: {}", k, v);
+            println!("{:20}: {}", "Pages", metadata.pages);
+            for (key, value) in [
+                ("Title", &metadata.title),
+                ("Author", &metadata.author),
+                ("Subject", &metadata.subject),
+                ("Keywords", &metadata.keywords),
+                ("Creator", &metadata.creator),
+                ("Producer", &metadata.producer),
+                ("PDF version", &metadata.pdf_version),
+            ] {
+                if let Some(value) = value {
+                    println!("{:20}: {}", key, value);
+                }
+            }
+            for (key, value) in [("Created", metadata.creation_date), ("Modified", metadata.modification_date)] {
+                if let Some(value) = value {
+                    println!("{:20}: {}", key, value.to_rfc3339());
+                }
+            }
+            if let Some(size) = &metadata.page_size {
+                println!("{:20}: {} x {} pts", "Page size", size.width, size.height);
             }
+            println!("{:20}: {}", "Encrypted", if metadata.encrypted { "yes" } else { "no" });
         }
         Err(e) => {
             eprintln!("Error getting metadata: {}", e);
@@ -146,21 +227,214 @@ pub fn handle_pdf_info(file: &str) {
     }
 }
 
-fn run_ocr_for_file_page(file: &str, page: u32, config: &Config) -> Result<String, String> {
-    let file_service = FileService::new(
-        config.resources_dir.clone(),
-        config.preview_dir.clone(),
-        config.ocr_cache_dir.clone(),
+pub fn handle_import_md(file: &str, book: &str, chapter: u32, title: Option<&str>) {
+    let markdown = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file, e);
+            return;
+        }
+    };
+
+    let request = MarkdownImportRequest {
+        book_id: book.to_string(),
+        chapter_num: chapter,
+        chapter_title: title.map(str::to_string),
+        markdown,
+    };
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let result = rt.block_on(async {
+        let db = Database::new(&crate::server::database_url()).await?;
+        import_markdown(&db, &request).await
+    });
+
+    match result {
+        Ok(summary) => {
+            println!(
+                "Imported {} problems ({} sub-problems, {} theory blocks) into {}",
+                summary.problems_imported,
+                summary.sub_problems_imported,
+                summary.theory_blocks_imported,
+                summary.chapter_id
+            );
+            if !summary.problem_numbers.is_empty() {
+                println!("Problems: {}", summary.problem_numbers.join(", "));
+            }
+            for block in &summary.unclassified {
+                warn!("Skipped text outside problems: {}", block.trim());
+            }
+        }
+        Err(e) => {
+            eprintln!("Import failed: {}", e);
+        }
+    }
+}
+
+pub fn handle_migrate_slugs(dry_run: bool) {
+    let config = Config::new();
+    let verb = if dry_run { "Would rename" } else { "Renamed" };
+
+    for dir in [&config.preview_dir, &config.ocr_cache_dir] {
+        match migrate_artifact_names(dir, dry_run) {
+            Ok(renamed) => {
+                for (old, new) in &renamed {
+                    println!("{} {} -> {}", verb, old, new);
+                }
+                println!("{}: {} files", dir.display(), renamed.len());
+            }
+            Err(e) => eprintln!("Failed to migrate {}: {}", dir.display(), e),
+        }
+    }
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let result: anyhow::Result<usize> = rt.block_on(async {
+        let db = Database::new(&crate::server::database_url()).await?;
+        let books = db.list_books().await?;
+        let mut renamed = 0;
+        for book in &books {
+            let slug = book_slug(&book.id);
+            if slug == book.id {
+                continue;
+            }
+            if books.iter().any(|b| b.id == slug) {
+                warn!("Book {} already exists, not renaming {}", slug, book.id);
+                continue;
+            }
+            if !dry_run {
+                db.rename_book(&book.id, &slug).await?;
+            }
+            println!("{} book {} -> {}", verb, book.id, slug);
+            renamed += 1;
+        }
+        Ok(renamed)
+    });
+
+    match result {
+        Ok(renamed) => println!("Books: {}", renamed),
+        Err(e) => eprintln!("Failed to migrate book ids: {}", e),
+    }
+}
+
+pub fn handle_migrate_previews(dry_run: bool) {
+    let config = Config::new();
+    let migration = match migrate_previews(&config.preview_dir, &config.resources_dir, dry_run) {
+        Ok(migration) => migration,
+        Err(e) => {
+            eprintln!("Failed to migrate {}: {}", config.preview_dir.display(), e);
+            return;
+        }
+    };
+
+    let verb = if dry_run { "Would rename" } else { "Renamed" };
+    for (old, new) in &migration.renamed {
+        println!("{} {} -> {}", verb, old, new);
+    }
+    for name in &migration.conflicts {
+        println!("Conflict (canonical name exists): {}", name);
+    }
+    for name in &migration.orphans {
+        println!("Orphan: {}", name);
+    }
+    println!(
+        "{}: {} renamed, {} conflicts, {} orphans",
+        config.preview_dir.display(),
+        migration.renamed.len(),
+        migration.conflicts.len(),
+        migration.orphans.len()
     );
+}
+
+pub fn handle_cleanup_solutions(book: Option<&str>, threshold: f64, apply: bool) {
+    if !(0.5..=1.0).contains(&threshold) {
+        eprintln!("--threshold must be between 0.5 and 1.0");
+        return;
+    }
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let result: anyhow::Result<()> = rt.block_on(async {
+        let db = Database::new(&crate::server::database_url()).await?;
+        let solutions = db.get_solutions_for_cleanup(book).await?;
+        let plan = plan_cleanup(&solutions, threshold);
+
+        for entry in &plan.archive {
+            let detail = match &entry.reason {
+                ArchiveReason::SameProvider => "same provider".to_string(),
+                ArchiveReason::NearDuplicate { similarity } => format!("{:.0}% similar", similarity * 100.0),
+            };
+            println!(
+                "{}: archive {} ({}, {}), keep {}",
+                entry.problem_id, entry.solution_id, entry.provider, detail, entry.kept_id
+            );
+        }
+        for rename in &plan.renames {
+            println!("rename provider of {}: {} -> {}", rename.solution_id, rename.from, rename.to);
+        }
+        println!(
+            "Scanned {} solutions: {} redundant in {} problems, {} provider renames",
+            plan.solutions_scanned,
+            plan.archive.len(),
+            plan.problems_affected,
+            plan.renames.len()
+        );
+
+        if apply {
+            let (archived, renamed) = db.apply_solution_cleanup(&plan).await?;
+            println!("Archived {} solutions, renamed {} providers", archived, renamed);
+        } else {
+            println!("Dry run; pass --apply to archive");
+        }
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        eprintln!("Solution cleanup failed: {}", e);
+    }
+}
+
+pub fn handle_cache(command: &CacheCommands) {
+    let config = Config::new();
+    let file_service = FileService::from_config(&config);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let result: anyhow::Result<()> = rt.block_on(async {
+        let db = Database::new(&crate::server::database_url()).await?;
+        match command {
+            CacheCommands::Export { book, output } => {
+                let output = output
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.{}", book_slug(book), BUNDLE_EXTENSION));
+                let summary = export_book(&db, &file_service, book, Path::new(&output)).await?;
+                println!(
+                    "Exported {} pages, {} OCR caches, {} previews and {} OCR images ({} bytes) to {}",
+                    summary.pages, summary.ocr_caches, summary.previews, summary.ocr_images, summary.bytes, output
+                );
+            }
+            CacheCommands::Import { file } => {
+                let summary = import_bundle(&db, &file_service, Path::new(file)).await?;
+                println!(
+                    "Imported {} pages, {} OCR caches, {} previews and {} OCR images ({} already up to date)",
+                    summary.pages, summary.ocr_caches, summary.previews, summary.ocr_images, summary.unchanged
+                );
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        eprintln!("Cache bundle failed: {}", e);
+    }
+}
+
+fn run_ocr_for_file_page(file: &str, page: u32, config: &Config) -> Result<String, String> {
+    let file_service = FileService::from_config(config);
 
     let preview_path = file_service
         .generate_preview(file, page)
         .map_err(|e| format!("Failed to generate preview: {}", e))?;
 
-    let api_key = std::env::var("MISTRAL_API_KEY")
-        .map_err(|_| "MISTRAL_API_KEY not set".to_string())?;
-
-    let provider = MistralOcrProvider::new(api_key);
+    let provider = default_ocr_provider(config)
+        .ok_or_else(|| NO_OCR_PROVIDER.to_string())?;
     let rt = tokio::runtime::Runtime::new().unwrap();
 
     let ocr_result = rt.block_on(provider.extract_text(
@@ -171,6 +445,9 @@ fn run_ocr_for_file_page(file: &str, page: u32, config: &Config) -> Result<Strin
 
     match ocr_result {
         Ok((ocr_text, ocr_payload)) => {
+            if let Some(confidence) = provider_confidence(&ocr_payload) {
+                info!("Provider confidence for page {}: {:.2}", page, confidence);
+            }
             if let Err(e) = file_service.save_ocr_cache(file, page, provider.provider_id(), ocr_payload) {
                 error!("Failed to save OCR cache: {}", e);
             }
@@ -179,30 +456,3 @@ fn run_ocr_for_file_page(file: &str, page: u32, config: &Config) -> Result<Strin
         Err(e) => Err(format!("Failed to perform OCR: {}", e)),
     }
 }
-
-fn parse_page_ranges(range_str: &str, total_pages: u32) -> BTreeSet<u32> {
-    let mut pages = BTreeSet::new();
-
-    for part in range_str.split(',') {
-        let part = part.trim();
-        if part.is_empty() {
-            continue;
-        }
-
-        if let Some((start, end)) = part.split_once('-') {
-            let start = start.trim().parse::<u32>().unwrap_or(1);
-            let end = if end.trim() == "e" {
-                total_pages
-            } else {
-                end.trim().parse::<u32>().unwrap_or(start)
-            };
-            for p in start..=end {
-                pages.insert(p);
-            }
-        } else if let Ok(p) = part.parse::<u32>() {
-            pages.insert(p);
-        }
-    }
-
-    pages
-}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::utils::command;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub host: String,
@@ -7,7 +9,112 @@ pub struct Config {
     pub resources_dir: PathBuf,
     pub preview_dir: PathBuf,
     pub ocr_cache_dir: PathBuf,
+    /// Job artifacts (error CSVs, reports), one directory per job
+    pub jobs_dir: PathBuf,
     pub base_url: String,
+    /// Second OCR provider used by the periodic quality audit (disabled when unset)
+    pub ocr_audit_provider: Option<String>,
+    /// Pages sampled per book on each audit run
+    pub ocr_audit_sample_size: usize,
+    pub ocr_audit_interval_hours: u64,
+    /// Webhook the weekly digest is posted to (`DIGEST_WEBHOOK_URL`, none when unset)
+    pub digest_webhook_url: Option<String>,
+    /// Days finished jobs are kept in the job history
+    pub job_history_retention_days: u32,
+    /// Hours the response of a request with an `Idempotency-Key` is replayed
+    pub idempotency_ttl_hours: u32,
+    /// Seconds before an external command (pdftoppm, pdfinfo, ...) is killed
+    pub command_timeout_secs: u64,
+    /// Estimated USD cost above which a batch job needs `confirm: true`
+    /// (`BATCH_BUDGET_USD`)
+    pub batch_budget_usd: f64,
+    /// Poppler binaries to run instead of the ones on PATH (`PDFTOPPM_PATH`,
+    /// `PDFINFO_PATH`, `PDFTOTEXT_PATH`), e.g. `C:\poppler\Library\bin\pdftoppm.exe`
+    pub pdftoppm_path: Option<PathBuf>,
+    pub pdfinfo_path: Option<PathBuf>,
+    pub pdftotext_path: Option<PathBuf>,
+    /// Local OCR binary for the `tesseract` provider (`TESSERACT_PATH`, PATH otherwise)
+    pub tesseract_path: Option<PathBuf>,
+    /// Tesseract language packs, `+`-separated (`TESSERACT_LANGS`, default `rus+eng`)
+    pub tesseract_languages: String,
+    /// Rasteriser for page previews (`RENDER_BACKEND=poppler|pdfium`)
+    pub render_backend: RenderBackend,
+    /// Resolution page previews are rendered at (`RENDER_DPI`)
+    pub render_dpi: u32,
+    /// Seconds before an OCR or AI provider call is abandoned
+    pub provider_timeout_secs: u64,
+    /// Proxy for all outbound calls (`PROXY_URL`, http(s):// or socks5://).
+    /// When unset, `HTTP_PROXY`/`HTTPS_PROXY`/`NO_PROXY` apply as usual.
+    pub proxy_url: Option<String>,
+    /// Letters and digits a page's PDF text layer needs to be used instead of
+    /// paid OCR (`TEXT_LAYER_MIN_CHARS`, 0 always calls the provider)
+    pub text_layer_min_chars: usize,
+    /// Rebuild templates when a file in them changes (`TEMPLATE_RELOAD=1`,
+    /// on by default in debug builds)
+    pub template_reload: bool,
+    /// Deployment theme: template overrides and CSS variables (`THEME_DIR`)
+    pub theme_dir: Option<PathBuf>,
+    /// Disable cloud OCR/AI providers; OCR falls back to the PDF text layer
+    /// and parsing to the regex parser (`OFFLINE_MODE=1`)
+    pub offline: bool,
+    /// Files attached to solutions, when they are stored locally (`ATTACHMENTS_DIR`)
+    pub attachments_dir: PathBuf,
+    /// Bucket for attachments with `ATTACHMENT_STORAGE=s3`; local storage otherwise
+    pub attachment_s3: Option<S3Config>,
+    /// Key share links are signed with (`SHARE_LINK_SECRET`); one is
+    /// generated and kept in the database when unset
+    pub share_link_secret: Option<String>,
+    /// Credentials of the `mathpix` OCR provider; it is unavailable without them
+    pub mathpix: Option<MathpixConfig>,
+}
+
+/// Mathpix app credentials, sent as its `app_id`/`app_key` headers
+#[derive(Debug, Clone)]
+pub struct MathpixConfig {
+    pub app_id: String,
+    pub app_key: String,
+}
+
+impl MathpixConfig {
+    /// `MATHPIX_APP_ID` and `MATHPIX_APP_KEY` (or `MATHPIX_API_KEY`, the
+    /// name `ocr.py` reads); `None` unless both are set
+    fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Some(Self {
+            app_id: var("MATHPIX_APP_ID")?,
+            app_key: var("MATHPIX_APP_KEY").or_else(|| var("MATHPIX_API_KEY"))?,
+        })
+    }
+}
+
+/// S3 (or S3-compatible, e.g. MinIO) bucket, addressed path-style as
+/// `{endpoint}/{bucket}/{key}`
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl S3Config {
+    /// `S3_BUCKET`, `S3_REGION` (us-east-1), `S3_ENDPOINT` (AWS for the
+    /// region) and the usual `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY`;
+    /// `None` unless the bucket and both keys are set
+    fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let region = var("S3_REGION").unwrap_or_else(|| "us-east-1".to_string());
+        Some(Self {
+            bucket: var("S3_BUCKET")?,
+            endpoint: var("S3_ENDPOINT")
+                .map(|e| e.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region)),
+            region,
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+        })
+    }
 }
 
 impl Default for Config {
@@ -31,14 +138,160 @@ impl Default for Config {
                 std::env::var("OCR_CACHE_DIR")
                     .unwrap_or_else(|_| "./resources/.ocr_cache".to_string()),
             ),
+            jobs_dir: PathBuf::from(
+                std::env::var("JOBS_DIR").unwrap_or_else(|_| "./data/jobs".to_string()),
+            ),
             base_url: std::env::var("BASE_URL")
                 .unwrap_or_else(|_| format!("http://{}:{}", host, port)),
+            ocr_audit_provider: std::env::var("OCR_AUDIT_PROVIDER").ok().filter(|p| !p.is_empty()),
+            ocr_audit_sample_size: std::env::var("OCR_AUDIT_SAMPLE_SIZE")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(3),
+            ocr_audit_interval_hours: std::env::var("OCR_AUDIT_INTERVAL_HOURS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(24),
+            digest_webhook_url: std::env::var("DIGEST_WEBHOOK_URL").ok().filter(|u| !u.is_empty()),
+            job_history_retention_days: std::env::var("JOB_HISTORY_RETENTION_DAYS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(30),
+            idempotency_ttl_hours: std::env::var("IDEMPOTENCY_TTL_HOURS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|&hours| hours > 0)
+                .unwrap_or(24),
+            command_timeout_secs: std::env::var("COMMAND_TIMEOUT_SECS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(120),
+            batch_budget_usd: std::env::var("BATCH_BUDGET_USD")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|usd: &f64| usd.is_finite() && *usd >= 0.0)
+                .unwrap_or(1.0),
+            pdftoppm_path: env_path("PDFTOPPM_PATH"),
+            pdfinfo_path: env_path("PDFINFO_PATH"),
+            pdftotext_path: env_path("PDFTOTEXT_PATH"),
+            tesseract_path: env_path("TESSERACT_PATH"),
+            tesseract_languages: std::env::var("TESSERACT_LANGS")
+                .ok()
+                .filter(|langs| !langs.trim().is_empty())
+                .unwrap_or_else(|| "rus+eng".to_string()),
+            render_backend: std::env::var("RENDER_BACKEND")
+                .ok()
+                .and_then(|name| RenderBackend::from_name(&name))
+                .unwrap_or(RenderBackend::Poppler),
+            render_dpi: std::env::var("RENDER_DPI")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|dpi| (MIN_RENDER_DPI..=MAX_RENDER_DPI).contains(dpi))
+                .unwrap_or(DEFAULT_RENDER_DPI),
+            provider_timeout_secs: std::env::var("PROVIDER_TIMEOUT_SECS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(120),
+            proxy_url: std::env::var("PROXY_URL")
+                .or_else(|_| std::env::var("SOCKS_PROXY"))
+                .ok()
+                .filter(|p| !p.is_empty()),
+            text_layer_min_chars: std::env::var("TEXT_LAYER_MIN_CHARS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(200),
+            template_reload: std::env::var("TEMPLATE_RELOAD")
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(cfg!(debug_assertions)),
+            theme_dir: env_path("THEME_DIR"),
+            offline: std::env::var("OFFLINE_MODE")
+                .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes")),
+            attachments_dir: PathBuf::from(
+                std::env::var("ATTACHMENTS_DIR").unwrap_or_else(|_| "./data/attachments".to_string()),
+            ),
+            attachment_s3: std::env::var("ATTACHMENT_STORAGE")
+                .is_ok_and(|v| v.trim().eq_ignore_ascii_case("s3"))
+                .then(S3Config::from_env)
+                .flatten(),
+            share_link_secret: std::env::var("SHARE_LINK_SECRET").ok().filter(|s| !s.is_empty()),
+            mathpix: MathpixConfig::from_env(),
         }
     }
 }
 
+/// pdftoppm's default resolution
+pub const DEFAULT_RENDER_DPI: u32 = 150;
+pub const MIN_RENDER_DPI: u32 = 36;
+pub const MAX_RENDER_DPI: u32 = 600;
+
+/// How PDF pages are rasterised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// The `pdftoppm` binary
+    Poppler,
+    /// PDFium in-process, no external binary needed
+    #[cfg(feature = "pdfium")]
+    Pdfium,
+}
+
+impl RenderBackend {
+    /// Backend for a `RENDER_BACKEND` value; `pdfium` is only known to builds
+    /// with `--features pdfium`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "poppler" | "pdftoppm" => Some(Self::Poppler),
+            #[cfg(feature = "pdfium")]
+            "pdfium" => Some(Self::Pdfium),
+            _ => None,
+        }
+    }
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name).filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+/// Error returned by endpoints that need a cloud provider in offline mode
+pub const OFFLINE_ERROR: &str = "Offline mode is enabled; cloud OCR/AI providers are disabled";
+
 impl Config {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Apply the settings of external commands (timeout, binary paths) to
+    /// every [`crate::utils::CommandRunner`] in the process
+    pub fn configure_commands(&self) {
+        command::set_default_timeout(std::time::Duration::from_secs(self.command_timeout_secs));
+        for (program, path) in [
+            ("pdftoppm", &self.pdftoppm_path),
+            ("pdfinfo", &self.pdfinfo_path),
+            ("pdftotext", &self.pdftotext_path),
+        ] {
+            if let Some(path) = path {
+                command::set_program_path(program, path.clone());
+            }
+        }
+    }
+
+    pub fn provider_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.provider_timeout_secs.max(1))
+    }
+
+    /// Proxy variables for child processes (the Python OCR/parsing scripts)
+    pub fn proxy_env(&self) -> Vec<(&'static str, String)> {
+        self.proxy_url
+            .iter()
+            .flat_map(|proxy| [("HTTP_PROXY", proxy.clone()), ("HTTPS_PROXY", proxy.clone())])
+            .collect()
+    }
+
+    /// Mistral key for AI parsing/tagging; `None` in offline mode so callers
+    /// use their local fallbacks
+    pub fn mistral_api_key(&self) -> Option<String> {
+        if self.offline {
+            return None;
+        }
+        std::env::var("MISTRAL_API_KEY").ok().filter(|k| !k.is_empty())
+    }
 }
@@ -0,0 +1,446 @@
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use tera::Context;
+
+use crate::config::Config;
+use crate::handlers::preferences::page_preferences;
+use crate::models::Problem;
+use crate::services::answer_key::AnswerVerdict;
+use crate::services::assignment::{
+    assignment_results, grade_answer, normalize_student_name, parse_due_at, Assignment, Submission, SubmissionSource,
+};
+use crate::services::attachments::AttachmentStorage;
+use crate::services::book_settings::DEFAULT_OCR_PROVIDER;
+use crate::services::database::Database;
+use crate::services::latex_macros::BookMacros;
+use crate::services::templates::Templates;
+use crate::services::OcrService;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAssignmentRequest {
+    pub title: Option<String>,
+    pub instructions: Option<String>,
+    pub problem_ids: Vec<String>,
+    /// RFC 3339, or `YYYY-MM-DD` for the end of that day (UTC)
+    pub due_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TypedAnswer {
+    pub problem_id: String,
+    pub answer: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitAnswersRequest {
+    pub student_name: String,
+    pub answers: Vec<TypedAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhotoSubmissionQuery {
+    pub student_name: String,
+    pub problem_id: String,
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+fn assignment_json(assignment: &Assignment) -> serde_json::Value {
+    serde_json::json!({
+        "assignment": assignment,
+        "share_url": assignment.share_path(),
+    })
+}
+
+/// The assignment, or the response to send when it can't be loaded
+async fn load_assignment(db: &Database, assignment_id: &str) -> Result<Assignment, HttpResponse> {
+    match db.get_assignment(assignment_id).await {
+        Ok(Some(assignment)) => Ok(assignment),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Assignment not found"
+        }))),
+        Err(e) => Err(internal_error("get assignment", e)),
+    }
+}
+
+/// The assignment behind a share link, or the response to send
+async fn load_shared(db: &Database, share_token: &str) -> Result<Assignment, HttpResponse> {
+    match db.get_assignment_by_token(share_token).await {
+        Ok(Some(assignment)) => Ok(assignment),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Assignment not found"
+        }))),
+        Err(e) => Err(internal_error("get assignment", e)),
+    }
+}
+
+/// Problems of an assignment in its order; deleted ones are skipped
+async fn assignment_problems(db: &Database, assignment: &Assignment) -> anyhow::Result<Vec<Problem>> {
+    let mut problems = Vec::with_capacity(assignment.problem_ids.len());
+    for problem_id in &assignment.problem_ids {
+        if let Some(problem) = db.get_problem(problem_id).await? {
+            problems.push(problem);
+        }
+    }
+    Ok(problems)
+}
+
+/// What students see of a problem: no extracted answer, no solutions
+fn student_problem_json(problem: &Problem) -> serde_json::Value {
+    serde_json::json!({
+        "id": problem.id,
+        "number": problem.number,
+        "display_name": problem.display_name,
+        "content": problem.content,
+    })
+}
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))
+}
+
+/// Create an assignment from selected problems and a due date; the response
+/// carries the link to share with students
+pub async fn create_assignment(
+    body: web::Json<CreateAssignmentRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let request = body.into_inner();
+    let Some(due_at) = parse_due_at(&request.due_at) else {
+        return Ok(bad_request("due_at must be an RFC 3339 date-time or YYYY-MM-DD"));
+    };
+
+    let mut problem_ids: Vec<String> = Vec::new();
+    for id in request.problem_ids.iter().map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        if !problem_ids.iter().any(|seen| seen == id) {
+            problem_ids.push(id.to_string());
+        }
+    }
+    if problem_ids.is_empty() {
+        return Ok(bad_request("Select at least one problem"));
+    }
+    for problem_id in &problem_ids {
+        match db.get_problem(problem_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return Ok(bad_request(&format!("Problem {} not found", problem_id))),
+            Err(e) => return Ok(internal_error("get problem", e)),
+        }
+    }
+
+    let title = request
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .unwrap_or("Домашнее задание");
+    let instructions = request.instructions.map(|i| i.trim().to_string()).filter(|i| !i.is_empty());
+    let assignment = Assignment::new(title, instructions, problem_ids, due_at);
+    match db.create_assignment(&assignment).await {
+        Ok(()) => Ok(HttpResponse::Created().json(assignment_json(&assignment))),
+        Err(e) => Ok(internal_error("create assignment", e)),
+    }
+}
+
+/// Assignments, newest first
+pub async fn list_assignments(db: web::Data<Database>) -> Result<HttpResponse, Error> {
+    match db.list_assignments().await {
+        Ok(assignments) => Ok(HttpResponse::Ok().json(assignments.iter().map(assignment_json).collect::<Vec<_>>())),
+        Err(e) => Ok(internal_error("list assignments", e)),
+    }
+}
+
+pub async fn get_assignment(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match load_assignment(&db, &path.into_inner()).await {
+        Ok(assignment) => Ok(HttpResponse::Ok().json(assignment_json(&assignment))),
+        Err(response) => Ok(response),
+    }
+}
+
+/// Delete an assignment with its submissions; its share link stops working
+pub async fn delete_assignment(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.delete_assignment(&path.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Assignment not found"
+        }))),
+        Err(e) => Ok(internal_error("delete assignment", e)),
+    }
+}
+
+/// Graded answers per student and correct counts per problem
+pub async fn get_assignment_results(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let assignment = match load_assignment(&db, &path.into_inner()).await {
+        Ok(assignment) => assignment,
+        Err(response) => return Ok(response),
+    };
+
+    let results = async {
+        let problems = assignment_problems(&db, &assignment).await?;
+        let submissions = db.get_assignment_submissions(&assignment.id).await?;
+        anyhow::Ok(assignment_results(&assignment, &problems, &submissions))
+    };
+    match results.await {
+        Ok(results) => Ok(HttpResponse::Ok().json(results)),
+        Err(e) => Ok(internal_error("build assignment results", e)),
+    }
+}
+
+/// Uploaded photo of a photo submission
+pub async fn get_submission_photo(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let submission = match db.get_submission(&path.into_inner()).await {
+        Ok(Some(submission)) => submission,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Submission not found"
+            })));
+        }
+        Err(e) => return Ok(internal_error("get submission", e)),
+    };
+    let Some(photo_key) = submission.photo_key else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Submission has no photo"
+        })));
+    };
+
+    let content_type = match photo_key.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    };
+    match AttachmentStorage::from_config(&config).get(&photo_key).await {
+        Ok(bytes) => Ok(HttpResponse::Ok().content_type(content_type).body(bytes)),
+        Err(e) => Ok(internal_error("read submission photo", e)),
+    }
+}
+
+/// An assignment as students see it, by its share token
+pub async fn get_shared_assignment(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let assignment = match load_shared(&db, &path.into_inner()).await {
+        Ok(assignment) => assignment,
+        Err(response) => return Ok(response),
+    };
+
+    match assignment_problems(&db, &assignment).await {
+        Ok(problems) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "title": assignment.title,
+            "instructions": assignment.instructions,
+            "due_at": assignment.due_at,
+            "overdue": Utc::now() > assignment.due_at,
+            "problems": problems.iter().map(student_problem_json).collect::<Vec<_>>(),
+        }))),
+        Err(e) => Ok(internal_error("get assignment problems", e)),
+    }
+}
+
+/// Typed answers of one student. Answers after the due date are accepted
+/// and marked late; verdicts are only shown to the teacher.
+pub async fn submit_assignment_answers(
+    path: web::Path<String>,
+    body: web::Json<SubmitAnswersRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let assignment = match load_shared(&db, &path.into_inner()).await {
+        Ok(assignment) => assignment,
+        Err(response) => return Ok(response),
+    };
+    let request = body.into_inner();
+    let Some(student_name) = normalize_student_name(&request.student_name) else {
+        return Ok(bad_request("Enter your name (up to 80 characters)"));
+    };
+
+    let answers: Vec<TypedAnswer> = request.answers.into_iter().filter(|a| !a.answer.trim().is_empty()).collect();
+    if answers.is_empty() {
+        return Ok(bad_request("No answers to submit"));
+    }
+    if let Some(answer) = answers.iter().find(|a| !assignment.problem_ids.contains(&a.problem_id)) {
+        return Ok(bad_request(&format!("Problem {} is not part of this assignment", answer.problem_id)));
+    }
+
+    let now = Utc::now();
+    let late = now > assignment.due_at;
+    for answer in &answers {
+        let problem = match db.get_problem(&answer.problem_id).await {
+            Ok(Some(problem)) => problem,
+            Ok(None) => return Ok(bad_request(&format!("Problem {} not found", answer.problem_id))),
+            Err(e) => return Ok(internal_error("get problem", e)),
+        };
+        let submission = Submission {
+            id: uuid::Uuid::new_v4().to_string(),
+            assignment_id: assignment.id.clone(),
+            problem_id: answer.problem_id.clone(),
+            student_name: student_name.clone(),
+            source: SubmissionSource::Typed,
+            answer: Some(answer.answer.trim().to_string()),
+            photo_key: None,
+            verdict: grade_answer(&problem, &answer.answer),
+            late,
+            submitted_at: now,
+        };
+        if let Err(e) = db.create_submission(&submission).await {
+            return Ok(internal_error("save submission", e));
+        }
+    }
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "submitted": answers.len(),
+        "late": late,
+    })))
+}
+
+/// Photo of a handwritten answer to one problem: the raw image as the body,
+/// e.g. `?student_name=...&problem_id=...`. The photo is kept and its OCR
+/// text graded; when OCR fails the answer waits for the teacher as unknown.
+pub async fn submit_assignment_photo(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<PhotoSubmissionQuery>,
+    body: web::Bytes,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let assignment = match load_shared(&db, &path.into_inner()).await {
+        Ok(assignment) => assignment,
+        Err(response) => return Ok(response),
+    };
+    let Some(student_name) = normalize_student_name(&query.student_name) else {
+        return Ok(bad_request("Enter your name (up to 80 characters)"));
+    };
+    if !assignment.problem_ids.contains(&query.problem_id) {
+        return Ok(bad_request(&format!("Problem {} is not part of this assignment", query.problem_id)));
+    }
+
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_default();
+    let extension = match content_type.as_str() {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/webp" => "webp",
+        _ => {
+            return Ok(HttpResponse::UnsupportedMediaType().json(serde_json::json!({
+                "error": "Photos must be PNG, JPEG or WebP"
+            })));
+        }
+    };
+    if body.is_empty() {
+        return Ok(bad_request("Empty photo"));
+    }
+
+    let problem = match db.get_problem(&query.problem_id).await {
+        Ok(Some(problem)) => problem,
+        Ok(None) => return Ok(bad_request(&format!("Problem {} not found", query.problem_id))),
+        Err(e) => return Ok(internal_error("get problem", e)),
+    };
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let photo_key = format!("submission-{}.{}", id, extension);
+    if let Err(e) = AttachmentStorage::from_config(&config).put(&photo_key, body.to_vec(), &content_type).await {
+        return Ok(internal_error("store photo", e));
+    }
+
+    // OCR reads from a file, so the photo is also written to a temporary one
+    let image_path = std::env::temp_dir().join(&photo_key);
+    let answer = async {
+        tokio::fs::write(&image_path, &body).await?;
+        OcrService::new(&config).run_ocr(&image_path, DEFAULT_OCR_PROVIDER).await
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&image_path).await;
+    let answer = match answer {
+        Ok(text) if !text.trim().is_empty() => Some(text.trim().to_string()),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("OCR of submission {} failed: {}", id, e);
+            None
+        }
+    };
+
+    let now = Utc::now();
+    let submission = Submission {
+        id,
+        assignment_id: assignment.id.clone(),
+        problem_id: problem.id.clone(),
+        student_name,
+        source: SubmissionSource::Photo,
+        verdict: answer.as_deref().map_or(AnswerVerdict::Unknown, |a| grade_answer(&problem, a)),
+        answer,
+        photo_key: Some(photo_key),
+        late: now > assignment.due_at,
+        submitted_at: now,
+    };
+    match db.create_submission(&submission).await {
+        Ok(()) => Ok(HttpResponse::Created().json(serde_json::json!({
+            "id": submission.id,
+            "problem_id": submission.problem_id,
+            "recognized_answer": submission.answer,
+            "late": submission.late,
+        }))),
+        Err(e) => Ok(internal_error("save submission", e)),
+    }
+}
+
+/// Page students open from the share link to answer an assignment
+pub async fn view_shared_assignment(
+    path: web::Path<String>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let share_token = path.into_inner();
+    let assignment = match db.get_assignment_by_token(&share_token).await {
+        Ok(Some(assignment)) => assignment,
+        Ok(None) => return Ok(HttpResponse::NotFound().body("Assignment not found")),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Database error"));
+        }
+    };
+    let problems = assignment_problems(&db, &assignment).await.map_err(|e| {
+        log::error!("Failed to get problems: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let mut context = Context::new();
+    context.insert("title", &assignment.title);
+    context.insert("instructions", &assignment.instructions);
+    context.insert("due_at", &assignment.due_at.format("%Y-%m-%d %H:%M UTC").to_string());
+    context.insert("overdue", &(Utc::now() > assignment.due_at));
+    context.insert("share_token", &share_token);
+    context.insert("problems", &problems.iter().map(student_problem_json).collect::<Vec<_>>());
+    context.insert("katex_macros", &BookMacros::default().effective());
+
+    context.insert("preferences", &page_preferences(&req, &db).await);
+    let rendered = tmpl.render("textbook/assignment.html", &context).map_err(|e| {
+        log::error!("Template error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
@@ -0,0 +1,174 @@
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::services::attachments::{extension_for, store_attachment, AttachmentStorage};
+use crate::services::database::Database;
+use crate::services::plot::{render_svg, PlotSpec};
+
+#[derive(Debug, Deserialize)]
+pub struct AttachmentUploadQuery {
+    pub file_name: Option<String>,
+}
+
+/// 404 unless the solution exists
+async fn check_solution(db: &Database, solution_id: &str) -> Result<Option<HttpResponse>, Error> {
+    let found = db.solution_exists(solution_id).await.map_err(|e| {
+        log::error!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+    Ok((!found).then(|| {
+        HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Solution not found"
+        }))
+    }))
+}
+
+/// Upload a file for a solution: the raw bytes as the body, typed by the
+/// Content-Type header, e.g. `?file_name=drawing.png`
+pub async fn upload_solution_attachment(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<AttachmentUploadQuery>,
+    body: web::Bytes,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let solution_id = path.into_inner();
+    if let Some(response) = check_solution(&db, &solution_id).await? {
+        return Ok(response);
+    }
+
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_default();
+    let Some(extension) = extension_for(&content_type) else {
+        return Ok(HttpResponse::UnsupportedMediaType().json(serde_json::json!({
+            "error": "Attachments must be PNG, JPEG, GIF, WebP, SVG or PDF"
+        })));
+    };
+    if body.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Empty attachment"
+        })));
+    }
+
+    let file_name = query
+        .file_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| format!("attachment.{}", extension));
+    let storage = AttachmentStorage::from_config(&config);
+    match store_attachment(&db, &storage, &solution_id, &file_name, &content_type, body.to_vec(), None).await {
+        Ok(attachment) => Ok(HttpResponse::Created().json(attachment)),
+        Err(e) => {
+            log::error!("Failed to store attachment for {}: {}", solution_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to store attachment: {}", e)
+            })))
+        }
+    }
+}
+
+/// Draw a function plot from a formula spec and attach it to the solution
+pub async fn create_solution_plot(
+    path: web::Path<String>,
+    body: web::Json<PlotSpec>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let solution_id = path.into_inner();
+    if let Some(response) = check_solution(&db, &solution_id).await? {
+        return Ok(response);
+    }
+
+    // Bad formulas are the caller's, so they are checked before storing
+    let svg = match render_svg(&body) {
+        Ok(svg) => svg,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            })));
+        }
+    };
+
+    let storage = AttachmentStorage::from_config(&config);
+    let spec = body.into_inner();
+    match store_attachment(&db, &storage, &solution_id, "plot.svg", "image/svg+xml", svg.into_bytes(), Some(spec)).await {
+        Ok(attachment) => Ok(HttpResponse::Created().json(attachment)),
+        Err(e) => {
+            log::error!("Failed to attach plot to {}: {}", solution_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to attach plot: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn list_solution_attachments(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let solution_id = path.into_inner();
+    if let Some(response) = check_solution(&db, &solution_id).await? {
+        return Ok(response);
+    }
+
+    match db.get_solution_attachments(&solution_id).await {
+        Ok(attachments) => Ok(HttpResponse::Ok().json(attachments)),
+        Err(e) => {
+            log::error!("Failed to list attachments of {}: {}", solution_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list attachments: {}", e)
+            })))
+        }
+    }
+}
+
+/// The attached file itself
+pub async fn get_attachment(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let attachment_id = path.into_inner();
+    let attachment = match db.get_solution_attachment(&attachment_id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Attachment not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get attachment {}: {}", attachment_id, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get attachment: {}", e)
+            })));
+        }
+    };
+
+    match AttachmentStorage::from_config(&config).get(&attachment.storage_key).await {
+        // Uploaded SVGs may carry scripts; they must not run on our origin
+        Ok(bytes) => Ok(HttpResponse::Ok()
+            .content_type(attachment.content_type.as_str())
+            .append_header(("Content-Security-Policy", "default-src 'none'; style-src 'unsafe-inline'; sandbox"))
+            .append_header((
+                "Content-Disposition",
+                format!("inline; filename=\"{}\"", attachment.file_name.replace('"', "")),
+            ))
+            .body(bytes)),
+        Err(e) => {
+            log::error!("Failed to read attachment {}: {}", attachment_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to read attachment: {}", e)
+            })))
+        }
+    }
+}
@@ -1,24 +1,44 @@
 use actix_web::{web, Error, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::config::Config;
-use crate::services::background::{JobManager, JobStatus};
+use crate::config::{Config, OFFLINE_ERROR};
+use crate::services::attachments::AttachmentStorage;
+use crate::services::background::{JobManager, JobRecord, JobStatus};
 use crate::services::batch_processor::BatchProcessor;
+use crate::services::cost_estimate::JobEstimate;
 use crate::services::database::Database;
+use crate::services::export_history::{store_export_artifact, store_export_spool, ExportFilters, ExportRun, ExportSpool};
+use crate::services::graph_render::GraphRenderService;
+use crate::services::job_artifacts::{artifact_path, content_type as artifact_content_type, list_artifacts};
+use crate::services::ocr_audit::OcrAuditor;
+use crate::services::FileService;
+use crate::utils::page_range::parse_page_ranges;
 
 // === Batch OCR ===
 
+/// Most pages one batch OCR job may cover
+const MAX_BATCH_PAGES: usize = 100;
+
 #[derive(Debug, Deserialize)]
 pub struct BatchOcrRequest {
     pub book_id: String,
-    pub start_page: u32,
-    pub end_page: u32,
+    #[serde(default)]
+    pub start_page: Option<u32>,
+    #[serde(default)]
+    pub end_page: Option<u32>,
+    /// Page selection like "1-10,15,20-e,odd"; replaces start/end_page
+    #[serde(default)]
+    pub pages: Option<String>,
     pub chapter_id: String,
     /// If true, skip pages that already have OCR cached
     pub incremental: Option<bool>,
     /// If true, force re-OCR even if cached
     pub force: Option<bool>,
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,6 +47,87 @@ pub struct BatchOcrResponse {
     pub status: String,
     pub message: String,
     pub total_pages: u32,
+    pub estimate: JobEstimate,
+}
+
+/// 409 carrying the estimate when a job over the budget, or with unknown
+/// cost, isn't confirmed
+fn unconfirmed_over_budget(estimate: &JobEstimate, config: &Config, confirm: bool) -> Option<HttpResponse> {
+    if confirm || !estimate.exceeds(config.batch_budget_usd) {
+        return None;
+    }
+    let error = if estimate.priced {
+        format!(
+            "Estimated cost ${:.2} is over the ${:.2} budget; send confirm=true to start",
+            estimate.usd, config.batch_budget_usd
+        )
+    } else {
+        "The provider's prices are unknown, so the cost can't be estimated; send confirm=true to start".to_string()
+    };
+    Some(HttpResponse::Conflict().json(serde_json::json!({
+        "error": error,
+        "estimate": estimate,
+        "budget_usd": config.batch_budget_usd,
+    })))
+}
+
+/// Pages of `pages` with OCR text cached, which batch OCR doesn't send to
+/// the provider again unless forced
+async fn cached_pages(db: &Database, book_id: &str, pages: &[u32]) -> u32 {
+    match db.get_pages_by_book(book_id).await {
+        Ok(stored) => stored
+            .iter()
+            .filter(|p| pages.contains(&p.page_number) && p.ocr_text.as_ref().is_some_and(|t| !t.is_empty()))
+            .count() as u32,
+        Err(e) => {
+            log::warn!("Failed to load OCR pages of {}: {}", book_id, e);
+            0
+        }
+    }
+}
+
+/// Pages of an OCR job that go through the LLM page parser, when it is configured
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParsedPages {
+    None,
+    /// Only the pages OCR'd by the job
+    Fresh,
+    /// Cached pages too
+    All,
+}
+
+/// Cost of OCR'ing `pages` of a book with its OCR provider
+async fn ocr_estimate(
+    processor: &BatchProcessor,
+    db: &Database,
+    config: &Config,
+    book_id: &str,
+    pages: &[u32],
+    force: bool,
+    parsed: ParsedPages,
+) -> JobEstimate {
+    let cached = if force { 0 } else { cached_pages(db, book_id, pages).await };
+    let fresh = pages.len() as u32 - cached;
+    // Offline OCR reads the text layer or runs tesseract, both free
+    let ocr_pages = if config.offline { 0 } else { fresh };
+    let parsed_pages = match parsed {
+        _ if config.mistral_api_key().is_none() => 0,
+        ParsedPages::None => 0,
+        ParsedPages::Fresh => fresh,
+        ParsedPages::All => pages.len() as u32,
+    };
+    JobEstimate::ocr(&processor.ocr_provider(book_id).await, ocr_pages, parsed_pages)
+}
+
+/// Page count of a book's PDF, 0 when it can't be read
+async fn book_page_count(file_service: &FileService, book_id: &str) -> u32 {
+    let file_service = file_service.clone();
+    let file = file_service.book_file(book_id);
+    tokio::task::spawn_blocking(move || file_service.get_pdf_page_count(&file))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .unwrap_or(0)
 }
 
 pub async fn start_batch_ocr(
@@ -34,17 +135,42 @@ pub async fn start_batch_ocr(
     job_manager: web::Data<Arc<JobManager>>,
     db: web::Data<Database>,
     config: web::Data<Config>,
+    file_service: web::Data<FileService>,
 ) -> Result<HttpResponse, Error> {
     // Validate page range
-    if body.start_page > body.end_page {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid page range: start_page must be <= end_page"
-        })));
-    }
-    
-    if body.end_page - body.start_page > 100 {
+    let pages: Vec<u32> = match (&body.pages, body.start_page, body.end_page) {
+        (Some(spec), _, _) => {
+            let total_pages = book_page_count(&file_service, &body.book_id).await;
+            match parse_page_ranges(spec, total_pages) {
+                Ok(pages) => pages.into_iter().collect(),
+                Err(e) => {
+                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                        "error": format!("Invalid pages: {}", e)
+                    })));
+                }
+            }
+        }
+        (None, Some(start), Some(end)) if start <= end && end - start < MAX_BATCH_PAGES as u32 => (start..=end).collect(),
+        (None, Some(start), Some(end)) if start <= end => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Page range too large (max {} pages per batch)", MAX_BATCH_PAGES)
+            })));
+        }
+        (None, Some(_), Some(_)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid page range: start_page must be <= end_page"
+            })));
+        }
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Either pages or start_page and end_page are required"
+            })));
+        }
+    };
+
+    if pages.len() > MAX_BATCH_PAGES {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Page range too large (max 100 pages per batch)"
+            "error": format!("Page range too large (max {} pages per batch)", MAX_BATCH_PAGES)
         })));
     }
     
@@ -56,14 +182,33 @@ pub async fn start_batch_ocr(
     
     let incremental = body.incremental.unwrap_or(false);
     let force = body.force.unwrap_or(false);
+
+    let parsed = if incremental { ParsedPages::Fresh } else { ParsedPages::All };
+    let estimate = ocr_estimate(&processor, &db, &config, &body.book_id, &pages, force, parsed).await;
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, body.confirm) {
+        return Ok(response);
+    }
     
-    match processor.start_batch_ocr(&body.book_id, body.start_page, body.end_page, &body.chapter_id, incremental, force).await {
+    let (first, last) = (pages[0], pages[pages.len() - 1]);
+    let total_pages = pages.len() as u32;
+    let started = if body.pages.is_some() {
+        processor.start_batch_ocr_pages(&body.book_id, pages, &body.chapter_id, incremental, force).await
+    } else {
+        processor.start_batch_ocr(&body.book_id, first, last, &body.chapter_id, incremental, force).await
+    };
+
+    match started {
         Ok(job_id) => {
+            let message = match &body.pages {
+                Some(spec) => format!("Batch OCR started for pages {}", spec),
+                None => format!("Batch OCR started for pages {}-{}", first, last),
+            };
             Ok(HttpResponse::Accepted().json(BatchOcrResponse {
                 job_id,
                 status: "pending".to_string(),
-                message: format!("Batch OCR started for pages {}-{}", body.start_page, body.end_page),
-                total_pages: body.end_page - body.start_page + 1,
+                message,
+                total_pages,
+                estimate,
             }))
         }
         Err(e) => {
@@ -75,12 +220,228 @@ pub async fn start_batch_ocr(
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ConfirmQuery {
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// Re-run a finished batch OCR job on just the pages it failed on, with the
+/// same book, chapter and `force` setting. `incremental` is turned off, since
+/// skipping cached pages would skip the pages being retried.
+pub async fn retry_job_failures(
+    path: web::Path<String>,
+    query: web::Query<ConfirmQuery>,
+    job_manager: web::Data<Arc<JobManager>>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let job_id = path.into_inner();
+
+    // Recently finished jobs are still in memory; older ones only in the history
+    let record = match job_manager.get_job(&job_id).await {
+        Some(job) => match JobRecord::from_job(&job) {
+            Some(record) => Some(record),
+            None => {
+                return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Job has not finished yet"
+                })));
+            }
+        },
+        None => match db.get_job_record(&job_id).await {
+            Ok(record) => record,
+            Err(e) => {
+                log::error!("Failed to load job {}: {}", job_id, e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to load job: {}", e)
+                })));
+            }
+        },
+    };
+
+    let Some(record) = record else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Job not found"
+        })));
+    };
+
+    if record.job_type != "batch_ocr" {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Only batch OCR jobs can be retried"
+        })));
+    }
+
+    let failed_pages = record.failed_pages();
+    if failed_pages.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Job has no failed pages"
+        })));
+    }
+
+    let param = |key: &str| record.params.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    let (Some(book_id), Some(chapter_id)) = (param("book_id"), param("chapter_id")) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Job parameters are incomplete"
+        })));
+    };
+    let force = record.params.get("force").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    let processor = BatchProcessor::new(
+        job_manager.get_ref().clone(),
+        Arc::new(db.get_ref().clone()),
+        Arc::new(config.get_ref().clone()),
+    );
+
+    let estimate = ocr_estimate(&processor, &db, &config, &book_id, &failed_pages, force, ParsedPages::All).await;
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, query.confirm) {
+        return Ok(response);
+    }
+
+    match processor.start_batch_ocr_pages(&book_id, failed_pages.clone(), &chapter_id, false, force).await {
+        Ok(new_job_id) => Ok(HttpResponse::Accepted().json(serde_json::json!({
+            "job_id": new_job_id,
+            "retry_of": job_id,
+            "status": "pending",
+            "pages": failed_pages,
+            "estimate": estimate,
+        }))),
+        Err(e) => {
+            log::error!("Failed to start retry of job {}: {}", job_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to start batch OCR: {}", e)
+            })))
+        }
+    }
+}
+
+// === Pending Pages ===
+
+/// Pages of a book that are referenced, e.g. by a problem continuing onto
+/// them, but haven't been OCR'd
+pub async fn list_pending_pages(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    match db.get_pending_pages(&book_id).await {
+        Ok(pages) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "total": pages.len(),
+            "pages": pages,
+        }))),
+        Err(e) => {
+            log::error!("Failed to list pending pages of {}: {}", book_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list pending pages: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PendingOcrRequest {
+    /// Chapter for the problems found on every page; by default each page
+    /// goes to the chapter it falls in
+    #[serde(default)]
+    pub chapter_id: Option<String>,
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// OCR every pending page of a book: one incremental batch OCR job per
+/// chapter the pages fall in, split at [`MAX_BATCH_PAGES`]
+pub async fn ocr_pending_pages(
+    path: web::Path<String>,
+    body: Option<web::Json<PendingOcrRequest>>,
+    job_manager: web::Data<Arc<JobManager>>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let request = body.map(web::Json::into_inner).unwrap_or_default();
+
+    let pending = match db.get_pending_pages(&book_id).await {
+        Ok(pages) => pages,
+        Err(e) => {
+            log::error!("Failed to list pending pages of {}: {}", book_id, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list pending pages: {}", e)
+            })));
+        }
+    };
+    if pending.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Book has no pending pages"
+        })));
+    }
+
+    let mut by_chapter: std::collections::BTreeMap<String, Vec<u32>> = Default::default();
+    let mut unassigned = Vec::new();
+    for page in pending {
+        match request.chapter_id.clone().or(page.chapter_id) {
+            Some(chapter_id) => by_chapter.entry(chapter_id).or_default().push(page.page_number),
+            None => unassigned.push(page.page_number),
+        }
+    }
+    if by_chapter.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Book has no chapters to put problems in; pass chapter_id",
+            "unassigned": unassigned,
+        })));
+    }
+
+    let processor = BatchProcessor::new(
+        job_manager.get_ref().clone(),
+        Arc::new(db.get_ref().clone()),
+        Arc::new(config.get_ref().clone()),
+    );
+
+    let assigned: Vec<u32> = by_chapter.values().flatten().copied().collect();
+    let estimate = ocr_estimate(&processor, &db, &config, &book_id, &assigned, false, ParsedPages::Fresh).await;
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, request.confirm) {
+        return Ok(response);
+    }
+
+    let mut jobs = Vec::new();
+    for (chapter_id, pages) in by_chapter {
+        for chunk in pages.chunks(MAX_BATCH_PAGES) {
+            match processor.start_batch_ocr_pages(&book_id, chunk.to_vec(), &chapter_id, true, false).await {
+                Ok(job_id) => jobs.push(serde_json::json!({
+                    "job_id": job_id,
+                    "chapter_id": chapter_id,
+                    "pages": chunk,
+                })),
+                Err(e) => {
+                    log::error!("Failed to start OCR of pending pages of {}: {}", book_id, e);
+                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": format!("Failed to start batch OCR: {}", e),
+                        "jobs": jobs,
+                    })));
+                }
+            }
+        }
+    }
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "status": "pending",
+        "jobs": jobs,
+        "unassigned": unassigned,
+        "estimate": estimate,
+    })))
+}
+
 // === Batch Solve ===
 
 #[derive(Debug, Deserialize)]
 pub struct BatchSolveRequest {
     pub problem_ids: Vec<String>,
     pub provider: Option<String>,
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -89,6 +450,7 @@ pub struct BatchSolveResponse {
     pub status: String,
     pub message: String,
     pub total_problems: usize,
+    pub estimate: JobEstimate,
 }
 
 pub async fn start_batch_solve(
@@ -109,7 +471,31 @@ pub async fn start_batch_solve(
         })));
     }
     
+    if config.offline {
+        return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": OFFLINE_ERROR
+        })));
+    }
+    
     let provider = body.provider.as_deref().unwrap_or("mistral");
+
+    // Problems that already have a solution are skipped by the job
+    let mut unsolved = 0;
+    for problem_id in &body.problem_ids {
+        match db.get_problem(problem_id).await {
+            Ok(Some(problem)) if problem.has_solution => {}
+            Ok(Some(_)) => unsolved += 1,
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("Failed to load problem {}: {}", problem_id, e);
+                unsolved += 1;
+            }
+        }
+    }
+    let estimate = JobEstimate::solve(provider, unsolved);
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, body.confirm) {
+        return Ok(response);
+    }
     
     let processor = BatchProcessor::new(
         job_manager.get_ref().clone(),
@@ -124,6 +510,7 @@ pub async fn start_batch_solve(
                 status: "pending".to_string(),
                 message: format!("Batch solve started with {} problems", body.problem_ids.len()),
                 total_problems: body.problem_ids.len(),
+                estimate,
             }))
         }
         Err(e) => {
@@ -135,6 +522,164 @@ pub async fn start_batch_solve(
     }
 }
 
+// === Answer Key ===
+
+#[derive(Debug, Deserialize)]
+pub struct AnswerKeyRequest {
+    pub start_page: u32,
+    pub end_page: u32,
+    /// Match only this chapter's problems (for books whose numbering restarts per chapter)
+    pub chapter_id: Option<String>,
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// OCR a book's answers section and store the answers on matching problems
+pub async fn import_answer_key(
+    path: web::Path<String>,
+    body: web::Json<AnswerKeyRequest>,
+    job_manager: web::Data<Arc<JobManager>>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    if body.start_page == 0 || body.start_page > body.end_page {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid page range: start_page must be >= 1 and <= end_page"
+        })));
+    }
+
+    if body.end_page - body.start_page > 100 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Page range too large (max 100 pages per batch)"
+        })));
+    }
+
+    let processor = BatchProcessor::new(
+        job_manager.get_ref().clone(),
+        Arc::new(db.get_ref().clone()),
+        Arc::new(config.get_ref().clone()),
+    );
+
+    // Answers are matched with regexes, never the LLM parser
+    let pages: Vec<u32> = (body.start_page..=body.end_page).collect();
+    let estimate = ocr_estimate(&processor, &db, &config, &book_id, &pages, false, ParsedPages::None).await;
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, body.confirm) {
+        return Ok(response);
+    }
+
+    match processor.start_answer_key(&book_id, body.start_page, body.end_page, body.chapter_id.as_deref()).await {
+        Ok(job_id) => Ok(HttpResponse::Accepted().json(serde_json::json!({
+            "job_id": job_id,
+            "status": "pending",
+            "message": format!("Reading answers from pages {}-{}", body.start_page, body.end_page),
+            "estimate": estimate,
+        }))),
+        Err(e) => {
+            log::error!("Failed to start answer key import: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to start answer key import: {}", e)
+            })))
+        }
+    }
+}
+
+// === OCR Quality Audit ===
+
+#[derive(Debug, Deserialize)]
+pub struct OcrAuditRequest {
+    /// Audit a single book; all books when omitted
+    pub book_id: Option<String>,
+    pub sample_size: Option<usize>,
+    pub provider: Option<String>,
+    /// Start even though the estimated cost is over `BATCH_BUDGET_USD`
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+pub async fn start_ocr_audit(
+    body: web::Json<OcrAuditRequest>,
+    job_manager: web::Data<Arc<JobManager>>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let provider = match body.provider.clone().or_else(|| config.ocr_audit_provider.clone()) {
+        Some(p) => p,
+        None => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "No audit provider given and OCR_AUDIT_PROVIDER is not set"
+            })));
+        }
+    };
+    let sample_size = body.sample_size.unwrap_or(config.ocr_audit_sample_size).clamp(1, 50);
+
+    // At most `sample_size` pages of every audited book
+    let books = match &body.book_id {
+        Some(_) => 1,
+        None => match db.list_books().await {
+            Ok(books) => books.len(),
+            Err(e) => {
+                log::error!("Failed to list books: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to list books: {}", e)
+                })));
+            }
+        },
+    };
+    let estimate = JobEstimate::ocr(&provider, (books * sample_size) as u32, 0);
+    if let Some(response) = unconfirmed_over_budget(&estimate, &config, body.confirm) {
+        return Ok(response);
+    }
+
+    let auditor = OcrAuditor::new(
+        job_manager.get_ref().clone(),
+        Arc::new(db.get_ref().clone()),
+        Arc::new(config.get_ref().clone()),
+    );
+
+    match auditor.start_audit(body.book_id.clone(), sample_size, &provider).await {
+        Ok(job_id) => Ok(HttpResponse::Accepted().json(serde_json::json!({
+            "job_id": job_id,
+            "status": "pending",
+            "provider": provider,
+            "sample_size": sample_size,
+            "estimate": estimate,
+        }))),
+        Err(e) => {
+            log::error!("Failed to start OCR audit: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to start OCR audit: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OcrAuditQuery {
+    pub book_id: Option<String>,
+    pub diverged: Option<bool>,
+    pub limit: Option<usize>,
+}
+
+pub async fn list_ocr_audits(
+    query: web::Query<OcrAuditQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let limit = query.limit.unwrap_or(100);
+
+    match db.get_ocr_audits(query.book_id.as_deref(), query.diverged.unwrap_or(false), limit).await {
+        Ok(audits) => Ok(HttpResponse::Ok().json(audits)),
+        Err(e) => {
+            log::error!("Failed to list OCR audits: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list OCR audits: {}", e)
+            })))
+        }
+    }
+}
+
 // === Job Management ===
 
 #[derive(Debug, Serialize)]
@@ -223,6 +768,86 @@ pub async fn list_jobs(
     Ok(HttpResponse::Ok().json(responses))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct JobHistoryQuery {
+    /// batch_ocr, batch_solve, export or ocr_audit
+    #[serde(rename = "type")]
+    pub job_type: Option<String>,
+    /// completed, failed or cancelled
+    pub status: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Finished jobs persisted across restarts, newest first
+pub async fn list_job_history(
+    query: web::Query<JobHistoryQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    match db.get_job_history(query.job_type.as_deref(), query.status.as_deref(), limit).await {
+        Ok(records) => Ok(HttpResponse::Ok().json(records)),
+        Err(e) => {
+            log::error!("Failed to load job history: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load job history: {}", e)
+            })))
+        }
+    }
+}
+
+/// Files attached to a job (error CSV, per-page report, ...)
+pub async fn list_job_artifacts(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let job_id = path.into_inner();
+
+    match list_artifacts(&config.jobs_dir, &job_id) {
+        Ok(artifacts) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "job_id": job_id,
+            "artifacts": artifacts,
+        }))),
+        Err(e) => {
+            log::error!("Failed to list artifacts of job {}: {}", job_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list artifacts: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn download_job_artifact(
+    path: web::Path<(String, String)>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let (job_id, name) = path.into_inner();
+
+    let Some(file) = artifact_path(&config.jobs_dir, &job_id, &name) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid artifact name"
+        })));
+    };
+
+    match std::fs::read(&file) {
+        Ok(data) => Ok(HttpResponse::Ok()
+            .content_type(artifact_content_type(&name))
+            .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", name)))
+            .body(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Artifact not found"
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to read artifact {}: {}", file.display(), e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to read artifact: {}", e)
+            })))
+        }
+    }
+}
+
 pub async fn cancel_job(
     path: web::Path<String>,
     job_manager: web::Data<Arc<JobManager>>,
@@ -251,40 +876,225 @@ pub async fn cancel_job(
 
 // === Export ===
 
+/// Response header naming the recorded export run, for `GET /api/exports/diff`
+const EXPORT_RUN_HEADER: &str = "X-Export-Run";
+
+fn export_response(mime_type: &str, filename: &str, run_id: Option<String>) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(mime_type)
+        .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)));
+    if let Some(run_id) = run_id {
+        response.append_header((EXPORT_RUN_HEADER, run_id));
+    }
+    response
+}
+
+/// Record what an export contains; the export goes out even when this fails
+async fn record_export_run(
+    db: &Database,
+    exporter: &crate::services::export::Exporter,
+    run: ExportRun,
+) -> Option<ExportRun> {
+    let result = match exporter.snapshot(&run.book_id, run.chapter_id.as_deref()).await {
+        Ok(problems) => db.save_export_run(&run, &problems).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Some(run),
+        Err(e) => {
+            log::warn!("Failed to record export of {}: {}", run.book_id, e);
+            None
+        }
+    }
+}
+
+/// Keep the file a recorded export produced for `GET /api/exports/{id}/download`
+async fn keep_export_file(
+    db: &Database,
+    storage: &AttachmentStorage,
+    run: Option<ExportRun>,
+    filename: &str,
+    mime_type: &str,
+    data: Vec<u8>,
+    started: Instant,
+) {
+    let Some(mut run) = run else { return };
+    if let Err(e) = store_export_artifact(db, storage, &mut run, filename, mime_type, data, started).await {
+        log::warn!("Failed to store export {}: {}", run.id, e);
+    }
+}
+
+/// A streamed export, spooled to a temporary file as it goes out so the
+/// whole file can be stored once the last chunk is sent. Dropped before that
+/// (the client went away), its run is marked failed.
+struct StreamedExport {
+    rx: tokio::sync::mpsc::Receiver<crate::services::export::ExportChunk>,
+    run: Option<ExportRun>,
+    spool: Option<ExportSpool>,
+    db: Database,
+    storage: AttachmentStorage,
+    filename: String,
+    mime_type: &'static str,
+    started: Instant,
+}
+
+impl StreamedExport {
+    async fn next_chunk(mut self) -> Option<(Result<web::Bytes, Error>, Self)> {
+        match self.rx.recv().await {
+            Some(Ok(chunk)) => {
+                if let Some(spool) = self.spool.as_mut()
+                    && let Err(e) = spool.write(&chunk).await
+                {
+                    // The client still gets the export, it just isn't kept
+                    log::warn!("Failed to spool export: {}", e);
+                    self.spool = None;
+                }
+                Some((Ok(web::Bytes::from(chunk)), self))
+            }
+            Some(Err(e)) => {
+                self.fail(&e).await;
+                Some((Err(actix_web::error::ErrorInternalServerError(e)), self))
+            }
+            None => {
+                match (self.run.take(), self.spool.take()) {
+                    (Some(mut run), Some(spool)) => {
+                        let stored = store_export_spool(
+                            &self.db, &self.storage, &mut run, &self.filename, self.mime_type, spool, self.started,
+                        )
+                        .await;
+                        if let Err(e) = stored {
+                            log::warn!("Failed to store export {}: {}", run.id, e);
+                        }
+                    }
+                    (Some(run), None) => self.run = Some(run),
+                    _ => {}
+                }
+                if self.run.is_some() {
+                    self.fail("Export file could not be kept").await;
+                }
+                None
+            }
+        }
+    }
+
+    async fn fail(&mut self, error: &str) {
+        if let Some(run) = self.run.take() {
+            let duration_ms = self.started.elapsed().as_millis() as u64;
+            if let Err(err) = self.db.fail_export_run(&run.id, error, duration_ms).await {
+                log::warn!("Failed to record failed export {}: {}", run.id, err);
+            }
+        }
+    }
+}
+
+impl Drop for StreamedExport {
+    fn drop(&mut self) {
+        let Some(run) = self.run.take() else { return };
+        let db = self.db.clone();
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        tokio::spawn(async move {
+            if let Err(e) = db.fail_export_run(&run.id, "Client disconnected", duration_ms).await {
+                log::warn!("Failed to record interrupted export {}: {}", run.id, e);
+            }
+        });
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ExportRequest {
     pub book_id: String,
-    pub format: String, // markdown, latex, json, anki
+    pub format: String, // markdown, latex, json, anki, beamer, moodle, qti
+    /// Only include problems approved in review
+    #[serde(default)]
+    pub approved_only: bool,
+    /// Solutions to include: a provider name, `verified`, or any (default)
+    pub provider: Option<String>,
+    /// Append the book's glossary (Markdown, LaTeX and JSON)
+    #[serde(default)]
+    pub include_glossary: bool,
 }
 
 pub async fn export_book(
     body: web::Json<ExportRequest>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
+    graphs: web::Data<GraphRenderService>,
 ) -> Result<HttpResponse, Error> {
+    use crate::models::SolutionFilter;
     use crate::services::export::{Exporter, ExportFormat};
     
-    let format = match body.format.as_str() {
-        "markdown" | "md" => ExportFormat::Markdown,
-        "latex" | "tex" => ExportFormat::Latex,
-        "json" => ExportFormat::Json,
-        "anki" => ExportFormat::Anki,
-        _ => {
+    let format = match ExportFormat::from_name(body.format.as_str()) {
+        Some(f) => f,
+        None => {
             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid format. Use: markdown, latex, json, anki"
+                "error": "Invalid format. Use: markdown, latex, json, anki, beamer, moodle, qti, pdf"
             })));
         }
     };
     
-    let exporter = Exporter::new(db.get_ref().clone());
+    let started = Instant::now();
+    let storage = AttachmentStorage::from_config(&config);
+    let exporter = Exporter::new(db.get_ref().clone())
+        .approved_only(body.approved_only)
+        .solutions(SolutionFilter::from_param(body.provider.as_deref()))
+        .glossary(body.include_glossary)
+        .attachments(storage.clone())
+        .graphs(graphs.get_ref().clone())
+        .config(&config);
+    
+    let filename = format!("{}_export.{}", body.book_id, format.extension());
+    let filters = ExportFilters {
+        approved_only: body.approved_only,
+        provider: body.provider.clone(),
+        include_glossary: body.include_glossary,
+        problems: None,
+    };
+
+    // Everything but zip packages is sent chapter by chapter while the export is
+    // still being written; the body has no Content-Length
+    if format.streams() {
+        let book = match db.get_book(&body.book_id).await {
+            Ok(Some(book)) => book,
+            Ok(None) => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "Book not found"
+                })));
+            }
+            Err(e) => {
+                log::error!("Export failed: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Export failed: {}", e)
+                })));
+            }
+        };
+        let run = ExportRun::new(&book.id, None, &body.format).with_filters(filters);
+        let run = record_export_run(&db, &exporter, run).await;
+        let run_id = run.as_ref().map(|r| r.id.clone());
+        let export = StreamedExport {
+            rx: exporter.stream_book(book, format),
+            spool: match run {
+                Some(_) => ExportSpool::create().await.inspect_err(|e| log::warn!("Failed to spool export: {}", e)).ok(),
+                None => None,
+            },
+            run,
+            db: db.get_ref().clone(),
+            storage,
+            filename: filename.clone(),
+            mime_type: format.mime_type(),
+            started,
+        };
+        let chunks = futures::stream::unfold(export, StreamedExport::next_chunk);
+        return Ok(export_response(format.mime_type(), &filename, run_id).streaming(chunks));
+    }
     
     match exporter.export_book(&body.book_id, format).await {
         Ok(data) => {
-            let filename = format!("{}_export.{}", body.book_id, format.extension());
-            
-            Ok(HttpResponse::Ok()
-                .content_type(format.mime_type())
-                .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
-                .body(data))
+            let run = ExportRun::new(&body.book_id, None, &body.format).with_filters(filters);
+            let run = record_export_run(&db, &exporter, run).await;
+            let run_id = run.as_ref().map(|r| r.id.clone());
+            keep_export_file(&db, &storage, run, &filename, format.mime_type(), data.clone(), started).await;
+            Ok(export_response(format.mime_type(), &filename, run_id).body(data))
         }
         Err(e) => {
             log::error!("Export failed: {}", e);
@@ -299,34 +1109,60 @@ pub async fn export_chapter(
     path: web::Path<String>,
     query: web::Query<std::collections::HashMap<String, String>>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
+    graphs: web::Data<GraphRenderService>,
 ) -> Result<HttpResponse, Error> {
+    use crate::models::SolutionFilter;
     use crate::services::export::{Exporter, ExportFormat};
     
     let chapter_id = path.into_inner();
     let format_str = query.get("format").map(|s| s.as_str()).unwrap_or("markdown");
     
-    let format = match format_str {
-        "markdown" | "md" => ExportFormat::Markdown,
-        "latex" | "tex" => ExportFormat::Latex,
-        "json" => ExportFormat::Json,
-        "anki" => ExportFormat::Anki,
-        _ => {
+    let format = match ExportFormat::from_name(format_str) {
+        Some(f) => f,
+        None => {
             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Invalid format"
             })));
         }
     };
     
-    let exporter = Exporter::new(db.get_ref().clone());
+    let started = Instant::now();
+    let storage = AttachmentStorage::from_config(&config);
+    let approved_only = query.get("approved_only").is_some_and(|v| v == "true" || v == "1");
+    let exporter = Exporter::new(db.get_ref().clone())
+        .approved_only(approved_only)
+        .solutions(SolutionFilter::from_param(query.get("provider").map(|s| s.as_str())))
+        .attachments(storage.clone())
+        .graphs(graphs.get_ref().clone())
+        .config(&config);
+
+    // Beamer decks can be limited to selected problems: ?format=beamer&problems=1,5,12
+    let selected: Option<Vec<String>> = query.get("problems").map(|p| {
+        p.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect()
+    });
+
+    let result = match (format, &selected) {
+        (ExportFormat::Beamer, Some(numbers)) => exporter.export_chapter_slides(&chapter_id, numbers).await,
+        _ => exporter.export_chapter(&chapter_id, format).await,
+    };
     
-    match exporter.export_chapter(&chapter_id, format).await {
+    match result {
         Ok(data) => {
             let filename = format!("chapter_{}_export.{}", chapter_id.replace(":", "_"), format.extension());
+            let book_id = chapter_id.split(':').next().unwrap_or(&chapter_id);
+            let filters = ExportFilters {
+                approved_only,
+                provider: query.get("provider").cloned(),
+                include_glossary: false,
+                problems: selected.filter(|_| matches!(format, ExportFormat::Beamer)),
+            };
+            let run = ExportRun::new(book_id, Some(&chapter_id), format_str).with_filters(filters);
+            let run = record_export_run(&db, &exporter, run).await;
+            let run_id = run.as_ref().map(|r| r.id.clone());
+            keep_export_file(&db, &storage, run, &filename, format.mime_type(), data.clone(), started).await;
             
-            Ok(HttpResponse::Ok()
-                .content_type(format.mime_type())
-                .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
-                .body(data))
+            Ok(export_response(format.mime_type(), &filename, run_id).body(data))
         }
         Err(e) => {
             log::error!("Export failed: {}", e);
@@ -369,7 +1205,7 @@ pub async fn validate_chapter(
     body: web::Json<ValidateRequest>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
-    use crate::services::validation::{validate_problem_sequence, validate_problem};
+    use crate::services::validation::{validate_problem_sequence, validate_problem, ValidationWarning};
     
     // Get all problems for chapter
     let problems = match db.get_problems_by_chapter(&body.chapter_id).await {
@@ -393,6 +1229,23 @@ pub async fn validate_chapter(
         all_errors.extend(problem_result.errors);
         all_warnings.extend(problem_result.warnings);
     }
+
+    // Surface pages flagged by the OCR quality audit
+    if let Ok(Some(chapter)) = db.get_chapter(&body.chapter_id).await {
+        let diverged = db.get_diverged_pages(&chapter.book_id).await.unwrap_or_default();
+        for audit in diverged {
+            if let Some(problem) = problems.iter().find(|p| p.page_number == Some(audit.page_number)) {
+                all_warnings.push(ValidationWarning {
+                    code: "OCR_DIVERGENCE".to_string(),
+                    message: format!(
+                        "Page {} OCR differs from '{}' re-check (similarity {:.2})",
+                        audit.page_number, audit.provider, audit.similarity
+                    ),
+                    problem_id: Some(problem.id.clone()),
+                });
+            }
+        }
+    }
     
     let response = ValidationResponse {
         is_valid: all_errors.is_empty(),
@@ -0,0 +1,1061 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::models::{Book, Chapter, Problem, SolutionFilter};
+use crate::services::ai_solver::AISolver;
+use crate::services::answer_key::AnswerVerdict;
+use crate::services::book_compare::compare_books;
+use crate::services::book_dashboard::{coverage, top_tags, validation_summary, DASHBOARD_RECENT_JOBS, DASHBOARD_TOP_TAGS};
+use crate::services::book_probe::{profile, sample_pages, ProbeFailure, ProbedPage, DEFAULT_PROBE_PAGES, MAX_PROBE_PAGES};
+use crate::services::book_settings::{book_file_service, book_parser_profile, BookSettings, ParserProfile};
+use crate::services::database::Database;
+use crate::services::heading_detector::{HeadingDetector, HeadingOverrides, DEFAULT_HEADING_PATTERNS};
+use crate::services::glossary::{build_glossary, parse_definitions, term_key, undefined_concepts};
+use crate::services::ingestion::analyze_pages;
+use crate::services::latex_macros::BookMacros;
+use crate::services::FileService;
+use crate::services::ocr_confidence::LOW_CONFIDENCE_THRESHOLD;
+use crate::services::ocr_rules::{compile_pattern, postprocess_ocr_text, preview_rules, OcrRule};
+use crate::services::page_classifier::page_columns;
+use crate::services::page_parser::PageContentParser;
+use crate::services::provider_registry::{self, ProviderKind};
+use crate::services::OcrService;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Deserialize)]
+pub struct CompareBooksQuery {
+    pub a: String,
+    pub b: String,
+}
+
+async fn load_book_problems(db: &Database, book_id: &str) -> anyhow::Result<(Vec<Chapter>, Vec<(u32, Problem)>)> {
+    let chapters = db.get_chapters_by_book(book_id).await?;
+    let mut problems = Vec::new();
+    for chapter in &chapters {
+        for problem in db.get_problems_by_chapter(&chapter.id).await? {
+            problems.push((chapter.number, problem));
+        }
+    }
+    Ok((chapters, problems))
+}
+
+/// Structured diff between two books (e.g. two editions of the same textbook)
+pub async fn compare_books_handler(
+    query: web::Query<CompareBooksQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    for book_id in [&query.a, &query.b] {
+        match db.get_book(book_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("Book {} not found", book_id)
+                })));
+            }
+            Err(e) => {
+                log::error!("Failed to get book: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to get book: {}", e)
+                })));
+            }
+        }
+    }
+
+    let loaded = tokio::try_join!(
+        load_book_problems(&db, &query.a),
+        load_book_problems(&db, &query.b),
+    );
+
+    match loaded {
+        Ok(((chapters_a, problems_a), (chapters_b, problems_b))) => {
+            let diff = compare_books(&chapters_a, &problems_a, &chapters_b, &problems_b);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "a": query.a,
+                "b": query.b,
+                "problems_a": problems_a.len(),
+                "problems_b": problems_b.len(),
+                "diff": diff,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to compare books: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to compare books: {}", e)
+            })))
+        }
+    }
+}
+
+// === OCR Post-processing Rules ===
+
+#[derive(Debug, Deserialize)]
+pub struct OcrRuleRequest {
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: String,
+    pub position: Option<i64>,
+    pub enabled: Option<bool>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OcrRulePreviewRequest {
+    /// Sample page whose stored OCR text is used
+    pub page_number: Option<u32>,
+    /// Explicit sample text (takes precedence over `page_number`)
+    pub text: Option<String>,
+    /// Candidate rule to test; when omitted the book's saved rules are previewed
+    pub rule: Option<OcrRuleRequest>,
+}
+
+fn invalid_pattern(e: anyhow::Error) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e.to_string()
+    }))
+}
+
+pub async fn list_ocr_rules(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.get_ocr_rules(&path).await {
+        Ok(rules) => Ok(HttpResponse::Ok().json(rules)),
+        Err(e) => {
+            log::error!("Failed to list OCR rules: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list OCR rules: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn create_ocr_rule(
+    path: web::Path<String>,
+    body: web::Json<OcrRuleRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Err(e) = compile_pattern(&body.pattern) {
+        return Ok(invalid_pattern(e));
+    }
+
+    // New rules go to the end unless a position is given
+    let position = match body.position {
+        Some(p) => p,
+        None => db
+            .get_ocr_rules(&book_id)
+            .await
+            .ok()
+            .and_then(|rules| rules.iter().map(|r| r.position).max())
+            .map_or(0, |max| max + 1),
+    };
+
+    let rule = OcrRule {
+        id: uuid::Uuid::new_v4().to_string(),
+        book_id,
+        pattern: body.pattern.clone(),
+        replacement: body.replacement.clone(),
+        position,
+        enabled: body.enabled.unwrap_or(true),
+        description: body.description.clone(),
+        created_at: chrono::Utc::now(),
+    };
+
+    match db.save_ocr_rule(&rule).await {
+        Ok(()) => Ok(HttpResponse::Created().json(rule)),
+        Err(e) => {
+            log::error!("Failed to save OCR rule: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save OCR rule: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn update_ocr_rule(
+    path: web::Path<(String, String)>,
+    body: web::Json<OcrRuleRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (book_id, rule_id) = path.into_inner();
+    if let Err(e) = compile_pattern(&body.pattern) {
+        return Ok(invalid_pattern(e));
+    }
+
+    let mut rule = match db.get_ocr_rule(&rule_id).await {
+        Ok(Some(rule)) if rule.book_id == book_id => rule,
+        Ok(_) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "OCR rule not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get OCR rule: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get OCR rule: {}", e)
+            })));
+        }
+    };
+
+    rule.pattern = body.pattern.clone();
+    rule.replacement = body.replacement.clone();
+    if let Some(position) = body.position {
+        rule.position = position;
+    }
+    if let Some(enabled) = body.enabled {
+        rule.enabled = enabled;
+    }
+    if body.description.is_some() {
+        rule.description = body.description.clone();
+    }
+
+    match db.save_ocr_rule(&rule).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(rule)),
+        Err(e) => {
+            log::error!("Failed to update OCR rule: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to update OCR rule: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn delete_ocr_rule(
+    path: web::Path<(String, String)>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (book_id, rule_id) = path.into_inner();
+
+    match db.get_ocr_rule(&rule_id).await {
+        Ok(Some(rule)) if rule.book_id == book_id => {}
+        Ok(_) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "OCR rule not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get OCR rule: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get OCR rule: {}", e)
+            })));
+        }
+    }
+
+    match db.delete_ocr_rule(&rule_id).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "deleted": rule_id
+        }))),
+        Err(e) => {
+            log::error!("Failed to delete OCR rule: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete OCR rule: {}", e)
+            })))
+        }
+    }
+}
+
+/// Dry-run rules against a sample page and return the resulting diff
+pub async fn preview_ocr_rules(
+    path: web::Path<String>,
+    body: web::Json<OcrRulePreviewRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let text = match (&body.text, body.page_number) {
+        (Some(text), _) => text.clone(),
+        (None, Some(page_number)) => match db.get_page(&book_id, page_number).await {
+            Ok(Some(page)) if page.ocr_text.is_some() => page.ocr_text.unwrap_or_default(),
+            Ok(_) => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("No OCR text for page {}", page_number)
+                })));
+            }
+            Err(e) => {
+                log::error!("Failed to get page: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to get page: {}", e)
+                })));
+            }
+        },
+        (None, None) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Provide either text or page_number"
+            })));
+        }
+    };
+
+    let rules = match &body.rule {
+        Some(candidate) => {
+            if let Err(e) = compile_pattern(&candidate.pattern) {
+                return Ok(invalid_pattern(e));
+            }
+            vec![OcrRule {
+                id: String::new(),
+                book_id: book_id.clone(),
+                pattern: candidate.pattern.clone(),
+                replacement: candidate.replacement.clone(),
+                position: 0,
+                enabled: true,
+                description: candidate.description.clone(),
+                created_at: chrono::Utc::now(),
+            }]
+        }
+        None => match db.get_ocr_rules(&book_id).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                log::error!("Failed to list OCR rules: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to list OCR rules: {}", e)
+                })));
+            }
+        },
+    };
+
+    Ok(HttpResponse::Ok().json(preview_rules(&rules, &text)))
+}
+
+// === Extraction Confidence ===
+
+#[derive(Debug, Deserialize)]
+pub struct LowConfidenceQuery {
+    pub threshold: Option<f32>,
+    pub limit: Option<usize>,
+}
+
+/// Problems most likely to contain OCR mistakes, for reviewers to check first
+pub async fn list_low_confidence_problems(
+    path: web::Path<String>,
+    query: web::Query<LowConfidenceQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let threshold = query.threshold.unwrap_or(LOW_CONFIDENCE_THRESHOLD);
+    let limit = query.limit.unwrap_or(50).min(500);
+
+    match db.get_low_confidence_problems(&book_id, threshold, limit).await {
+        Ok(problems) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "threshold": threshold,
+            "count": problems.len(),
+            "problems": problems,
+        }))),
+        Err(e) => {
+            log::error!("Failed to list low-confidence problems: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list problems: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookSolutionsQuery {
+    /// Provider name, `verified`, or `any` (default)
+    pub provider: Option<String>,
+    /// Only solutions with this recorded answer verdict (`match`, `mismatch`, `unknown`)
+    pub answer_verdict: Option<String>,
+}
+
+/// All solutions of a book, optionally limited to one provider or to verified
+/// ones, with the answer verdicts recorded by answer checks
+pub async fn list_book_solutions(
+    path: web::Path<String>,
+    query: web::Query<BookSolutionsQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let filter = SolutionFilter::from_param(query.provider.as_deref());
+
+    let solutions = async {
+        let solutions = db.get_book_solutions(&book_id, &filter).await?;
+        let verdicts = db.get_answer_verdicts(&book_id).await?;
+        anyhow::Ok((solutions, verdicts))
+    };
+    match solutions.await {
+        Ok((mut solutions, verdicts)) => {
+            if let Some(wanted) = query.answer_verdict.as_deref().map(AnswerVerdict::parse) {
+                solutions.retain(|s| verdicts.get(&s.id) == Some(&wanted));
+            }
+            let answer_verdicts: BTreeMap<&str, AnswerVerdict> = solutions
+                .iter()
+                .filter_map(|s| verdicts.get(&s.id).map(|v| (s.id.as_str(), *v)))
+                .collect();
+            let mut by_provider: BTreeMap<&str, usize> = BTreeMap::new();
+            for s in &solutions {
+                *by_provider.entry(s.provider.as_str()).or_default() += 1;
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "book_id": book_id,
+                "provider": query.provider.as_deref().unwrap_or("any"),
+                "count": solutions.len(),
+                "by_provider": by_provider,
+                "answer_verdicts": answer_verdicts,
+                "solutions": solutions,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list solutions: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list solutions: {}", e)
+            })))
+        }
+    }
+}
+
+// === Ingestion Wizard ===
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyzeBookQuery {
+    /// Create the suggested chapters right away
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Page count of a book and the text of every page that has some: cached
+/// OCR where available, the PDF text layer otherwise. Stores the page count
+/// when the file disagrees with the book record.
+async fn book_page_texts(
+    db: &Database,
+    file_service: &FileService,
+    book: &Book,
+) -> Result<(u32, Vec<(u32, String)>), tokio::task::JoinError> {
+    let file = file_service.book_file(&book.id);
+    let (count, text) = tokio::task::spawn_blocking({
+        let file_service = file_service.clone();
+        move || (file_service.get_pdf_page_count(&file), file_service.extract_pdf_text(&file))
+    })
+    .await?;
+    let total_pages = count.unwrap_or_else(|e| {
+        log::warn!("Failed to get PDF page count: {}, using {}", e, book.total_pages);
+        book.total_pages
+    });
+    let text_layer = text.unwrap_or_else(|e| {
+        log::warn!("No PDF text layer for {}: {}", book.id, e);
+        Vec::new()
+    });
+
+    if total_pages != book.total_pages
+        && let Err(e) = db.set_book_total_pages(&book.id, total_pages).await
+    {
+        log::warn!("Failed to store page count of {}: {}", book.id, e);
+    }
+
+    let mut texts: BTreeMap<u32, String> = text_layer.into_iter().collect();
+    match db.get_pages_by_book(&book.id).await {
+        Ok(pages) => {
+            for page in pages {
+                if let Some(text) = page.ocr_text.filter(|t| !t.trim().is_empty()) {
+                    texts.insert(page.page_number, text);
+                }
+            }
+        }
+        Err(e) => log::warn!("Failed to load OCR pages of {}: {}", book.id, e),
+    }
+    Ok((total_pages, texts.into_iter().collect()))
+}
+
+/// Suggest an ingestion plan for a book: content page range, chapters, answer
+/// pages and the batch OCR requests covering them. Page texts come from the
+/// cached OCR where available and from the PDF text layer otherwise.
+pub async fn analyze_book(
+    path: web::Path<String>,
+    query: web::Query<AnalyzeBookQuery>,
+    db: web::Data<Database>,
+    file_service: web::Data<FileService>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(b)) => b,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+
+    let (total_pages, pages) = match book_page_texts(&db, &file_service, &book).await {
+        Ok(texts) => texts,
+        Err(e) => {
+            log::error!("Failed to analyze PDF: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to analyze PDF: {}", e)
+            })));
+        }
+    };
+    let headings = HeadingDetector::for_book(&db, &book_id).await;
+    let plan = analyze_pages(&book_id, total_pages, &pages, &headings);
+
+    if query.apply {
+        for planned in &plan.chapters {
+            let chapter = Chapter {
+                id: planned.chapter_id.clone(),
+                book_id: book_id.clone(),
+                number: planned.number,
+                title: planned.title.clone(),
+                description: Some(format!("Pages {}-{}", planned.start_page, planned.end_page)),
+                problem_count: 0,
+                theory_count: 0,
+                created_at: chrono::Utc::now(),
+            };
+            if let Err(e) = db.create_chapter(&chapter).await {
+                log::error!("Failed to create chapter {}: {}", chapter.id, e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to create chapters: {}", e)
+                })));
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "plan": plan,
+        "applied": query.apply,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProbeBookQuery {
+    /// Pages to sample, [`DEFAULT_PROBE_PAGES`] by default
+    pub pages: Option<usize>,
+    /// OCR provider to probe instead of the book's own
+    pub provider: Option<String>,
+}
+
+/// OCR and parse a few random content pages and profile the book from them:
+/// numbering style, language, column layout, formula density and the
+/// estimated cost of ingesting all of it. Nothing is stored.
+pub async fn probe_book(
+    path: web::Path<String>,
+    query: web::Query<ProbeBookQuery>,
+    db: web::Data<Database>,
+    file_service: web::Data<FileService>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(b)) => b,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+
+    let settings = db.get_book_settings(&book_id).await.unwrap_or_else(|e| {
+        log::warn!("Failed to load settings of {}: {}", book_id, e);
+        BookSettings::default()
+    });
+    let provider = query.provider.clone().unwrap_or_else(|| settings.ocr_provider().to_string());
+    if provider_registry::capabilities(ProviderKind::Ocr, &provider).is_none() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unknown OCR provider: {}", provider)
+        })));
+    }
+
+    let (total_pages, pages) = match book_page_texts(&db, &file_service, &book).await {
+        Ok(texts) => texts,
+        Err(e) => {
+            log::error!("Failed to analyze PDF: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to analyze PDF: {}", e)
+            })));
+        }
+    };
+    let headings = HeadingDetector::for_book(&db, &book_id).await;
+    let plan = analyze_pages(&book_id, total_pages, &pages, &headings);
+
+    let count = query.pages.unwrap_or(DEFAULT_PROBE_PAGES).clamp(1, MAX_PROBE_PAGES);
+    let sample = sample_pages(&plan, count, &mut rand::thread_rng());
+
+    let file = file_service.book_file(&book_id);
+    let files = book_file_service(&db, &file_service, &book_id).await;
+    let ocr = OcrService::new(&config);
+    let api_key = match book_parser_profile(&db, &book_id).await {
+        ParserProfile::Regex => None,
+        _ => config.mistral_api_key(),
+    };
+    let parser = PageContentParser::new(api_key).timeout(config.provider_timeout());
+    let (mut sampled, mut failed) = (Vec::new(), Vec::new());
+    for page in sample {
+        let started = std::time::Instant::now();
+        let probed = async {
+            let image = match files.generate_preview(&file, page) {
+                Ok(path) => path,
+                Err(_) if config.offline => file_service.page_image(&file, page),
+                Err(e) => anyhow::bail!("Failed to generate preview: {}", e),
+            };
+            let columns = page_columns(&image).unwrap_or_else(|e| {
+                log::debug!("No column layout for page {} of {}: {}", page, book_id, e);
+                None
+            });
+            let ocr_text = ocr.ocr_page(&file, page, &image, &provider, &CancellationToken::new()).await?;
+            let text = postprocess_ocr_text(&db, &book_id, &ocr_text.text).await;
+            let parsed = parser.parse_page(&text, Some(page)).await?;
+            let seconds = started.elapsed().as_secs_f64();
+            anyhow::Ok(ProbedPage::new(page, text, ocr_text.provider, &parsed, columns, seconds))
+        };
+        match probed.await {
+            Ok(probed) => sampled.push(probed),
+            Err(e) => {
+                log::warn!("Failed to probe page {} of {}: {}", page, book_id, e);
+                failed.push(ProbeFailure { page, error: e.to_string() });
+            }
+        }
+    }
+
+    let ai_parsing = config.mistral_api_key().is_some();
+    Ok(HttpResponse::Ok().json(profile(&plan, &settings, &provider, ai_parsing, sampled, failed)))
+}
+
+// === Glossary ===
+
+#[derive(Debug, Deserialize)]
+pub struct GlossaryQuery {
+    /// Ask the LLM to define concepts that only appear in problems
+    #[serde(default)]
+    pub generate: bool,
+    pub provider: Option<String>,
+}
+
+/// Most concepts defined per generation request
+const MAX_GENERATED_TERMS: usize = 30;
+
+/// Glossary of a book built from its definition blocks, plus LLM-written
+/// definitions for concepts the book uses without defining (`?generate=true`)
+pub async fn get_book_glossary(
+    path: web::Path<String>,
+    query: web::Query<GlossaryQuery>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(b)) => b,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+
+    let glossary = async {
+        let entries = build_glossary(&db, &book_id).await?;
+        let (_, problems) = load_book_problems(&db, &book_id).await?;
+        let problems: Vec<Problem> = problems.into_iter().map(|(_, p)| p).collect();
+        anyhow::Ok((entries, problems))
+    };
+    let (mut entries, problems) = match glossary.await {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Failed to build glossary: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to build glossary: {}", e)
+            })));
+        }
+    };
+    let mut undefined = undefined_concepts(&problems, &entries);
+
+    let mut generated = 0;
+    if query.generate && !undefined.is_empty() {
+        let solver = match AISolver::new(&config) {
+            Ok(s) => s,
+            Err(e) => {
+                return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "error": format!("AI solver not available: {}", e)
+                })));
+            }
+        };
+
+        let terms: Vec<String> = undefined.iter().take(MAX_GENERATED_TERMS).cloned().collect();
+        let subject = match &book.subject {
+            Some(subject) => format!("{} ({})", book.title, subject),
+            None => book.title.clone(),
+        };
+        let text = match solver.define_terms(&terms, query.provider.as_deref(), Some(&subject)).await {
+            Ok(t) => t,
+            Err(e) => {
+                log::error!("Failed to generate glossary definitions: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to generate definitions: {}", e)
+                })));
+            }
+        };
+
+        // Keep only definitions of the terms asked for
+        let requested: Vec<String> = terms.iter().map(|t| term_key(t)).collect();
+        for (term, definition) in parse_definitions(&text) {
+            if !requested.contains(&term_key(&term)) {
+                continue;
+            }
+            match db.save_generated_definition(&book_id, &term, &definition).await {
+                Ok(()) => generated += 1,
+                Err(e) => log::warn!("Failed to save definition of {}: {}", term, e),
+            }
+        }
+
+        match build_glossary(&db, &book_id).await {
+            Ok(e) => entries = e,
+            Err(e) => log::warn!("Failed to reload glossary: {}", e),
+        }
+        undefined = undefined_concepts(&problems, &entries);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "book_id": book_id,
+        "count": entries.len(),
+        "generated": generated,
+        "undefined": undefined,
+        "entries": entries,
+    })))
+}
+
+async fn book_exists(db: &Database, book_id: &str) -> Result<Option<HttpResponse>, Error> {
+    match db.get_book(book_id).await {
+        Ok(Some(_)) => Ok(None),
+        Ok(None) => Ok(Some(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Book not found"
+        })))),
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            Ok(Some(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            }))))
+        }
+    }
+}
+
+/// Problems per page for the viewer's density bar. `counts[i]` is the
+/// number of problems starting on page `i + 1`.
+pub async fn get_problem_density(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(book)) => book,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+
+    match db.get_problem_density(&book_id).await {
+        Ok(density) => {
+            let last_page = density.last().map_or(0, |(page, _)| *page);
+            let mut counts = vec![0u32; book.total_pages.max(last_page) as usize];
+            for (page, count) in density {
+                if page > 0 {
+                    counts[page as usize - 1] = count;
+                }
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "book_id": book_id,
+                "total_pages": counts.len(),
+                "total_problems": counts.iter().sum::<u32>(),
+                "max_per_page": counts.iter().max().copied().unwrap_or(0),
+                "counts": counts,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to get problem density: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem density: {}", e)
+            })))
+        }
+    }
+}
+
+/// LaTeX macros of a book: its own definitions plus the effective set
+/// (built-in Russian notation overridden by the book) used for rendering
+pub async fn get_book_latex_macros(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    match db.get_book_macros(&book_id).await {
+        Ok(macros) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "effective": macros.effective(),
+            "macros": macros.macros,
+            "preamble": macros.preamble,
+        }))),
+        Err(e) => {
+            log::error!("Failed to get LaTeX macros: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get LaTeX macros: {}", e)
+            })))
+        }
+    }
+}
+
+/// Replace a book's LaTeX macros and extra preamble
+pub async fn update_book_latex_macros(
+    path: web::Path<String>,
+    body: web::Json<BookMacros>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    let mut macros = body.into_inner();
+    macros.preamble = macros.preamble.filter(|p| !p.trim().is_empty());
+    if let Err(e) = macros.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.save_book_macros(&book_id, &macros).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "effective": macros.effective(),
+            "macros": macros.macros,
+            "preamble": macros.preamble,
+        }))),
+        Err(e) => {
+            log::error!("Failed to save LaTeX macros: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save LaTeX macros: {}", e)
+            })))
+        }
+    }
+}
+
+/// A book's chapter heading patterns and the defaults they extend
+pub async fn get_book_heading_patterns(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    match db.get_heading_overrides(&book_id).await {
+        Ok(overrides) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "defaults": DEFAULT_HEADING_PATTERNS,
+            "patterns": overrides.patterns,
+            "replace_defaults": overrides.replace_defaults,
+        }))),
+        Err(e) => {
+            log::error!("Failed to get heading patterns: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get heading patterns: {}", e)
+            })))
+        }
+    }
+}
+
+/// Replace a book's chapter heading patterns. Each pattern must capture the
+/// chapter number as `(?P<num>...)`.
+pub async fn update_book_heading_patterns(
+    path: web::Path<String>,
+    body: web::Json<HeadingOverrides>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    let mut overrides = body.into_inner();
+    overrides.patterns.retain(|p| !p.trim().is_empty());
+    if let Err(e) = overrides.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.save_heading_overrides(&book_id, &overrides).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "defaults": DEFAULT_HEADING_PATTERNS,
+            "patterns": overrides.patterns,
+            "replace_defaults": overrides.replace_defaults,
+        }))),
+        Err(e) => {
+            log::error!("Failed to save heading patterns: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save heading patterns: {}", e)
+            })))
+        }
+    }
+}
+
+/// A book's pipeline settings, defaults filled in
+pub async fn get_book_settings(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    match db.get_book_settings(&book_id).await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "settings": settings,
+        }))),
+        Err(e) => {
+            log::error!("Failed to get book settings: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book settings: {}", e)
+            })))
+        }
+    }
+}
+
+/// Replace a book's pipeline settings; options left out reset to defaults
+pub async fn update_book_settings(
+    path: web::Path<String>,
+    body: web::Json<BookSettings>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    if let Some(response) = book_exists(&db, &book_id).await? {
+        return Ok(response);
+    }
+
+    let mut settings = body.into_inner();
+    settings.language = settings.language.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    settings.ocr_provider = settings.ocr_provider.filter(|p| !p.trim().is_empty());
+    if let Err(e) = settings.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.save_book_settings(&book_id, &settings).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "settings": settings,
+        }))),
+        Err(e) => {
+            log::error!("Failed to save book settings: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save book settings: {}", e)
+            })))
+        }
+    }
+}
+
+// === Dashboard ===
+
+async fn load_chapter_problems(db: &Database, book_id: &str) -> anyhow::Result<Vec<(String, Vec<Problem>)>> {
+    let mut chapters = Vec::new();
+    for chapter in db.get_chapters_by_book(book_id).await? {
+        let problems = db.get_problems_by_chapter(&chapter.id).await?;
+        chapters.push((chapter.id, problems));
+    }
+    Ok(chapters)
+}
+
+/// Everything the book page shows in one response: the book and its PDF
+/// metadata, coverage, recent jobs, validation issues, top tags and the
+/// number of bookmarks. Parts that fail to load are `null` (bookmarks `0`)
+/// and logged rather than failing the whole dashboard.
+pub async fn get_book_dashboard(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    file_service: web::Data<FileService>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(book)) => book,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+
+    let metadata = tokio::task::spawn_blocking({
+        let file_service = file_service.get_ref().clone();
+        let file = file_service.book_file(&book_id);
+        move || file_service.get_pdf_metadata(&file)
+    });
+    let (chapters, pages, jobs, bookmarks) = tokio::join!(
+        load_chapter_problems(&db, &book_id),
+        db.get_pages_by_book(&book_id),
+        db.get_book_job_history(&book_id, DASHBOARD_RECENT_JOBS),
+        db.count_book_bookmarks(&book_id),
+    );
+    let metadata = match metadata.await {
+        Ok(Ok(metadata)) => Some(metadata),
+        Ok(Err(e)) => {
+            log::warn!("No PDF metadata for {}: {}", book_id, e);
+            None
+        }
+        Err(e) => {
+            log::error!("Failed to read PDF metadata of {}: {}", book_id, e);
+            None
+        }
+    };
+
+    let chapters = chapters.unwrap_or_else(|e| {
+        log::error!("Failed to load problems of {}: {}", book_id, e);
+        Vec::new()
+    });
+    let ocr_pages = match pages {
+        Ok(pages) => pages.iter().filter(|p| p.ocr_text.as_deref().is_some_and(|t| !t.trim().is_empty())).count(),
+        Err(e) => {
+            log::error!("Failed to load pages of {}: {}", book_id, e);
+            0
+        }
+    };
+    let total_pages = metadata.as_ref().map_or(book.total_pages, |m| m.pages);
+    let problems: Vec<&Problem> = chapters.iter().flat_map(|(_, problems)| problems).collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "book": book,
+        "metadata": metadata,
+        "coverage": coverage(total_pages, ocr_pages, &chapters),
+        "recent_jobs": jobs.map_err(|e| log::error!("Failed to load jobs of {}: {}", book_id, e)).ok(),
+        "validation": validation_summary(&chapters),
+        "top_tags": top_tags(&problems, DASHBOARD_TOP_TAGS),
+        "bookmarks": bookmarks.unwrap_or_else(|e| {
+            log::error!("Failed to count bookmarks of {}: {}", book_id, e);
+            0
+        }),
+    })))
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use actix_web::{web, Error, HttpResponse};
+
+use crate::services::calibration::recalibrate;
+use crate::services::database::Database;
+
+/// Recalibrate difficulty from all graded answers now rather than on the
+/// next hourly run
+pub async fn run_calibration(db: web::Data<Database>) -> Result<HttpResponse, Error> {
+    match recalibrate(&db, chrono::Utc::now()).await {
+        Ok(calibrations) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "calibrated": calibrations.len(),
+        }))),
+        Err(e) => {
+            log::error!("Failed to calibrate difficulty: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to calibrate difficulty: {}", e)
+            })))
+        }
+    }
+}
+
+/// Calibrated problems of a book next to their static difficulty, the ones
+/// that proved hardest first
+pub async fn get_book_calibration(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let calibrated = async {
+        let calibrations = db.get_calibrations(Some(&book_id)).await?;
+        let mut problems = HashMap::new();
+        for chapter in db.get_chapters_by_book(&book_id).await? {
+            for problem in db.get_problems_by_chapter(&chapter.id).await? {
+                if calibrations.contains_key(&problem.id) {
+                    problems.insert(problem.id.clone(), problem);
+                }
+            }
+        }
+        anyhow::Ok((calibrations, problems))
+    };
+
+    match calibrated.await {
+        Ok((calibrations, problems)) => {
+            let mut calibrations: Vec<_> = calibrations.into_values().collect();
+            calibrations.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+            let entries: Vec<serde_json::Value> = calibrations
+                .iter()
+                .map(|c| {
+                    let problem = problems.get(&c.problem_id);
+                    serde_json::json!({
+                        "problem_id": c.problem_id,
+                        "display_name": problem.map(|p| p.display_name.as_str()),
+                        "static_difficulty": problem.and_then(|p| p.difficulty),
+                        "difficulty": c.difficulty,
+                        "rating": c.rating,
+                        "attempts": c.attempts,
+                        "success_rate": c.success_rate,
+                        "median_seconds": c.median_seconds,
+                        "calibrated_at": c.calibrated_at,
+                    })
+                })
+                .collect();
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "book_id": book_id,
+                "count": entries.len(),
+                "problems": entries,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to get calibration of {}: {}", book_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get calibration: {}", e)
+            })))
+        }
+    }
+}
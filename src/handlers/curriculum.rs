@@ -0,0 +1,204 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::models::Problem;
+use crate::services::auto_tagger::AutoTagger;
+use crate::services::curriculum::{coverage_report, match_standards, CurriculumStandard, DEFAULT_FRAMEWORK};
+use crate::services::database::Database;
+
+#[derive(Debug, Deserialize)]
+pub struct FrameworkQuery {
+    pub framework: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StandardTagResult {
+    pub problem_id: String,
+    pub tags: Vec<String>,
+    /// `framework:code` of the standards the tags map to
+    pub codes: Vec<String>,
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+/// Stored standards, optionally of one framework
+pub async fn list_curricula(
+    query: web::Query<FrameworkQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.get_curricula(query.framework.as_deref()).await {
+        Ok(standards) => Ok(HttpResponse::Ok().json(standards)),
+        Err(e) => Ok(internal_error("list curricula", e)),
+    }
+}
+
+/// Add a standard or replace the title and tags of one with the same code
+pub async fn save_curriculum_standard(
+    body: web::Json<CurriculumStandard>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let mut standard = body.into_inner();
+    standard.code = standard.code.trim().to_string();
+    standard.tags = standard.tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+    if let Err(e) = standard.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.save_curriculum_standard(&standard).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(standard)),
+        Err(e) => Ok(internal_error("save standard", e)),
+    }
+}
+
+pub async fn delete_curriculum_standard(
+    path: web::Path<(String, String)>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (framework, code) = path.into_inner();
+    match db.delete_curriculum_standard(&framework, &code).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "deleted": format!("{}:{}", framework, code)
+        }))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Standard not found"
+        }))),
+        Err(e) => Ok(internal_error("delete standard", e)),
+    }
+}
+
+/// Run the auto-tagger over the problems and store the codes their tags
+/// map to, replacing earlier auto-tagged ones
+async fn tag_with_standards(
+    db: &Database,
+    config: &Config,
+    problems: &[Problem],
+) -> anyhow::Result<Vec<StandardTagResult>> {
+    let standards = db.get_curricula(None).await?;
+    let tagger = AutoTagger::new(config);
+
+    let mut results = Vec::new();
+    for tags in tagger.tag_problems(problems).await {
+        let matched = match_standards(&tags.tags, &standards);
+        db.replace_auto_problem_standards(&tags.problem_id, &matched).await?;
+        results.push(StandardTagResult {
+            codes: matched.iter().map(|s| format!("{}:{}", s.framework, s.code)).collect(),
+            tags: tags.tags.into_iter().map(|t| t.name).collect(),
+            problem_id: tags.problem_id,
+        });
+    }
+    Ok(results)
+}
+
+/// Tag one problem with curriculum codes
+pub async fn auto_tag_problem_standards(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    let problem = match db.get_problem(&problem_id).await {
+        Ok(Some(problem)) => problem,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Problem not found"
+            })));
+        }
+        Err(e) => return Ok(internal_error("get problem", e)),
+    };
+
+    match tag_with_standards(&db, &config, std::slice::from_ref(&problem)).await {
+        Ok(mut results) => Ok(HttpResponse::Ok().json(results.pop())),
+        Err(e) => Ok(internal_error("tag problem", e)),
+    }
+}
+
+/// Tag every problem of a chapter, sub-problems included, with curriculum codes
+pub async fn auto_tag_chapter_standards(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let chapter_id = path.into_inner();
+    match db.get_chapter(&chapter_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Chapter not found"
+            })));
+        }
+        Err(e) => return Ok(internal_error("get chapter", e)),
+    }
+
+    let problems = match db.get_problems_by_chapter(&chapter_id).await {
+        Ok(problems) => problems,
+        Err(e) => return Ok(internal_error("get problems", e)),
+    };
+
+    match tag_with_standards(&db, &config, &problems).await {
+        Ok(results) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "chapter_id": chapter_id,
+            "tagged": results.len(),
+            "results": results,
+        }))),
+        Err(e) => Ok(internal_error("tag chapter", e)),
+    }
+}
+
+pub async fn get_problem_standards(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    match db.get_problem_standards(&problem_id).await {
+        Ok(standards) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "problem_id": problem_id,
+            "standards": standards,
+        }))),
+        Err(e) => Ok(internal_error("get problem standards", e)),
+    }
+}
+
+/// Which standards of a framework (`?framework=`, default `fgos`) the
+/// book's problems exercise, and which none of them do
+pub async fn get_book_standards_coverage(
+    path: web::Path<String>,
+    query: web::Query<FrameworkQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    match db.get_book(&book_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => return Ok(internal_error("get book", e)),
+    }
+
+    let framework = query.framework.as_deref().unwrap_or(DEFAULT_FRAMEWORK);
+    let report = async {
+        let standards = db.get_curricula(Some(framework)).await?;
+        let tagged = db.get_book_standard_tags(&book_id, framework).await?;
+        let mut problem_count = 0;
+        for chapter in db.get_chapters_by_book(&book_id).await? {
+            problem_count += db.get_problems_by_chapter(&chapter.id).await?.len();
+        }
+        anyhow::Ok(coverage_report(framework, &standards, &tagged, problem_count))
+    };
+
+    match report.await {
+        Ok(report) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "coverage": report,
+        }))),
+        Err(e) => Ok(internal_error("build coverage report", e)),
+    }
+}
@@ -0,0 +1,142 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+use tera::Context;
+
+use crate::config::Config;
+use crate::handlers::textbook::katex_macros;
+use crate::models::SolutionFilter;
+use crate::services::database::Database;
+use crate::services::embed::{problem_id_from_url, problem_oembed};
+use crate::services::templates::Templates;
+
+#[derive(Debug, Deserialize)]
+pub struct EmbedQuery {
+    /// Show the problem's best solution under it
+    #[serde(default)]
+    pub solution: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OEmbedQuery {
+    pub url: String,
+    pub format: Option<String>,
+    pub maxwidth: Option<u32>,
+    pub maxheight: Option<u32>,
+}
+
+/// oEmbed discovery URL for a problem page
+pub(crate) fn oembed_url(config: &Config, problem_id: &str) -> String {
+    let base = config.base_url.trim_end_matches('/');
+    let page = format!("{}/problems/{}", base, urlencoding::encode(problem_id));
+    format!("{}/oembed?format=json&url={}", base, urlencoding::encode(&page))
+}
+
+/// A problem as a small standalone page for an iframe
+pub async fn embed_problem(
+    path: web::Path<String>,
+    query: web::Query<EmbedQuery>,
+    tmpl: web::Data<Templates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    let problem = match db.get_problem_with_subs(&problem_id).await {
+        Ok(Some(problem)) => problem,
+        Ok(None) => return Ok(HttpResponse::NotFound().body("Problem not found")),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Database error"));
+        }
+    };
+    let book_id = problem.chapter_id.split(':').next().unwrap_or_default().to_string();
+    let book_title = match db.get_book(&book_id).await {
+        Ok(book) => book.map(|b| b.title),
+        Err(e) => {
+            log::warn!("Failed to get book {}: {}", book_id, e);
+            None
+        }
+    };
+    let solution = if query.solution {
+        db.get_filtered_solutions(std::slice::from_ref(&problem.id), &SolutionFilter::Any)
+            .await
+            .map_err(|e| {
+                log::error!("Failed to get solution of {}: {}", problem.id, e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?
+            .remove(&problem.id)
+            .map(|s| s.content)
+    } else {
+        None
+    };
+
+    let mut context = Context::new();
+    context.insert("problem", &problem);
+    context.insert("solution", &solution);
+    context.insert("book_title", &book_title.unwrap_or(book_id.clone()));
+    context.insert(
+        "page_url",
+        &format!("{}/problems/{}", config.base_url.trim_end_matches('/'), urlencoding::encode(&problem.id)),
+    );
+    context.insert("oembed_url", &oembed_url(&config, &problem.id));
+    context.insert("katex_macros", &katex_macros(&db, &book_id).await);
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    context.insert("csp_nonce", &nonce);
+    let rendered = tmpl.render("textbook/embed.html", &context).map_err(|e| {
+        log::error!("Template error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        // Meant to be framed by other sites, so scripts are limited to KaTeX
+        // and the page's own render call
+        .append_header(("Content-Security-Policy", embed_csp(&nonce)))
+        .body(rendered))
+}
+
+/// Where the embed page may load scripts from
+const KATEX_ASSETS: &str = "https://cdn.jsdelivr.net/npm/katex@0.16.9/";
+
+fn embed_csp(nonce: &str) -> String {
+    format!(
+        "frame-ancestors *; script-src 'self' {} 'nonce-{}'; object-src 'none'; base-uri 'none'",
+        KATEX_ASSETS, nonce
+    )
+}
+
+/// oEmbed endpoint for problem links of this instance
+pub async fn oembed(
+    query: web::Query<OEmbedQuery>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    // The spec's answer to a format the provider doesn't offer
+    if query.format.as_deref().is_some_and(|f| f != "json") {
+        return Ok(HttpResponse::NotImplemented().json(serde_json::json!({
+            "error": "Only format=json is supported"
+        })));
+    }
+    let Some(problem_id) = problem_id_from_url(&query.url, &config.base_url) else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Not a problem link of this instance"
+        })));
+    };
+    match db.get_problem(&problem_id).await {
+        Ok(Some(problem)) => Ok(HttpResponse::Ok().json(problem_oembed(
+            &config.base_url,
+            &problem.id,
+            &problem.display_name,
+            query.maxwidth,
+            query.maxheight,
+        ))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem {}: {}", problem_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })))
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::services::attachments::AttachmentStorage;
+use crate::services::database::Database;
+use crate::services::export::Exporter;
+use crate::services::export_diff::diff_snapshots;
+use crate::services::export_history::ExportRun;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportDiffQuery {
+    pub book: String,
+    /// Run id from the `X-Export-Run` header of an export
+    pub from: String,
+    /// Later run; the book as it is now when left out
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportListQuery {
+    pub book: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+/// The run, or the response to send when it isn't an export of `book_id`
+async fn load_run(db: &Database, run_id: &str, book_id: &str) -> Result<ExportRun, HttpResponse> {
+    match db.get_export_run(run_id).await {
+        Ok(Some(run)) if run.book_id == book_id => Ok(run),
+        Ok(_) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Export run {} of {} not found", run_id, book_id)
+        }))),
+        Err(e) => Err(internal_error("get export run", e)),
+    }
+}
+
+fn run_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Export run not found"
+    }))
+}
+
+/// Past exports, newest first: `?book=algebra-7&limit=50`
+pub async fn list_exports(
+    query: web::Query<ExportListQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    match db.list_export_runs(query.book.as_deref(), limit).await {
+        Ok(runs) => Ok(HttpResponse::Ok().json(runs)),
+        Err(e) => Ok(internal_error("list exports", e)),
+    }
+}
+
+pub async fn get_export(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.get_export_run(&path.into_inner()).await {
+        Ok(Some(run)) => Ok(HttpResponse::Ok().json(run)),
+        Ok(None) => Ok(run_not_found()),
+        Err(e) => Ok(internal_error("get export run", e)),
+    }
+}
+
+/// The file an export produced, exactly as it was sent
+pub async fn download_export(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let run = match db.get_export_run(&path.into_inner()).await {
+        Ok(Some(run)) => run,
+        Ok(None) => return Ok(run_not_found()),
+        Err(e) => return Ok(internal_error("get export run", e)),
+    };
+    let (Some(key), Some(file_name)) = (&run.storage_key, &run.file_name) else {
+        // Failed, interrupted, pruned, or recorded before files were kept
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Export {} has no stored file", run.id)
+        })));
+    };
+
+    match AttachmentStorage::from_config(&config).get(key).await {
+        Ok(data) => Ok(HttpResponse::Ok()
+            .content_type(run.content_type.as_deref().unwrap_or("application/octet-stream"))
+            .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+            .append_header(("X-Export-Checksum", run.checksum.clone().unwrap_or_default()))
+            .body(data)),
+        Err(e) => Ok(internal_error("read export file", e)),
+    }
+}
+
+/// Problem counts per chapter and added, removed and changed problems
+/// between two exports of a book, e.g. `?book=algebra-7&from=<run>&to=<run>`
+pub async fn diff_exports(
+    query: web::Query<ExportDiffQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let from = match load_run(&db, &query.from, &query.book).await {
+        Ok(run) => run,
+        Err(response) => return Ok(response),
+    };
+    let to = match &query.to {
+        Some(run_id) => match load_run(&db, run_id, &query.book).await {
+            Ok(run) => Some(run),
+            Err(response) => return Ok(response),
+        },
+        None => None,
+    };
+
+    let problems = async {
+        let from_problems = db.get_export_run_problems(&from.id).await?;
+        let to_problems = match &to {
+            Some(run) => db.get_export_run_problems(&run.id).await?,
+            // Current content of the same book or chapter, without export filters
+            None => Exporter::new(db.get_ref().clone()).snapshot(&from.book_id, from.chapter_id.as_deref()).await?,
+        };
+        anyhow::Ok((from_problems, to_problems))
+    };
+
+    match problems.await {
+        Ok((from_problems, to_problems)) => {
+            Ok(HttpResponse::Ok().json(diff_snapshots(from, &from_problems, to, &to_problems)))
+        }
+        Err(e) => Ok(internal_error("diff exports", e)),
+    }
+}
@@ -0,0 +1,50 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::services::database::Database;
+use crate::services::feed::{merge_entries, render_atom, FeedEntry, DEFAULT_FEED_ENTRIES, MAX_FEED_ENTRIES};
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub limit: Option<usize>,
+}
+
+/// Atom feed of a book's newly parsed problems and generated solutions
+pub async fn book_feed(
+    path: web::Path<String>,
+    query: web::Query<FeedQuery>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_FEED_ENTRIES).clamp(1, MAX_FEED_ENTRIES);
+
+    let entries = async {
+        let Some(book) = db.get_book(&book_id).await? else { return anyhow::Ok(None) };
+        let problems = db.recent_book_problems(&book_id, limit).await?;
+        let solutions = db.recent_book_solutions(&book_id, limit).await?;
+
+        let mut entries: Vec<FeedEntry> = problems.iter().map(|p| FeedEntry::problem(&config.base_url, p)).collect();
+        entries.extend(solutions.iter().map(|(s, name)| FeedEntry::solution(&config.base_url, s, name)));
+        Ok(Some((book, merge_entries(entries, limit))))
+    };
+
+    match entries.await {
+        Ok(Some((book, entries))) => {
+            let feed_url = format!(
+                "{}/feeds/books/{}.atom",
+                config.base_url.trim_end_matches('/'),
+                urlencoding::encode(&book.id)
+            );
+            Ok(HttpResponse::Ok()
+                .content_type("application/atom+xml; charset=utf-8")
+                .body(render_atom(&book, &config.base_url, &feed_url, &entries)))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().body("Book not found")),
+        Err(e) => {
+            log::error!("Failed to build feed of {}: {}", book_id, e);
+            Ok(HttpResponse::InternalServerError().body("Failed to build feed"))
+        }
+    }
+}
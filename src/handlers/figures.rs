@@ -0,0 +1,152 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::models::Problem;
+use crate::services::ai_solver::AISolver;
+use crate::services::database::Database;
+use crate::services::figure::{is_geometry_problem, reconstruct_figure, ProblemFigure};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReconstructFigureRequest {
+    pub provider: Option<String>,
+    /// Draw again even when the problem already has a figure
+    #[serde(default)]
+    pub refresh: bool,
+    /// Draw problems that don't read as geometry too
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// The problem, or the response to send when it can't be loaded
+async fn load_problem(db: &Database, problem_id: &str) -> Result<Problem, HttpResponse> {
+    match db.get_problem(problem_id).await {
+        Ok(Some(problem)) => Ok(problem),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })))
+        }
+    }
+}
+
+fn figure_json(figure: &ProblemFigure) -> serde_json::Value {
+    serde_json::json!({
+        "figure": figure,
+        "svg": figure.description.to_svg(),
+        "tikz": figure.description.to_tikz(),
+    })
+}
+
+/// Reconstruct the drawing of a geometry problem from its statement (experimental)
+pub async fn reconstruct_problem_figure(
+    path: web::Path<String>,
+    body: Option<web::Json<ReconstructFigureRequest>>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    let request = body.map(|b| b.into_inner()).unwrap_or_default();
+
+    let problem = match load_problem(&db, &problem_id).await {
+        Ok(problem) => problem,
+        Err(response) => return Ok(response),
+    };
+    if !request.force && !is_geometry_problem(&problem) {
+        return Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "Problem doesn't look like a geometry problem; pass \"force\": true to draw it anyway"
+        })));
+    }
+
+    if !request.refresh {
+        match db.get_problem_figure(&problem_id).await {
+            Ok(Some(figure)) => return Ok(HttpResponse::Ok().json(figure_json(&figure))),
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to read figure of {}: {}", problem_id, e),
+        }
+    }
+
+    let solver = match AISolver::new(&config) {
+        Ok(s) => s,
+        Err(e) => {
+            return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "error": format!("AI solver not available: {}", e)
+            })));
+        }
+    };
+
+    match reconstruct_figure(&db, &solver, &problem, request.provider.as_deref()).await {
+        Ok(figure) => Ok(HttpResponse::Created().json(figure_json(&figure))),
+        Err(e) => {
+            log::error!("Failed to reconstruct figure of {}: {}", problem_id, e);
+            Ok(HttpResponse::BadGateway().json(serde_json::json!({
+                "error": format!("Failed to reconstruct figure: {}", e)
+            })))
+        }
+    }
+}
+
+/// Stored figure of a problem with its SVG and TikZ
+pub async fn get_problem_figure(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    match db.get_problem_figure(&problem_id).await {
+        Ok(Some(figure)) => Ok(HttpResponse::Ok().json(figure_json(&figure))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem has no figure"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get figure of {}: {}", problem_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get figure: {}", e)
+            })))
+        }
+    }
+}
+
+/// The figure as an SVG image, for `<img>` tags
+pub async fn get_problem_figure_svg(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    match db.get_problem_figure(&problem_id).await {
+        Ok(Some(figure)) => Ok(HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .body(figure.description.to_svg())),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem has no figure"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get figure of {}: {}", problem_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get figure: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn delete_problem_figure(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    match db.delete_problem_figure(&problem_id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem has no figure"
+        }))),
+        Err(e) => {
+            log::error!("Failed to delete figure of {}: {}", problem_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete figure: {}", e)
+            })))
+        }
+    }
+}
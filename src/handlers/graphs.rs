@@ -0,0 +1,83 @@
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::services::graph_render::{GraphFormat, GraphRenderService};
+use crate::services::plot::{function_body, PlotSpec, MAX_FORMULA_CHARS, MAX_PLOT_FUNCTIONS};
+
+#[derive(Debug, Deserialize)]
+pub struct GraphQuery {
+    /// One or more functions separated by `;`, e.g. `y = 2x + 1; x^2`
+    pub expr: String,
+    pub format: Option<String>,
+    pub x_min: Option<f64>,
+    pub x_max: Option<f64>,
+    pub y_min: Option<f64>,
+    pub y_max: Option<f64>,
+    pub title: Option<String>,
+}
+
+/// Graph of the functions in `expr` as SVG (default) or PNG, for `<img>`
+/// tags in the viewer and in exported pages
+pub async fn render_graph(
+    req: HttpRequest,
+    query: web::Query<GraphQuery>,
+    graphs: web::Data<GraphRenderService>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    let Some(format) = GraphFormat::from_name(query.format.as_deref().unwrap_or("svg")) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid format. Use: svg, png"
+        })));
+    };
+
+    let functions: Vec<String> = query
+        .expr
+        .split(';')
+        .map(function_body)
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+        .collect();
+    if functions.is_empty() || functions.len() > MAX_PLOT_FUNCTIONS {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("expr needs 1 to {} functions separated by ';'", MAX_PLOT_FUNCTIONS)
+        })));
+    }
+    // The endpoint is public; refuse long input before it reaches the parser
+    if functions.iter().any(|f| f.chars().count() > MAX_FORMULA_CHARS) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Each function must be at most {} characters", MAX_FORMULA_CHARS)
+        })));
+    }
+
+    let defaults = PlotSpec::new(functions);
+    let spec = PlotSpec {
+        x_min: query.x_min.unwrap_or(defaults.x_min),
+        x_max: query.x_max.unwrap_or(defaults.x_max),
+        y_min: query.y_min,
+        y_max: query.y_max,
+        title: query.title,
+        ..defaults
+    };
+
+    let etag = format!("\"{}\"", GraphRenderService::graph_hash(&spec, format));
+    if req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag))
+    {
+        return Ok(HttpResponse::NotModified().append_header((header::ETAG, etag)).finish());
+    }
+
+    match graphs.render(&spec, format).await {
+        Ok(graph) => Ok(HttpResponse::Ok()
+            .content_type(graph.format.mime_type())
+            .append_header((header::ETAG, etag))
+            .append_header((header::CACHE_CONTROL, "public, max-age=86400"))
+            .body(graph.bytes.as_ref().clone())),
+        Err(e) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
+}
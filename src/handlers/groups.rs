@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::services::assignment::normalize_student_name;
+use crate::services::database::Database;
+use crate::services::group::{group_analytics, Group};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupMemberRequest {
+    pub student_name: String,
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+fn group_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Group not found"
+    }))
+}
+
+/// The group, or the response to send when it can't be loaded
+async fn load_group(db: &Database, group_id: &str) -> Result<Group, HttpResponse> {
+    match db.get_group(group_id).await {
+        Ok(Some(group)) => Ok(group),
+        Ok(None) => Err(group_not_found()),
+        Err(e) => Err(internal_error("get group", e)),
+    }
+}
+
+/// Create a group, optionally with its members
+pub async fn create_group(
+    body: web::Json<CreateGroupRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let request = body.into_inner();
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Group name is required"
+        })));
+    }
+
+    let mut group = Group::new(name);
+    for member in &request.members {
+        let Some(member) = normalize_student_name(member) else {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid member name {:?}", member)
+            })));
+        };
+        if !group.members.iter().any(|m| m.to_lowercase() == member.to_lowercase()) {
+            group.members.push(member);
+        }
+    }
+    group.members.sort_by_key(|m| m.to_lowercase());
+
+    match db.create_group(&group).await {
+        Ok(()) => Ok(HttpResponse::Created().json(group)),
+        Err(e) => Ok(internal_error("create group", e)),
+    }
+}
+
+pub async fn list_groups(db: web::Data<Database>) -> Result<HttpResponse, Error> {
+    match db.list_groups().await {
+        Ok(groups) => Ok(HttpResponse::Ok().json(groups)),
+        Err(e) => Ok(internal_error("list groups", e)),
+    }
+}
+
+pub async fn get_group(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match load_group(&db, &path.into_inner()).await {
+        Ok(group) => Ok(HttpResponse::Ok().json(group)),
+        Err(response) => Ok(response),
+    }
+}
+
+/// Delete a group; its assignments and their submissions stay
+pub async fn delete_group(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.delete_group(&path.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(group_not_found()),
+        Err(e) => Ok(internal_error("delete group", e)),
+    }
+}
+
+/// Add a student by the name they submit answers under
+pub async fn add_group_member(
+    path: web::Path<String>,
+    body: web::Json<GroupMemberRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let group_id = path.into_inner();
+    if let Err(response) = load_group(&db, &group_id).await {
+        return Ok(response);
+    }
+    let Some(student_name) = normalize_student_name(&body.student_name) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Student name must be 1-80 characters"
+        })));
+    };
+
+    match db.add_group_member(&group_id, &student_name).await {
+        Ok(true) => Ok(HttpResponse::Created().json(serde_json::json!({ "student_name": student_name }))),
+        Ok(false) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("{} is already in the group", student_name)
+        }))),
+        Err(e) => Ok(internal_error("add group member", e)),
+    }
+}
+
+pub async fn remove_group_member(
+    path: web::Path<(String, String)>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (group_id, student_name) = path.into_inner();
+    match db.remove_group_member(&group_id, student_name.trim()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Member not found"
+        }))),
+        Err(e) => Ok(internal_error("remove group member", e)),
+    }
+}
+
+/// Hand an assignment to the group, so its analytics include it
+pub async fn add_group_assignment(
+    path: web::Path<(String, String)>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (group_id, assignment_id) = path.into_inner();
+    if let Err(response) = load_group(&db, &group_id).await {
+        return Ok(response);
+    }
+    match db.get_assignment(&assignment_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Assignment not found"
+            })));
+        }
+        Err(e) => return Ok(internal_error("get assignment", e)),
+    }
+
+    match db.add_group_assignment(&group_id, &assignment_id).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(internal_error("add group assignment", e)),
+    }
+}
+
+pub async fn remove_group_assignment(
+    path: web::Path<(String, String)>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let (group_id, assignment_id) = path.into_inner();
+    match db.remove_group_assignment(&group_id, &assignment_id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Assignment is not handed to this group"
+        }))),
+        Err(e) => Ok(internal_error("remove group assignment", e)),
+    }
+}
+
+/// Completion of the group's assignments and the topics and answers its
+/// members get wrong most
+pub async fn get_group_analytics(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let group = match load_group(&db, &path.into_inner()).await {
+        Ok(group) => group,
+        Err(response) => return Ok(response),
+    };
+
+    let analytics = async {
+        let mut assignments = Vec::with_capacity(group.assignment_ids.len());
+        let mut problems = HashMap::new();
+        for assignment_id in &group.assignment_ids {
+            let Some(assignment) = db.get_assignment(assignment_id).await? else {
+                continue;
+            };
+            for problem_id in &assignment.problem_ids {
+                if problems.contains_key(problem_id) {
+                    continue;
+                }
+                if let Some(problem) = db.get_problem(problem_id).await? {
+                    problems.insert(problem_id.clone(), problem);
+                }
+            }
+            let submissions = db.get_assignment_submissions(assignment_id).await?;
+            assignments.push((assignment, submissions));
+        }
+        anyhow::Ok(group_analytics(&group, &assignments, &problems))
+    };
+
+    match analytics.await {
+        Ok(analytics) => Ok(HttpResponse::Ok().json(analytics)),
+        Err(e) => Ok(internal_error("build group analytics", e)),
+    }
+}
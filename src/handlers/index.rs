@@ -1,10 +1,36 @@
-use actix_web::{web, Error, HttpResponse};
-use tera::{Context, Tera};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use tera::Context;
 use walkdir::WalkDir;
 
 use crate::config::Config;
+use crate::handlers::preferences::page_preferences;
+use crate::services::database::Database;
+use crate::services::latex_macros::BookMacros;
+use crate::services::templates::Templates;
+use crate::services::FileService;
+use crate::utils::slug::{book_id_of, book_slug};
 
-pub async fn index(tmpl: web::Data<Tera>, config: web::Data<Config>) -> Result<HttpResponse, Error> {
+/// A PDF/EPUB in the resources directory and the id of its book
+#[derive(serde::Serialize)]
+struct ResourceFile {
+    name: String,
+    book_id: String,
+}
+
+/// The theme's CSS variables, loaded by every page after its own styles
+pub async fn theme_css(tmpl: web::Data<Templates>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(tmpl.theme_css())
+}
+
+pub async fn index(
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
+    config: web::Data<Config>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
     let mut context = Context::new();
     let mut files = Vec::new();
 
@@ -16,8 +42,14 @@ pub async fn index(tmpl: web::Data<Tera>, config: web::Data<Config>) -> Result<H
         if path.is_file() {
             if let Some(ext) = path.extension() {
                 if ext == "pdf" || ext == "epub" {
-                    if let Some(fname) = path.file_name().and_then(|n| n.to_str()) {
-                        files.push(fname.to_string());
+                    if let (Some(fname), Some(stem)) = (
+                        path.file_name().and_then(|n| n.to_str()),
+                        path.file_stem().and_then(|n| n.to_str()),
+                    ) {
+                        files.push(ResourceFile {
+                            name: fname.to_string(),
+                            book_id: book_slug(stem),
+                        });
                     }
                 }
             }
@@ -25,6 +57,7 @@ pub async fn index(tmpl: web::Data<Tera>, config: web::Data<Config>) -> Result<H
     }
 
     context.insert("files", &files);
+    context.insert("preferences", &page_preferences(&req, &db).await);
     let rendered = tmpl.render("index.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
@@ -33,13 +66,24 @@ pub async fn index(tmpl: web::Data<Tera>, config: web::Data<Config>) -> Result<H
     Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
 }
 
+/// Viewer of `?book=` (an id); `?file=` links from older pages name the file
 pub async fn view_file(
+    req: HttpRequest,
     query: web::Query<std::collections::HashMap<String, String>>,
-    tmpl: web::Data<Tera>,
+    tmpl: web::Data<Templates>,
+    file_service: web::Data<FileService>,
+    db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
-    let file = query.get("file").cloned().unwrap_or_default();
+    let reference = query.get("book").or_else(|| query.get("file")).cloned().unwrap_or_default();
+    let (book_id, file) = match file_service.resolve_book(&reference) {
+        Some(book) => (book.id, book.file),
+        None => (book_id_of(&reference), reference),
+    };
     let mut context = Context::new();
     context.insert("file", &file);
+    context.insert("book_id", &book_id);
+    context.insert("katex_macros", &BookMacros::default().effective());
+    context.insert("preferences", &page_preferences(&req, &db).await);
 
     let rendered = tmpl.render("pdf_view.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
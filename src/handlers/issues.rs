@@ -0,0 +1,218 @@
+use actix_web::{web, Error, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Deserializer};
+
+use crate::services::database::Database;
+use crate::services::issues::{change_note, Issue, IssueComment, IssueEntity, IssueFilter, IssueStatus};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateIssueRequest {
+    pub entity_type: IssueEntity,
+    pub entity_id: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub assignee: Option<String>,
+    /// Who reports it
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateIssueRequest {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub status: Option<IssueStatus>,
+    /// `null` or `""` unassigns; absent keeps the assignee
+    #[serde(default, deserialize_with = "present")]
+    pub assignee: Option<Option<String>>,
+    /// Who makes the change, for the comment recording it
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueCommentRequest {
+    pub author: Option<String>,
+    pub body: String,
+}
+
+/// Tell a field sent as `null` (`Some(None)`) from one left out (`None`)
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+/// Trimmed text, `None` when empty
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+fn issue_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Issue not found"
+    }))
+}
+
+/// Open an issue about a book, chapter, page, problem or solution
+pub async fn create_issue(
+    body: web::Json<CreateIssueRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let request = body.into_inner();
+    let title = request.title.trim();
+    if title.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Issue title is required"
+        })));
+    }
+
+    let book_id = match db.issue_entity_book(request.entity_type, &request.entity_id).await {
+        Ok(Some(book_id)) => book_id,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("{} {} not found", request.entity_type.as_str(), request.entity_id)
+            })));
+        }
+        Err(e) => return Ok(internal_error("look up issue record", e)),
+    };
+
+    let now = Utc::now();
+    let issue = Issue {
+        id: uuid::Uuid::new_v4().to_string(),
+        entity_type: request.entity_type,
+        entity_id: request.entity_id,
+        book_id,
+        title: title.to_string(),
+        body: non_empty(request.body),
+        status: IssueStatus::Open,
+        assignee: non_empty(request.assignee),
+        created_by: non_empty(request.author),
+        created_at: now,
+        updated_at: now,
+    };
+    match db.save_issue(&issue).await {
+        Ok(()) => Ok(HttpResponse::Created().json(issue)),
+        Err(e) => Ok(internal_error("create issue", e)),
+    }
+}
+
+/// Issues by `?status=open&book=...&entity_type=...&entity_id=...&assignee=...`,
+/// most recently updated first
+pub async fn list_issues(
+    query: web::Query<IssueFilter>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.list_issues(&query).await {
+        Ok(issues) => Ok(HttpResponse::Ok().json(issues)),
+        Err(e) => Ok(internal_error("list issues", e)),
+    }
+}
+
+/// An issue with its comment thread
+pub async fn get_issue(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let issue_id = path.into_inner();
+    let issue = match db.get_issue(&issue_id).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return Ok(issue_not_found()),
+        Err(e) => return Ok(internal_error("get issue", e)),
+    };
+    match db.get_issue_comments(&issue_id).await {
+        Ok(comments) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "issue": issue,
+            "comments": comments,
+        }))),
+        Err(e) => Ok(internal_error("get issue comments", e)),
+    }
+}
+
+/// Change the title, body, status or assignee; status and assignee changes
+/// are recorded in the thread
+pub async fn update_issue(
+    path: web::Path<String>,
+    body: web::Json<UpdateIssueRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let issue_id = path.into_inner();
+    let mut issue = match db.get_issue(&issue_id).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return Ok(issue_not_found()),
+        Err(e) => return Ok(internal_error("get issue", e)),
+    };
+    let request = body.into_inner();
+    let assignee = request.assignee.map(non_empty);
+
+    if let Some(note) = change_note(&issue, request.status, assignee.as_ref()) {
+        let comment = IssueComment::new(&issue.id, non_empty(request.author), note);
+        if let Err(e) = db.add_issue_comment(&comment).await {
+            return Ok(internal_error("record issue change", e));
+        }
+    }
+
+    if let Some(title) = non_empty(request.title) {
+        issue.title = title;
+    }
+    if let Some(body) = request.body {
+        issue.body = non_empty(Some(body));
+    }
+    if let Some(status) = request.status {
+        issue.status = status;
+    }
+    if let Some(assignee) = assignee {
+        issue.assignee = assignee;
+    }
+    issue.updated_at = Utc::now();
+
+    match db.save_issue(&issue).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(issue)),
+        Err(e) => Ok(internal_error("update issue", e)),
+    }
+}
+
+pub async fn add_issue_comment(
+    path: web::Path<String>,
+    body: web::Json<IssueCommentRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let issue_id = path.into_inner();
+    let mut issue = match db.get_issue(&issue_id).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return Ok(issue_not_found()),
+        Err(e) => return Ok(internal_error("get issue", e)),
+    };
+    let request = body.into_inner();
+    let text = request.body.trim();
+    if text.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Comment is empty"
+        })));
+    }
+
+    let comment = IssueComment::new(&issue_id, non_empty(request.author), text.to_string());
+    if let Err(e) = db.add_issue_comment(&comment).await {
+        return Ok(internal_error("add issue comment", e));
+    }
+    // Commenting counts as activity for the "recently updated" order
+    issue.updated_at = comment.created_at;
+    if let Err(e) = db.save_issue(&issue).await {
+        log::warn!("Failed to touch issue {}: {}", issue_id, e);
+    }
+    Ok(HttpResponse::Created().json(comment))
+}
+
+pub async fn delete_issue(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.delete_issue(&path.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(issue_not_found()),
+        Err(e) => Ok(internal_error("delete issue", e)),
+    }
+}
@@ -1,18 +1,26 @@
 use actix_web::{web, HttpResponse, Error};
-use crate::models::MetadataResponse;
+use crate::models::{MetadataResponse, SafeFileName};
+use crate::services::database::Database;
 use crate::services::FileService;
-use log::error;
+use log::{error, warn};
 
 pub async fn get_pdf_metadata(
-    file: web::Path<String>,
+    file: web::Path<SafeFileName>,
     file_service: web::Data<FileService>,
+    db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
-    match file_service.get_pdf_metadata(&file) {
-        Ok(metadata) => Ok(HttpResponse::Ok()
-            .json(MetadataResponse { metadata })),
+    match file_service.get_pdf_metadata(&file_service.book_file(&file)) {
+        Ok(metadata) => {
+            if let Some(book) = file_service.resolve_book(&file)
+                && let Err(e) = db.set_book_total_pages(&book.id, metadata.pages).await
+            {
+                warn!("Failed to store page count of {}: {}", book.id, e);
+            }
+            Ok(HttpResponse::Ok().json(MetadataResponse { metadata }))
+        }
         Err(e) => {
             error!("Failed to get metadata: {}", e);
             Ok(HttpResponse::InternalServerError().body("Failed to get metadata"))
         }
     }
-} 
\ No newline at end of file
+}
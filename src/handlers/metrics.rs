@@ -0,0 +1,48 @@
+use actix_web::{web, Error, HttpResponse};
+
+use crate::services::database::Database;
+use crate::services::FileService;
+use crate::services::solve_cache::cache_stats;
+use crate::utils::command::command_stats;
+
+/// Runtime counters: external command invocations per binary
+pub async fn get_metrics() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "commands": command_stats(),
+    })))
+}
+
+/// AI usage: how many solve requests the cache answered without a provider
+/// call, and how much disk the stored OCR payloads take
+pub async fn get_usage(db: web::Data<Database>, file_service: web::Data<FileService>) -> Result<HttpResponse, Error> {
+    let cached_solutions = match db.count_cached_solutions().await {
+        Ok(count) => count,
+        Err(e) => {
+            log::error!("Failed to count cached solutions: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to count cached solutions: {}", e)
+            })));
+        }
+    };
+
+    let ocr_cache = match web::block(move || file_service.ocr_cache_stats()).await {
+        Ok(Ok(stats)) => stats,
+        Ok(Err(e)) => {
+            log::error!("Failed to read OCR cache stats: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to read OCR cache stats: {}", e)
+            })));
+        }
+        Err(e) => {
+            log::error!("OCR cache stats task failed: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "OCR cache stats task failed"
+            })));
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "solve_cache": cache_stats(cached_solutions),
+        "ocr_cache": ocr_cache,
+    })))
+}
@@ -8,6 +8,25 @@ pub mod textbook;
 pub mod batch;
 pub mod websocket;
 pub mod smart_features;
+pub mod providers;
+pub mod worksheets;
+pub mod books;
+pub mod review;
+pub mod metrics;
+pub mod preferences;
+pub mod attachments;
+pub mod graphs;
+pub mod figures;
+pub mod curriculum;
+pub mod assignments;
+pub mod groups;
+pub mod reports;
+pub mod issues;
+pub mod exports;
+pub mod share;
+pub mod embed;
+pub mod feeds;
+pub mod calibration;
 
 pub use index::*;
 pub use metadata::*;
@@ -19,3 +38,22 @@ pub use textbook::*;
 pub use batch::*;
 pub use websocket::*;
 pub use smart_features::*;
+pub use providers::*;
+pub use worksheets::*;
+pub use books::*;
+pub use review::*;
+pub use metrics::*;
+pub use preferences::*;
+pub use attachments::*;
+pub use graphs::*;
+pub use figures::*;
+pub use curriculum::*;
+pub use assignments::*;
+pub use groups::*;
+pub use reports::*;
+pub use issues::*;
+pub use exports::*;
+pub use share::*;
+pub use embed::*;
+pub use feeds::*;
+pub use calibration::*;
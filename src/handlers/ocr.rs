@@ -1,14 +1,59 @@
 use actix_web::{web, Error, HttpResponse};
 use log::error;
 
+use crate::config::{Config, OFFLINE_ERROR};
 use crate::models::{OcrResponse, PreviewParams};
-use crate::services::{FileService, MistralOcrProvider, OcrProvider};
+use crate::services::book_settings::book_file_service;
+use crate::services::database::Database;
+use crate::services::epub::is_epub;
+use crate::services::{default_ocr_provider, extract_page_text, is_substantial_text, FileService, NO_OCR_PROVIDER};
+use crate::utils::slug::book_id_of;
 
 pub async fn perform_ocr(
     params: web::Path<PreviewParams>,
     file_service: web::Data<FileService>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let preview_path = match file_service.generate_preview(&params.file, params.page) {
+    let file_service = book_file_service(&db, &file_service, &book_id_of(&params.file)).await;
+    let file = file_service.book_file(&params.file);
+    let path = match file_service.resolve_resource(&file) {
+        Ok(path) => path,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(OcrResponse { result: e })),
+    };
+    // EPUB chapters are text already
+    if is_epub(&path) {
+        return Ok(match extract_page_text(&path, params.page.get()) {
+            Ok(text) => HttpResponse::Ok().json(OcrResponse { result: text }),
+            Err(e) => HttpResponse::BadRequest().json(OcrResponse { result: e }),
+        });
+    }
+
+    let provider = default_ocr_provider(&config);
+    if config.offline {
+        match extract_page_text(&path, params.page.get()) {
+            Ok(text) if !text.trim().is_empty() => return Ok(HttpResponse::Ok().json(OcrResponse { result: text })),
+            // Tesseract still reads the rendered page
+            Ok(_) if provider.is_some() => {}
+            Ok(_) => {
+                return Ok(HttpResponse::ServiceUnavailable().json(OcrResponse {
+                    result: format!("{}; page {} has no text layer", OFFLINE_ERROR, params.page),
+                }));
+            }
+            Err(e) => return Ok(HttpResponse::InternalServerError().json(OcrResponse { result: e })),
+        }
+    }
+
+    // Born-digital pages don't need a paid OCR call
+    if !config.offline
+        && config.text_layer_min_chars > 0
+        && let Ok(text) = extract_page_text(&path, params.page.get())
+        && is_substantial_text(&text, config.text_layer_min_chars)
+    {
+        return Ok(HttpResponse::Ok().json(OcrResponse { result: text }));
+    }
+
+    let preview_path = match file_service.generate_preview(&file, params.page.get()) {
         Ok(path) => path,
         Err(e) => {
             error!("Failed to generate preview: {}", e);
@@ -17,23 +62,20 @@ pub async fn perform_ocr(
         }
     };
 
-    let api_key = match std::env::var("MISTRAL_API_KEY") {
-        Ok(key) => key,
-        Err(_) => {
-            error!("MISTRAL_API_KEY not set");
-            return Ok(HttpResponse::InternalServerError()
-                .json(OcrResponse { result: "MISTRAL_API_KEY not set".to_string() }));
-        }
+    let Some(provider) = provider else {
+        error!("{}", NO_OCR_PROVIDER);
+        return Ok(HttpResponse::InternalServerError().json(OcrResponse {
+            result: NO_OCR_PROVIDER.to_string(),
+        }));
     };
 
-    let provider = MistralOcrProvider::new(api_key);
     match provider
-        .extract_text(&preview_path.to_string_lossy(), &params.file, params.page)
+        .extract_text(&preview_path.to_string_lossy(), &file, params.page.get())
         .await
     {
         Ok((ocr_text, ocr_result)) => {
             if let Err(e) =
-                file_service.save_ocr_cache(&params.file, params.page, provider.provider_id(), ocr_result)
+                file_service.save_ocr_cache(&file, params.page.get(), provider.provider_id(), ocr_result)
             {
                 error!("Failed to save OCR cache: {}", e);
             }
@@ -51,7 +93,7 @@ pub async fn get_ocr_cache(
     params: web::Path<PreviewParams>,
     file_service: web::Data<FileService>,
 ) -> Result<HttpResponse, Error> {
-    match file_service.get_ocr_cache(&params.file, params.page) {
+    match file_service.get_ocr_cache(&file_service.book_file(&params.file), params.page.get()) {
         Some(data) => Ok(HttpResponse::Ok().content_type("application/json").body(data)),
         None => Ok(HttpResponse::NotFound().body("")),
     }
@@ -3,10 +3,17 @@ use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
 use crate::services::database::Database;
-use crate::services::ai_parser::HybridParser;
-use crate::services::OcrService;
+use crate::services::ai_parser::{AIParseResult, HybridParser};
+use crate::services::{FileService, OcrService};
+use tokio_util::sync::CancellationToken;
+use crate::services::book_settings::{book_file_service, book_parser_profile, ParserProfile, DEFAULT_OCR_PROVIDER};
+use crate::services::ocr_rules::postprocess_ocr_text;
+use crate::utils::slug::book_id_of;
+use crate::services::ocr_confidence::problem_confidence;
+use crate::services::formula_fallback::{invalid_formulas, FALLBACK_AFTER_ATTEMPTS};
 use crate::services::page_parser::{PageContentParser, convert_to_models};
-use crate::models::{Problem, Book};
+use crate::models::{Problem, Book, PreviewImageParams, ReviewStatus};
+use crate::handlers::problems::ConfidenceFilter;
 
 #[derive(Debug, Deserialize)]
 pub struct ParseProblemsRequest {
@@ -25,6 +32,8 @@ pub struct CreateProblemsRequest {
     pub page_number: Option<u32>,
     /// Previous page's last problem number (for cross-page detection)
     pub prev_page_last_problem: Option<String>,
+    /// Page-level confidence reported by the OCR provider, if any
+    pub ocr_confidence: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -66,7 +75,7 @@ pub struct CrossPageLink {
 
 #[derive(Debug, Deserialize)]
 pub struct PageOcrRequest {
-    pub provider: Option<String>, // mistral, mathpix, etc.
+    pub provider: Option<String>, // mistral, mathpix, etc.; defaults to the book's setting
 }
 
 #[derive(Debug, Serialize)]
@@ -76,40 +85,53 @@ pub struct PageOcrResponse {
     pub provider: String,
 }
 
-/// Get the hybrid parser (AI + regex fallback)
-fn get_parser() -> HybridParser {
-    let api_key = std::env::var("MISTRAL_API_KEY").ok();
-    HybridParser::new(api_key)
+/// Get the hybrid parser (AI + regex fallback; regex only in offline mode
+/// or when the book's parser profile asks for it)
+async fn get_parser(db: &Database, config: &Config, book_id: &str) -> HybridParser {
+    HybridParser::new(config.mistral_api_key())
+        .timeout(config.provider_timeout())
+        .profile(book_parser_profile(db, book_id).await)
 }
 
 /// Perform OCR on a specific PDF page
 pub async fn ocr_pdf_page(
-    path: web::Path<(String, u32)>,
+    path: web::Path<PreviewImageParams>,
     query: web::Query<PageOcrRequest>,
+    file_service: web::Data<FileService>,
+    db: web::Data<Database>,
     config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let (filename, page) = path.into_inner();
-    let provider = query.provider.as_deref().unwrap_or("mistral");
-    
-    // Check if preview image exists
-    let preview_dir = &config.preview_dir;
-    let png_path = preview_dir.join(format!("{}_{}.png", filename, page));
-    let jpg_path = preview_dir.join(format!("{}_{}.jpg", filename, page));
+    let file = file_service.book_file(&path.filename);
+    let (filename, page) = (file.as_str(), path.page.get());
+    let provider = match &query.provider {
+        Some(provider) => provider.clone(),
+        None => db
+            .get_book_settings(&book_id_of(filename))
+            .await
+            .map(|settings| settings.ocr_provider().to_string())
+            .unwrap_or_else(|_| DEFAULT_OCR_PROVIDER.to_string()),
+    };
     
-    let image_path = if png_path.exists() {
-        png_path
-    } else if jpg_path.exists() {
-        jpg_path
-    } else {
-        return Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "error": "Preview image not found. Generate previews first."
-        })));
+    // Use an existing preview, or render just this page (offline OCR reads
+    // the PDF text layer, or the rendered page with tesseract)
+    let image_path = match book_file_service(&db, &file_service, &book_id_of(filename)).await.generate_preview(filename, page) {
+        Ok(path) => path,
+        Err(_) if config.offline => file_service.page_image(filename, page),
+        Err(e) => {
+            log::error!("Failed to generate preview of {} page {}: {}", filename, page, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to generate preview: {}", e)
+            })));
+        }
     };
     
     // Run OCR using the shared OCR service (supports provider selection and retries).
-    let ocr_service = OcrService::new(config.preview_dir.clone());
-    let ocr_result = match ocr_service.run_ocr(&image_path, provider).await {
-        Ok(text) => text,
+    let ocr_service = OcrService::new(&config);
+    let ocr_result = match ocr_service
+        .ocr_page(filename, page, &image_path, &provider, &CancellationToken::new())
+        .await
+    {
+        Ok(result) => result,
         Err(e) => {
             log::error!("OCR failed: {}", e);
             return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -120,22 +142,25 @@ pub async fn ocr_pdf_page(
     
     Ok(HttpResponse::Ok().json(PageOcrResponse {
         page,
-        text: ocr_result,
-        provider: provider.to_string(),
+        text: ocr_result.text,
+        provider: ocr_result.provider,
     }))
 }
 
 /// Parse problems from OCR text using hybrid AI+regex parser
 pub async fn parse_problems_from_text(
     body: web::Json<ParseProblemsRequest>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let parser = get_parser();
+    let parser = get_parser(&db, &config, &body.book_id).await;
     let page_number = body.page_number;
+    let text = postprocess_ocr_text(&db, &body.book_id, &body.text).await;
     
     // Parse with hybrid parser (AI first, regex fallback)
-    match parser.parse_text(&body.book_id, &body.text, page_number).await {
+    match parser.parse_text(&body.book_id, &text, page_number).await {
         Ok(result) => {
-            let parser_used = if std::env::var("MISTRAL_API_KEY").is_ok() { "ai" } else { "regex" };
+            let parser_used = if parser.uses_ai() { "ai" } else { "regex" };
             
             // Convert to response format
             let problems: Vec<ParsedProblem> = result.problems.iter().map(|p| {
@@ -166,15 +191,17 @@ pub async fn parse_problems_from_text(
 pub async fn create_problems_from_ocr(
     body: web::Json<CreateProblemsRequest>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
     log::info!("Creating problems for book={}, chapter={}, page={:?}", 
                body.book_id, body.chapter_id, body.page_number);
     
-    let parser = get_parser();
+    let parser = get_parser(&db, &config, &body.book_id).await;
     let page_number = body.page_number.unwrap_or(1);
+    let text = postprocess_ocr_text(&db, &body.book_id, &body.text).await;
     
     // Parse with hybrid parser
-    let result = match parser.parse_text(&body.book_id, &body.text, Some(page_number)).await {
+    let result = match parser.parse_text(&body.book_id, &text, Some(page_number)).await {
         Ok(r) => {
             log::info!("Parsed {} problems", r.problems.len());
             r
@@ -204,10 +231,6 @@ pub async fn create_problems_from_ocr(
         created_at: chrono::Utc::now(),
     };
     
-    if let Err(e) = db.create_book(&book).await {
-        log::debug!("Book may already exist: {}", e);
-    }
-    
     // Ensure chapter exists
     let chapter = crate::models::Chapter {
         id: body.chapter_id.clone(),
@@ -220,46 +243,92 @@ pub async fn create_problems_from_ocr(
         created_at: chrono::Utc::now(),
     };
     
-    if let Err(e) = db.create_chapter(&chapter).await {
-        log::debug!("Chapter may already exist: {}", e);
-    }
-    
-    // Get or create the page
-    let page = match db.get_or_create_page(&body.book_id, page_number).await {
-        Ok(p) => p,
-        Err(e) => {
-            log::error!("Failed to get/create page: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to create page: {}", e)
-            })));
+    // Book, chapter, page and problems are written in one transaction, so a
+    // failure part-way leaves the page's old problems in place
+    let saved = db.transaction(async |tx| {
+        // An existing book or chapter row that can't be updated (e.g. its
+        // number is taken) is used as it is
+        if let Err(e) = tx.create_book(&book).await {
+            log::debug!("Book may already exist: {}", e);
         }
-    };
+        if let Err(e) = tx.create_chapter(&chapter).await {
+            log::debug!("Chapter may already exist: {}", e);
+        }
+        let page = tx.get_or_create_page(&body.book_id, page_number).await?;
+        
+        // DELETE ALL old problems on this page before creating new ones
+        let deleted_count = tx.delete_problems_by_page(&page.id).await?;
+        tx.update_page_ocr(&page.id, &text, result.problems.len() as u32).await?;
+        
+        let (problems, cross_page_links) = page_problems(&body, &result, &page.id, page_number);
+        log::info!("Saving {} problems to database", problems.len());
+        let writes = tx.save_page_problems(&page.id, page_number, &problems).await?;
+        Ok((page, deleted_count, problems, cross_page_links, writes))
+    }).await;
     
-    // DELETE ALL old problems on this page before creating new ones
-    let deleted_count = match db.delete_problems_by_page(&page.id).await {
-        Ok(count) => {
-            if count > 0 {
-                log::info!("🗑️ Deleted {} old problems from page {}", count, page.id);
+    match saved {
+        Ok((page, deleted_count, problems_to_create, cross_page_links, writes)) => {
+            if deleted_count > 0 {
+                log::info!("🗑️ Deleted {} old problems from page {}", deleted_count, page.id);
+            }
+            let (written, conflicts): (Vec<_>, Vec<_>) = problems_to_create
+                .iter()
+                .zip(writes)
+                .partition(|(_, w)| w.is_written());
+            let count = written.len();
+            log::info!("Successfully created {} problems ({} conflicts)", count, conflicts.len());
+            let problem_ids: Vec<String> = written.iter()
+                .filter(|(p, _)| p.parent_id.is_none()) // Only main problems
+                .map(|(p, _)| p.id.clone())
+                .collect();
+            
+            // Track formulas that keep failing validation across OCR rounds
+            let mut formula_fallback_candidates = Vec::new();
+            for (problem, _) in &written {
+                let failed = !invalid_formulas(&problem.content).is_empty();
+                match db.record_formula_attempt(&problem.id, failed).await {
+                    Ok(attempts) if attempts >= FALLBACK_AFTER_ATTEMPTS => {
+                        formula_fallback_candidates.push(problem.id.clone());
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Failed to record formula attempt for {}: {}", problem.id, e),
+                }
             }
-            count
+            
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "deleted_count": deleted_count,
+                "created_count": count,
+                "page_id": page.id,
+                "page_number": page_number,
+                "problems": problem_ids,
+                "cross_page_links": cross_page_links,
+                "formula_fallback_candidates": formula_fallback_candidates,
+                "conflicts": conflicts.into_iter().map(|(_, w)| w).collect::<Vec<_>>(),
+                "message": format!("Replaced: deleted {}, created {}", deleted_count, count),
+            })))
         }
         Err(e) => {
-            log::error!("Failed to delete old problems: {}", e);
-            0
+            log::error!("Failed to create problems: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to create problems: {}", e)
+            })))
         }
-    };
-    
-    // Update page with OCR text
-    if let Err(e) = db.update_page_ocr(&page.id, &body.text, result.problems.len() as u32).await {
-        log::error!("Failed to update page OCR: {}", e);
     }
-    
-    // Build problems with cross-page detection
+}
+
+/// Problem rows (sub-problems included) for one parsed page, plus the
+/// problems that continue on a neighbouring page
+fn page_problems(
+    body: &CreateProblemsRequest,
+    result: &AIParseResult,
+    page_id: &str,
+    page_number: u32,
+) -> (Vec<Problem>, Vec<CrossPageLink>) {
     let mut problems_to_create: Vec<Problem> = Vec::new();
     let mut cross_page_links: Vec<CrossPageLink> = Vec::new();
     
     for ai_problem in &result.problems {
-        let problem_id = format!("{}:{}:{}", body.book_id, body.chapter_num, ai_problem.number);
+        let problem_id = Problem::generate_id(&body.book_id, body.chapter_num, &ai_problem.number);
         
         // Track cross-page links
         if ai_problem.continues_from_prev || ai_problem.continues_to_next {
@@ -278,7 +347,7 @@ pub async fn create_problems_from_ocr(
         let main_problem = Problem {
             id: problem_id.clone(),
             chapter_id: body.chapter_id.clone(),
-            page_id: Some(page.id.clone()),
+            page_id: Some(page_id.to_string()),
             parent_id: None,
             number: ai_problem.number.clone(),
             display_name: format!("Задача {}", ai_problem.number),
@@ -298,6 +367,11 @@ pub async fn create_problems_from_ocr(
             } else { None },
             is_cross_page: ai_problem.continues_from_prev || ai_problem.continues_to_next,
             is_bookmarked: false,
+            confidence: Some(problem_confidence(&ai_problem.content, body.ocr_confidence)),
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
         };
         
         problems_to_create.push(main_problem);
@@ -308,7 +382,7 @@ pub async fn create_problems_from_ocr(
             let sub_problem = Problem {
                 id: sub_id,
                 chapter_id: body.chapter_id.clone(),
-                page_id: Some(page.id.clone()),
+                page_id: Some(page_id.to_string()),
                 parent_id: Some(problem_id.clone()),
                 number: sub.letter.clone(),
                 display_name: format!("{})", sub.letter),
@@ -324,38 +398,17 @@ pub async fn create_problems_from_ocr(
                 continues_to_page: None,
                 is_cross_page: false,
                 is_bookmarked: false,
+                confidence: Some(problem_confidence(&sub.content, body.ocr_confidence)),
+                review_status: ReviewStatus::Unreviewed,
+                review_note: None,
+                reference_answer: None,
+                version: 1,
             };
             problems_to_create.push(sub_problem);
         }
     }
-    
-    // Save to database
-    log::info!("Saving {} problems to database", problems_to_create.len());
-    match db.create_or_update_problems(&problems_to_create).await {
-        Ok(count) => {
-            log::info!("Successfully created {} problems", count);
-            let problem_ids: Vec<String> = problems_to_create.iter()
-                .filter(|p| p.parent_id.is_none()) // Only main problems
-                .map(|p| p.id.clone())
-                .collect();
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "deleted_count": deleted_count,
-                "created_count": count,
-                "page_id": page.id,
-                "page_number": page_number,
-                "problems": problem_ids,
-                "cross_page_links": cross_page_links,
-                "message": format!("Replaced: deleted {}, created {}", deleted_count, count),
-            })))
-        }
-        Err(e) => {
-            log::error!("Failed to create problems: {}", e);
-            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to create problems: {}", e)
-            })))
-        }
-    }
+
+    (problems_to_create, cross_page_links)
 }
 
 /// Get existing OCR text for a page
@@ -371,6 +424,7 @@ pub async fn get_page_ocr(
                 "page_id": page.id,
                 "page_number": page.page_number,
                 "has_ocr": page.ocr_text.is_some(),
+                "pending": page.is_pending(),
                 "ocr_text": page.ocr_text.unwrap_or_default(),
                 "has_problems": page.has_problems,
                 "problem_count": page.problem_count,
@@ -381,6 +435,7 @@ pub async fn get_page_ocr(
             "page_id": format!("{}:page:{}", book_id, page_number),
             "page_number": page_number,
             "has_ocr": false,
+            "pending": false,
             "ocr_text": "",
             "has_problems": false,
             "problem_count": 0,
@@ -397,12 +452,13 @@ pub async fn get_page_ocr(
 /// Get problems by page ID
 pub async fn get_problems_by_page(
     path: web::Path<String>,
+    query: web::Query<ConfidenceFilter>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let page_id = path.into_inner();
     
     match db.get_problems_by_page(&page_id).await {
-        Ok(problems) => Ok(HttpResponse::Ok().json(problems)),
+        Ok(problems) => Ok(HttpResponse::Ok().json(query.apply(problems))),
         Err(e) => {
             log::error!("Failed to get problems by page: {}", e);
             Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -491,12 +547,17 @@ pub struct ParseFullPageResponse {
 pub async fn parse_full_page(
     body: web::Json<ParseFullPageRequest>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let api_key = std::env::var("MISTRAL_API_KEY").ok();
-    let parser = PageContentParser::new(api_key);
+    let api_key = match book_parser_profile(&db, &body.book_id).await {
+        ParserProfile::Regex => None,
+        _ => config.mistral_api_key(),
+    };
+    let parser = PageContentParser::new(api_key).timeout(config.provider_timeout());
+    let text = postprocess_ocr_text(&db, &body.book_id, &body.text).await;
     
     // Parse the page
-    let result = match parser.parse_page(&body.text, body.page_number).await {
+    let result = match parser.parse_page(&text, body.page_number).await {
         Ok(r) => r,
         Err(e) => {
             log::error!("Full page parsing failed: {}", e);
@@ -519,7 +580,6 @@ pub async fn parse_full_page(
         total_pages: 0,
         created_at: chrono::Utc::now(),
     };
-    let _ = db.create_book(&book).await;
     
     let chapter = crate::models::Chapter {
         id: format!("{}:{}", body.book_id, body.chapter_num),
@@ -531,31 +591,42 @@ pub async fn parse_full_page(
         theory_count: 0,
         created_at: chrono::Utc::now(),
     };
-    let _ = db.create_chapter(&chapter).await;
     
-    // Save problems
-    let mut problems_created = 0;
-    if !problems.is_empty() {
-        match db.create_or_update_problems(&problems).await {
-            Ok(count) => problems_created = count,
-            Err(e) => log::error!("Failed to save problems: {}", e),
+    // Save book, chapter, problems and theory blocks together
+    let saved = db.transaction(async |tx| {
+        // An existing book or chapter row that can't be updated (e.g. its
+        // number is taken) is used as it is
+        if let Err(e) = tx.create_book(&book).await {
+            log::debug!("Book may already exist: {}", e);
         }
-    }
-    
-    // Save theory blocks
-    let mut theory_created = 0;
-    for theory in &theories {
-        match db.create_theory_block(theory).await {
-            Ok(_) => theory_created += 1,
-            Err(e) => log::error!("Failed to save theory: {}", e),
+        if let Err(e) = tx.create_chapter(&chapter).await {
+            log::debug!("Chapter may already exist: {}", e);
         }
-    }
+        let writes = tx.create_or_update_problems(&problems).await?;
+        for theory in &theories {
+            tx.create_theory_block(theory).await?;
+        }
+        Ok(writes)
+    }).await;
+    let writes = match saved {
+        Ok(writes) => writes,
+        Err(e) => {
+            log::error!("Failed to save parsed page: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save parsed page: {}", e)
+            })));
+        }
+    };
+    let (written, problem_conflicts): (Vec<_>, Vec<_>) = writes.into_iter().partition(|w| w.is_written());
+    let problems_created = written.len();
+    let theory_created = theories.len();
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "metadata": result.metadata,
         "elements": result.elements,
         "stats": result.stats,
         "problems_created": problems_created,
+        "problem_conflicts": problem_conflicts,
         "theory_created": theory_created,
     })))
 }
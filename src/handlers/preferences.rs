@@ -0,0 +1,60 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+
+use crate::services::database::Database;
+use crate::services::ui_preferences::{profile_name, UiPreferences, PROFILE_COOKIE};
+
+fn request_profile(req: &HttpRequest) -> String {
+    profile_name(req.cookie(PROFILE_COOKIE).as_ref().map(|c| c.value()))
+}
+
+/// Preferences of the requesting browser's profile for page templates;
+/// defaults when they can't be loaded
+pub async fn page_preferences(req: &HttpRequest, db: &Database) -> UiPreferences {
+    let profile = request_profile(req);
+    db.get_ui_preferences(&profile).await.unwrap_or_else(|e| {
+        log::warn!("Failed to load preferences of {}: {}", profile, e);
+        UiPreferences::default()
+    })
+}
+
+pub async fn get_preferences(req: HttpRequest, db: web::Data<Database>) -> Result<HttpResponse, Error> {
+    let profile = request_profile(&req);
+    match db.get_ui_preferences(&profile).await {
+        Ok(preferences) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "profile": profile,
+            "preferences": preferences,
+        }))),
+        Err(e) => {
+            log::error!("Failed to get preferences: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get preferences: {}", e)
+            })))
+        }
+    }
+}
+
+/// Replace the profile's preferences; options left out reset to defaults
+pub async fn update_preferences(
+    req: HttpRequest,
+    body: web::Json<UiPreferences>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let profile = request_profile(&req);
+    let preferences = body.into_inner();
+    if let Err(e) = preferences.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.save_ui_preferences(&profile, &preferences).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "profile": profile,
+            "preferences": preferences,
+        }))),
+        Err(e) => {
+            log::error!("Failed to save preferences: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to save preferences: {}", e)
+            })))
+        }
+    }
+}
@@ -1,15 +1,19 @@
 use actix_files::NamedFile;
 use actix_web::{web, Error, HttpResponse};
 use log::{error, info};
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::process::Command;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::thread;
 use tokio::sync::Mutex;
 
-use crate::models::PreviewImageParams;
+use crate::models::{PreviewImageParams, SafeFileName};
+use crate::services::book_settings::book_file_service;
+use crate::services::database::Database;
 use crate::services::FileService;
+use crate::utils::slug::book_id_of;
+use crate::utils::page_range::parse_page_ranges;
 
 #[derive(Clone)]
 struct GenerationProgress {
@@ -22,20 +26,22 @@ lazy_static::lazy_static! {
     static ref GENERATION_PROGRESS: Mutex<HashMap<String, GenerationProgress>> = Mutex::new(HashMap::new());
 }
 
+fn invalid_path(e: String) -> Error {
+    error!("Rejected preview path: {}", e);
+    actix_web::error::ErrorBadRequest(e)
+}
+
 pub async fn get_preview_image(
     path: web::Path<PreviewImageParams>,
     file_service: web::Data<FileService>,
+    db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
-    // Try PNG first, then JPG
-    let base_path = file_service.get_preview_dir();
-    let png_path = base_path.join(format!("{}_{}.png", path.filename, path.page));
-    let jpg_path = base_path.join(format!("{}_{}.jpg", path.filename, path.page));
-    
-    let (preview_path, content_type) = if png_path.exists() {
-        (png_path, "image/png")
-    } else if jpg_path.exists() {
-        (jpg_path, "image/jpeg")
-    } else {
+    // At the book's own resolution, the preview its OCR reads
+    let file_service = book_file_service(&db, &file_service, &book_id_of(&path.filename)).await;
+    let Some((preview_path, content_type)) = file_service
+        .find_preview(&file_service.book_file(&path.filename), path.page.get())
+        .map_err(invalid_path)?
+    else {
         return Ok(HttpResponse::NotFound().body("Image not found"));
     };
 
@@ -49,37 +55,29 @@ pub async fn get_preview_image(
 }
 
 pub async fn get_pdf_preview(
-    path: web::Path<(String, Option<u32>)>,
+    path: web::Path<PreviewImageParams>,
     file_service: web::Data<FileService>,
+    db: web::Data<Database>,
 ) -> actix_web::Result<NamedFile> {
-    let (file_or_image, page_opt) = path.into_inner();
+    let file_service = book_file_service(&db, &file_service, &book_id_of(&path.filename)).await;
+    let file = file_service.book_file(&path.filename);
+    if !file_service.resolve_resource(&file).map_err(invalid_path)?.exists() {
+        return Err(actix_web::error::ErrorNotFound("File not found"));
+    }
 
-    match page_opt {
-        Some(page) => {
-            let preview_path = file_service.generate_preview(&file_or_image, page).map_err(|e| {
-                error!("Failed to generate preview: {}", e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?;
+    let preview_path = file_service.generate_preview(&file, path.page.get()).map_err(|e| {
+        error!("Failed to generate preview: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
 
-            Ok(NamedFile::open(preview_path)?.use_last_modified(true))
-        }
-        None => {
-            let full_path = file_service.get_preview_dir().join(&file_or_image);
-            if full_path.exists() {
-                Ok(NamedFile::open(full_path)?.use_last_modified(true))
-            } else {
-                Err(actix_web::error::ErrorNotFound("Image not found"))
-            }
-        }
-    }
+    Ok(NamedFile::open(preview_path)?.use_last_modified(true))
 }
 
 pub async fn get_ocr_image(
-    path: web::Path<String>,
+    path: web::Path<SafeFileName>,
     file_service: web::Data<FileService>,
 ) -> Result<HttpResponse, Error> {
-    let filename = path.into_inner();
-    let full_path = file_service.get_preview_dir().join(&filename);
+    let full_path = file_service.resolve_preview(&path).map_err(invalid_path)?;
 
     log::info!("Looking for OCR image at: {:?}", full_path);
 
@@ -97,8 +95,11 @@ pub async fn get_ocr_image(
     }
 }
 
-pub async fn get_generation_status(path: web::Path<String>) -> Result<HttpResponse, Error> {
-    let file = path.into_inner();
+pub async fn get_generation_status(
+    path: web::Path<String>,
+    file_service: web::Data<FileService>,
+) -> Result<HttpResponse, Error> {
+    let file = file_service.book_file(&path);
     let progress = GENERATION_PROGRESS.lock().await;
 
     if let Some(gen_progress) = progress.get(&file) {
@@ -118,12 +119,20 @@ pub async fn get_generation_status(path: web::Path<String>) -> Result<HttpRespon
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GeneratePreviewsQuery {
+    /// Page selection like "1-10,15,20-e,odd"; all pages by default
+    pub pages: Option<String>,
+}
+
 pub async fn generate_all_previews(
     file_service: web::Data<FileService>,
-    path: web::Path<String>,
+    db: web::Data<Database>,
+    path: web::Path<SafeFileName>,
+    query: web::Query<GeneratePreviewsQuery>,
 ) -> Result<HttpResponse, Error> {
-    let file = path.into_inner();
-    let file_path = file_service.get_resources_dir().join(&file);
+    let file = file_service.book_file(&path);
+    let file_path = file_service.resolve_resource(&file).map_err(invalid_path)?;
 
     if !file_path.exists() {
         return Ok(HttpResponse::NotFound().json(serde_json::json!({
@@ -131,25 +140,15 @@ pub async fn generate_all_previews(
         })));
     }
 
-    let output = Command::new("pdfinfo").arg(&file_path).output().map_err(|e| {
-        error!("Failed to execute pdfinfo: {}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
-    if !output.status.success() {
-        error!("Failed to get PDF info for {:?}: {:?}", file_path, output);
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to get PDF info"
-        })));
-    }
-
-    let metadata = String::from_utf8_lossy(&output.stdout);
-    let total_pages = metadata
-        .lines()
-        .find(|line| line.starts_with("Pages:"))
-        .and_then(|line| line.split_once(':'))
-        .and_then(|(_, num)| num.trim().parse::<u32>().ok())
-        .unwrap_or(0);
+    let total_pages = match file_service.get_pdf_page_count(&file) {
+        Ok(pages) => pages,
+        Err(e) => {
+            error!("Failed to get PDF info for {:?}: {}", file_path, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to get PDF info"
+            })));
+        }
+    };
 
     if total_pages == 0 {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
@@ -157,8 +156,18 @@ pub async fn generate_all_previews(
         })));
     }
 
+    let pages: Vec<u32> = match parse_page_ranges(query.pages.as_deref().unwrap_or("1-e"), total_pages) {
+        Ok(pages) => pages.into_iter().collect(),
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid pages: {}", e)
+            })));
+        }
+    };
+    let selected_pages = pages.len() as u32;
+
     let progress = GenerationProgress {
-        total_pages,
+        total_pages: selected_pages,
         processed_pages: Arc::new(AtomicU32::new(0)),
         is_complete: Arc::new(AtomicU32::new(0)),
     };
@@ -168,18 +177,18 @@ pub async fn generate_all_previews(
         progress_map.insert(file.clone(), progress.clone());
     }
 
-    let file_service = Arc::new(file_service);
+    let file_service = book_file_service(&db, &file_service, &book_id_of(&file)).await;
     let file_clone = file.clone();
     let progress_clone = progress.clone();
 
     tokio::spawn(async move {
         let thread_id = thread::current().id();
         info!(
-            "[Thread {:?}] Starting preview generation for {} ({} pages)",
-            thread_id, file_clone, total_pages
+            "[Thread {:?}] Starting preview generation for {} ({} of {} pages)",
+            thread_id, file_clone, selected_pages, total_pages
         );
 
-        for page in 1..=total_pages {
+        for page in pages {
             info!(
                 "[Thread {:?}] Generating preview for {} - page {}/{}",
                 thread_id, file_clone, page, total_pages
@@ -200,13 +209,13 @@ pub async fn generate_all_previews(
         }
         progress_clone.is_complete.store(1, Ordering::Relaxed);
         info!(
-            "[Thread {:?}] Finished generating all previews for {} ({} pages total)",
-            thread_id, file_clone, total_pages
+            "[Thread {:?}] Finished generating previews for {} ({} pages)",
+            thread_id, file_clone, selected_pages
         );
     });
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Preview generation started",
-        "total_pages": total_pages
+        "total_pages": selected_pages
     })))
 }
@@ -1,20 +1,71 @@
-use actix_web::{web, Error, HttpResponse};
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 
-use crate::models::{SolveRequest, SolutionResponse};
+use crate::models::{Problem, ProblemHint, Solution, SolutionFilter, SolveRequest, SolutionResponse, TheoryBlock, TheoryType};
+use crate::services::book_settings::book_file_service;
 use crate::services::database::Database;
-use crate::services::ai_solver::AISolver;
+use crate::services::FileService;
+use crate::services::ai_solver::{default_solve_provider, resolve_solve_options, AISolver};
+use crate::services::answer_key::{check_answer, AnswerVerdict};
+use crate::services::calc_check::{calculation, check_calculation, Calculation};
+use crate::services::attachments::{attach_problem_plot, AttachmentStorage};
+use crate::services::edit_version::{etag, parse_if_match};
+use crate::services::markdown_sanitizer::sanitize_markdown;
+use crate::services::solution_cleanup::{plan_cleanup, DEFAULT_SIMILARITY_THRESHOLD};
+use crate::services::solve_cache::{self, solve_cache_key};
+use crate::services::text_search::{match_expression, SearchHit, SearchKind};
+use crate::services::study_pack::{build_study_pack, pick_representative, render_markdown, PACK_PROBLEMS};
 use crate::config::Config;
+use crate::utils::page_range::parse_page_ranges;
+use crate::services::formula_fallback::{
+    crop_formula, formula_image_dir, formula_image_name, formula_image_path, invalid_formulas, replace_with_image, FormulaRegion,
+    FALLBACK_AFTER_ATTEMPTS, FORMULA_IMAGE_ROUTE, FORMULA_RENDER_DPI,
+};
+
+/// Confidence filter shared by problem list endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct ConfidenceFilter {
+    pub min_confidence: Option<f32>,
+    pub max_confidence: Option<f32>,
+    /// `confidence` lists the least certain extractions first
+    pub sort: Option<String>,
+}
+
+impl ConfidenceFilter {
+    /// Problems without a confidence score are dropped once a bound is set
+    pub fn apply(&self, mut problems: Vec<crate::models::Problem>) -> Vec<crate::models::Problem> {
+        if self.min_confidence.is_some() || self.max_confidence.is_some() {
+            problems.retain(|p| {
+                p.confidence.is_some_and(|c| {
+                    self.min_confidence.is_none_or(|min| c >= min) && self.max_confidence.is_none_or(|max| c <= max)
+                })
+            });
+        }
+        if self.sort.as_deref() == Some("confidence") {
+            problems.sort_by(|a, b| {
+                a.confidence
+                    .unwrap_or(1.0)
+                    .partial_cmp(&b.confidence.unwrap_or(1.0))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+        problems
+    }
+}
 
 /// Get all problems for a chapter
 pub async fn get_chapter_problems(
     path: web::Path<String>,
+    query: web::Query<ConfidenceFilter>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let chapter_id = path.into_inner();
     
     match db.get_problems_by_chapter(&chapter_id).await {
-        Ok(problems) => Ok(HttpResponse::Ok().json(problems)),
+        Ok(problems) => Ok(HttpResponse::Ok().json(query.apply(problems))),
         Err(e) => {
             log::error!("Failed to get problems: {}", e);
             Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -24,6 +75,123 @@ pub async fn get_chapter_problems(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteQuery {
+    /// Pages whose problems go, e.g. `30-60`; required for a book
+    pub pages: Option<String>,
+    /// Only count what would be deleted
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Delete all problems of a chapter with their sub-problems and solutions
+pub async fn delete_chapter_problems(
+    path: web::Path<String>,
+    query: web::Query<BulkDeleteQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let chapter_id = path.into_inner();
+
+    match db.delete_chapter_problems(&chapter_id, query.dry_run).await {
+        Ok(Some(deleted)) => {
+            if !query.dry_run {
+                log::info!("Deleted problems of chapter {}: {:?}", chapter_id, deleted);
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "dry_run": query.dry_run,
+                "deleted": deleted,
+            })))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Chapter not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to delete problems: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete problems: {}", e)
+            })))
+        }
+    }
+}
+
+/// Delete the problems on a range of a book's pages (`?pages=30-60`) with
+/// their sub-problems and solutions
+pub async fn delete_book_problems(
+    path: web::Path<String>,
+    query: web::Query<BulkDeleteQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+    let Some(spec) = query.pages.as_deref() else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "pages is required, e.g. ?pages=30-60"
+        })));
+    };
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(book)) => book,
+        Ok(None) => return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Book not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    };
+    // Without a page count any range would be accepted
+    if book.total_pages == 0 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "The book's page count is unknown; delete problems by chapter instead"
+        })));
+    }
+    let pages: Vec<u32> = match parse_page_ranges(spec, book.total_pages) {
+        Ok(pages) => pages.into_iter().collect(),
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid pages: {}", e)
+            })));
+        }
+    };
+
+    match db.delete_book_problems_on_pages(&book_id, &pages, query.dry_run).await {
+        Ok(deleted) => {
+            if !query.dry_run {
+                log::info!("Deleted problems on pages {} of {}: {:?}", spec, book_id, deleted);
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "dry_run": query.dry_run,
+                "pages": pages.len(),
+                "deleted": deleted,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to delete problems: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete problems: {}", e)
+            })))
+        }
+    }
+}
+
+/// Problems of a chapter with their sub-problems and best solutions
+pub async fn get_chapter_full(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let chapter_id = path.into_inner();
+
+    match db.get_chapter_full(&chapter_id).await {
+        Ok(problems) => Ok(HttpResponse::Ok().json(problems)),
+        Err(e) => {
+            log::error!("Failed to get chapter problems: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get chapter problems: {}", e)
+            })))
+        }
+    }
+}
+
 /// Get single problem with optional solution
 pub async fn get_problem(
     path: web::Path<String>,
@@ -58,7 +226,37 @@ pub async fn get_problem(
         }
     }
 
-    Ok(HttpResponse::Ok().json(problem))
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(problem.version)))
+        .json(problem))
+}
+
+/// Version an update was based on: the `If-Match` header, else `version` in
+/// the body
+fn expected_version(req: &HttpRequest, body_version: Option<u32>) -> Option<u32> {
+    req.headers()
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_if_match)
+        .or(body_version)
+}
+
+/// 428 for an update of an existing record that didn't say which version it
+/// was based on
+fn version_required<T: Serialize>(current: &T) -> HttpResponse {
+    HttpResponse::PreconditionRequired().json(serde_json::json!({
+        "error": "Send the version being edited as If-Match or \"version\"",
+        "current": current,
+    }))
+}
+
+/// 409 with the stored record, so the client can merge and retry with its
+/// version
+fn version_conflict<T: Serialize>(current: &T) -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Edited concurrently: a newer version was saved",
+        "current": current,
+    }))
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,6 +264,40 @@ pub struct GetProblemQuery {
     with_solution: Option<bool>,
 }
 
+/// Pages a problem's statement is printed on, with the part on each
+pub async fn get_problem_pages(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+
+    match db.get_problem(&problem_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })));
+        }
+    }
+
+    match db.get_problem_pages(&problem_id).await {
+        Ok(pages) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "problem_id": problem_id,
+            "pages": pages,
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem pages: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem pages: {}", e)
+            })))
+        }
+    }
+}
+
 /// Generate or retrieve solution for a problem
 pub async fn solve_problem(
     path: web::Path<String>,
@@ -89,20 +321,8 @@ pub async fn solve_problem(
         }
     };
 
-    // Check for existing solution if not forcing regeneration
-    if !body.force_regenerate.unwrap_or(false) {
-        let provider = body.provider.as_deref().unwrap_or("claude");
-        if let Ok(Some(existing)) = db.get_solution(&problem_id, provider).await {
-            return Ok(HttpResponse::Ok().json(SolutionResponse {
-                problem,
-                solution: existing,
-                generation_time_ms: 0,
-            }));
-        }
-    }
-
     // Get theory context for better solutions
-    let theory_context = db.get_theory_blocks_by_chapter(&problem.chapter_id)
+    let mut theory_context = db.get_theory_blocks_by_chapter(&problem.chapter_id)
         .await
         .ok()
         .map(|blocks| {
@@ -113,6 +333,72 @@ pub async fn solve_problem(
         })
         .unwrap_or_default();
 
+    // A drawing reconstructed for the problem tells the model what it shows
+    if let Ok(Some(figure)) = db.get_problem_figure(&problem.id).await {
+        if !theory_context.is_empty() {
+            theory_context.push_str("\n\n");
+        }
+        theory_context.push_str(&figure.description.summary());
+    }
+
+    let provider = body.provider.clone()
+        .or_else(|| default_solve_provider().map(String::from))
+        .unwrap_or_else(|| "claude".to_string());
+    let params = match resolve_solve_options(&provider, &body.options) {
+        Ok(params) => params,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+
+    // Unless forced, an identical earlier request answers this one, even one
+    // made for another problem with the same text
+    if !body.force_regenerate.unwrap_or(false) {
+        let key = solve_cache_key(&provider, &problem.content, &theory_context, &params);
+        match db.get_solution_by_prompt_hash(&key).await {
+            Ok(Some(cached)) => {
+                solve_cache::record_hit();
+                let solution = if cached.problem_id == problem.id {
+                    cached
+                } else {
+                    let copy = Solution {
+                        id: Solution::generate_id(&problem.id),
+                        problem_id: problem.id.clone(),
+                        is_verified: false,
+                        rating: None,
+                        created_at: chrono::Utc::now(),
+                        updated_at: chrono::Utc::now(),
+                        ..cached
+                    };
+                    if let Err(e) = db.create_or_update_solution(&copy).await {
+                        log::error!("Failed to save cached solution: {}", e);
+                    }
+                    copy
+                };
+                return Ok(HttpResponse::Ok().json(SolutionResponse {
+                    problem,
+                    solution,
+                    generation_time_ms: 0,
+                    cached: true,
+                }));
+            }
+            Ok(None) => solve_cache::record_miss(),
+            Err(e) => log::warn!("Solve cache lookup failed: {}", e),
+        }
+
+        // Solutions saved before the cache existed have no key; keep
+        // returning them for plain requests rather than paying again
+        if body.options.is_default()
+            && let Ok(Some(existing)) = db.get_solution(&problem_id, &provider).await
+            && existing.prompt_hash.is_none()
+        {
+            return Ok(HttpResponse::Ok().json(SolutionResponse {
+                problem,
+                solution: existing,
+                generation_time_ms: 0,
+                cached: false,
+            }));
+        }
+    }
+
     // Generate solution
     let start_time = std::time::Instant::now();
     let solver = match AISolver::new(&config) {
@@ -126,8 +412,9 @@ pub async fn solve_problem(
 
     let solution = match solver.solve(
         &problem,
-        body.provider.as_deref(),
-        if theory_context.is_empty() { None } else { Some(&theory_context) }
+        Some(&provider),
+        if theory_context.is_empty() { None } else { Some(&theory_context) },
+        &body.options,
     ).await {
         Ok(s) => s,
         Err(e) => {
@@ -143,17 +430,26 @@ pub async fn solve_problem(
         log::error!("Failed to save solution: {}", e);
     }
 
+    // "Постройте график" problems get the graph drawn and attached
+    let storage = AttachmentStorage::from_config(&config);
+    if let Err(e) = attach_problem_plot(&db, &storage, &problem, &solution.provider).await {
+        log::warn!("Failed to plot {}: {}", problem.id, e);
+    }
+
     let generation_time_ms = start_time.elapsed().as_millis() as u64;
 
     Ok(HttpResponse::Ok().json(SolutionResponse {
         problem,
         solution,
         generation_time_ms,
+        cached: false,
     }))
 }
 
-/// Save or update solution manually
+/// Save or update solution manually. Replacing an existing solution needs
+/// the version it was based on.
 pub async fn save_solution(
+    req: HttpRequest,
     path: web::Path<String>,
     body: web::Json<SaveSolutionRequest>,
     db: web::Data<Database>,
@@ -170,24 +466,63 @@ pub async fn save_solution(
         })));
     }
 
-    let solution = crate::models::Solution {
-        id: crate::models::Solution::generate_id(&problem_id),
-        problem_id,
-        provider: body.provider.clone().unwrap_or_else(|| "manual".to_string()),
-        content: body.content.clone(),
-        latex_formulas: extract_latex(&body.content),
-        is_verified: body.is_verified.unwrap_or(false),
-        rating: None,
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
+    let provider = body.provider.clone().unwrap_or_else(|| "manual".to_string());
+    let content = sanitize_markdown(&body.content);
+    let latex_formulas = extract_latex(&content);
+    let existing = db.get_solution(&problem_id, &provider).await.map_err(|e| {
+        log::error!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let saved = match existing {
+        Some(current) => {
+            let Some(version) = expected_version(&req, body.version) else {
+                return Ok(version_required(&current));
+            };
+            db.update_solution_content(&problem_id, &provider, &content, &latex_formulas, body.is_verified, version)
+                .await
+                .map(|updated| updated.is_some())
+        }
+        None => {
+            let solution = crate::models::Solution {
+                id: crate::models::Solution::generate_id(&problem_id),
+                problem_id: problem_id.clone(),
+                provider: provider.clone(),
+                latex_formulas,
+                content,
+                is_verified: body.is_verified.unwrap_or(false),
+                rating: None,
+                generation: None,
+                prompt_hash: None,
+                version: 1,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            };
+            db.create_or_update_solution(&solution).await.map(|_| true)
+        }
     };
 
-    match db.create_or_update_solution(&solution).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(solution)),
+    let saved = match saved {
+        Ok(saved) => saved,
         Err(e) => {
             log::error!("Failed to save solution: {}", e);
-            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
                 "error": format!("Failed to save solution: {}", e)
+            })));
+        }
+    };
+    match db.get_solution(&problem_id, &provider).await {
+        Ok(Some(solution)) if saved => Ok(HttpResponse::Ok()
+            .insert_header((header::ETAG, etag(solution.version)))
+            .json(solution)),
+        Ok(Some(current)) => Ok(version_conflict(&current)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Solution not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get solution: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get solution: {}", e)
             })))
         }
     }
@@ -198,6 +533,8 @@ pub struct SaveSolutionRequest {
     pub content: String,
     pub provider: Option<String>,
     pub is_verified: Option<bool>,
+    /// Version being replaced when the request has no `If-Match`
+    pub version: Option<u32>,
 }
 
 /// Rate a solution
@@ -221,6 +558,64 @@ pub async fn rate_solution(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SolutionCleanupRequest {
+    /// Limit to one book; every book when omitted
+    pub book_id: Option<String>,
+    /// Content similarity (0.5-1.0) above which two solutions are duplicates
+    pub threshold: Option<f64>,
+    /// Report only; pass `false` to archive
+    pub dry_run: Option<bool>,
+}
+
+/// Find redundant solutions (re-runs of a provider, near-identical content)
+/// and archive all but the best of each; a dry run by default
+pub async fn cleanup_solutions(
+    body: web::Json<SolutionCleanupRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let threshold = body.threshold.unwrap_or(DEFAULT_SIMILARITY_THRESHOLD);
+    if !(0.5..=1.0).contains(&threshold) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "threshold must be between 0.5 and 1.0"
+        })));
+    }
+    let dry_run = body.dry_run.unwrap_or(true);
+
+    let solutions = match db.get_solutions_for_cleanup(body.book_id.as_deref()).await {
+        Ok(solutions) => solutions,
+        Err(e) => {
+            log::error!("Failed to load solutions: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load solutions: {}", e)
+            })));
+        }
+    };
+
+    let plan = plan_cleanup(&solutions, threshold);
+    let (archived, renamed) = if dry_run {
+        (0, 0)
+    } else {
+        match db.apply_solution_cleanup(&plan).await {
+            Ok(counts) => counts,
+            Err(e) => {
+                log::error!("Failed to clean up solutions: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to clean up solutions: {}", e)
+                })));
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "dry_run": dry_run,
+        "threshold": threshold,
+        "archived": archived,
+        "renamed": renamed,
+        "plan": plan,
+    })))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RateRequest {
     pub rating: u8, // 1-5
@@ -232,6 +627,185 @@ pub struct HintRequest {
     pub provider: Option<String>,
 }
 
+/// Compare each solution's final answer with the book's own answer and
+/// record the verdicts on the solutions
+pub async fn check_problem_answer(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+
+    let problem = match db.get_problem(&problem_id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })));
+        }
+    };
+
+    let Some(reference) = problem.reference_answer else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No reference answer for this problem; import the book's answers section first"
+        })));
+    };
+
+    match db.get_solutions_by_problem(&problem_id).await {
+        Ok(solutions) => {
+            let mut checks: Vec<serde_json::Value> = Vec::with_capacity(solutions.len());
+            for solution in &solutions {
+                let check = check_answer(&reference, &solution.content);
+                if let Err(e) = db.record_answer_verdict(&solution.id, check.verdict).await {
+                    log::warn!("Failed to record answer verdict of {}: {}", solution.id, e);
+                }
+                checks.push(serde_json::json!({
+                    "solution_id": solution.id,
+                    "provider": solution.provider,
+                    "final_answer": check.final_answer,
+                    "verdict": check.verdict,
+                    "symbolic": check.symbolic,
+                }));
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "problem_id": problem_id,
+                "reference_answer": reference,
+                "checks": checks,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to get solutions: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get solutions: {}", e)
+            })))
+        }
+    }
+}
+
+/// Re-compute a numeric problem's answer and compare each solution with it
+pub async fn check_problem_calculation(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+
+    let problem = match db.get_problem(&problem_id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Problem not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get problem: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })));
+        }
+    };
+    let parent = match &problem.parent_id {
+        Some(parent_id) => db.get_problem(parent_id).await.unwrap_or_else(|e| {
+            log::warn!("Failed to get parent problem {}: {}", parent_id, e);
+            None
+        }),
+        None => None,
+    };
+
+    let Some(calculation) = calculation(&problem, parent.as_ref()) else {
+        return Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "Not a numeric problem that can be re-computed (arithmetic, percentage or linear equation)"
+        })));
+    };
+
+    match db.get_solutions_by_problem(&problem_id).await {
+        Ok(solutions) => {
+            let checks: Vec<serde_json::Value> = solutions
+                .iter()
+                .map(|solution| {
+                    let (answer, verdict) = check_calculation(&calculation, &solution.content);
+                    serde_json::json!({
+                        "solution_id": solution.id,
+                        "provider": solution.provider,
+                        "final_answer": answer,
+                        "verdict": verdict,
+                    })
+                })
+                .collect();
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "problem_id": problem_id,
+                "calculation": calculation,
+                "checks": checks,
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to get solutions: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get solutions: {}", e)
+            })))
+        }
+    }
+}
+
+/// Cross-check every solution of a book's numeric problems and list the
+/// ones whose final answer disagrees with the calculation
+pub async fn check_book_calculations(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let report = async {
+        let mut calculations: HashMap<String, (Problem, Calculation)> = HashMap::new();
+        for chapter in db.get_chapters_by_book(&book_id).await? {
+            for problem in db.get_problem_tree_by_chapter(&chapter.id).await? {
+                for sub in problem.sub_problems.iter().flatten() {
+                    if let Some(calc) = calculation(sub, Some(&problem)) {
+                        calculations.insert(sub.id.clone(), (sub.clone(), calc));
+                    }
+                }
+                if let Some(calc) = calculation(&problem, None) {
+                    calculations.insert(problem.id.clone(), (problem, calc));
+                }
+            }
+        }
+
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        let mut discrepancies = Vec::new();
+        for solution in db.get_book_solutions(&book_id, &SolutionFilter::Any).await? {
+            let Some((problem, calc)) = calculations.get(&solution.problem_id) else { continue };
+            let (answer, verdict) = check_calculation(calc, &solution.content);
+            *counts.entry(verdict.as_str()).or_default() += 1;
+            if verdict == AnswerVerdict::Mismatch {
+                discrepancies.push(serde_json::json!({
+                    "problem_id": problem.id,
+                    "display_name": problem.display_name,
+                    "calculation": calc,
+                    "solution_id": solution.id,
+                    "provider": solution.provider,
+                    "final_answer": answer,
+                }));
+            }
+        }
+        anyhow::Ok(serde_json::json!({
+            "book_id": book_id,
+            "numeric_problems": calculations.len(),
+            "verdicts": counts,
+            "discrepancies": discrepancies,
+        }))
+    };
+
+    match report.await {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => {
+            log::error!("Failed to check calculations of {}: {}", book_id, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to check calculations: {}", e)
+            })))
+        }
+    }
+}
+
 /// Generate hint for a problem
 pub async fn hint_problem(
     path: web::Path<String>,
@@ -279,7 +853,7 @@ pub async fn hint_problem(
 
     let hint_level = body.hint_level.unwrap_or(2).min(3).max(1);
     
-    let hint = match solver.hint(
+    let (provider, hint) = match solver.hint(
         &problem,
         body.provider.as_deref(),
         if theory_context.is_empty() { None } else { Some(&theory_context) },
@@ -294,6 +868,17 @@ pub async fn hint_problem(
         }
     };
 
+    let stored = ProblemHint {
+        problem_id: problem_id.clone(),
+        hint_level,
+        provider,
+        content: hint.clone(),
+        created_at: chrono::Utc::now(),
+    };
+    if let Err(e) = db.save_problem_hint(&stored).await {
+        log::warn!("Failed to store hint: {}", e);
+    }
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "problem_id": problem_id,
         "hint": hint,
@@ -301,14 +886,26 @@ pub async fn hint_problem(
     })))
 }
 
-/// Add problem to bookmarks
+#[derive(Debug, Deserialize)]
+pub struct BookmarkRequest {
+    pub folder: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Add problem to bookmarks (optionally into a folder with a note)
 pub async fn add_bookmark(
     path: web::Path<String>,
+    body: Option<web::Json<BookmarkRequest>>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let problem_id = path.into_inner();
+
+    let result = match body {
+        Some(b) => db.upsert_bookmark(&problem_id, b.folder.as_deref(), b.note.as_deref()).await,
+        None => db.add_bookmark(&problem_id).await,
+    };
     
-    match db.add_bookmark(&problem_id).await {
+    match result {
         Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
             "success": true,
             "problem_id": problem_id,
@@ -358,6 +955,112 @@ pub async fn list_bookmarks(
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookmarkExport {
+    pub version: u32,
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub bookmarks: Vec<BookmarkExportEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookmarkExportEntry {
+    #[serde(flatten)]
+    pub bookmark: crate::models::problem::Bookmark,
+    /// Problem snapshot (with sub-problems) used to restore it if missing on import
+    pub problem: Option<crate::models::Problem>,
+}
+
+/// Export all bookmarks with problem snapshots as JSON
+pub async fn export_bookmarks(
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let bookmarks = match db.get_bookmarks().await {
+        Ok(b) => b,
+        Err(e) => {
+            log::error!("Failed to export bookmarks: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to export bookmarks: {}", e)
+            })));
+        }
+    };
+
+    let mut entries = Vec::new();
+    for bookmark in bookmarks {
+        let problem = db.get_problem_with_subs(&bookmark.problem_id).await.ok().flatten();
+        entries.push(BookmarkExportEntry { bookmark, problem });
+    }
+
+    let export = BookmarkExport {
+        version: 1,
+        exported_at: chrono::Utc::now(),
+        bookmarks: entries,
+    };
+
+    Ok(HttpResponse::Ok()
+        .append_header(("Content-Disposition", "attachment; filename=\"bookmarks.json\""))
+        .json(export))
+}
+
+/// Import bookmarks exported by `export_bookmarks`.
+///
+/// Problems are matched by ID; missing ones are restored from the snapshot
+/// when their chapter exists, otherwise the bookmark is skipped.
+pub async fn import_bookmarks(
+    body: web::Json<BookmarkExport>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let mut imported = 0;
+    let mut restored = 0;
+    let mut skipped = Vec::new();
+
+    for entry in &body.bookmarks {
+        let problem_id = &entry.bookmark.problem_id;
+
+        let exists = matches!(db.get_problem(problem_id).await, Ok(Some(_)));
+        if !exists {
+            let chapter_exists = match &entry.problem {
+                Some(p) => matches!(db.get_chapter(&p.chapter_id).await, Ok(Some(_))),
+                None => false,
+            };
+            let Some(snapshot) = entry.problem.as_ref().filter(|_| chapter_exists) else {
+                skipped.push(problem_id.clone());
+                continue;
+            };
+
+            let mut to_create = vec![snapshot.clone()];
+            to_create.extend(snapshot.sub_problems.clone().unwrap_or_default());
+            match db.create_or_update_problems(&to_create).await {
+                Ok(writes) if writes[0].is_written() => {}
+                Ok(writes) => {
+                    log::warn!("Problem {} clashes with {:?}, not restored", problem_id, writes[0].existing_id);
+                    skipped.push(problem_id.clone());
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Failed to restore problem {}: {}", problem_id, e);
+                    skipped.push(problem_id.clone());
+                    continue;
+                }
+            }
+            restored += 1;
+        }
+
+        match db.upsert_bookmark(problem_id, entry.bookmark.folder.as_deref(), entry.bookmark.note.as_deref()).await {
+            Ok(_) => imported += 1,
+            Err(e) => {
+                log::warn!("Failed to import bookmark {}: {}", problem_id, e);
+                skipped.push(problem_id.clone());
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "imported": imported,
+        "restored_problems": restored,
+        "skipped": skipped,
+    })))
+}
+
 /// Get theory blocks for a chapter
 pub async fn get_chapter_theory(
     path: web::Path<String>,
@@ -376,6 +1079,312 @@ pub async fn get_chapter_theory(
     }
 }
 
+pub async fn get_theory_block(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let theory_id = path.into_inner();
+
+    match db.get_theory_block(&theory_id).await {
+        Ok(Some(theory)) => Ok(HttpResponse::Ok().json(theory)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Theory block not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get theory block: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get theory block: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTheoryRequest {
+    pub chapter_id: String,
+    pub title: Option<String>,
+    pub block_type: String,
+    pub content: String,
+    pub page_number: Option<u32>,
+}
+
+/// Fields of a theory block to change; omitted ones are kept
+#[derive(Debug, Deserialize)]
+pub struct UpdateTheoryRequest {
+    pub title: Option<String>,
+    pub block_type: Option<String>,
+    pub content: Option<String>,
+    pub page_number: Option<u32>,
+}
+
+fn parse_theory_type(name: &str) -> Result<TheoryType, HttpResponse> {
+    TheoryType::from_name(name).ok_or_else(|| {
+        let allowed: Vec<&str> = TheoryType::ALL.iter().map(TheoryType::as_str).collect();
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unknown block_type '{}'; expected one of: {}", name, allowed.join(", "))
+        }))
+    })
+}
+
+/// Add a hand-written theory block to the end of a chapter
+pub async fn create_theory_block(
+    body: web::Json<CreateTheoryRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let block_type = match parse_theory_type(&body.block_type) {
+        Ok(t) => t,
+        Err(response) => return Ok(response),
+    };
+    if body.content.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "content must not be empty"
+        })));
+    }
+
+    let chapter = match db.get_chapter(&body.chapter_id).await {
+        Ok(Some(chapter)) => chapter,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Chapter not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            })));
+        }
+    };
+
+    let block_num = db.next_theory_block_num(&chapter.id).await.map_err(|e| {
+        log::error!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+    let theory = TheoryBlock {
+        id: TheoryBlock::generate_id(&chapter.book_id, chapter.number, block_num),
+        chapter_id: chapter.id,
+        block_num,
+        title: body.title.clone().filter(|t| !t.trim().is_empty()),
+        block_type,
+        latex_formulas: extract_latex(&body.content),
+        content: body.content.clone(),
+        page_number: body.page_number,
+        created_at: chrono::Utc::now(),
+    };
+
+    match db.create_theory_block(&theory).await {
+        Ok(_) => Ok(HttpResponse::Created().json(theory)),
+        Err(e) => {
+            log::error!("Failed to create theory block: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to create theory block: {}", e)
+            })))
+        }
+    }
+}
+
+/// Fix the title, type, content or page of a theory block
+pub async fn update_theory_block(
+    path: web::Path<String>,
+    body: web::Json<UpdateTheoryRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let theory_id = path.into_inner();
+
+    let mut theory = match db.get_theory_block(&theory_id).await {
+        Ok(Some(theory)) => theory,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Theory block not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            })));
+        }
+    };
+
+    if let Some(name) = &body.block_type {
+        theory.block_type = match parse_theory_type(name) {
+            Ok(t) => t,
+            Err(response) => return Ok(response),
+        };
+    }
+    if let Some(content) = &body.content {
+        if content.trim().is_empty() {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "content must not be empty"
+            })));
+        }
+        theory.latex_formulas = extract_latex(content);
+        theory.content = content.clone();
+    }
+    if let Some(title) = &body.title {
+        theory.title = Some(title.clone()).filter(|t| !t.trim().is_empty());
+    }
+    if body.page_number.is_some() {
+        theory.page_number = body.page_number;
+    }
+
+    match db.update_theory_block(&theory).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(theory)),
+        Err(e) => {
+            log::error!("Failed to update theory block: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to update theory block: {}", e)
+            })))
+        }
+    }
+}
+
+pub async fn delete_theory_block(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let theory_id = path.into_inner();
+
+    match db.delete_theory_block(&theory_id).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "theory_id": theory_id,
+        }))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Theory block not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to delete theory block: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete theory block: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StudyPackQuery {
+    /// `json` (default) or `markdown`
+    pub format: Option<String>,
+    /// Ask the AI for a minimal hint for picked problems that have none yet
+    #[serde(default)]
+    pub generate_hints: bool,
+    pub provider: Option<String>,
+}
+
+/// Theory summary, key formulas and representative problems with their
+/// hints for a chapter, in one bundle
+pub async fn get_study_pack(
+    path: web::Path<String>,
+    query: web::Query<StudyPackQuery>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let chapter_id = path.into_inner();
+
+    let markdown = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("markdown") | Some("md") => true,
+        Some(other) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Unknown format '{}'; expected json or markdown", other)
+            })));
+        }
+    };
+
+    let chapter = match db.get_chapter(&chapter_id).await {
+        Ok(Some(chapter)) => chapter,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Chapter not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            })));
+        }
+    };
+
+    let (theory, problems) = match (
+        db.get_theory_blocks_by_chapter(&chapter_id).await,
+        db.get_problems_by_chapter(&chapter_id).await,
+    ) {
+        (Ok(theory), Ok(problems)) => (theory, problems),
+        (Err(e), _) | (_, Err(e)) => {
+            log::error!("Failed to load chapter {}: {}", chapter_id, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load chapter: {}", e)
+            })));
+        }
+    };
+
+    let picked = pick_representative(&problems, PACK_PROBLEMS);
+    let mut with_hints = Vec::with_capacity(picked.len());
+    for problem in picked {
+        let hints = db.get_problem_hints(&problem.id).await.unwrap_or_else(|e| {
+            log::warn!("Failed to load hints of {}: {}", problem.id, e);
+            Vec::new()
+        });
+        with_hints.push((problem, hints));
+    }
+
+    if query.generate_hints && with_hints.iter().any(|(_, hints)| hints.is_empty()) {
+        let solver = match AISolver::new(&config) {
+            Ok(s) => s,
+            Err(e) => {
+                return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "error": format!("AI solver not available: {}", e)
+                })));
+            }
+        };
+        let theory_context = theory.iter().map(|t| t.content.as_str()).collect::<Vec<_>>().join("\n\n");
+        let theory_context = Some(theory_context.as_str()).filter(|c| !c.is_empty());
+
+        let missing: Vec<&crate::models::Problem> =
+            with_hints.iter().filter(|(_, hints)| hints.is_empty()).map(|(problem, _)| *problem).collect();
+        let generated = futures::future::join_all(missing.into_iter().map(|problem| {
+            let solver = &solver;
+            let provider = query.provider.as_deref();
+            async move {
+                match solver.hint(problem, provider, theory_context, 1).await {
+                    Ok((provider, content)) => Some(ProblemHint {
+                        problem_id: problem.id.clone(),
+                        hint_level: 1,
+                        provider,
+                        content,
+                        created_at: chrono::Utc::now(),
+                    }),
+                    Err(e) => {
+                        log::warn!("Failed to generate hint for {}: {}", problem.id, e);
+                        None
+                    }
+                }
+            }
+        }))
+        .await;
+
+        for hint in generated.into_iter().flatten() {
+            if let Err(e) = db.save_problem_hint(&hint).await {
+                log::warn!("Failed to store hint: {}", e);
+            }
+            if let Some((_, hints)) = with_hints.iter_mut().find(|(problem, _)| problem.id == hint.problem_id) {
+                hints.push(hint);
+            }
+        }
+    }
+
+    let pack = build_study_pack(&chapter, &theory, &with_hints);
+    if markdown {
+        Ok(HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .body(render_markdown(&pack)))
+    } else {
+        Ok(HttpResponse::Ok().json(pack))
+    }
+}
+
 /// Record problem view in history
 pub async fn record_view(
     path: web::Path<String>,
@@ -436,10 +1445,20 @@ pub struct SearchQuery {
     chapter_id: Option<String>,
     book_id: Option<String>,
     has_solution: Option<bool>,
+    /// Limit `q` matches to problems, theory or solutions
+    kind: Option<SearchKind>,
     limit: Option<usize>,
     offset: Option<usize>,
 }
 
+#[derive(Serialize)]
+pub struct TextSearchResponse {
+    query: String,
+    hits: Vec<SearchHit>,
+    limit: usize,
+    offset: usize,
+}
+
 #[derive(Serialize)]
 pub struct SearchResponse {
     problems: Vec<crate::models::Problem>,
@@ -448,13 +1467,30 @@ pub struct SearchResponse {
     offset: usize,
 }
 
-/// Advanced search for problems
+/// Search: `?q=` is a full-text search of problem, theory and solution text
+/// returning ranked snippets (`&book_id=`, `&kind=problem|theory|solution`);
+/// without `q`, problems by formula, chapter, book or solution state
 pub async fn search_problems(
     query: web::Query<SearchQuery>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let limit = query.limit.unwrap_or(50).min(200);
     let offset = query.offset.unwrap_or(0);
+
+    if let Some(q) = query.q.as_deref().filter(|q| !q.trim().is_empty()) {
+        let Some(expression) = match_expression(q) else {
+            return Ok(HttpResponse::Ok().json(TextSearchResponse { query: q.to_string(), hits: Vec::new(), limit, offset }));
+        };
+        return match db.full_text_search(&expression, query.kind, query.book_id.as_deref(), limit, offset).await {
+            Ok(hits) => Ok(HttpResponse::Ok().json(TextSearchResponse { query: q.to_string(), hits, limit, offset })),
+            Err(e) => {
+                log::error!("Search failed: {}", e);
+                Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Search failed: {}", e)
+                })))
+            }
+        };
+    }
     
     let problems = db.advanced_search(
         query.q.as_deref(),
@@ -496,8 +1532,10 @@ pub async fn search_problems(
     }
 }
 
-/// Update problem content (e.g., from OCR import)
+/// Update problem content (e.g., from OCR import). The request names the
+/// version it was based on; 409 with the current problem if it's outdated.
 pub async fn update_problem(
+    req: HttpRequest,
     path: web::Path<String>,
     body: web::Json<UpdateProblemRequest>,
     db: web::Data<Database>,
@@ -505,23 +1543,41 @@ pub async fn update_problem(
     let problem_id = path.into_inner();
     
     // Verify problem exists
-    if db.get_problem(&problem_id).await.map_err(|e| {
+    let Some(current) = db.get_problem(&problem_id).await.map_err(|e| {
         log::error!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
-    })?.is_none() {
+    })? else {
         return Ok(HttpResponse::NotFound().json(serde_json::json!({
             "error": "Problem not found"
         })));
-    }
+    };
+    let Some(version) = expected_version(&req, body.version) else {
+        return Ok(version_required(&current));
+    };
 
     // Extract LaTeX formulas from content
     let latex_formulas = extract_latex(&body.content);
 
-    match db.update_problem_content(&problem_id, &body.content, latex_formulas).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": "Problem updated successfully"
-        }))),
+    match db.update_problem_content(&problem_id, &body.content, latex_formulas, Some(version)).await {
+        Ok(Some(version)) => Ok(HttpResponse::Ok()
+            .insert_header((header::ETAG, etag(version)))
+            .json(serde_json::json!({
+                "success": true,
+                "message": "Problem updated successfully",
+                "version": version,
+            }))),
+        Ok(None) => match db.get_problem(&problem_id).await {
+            Ok(Some(current)) => Ok(version_conflict(&current)),
+            Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Problem not found"
+            }))),
+            Err(e) => {
+                log::error!("Failed to get problem: {}", e);
+                Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to get problem: {}", e)
+                })))
+            }
+        },
         Err(e) => {
             log::error!("Failed to update problem: {}", e);
             Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -534,6 +1590,132 @@ pub async fn update_problem(
 #[derive(Debug, Deserialize)]
 pub struct UpdateProblemRequest {
     pub content: String,
+    /// Version being replaced when the request has no `If-Match`
+    pub version: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormulaFallbackRequest {
+    /// Formula to replace, delimiters included; defaults to the first invalid one
+    pub formula: Option<String>,
+    pub region: FormulaRegion,
+    /// Replace even if the formula hasn't failed enough OCR rounds yet
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Replace an unrecoverable formula with a crop of the original page image
+pub async fn apply_formula_fallback(
+    path: web::Path<String>,
+    body: web::Json<FormulaFallbackRequest>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+
+    if let Err(e) = body.region.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    let problem = match db.get_problem(&problem_id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Problem not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            })));
+        }
+    };
+
+    let Some(formula) = body
+        .formula
+        .clone()
+        .or_else(|| invalid_formulas(&problem.content).into_iter().next())
+    else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Problem has no invalid formulas"
+        })));
+    };
+
+    let attempts = db.get_formula_attempts(&problem_id).await.unwrap_or(0);
+    if attempts < FALLBACK_AFTER_ATTEMPTS && !body.force {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!(
+                "Formula failed {} of {} OCR attempts; re-run OCR or pass force=true",
+                attempts, FALLBACK_AFTER_ATTEMPTS
+            ),
+            "attempts": attempts,
+        })));
+    }
+
+    let (Some(page_number), Some(book_id)) = (problem.page_number, problem.chapter_id.split(':').next()) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Problem has no source page"
+        })));
+    };
+    let files = book_file_service(&db, &FileService::from_config(&config), book_id).await;
+    let book_file = files.book_file(book_id);
+    let image_name = formula_image_name(&problem_id, &formula);
+    let output = formula_image_dir(&config.preview_dir).join(&image_name);
+    // Render the region from the PDF at print resolution; the preview is
+    // only cropped when the book can't be rendered
+    if let Err(e) = files.render_region(&book_file, page_number, body.region, FORMULA_RENDER_DPI, &output) {
+        log::warn!("Failed to render formula region, cropping the preview instead: {}", e);
+        let page_image = files.page_image(&book_file, page_number);
+        if !page_image.exists() {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Page image not found. Generate previews first."
+            })));
+        }
+        if let Err(e) = crop_formula(&page_image, body.region, &output) {
+            log::error!("Failed to crop formula: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to crop formula: {}", e)
+            })));
+        }
+    }
+
+    let image_url = format!("{}/{}", FORMULA_IMAGE_ROUTE, image_name);
+    let Some(content) = replace_with_image(&problem.content, &formula, &image_url) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Formula not found in problem content"
+        })));
+    };
+
+    match db.update_problem_content(&problem_id, &content, extract_latex(&content), None).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "image_url": image_url,
+            "content": content,
+        }))),
+        Err(e) => {
+            log::error!("Failed to update problem: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to update problem: {}", e)
+            })))
+        }
+    }
+}
+
+/// Serve a cropped formula image
+pub async fn get_formula_image(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let url = format!("{}/{}", FORMULA_IMAGE_ROUTE, path.into_inner());
+    let Some(file) = formula_image_path(&config.preview_dir, &url) else {
+        return Ok(HttpResponse::NotFound().body("Image not found"));
+    };
+
+    match std::fs::read(&file) {
+        Ok(data) => Ok(HttpResponse::Ok().content_type("image/png").body(data)),
+        Err(_) => Ok(HttpResponse::NotFound().body("Image not found")),
+    }
 }
 
 /// Helper function to extract LaTeX formulas
@@ -0,0 +1,69 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::services::credentials::ProviderCredentials;
+use crate::services::provider_registry::{self, ProviderKind};
+
+#[derive(Debug, Deserialize)]
+pub struct ProvidersQuery {
+    /// "ocr" or "solve"; both when omitted
+    pub kind: Option<String>,
+    /// Only return providers with credentials configured
+    pub configured: Option<bool>,
+}
+
+/// List OCR and solve providers with capabilities, masked keys and circuit-breaker state
+pub async fn list_providers(
+    query: web::Query<ProvidersQuery>,
+) -> Result<HttpResponse, Error> {
+    let kind = match query.kind.as_deref() {
+        None => None,
+        Some("ocr") => Some(ProviderKind::Ocr),
+        Some("solve") => Some(ProviderKind::Solve),
+        Some(other) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid kind '{}'. Use: ocr, solve", other)
+            })));
+        }
+    };
+
+    let mut providers = provider_registry::list_providers(kind);
+    if query.configured.unwrap_or(false) {
+        providers.retain(|p| p.configured);
+    }
+
+    Ok(HttpResponse::Ok().json(providers))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProviderHealthQuery {
+    pub provider: Option<String>,
+}
+
+/// Ping each configured provider key with a cheap authenticated request
+pub async fn providers_health(
+    query: web::Query<ProviderHealthQuery>,
+) -> Result<HttpResponse, Error> {
+    let credentials = ProviderCredentials::global();
+
+    let providers: Vec<String> = match &query.provider {
+        Some(p) if !credentials.has_provider(p) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("Provider {} not configured", p)
+            })));
+        }
+        Some(p) => vec![p.clone()],
+        None => credentials.providers().into_iter().map(String::from).collect(),
+    };
+
+    let mut results = Vec::new();
+    for provider in &providers {
+        results.extend(credentials.check_health(provider).await);
+    }
+
+    let healthy = results.iter().all(|r| r.healthy);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "healthy": healthy,
+        "results": results,
+    })))
+}
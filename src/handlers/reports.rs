@@ -0,0 +1,66 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::services::database::Database;
+use crate::services::digest::{deliver_digest, generate_digest, DigestReport};
+
+#[derive(Debug, Deserialize)]
+pub struct ReportFormatQuery {
+    /// `json` (default), `markdown` or `html`
+    pub format: Option<String>,
+}
+
+fn report_response(report: &DigestReport, format: Option<&str>) -> HttpResponse {
+    match format {
+        Some("markdown") | Some("md") => HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .body(report.markdown.clone()),
+        Some("html") => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(report.html.clone()),
+        _ => HttpResponse::Ok().json(report),
+    }
+}
+
+/// Latest weekly digest, e.g. `?format=html` for the rendered report
+pub async fn get_latest_report(
+    query: web::Query<ReportFormatQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.get_latest_digest_report().await {
+        Ok(Some(report)) => Ok(report_response(&report, query.format.as_deref())),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No digest has been generated yet"
+        }))),
+        Err(e) => {
+            log::error!("Failed to get latest digest: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get latest digest: {}", e)
+            })))
+        }
+    }
+}
+
+/// Compose the digest of the time since the last one now instead of waiting
+/// for the weekly run, and send it to the digest webhook
+pub async fn generate_report(
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let mut report = match generate_digest(&db, chrono::Utc::now()).await {
+        Ok(report) => report,
+        Err(e) => {
+            log::error!("Failed to generate digest: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to generate digest: {}", e)
+            })));
+        }
+    };
+
+    match deliver_digest(&db, &config, &report).await {
+        Ok(delivered) => report.delivered = delivered,
+        Err(e) => log::warn!("Failed to deliver digest {}: {}", report.id, e),
+    }
+    Ok(HttpResponse::Created().json(report))
+}
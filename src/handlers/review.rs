@@ -0,0 +1,279 @@
+use std::collections::hash_map::{Entry, HashMap};
+
+use actix_web::{web, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Problem, ReviewStatus};
+use crate::services::database::Database;
+use crate::services::review::{ocr_snippet, validate_decision};
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ReviewRequest {
+    pub note: Option<String>,
+}
+
+/// Next unreviewed problem of a book (with sub-problems) and the review progress
+pub async fn get_next_for_review(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    match db.get_book(&book_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    }
+
+    let next = match db.get_next_unreviewed_problem(&book_id).await {
+        Ok(Some(problem)) => db.get_problem_with_subs(&problem.id).await,
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    match (next, db.get_review_progress(&book_id).await) {
+        (Ok(problem), Ok(progress)) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": book_id,
+            "problem": problem,
+            "progress": progress,
+        }))),
+        (Err(e), _) | (_, Err(e)) => {
+            log::error!("Failed to load review queue: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load review queue: {}", e)
+            })))
+        }
+    }
+}
+
+async fn set_review(
+    db: &Database,
+    problem_id: &str,
+    status: ReviewStatus,
+    note: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    let problem = match db.get_problem(problem_id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Problem not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get problem: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get problem: {}", e)
+            })));
+        }
+    };
+
+    if let Err(e) = validate_decision(problem.review_status, status, note) {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({ "error": e })));
+    }
+
+    match db.set_problem_review(problem_id, status, note).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "problem_id": problem_id,
+            "review_status": status,
+            "review_note": note,
+        }))),
+        Err(e) => {
+            log::error!("Failed to update review status: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to update review status: {}", e)
+            })))
+        }
+    }
+}
+
+/// Mark a problem as approved; the note is optional
+pub async fn approve_problem(
+    path: web::Path<String>,
+    body: Option<web::Json<ReviewRequest>>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    let body = body.map(|b| b.into_inner()).unwrap_or_default();
+    set_review(&db, &problem_id, ReviewStatus::Approved, body.note.as_deref()).await
+}
+
+/// Send a problem back for fixing; a note saying what is wrong is required
+pub async fn request_problem_fix(
+    path: web::Path<String>,
+    body: web::Json<ReviewRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let problem_id = path.into_inner();
+    let note = body.note.as_deref().map(str::trim).unwrap_or("");
+    if note.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "A note describing what needs fixing is required"
+        })));
+    }
+    set_review(&db, &problem_id, ReviewStatus::NeedsFix, Some(note)).await
+}
+
+// === Bulk review ===
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewBatchQuery {
+    pub book: String,
+    pub size: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewBatchItem {
+    pub problem: Problem,
+    /// Page OCR text around the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ocr_snippet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview_url: Option<String>,
+}
+
+/// Next `size` unreviewed problems with their page context, for keyboard-driven
+/// review sessions that shouldn't round-trip per problem
+pub async fn get_review_batch(
+    query: web::Query<ReviewBatchQuery>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let size = query.size.unwrap_or(20).clamp(1, 100);
+
+    let problems = match db.get_unreviewed_problems(&query.book, size).await {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("Failed to load review batch: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load review batch: {}", e)
+            })));
+        }
+    };
+
+    let mut page_texts: HashMap<u32, Option<String>> = HashMap::new();
+    let mut items = Vec::with_capacity(problems.len());
+
+    for problem in problems {
+        let problem = match db.get_problem_with_subs(&problem.id).await {
+            Ok(Some(p)) => p,
+            Ok(None) => problem,
+            Err(e) => {
+                log::warn!("Failed to load sub-problems of {}: {}", problem.id, e);
+                problem
+            }
+        };
+
+        let (ocr_snippet, preview_url) = match problem.page_number {
+            Some(page_number) => {
+                let text = match page_texts.entry(page_number) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => {
+                        let text = match db.get_page(&query.book, page_number).await {
+                            Ok(page) => {
+                                let text = page.and_then(|p| p.ocr_text);
+                                if text.is_none()
+                                    && let Err(e) = db.add_pending_pages(&query.book, &[page_number]).await
+                                {
+                                    log::warn!("Failed to mark page {} pending: {}", page_number, e);
+                                }
+                                text
+                            }
+                            Err(e) => {
+                                log::warn!("Failed to load page {}: {}", page_number, e);
+                                None
+                            }
+                        };
+                        e.insert(text)
+                    }
+                };
+                (
+                    text.as_deref().map(|t| ocr_snippet(t, &problem.content)),
+                    Some(format!("/preview_image/{}.pdf/{}", query.book, page_number)),
+                )
+            }
+            None => (None, None),
+        };
+
+        items.push(ReviewBatchItem { problem, ocr_snippet, preview_url });
+    }
+
+    match db.get_review_progress(&query.book).await {
+        Ok(progress) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "book_id": query.book,
+            "items": items,
+            "progress": progress,
+        }))),
+        Err(e) => {
+            log::error!("Failed to load review progress: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load review progress: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewDecision {
+    pub problem_id: String,
+    pub status: ReviewStatus,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewBatchRequest {
+    pub decisions: Vec<ReviewDecision>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewDecisionResult {
+    pub problem_id: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Apply several review decisions at once. Each decision is validated on its
+/// own; invalid ones are reported without failing the rest.
+pub async fn submit_review_batch(
+    body: web::Json<ReviewBatchRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let mut results = Vec::with_capacity(body.decisions.len());
+
+    for decision in &body.decisions {
+        let note = decision.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+        let outcome = match db.get_problem(&decision.problem_id).await {
+            Ok(Some(problem)) => match validate_decision(problem.review_status, decision.status, note) {
+                Ok(()) => db
+                    .set_problem_review(&decision.problem_id, decision.status, note)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            },
+            Ok(None) => Err("Problem not found".to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        results.push(ReviewDecisionResult {
+            problem_id: decision.problem_id.clone(),
+            ok: outcome.is_ok(),
+            error: outcome.err(),
+        });
+    }
+
+    let applied = results.iter().filter(|r| r.ok).count();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "applied": applied,
+        "failed": results.len() - applied,
+        "results": results,
+    })))
+}
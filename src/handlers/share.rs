@@ -0,0 +1,220 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use tera::Context;
+
+use crate::config::Config;
+use crate::handlers::preferences::page_preferences;
+use crate::handlers::textbook::katex_macros;
+use crate::models::{Problem, SolutionFilter};
+use crate::services::database::Database;
+use crate::services::share_link::{verify_token, ShareLink, ShareTarget, TokenError, DEFAULT_TTL_SECS};
+use crate::services::templates::Templates;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareLinkRequest {
+    /// `{"type": "problem" | "chapter" | "worksheet", "id": "..."}`
+    pub target: ShareTarget,
+    /// Seconds the link works; 7 days by default, 90 at most
+    pub ttl: Option<u64>,
+}
+
+fn internal_error(what: &str, e: anyhow::Error) -> HttpResponse {
+    log::error!("Failed to {}: {}", what, e);
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}: {}", what, e)
+    }))
+}
+
+/// `SHARE_LINK_SECRET`, or the one generated for this database
+async fn share_secret(config: &Config, db: &Database) -> anyhow::Result<Vec<u8>> {
+    match &config.share_link_secret {
+        Some(secret) => Ok(secret.as_bytes().to_vec()),
+        None => Ok(db.app_secret("share_links").await?.into_bytes()),
+    }
+}
+
+fn link_json(link: &ShareLink, config: &Config, secret: &[u8]) -> serde_json::Value {
+    serde_json::json!({
+        "id": link.id,
+        "target": link.target,
+        "expires_at": link.expires_at,
+        "created_at": link.created_at,
+        "url": format!("{}{}", config.base_url.trim_end_matches('/'), link.url(secret)),
+    })
+}
+
+async fn target_exists(db: &Database, target: &ShareTarget) -> anyhow::Result<bool> {
+    Ok(match target {
+        ShareTarget::Problem(id) => db.get_problem(id).await?.is_some(),
+        ShareTarget::Chapter(id) => db.get_chapter(id).await?.is_some(),
+        ShareTarget::Worksheet(id) => db.get_worksheet(id).await?.is_some(),
+    })
+}
+
+/// Create an expiring link that opens a read-only view of a problem (with
+/// its solution), a chapter summary or a worksheet without signing in
+pub async fn create_share_link(
+    body: web::Json<CreateShareLinkRequest>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let request = body.into_inner();
+    if request.ttl == Some(0) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "ttl must be at least one second"
+        })));
+    }
+    match target_exists(&db, &request.target).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("{} {} not found", request.target.kind(), request.target.id())
+            })));
+        }
+        Err(e) => return Ok(internal_error("look up share target", e)),
+    }
+    let secret = match share_secret(&config, &db).await {
+        Ok(secret) => secret,
+        Err(e) => return Ok(internal_error("load share secret", e)),
+    };
+
+    let link = ShareLink::new(request.target, request.ttl.unwrap_or(DEFAULT_TTL_SECS));
+    match db.save_share_link(&link).await {
+        Ok(()) => Ok(HttpResponse::Created().json(link_json(&link, &config, &secret))),
+        Err(e) => Ok(internal_error("create share link", e)),
+    }
+}
+
+/// Links that haven't expired yet, newest first
+pub async fn list_share_links(
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let secret = match share_secret(&config, &db).await {
+        Ok(secret) => secret,
+        Err(e) => return Ok(internal_error("load share secret", e)),
+    };
+    match db.list_share_links().await {
+        Ok(links) => Ok(HttpResponse::Ok().json(
+            links.iter().map(|link| link_json(link, &config, &secret)).collect::<Vec<_>>(),
+        )),
+        Err(e) => Ok(internal_error("list share links", e)),
+    }
+}
+
+/// Revoke a link before it expires
+pub async fn delete_share_link(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.delete_share_link(&path.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Share link not found"
+        }))),
+        Err(e) => Ok(internal_error("delete share link", e)),
+    }
+}
+
+fn shared_problem_json(problem: &Problem) -> serde_json::Value {
+    serde_json::json!({
+        "display_name": problem.display_name,
+        "content": problem.content,
+        "sub_problems": problem.sub_problems.iter().flatten().map(|sub| serde_json::json!({
+            "number": sub.number,
+            "content": sub.content,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn gone(message: &str) -> HttpResponse {
+    HttpResponse::Gone().content_type("text/plain; charset=utf-8").body(message.to_string())
+}
+
+/// Read-only page a share link opens
+pub async fn view_share_link(
+    path: web::Path<String>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let token = path.into_inner();
+    let secret = share_secret(&config, &db).await.map_err(|e| {
+        log::error!("Failed to load share secret: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+    let link_id = match verify_token(&token, &secret, Utc::now()) {
+        Ok(id) => id,
+        Err(TokenError::Expired) => return Ok(gone("This link has expired")),
+        Err(TokenError::Invalid) => return Ok(HttpResponse::NotFound().body("Link not found")),
+    };
+    let link = match db.get_share_link(link_id).await {
+        Ok(Some(link)) => link,
+        // Revoked
+        Ok(None) => return Ok(gone("This link is no longer available")),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Database error"));
+        }
+    };
+
+    let mut context = Context::new();
+    let content = async {
+        let book_id = match &link.target {
+            ShareTarget::Problem(id) => {
+                let Some(problem) = db.get_problem_with_subs(id).await? else { return anyhow::Ok(None) };
+                let mut best = db.get_filtered_solutions(std::slice::from_ref(&problem.id), &SolutionFilter::Any).await?;
+                context.insert("title", &problem.display_name);
+                context.insert("problems", &[shared_problem_json(&problem)]);
+                context.insert("solution", &best.remove(&problem.id).map(|s| s.content));
+                problem.chapter_id.split(':').next().map(str::to_string)
+            }
+            ShareTarget::Chapter(id) => {
+                let Some(chapter) = db.get_chapter(id).await? else { return Ok(None) };
+                let theory = db.get_theory_blocks_by_chapter(id).await?;
+                let problems = db.get_problem_tree_by_chapter(id).await?;
+                context.insert("title", &chapter.title);
+                context.insert("description", &chapter.description);
+                context.insert("theory", &theory);
+                context.insert("problems", &problems.iter().map(shared_problem_json).collect::<Vec<_>>());
+                Some(chapter.book_id)
+            }
+            ShareTarget::Worksheet(id) => {
+                let Some(worksheet) = db.get_worksheet(id).await? else { return Ok(None) };
+                let mut problems = Vec::new();
+                for problem_id in &worksheet.problem_ids {
+                    problems.extend(db.get_problem_with_subs(problem_id).await?);
+                }
+                context.insert("title", &worksheet.title);
+                context.insert("problems", &problems.iter().map(shared_problem_json).collect::<Vec<_>>());
+                problems.first().and_then(|p| p.chapter_id.split(':').next()).map(str::to_string)
+            }
+        };
+        Ok(Some(book_id))
+    };
+    let book_id = match content.await {
+        Ok(Some(book_id)) => book_id,
+        Ok(None) => return Ok(HttpResponse::NotFound().body("The shared content no longer exists")),
+        Err(e) => {
+            log::error!("Failed to load shared {} {}: {}", link.target.kind(), link.target.id(), e);
+            return Ok(HttpResponse::InternalServerError().body("Database error"));
+        }
+    };
+
+    context.insert("kind", link.target.kind());
+    context.insert("expires_at", &link.expires_at.format("%Y-%m-%d %H:%M UTC").to_string());
+    context.insert("katex_macros", &katex_macros(&db, book_id.as_deref().unwrap_or_default()).await);
+    context.insert("preferences", &page_preferences(&req, &db).await);
+    let rendered = tmpl.render("textbook/shared.html", &context).map_err(|e| {
+        log::error!("Template error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        // Keep the token out of referrers when the page links elsewhere
+        .append_header(("Referrer-Policy", "no-referrer"))
+        .body(rendered))
+}
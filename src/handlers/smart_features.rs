@@ -1,7 +1,10 @@
 use actix_web::{web, Error, HttpResponse};
 use serde::{Deserialize, Serialize};
 
+use crate::config::Config;
+use crate::services::ai_solver::AISolver;
 use crate::services::database::Database;
+use crate::services::explain::{normalize_selection, page_context, selection_key, Explanation, MAX_SELECTION_CHARS};
 use crate::services::toc_detector::{TocDetector, SmartImporter};
 use crate::services::knowledge_graph::{KnowledgeGraphBuilder};
 use crate::services::auto_tagger::AutoTagger;
@@ -182,9 +185,9 @@ pub struct TagResultResponse {
 pub async fn auto_tag_problems(
     body: web::Json<AutoTagRequest>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let api_key = std::env::var("MISTRAL_API_KEY").ok();
-    let tagger = AutoTagger::new(api_key);
+    let tagger = AutoTagger::new(&config);
 
     // Get problems
     let mut problems = Vec::new();
@@ -310,6 +313,8 @@ pub struct RecommendationResponse {
     pub problem_number: String,
     pub reason: String,
     pub similarity: f64,
+    /// Calibrated from students' answers when there are enough, else the static estimate
+    pub difficulty: Option<u8>,
 }
 
 pub async fn recommend_problems(
@@ -333,11 +338,16 @@ pub async fn recommend_problems(
     let recommender = ProblemRecommender::new();
     let recommendations = recommender.recommend_for_practice(&solved, &all_problems, count);
 
-    // Enrich with problem numbers
+    // Enrich with problem numbers and how hard the problems proved
+    let calibrations = db.get_calibrations(None).await.unwrap_or_else(|e| {
+        log::warn!("Failed to get difficulty calibrations: {}", e);
+        Default::default()
+    });
     let mut enriched = Vec::new();
     for rec in recommendations {
         if let Ok(Some(problem)) = db.get_problem(&rec.problem_id).await {
             enriched.push(RecommendationResponse {
+                difficulty: calibrations.get(&problem.id).map(|c| c.difficulty).or(problem.difficulty),
                 problem_id: rec.problem_id,
                 problem_number: problem.number,
                 reason: rec.reason,
@@ -412,3 +422,116 @@ pub async fn find_duplicates(
         groups: enriched_groups,
     }))
 }
+
+// === Explain Selection ===
+
+#[derive(Debug, Deserialize)]
+pub struct ExplainRequest {
+    pub book_id: String,
+    pub page: u32,
+    pub selected_text: String,
+    pub provider: Option<String>,
+    /// Ignore a cached explanation and ask again
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// Explain a theory passage or formula selected in the reader, using the rest
+/// of the page as context. Explanations are cached per selection.
+pub async fn explain_selection(
+    body: web::Json<ExplainRequest>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let selection = normalize_selection(&body.selected_text);
+    if selection.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "selected_text is empty"
+        })));
+    }
+    if selection.chars().count() > MAX_SELECTION_CHARS {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Selection too long (max {} characters)", MAX_SELECTION_CHARS)
+        })));
+    }
+
+    match db.get_book(&body.book_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Book not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get book: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get book: {}", e)
+            })));
+        }
+    }
+
+    let key = selection_key(&body.book_id, body.page, &selection);
+    if !body.refresh {
+        match db.get_explanation(&key).await {
+            Ok(Some(explanation)) => {
+                return Ok(HttpResponse::Ok().json(serde_json::json!({
+                    "explanation": explanation,
+                    "cached": true,
+                })));
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to read explanation cache: {}", e),
+        }
+    }
+
+    let context = match db.get_page(&body.book_id, body.page).await {
+        Ok(Some(page)) => page.ocr_text.map(|text| page_context(&text, &selection)),
+        Ok(None) => {
+            if let Err(e) = db.add_pending_pages(&body.book_id, &[body.page]).await {
+                log::warn!("Failed to mark page {} of {} pending: {}", body.page, body.book_id, e);
+            }
+            None
+        }
+        Err(e) => {
+            log::warn!("Failed to load page {} of {}: {}", body.page, body.book_id, e);
+            None
+        }
+    };
+
+    let solver = match AISolver::new(&config) {
+        Ok(s) => s,
+        Err(e) => {
+            return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "error": format!("AI solver not available: {}", e)
+            })));
+        }
+    };
+
+    let (provider, content) = match solver.explain(&selection, body.provider.as_deref(), context.as_deref()).await {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Failed to explain selection: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to explain selection: {}", e)
+            })));
+        }
+    };
+
+    let explanation = Explanation {
+        id: key,
+        book_id: body.book_id.clone(),
+        page_number: body.page,
+        selected_text: selection,
+        provider,
+        content,
+        created_at: chrono::Utc::now(),
+    };
+    if let Err(e) = db.save_explanation(&explanation).await {
+        log::warn!("Failed to cache explanation: {}", e);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "explanation": explanation,
+        "cached": false,
+    })))
+}
@@ -1,13 +1,92 @@
-use actix_web::{web, Error, HttpResponse};
-use tera::{Context, Tera};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use tera::Context;
 
+use crate::config::Config;
+use crate::handlers::embed::oembed_url;
+use crate::handlers::preferences::page_preferences;
 use crate::services::database::Database;
+use crate::services::anki_import::{import_cards, is_anki_book, parse_anki_text, read_apkg};
+use crate::services::figure::is_geometry_problem;
+use crate::services::latex_macros::BookMacros;
+use crate::services::plot::plot_request;
+use crate::services::templates::Templates;
+use crate::services::markdown_import::{import_markdown, MarkdownImportRequest};
 use crate::services::parser::TextbookParser;
 
+/// Macros passed to KaTeX so book notation (`\tg`, `\ctg`, ...) renders;
+/// the built-in defaults when the book's macros can't be loaded
+pub(crate) async fn katex_macros(db: &Database, book_id: &str) -> std::collections::BTreeMap<String, String> {
+    match db.get_book_macros(book_id).await {
+        Ok(macros) => macros.effective(),
+        Err(e) => {
+            log::warn!("Failed to load LaTeX macros for {}: {}", book_id, e);
+            BookMacros::default().effective()
+        }
+    }
+}
+
+/// Book overview page: chapters with their progress
+pub async fn view_book(
+    path: web::Path<String>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let book = match db.get_book(&book_id).await {
+        Ok(Some(b)) => b,
+        Ok(None) => return Ok(HttpResponse::NotFound().body("Book not found")),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().body("Database error"));
+        }
+    };
+
+    let chapters = db.get_chapters_by_book(&book_id).await.map_err(|e| {
+        log::error!("Failed to get chapters: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let mut chapter_rows = Vec::with_capacity(chapters.len());
+    let (mut problem_total, mut solved_total) = (0, 0);
+    for chapter in chapters {
+        let problems = db.get_problems_by_chapter(&chapter.id).await.map_err(|e| {
+            log::error!("Failed to get problems: {}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+        let solved = problems.iter().filter(|p| p.has_solution).count();
+        problem_total += problems.len();
+        solved_total += solved;
+        chapter_rows.push(serde_json::json!({
+            "chapter": chapter,
+            "problem_count": problems.len(),
+            "solved_count": solved,
+        }));
+    }
+
+    let mut context = Context::new();
+    context.insert("book", &book);
+    context.insert("book_id", &book.id);
+    context.insert("chapters", &chapter_rows);
+    context.insert("problem_count", &problem_total);
+    context.insert("solved_count", &solved_total);
+    context.insert("katex_macros", &katex_macros(&db, &book.id).await);
+
+    context.insert("preferences", &page_preferences(&req, &db).await);
+    let rendered = tmpl.render("textbook/book_view.html", &context).map_err(|e| {
+        log::error!("Template error: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
 /// View chapter problems page
 pub async fn view_chapter(
     path: web::Path<String>,
-    tmpl: web::Data<Tera>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let chapter_id = path.into_inner();
@@ -22,8 +101,8 @@ pub async fn view_chapter(
         }
     };
     
-    // Get problems
-    let problems = db.get_problems_by_chapter(&chapter_id).await.map_err(|e| {
+    // Get problems with sub-problems and solutions
+    let problems = db.get_chapter_full(&chapter_id).await.map_err(|e| {
         log::error!("Failed to get problems: {}", e);
         actix_web::error::ErrorInternalServerError(e)
     })?;
@@ -35,7 +114,7 @@ pub async fn view_chapter(
     })?;
     
     // Count solved problems
-    let solved_count = problems.iter().filter(|p| p.has_solution).count();
+    let solved_count = problems.iter().filter(|p| p.problem.has_solution).count();
     
     // Get book info
     let book = db.get_book(&chapter.book_id).await.map_err(|e| {
@@ -59,7 +138,9 @@ pub async fn view_chapter(
     context.insert("book", &book);
     context.insert("book_id", &book.id);
     context.insert("book_title", &book.title);
+    context.insert("katex_macros", &katex_macros(&db, &book.id).await);
     
+    context.insert("preferences", &page_preferences(&req, &db).await);
     let rendered = tmpl.render("textbook/chapter_problems.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
@@ -71,8 +152,10 @@ pub async fn view_chapter(
 /// View single problem page
 pub async fn view_problem(
     path: web::Path<String>,
-    tmpl: web::Data<Tera>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
     db: web::Data<Database>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, Error> {
     let problem_id = path.into_inner();
     
@@ -121,15 +204,38 @@ pub async fn view_problem(
     } else {
         None
     };
-    
+
+    // Stored solutions, newest first
+    let solutions = db.get_solutions_by_problem(&problem_id).await.unwrap_or_else(|e| {
+        log::warn!("Failed to get solutions for {}: {}", problem_id, e);
+        Vec::new()
+    });
+
+    // "Постройте график" problems show the graph of their functions
+    let graph_url = plot_request(&problem)
+        .map(|spec| format!("/api/render/graph?expr={}", urlencoding::encode(&spec.functions.join("; "))));
+
+    // Geometry problems show their stored drawing and can ask for one
+    let figure = db.get_problem_figure(&problem_id).await.unwrap_or_else(|e| {
+        log::warn!("Failed to get figure of {}: {}", problem_id, e);
+        None
+    });
+
     let mut context = Context::new();
     context.insert("problem", &problem);
     context.insert("parent_problem", &parent_problem);
+    context.insert("graph_url", &graph_url);
+    context.insert("figure", &figure);
+    context.insert("is_geometry", &is_geometry_problem(&problem));
+    context.insert("solutions", &solutions);
     context.insert("chapter", &chapter);
     context.insert("book", &book);
     context.insert("book_id", &book.id);
     context.insert("book_title", &book.title);
+    context.insert("katex_macros", &katex_macros(&db, &book.id).await);
+    context.insert("oembed_url", &oembed_url(&config, &problem_id));
     
+    context.insert("preferences", &page_preferences(&req, &db).await);
     let rendered = tmpl.render("textbook/problem_view.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
@@ -204,10 +310,88 @@ pub async fn import_textbook(
     })))
 }
 
+/// Import hand-written problems from markdown (`## Задача 5`, `а) ...`)
+pub async fn import_markdown_problems(
+    body: web::Json<MarkdownImportRequest>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match import_markdown(&db, &body).await {
+        Ok(summary) => Ok(HttpResponse::Ok().json(summary)),
+        Err(e) => {
+            log::error!("Markdown import failed: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Import failed: {}", e)
+            })))
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AnkiImportQuery {
+    pub book_id: String,
+    pub title: Option<String>,
+}
+
+/// Import an Anki `.apkg` package or plain-text notes export sent as the request body
+pub async fn import_anki_deck(
+    query: web::Query<AnkiImportQuery>,
+    body: web::Bytes,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    let title = query.title.clone().unwrap_or_else(|| format!("Anki: {}", query.book_id));
+    match db.get_book(&query.book_id).await {
+        Ok(Some(book)) if !is_anki_book(&book) => {
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("Book {} is not an Anki import; choose a new book_id", query.book_id)
+            })));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to load book: {}", e)
+            })));
+        }
+    }
+
+    // .apkg files are zip archives
+    let cards = if body.starts_with(b"PK") {
+        read_apkg(&body).await
+    } else {
+        std::str::from_utf8(&body)
+            .map(|text| parse_anki_text(text, &title))
+            .map_err(anyhow::Error::from)
+    };
+
+    let cards = match cards {
+        Ok(cards) if cards.is_empty() => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "No notes found in the Anki export"
+            })));
+        }
+        Ok(cards) => cards,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Failed to read Anki export: {}", e)
+            })));
+        }
+    };
+
+    match import_cards(&db, &query.book_id, &title, &cards).await {
+        Ok(summary) => Ok(HttpResponse::Ok().json(summary)),
+        Err(e) => {
+            log::error!("Anki import failed: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Import failed: {}", e)
+            })))
+        }
+    }
+}
+
 /// View book pages (page browser) - shows ALL pages from PDF
 pub async fn view_book_pages(
     path: web::Path<String>,
-    tmpl: web::Data<Tera>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
     db: web::Data<Database>,
     file_service: web::Data<crate::services::FileService>,
 ) -> Result<HttpResponse, Error> {
@@ -235,8 +419,19 @@ pub async fn view_book_pages(
         .collect();
     
     // Get total pages from PDF metadata
-    let total_pages = match file_service.get_pdf_page_count(&format!("{}.pdf", book_id)) {
-        Ok(count) => count,
+    let total_pages = match file_service.get_pdf_page_count(&file_service.book_file(&book_id)) {
+        Ok(count) => {
+            if count != book.total_pages
+                && let Err(e) = db.set_book_total_pages(&book_id, count).await
+            {
+                log::warn!("Failed to store page count of {}: {}", book_id, e);
+            }
+            count
+        }
+        Err(e) if book.total_pages > 0 => {
+            log::warn!("Failed to get PDF page count: {}, using stored {}", e, book.total_pages);
+            book.total_pages
+        }
         Err(e) => {
             log::warn!("Failed to get PDF page count: {}, using default 100", e);
             100
@@ -257,6 +452,8 @@ pub async fn view_book_pages(
                 ocr_text: None,
                 has_problems: false,
                 problem_count: 0,
+                page_kind: None,
+                ocr_provider: None,
                 created_at: chrono::Utc::now(),
                 updated_at: chrono::Utc::now(),
             });
@@ -269,6 +466,7 @@ pub async fn view_book_pages(
     context.insert("total_pages", &total_pages);
     context.insert("pages_with_ocr", &ocr_pages_map.len());
     
+    context.insert("preferences", &page_preferences(&req, &db).await);
     let rendered = tmpl.render("textbook/page_browser.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
@@ -280,7 +478,8 @@ pub async fn view_book_pages(
 /// View specific page with OCR and problems
 pub async fn view_page(
     path: web::Path<(String, u32)>,
-    tmpl: web::Data<Tera>,
+    req: HttpRequest,
+    tmpl: web::Data<Templates>,
     db: web::Data<Database>,
 ) -> Result<HttpResponse, Error> {
     let (book_id, page_number) = path.into_inner();
@@ -329,7 +528,9 @@ pub async fn view_page(
     context.insert("page", &page);
     context.insert("problems", &problems);
     context.insert("preview_path", &preview_path);
+    context.insert("katex_macros", &katex_macros(&db, &book_id).await);
     
+    context.insert("preferences", &page_preferences(&req, &db).await);
     let rendered = tmpl.render("textbook/page_view.html", &context).map_err(|e| {
         log::error!("Template error: {}", e);
         actix_web::error::ErrorInternalServerError(e)
@@ -0,0 +1,151 @@
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::services::database::Database;
+use crate::services::worksheet::{RenderedWorksheet, WorksheetConstraints, WorksheetGenerator};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWorksheetRequest {
+    pub title: Option<String>,
+    /// Explicit problem selection; takes precedence over `constraints`
+    pub problem_ids: Option<Vec<String>>,
+    pub constraints: Option<WorksheetConstraints>,
+    pub include_answer_key: Option<bool>,
+}
+
+fn worksheet_response(id: &str, rendered: RenderedWorksheet) -> HttpResponse {
+    let filename = format!("worksheet_{}.{}", id, rendered.extension);
+    HttpResponse::Ok()
+        .content_type(rendered.mime_type())
+        .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .append_header(("X-Worksheet-Id", id.to_string()))
+        .body(rendered.data)
+}
+
+/// Create a worksheet from problem IDs or generation constraints and return the document
+pub async fn create_worksheet(
+    body: web::Json<CreateWorksheetRequest>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let generator = WorksheetGenerator::new(db.get_ref().clone(), &config);
+
+    let problem_ids = match (&body.problem_ids, &body.constraints) {
+        (Some(ids), _) if !ids.is_empty() => {
+            let mut problem_ids: Vec<String> = Vec::new();
+            for id in ids.iter().map(|id| id.trim()).filter(|id| !id.is_empty()) {
+                if !problem_ids.iter().any(|seen| seen == id) {
+                    problem_ids.push(id.to_string());
+                }
+            }
+            for problem_id in &problem_ids {
+                match db.get_problem(problem_id).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                            "error": format!("Problem {} not found", problem_id)
+                        })));
+                    }
+                    Err(e) => {
+                        log::error!("Failed to get problem: {}", e);
+                        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                            "error": format!("Failed to get problem: {}", e)
+                        })));
+                    }
+                }
+            }
+            problem_ids
+        }
+        (_, Some(constraints)) => match generator.select_problems(constraints).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Failed to select problems: {}", e)
+                })));
+            }
+        },
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Provide problem_ids or constraints"
+            })));
+        }
+    };
+
+    if problem_ids.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No problems match the given constraints"
+        })));
+    }
+
+    let title = body.title.clone().unwrap_or_else(|| "Самостоятельная работа".to_string());
+    let worksheet = match generator.create(&title, problem_ids, body.include_answer_key.unwrap_or(true)).await {
+        Ok(w) => w,
+        Err(e) => {
+            log::error!("Failed to create worksheet: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to create worksheet: {}", e)
+            })));
+        }
+    };
+
+    match generator.render(&worksheet).await {
+        Ok(rendered) => Ok(worksheet_response(&worksheet.id, rendered)),
+        Err(e) => {
+            log::error!("Failed to render worksheet: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to render worksheet: {}", e)
+            })))
+        }
+    }
+}
+
+/// List stored worksheets
+pub async fn list_worksheets(
+    db: web::Data<Database>,
+) -> Result<HttpResponse, Error> {
+    match db.list_worksheets().await {
+        Ok(worksheets) => Ok(HttpResponse::Ok().json(worksheets)),
+        Err(e) => {
+            log::error!("Failed to list worksheets: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to list worksheets: {}", e)
+            })))
+        }
+    }
+}
+
+/// Re-render a stored worksheet (same problems, same order)
+pub async fn get_worksheet(
+    path: web::Path<String>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let worksheet_id = path.into_inner();
+
+    let worksheet = match db.get_worksheet(&worksheet_id).await {
+        Ok(Some(w)) => w,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Worksheet not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to get worksheet: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to get worksheet: {}", e)
+            })));
+        }
+    };
+
+    let generator = WorksheetGenerator::new(db.get_ref().clone(), &config);
+    match generator.render(&worksheet).await {
+        Ok(rendered) => Ok(worksheet_response(&worksheet.id, rendered)),
+        Err(e) => {
+            log::error!("Failed to render worksheet: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to render worksheet: {}", e)
+            })))
+        }
+    }
+}
@@ -16,6 +16,11 @@ fn main() {
 
     let cli = Cli::parse();
 
+    // The server applies them itself
+    if !matches!(cli.command, Some(Commands::Serve) | None) {
+        config::Config::new().configure_commands();
+    }
+
     match &cli.command {
         Some(Commands::Serve) | None => {
             actix_web::rt::System::new()
@@ -31,5 +36,20 @@ fn main() {
         Some(Commands::PdfInfo { file }) => {
             cli::handle_pdf_info(file);
         }
+        Some(Commands::ImportMd { file, book, chapter, title }) => {
+            cli::handle_import_md(file, book, *chapter, title.as_deref());
+        }
+        Some(Commands::MigrateSlugs { dry_run }) => {
+            cli::handle_migrate_slugs(*dry_run);
+        }
+        Some(Commands::MigratePreviews { dry_run }) => {
+            cli::handle_migrate_previews(*dry_run);
+        }
+        Some(Commands::CleanupSolutions { book, threshold, apply }) => {
+            cli::handle_cleanup_solutions(book.as_deref(), *threshold, *apply);
+        }
+        Some(Commands::Cache { command }) => {
+            cli::handle_cache(command);
+        }
     }
 }
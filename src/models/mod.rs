@@ -1,3 +1,4 @@
+pub mod pdf;
 pub mod problem;
 
 use serde::{Deserialize, Serialize};
@@ -15,16 +16,90 @@ impl fmt::Display for OcrError {
 
 impl Error for OcrError {}
 
+/// File name taken from a URL, checked to be a plain relative path: no `..`,
+/// no absolute paths, no backslashes or NUL bytes. Percent-encoded
+/// separators are already decoded when this is deserialized, so
+/// `..%2fsecret` is refused as well.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SafeFileName(String);
+
+impl SafeFileName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for SafeFileName {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if name.is_empty() {
+            return Err("File name is empty".to_string());
+        }
+        if name.contains(['\\', '\0']) {
+            return Err(format!("Invalid characters in file name: {:?}", name));
+        }
+        let path = std::path::Path::new(&name);
+        if !path.components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+            return Err(format!("File name must be a relative path without '..': {:?}", name));
+        }
+        Ok(Self(name))
+    }
+}
+
+impl std::ops::Deref for SafeFileName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SafeFileName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// 1-based page number from a URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u32")]
+pub struct PageNumber(u32);
+
+impl PageNumber {
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for PageNumber {
+    type Error = String;
+
+    fn try_from(page: u32) -> Result<Self, Self::Error> {
+        if page == 0 {
+            return Err("Page numbers start at 1".to_string());
+        }
+        Ok(Self(page))
+    }
+}
+
+impl fmt::Display for PageNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PreviewImageParams {
-    pub filename: String,
-    pub page: usize,
+    pub filename: SafeFileName,
+    pub page: PageNumber,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PreviewParams {
-    pub file: String,
-    pub page: u32,
+    pub file: SafeFileName,
+    pub page: PageNumber,
 }
 
 #[derive(Debug, Serialize)]
@@ -34,8 +109,10 @@ pub struct OcrResponse {
 
 #[derive(Debug, Serialize)]
 pub struct MetadataResponse {
-    pub metadata: std::collections::HashMap<String, String>,
+    pub metadata: PdfMetadata,
 }
 
+pub use pdf::PdfMetadata;
+
 // Re-export problem models
 pub use problem::*; 
\ No newline at end of file
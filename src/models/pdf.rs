@@ -0,0 +1,181 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Document information of a PDF, as reported by `pdfinfo`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PdfMetadata {
+    pub pages: u32,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<DateTime<FixedOffset>>,
+    pub modification_date: Option<DateTime<FixedOffset>>,
+    /// Size of the first page
+    pub page_size: Option<PageSize>,
+    pub pdf_version: Option<String>,
+    pub encrypted: bool,
+    /// Every field as printed, including the ones without a typed counterpart
+    pub raw: BTreeMap<String, String>,
+}
+
+/// Page dimensions in PostScript points (1/72 inch)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageSize {
+    pub width: f64,
+    pub height: f64,
+    /// Paper name pdfinfo recognised, e.g. `A4` or `letter`
+    pub name: Option<String>,
+}
+
+impl PdfMetadata {
+    /// Metadata from `pdfinfo`'s `Key: value` fields; the page count is required
+    pub fn from_fields(fields: &HashMap<String, String>) -> Result<Self, String> {
+        let field = |key: &str| fields.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let text = |key: &str| field(key).map(str::to_string);
+        let pages = field("Pages")
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(|| "Could not parse page count".to_string())?;
+        Ok(Self {
+            pages,
+            title: text("Title"),
+            author: text("Author"),
+            subject: text("Subject"),
+            keywords: text("Keywords"),
+            creator: text("Creator"),
+            producer: text("Producer"),
+            creation_date: field("CreationDate").and_then(parse_pdf_date),
+            modification_date: field("ModDate").and_then(parse_pdf_date),
+            page_size: field("Page size").and_then(PageSize::parse),
+            pdf_version: text("PDF version"),
+            encrypted: field("Encrypted").is_some_and(|v| v.starts_with("yes")),
+            raw: fields.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        })
+    }
+}
+
+impl PageSize {
+    /// `612 x 792 pts (letter)`, `595.276 x 841.89 pts`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (dimensions, name) = match value.split_once('(') {
+            Some((dimensions, name)) => (dimensions, Some(name.trim_end_matches(')').trim())),
+            None => (value, None),
+        };
+        let (width, height) = dimensions.trim().trim_end_matches("pts").split_once('x')?;
+        Some(Self {
+            width: width.trim().parse().ok()?,
+            height: height.trim().parse().ok()?,
+            name: name.filter(|n| !n.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+/// `Key: value` lines of `pdfinfo`'s output; values may contain colons
+pub fn pdfinfo_fields(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Date as printed by `pdfinfo -isodates` (`2019-06-13T11:30:47+02`) or
+/// stored in the PDF (`D:20190613113047+02'00'`). Dates without a time zone
+/// are taken as UTC.
+pub fn parse_pdf_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Some(pdf_date) = value.strip_prefix("D:") {
+        return parse_pdf_date_string(pdf_date);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date);
+    }
+    if let Ok(date) = DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%#z") {
+        return Some(date);
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .map(|date| date.and_utc().fixed_offset())
+}
+
+/// `YYYYMMDDHHmmSS` followed by `Z` or `+HH'mm'`, every part after the year
+/// optional
+fn parse_pdf_date_string(value: &str) -> Option<DateTime<FixedOffset>> {
+    let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+    let (stamp, zone) = value.split_at(digits);
+    if stamp.len() < 4 || stamp.len() % 2 != 0 {
+        return None;
+    }
+    let part = |range: std::ops::Range<usize>, default: u32| stamp.get(range).map_or(Some(default), |p| p.parse().ok());
+    let date = NaiveDate::from_ymd_opt(stamp[..4].parse().ok()?, part(4..6, 1)?, part(6..8, 1)?)?
+        .and_hms_opt(part(8..10, 0)?, part(10..12, 0)?, part(12..14, 0)?)?;
+
+    let offset = match zone.chars().next() {
+        None | Some('Z') => 0,
+        Some(sign @ ('+' | '-')) => {
+            let mut numbers = zone[1..].split('\'').filter(|n| !n.is_empty());
+            let hours: i32 = numbers.next()?.parse().ok().filter(|h| *h <= 23)?;
+            let minutes: i32 = numbers.next().map_or(Some(0), |m| m.parse().ok())?;
+            if minutes > 59 {
+                return None;
+            }
+            let seconds = hours * 3600 + minutes * 60;
+            if sign == '-' { -seconds } else { seconds }
+        }
+        Some(_) => return None,
+    };
+    date.and_local_timezone(FixedOffset::east_opt(offset)?).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pdfinfo_output() {
+        let output = "Title:           Алгебра 7: учебник\nAuthor:          \nCreationDate:    2019-06-13T11:30:47+02\n\
+                      Tagged:          no\nPages:           212\nEncrypted:       no\n\
+                      Page size:       595.276 x 841.89 pts (A4)\nPDF version:    1.6\n";
+        let metadata = PdfMetadata::from_fields(&pdfinfo_fields(output)).unwrap();
+        assert_eq!(metadata.pages, 212);
+        assert_eq!(metadata.title.as_deref(), Some("Алгебра 7: учебник"));
+        assert_eq!(metadata.author, None);
+        assert_eq!(metadata.creation_date.unwrap().to_rfc3339(), "2019-06-13T11:30:47+02:00");
+        assert_eq!(
+            metadata.page_size,
+            Some(PageSize { width: 595.276, height: 841.89, name: Some("A4".to_string()) })
+        );
+        assert_eq!(metadata.pdf_version.as_deref(), Some("1.6"));
+        assert!(!metadata.encrypted);
+        assert_eq!(metadata.raw.get("Tagged").map(String::as_str), Some("no"));
+        assert!(PdfMetadata::from_fields(&pdfinfo_fields("Title: x\n")).is_err());
+    }
+
+    #[test]
+    fn parses_page_sizes() {
+        assert_eq!(PageSize::parse("612 x 792 pts"), Some(PageSize { width: 612.0, height: 792.0, name: None }));
+        assert_eq!(PageSize::parse("612 x 792 pts (letter)").unwrap().name.as_deref(), Some("letter"));
+        assert_eq!(PageSize::parse("unknown"), None);
+    }
+
+    #[test]
+    fn parses_iso_and_pdf_dates() {
+        for (value, expected) in [
+            ("2019-06-13T11:30:47Z", "2019-06-13T11:30:47+00:00"),
+            ("2019-06-13T11:30:47-05:30", "2019-06-13T11:30:47-05:30"),
+            ("2019-06-13T11:30:47", "2019-06-13T11:30:47+00:00"),
+            ("D:20190613113047+02'00'", "2019-06-13T11:30:47+02:00"),
+            ("D:20190613113047-05'30", "2019-06-13T11:30:47-05:30"),
+            ("D:20190613113047Z", "2019-06-13T11:30:47+00:00"),
+            ("D:2019", "2019-01-01T00:00:00+00:00"),
+        ] {
+            assert_eq!(parse_pdf_date(value).map(|d| d.to_rfc3339()).as_deref(), Some(expected), "{}", value);
+        }
+        for value in ["", "Thu Jun 13 11:30:47 2019", "D:201", "D:20191313", "D:2019+99999999", "D:2019-02'99999999"] {
+            assert_eq!(parse_pdf_date(value), None, "{}", value);
+        }
+    }
+}
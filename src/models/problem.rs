@@ -52,6 +52,22 @@ pub struct Problem {
     /// Is this problem bookmarked/favorited?
     #[serde(default)]
     pub is_bookmarked: bool,
+    /// Extraction confidence (0.0 - 1.0) from OCR provider and text heuristics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    /// Manual review state of the extracted problem
+    #[serde(default)]
+    pub review_status: ReviewStatus,
+    /// Reviewer's note, e.g. what needs fixing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review_note: Option<String>,
+    /// Short answer printed in the book's answers section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_answer: Option<String>,
+    /// Edit counter for optimistic concurrency: bumped whenever the content
+    /// changes, sent back as `If-Match` by clients updating the problem
+    #[serde(default)]
+    pub version: u32,
 }
 
 /// Represents a PDF page with OCR text
@@ -63,10 +79,63 @@ pub struct Page {
     pub ocr_text: Option<String>,
     pub has_problems: bool,
     pub problem_count: u32,
+    /// Set by the image check before batch OCR; blank pages are not sent to OCR
+    pub page_kind: Option<PageKind>,
+    /// OCR provider that read `ocr_text`; "pdftext" for the PDF's own text layer
+    pub ocr_provider: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// What a page image shows, judged from its brightness histogram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageKind {
+    Blank,
+    Text,
+    /// Full-page illustration, photo or decorated title page
+    ImageOnly,
+}
+
+impl PageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageKind::Blank => "blank",
+            PageKind::Text => "text",
+            PageKind::ImageOnly => "image_only",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blank" => Some(PageKind::Blank),
+            "text" => Some(PageKind::Text),
+            "image_only" => Some(PageKind::ImageOnly),
+            _ => None,
+        }
+    }
+}
+
+impl Page {
+    /// Not read yet: no OCR text, and not skipped as blank
+    pub fn is_pending(&self) -> bool {
+        self.ocr_text.is_none() && self.page_kind != Some(PageKind::Blank)
+    }
+}
+
+/// Page that is referenced, e.g. by a problem continuing onto it, but hasn't
+/// been OCR'd. It has a placeholder row until it is.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingPage {
+    pub page_number: u32,
+    /// Chapter the page falls in, that of the closest problem at or before
+    /// it; problems found by OCR go there
+    pub chapter_id: Option<String>,
+    /// Top-level problems on the page or continuing onto it
+    pub problem_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
 /// Represents a theory/explanation block from textbook
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TheoryBlock {
@@ -100,6 +169,85 @@ pub enum TheoryType {
     Other,
 }
 
+impl TheoryType {
+    pub const ALL: [TheoryType; 8] = [
+        TheoryType::Definition,
+        TheoryType::Theorem,
+        TheoryType::Proof,
+        TheoryType::Property,
+        TheoryType::Formula,
+        TheoryType::Explanation,
+        TheoryType::Example,
+        TheoryType::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TheoryType::Definition => "definition",
+            TheoryType::Theorem => "theorem",
+            TheoryType::Proof => "proof",
+            TheoryType::Property => "property",
+            TheoryType::Formula => "formula",
+            TheoryType::Explanation => "explanation",
+            TheoryType::Example => "example",
+            TheoryType::Other => "other",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == name)
+    }
+}
+
+/// Review state of a parsed problem.
+///
+/// New problems start `Unreviewed`; a reviewer approves them or sends them
+/// back as `NeedsFix` with a note. A fixed problem can then be approved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    #[default]
+    Unreviewed,
+    Approved,
+    NeedsFix,
+}
+
+impl ReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewStatus::Unreviewed => "unreviewed",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::NeedsFix => "needs_fix",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unreviewed" => Some(ReviewStatus::Unreviewed),
+            "approved" => Some(ReviewStatus::Approved),
+            "needs_fix" => Some(ReviewStatus::NeedsFix),
+            _ => None,
+        }
+    }
+
+    /// Whether a reviewer may move a problem from `self` to `next`.
+    /// Nothing moves back to `Unreviewed`; that only happens on re-parse.
+    pub fn can_transition_to(&self, next: ReviewStatus) -> bool {
+        !matches!(
+            (self, next),
+            (_, ReviewStatus::Unreviewed) | (ReviewStatus::Approved, ReviewStatus::Approved)
+        )
+    }
+}
+
+/// Per-book review counts (top-level problems only)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ReviewProgress {
+    pub unreviewed: u32,
+    pub approved: u32,
+    pub needs_fix: u32,
+}
+
 /// AI-generated solution for a problem
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Solution {
@@ -115,12 +263,62 @@ pub struct Solution {
     pub is_verified: bool,
     /// User rating (1-5)
     pub rating: Option<u8>,
+    /// Model parameters used; None for manual and imported solutions
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
+    /// Solve cache key of the request that generated it, see
+    /// [`crate::services::solve_cache::solve_cache_key`]
+    #[serde(default, skip_serializing)]
+    pub prompt_hash: Option<String>,
+    /// Edit counter, bumped whenever the content is replaced
+    #[serde(default)]
+    pub version: u32,
     /// Generation timestamp
     pub created_at: DateTime<Utc>,
     /// Last update timestamp
     pub updated_at: DateTime<Utc>,
 }
 
+/// File attached to a solution: an uploaded image or a generated plot. The
+/// bytes live in the attachment storage under `storage_key`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolutionAttachment {
+    pub id: String,
+    pub solution_id: SolutionId,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    /// Spec the plot was drawn from; None for uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plot_spec: Option<crate::services::plot::PlotSpec>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Which solutions to use when exporting or listing a book's solutions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SolutionFilter {
+    /// Best available: verified first, then highest rated
+    #[default]
+    Any,
+    /// Only solutions a user marked as verified
+    Verified,
+    /// Only solutions generated by this provider
+    Provider(String),
+}
+
+impl SolutionFilter {
+    /// Parse the `provider` query parameter (`verified`, `any`, or a provider name)
+    pub fn from_param(param: Option<&str>) -> Self {
+        match param.map(str::trim) {
+            None | Some("") | Some("any") => SolutionFilter::Any,
+            Some("verified") => SolutionFilter::Verified,
+            Some(provider) => SolutionFilter::Provider(provider.to_string()),
+        }
+    }
+}
+
 /// Chapter/section of a book
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chapter {
@@ -150,12 +348,128 @@ pub struct Book {
     pub created_at: DateTime<Utc>,
 }
 
+/// AI hint for a problem, kept so study packs can include it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemHint {
+    pub problem_id: ProblemId,
+    /// 1 (minimal) to 3 (strong)
+    pub hint_level: u8,
+    pub provider: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The part of a problem's statement printed on one page. A problem that
+/// runs across pages has one part per page and a single `problems` row
+/// whose content joins them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemPage {
+    pub problem_id: ProblemId,
+    pub page_id: String,
+    pub page_number: u32,
+    pub content: String,
+    /// Starts on an earlier page
+    pub continues_from_prev: bool,
+    /// Goes on to the next page
+    pub continues_to_next: bool,
+}
+
+/// Top-level problem of a chapter with its sub-problems loaded and its best
+/// solution, as returned by `Database::get_chapter_full`
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterProblem {
+    #[serde(flatten)]
+    pub problem: Problem,
+    pub best_solution: Option<Solution>,
+}
+
+/// What saving one problem of a batch did to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemWriteOutcome {
+    Inserted,
+    /// Existing row whose statement changed (its review starts over)
+    Updated,
+    Unchanged,
+    /// Not written: another problem already holds this number, or the
+    /// problem's parent wasn't written
+    Conflict,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemWrite {
+    pub id: ProblemId,
+    pub outcome: ProblemWriteOutcome,
+    /// For conflicts, the problem holding the number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub existing_id: Option<ProblemId>,
+}
+
+impl ProblemWrite {
+    pub fn is_written(&self) -> bool {
+        self.outcome != ProblemWriteOutcome::Conflict
+    }
+}
+
+/// Rows a bulk problem delete removed, or would remove for a dry run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ProblemDeletion {
+    pub problems: u32,
+    pub sub_problems: u32,
+    pub solutions: u32,
+}
+
+impl ProblemDeletion {
+    /// What went between counts taken before (`self`) and after a delete
+    pub fn minus(self, after: Self) -> Self {
+        Self {
+            problems: self.problems.saturating_sub(after.problems),
+            sub_problems: self.sub_problems.saturating_sub(after.sub_problems),
+            solutions: self.solutions.saturating_sub(after.solutions),
+        }
+    }
+}
+
+/// Bookmarked problem with optional folder and note
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub problem_id: ProblemId,
+    /// User-defined folder (e.g., "exam prep")
+    pub folder: Option<String>,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
 /// Request to generate solution
 #[derive(Debug, Deserialize)]
 pub struct SolveRequest {
     pub provider: Option<String>, // openai, claude, mistral
     pub force_regenerate: Option<bool>,
     pub custom_prompt: Option<String>,
+    #[serde(flatten)]
+    pub options: SolveOptions,
+}
+
+/// Per-request model parameters; unset ones use the provider's defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SolveOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+impl SolveOptions {
+    pub fn is_default(&self) -> bool {
+        self.model.is_none() && self.temperature.is_none() && self.max_tokens.is_none()
+    }
+}
+
+/// Model parameters a solution was generated with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u32,
 }
 
 /// Response with solution
@@ -164,6 +478,8 @@ pub struct SolutionResponse {
     pub problem: Problem,
     pub solution: Solution,
     pub generation_time_ms: u64,
+    /// Served from the solve cache instead of calling the provider
+    pub cached: bool,
 }
 
 /// Problem with truncated info (for lists)
@@ -184,10 +500,104 @@ pub struct FormulaSearchResult {
     pub theory_blocks: Vec<TheoryBlock>,
 }
 
+/// Sub-problem letters in alphabet order; `ё` sorts after `е`, not after `я`
+/// as its code point would
+const RUSSIAN_ALPHABET: &str = "абвгдеёжзийклмнопрстуфхцчшщъыьэюя";
+
+/// Latin letters OCR returns for Cyrillic ones
+const LOOKALIKES: [(char, char); 8] =
+    [('a', 'а'), ('c', 'с'), ('e', 'е'), ('k', 'к'), ('o', 'о'), ('p', 'р'), ('x', 'х'), ('y', 'у')];
+
 impl Problem {
-    /// Generate unique problem ID
+    /// Generate unique problem ID; the number is normalized first so OCR
+    /// variants of it give the same ID
     pub fn generate_id(book_id: &str, chapter_num: u32, problem_num: &str) -> ProblemId {
-        format!("{}:{}:{}", book_id, chapter_num, problem_num)
+        format!("{}:{}:{}", book_id, chapter_num, Self::normalize_number(problem_num))
+    }
+
+    /// Canonical problem number: `№ 71.` is `71` and `5,66` is `5.66`.
+    /// Drops the number sign, surrounding punctuation and spaces, and uses a
+    /// dot as the separator of compound numbers.
+    pub fn normalize_number(raw: &str) -> String {
+        let number: String = raw
+            .trim()
+            .trim_start_matches(['№', '#', '('])
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == ',' { '.' } else { c })
+            .collect::<String>()
+            .trim_end_matches(['.', ':', ';', ')'])
+            .to_lowercase();
+        if number.is_empty() { raw.trim().to_string() } else { number }
+    }
+
+    /// Sort key of a problem number in natural order: runs of digits are
+    /// zero-padded, so `21` < `100` and `5.2` < `5.12` < `5.12а`
+    pub fn numeric_order(number: &str) -> String {
+        let mut key = String::with_capacity(number.len() + 8);
+        let mut digits = String::new();
+        for c in number.chars().flat_map(char::to_lowercase) {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                continue;
+            }
+            if !digits.is_empty() {
+                key.push_str(&format!("{:0>8}", digits));
+                digits.clear();
+            }
+            key.push(c);
+        }
+        if !digits.is_empty() {
+            key.push_str(&format!("{:0>8}", digits));
+        }
+        key
+    }
+
+    /// Canonical letters of one problem's sub-problems. OCR mixes Latin
+    /// look-alikes into Cyrillic lists (`a)` for `а)`), so a list is Cyrillic
+    /// unless it has a Latin letter without a Cyrillic twin (`b`, `d`, ...),
+    /// and look-alikes are switched to the list's alphabet. Letters are
+    /// lowercased and lose their `)` or `.`.
+    pub fn normalize_sub_letters<S: AsRef<str>>(letters: &[S]) -> Vec<String> {
+        let letters: Vec<String> = letters
+            .iter()
+            .map(|l| l.as_ref().trim().trim_end_matches([')', '.']).trim().to_lowercase())
+            .collect();
+        let latin = letters.iter().flat_map(|l| l.chars()).any(|c| {
+            c.is_ascii_lowercase() && !LOOKALIKES.iter().any(|(latin, _)| *latin == c)
+        });
+        letters
+            .into_iter()
+            .map(|letter| {
+                letter
+                    .chars()
+                    .map(|c| {
+                        LOOKALIKES
+                            .iter()
+                            .find(|(l, cyr)| if latin { *cyr == c } else { *l == c })
+                            .map_or(c, |(l, cyr)| if latin { *l } else { *cyr })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Position of a sub-problem letter in its alphabet (`а` = 1, `ё` = 7,
+    /// `я` = 33; `a` = 1, `z` = 26), or the value of a numbered sub-problem
+    pub fn sub_position(letter: &str) -> Option<u32> {
+        let letter = letter.trim().trim_end_matches([')', '.']).to_lowercase();
+        if let Ok(n) = letter.parse::<u32>() {
+            return Some(n);
+        }
+        let mut chars = letter.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        RUSSIAN_ALPHABET
+            .chars()
+            .position(|a| a == c)
+            .or_else(|| c.is_ascii_lowercase().then(|| (c as u8 - b'a') as usize))
+            .map(|i| i as u32 + 1)
     }
 
     /// Extract LaTeX formulas from content for indexing
@@ -235,6 +645,45 @@ mod tests {
     fn test_problem_id_generation() {
         let id = Problem::generate_id("algebra-7", 3, "15");
         assert_eq!(id, "algebra-7:3:15");
+        assert_eq!(Problem::generate_id("algebra-7", 3, "№ 15."), id);
+    }
+
+    #[test]
+    fn test_problem_number_normalization() {
+        for (raw, normalized) in [
+            ("71.", "71"),
+            (" № 71 ", "71"),
+            ("5,66", "5.66"),
+            ("5. 66.", "5.66"),
+            ("(12)", "12"),
+            ("12А", "12а"),
+            ("", ""),
+        ] {
+            assert_eq!(Problem::normalize_number(raw), normalized, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn test_sub_letter_normalization() {
+        assert_eq!(Problem::normalize_sub_letters(&["a)", "б)", "в"]), vec!["а", "б", "в"]);
+        assert_eq!(Problem::normalize_sub_letters(&["a", "b", "с"]), vec!["a", "b", "c"]);
+        assert_eq!(Problem::normalize_sub_letters(&["Д", "e", "ё"]), vec!["д", "е", "ё"]);
+
+        let mut letters = vec!["я", "ё", "ж", "е", "а"];
+        letters.sort_by_key(|l| Problem::sub_position(l));
+        assert_eq!(letters, vec!["а", "е", "ё", "ж", "я"]);
+        assert_eq!(Problem::sub_position("a"), Problem::sub_position("а"));
+        assert_eq!(Problem::sub_position("c"), Some(3));
+        assert_eq!(Problem::sub_position("12"), Some(12));
+        assert_eq!(Problem::sub_position("аб"), None);
+    }
+
+    #[test]
+    fn test_numeric_order() {
+        let mut numbers = vec!["100", "21", "5.12", "5.2", "5.12а", "5", "а", "б"];
+        numbers.sort_by_key(|n| Problem::numeric_order(n));
+        assert_eq!(numbers, vec!["5", "5.2", "5.12", "5.12а", "21", "100", "а", "б"]);
+        assert_eq!(Problem::numeric_order("12А"), Problem::numeric_order("12а"));
     }
 
     #[test]
@@ -258,9 +707,36 @@ mod tests {
             continues_to_page: None,
             is_cross_page: false,
             is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
         };
 
         let formulas = problem.extract_formulas();
         assert!(formulas.contains(&"x^2 + y^2 = z^2".to_string()));
     }
+
+    #[test]
+    fn test_solution_filter_param() {
+        assert_eq!(SolutionFilter::from_param(None), SolutionFilter::Any);
+        assert_eq!(SolutionFilter::from_param(Some("verified")), SolutionFilter::Verified);
+        assert_eq!(
+            SolutionFilter::from_param(Some("claude")),
+            SolutionFilter::Provider("claude".to_string())
+        );
+    }
+
+    #[test]
+    fn test_review_transitions() {
+        use ReviewStatus::*;
+        assert!(Unreviewed.can_transition_to(Approved));
+        assert!(Unreviewed.can_transition_to(NeedsFix));
+        assert!(NeedsFix.can_transition_to(Approved));
+        assert!(Approved.can_transition_to(NeedsFix));
+        assert!(!Approved.can_transition_to(Approved));
+        assert!(!NeedsFix.can_transition_to(Unreviewed));
+        assert_eq!(ReviewStatus::from_name("needs_fix"), Some(NeedsFix));
+    }
 }
@@ -1,14 +1,34 @@
 use actix_files::Files;
-use actix_web::middleware::Logger;
-use actix_web::{web, App, HttpServer};
+use actix_web::body::{self, BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::{from_fn, Logger, Next};
+use actix_web::{web, App, Error, HttpMessage, HttpResponse, HttpServer};
+use futures::StreamExt;
 use log::info;
 use std::sync::Arc;
 use std::time::Instant;
-use tera::Tera;
 
 use crate::config::Config;
 use crate::handlers;
-use crate::services::{FileService, database::Database, background::JobManager};
+use crate::services::idempotency::{
+    request_fingerprint, should_store, valid_key, IdempotencyClaim, StoredResponse, IDEMPOTENCY_KEY_HEADER,
+    IDEMPOTENT_REPLAY_HEADER, MAX_KEYED_REQUEST_BYTES,
+};
+use crate::services::graph_render::GraphRenderService;
+use crate::services::templates::Templates;
+use crate::services::{FileService, database::Database, background::JobManager, calibration, digest, job_artifacts, ocr_audit::OcrAuditor};
+
+/// SQLite URL for `data/textbooks.db`, creating the file if it doesn't exist yet
+pub fn database_url() -> String {
+    std::fs::create_dir_all("data").expect("Failed to create data directory");
+    // Use file-based database for persistence, create file if not exists
+    let db_path = std::env::current_dir().unwrap().join("data/textbooks.db");
+    if !db_path.exists() {
+        std::fs::File::create(&db_path).expect("Failed to create database file");
+    }
+    format!("sqlite:{}", db_path.to_str().unwrap())
+}
 
 pub async fn run() -> std::io::Result<()> {
     let config = Config::new();
@@ -19,67 +39,126 @@ pub async fn run() -> std::io::Result<()> {
     info!("Server running at http://{}:{}/", host, port);
 
     let startup_time = Instant::now();
-    let mut tera = Tera::new("templates/**/*").expect("Failed to initialize Tera templates");
-    
-    // Register markdown filter
-    tera.register_filter("markdown", |value: &tera::Value, _args: &std::collections::HashMap<String, tera::Value>| {
-        let text = value.as_str().unwrap_or("");
-        // Simple markdown to HTML conversion
-        let html = text
-            .replace("**", "<strong>")
-            .replace("*", "<em>")
-            .replace("`", "<code>")
-            .replace("\n\n", "</p><p>")
-            .replace("\n", "<br>");
-        Ok(tera::Value::String(format!("<p>{}</p>", html)))
-    });
-    
-    // Register truncate filter
-    tera.register_filter("truncate", |value: &tera::Value, args: &std::collections::HashMap<String, tera::Value>| {
-        let text = value.as_str().unwrap_or("");
-        let length = args.get("length").and_then(|v| v.as_i64()).unwrap_or(100) as usize;
-        if text.len() > length {
-            Ok(tera::Value::String(format!("{}...", &text[..length])))
-        } else {
-            Ok(tera::Value::String(text.to_string()))
-        }
-    });
+    let templates = web::Data::new(Templates::from_config(&config).expect("Failed to initialize Tera templates"));
+    let graph_renderer = web::Data::new(GraphRenderService::new());
+    if config.template_reload {
+        info!("Template reload is on: edited templates apply on the next page view");
+    }
 
-    let file_service = FileService::new(
-        config.resources_dir.clone(),
-        config.preview_dir.clone(),
-        config.ocr_cache_dir.clone(),
-    );
+    config.configure_commands();
+
+    let file_service = FileService::from_config(&config);
 
     // Initialize database
-    std::fs::create_dir_all("data").expect("Failed to create data directory");
-    // Use file-based database for persistence, create file if not exists
-    let db_path = std::env::current_dir().unwrap().join("data/textbooks.db");
-    if !db_path.exists() {
-        std::fs::File::create(&db_path).expect("Failed to create database file");
-    }
-    let db_url = format!("sqlite:{}", db_path.to_str().unwrap());
+    let db_url = database_url();
     let database = Database::new(&db_url)
         .await
         .expect("Failed to initialize database");
 
     // Initialize job manager for background tasks
-    let job_manager = Arc::new(JobManager::new());
+    let job_manager = Arc::new(JobManager::with_history(
+        database.clone(),
+        config.job_history_retention_days,
+    ));
     
     // Spawn cleanup task for old jobs
     let cleanup_jobs = job_manager.clone();
+    let cleanup_db = database.clone();
+    let jobs_dir = config.jobs_dir.clone();
+    let retention_days = config.job_history_retention_days;
+    let idempotency_ttl_hours = config.idempotency_ttl_hours;
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600)); // Every hour
         loop {
             interval.tick().await;
             cleanup_jobs.cleanup_old_jobs().await;
+            let removed = job_artifacts::prune_artifacts(&jobs_dir, retention_days);
+            if removed > 0 {
+                info!("Removed artifacts of {} old jobs", removed);
+            }
+            if let Err(e) = cleanup_db.prune_idempotency_keys(idempotency_ttl_hours).await {
+                log::warn!("Failed to prune idempotency keys: {}", e);
+            }
+        }
+    });
+
+    if config.offline {
+        info!("Offline mode: cloud OCR/AI providers are disabled");
+    }
+    info!("Rendering previews with {:?} at {} dpi", config.render_backend, config.render_dpi);
+
+    // Spawn periodic OCR quality audit if a second provider is configured
+    if let Some(provider) = config.ocr_audit_provider.clone().filter(|_| !config.offline) {
+        let auditor = OcrAuditor::new(
+            job_manager.clone(),
+            Arc::new(database.clone()),
+            Arc::new(config.clone()),
+        );
+        let sample_size = config.ocr_audit_sample_size;
+        let period = tokio::time::Duration::from_secs(config.ocr_audit_interval_hours.max(1) * 3600);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await; // Skip the immediate first tick
+            loop {
+                interval.tick().await;
+                if let Err(e) = auditor.start_audit(None, sample_size, &provider).await {
+                    log::error!("Failed to start OCR audit: {}", e);
+                }
+            }
+        });
+    }
+
+    // Difficulty calibration: replayed hourly from all graded answers
+    let calibration_db = database.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match calibration::recalibrate(&calibration_db, chrono::Utc::now()).await {
+                Ok(calibrations) if !calibrations.is_empty() => {
+                    info!("Calibrated difficulty of {} problems", calibrations.len())
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Failed to calibrate difficulty: {}", e),
+            }
+        }
+    });
+
+    // Weekly digest: checked hourly, composed a week after the last one ended
+    let digest_db = database.clone();
+    let digest_config = config.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now();
+            match digest_db.get_latest_digest_report().await {
+                Ok(latest) if !digest::digest_due(latest.as_ref(), now) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("Failed to read the latest digest: {}", e);
+                    continue;
+                }
+            }
+            match digest::generate_digest(&digest_db, now).await {
+                Ok(report) => {
+                    info!("Composed weekly digest {}", report.id);
+                    if let Err(e) = digest::deliver_digest(&digest_db, &digest_config, &report).await {
+                        log::warn!("Failed to deliver digest {}: {}", report.id, e);
+                    }
+                }
+                Err(e) => log::error!("Failed to compose weekly digest: {}", e),
+            }
         }
     });
 
     HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(idempotent_requests))
+            .wrap(from_fn(redirect_book_files))
             .wrap(Logger::default())
-            .app_data(web::Data::new(tera.clone()))
+            .app_data(templates.clone())
+            .app_data(graph_renderer.clone())
             .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(file_service.clone()))
             .app_data(web::Data::new(database.clone()))
@@ -94,9 +173,181 @@ pub async fn run() -> std::io::Result<()> {
     Ok(())
 }
 
+/// Routes whose first parameter names a book. Every one of them takes the
+/// book id; the file-name URLs they started with still work through
+/// [`redirect_book_files`].
+const BOOK_ROUTES: &[&str] = &[
+    "/preview/",
+    "/preview_image/",
+    "/metadata/",
+    "/ocr/",
+    "/ocr_cache/",
+    "/api/ocr_page/",
+    "/api/page_ocr/",
+    "/generate_all_previews/",
+    "/generation_status/",
+    "/api/books/",
+    "/textbook/book/",
+    "/books/",
+];
+
+/// Redirect URLs naming a book by its file (`/preview/algebra-7.pdf/3`) to
+/// the same route with the book id (`/preview/algebra-7/3`). 308 keeps the
+/// method and body, so POSTs follow it too.
+async fn redirect_book_files(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let location = req
+        .app_data::<web::Data<FileService>>()
+        .and_then(|files| book_id_url(files, req.path(), req.query_string()));
+    match location {
+        Some(location) => {
+            let response = HttpResponse::PermanentRedirect()
+                .insert_header((header::LOCATION, location))
+                .finish();
+            Ok(req.into_response(response).map_into_right_body())
+        }
+        None => next.call(req).await.map(ServiceResponse::map_into_left_body),
+    }
+}
+
+/// Answer a retried mutating request carrying an `Idempotency-Key` with the
+/// response to the first one instead of running it again. Runs inside
+/// [`redirect_book_files`], so a redirected request is keyed by the URL it
+/// is handled at.
+async fn idempotent_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let mutating = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) if mutating => value.to_str().ok().filter(|key| valid_key(key)).map(str::to_string),
+        _ => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+    let Some(key) = key else {
+        let response = HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Idempotency-Key must be 1-255 printable ASCII characters"
+        }));
+        return Ok(req.into_response(response));
+    };
+    let (Some(db), Some(config)) = (req.app_data::<web::Data<Database>>().cloned(), req.app_data::<web::Data<Config>>()) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    let ttl_hours = config.idempotency_ttl_hours;
+
+    // The body is part of the request's identity; read it and put it back
+    let mut payload = req.take_payload();
+    let mut request_body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if request_body.len() + chunk.len() > MAX_KEYED_REQUEST_BYTES {
+            let response = HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": "Request body too large for an Idempotency-Key"
+            }));
+            return Ok(req.into_response(response));
+        }
+        request_body.extend_from_slice(&chunk);
+    }
+    let request_body = request_body.freeze();
+    let fingerprint = request_fingerprint(req.method().as_str(), req.path(), req.query_string(), &request_body);
+    req.set_payload(Payload::from(request_body));
+
+    let claim = match db.claim_idempotency_key(&key, &fingerprint, ttl_hours).await {
+        Ok(claim) => claim,
+        Err(e) => {
+            log::error!("Failed to claim idempotency key, handling the request without it: {}", e);
+            return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+        }
+    };
+    let response = match claim {
+        IdempotencyClaim::New => None,
+        IdempotencyClaim::Completed(stored) => {
+            let mut response = HttpResponse::build(
+                actix_web::http::StatusCode::from_u16(stored.status).unwrap_or(actix_web::http::StatusCode::OK),
+            );
+            response.insert_header((IDEMPOTENT_REPLAY_HEADER, "true"));
+            if let Some(content_type) = stored.content_type {
+                response.insert_header((header::CONTENT_TYPE, content_type));
+            }
+            Some(response.body(stored.body))
+        }
+        IdempotencyClaim::InProgress => Some(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "A request with this Idempotency-Key is still being processed"
+        }))),
+        IdempotencyClaim::Mismatch => Some(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "Idempotency-Key was already used for a different request"
+        }))),
+    };
+    if let Some(response) = response {
+        return Ok(req.into_response(response));
+    }
+
+    let response = match next.call(req).await {
+        Ok(response) => response,
+        Err(e) => {
+            if let Err(e) = db.release_idempotency_key(&key).await {
+                log::warn!("Failed to release idempotency key: {}", e);
+            }
+            return Err(e);
+        }
+    };
+    let (req, response) = response.into_parts();
+    let (response, response_body) = response.into_parts();
+    let response_body = match body::to_bytes(response_body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            if let Err(e) = db.release_idempotency_key(&key).await {
+                log::warn!("Failed to release idempotency key: {}", e);
+            }
+            return Err(actix_web::error::ErrorInternalServerError(e.into()));
+        }
+    };
+
+    let status = response.status().as_u16();
+    let stored = if should_store(status, response_body.len()) {
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let stored = StoredResponse { status, content_type, body: response_body.to_vec() };
+        db.complete_idempotency_key(&key, &stored).await
+    } else {
+        db.release_idempotency_key(&key).await
+    };
+    if let Err(e) = stored {
+        log::warn!("Failed to store idempotent response: {}", e);
+    }
+
+    Ok(ServiceResponse::new(req, response.set_body(response_body).map_into_boxed_body()))
+}
+
+/// `path` with its book segment replaced by the book id, if it names a
+/// known book in any other way
+fn book_id_url(files: &FileService, path: &str, query: &str) -> Option<String> {
+    let (prefix, rest) = BOOK_ROUTES
+        .iter()
+        .find_map(|prefix| Some((*prefix, path.strip_prefix(prefix)?)))?;
+    let (segment, tail) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    let reference = urlencoding::decode(segment).ok()?;
+    let book = files.resolve_book(&reference)?;
+    if book.id == reference {
+        return None;
+    }
+
+    let mut url = format!("{}{}{}", prefix, urlencoding::encode(&book.id), tail);
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(query);
+    }
+    Some(url)
+}
+
 fn configure_routes(cfg: &mut web::ServiceConfig) {
     // Static and main pages
     cfg.route("/", web::get().to(handlers::index))
+        .route("/theme.css", web::get().to(handlers::theme_css))
         .route("/view", web::get().to(handlers::view_file))
         .service(Files::new("/static", "static").show_files_listing());
 
@@ -135,7 +386,10 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
         );
 
     // Textbook HTML views
-    cfg.route(
+    cfg.route("/books/{book_id}", web::get().to(handlers::view_book))
+        .route("/chapters/{chapter_id}", web::get().to(handlers::view_chapter))
+        .route("/problems/{problem_id}", web::get().to(handlers::view_problem))
+        .route(
             "/textbook/book/{book_id}/pages",
             web::get().to(handlers::view_book_pages),
         )
@@ -152,15 +406,50 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
             web::get().to(handlers::view_problem),
         );
 
+    // Problems embedded in other sites
+    cfg.route("/embed/problem/{problem_id}", web::get().to(handlers::embed_problem))
+        .route("/oembed", web::get().to(handlers::oembed));
+
+    // Feeds
+    cfg.route("/feeds/books/{book_id}.atom", web::get().to(handlers::book_feed));
+
     // Problem API routes
     cfg.route(
             "/api/chapters/{chapter_id}/problems",
             web::get().to(handlers::get_chapter_problems),
         )
+        .route(
+            "/api/chapters/{chapter_id}/problems",
+            web::delete().to(handlers::delete_chapter_problems),
+        )
+        .route(
+            "/api/chapters/{chapter_id}/full",
+            web::get().to(handlers::get_chapter_full),
+        )
         .route(
             "/api/chapters/{chapter_id}/theory",
             web::get().to(handlers::get_chapter_theory),
         )
+        .route(
+            "/api/chapters/{chapter_id}/study_pack",
+            web::get().to(handlers::get_study_pack),
+        )
+        .route(
+            "/api/theory",
+            web::post().to(handlers::create_theory_block),
+        )
+        .route(
+            "/api/theory/{theory_id}",
+            web::get().to(handlers::get_theory_block),
+        )
+        .route(
+            "/api/theory/{theory_id}",
+            web::put().to(handlers::update_theory_block),
+        )
+        .route(
+            "/api/theory/{theory_id}",
+            web::delete().to(handlers::delete_theory_block),
+        )
         .route(
             "/api/problems/{problem_id}",
             web::get().to(handlers::get_problem),
@@ -169,6 +458,18 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
             "/api/problems/{problem_id}",
             web::put().to(handlers::update_problem),
         )
+        .route(
+            "/api/problems/{problem_id}/pages",
+            web::get().to(handlers::get_problem_pages),
+        )
+        .route(
+            "/api/problems/{problem_id}/formula-image",
+            web::post().to(handlers::apply_formula_fallback),
+        )
+        .route(
+            "/formula_image/{name}",
+            web::get().to(handlers::get_formula_image),
+        )
         .route(
             "/api/problems/{problem_id}/solve",
             web::post().to(handlers::solve_problem),
@@ -181,14 +482,71 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
             "/api/problems/{problem_id}/solutions/{solution_id}/rate",
             web::post().to(handlers::rate_solution),
         )
+        .service(
+            web::resource("/api/solutions/{solution_id}/attachments")
+                .app_data(web::PayloadConfig::new(crate::services::attachments::MAX_ATTACHMENT_SIZE))
+                .route(web::get().to(handlers::list_solution_attachments))
+                .route(web::post().to(handlers::upload_solution_attachment)),
+        )
+        .route(
+            "/api/solutions/{solution_id}/attachments/plot",
+            web::post().to(handlers::create_solution_plot),
+        )
+        .route(
+            "/api/attachments/{attachment_id}",
+            web::get().to(handlers::get_attachment),
+        )
+        .route(
+            "/api/render/graph",
+            web::get().to(handlers::render_graph),
+        )
+        .service(
+            web::resource("/api/problems/{problem_id}/figure")
+                .route(web::get().to(handlers::get_problem_figure))
+                .route(web::post().to(handlers::reconstruct_problem_figure))
+                .route(web::delete().to(handlers::delete_problem_figure)),
+        )
+        .route(
+            "/api/problems/{problem_id}/figure.svg",
+            web::get().to(handlers::get_problem_figure_svg),
+        )
+        .route(
+            "/api/solutions/cleanup",
+            web::post().to(handlers::cleanup_solutions),
+        )
         .route(
             "/api/problems/{problem_id}/hint",
             web::post().to(handlers::hint_problem),
         )
+        .route(
+            "/api/problems/{problem_id}/answer_check",
+            web::get().to(handlers::check_problem_answer),
+        )
+        .route(
+            "/api/problems/{problem_id}/calc_check",
+            web::get().to(handlers::check_problem_calculation),
+        )
         .route(
             "/api/import",
             web::post().to(handlers::import_textbook),
         )
+        .route(
+            "/api/import/markdown",
+            web::post().to(handlers::import_markdown_problems),
+        )
+        .service(
+            web::resource("/api/import/anki")
+                .app_data(web::PayloadConfig::new(64 * 1024 * 1024))
+                .route(web::post().to(handlers::import_anki_deck)),
+        )
+        .route(
+            "/api/bookmarks/export",
+            web::get().to(handlers::export_bookmarks),
+        )
+        .route(
+            "/api/bookmarks/import",
+            web::post().to(handlers::import_bookmarks),
+        )
         .route(
             "/api/bookmarks/{problem_id}",
             web::post().to(handlers::add_bookmark),
@@ -221,12 +579,77 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.route("/api/batch/ocr", web::post().to(handlers::start_batch_ocr))
         .route("/api/batch/solve", web::post().to(handlers::start_batch_solve))
         .route("/api/jobs", web::get().to(handlers::list_jobs))
+        .route("/api/jobs/history", web::get().to(handlers::list_job_history))
         .route("/api/jobs/{job_id}", web::get().to(handlers::get_job_status))
-        .route("/api/jobs/{job_id}/cancel", web::post().to(handlers::cancel_job));
+        .route("/api/jobs/{job_id}/cancel", web::post().to(handlers::cancel_job))
+        .route("/api/jobs/{job_id}/retry_failures", web::post().to(handlers::retry_job_failures))
+        .route("/api/jobs/{job_id}/artifacts", web::get().to(handlers::list_job_artifacts))
+        .route("/api/jobs/{job_id}/artifacts/{name}", web::get().to(handlers::download_job_artifact));
+
+    // AI providers
+    cfg.route("/api/providers", web::get().to(handlers::list_providers))
+        .route("/api/providers/health", web::get().to(handlers::providers_health));
+    cfg.route("/api/metrics", web::get().to(handlers::get_metrics));
+    cfg.route("/api/usage", web::get().to(handlers::get_usage));
+    cfg.route("/api/reports/latest", web::get().to(handlers::get_latest_report))
+        .route("/api/reports/generate", web::post().to(handlers::generate_report));
+
+    // OCR quality audit
+    cfg.route("/api/audit/ocr", web::post().to(handlers::start_ocr_audit))
+        .route("/api/audit/ocr", web::get().to(handlers::list_ocr_audits));
     
     // Export routes
     cfg.route("/api/export/book", web::post().to(handlers::export_book))
-        .route("/api/export/chapter/{chapter_id}", web::get().to(handlers::export_chapter));
+        .route("/api/export/chapter/{chapter_id}", web::get().to(handlers::export_chapter))
+        .route("/api/exports", web::get().to(handlers::list_exports))
+        .route("/api/exports/diff", web::get().to(handlers::diff_exports))
+        .route("/api/exports/{id}", web::get().to(handlers::get_export))
+        .route("/api/exports/{id}/download", web::get().to(handlers::download_export));
+    
+    // Book-level reports
+    cfg.route("/api/books/compare", web::get().to(handlers::compare_books_handler));
+
+    // OCR post-processing rules
+    cfg.route("/api/books/{book_id}/ocr-rules", web::get().to(handlers::list_ocr_rules))
+        .route("/api/books/{book_id}/ocr-rules", web::post().to(handlers::create_ocr_rule))
+        .route("/api/books/{book_id}/ocr-rules/preview", web::post().to(handlers::preview_ocr_rules))
+        .route("/api/books/{book_id}/ocr-rules/{rule_id}", web::put().to(handlers::update_ocr_rule))
+        .route("/api/books/{book_id}/ocr-rules/{rule_id}", web::delete().to(handlers::delete_ocr_rule));
+    cfg.route("/api/books/{book_id}/low-confidence", web::get().to(handlers::list_low_confidence_problems));
+    cfg.route("/api/books/{book_id}/problems", web::delete().to(handlers::delete_book_problems));
+    cfg.route("/api/books/{book_id}/solutions", web::get().to(handlers::list_book_solutions));
+    cfg.route("/api/books/{book_id}/analyze", web::post().to(handlers::analyze_book));
+    cfg.route("/api/books/{book_id}/probe", web::post().to(handlers::probe_book));
+    cfg.route("/api/books/{book_id}/answers/import", web::post().to(handlers::import_answer_key));
+    cfg.route("/api/books/{book_id}/calc_check", web::get().to(handlers::check_book_calculations));
+    cfg.route("/api/books/{book_id}/calibration", web::get().to(handlers::get_book_calibration));
+    cfg.route("/api/calibration/run", web::post().to(handlers::run_calibration));
+    cfg.route("/api/books/{book_id}/glossary", web::get().to(handlers::get_book_glossary));
+    cfg.route("/api/books/{book_id}/problem_density", web::get().to(handlers::get_problem_density));
+    cfg.route("/api/books/{book_id}/pending_pages", web::get().to(handlers::list_pending_pages))
+        .route("/api/books/{book_id}/pending_pages/ocr", web::post().to(handlers::ocr_pending_pages));
+    cfg.route("/api/books/{book_id}/dashboard", web::get().to(handlers::get_book_dashboard));
+    cfg.route("/api/books/{book_id}/latex-macros", web::get().to(handlers::get_book_latex_macros))
+        .route("/api/books/{book_id}/latex-macros", web::put().to(handlers::update_book_latex_macros))
+        .route("/api/books/{book_id}/heading-patterns", web::get().to(handlers::get_book_heading_patterns))
+        .route("/api/books/{book_id}/heading-patterns", web::put().to(handlers::update_book_heading_patterns))
+        .route("/api/books/{book_id}/settings", web::get().to(handlers::get_book_settings))
+        .route("/api/books/{book_id}/settings", web::put().to(handlers::update_book_settings));
+
+    cfg.route("/api/preferences", web::get().to(handlers::get_preferences))
+        .route("/api/preferences", web::put().to(handlers::update_preferences));
+
+    // Review queue
+    cfg.route("/api/books/{book_id}/review/next", web::get().to(handlers::get_next_for_review))
+        .route("/api/problems/{problem_id}/review/approve", web::post().to(handlers::approve_problem))
+        .route("/api/problems/{problem_id}/review/needs-fix", web::post().to(handlers::request_problem_fix));
+    cfg.route("/api/review/next_batch", web::get().to(handlers::get_review_batch))
+        .route("/api/review/batch", web::post().to(handlers::submit_review_batch));
+
+    // Worksheets
+    cfg.route("/api/worksheets", web::post().to(handlers::create_worksheet))
+        .route("/api/worksheets", web::get().to(handlers::list_worksheets))
+        .route("/api/worksheets/{worksheet_id}", web::get().to(handlers::get_worksheet));
     
     // Validation routes
     cfg.route("/api/validate/chapter", web::post().to(handlers::validate_chapter));
@@ -246,11 +669,67 @@ fn configure_routes(cfg: &mut web::ServiceConfig) {
     
     // Auto-tagging
     cfg.route("/api/smart/auto_tag", web::post().to(handlers::auto_tag_problems));
+
+    // Curriculum standards
+    cfg.route("/api/curricula", web::get().to(handlers::list_curricula))
+        .route("/api/curricula", web::put().to(handlers::save_curriculum_standard))
+        .route("/api/curricula/{framework}/{code}", web::delete().to(handlers::delete_curriculum_standard))
+        .route("/api/problems/{problem_id}/standards", web::get().to(handlers::get_problem_standards))
+        .route("/api/problems/{problem_id}/standards/auto", web::post().to(handlers::auto_tag_problem_standards))
+        .route("/api/chapters/{chapter_id}/standards/auto", web::post().to(handlers::auto_tag_chapter_standards))
+        .route("/api/books/{book_id}/standards/coverage", web::get().to(handlers::get_book_standards_coverage));
+
+    // Assignments: teacher endpoints by id, student ones by share token
+    cfg.route("/api/assignments", web::post().to(handlers::create_assignment))
+        .route("/api/assignments", web::get().to(handlers::list_assignments))
+        .route("/api/assignments/{assignment_id}", web::get().to(handlers::get_assignment))
+        .route("/api/assignments/{assignment_id}", web::delete().to(handlers::delete_assignment))
+        .route("/api/assignments/{assignment_id}/results", web::get().to(handlers::get_assignment_results))
+        .route("/api/submissions/{submission_id}/photo", web::get().to(handlers::get_submission_photo))
+        .route("/a/{share_token}", web::get().to(handlers::view_shared_assignment))
+        .route("/api/shared/assignments/{share_token}", web::get().to(handlers::get_shared_assignment))
+        .route(
+            "/api/shared/assignments/{share_token}/submissions",
+            web::post().to(handlers::submit_assignment_answers),
+        );
+    cfg.service(
+        web::resource("/api/shared/assignments/{share_token}/photo")
+            .app_data(web::PayloadConfig::new(crate::services::attachments::MAX_ATTACHMENT_SIZE))
+            .route(web::post().to(handlers::submit_assignment_photo)),
+    );
+
+    // Student groups
+    cfg.route("/api/groups", web::post().to(handlers::create_group))
+        .route("/api/groups", web::get().to(handlers::list_groups))
+        .route("/api/groups/{group_id}", web::get().to(handlers::get_group))
+        .route("/api/groups/{group_id}", web::delete().to(handlers::delete_group))
+        .route("/api/groups/{group_id}/members", web::post().to(handlers::add_group_member))
+        .route("/api/groups/{group_id}/members/{student_name}", web::delete().to(handlers::remove_group_member))
+        .route("/api/groups/{group_id}/assignments/{assignment_id}", web::put().to(handlers::add_group_assignment))
+        .route("/api/groups/{group_id}/assignments/{assignment_id}", web::delete().to(handlers::remove_group_assignment))
+        .route("/api/groups/{group_id}/analytics", web::get().to(handlers::get_group_analytics));
+
+    // Content issues
+    cfg.route("/api/issues", web::post().to(handlers::create_issue))
+        .route("/api/issues", web::get().to(handlers::list_issues))
+        .route("/api/issues/{issue_id}", web::get().to(handlers::get_issue))
+        .route("/api/issues/{issue_id}", web::patch().to(handlers::update_issue))
+        .route("/api/issues/{issue_id}", web::delete().to(handlers::delete_issue))
+        .route("/api/issues/{issue_id}/comments", web::post().to(handlers::add_issue_comment));
+
+    // Share links: created and revoked by id, opened by token without signing in
+    cfg.route("/api/share", web::post().to(handlers::create_share_link))
+        .route("/api/share", web::get().to(handlers::list_share_links))
+        .route("/api/share/{link_id}", web::delete().to(handlers::delete_share_link))
+        .route("/s/{token}", web::get().to(handlers::view_share_link));
     
     // Similarity & Recommendations
     cfg.route("/api/smart/similar", web::post().to(handlers::find_similar_problems))
         .route("/api/smart/recommend", web::post().to(handlers::recommend_problems))
         .route("/api/smart/duplicates", web::post().to(handlers::find_duplicates));
+
+    // Explain a selection in the reader
+    cfg.route("/api/explain", web::post().to(handlers::explain_selection));
         
     // Health check
     cfg.route("/healthz", web::get().to(|| async { "OK" }));
@@ -1,12 +1,162 @@
+use lazy_regex::regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use crate::models::Problem;
+use crate::services::book_settings::ParserProfile;
 use crate::services::parser::TextbookParser;
 use crate::services::cache::AIParseCache;
+use crate::services::http_client::HttpClientFactory;
+use crate::services::provider_registry::{self, ProviderKind};
 use crate::services::retry::{retry_with_backoff, RetryConfig};
 
+/// Version of the page parsers (prompt, regex rules, book-specific parsers).
+/// Bump it when their output changes so batch runs parse stored pages again.
+pub const PARSER_VERSION: u32 = 1;
+
+/// Provider whose circuit breaker parsing requests go through
+const PARSE_PROVIDER: &str = "mistral";
+/// Chat endpoint both page parsers call
+const MISTRAL_CHAT_URL: &str = "https://api.mistral.ai/v1/chat/completions";
+const PARSE_MODEL: &str = "mistral-large-latest";
+const PARSE_MAX_TOKENS: u32 = 8000;
+/// Request timeout until a parser is given the configured one
+pub(crate) const DEFAULT_PARSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Prompt of [`HybridParser`]; the page's OCR text goes between the two parts
+const PROBLEMS_PROMPT_HEAD: &str = r#"Ты - эксперт по анализу математических учебников с 99% точностью.
+
+ЗАДАЧА: Разбери OCR текст и выдели ВСЕ задачи с подзадачами.
+
+КРИТИЧЕСКИ ВАЖНЫЕ ПРАВИЛА:
+1. Номера задач: 223, 224, 225 (целые числа, могут быть точки для подномеров: 1.1, 1.2)
+2. Подзадачи ВСЕГДА начинаются с буквы и скобки: а), б), в), г), д), е), ж), з), и), к), л), м), н), о), п), р), с), т)
+3. Подзадача = буква + ) + пробел/перенос + текст
+4. Если текст содержит "а)" или "б)" - это подзадачи
+5. Задача заканчивается перед следующей задачей или концом текста
+6. Игнорируй: теоремы, определения, примеры, упражнения без номеров
+7. Верни ТОЛЬКО JSON
+
+ОСОБЫЕ СЛУЧАИ:
+- "289. Текст... а)... б)... в)..." - это задача 289 с подзадачами
+- "Докажите, что..." без номера - НЕ задача
+- "Пример 1" - НЕ задача (это пример)
+
+ФОРМАТ ОТВЕТА (строго JSON):
+{
+  "problems": [
+    {
+      "number": "289",
+      "content": "Полный текст задачи со всеми подзадачами (а), б), в)...)",
+      "sub_problems": [
+        {"letter": "а", "content": "Текст подзадачи без 'а)'"},
+        {"letter": "б", "content": "Текст подзадачи без 'б)'"},
+        {"letter": "в", "content": "Текст подзадачи без 'в)'"}
+      ],
+      "continues_from_prev": false,
+      "continues_to_next": false
+    }
+  ]
+}
+
+Если задача начинается на этой странице (есть номер в начале) - continues_from_prev = false
+Если задача очевидно продолжается с предыдущей страницы (начинается с текста без номера, который логически продолжает предыдущую) - continues_from_prev = true
+
+OCR текст:
+"#;
+const PROBLEMS_PROMPT_TAIL: &str = r#"
+
+Верни ТОЛЬКО JSON, без markdown (без ```)."#;
+
+/// Mistral's JSON answer to a parsing prompt, retried with backoff and
+/// behind the same circuit breaker as Mistral solves. The OCR text only ever
+/// travels as message content, so nothing in it can change what runs.
+pub(crate) async fn mistral_parse_json<T: DeserializeOwned>(
+    api_key: &str,
+    prompt: &str,
+    temperature: f32,
+    timeout: Duration,
+) -> anyhow::Result<T> {
+    if !provider_registry::circuit_allows(ProviderKind::Solve, PARSE_PROVIDER) {
+        return Err(anyhow::anyhow!("Circuit open for provider '{}'", PARSE_PROVIDER));
+    }
+    let result = retry_with_backoff(&RetryConfig::default(), "AI parse", || {
+        mistral_parse_once(api_key, prompt, temperature, timeout)
+    })
+    .await;
+    provider_registry::record_outcome(ProviderKind::Solve, PARSE_PROVIDER, result.is_ok());
+    result
+}
+
+async fn mistral_parse_once<T: DeserializeOwned>(
+    api_key: &str,
+    prompt: &str,
+    temperature: f32,
+    timeout: Duration,
+) -> anyhow::Result<T> {
+    let request_body = serde_json::json!({
+        "model": PARSE_MODEL,
+        "messages": [{ "role": "user", "content": prompt }],
+        "temperature": temperature,
+        "max_tokens": PARSE_MAX_TOKENS,
+    });
+
+    let response = HttpClientFactory::global()
+        .client(timeout)
+        .post(MISTRAL_CHAT_URL)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&request_body)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().await?;
+        return Err(anyhow::anyhow!("Mistral API error: {}", error_text));
+    }
+
+    let result: Value = response.json().await?;
+    let content = result["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?;
+    let json = strip_code_fence(content);
+    serde_json::from_str(json).map_err(|e| anyhow::anyhow!("Failed to parse AI response: {}. Output: {}", e, json))
+}
+
+/// Answer without the ```json fence models wrap it in despite being asked not to
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let text = text.strip_prefix("```json").or_else(|| text.strip_prefix("```")).unwrap_or(text);
+    text.strip_suffix("```").unwrap_or(text).trim()
+}
+
+/// Undo a common OCR artifact: a word broken over lines with its last
+/// letter repeated at the start of the next one (`задач\nча` -> `задача`)
+pub(crate) fn join_split_letters(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut joined = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        joined.push(c);
+        if matches!(c, 'а'..='я' | 'a'..='z') && chars.get(i + 1) == Some(&'\n') {
+            let next = (i + 2..chars.len()).find(|&j| !chars[j].is_whitespace());
+            if let Some(j) = next.filter(|&j| chars[j] == c) {
+                i = j + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    joined
+}
+
 /// Hybrid parser: AI (Mistral) + Regex fallback
 pub struct HybridParser {
     api_key: Option<String>,
+    profile: ParserProfile,
+    timeout: Duration,
     regex_parser: TextbookParser,
     cache: AIParseCache,
 }
@@ -31,6 +181,9 @@ pub struct ParsedProblem {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AIParseResult {
     pub problems: Vec<ParsedProblem>,
+    /// Regex fallback after the AI parser failed; worth parsing again later
+    #[serde(skip)]
+    pub degraded: bool,
 }
 
 /// Cross-page analysis result
@@ -48,14 +201,65 @@ impl HybridParser {
     pub fn new(api_key: Option<String>) -> Self {
         Self {
             api_key,
+            profile: ParserProfile::default(),
+            timeout: DEFAULT_PARSE_TIMEOUT,
             regex_parser: TextbookParser::new(),
             cache: AIParseCache::new(),
         }
     }
 
-    /// Main parse method - tries AI first, falls back to regex
+    /// Timeout of each parsing request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Parser choice from the book's settings
+    pub fn profile(mut self, profile: ParserProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Whether pages go to the LLM parser
+    pub fn uses_ai(&self) -> bool {
+        self.api_key.is_some() && self.profile != ParserProfile::Regex
+    }
+
+    /// Hash of everything that decides what [`Self::parse_text`] returns for
+    /// a page. A stored result with the same fingerprint can be reused
+    /// instead of parsing the page again.
+    pub fn fingerprint(&self, book_id: &str, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        let mode = match self.profile {
+            _ if !self.uses_ai() => "regex",
+            ParserProfile::Ai => "ai-only",
+            _ => "ai",
+        };
+        for part in [PARSER_VERSION.to_string().as_str(), mode, book_id, text] {
+            hasher.update(part.as_bytes());
+            hasher.update(b"\n");
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Main parse method - tries AI first, falls back to regex. Problem
+    /// numbers and sub-problem letters come back normalized, see
+    /// [`Problem::normalize_number`] and [`Problem::normalize_sub_letters`].
     pub async fn parse_text(&self, book_id: &str, text: &str, page_num: Option<u32>) -> anyhow::Result<AIParseResult> {
-        let cache_key = format!("{}\n{}", book_id, text);
+        let mut result = self.parse_text_raw(book_id, text, page_num).await?;
+        for problem in &mut result.problems {
+            problem.number = Problem::normalize_number(&problem.number);
+            let letters: Vec<&str> = problem.sub_problems.iter().map(|s| s.letter.as_str()).collect();
+            let letters = Problem::normalize_sub_letters(&letters);
+            for (sub, letter) in problem.sub_problems.iter_mut().zip(letters) {
+                sub.letter = letter;
+            }
+        }
+        Ok(result)
+    }
+
+    async fn parse_text_raw(&self, book_id: &str, text: &str, page_num: Option<u32>) -> anyhow::Result<AIParseResult> {
+        let cache_key = format!("{}\n{:?}\n{}", book_id, self.profile, text);
 
         // Check cache first
         if let Some(cached) = self.cache.get(&cache_key).await {
@@ -64,7 +268,7 @@ impl HybridParser {
         }
 
         // Book-specific parser (deterministic) for known textbooks.
-        if algebra7_parser::matches(book_id) {
+        if self.profile == ParserProfile::Auto && algebra7_parser::matches(book_id) {
             log::info!("Using book-specific parser for {}", book_id);
             let result = algebra7_parser::parse(text);
             self.cache.set(&cache_key, result.clone()).await;
@@ -72,8 +276,8 @@ impl HybridParser {
         }
         
         // Try AI parser first if API key available
-        if let Some(ref _key) = self.api_key {
-            match self.ai_parse_with_retry(text).await {
+        if self.uses_ai() {
+            match self.ai_parse(text).await {
                 Ok(result) => {
                     log::info!("✅ AI parser successfully found {} problems", result.problems.len());
                     // Cache the result
@@ -108,7 +312,7 @@ impl HybridParser {
             }
         }).collect();
         
-        let result = AIParseResult { problems };
+        let result = AIParseResult { problems, degraded: self.uses_ai() };
         
         // Cache regex results too
         self.cache.set(&cache_key, result.clone()).await;
@@ -116,124 +320,21 @@ impl HybridParser {
         Ok(result)
     }
     
-    /// AI-powered parsing with retry logic
-    async fn ai_parse_with_retry(&self, text: &str) -> anyhow::Result<AIParseResult> {
-        let config = RetryConfig::default();
-        
-        retry_with_backoff(&config, "AI parse", || async {
-            self.ai_parse_internal(text).await
-        }).await
-    }
-
-    /// AI-powered parsing via Mistral (internal implementation)
-    async fn ai_parse_internal(&self, text: &str) -> anyhow::Result<AIParseResult> {
+    /// AI-powered parsing via Mistral
+    async fn ai_parse(&self, text: &str) -> anyhow::Result<AIParseResult> {
         let api_key = self.api_key.as_ref()
             .ok_or_else(|| anyhow::anyhow!("No API key available"))?;
-        
-        let python_script = format!(r#"
-import json
-import os
-import re
-from mistralai import Mistral
-
-api_key = os.getenv("MISTRAL_API_KEY", "{}")
-client = Mistral(api_key=api_key)
-
-ocr_text = '''{}'''
-
-# Clean OCR text - remove obvious OCR artifacts
-ocr_text = re.sub(r'([а-яa-z])\n\s*\1', r'\1', ocr_text)  # Remove duplicate letters at line breaks
-ocr_text = re.sub(r'\n\s*\n', '\n', ocr_text)  # Remove excessive blank lines
-
-prompt = '''Ты - эксперт по анализу математических учебников с 99% точностью.
-
-ЗАДАЧА: Разбери OCR текст и выдели ВСЕ задачи с подзадачами.
-
-КРИТИЧЕСКИ ВАЖНЫЕ ПРАВИЛА:
-1. Номера задач: 223, 224, 225 (целые числа, могут быть точки для подномеров: 1.1, 1.2)
-2. Подзадачи ВСЕГДА начинаются с буквы и скобки: а), б), в), г), д), е), ж), з), и), к), л), м), н), о), п), р), с), т)
-3. Подзадача = буква + ) + пробел/перенос + текст
-4. Если текст содержит "а)" или "б)" - это подзадачи
-5. Задача заканчивается перед следующей задачей или концом текста
-6. Игнорируй: теоремы, определения, примеры, упражнения без номеров
-7. Верни ТОЛЬКО JSON
-
-ОСОБЫЕ СЛУЧАИ:
-- "289. Текст... а)... б)... в)..." - это задача 289 с подзадачами
-- "Докажите, что..." без номера - НЕ задача
-- "Пример 1" - НЕ задача (это пример)
-
-ФОРМАТ ОТВЕТА (строго JSON):
-{{
-  "problems": [
-    {{
-      "number": "289",
-      "content": "Полный текст задачи со всеми подзадачами (а), б), в)...)",
-      "sub_problems": [
-        {{"letter": "а", "content": "Текст подзадачи без 'а)'"}},
-        {{"letter": "б", "content": "Текст подзадачи без 'б)'"}},
-        {{"letter": "в", "content": "Текст подзадачи без 'в)'"}}
-      ],
-      "continues_from_prev": false,
-      "continues_to_next": false
-    }}
-  ]
-}}
 
-Если задача начинается на этой странице (есть номер в начале) - continues_from_prev = false
-Если задача очевидно продолжается с предыдущей страницы (начинается с текста без номера, который логически продолжает предыдущую) - continues_from_prev = true
-
-OCR текст:
-''' + ocr_text + '''
+        let ocr_text = regex!(r"\n\s*\n").replace_all(&join_split_letters(text), "\n").into_owned();
+        let prompt = [PROBLEMS_PROMPT_HEAD, &ocr_text, PROBLEMS_PROMPT_TAIL].concat();
 
-Верни ТОЛЬКО JSON, без markdown (без ```).'''
-
-try:
-    response = client.chat.complete(
-        model="mistral-large-latest",
-        messages=[{{"role": "user", "content": prompt}}],
-        temperature=0.05,
-        max_tokens=8000
-    )
-    
-    result_text = response.choices[0].message.content.strip()
-    
-    # Clean markdown
-    result_text = re.sub(r'^```json\s*', '', result_text)
-    result_text = re.sub(r'^```\s*', '', result_text)
-    result_text = re.sub(r'```\s*$', '', result_text)
-    result_text = result_text.strip()
-    
-    data = json.loads(result_text)
-    
-    if "problems" not in data:
-        data = {{"problems": []}}
-    
-    print(json.dumps(data, ensure_ascii=False))
-    
-except Exception as e:
-    print(json.dumps({{"error": str(e), "problems": []}}, ensure_ascii=False))
-    raise
-"#, api_key, text.replace("'''", "'''"));
-
-        let output = Command::new("python3")
-            .arg("-c")
-            .arg(&python_script)
-            .env("MISTRAL_API_KEY", api_key)
-            .output()
-            .map_err(|e| anyhow::anyhow!("Failed to run Python: {}", e))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-
-        if !stderr.is_empty() {
-            log::warn!("AI parser stderr: {}", stderr);
+        #[derive(Deserialize)]
+        struct Answer {
+            #[serde(default)]
+            problems: Vec<ParsedProblem>,
         }
-
-        let result: AIParseResult = serde_json::from_str(&stdout)
-            .map_err(|e| anyhow::anyhow!("Failed to parse AI response: {}. Output: {}", e, stdout))?;
-
-        Ok(result)
+        let answer: Answer = mistral_parse_json(api_key, &prompt, 0.05, self.timeout).await?;
+        Ok(AIParseResult { problems: answer.problems, degraded: false })
     }
 
     /// Analyze if problems continue across pages
@@ -287,7 +388,7 @@ except Exception as e:
         if let Some(prev) = prev_problems {
             if let Some(first) = current_problems.first_mut() {
                 // If first problem has same number as last on prev page
-                if first.number == prev.number {
+                if same_number(&first.number, &prev.number) {
                     first.continues_from_prev = true;
                 }
             }
@@ -297,7 +398,7 @@ except Exception as e:
         if let Some(analysis) = next_page_analysis {
             if let Some(ref incomplete_num) = analysis.incomplete_problem {
                 for problem in current_problems.iter_mut() {
-                    if &problem.number == incomplete_num {
+                    if same_number(&problem.number, incomplete_num) {
                         problem.continues_to_next = true;
                     }
                 }
@@ -364,7 +465,7 @@ except Exception as e:
         // Merge with previous page content
         if let Some(first) = current_problems.first_mut() {
             if let Some(prev) = prev_problem {
-                if first.number == prev.number {
+                if same_number(&first.number, &prev.number) {
                     first.continues_from_prev = true;
                     if let Some(tail) = prev_continuation_tail {
                         first.content = self.merge_with_prev_content(&first.content, Some(tail));
@@ -387,7 +488,7 @@ except Exception as e:
         let should_mark_continuation = if let Some(next) = next_problems {
             if let Some(current_last) = current_problems.last() {
                 if let Some(next_first) = next.first() {
-                    current_last.number == next_first.number
+                    same_number(&current_last.number, &next_first.number)
                 } else {
                     false
                 }
@@ -427,10 +528,22 @@ except Exception as e:
     }
 }
 
+/// Same problem number up to OCR noise ("71." and "71")
+fn same_number(a: &str, b: &str) -> bool {
+    Problem::normalize_number(a) == Problem::normalize_number(b)
+}
+
 #[cfg(test)]
 mod cross_page_tests {
     use super::*;
 
+    #[test]
+    fn prepares_text_and_reads_fenced_answers() {
+        assert_eq!(join_split_letters("Решите задач\n  ча 5\nа) x"), "Решите задача 5\nа) x");
+        assert_eq!(strip_code_fence("```json\n{\"problems\": []}\n```"), "{\"problems\": []}");
+        assert_eq!(strip_code_fence(" {} "), "{}");
+    }
+
     #[test]
     fn test_extract_continuation_tail_incomplete() {
         let parser = HybridParser::new(None);
@@ -568,10 +681,10 @@ mod cross_page_tests {
 /// like `а)` / `a)`) while avoiding false positives from step lists like `1) ...` inside examples.
 mod algebra7_parser {
     use super::{AIParseResult, ParsedProblem, ParsedSubProblem};
+    use crate::services::heading_detector::HeadingDetector;
 
     pub fn matches(book_id: &str) -> bool {
-        let id = book_id.trim().trim_end_matches(".pdf");
-        id.eq_ignore_ascii_case("algebra-7")
+        crate::utils::slug::book_id_of(book_id).eq_ignore_ascii_case("algebra-7")
     }
 
     pub fn parse(text: &str) -> AIParseResult {
@@ -592,7 +705,7 @@ mod algebra7_parser {
 
             // Chapter header often appears at page boundary and should not be appended
             // to the previous problem content.
-            if is_chapter_heading_line(line) {
+            if HeadingDetector::defaults().is_heading_line(line) {
                 if let Some(pb) = current.take() {
                     out.push(pb.finish());
                 }
@@ -627,35 +740,7 @@ mod algebra7_parser {
             out.push(pb.finish());
         }
 
-        AIParseResult { problems: out }
-    }
-
-    fn is_chapter_heading_line(line: &str) -> bool {
-        let lower = line.trim().to_lowercase();
-        let Some(rest) = lower.strip_prefix("глава") else {
-            return false;
-        };
-
-        let rest = rest.trim_start();
-        if rest.is_empty() {
-            return false;
-        }
-
-        let chapter_token: String = rest
-            .chars()
-            .take_while(|c| !c.is_whitespace() && *c != '.' && *c != ':')
-            .collect();
-
-        if chapter_token.is_empty() {
-            return false;
-        }
-
-        let is_digits = chapter_token.chars().all(|c| c.is_ascii_digit());
-        let is_roman = chapter_token
-            .chars()
-            .all(|c| matches!(c, 'i' | 'v' | 'x' | 'l' | 'c' | 'd' | 'm'));
-
-        is_digits || is_roman
+        AIParseResult { problems: out, degraded: false }
     }
 
     fn parse_main_problem_start(line: &str) -> Option<(String, String)> {
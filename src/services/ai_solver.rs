@@ -1,17 +1,80 @@
-use crate::config::Config;
-use crate::models::problem::{Problem, Solution};
+use crate::config::{Config, OFFLINE_ERROR};
+use crate::models::problem::{GenerationParams, Problem, Solution, SolveOptions};
+use crate::services::credentials::ProviderCredentials;
+use crate::services::http_client::HttpClientFactory;
+use crate::services::markdown_sanitizer::sanitize_markdown;
+use crate::services::provider_registry::{self, ProviderKind};
+use crate::services::solve_cache::solve_cache_key;
 use async_trait::async_trait;
 use chrono::Utc;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Sampling temperature for solutions when the request doesn't set one
+const SOLVE_TEMPERATURE: f32 = 0.3;
+
+/// Output token budget for solutions when the request doesn't set one
+const SOLVE_MAX_TOKENS: u32 = 4096;
+
+/// Version of `build_solution_prompt`. Bump it when the prompt changes so
+/// solutions cached for the old wording aren't served for the new one.
+pub const SOLUTION_PROMPT_VERSION: u32 = 1;
+
+/// Provider used when a solve request doesn't name one: the first of claude,
+/// openai and mistral with an API key
+pub fn default_solve_provider() -> Option<&'static str> {
+    let credentials = ProviderCredentials::global();
+    ["claude", "openai", "mistral"].into_iter().find(|p| credentials.has_provider(p))
+}
+
+/// Fill in a solve provider's defaults and reject a model it doesn't offer
+/// or a temperature/token budget outside its limits
+pub fn resolve_solve_options(provider: &str, options: &SolveOptions) -> Result<GenerationParams, String> {
+    let caps = provider_registry::capabilities(ProviderKind::Solve, provider)
+        .ok_or_else(|| format!("Unknown solve provider '{}'", provider))?;
+
+    let model = match &options.model {
+        Some(model) if caps.models.contains(&model.as_str()) => model.clone(),
+        Some(model) => {
+            return Err(format!(
+                "Model '{}' is not available for {}; expected one of: {}",
+                model,
+                provider,
+                caps.models.join(", ")
+            ));
+        }
+        None => caps.model.unwrap_or_default().to_string(),
+    };
+
+    let temperature = options.temperature.unwrap_or(SOLVE_TEMPERATURE);
+    let max_temperature = caps.max_temperature.unwrap_or(1.0);
+    if !(0.0..=max_temperature).contains(&temperature) {
+        return Err(format!("temperature must be between 0 and {} for {}", max_temperature, provider));
+    }
+
+    let max_tokens = options.max_tokens.unwrap_or(SOLVE_MAX_TOKENS);
+    let token_limit = caps.max_tokens.unwrap_or(SOLVE_MAX_TOKENS);
+    if max_tokens == 0 || max_tokens > token_limit {
+        return Err(format!("max_tokens must be between 1 and {} for {}", token_limit, provider));
+    }
+
+    Ok(GenerationParams { model, temperature, max_tokens })
+}
 
 /// AI Provider trait for generating solutions
 #[async_trait]
 pub trait SolutionProvider: Send + Sync {
     /// Generate solution for a problem
-    async fn solve(&self, problem: &Problem, context: &str) -> anyhow::Result<String>;
+    async fn solve(&self, problem: &Problem, context: &str, params: &GenerationParams) -> anyhow::Result<String>;
     /// Generate a hint for a problem
     async fn hint(&self, problem: &Problem, context: &str, hint_level: u8) -> anyhow::Result<String>;
+    /// Explain a passage or formula selected in the textbook
+    async fn explain(&self, selection: &str, context: &str) -> anyhow::Result<String>;
+    /// Write short definitions of terms, one `term: definition` line each
+    async fn define_terms(&self, terms: &[String], context: &str) -> anyhow::Result<String>;
+    /// Describe the drawing of a geometry problem as JSON, see [`crate::services::figure`]
+    async fn describe_figure(&self, problem: &str) -> anyhow::Result<String>;
     /// Provider name
     fn name(&self) -> &'static str;
 }
@@ -23,42 +86,43 @@ pub struct AISolver {
 }
 
 impl AISolver {
-    pub fn new(_config: &Config) -> anyhow::Result<Self> {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        if config.offline {
+            return Err(anyhow::anyhow!(OFFLINE_ERROR));
+        }
+
         let mut providers: HashMap<String, Box<dyn SolutionProvider>> = HashMap::new();
 
+        let credentials = ProviderCredentials::global();
+        let http = HttpClientFactory::global();
+
         // Add OpenAI provider if API key is available
-        if let Ok(key) = std::env::var("OPENAI_API_KEY") {
+        if credentials.has_provider("openai") {
             providers.insert(
                 "openai".to_string(),
-                Box::new(OpenAIProvider::new(key)),
+                Box::new(OpenAIProvider::new(credentials.clone(), http.client(config.provider_timeout()))),
             );
         }
 
         // Add Claude provider if API key is available
-        if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
+        if credentials.has_provider("claude") {
             providers.insert(
                 "claude".to_string(),
-                Box::new(ClaudeProvider::new(key)),
+                Box::new(ClaudeProvider::new(credentials.clone(), http.client(config.provider_timeout()))),
             );
         }
 
         // Add Mistral provider if API key is available
-        if let Ok(key) = std::env::var("MISTRAL_API_KEY") {
+        if credentials.has_provider("mistral") {
             providers.insert(
                 "mistral".to_string(),
-                Box::new(MistralProvider::new(key)),
+                Box::new(MistralProvider::new(credentials.clone(), http.client(config.provider_timeout()))),
             );
         }
 
-        let default_provider = if providers.contains_key("claude") {
-            "claude"
-        } else if providers.contains_key("openai") {
-            "openai"
-        } else if providers.contains_key("mistral") {
-            "mistral"
-        } else {
-            return Err(anyhow::anyhow!("No AI providers configured. Set OPENAI_API_KEY, ANTHROPIC_API_KEY, or MISTRAL_API_KEY"));
-        }.to_string();
+        let default_provider = default_solve_provider()
+            .ok_or_else(|| anyhow::anyhow!("No AI providers configured. Set OPENAI_API_KEY, ANTHROPIC_API_KEY, or MISTRAL_API_KEY"))?
+            .to_string();
 
         Ok(Self {
             providers,
@@ -72,14 +136,22 @@ impl AISolver {
         problem: &Problem,
         provider: Option<&str>,
         theory_context: Option<&str>,
+        options: &SolveOptions,
     ) -> anyhow::Result<Solution> {
         let provider_name = provider.unwrap_or(&self.default_provider);
         let provider = self.providers
             .get(provider_name)
             .ok_or_else(|| anyhow::anyhow!("Provider {} not available", provider_name))?;
+        let params = resolve_solve_options(provider_name, options).map_err(|e| anyhow::anyhow!(e))?;
+
+        if !provider_registry::circuit_allows(ProviderKind::Solve, provider_name) {
+            return Err(anyhow::anyhow!("Provider {} is temporarily disabled after repeated failures", provider_name));
+        }
 
         let context = theory_context.unwrap_or("");
-        let content = provider.solve(problem, context).await?;
+        let result = provider.solve(problem, context, &params).await;
+        provider_registry::record_outcome(ProviderKind::Solve, provider_name, result.is_ok());
+        let content = sanitize_markdown(&result?);
 
         Ok(Solution {
             id: Solution::generate_id(&problem.id),
@@ -89,26 +161,100 @@ impl AISolver {
             latex_formulas: extract_latex_formulas(&content),
             is_verified: false,
             rating: None,
+            prompt_hash: Some(solve_cache_key(provider_name, &problem.content, context, &params)),
+            version: 1,
+            generation: Some(params),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         })
     }
 
-    /// Generate hint for a problem
+    /// Generate hint for a problem; returns the provider used and the hint
     pub async fn hint(
         &self,
         problem: &Problem,
         provider: Option<&str>,
         theory_context: Option<&str>,
         hint_level: u8,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<(String, String)> {
         let provider_name = provider.unwrap_or(&self.default_provider);
         let provider = self.providers
             .get(provider_name)
             .ok_or_else(|| anyhow::anyhow!("Provider {} not available", provider_name))?;
 
+        if !provider_registry::circuit_allows(ProviderKind::Solve, provider_name) {
+            return Err(anyhow::anyhow!("Provider {} is temporarily disabled after repeated failures", provider_name));
+        }
+
         let context = theory_context.unwrap_or("");
-        provider.hint(problem, context, hint_level).await
+        let result = provider.hint(problem, context, hint_level).await;
+        provider_registry::record_outcome(ProviderKind::Solve, provider_name, result.is_ok());
+        Ok((provider_name.to_string(), result?))
+    }
+
+    /// Explain a selected passage; returns the provider used and the explanation
+    pub async fn explain(
+        &self,
+        selection: &str,
+        provider: Option<&str>,
+        page_context: Option<&str>,
+    ) -> anyhow::Result<(String, String)> {
+        let provider_name = provider.unwrap_or(&self.default_provider);
+        let provider = self.providers
+            .get(provider_name)
+            .ok_or_else(|| anyhow::anyhow!("Provider {} not available", provider_name))?;
+
+        if !provider_registry::circuit_allows(ProviderKind::Solve, provider_name) {
+            return Err(anyhow::anyhow!("Provider {} is temporarily disabled after repeated failures", provider_name));
+        }
+
+        let context = page_context.unwrap_or("");
+        let result = provider.explain(selection, context).await;
+        provider_registry::record_outcome(ProviderKind::Solve, provider_name, result.is_ok());
+        Ok((provider_name.to_string(), result?))
+    }
+
+    /// Write concise definitions for glossary terms the book doesn't define
+    pub async fn define_terms(
+        &self,
+        terms: &[String],
+        provider: Option<&str>,
+        subject_context: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let provider_name = provider.unwrap_or(&self.default_provider);
+        let provider = self.providers
+            .get(provider_name)
+            .ok_or_else(|| anyhow::anyhow!("Provider {} not available", provider_name))?;
+
+        if !provider_registry::circuit_allows(ProviderKind::Solve, provider_name) {
+            return Err(anyhow::anyhow!("Provider {} is temporarily disabled after repeated failures", provider_name));
+        }
+
+        let context = subject_context.unwrap_or("");
+        let result = provider.define_terms(terms, context).await;
+        provider_registry::record_outcome(ProviderKind::Solve, provider_name, result.is_ok());
+        result
+    }
+
+    /// Ask for the figure of a geometry problem; returns the provider used
+    /// and its raw answer
+    pub async fn describe_figure(
+        &self,
+        problem: &Problem,
+        provider: Option<&str>,
+    ) -> anyhow::Result<(String, String)> {
+        let provider_name = provider.unwrap_or(&self.default_provider);
+        let provider = self.providers
+            .get(provider_name)
+            .ok_or_else(|| anyhow::anyhow!("Provider {} not available", provider_name))?;
+
+        if !provider_registry::circuit_allows(ProviderKind::Solve, provider_name) {
+            return Err(anyhow::anyhow!("Provider {} is temporarily disabled after repeated failures", provider_name));
+        }
+
+        let result = provider.describe_figure(&problem.content).await;
+        provider_registry::record_outcome(ProviderKind::Solve, provider_name, result.is_ok());
+        Ok((provider_name.to_string(), result?))
     }
 
     /// List available providers
@@ -119,26 +265,23 @@ impl AISolver {
 
 /// OpenAI GPT-4o provider
 pub struct OpenAIProvider {
-    api_key: String,
+    credentials: Arc<ProviderCredentials>,
     client: reqwest::Client,
 }
 
 impl OpenAIProvider {
-    pub fn new(api_key: String) -> Self {
-        Self {
-            api_key,
-            client: reqwest::Client::new(),
-        }
+    pub fn new(credentials: Arc<ProviderCredentials>, client: reqwest::Client) -> Self {
+        Self { credentials, client }
     }
 }
 
 #[async_trait]
 impl SolutionProvider for OpenAIProvider {
-    async fn solve(&self, problem: &Problem, context: &str) -> anyhow::Result<String> {
+    async fn solve(&self, problem: &Problem, context: &str, params: &GenerationParams) -> anyhow::Result<String> {
         let prompt = build_solution_prompt(&problem.content, context);
 
         let request_body = serde_json::json!({
-            "model": "gpt-4o",
+            "model": params.model,
             "messages": [
                 {
                     "role": "system",
@@ -149,15 +292,17 @@ impl SolutionProvider for OpenAIProvider {
                     "content": prompt
                 }
             ],
-            "temperature": 0.3,
-            "max_tokens": 4096
+            "temperature": params.temperature,
+            "max_tokens": params.max_tokens
         });
 
-        let response = self.client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("openai", |key| {
+                self.client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -193,11 +338,139 @@ impl SolutionProvider for OpenAIProvider {
             "max_tokens": 1024
         });
 
-        let response = self.client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("openai", |key| {
+                self.client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn explain(&self, selection: &str, context: &str) -> anyhow::Result<String> {
+        let prompt = build_explain_prompt(selection, context);
+
+        let request_body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert math teacher. Explain textbook passages and formulas clearly to a student. Use LaTeX for math formulas."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.4,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("openai", |key| {
+                self.client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn define_terms(&self, terms: &[String], context: &str) -> anyhow::Result<String> {
+        let prompt = build_glossary_prompt(terms, context);
+
+        let request_body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert math teacher writing a glossary for a textbook. Give short, precise definitions. Use LaTeX for math formulas."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.2,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("openai", |key| {
+                self.client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn describe_figure(&self, problem: &str) -> anyhow::Result<String> {
+        let prompt = build_figure_prompt(problem);
+
+        let request_body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert geometry teacher. Reconstruct the drawing a geometry problem refers to as exact coordinates. Answer with JSON only."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.1,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("openai", |key| {
+                self.client
+                    .post("https://api.openai.com/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -221,27 +494,25 @@ impl SolutionProvider for OpenAIProvider {
 
 /// Claude provider
 pub struct ClaudeProvider {
-    api_key: String,
+    credentials: Arc<ProviderCredentials>,
     client: reqwest::Client,
 }
 
 impl ClaudeProvider {
-    pub fn new(api_key: String) -> Self {
-        Self {
-            api_key,
-            client: reqwest::Client::new(),
-        }
+    pub fn new(credentials: Arc<ProviderCredentials>, client: reqwest::Client) -> Self {
+        Self { credentials, client }
     }
 }
 
 #[async_trait]
 impl SolutionProvider for ClaudeProvider {
-    async fn solve(&self, problem: &Problem, context: &str) -> anyhow::Result<String> {
+    async fn solve(&self, problem: &Problem, context: &str, params: &GenerationParams) -> anyhow::Result<String> {
         let prompt = build_solution_prompt(&problem.content, context);
 
         let request_body = serde_json::json!({
-            "model": "claude-3-5-sonnet-20241022",
-            "max_tokens": 4096,
+            "model": params.model,
+            "max_tokens": params.max_tokens,
+            "temperature": params.temperature,
             "messages": [
                 {
                     "role": "user",
@@ -251,13 +522,15 @@ impl SolutionProvider for ClaudeProvider {
             "system": "You are an expert math teacher. Solve problems step by step, explaining each step clearly. Use LaTeX for math formulas ($...$ for inline, $$...$$ for display)."
         });
 
-        let response = self.client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("claude", |key| {
+                self.client
+                    .post("https://api.anthropic.com/v1/messages")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("content-type", "application/json")
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -289,13 +562,135 @@ impl SolutionProvider for ClaudeProvider {
             "system": "You are an expert math teacher. Provide helpful hints without giving away the full solution. Use LaTeX for math formulas."
         });
 
-        let response = self.client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("claude", |key| {
+                self.client
+                    .post("https://api.anthropic.com/v1/messages")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("content-type", "application/json")
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Claude API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["content"][0]["text"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn explain(&self, selection: &str, context: &str) -> anyhow::Result<String> {
+        let prompt = build_explain_prompt(selection, context);
+
+        let request_body = serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "max_tokens": 2048,
+            "messages": [
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "system": "You are an expert math teacher. Explain textbook passages and formulas clearly to a student. Use LaTeX for math formulas."
+        });
+
+        let response = self.credentials
+            .send_with_rotation("claude", |key| {
+                self.client
+                    .post("https://api.anthropic.com/v1/messages")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("content-type", "application/json")
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Claude API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["content"][0]["text"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn define_terms(&self, terms: &[String], context: &str) -> anyhow::Result<String> {
+        let prompt = build_glossary_prompt(terms, context);
+
+        let request_body = serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "max_tokens": 2048,
+            "messages": [
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "system": "You are an expert math teacher writing a glossary for a textbook. Give short, precise definitions. Use LaTeX for math formulas."
+        });
+
+        let response = self.credentials
+            .send_with_rotation("claude", |key| {
+                self.client
+                    .post("https://api.anthropic.com/v1/messages")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("content-type", "application/json")
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Claude API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["content"][0]["text"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn describe_figure(&self, problem: &str) -> anyhow::Result<String> {
+        let prompt = build_figure_prompt(problem);
+
+        let request_body = serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "max_tokens": 2048,
+            "messages": [
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "system": "You are an expert geometry teacher. Reconstruct the drawing a geometry problem refers to as exact coordinates. Answer with JSON only."
+        });
+
+        let response = self.credentials
+            .send_with_rotation("claude", |key| {
+                self.client
+                    .post("https://api.anthropic.com/v1/messages")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("content-type", "application/json")
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -319,26 +714,23 @@ impl SolutionProvider for ClaudeProvider {
 
 /// Mistral provider
 pub struct MistralProvider {
-    api_key: String,
+    credentials: Arc<ProviderCredentials>,
     client: reqwest::Client,
 }
 
 impl MistralProvider {
-    pub fn new(api_key: String) -> Self {
-        Self {
-            api_key,
-            client: reqwest::Client::new(),
-        }
+    pub fn new(credentials: Arc<ProviderCredentials>, client: reqwest::Client) -> Self {
+        Self { credentials, client }
     }
 }
 
 #[async_trait]
 impl SolutionProvider for MistralProvider {
-    async fn solve(&self, problem: &Problem, context: &str) -> anyhow::Result<String> {
+    async fn solve(&self, problem: &Problem, context: &str, params: &GenerationParams) -> anyhow::Result<String> {
         let prompt = build_solution_prompt(&problem.content, context);
 
         let request_body = serde_json::json!({
-            "model": "mistral-large-latest",
+            "model": params.model,
             "messages": [
                 {
                     "role": "system",
@@ -349,15 +741,17 @@ impl SolutionProvider for MistralProvider {
                     "content": prompt
                 }
             ],
-            "temperature": 0.3,
-            "max_tokens": 4096
+            "temperature": params.temperature,
+            "max_tokens": params.max_tokens
         });
 
-        let response = self.client
-            .post("https://api.mistral.ai/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("mistral", |key| {
+                self.client
+                    .post("https://api.mistral.ai/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -393,11 +787,139 @@ impl SolutionProvider for MistralProvider {
             "max_tokens": 1024
         });
 
-        let response = self.client
-            .post("https://api.mistral.ai/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
+        let response = self.credentials
+            .send_with_rotation("mistral", |key| {
+                self.client
+                    .post("https://api.mistral.ai/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Mistral API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn explain(&self, selection: &str, context: &str) -> anyhow::Result<String> {
+        let prompt = build_explain_prompt(selection, context);
+
+        let request_body = serde_json::json!({
+            "model": "mistral-large-latest",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert math teacher. Explain textbook passages and formulas clearly to a student. Use LaTeX for math formulas."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.4,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("mistral", |key| {
+                self.client
+                    .post("https://api.mistral.ai/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Mistral API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn define_terms(&self, terms: &[String], context: &str) -> anyhow::Result<String> {
+        let prompt = build_glossary_prompt(terms, context);
+
+        let request_body = serde_json::json!({
+            "model": "mistral-large-latest",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert math teacher writing a glossary for a textbook. Give short, precise definitions. Use LaTeX for math formulas."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.2,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("mistral", |key| {
+                self.client
+                    .post("https://api.mistral.ai/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow::anyhow!("Mistral API error: {}", error_text));
+        }
+
+        let result: Value = response.json().await?;
+        let content = result["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response format"))?
+            .to_string();
+
+        Ok(content)
+    }
+
+    async fn describe_figure(&self, problem: &str) -> anyhow::Result<String> {
+        let prompt = build_figure_prompt(problem);
+
+        let request_body = serde_json::json!({
+            "model": "mistral-large-latest",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are an expert geometry teacher. Reconstruct the drawing a geometry problem refers to as exact coordinates. Answer with JSON only."
+                },
+                {
+                    "role": "user",
+                    "content": prompt
+                }
+            ],
+            "temperature": 0.1,
+            "max_tokens": 2048
+        });
+
+        let response = self.credentials
+            .send_with_rotation("mistral", |key| {
+                self.client
+                    .post("https://api.mistral.ai/v1/chat/completions")
+                    .header("Authorization", format!("Bearer {}", key))
+                    .json(&request_body)
+            })
             .await?;
 
         if !response.status().is_success() {
@@ -476,6 +998,82 @@ Hint:"#,
     )
 }
 
+/// Build the prompt explaining a selected textbook passage
+fn build_explain_prompt(selection: &str, context: &str) -> String {
+    format!(
+        r#"A student selected the following passage in a math textbook and asked for an explanation.
+
+Selected passage:
+{}
+
+Surrounding text from the same page:
+{}
+
+Requirements:
+1. Explain what the passage or formula means in simple terms
+2. Define any notation or terms it uses
+3. If it is a formula or theorem, show a short worked example
+4. Use LaTeX for mathematical expressions ($...$ for inline, $$...$$ for display math)
+5. Use Russian language
+
+Explanation:"#,
+        selection,
+        if context.is_empty() { "None provided" } else { context }
+    )
+}
+
+/// Build the prompt defining glossary terms
+fn build_glossary_prompt(terms: &[String], context: &str) -> String {
+    format!(
+        r#"Write a glossary definition for each of the following terms from a math textbook.
+
+Terms:
+{}
+
+Textbook:
+{}
+
+Requirements:
+1. One line per term in the form `term: definition`, keeping the term exactly as given
+2. One or two sentences per definition, at the level of the textbook
+3. Use LaTeX for mathematical expressions ($...$ for inline)
+4. Use Russian language
+5. No introduction or closing remarks
+
+Glossary:"#,
+        terms.join("\n"),
+        if context.is_empty() { "None provided" } else { context }
+    )
+}
+
+/// Build the prompt reconstructing a geometry problem's figure
+fn build_figure_prompt(problem: &str) -> String {
+    format!(
+        r#"The following geometry problem comes from a textbook whose drawing is missing. Reconstruct the drawing.
+
+Problem:
+{}
+
+Answer with one JSON object:
+{{"points": [{{"name": "A", "x": 0, "y": 0}}, ...],
+ "segments": [["A", "B"], ...],
+ "polygons": [["A", "B", "C"], ...],
+ "circles": [{{"center": "O", "radius": 2}}, ...],
+ "right_angles": [["A", "C", "B"], ...],
+ "labels": [{{"from": "A", "to": "B", "text": "5 см"}}, ...]}}
+
+Requirements:
+1. Use the point names of the problem; add named points (feet of heights, centers) only when the problem mentions them
+2. Coordinates must satisfy the given data (lengths, angles, parallel and perpendicular lines) as closely as possible, y pointing up
+3. Draw polygons as polygons, other lines as segments; right_angles lists [A, B, C] for the angle at B
+4. Label only lengths and angles given in the problem, never the unknowns
+5. No markup and no text outside the JSON
+
+JSON:"#,
+        problem
+    )
+}
+
 /// Extract LaTeX formulas from solution text
 fn extract_latex_formulas(text: &str) -> Vec<String> {
     let mut formulas = Vec::new();
@@ -494,3 +1092,31 @@ fn extract_latex_formulas(text: &str) -> Vec<String> {
 
     formulas
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_options_are_checked_against_provider_capabilities() {
+        let defaults = resolve_solve_options("claude", &SolveOptions::default()).unwrap();
+        assert_eq!(defaults.model, "claude-3-5-sonnet-20241022");
+        assert_eq!((defaults.temperature, defaults.max_tokens), (SOLVE_TEMPERATURE, SOLVE_MAX_TOKENS));
+
+        let opus = SolveOptions {
+            model: Some("claude-opus-4-20250514".to_string()),
+            temperature: Some(0.0),
+            max_tokens: Some(8192),
+        };
+        assert_eq!(resolve_solve_options("claude", &opus).unwrap().model, "claude-opus-4-20250514");
+
+        // The model belongs to another provider, and the budget/temperature are over the limits
+        assert!(resolve_solve_options("mistral", &opus).is_err());
+        let too_long = SolveOptions { max_tokens: Some(100_000), ..Default::default() };
+        assert!(resolve_solve_options("openai", &too_long).is_err());
+        let too_hot = SolveOptions { temperature: Some(1.5), ..Default::default() };
+        assert!(resolve_solve_options("claude", &too_hot).is_err());
+        assert!(resolve_solve_options("openai", &too_hot).is_ok());
+        assert!(resolve_solve_options("gemini", &SolveOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Anki `.apkg` packages: a zip with a schema-11 SQLite collection
+//! (`collection.anki2`) and the media files, numbered, with a `media` JSON map
+//! back to their names. Cards use one "Basic" note type; math is written with
+//! `\(...\)` and `\[...\]` so Anki's MathJax renders it.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+use lazy_regex::regex;
+use sha1::{Digest, Sha1};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+use crate::services::anki_import::FIELD_SEPARATOR;
+
+/// Note type of the exported cards; fixed so re-imports update the same type
+const MODEL_ID: i64 = 1_607_392_319_001;
+const DEFAULT_DECK_ID: i64 = 1;
+/// Deck ids are allocated from here, one per deck in order of appearance
+const FIRST_DECK_ID: i64 = 1_607_392_320_000;
+
+const CARD_CSS: &str = ".card { font-family: arial; font-size: 20px; text-align: left; color: black; background-color: white; }\n\
+img { max-width: 100%; }";
+
+const SCHEMA: &str = r#"
+CREATE TABLE col (
+    id integer primary key, crt integer not null, mod integer not null, scm integer not null,
+    ver integer not null, dty integer not null, usn integer not null, ls integer not null,
+    conf text not null, models text not null, decks text not null, dconf text not null, tags text not null
+);
+CREATE TABLE notes (
+    id integer primary key, guid text not null, mid integer not null, mod integer not null,
+    usn integer not null, tags text not null, flds text not null, sfld integer not null,
+    csum integer not null, flags integer not null, data text not null
+);
+CREATE TABLE cards (
+    id integer primary key, nid integer not null, did integer not null, ord integer not null,
+    mod integer not null, usn integer not null, type integer not null, queue integer not null,
+    due integer not null, ivl integer not null, factor integer not null, reps integer not null,
+    lapses integer not null, left integer not null, odue integer not null, odid integer not null,
+    flags integer not null, data text not null
+);
+CREATE TABLE revlog (
+    id integer primary key, cid integer not null, usn integer not null, ease integer not null,
+    ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null,
+    type integer not null
+);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+"#;
+
+/// One card to export; `front` and `back` are already card HTML (see
+/// [`card_html`])
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnkiNote {
+    /// Stable across exports (the problem id), so a re-import updates the
+    /// note instead of adding a copy
+    pub key: String,
+    /// `Book::Глава 1`; `::` nests decks
+    pub deck: String,
+    pub front: String,
+    pub back: String,
+    pub tags: Vec<String>,
+}
+
+/// Media file referenced from card HTML as `<img src="{name}">`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnkiMedia {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Card HTML of Markdown with LaTeX. Text is escaped (so `a < b` inside
+/// math survives), math delimiters become MathJax ones, `**bold**` and line
+/// breaks become tags. `image` maps a Markdown image URL to the media name it
+/// is exported as; images it returns None for are dropped.
+pub fn card_html(markdown: &str, image: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    // Images are set aside so escaping and math conversion leave them alone
+    let mut images: Vec<String> = Vec::new();
+    let text = regex!(r"!\[([^\]]*)\]\(([^)\s]+)\)").replace_all(markdown, |caps: &lazy_regex::Captures| {
+        let Some(name) = image(&caps[2]) else { return String::new() };
+        images.push(format!("<img src=\"{}\" alt=\"{}\">", escape_html(&name), escape_html(&caps[1])));
+        format!("\u{0}{}\u{0}", images.len() - 1)
+    });
+
+    let text = escape_html(&text).replace("$$", "\u{1}");
+    let mut output = String::with_capacity(text.len());
+    let (mut inline_open, mut display_open) = (true, true);
+    for c in text.chars() {
+        match c {
+            '$' => {
+                output.push_str(if inline_open { "\\(" } else { "\\)" });
+                inline_open = !inline_open;
+            }
+            '\u{1}' => {
+                output.push_str(if display_open { "\\[" } else { "\\]" });
+                display_open = !display_open;
+            }
+            c => output.push(c),
+        }
+    }
+    let output = regex!(r"\x00(\d+)\x00").replace_all(&output, |caps: &lazy_regex::Captures| {
+        caps[1].parse::<usize>().ok().and_then(|i| images.get(i).cloned()).unwrap_or_default()
+    });
+    let output = regex!(r"\*\*([^*\n]+)\*\*").replace_all(&output, "<b>$1</b>");
+    output.trim().replace('\n', "<br>\n")
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Build an `.apkg` package of the notes and media
+pub async fn build_apkg(notes: &[AnkiNote], media: &[AnkiMedia]) -> Result<Vec<u8>> {
+    // sqlx needs a file on disk for the collection
+    let path = std::env::temp_dir().join(format!("bookers-anki-export-{}.db", uuid::Uuid::new_v4()));
+    let collection = async {
+        let options = SqliteConnectOptions::new().filename(&path).create_if_missing(true);
+        let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await?;
+        let written = write_collection(&pool, notes).await;
+        pool.close().await;
+        written?;
+        anyhow::Ok(std::fs::read(&path)?)
+    }
+    .await;
+    let _ = std::fs::remove_file(&path);
+    let collection = collection?;
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("collection.anki2", options)?;
+    zip.write_all(&collection)?;
+
+    let mut names: BTreeMap<String, &str> = BTreeMap::new();
+    for (i, file) in media.iter().enumerate() {
+        zip.start_file(i.to_string(), options)?;
+        zip.write_all(&file.data)?;
+        names.insert(i.to_string(), &file.name);
+    }
+    zip.start_file("media", options)?;
+    zip.write_all(serde_json::to_string(&names)?.as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+async fn write_collection(pool: &SqlitePool, notes: &[AnkiNote]) -> Result<()> {
+    let now = chrono::Utc::now();
+    let (now_s, now_ms) = (now.timestamp(), now.timestamp_millis());
+
+    sqlx::raw_sql(SCHEMA).execute(pool).await?;
+
+    let mut deck_ids: BTreeMap<&str, i64> = BTreeMap::new();
+    for note in notes {
+        let next = FIRST_DECK_ID + deck_ids.len() as i64;
+        deck_ids.entry(note.deck.as_str()).or_insert(next);
+    }
+    let mut decks = serde_json::Map::new();
+    decks.insert(DEFAULT_DECK_ID.to_string(), deck_json(DEFAULT_DECK_ID, "Default", now_s));
+    for (name, id) in &deck_ids {
+        decks.insert(id.to_string(), deck_json(*id, name, now_s));
+    }
+    let first_deck = deck_ids.values().min().copied().unwrap_or(DEFAULT_DECK_ID);
+
+    sqlx::query(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?3, 11, 0, 0, 0, ?4, ?5, ?6, ?7, '{}')"
+    )
+    .bind(now_s)
+    .bind(now_ms)
+    .bind(now_ms)
+    .bind(collection_conf(first_deck).to_string())
+    .bind(serde_json::json!({ MODEL_ID.to_string(): model_json(first_deck, now_s) }).to_string())
+    .bind(serde_json::Value::Object(decks).to_string())
+    .bind(serde_json::json!({ "1": deck_conf_json(now_s) }).to_string())
+    .execute(pool)
+    .await?;
+
+    for (i, note) in notes.iter().enumerate() {
+        let note_id = now_ms + i as i64;
+        let tags: Vec<String> = note.tags.iter().map(|t| t.replace(char::is_whitespace, "_")).collect();
+        let tags = if tags.is_empty() { String::new() } else { format!(" {} ", tags.join(" ")) };
+        sqlx::query("INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')")
+            .bind(note_id)
+            .bind(note_guid(&note.key))
+            .bind(MODEL_ID)
+            .bind(now_s)
+            .bind(tags)
+            .bind(format!("{}{}{}", note.front, FIELD_SEPARATOR, note.back))
+            .bind(strip_html(&note.front))
+            .bind(field_checksum(&note.front))
+            .execute(pool)
+            .await?;
+        sqlx::query("INSERT INTO cards VALUES (?1, ?2, ?3, 0, ?4, -1, 0, 0, ?5, 0, 0, 0, 0, 0, 0, 0, 0, '')")
+            .bind(note_id)
+            .bind(note_id)
+            .bind(deck_ids[note.deck.as_str()])
+            .bind(now_s)
+            .bind(i as i64 + 1)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Note guid derived from the note's key
+fn note_guid(key: &str) -> String {
+    let digest = Sha1::digest(key.as_bytes());
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Anki's duplicate-check checksum: the first 32 bits of the SHA-1 of the
+/// first field without HTML
+fn field_checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(strip_html(field).as_bytes());
+    i64::from(u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]))
+}
+
+fn strip_html(html: &str) -> String {
+    let text = regex!(r"<[^>]*>").replace_all(html, "");
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&").trim().to_string()
+}
+
+fn model_json(deck_id: i64, now: i64) -> serde_json::Value {
+    let field = |name: &str, ord: u32| {
+        serde_json::json!({
+            "name": name, "ord": ord, "sticky": false, "rtl": false,
+            "font": "Arial", "size": 20, "media": []
+        })
+    };
+    serde_json::json!({
+        "id": MODEL_ID,
+        "name": "Bookers",
+        "type": 0,
+        "mod": now,
+        "usn": -1,
+        "sortf": 0,
+        "did": deck_id,
+        "tmpls": [{
+            "name": "Card 1",
+            "ord": 0,
+            "qfmt": "{{Front}}",
+            "afmt": "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}",
+            "bqfmt": "",
+            "bafmt": "",
+            "did": null
+        }],
+        "flds": [field("Front", 0), field("Back", 1)],
+        "css": CARD_CSS,
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "latexsvg": false,
+        "req": [[0, "any", [0]]],
+        "tags": [],
+        "vers": []
+    })
+}
+
+fn deck_json(id: i64, name: &str, now: i64) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "name": name,
+        "mod": now,
+        "usn": -1,
+        "desc": "",
+        "dyn": 0,
+        "conf": 1,
+        "collapsed": false,
+        "browserCollapsed": false,
+        "extendNew": 10,
+        "extendRev": 50,
+        "newToday": [0, 0],
+        "revToday": [0, 0],
+        "lrnToday": [0, 0],
+        "timeToday": [0, 0]
+    })
+}
+
+fn deck_conf_json(now: i64) -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "name": "Default",
+        "mod": now,
+        "usn": -1,
+        "maxTaken": 60,
+        "autoplay": true,
+        "timer": 0,
+        "replayq": true,
+        "dyn": false,
+        "new": {
+            "delays": [1, 10], "ints": [1, 4, 7], "initialFactor": 2500,
+            "order": 1, "perDay": 20, "bury": true, "separate": true
+        },
+        "rev": {
+            "perDay": 200, "ease4": 1.3, "fuzz": 0.05, "ivlFct": 1,
+            "maxIvl": 36500, "bury": true, "minSpace": 1
+        },
+        "lapse": {
+            "delays": [10], "mult": 0, "minInt": 1, "leechFails": 8, "leechAction": 0
+        }
+    })
+}
+
+fn collection_conf(deck_id: i64) -> serde_json::Value {
+    serde_json::json!({
+        "activeDecks": [deck_id],
+        "curDeck": deck_id,
+        "newSpread": 0,
+        "collapseTime": 1200,
+        "timeLim": 0,
+        "estTimes": true,
+        "dueCounts": true,
+        "curModel": MODEL_ID.to_string(),
+        "nextPos": 1,
+        "sortType": "noteFld",
+        "sortBackwards": false,
+        "addToCur": true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::anki_import::read_apkg;
+
+    #[test]
+    fn writes_math_for_mathjax() {
+        let mut no_images = |_: &str| None;
+        assert_eq!(
+            card_html("**Задача 1.** Докажите, что $a < b$:\n$$a^2 < b^2$$", &mut no_images),
+            "<b>Задача 1.</b> Докажите, что \\(a &lt; b\\):<br>\n\\[a^2 &lt; b^2\\]"
+        );
+        let mut media = |url: &str| (url == "/formula_image/p.png").then(|| "formula-1.png".to_string());
+        assert_eq!(card_html("См. ![formula](/formula_image/p.png)", &mut media), "См. <img src=\"formula-1.png\" alt=\"formula\">");
+    }
+
+    #[tokio::test]
+    async fn package_reads_back() {
+        let note = |key: &str, deck: &str, front: &str| AnkiNote {
+            key: key.to_string(),
+            deck: deck.to_string(),
+            front: front.to_string(),
+            back: "\\(x = 4\\)".to_string(),
+            tags: vec!["algebra_7::chapter_1".to_string()],
+        };
+        let notes = vec![note("b:1:1", "Алгебра::Глава 1", "Решите \\(2x = 8\\)"), note("b:2:1", "Алгебра::Глава 2", "Задача 2")];
+        let media = vec![AnkiMedia { name: "formula-1.png".to_string(), data: vec![1, 2, 3] }];
+        let package = build_apkg(&notes, &media).await.unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(package.as_slice())).unwrap();
+        let mut map = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("media").unwrap(), &mut map).unwrap();
+        assert_eq!(map, r#"{"0":"formula-1.png"}"#);
+        assert_eq!(archive.by_name("0").unwrap().size(), 3);
+
+        let cards = read_apkg(&package).await.unwrap();
+        assert_eq!(cards.len(), 2);
+        assert_eq!((cards[0].deck.as_str(), cards[0].front.as_str()), ("Алгебра::Глава 1", "Решите $2x = 8$"));
+        assert_eq!(cards[1].deck, "Алгебра::Глава 2");
+        assert_eq!(cards[0].tags, vec!["algebra_7::chapter_1"]);
+    }
+}
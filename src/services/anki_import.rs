@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use lazy_regex::regex;
+use serde::Serialize;
+use sqlx::Row;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use crate::models::{Book, Chapter, Problem, Solution};
+use crate::services::database::Database;
+use crate::services::markdown_sanitizer::sanitize_markdown;
+
+/// Anki field separator inside `notes.flds`
+pub(crate) const FIELD_SEPARATOR: char = '\u{1f}';
+
+/// Provider name stored on solutions created from card backs
+pub const ANKI_PROVIDER: &str = "anki";
+/// Subject of books created by an import
+const ANKI_SUBJECT: &str = "Anki";
+
+/// Single note from an Anki collection, already converted to plain markdown
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnkiCard {
+    pub deck: String,
+    pub front: String,
+    pub back: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnkiImportSummary {
+    pub book_id: String,
+    pub chapters: Vec<AnkiChapterSummary>,
+    pub problems_imported: usize,
+    pub solutions_imported: usize,
+    pub skipped_empty: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnkiChapterSummary {
+    pub chapter_id: String,
+    pub deck: String,
+    pub cards: usize,
+}
+
+/// Read notes from an `.apkg` package (a zip with a SQLite collection inside).
+///
+/// Only the legacy `collection.anki2`/`collection.anki21` databases are
+/// supported; packages exported with "support older Anki versions" unchecked
+/// only contain a zstd-compressed `collection.anki21b`.
+pub async fn read_apkg(bytes: &[u8]) -> anyhow::Result<Vec<AnkiCard>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+
+    let entry_name = ["collection.anki21", "collection.anki2"]
+        .into_iter()
+        .find(|name| archive.by_name(name).is_ok());
+    let Some(entry_name) = entry_name else {
+        if archive.by_name("collection.anki21b").is_ok() {
+            anyhow::bail!("This .apkg uses the new compressed format; re-export it with \"Support older Anki versions\" enabled");
+        }
+        anyhow::bail!("Not an Anki package: collection database not found");
+    };
+
+    let mut collection = Vec::new();
+    archive.by_name(entry_name)?.read_to_end(&mut collection)?;
+
+    // sqlx needs a file on disk to open the collection
+    let path = std::env::temp_dir().join(format!("bookers-anki-{}.db", uuid::Uuid::new_v4()));
+    std::fs::write(&path, &collection)?;
+    let result = read_collection(&path).await;
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+async fn read_collection(path: &std::path::Path) -> anyhow::Result<Vec<AnkiCard>> {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let pool = SqlitePoolOptions::new().max_connections(1).connect_with(options).await?;
+
+    let decks_json: String = sqlx::query("SELECT decks FROM col LIMIT 1")
+        .fetch_one(&pool)
+        .await?
+        .try_get("decks")?;
+    let decks: HashMap<String, String> = serde_json::from_str::<HashMap<String, serde_json::Value>>(&decks_json)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(id, deck)| deck.get("name").and_then(|n| n.as_str()).map(|n| (id, n.to_string())))
+        .collect();
+
+    let rows = sqlx::query(
+        r#"
+        SELECT n.flds AS flds, n.tags AS tags, MIN(c.did) AS did
+        FROM notes n LEFT JOIN cards c ON c.nid = n.id
+        GROUP BY n.id
+        ORDER BY n.id
+        "#,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let mut cards = Vec::new();
+    for row in rows {
+        let fields: String = row.try_get("flds")?;
+        let tags: String = row.try_get("tags")?;
+        let deck_id: Option<i64> = row.try_get("did")?;
+
+        let mut parts = fields.split(FIELD_SEPARATOR);
+        let front = parts.next().unwrap_or_default();
+        let back = parts.next().unwrap_or_default();
+
+        cards.push(AnkiCard {
+            deck: deck_id
+                .and_then(|id| decks.get(&id.to_string()).cloned())
+                .unwrap_or_else(|| "Default".to_string()),
+            front: html_to_markdown(front),
+            back: html_to_markdown(back),
+            tags: tags.split_whitespace().map(str::to_string).collect(),
+        });
+    }
+
+    pool.close().await;
+    Ok(cards)
+}
+
+/// Parse a "Notes in Plain Text" export.
+///
+/// Honours the `#separator:`, `#html:`, `#deck column:` and `#tags column:`
+/// header lines written by Anki 2.1.55+; older exports are tab-separated
+/// `front<TAB>back` lines.
+pub fn parse_anki_text(text: &str, default_deck: &str) -> Vec<AnkiCard> {
+    let mut separator = '\t';
+    let mut html = true;
+    let mut deck_column: Option<usize> = None;
+    let mut tags_column: Option<usize> = None;
+    let mut cards = Vec::new();
+
+    for line in text.lines() {
+        if let Some(header) = line.strip_prefix('#') {
+            if let Some((key, value)) = header.split_once(':') {
+                let value = value.trim();
+                match key.trim() {
+                    "separator" => {
+                        separator = match value.to_lowercase().as_str() {
+                            "tab" => '\t',
+                            "comma" => ',',
+                            "semicolon" => ';',
+                            "space" => ' ',
+                            "pipe" => '|',
+                            "colon" => ':',
+                            other => other.chars().next().unwrap_or('\t'),
+                        }
+                    }
+                    "html" => html = value.eq_ignore_ascii_case("true"),
+                    "deck column" => deck_column = value.parse::<usize>().ok().and_then(|c| c.checked_sub(1)),
+                    "tags column" => tags_column = value.parse::<usize>().ok().and_then(|c| c.checked_sub(1)),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let columns: Vec<&str> = line.split(separator).map(unquote).collect();
+        let content: Vec<&str> = columns
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != deck_column && Some(*i) != tags_column)
+            .map(|(_, c)| *c)
+            .collect();
+
+        let convert = |s: &str| if html { html_to_markdown(s) } else { s.trim().to_string() };
+
+        cards.push(AnkiCard {
+            deck: deck_column
+                .and_then(|c| columns.get(c))
+                .filter(|d| !d.is_empty())
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| default_deck.to_string()),
+            front: convert(content.first().copied().unwrap_or_default()),
+            back: convert(content.get(1).copied().unwrap_or_default()),
+            tags: tags_column
+                .and_then(|c| columns.get(c))
+                .map(|t| t.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+        });
+    }
+
+    cards
+}
+
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+/// Convert card HTML to the markdown + `$...$` math used for problems
+pub fn html_to_markdown(html: &str) -> String {
+    let text = regex!(r"(?i)<br\s*/?>|</div>|</p>|</li>").replace_all(html, "\n");
+    let text = regex!(r"(?s)<[^>]*>").replace_all(&text, "");
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    // Anki's MathJax delimiters
+    let text = regex!(r"(?s)\\\[(.+?)\\\]").replace_all(&text, "$$$$$1$$$$");
+    let text = regex!(r"(?s)\\\((.+?)\\\)").replace_all(&text, "$$$1$$");
+    // Cloze deletions keep their answer text
+    let text = regex!(r"\{\{c\d+::(.*?)(?:::[^}]*)?\}\}").replace_all(&text, "$1");
+
+    text.lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Whether a book was created by an Anki import, so decks may be imported
+/// into it again. Chapters of other books hold parsed textbook content that
+/// an import would overwrite.
+pub fn is_anki_book(book: &Book) -> bool {
+    book.subject.as_deref() == Some(ANKI_SUBJECT) && book.file_path.is_empty()
+}
+
+/// Store cards as problems: one chapter per deck, card backs as solutions.
+/// Re-importing into an Anki book updates its cards; other books are refused.
+pub async fn import_cards(
+    db: &Database,
+    book_id: &str,
+    title: &str,
+    cards: &[AnkiCard],
+) -> anyhow::Result<AnkiImportSummary> {
+    if let Some(book) = db.get_book(book_id).await? {
+        if !is_anki_book(&book) {
+            anyhow::bail!("Book {} is not an Anki import; import decks into a new book", book_id);
+        }
+    } else {
+        db.create_book(&Book {
+            id: book_id.to_string(),
+            title: title.to_string(),
+            author: None,
+            subject: Some(ANKI_SUBJECT.to_string()),
+            file_path: String::new(),
+            total_pages: 0,
+            created_at: chrono::Utc::now(),
+        })
+        .await?;
+    }
+
+    // Decks become chapters in first-seen order
+    let mut decks: Vec<(&str, Vec<&AnkiCard>)> = Vec::new();
+    let mut skipped_empty = 0;
+    for card in cards {
+        if card.front.is_empty() {
+            skipped_empty += 1;
+            continue;
+        }
+        match decks.iter_mut().find(|(deck, _)| *deck == card.deck) {
+            Some((_, deck_cards)) => deck_cards.push(card),
+            None => decks.push((&card.deck, vec![card])),
+        }
+    }
+
+    let mut chapters = Vec::new();
+    let mut problems_imported = 0;
+    let mut solutions_imported = 0;
+
+    for (idx, (deck, deck_cards)) in decks.iter().enumerate() {
+        let chapter_num = idx as u32 + 1;
+        let chapter_id = format!("{}:{}", book_id, chapter_num);
+        db.create_chapter(&Chapter {
+            id: chapter_id.clone(),
+            book_id: book_id.to_string(),
+            number: chapter_num,
+            title: deck.replace("::", " / "),
+            description: None,
+            problem_count: deck_cards.len() as u32,
+            theory_count: 0,
+            created_at: chrono::Utc::now(),
+        })
+        .await?;
+
+        for (card_idx, card) in deck_cards.iter().enumerate() {
+            let number = (card_idx + 1).to_string();
+            let mut problem = Problem {
+                id: Problem::generate_id(book_id, chapter_num, &number),
+                chapter_id: chapter_id.clone(),
+                display_name: format!("Card {}", number),
+                number,
+                content: card.front.clone(),
+                ..Default::default()
+            };
+            problem.latex_formulas = problem.extract_formulas();
+            db.create_problem(&problem).await?;
+            problems_imported += 1;
+
+            if !card.back.is_empty() {
+                let now = chrono::Utc::now();
+                let solution = Solution {
+                    id: Solution::generate_id(&problem.id),
+                    problem_id: problem.id.clone(),
+                    provider: ANKI_PROVIDER.to_string(),
+                    content: sanitize_markdown(&card.back),
+                    latex_formulas: Vec::new(),
+                    is_verified: false,
+                    rating: None,
+                    generation: None,
+                    prompt_hash: None,
+                    version: 1,
+                    created_at: now,
+                    updated_at: now,
+                };
+                db.save_solution(&solution).await?;
+                db.update_problem_solution_status(&problem.id, true).await?;
+                solutions_imported += 1;
+            }
+        }
+
+        chapters.push(AnkiChapterSummary {
+            chapter_id,
+            deck: deck.to_string(),
+            cards: deck_cards.len(),
+        });
+    }
+
+    Ok(AnkiImportSummary {
+        book_id: book_id.to_string(),
+        chapters,
+        problems_imported,
+        solutions_imported,
+        skipped_empty,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_card_html() {
+        let html = "Solve \\(x^2 = 4\\)<br>for&nbsp;<b>x</b>";
+        assert_eq!(html_to_markdown(html), "Solve $x^2 = 4$\nfor x");
+        assert_eq!(html_to_markdown("\\[a+b\\]"), "$$a+b$$");
+        assert_eq!(html_to_markdown("{{c1::Пифагор::кто?}} доказал"), "Пифагор доказал");
+    }
+
+    #[test]
+    fn parses_plain_text_export_with_headers() {
+        let text = "#separator:tab\n#html:true\n#deck column:3\n\
+                    2+2?\t4\tMath::Arithmetic\n\
+                    \"Корень из 9\"\t3\t\n";
+        let cards = parse_anki_text(text, "Imported");
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].deck, "Math::Arithmetic");
+        assert_eq!(cards[0].back, "4");
+        assert_eq!(cards[1].deck, "Imported");
+        assert_eq!(cards[1].front, "Корень из 9");
+    }
+
+    #[tokio::test]
+    async fn reads_apkg_collection() {
+        let path = std::env::temp_dir().join(format!("anki-test-{}.db", uuid::Uuid::new_v4()));
+        let options = SqliteConnectOptions::new().filename(&path).create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await.unwrap();
+        for sql in [
+            "CREATE TABLE col (decks TEXT)",
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, flds TEXT, tags TEXT)",
+            "CREATE TABLE cards (id INTEGER PRIMARY KEY, nid INTEGER, did INTEGER)",
+            r#"INSERT INTO col VALUES ('{"1": {"name": "Default"}, "42": {"name": "Алгебра"}}')"#,
+            "INSERT INTO notes VALUES (1, 'Front' || char(31) || 'Back', ' tag1 tag2 ')",
+            "INSERT INTO cards VALUES (10, 1, 42)",
+        ] {
+            sqlx::query(sql).execute(&pool).await.unwrap();
+        }
+        pool.close().await;
+
+        let mut buf = std::io::Cursor::new(Vec::new());
+        {
+            let mut zip = zip::ZipWriter::new(&mut buf);
+            zip.start_file("collection.anki2", zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zip, &std::fs::read(&path).unwrap()).unwrap();
+            zip.finish().unwrap();
+        }
+        let _ = std::fs::remove_file(&path);
+
+        let cards = read_apkg(buf.get_ref()).await.unwrap();
+        assert_eq!(
+            cards,
+            vec![AnkiCard {
+                deck: "Алгебра".to_string(),
+                front: "Front".to_string(),
+                back: "Back".to_string(),
+                tags: vec!["tag1".to_string(), "tag2".to_string()],
+            }]
+        );
+    }
+}
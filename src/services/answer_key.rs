@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use lazy_regex::regex;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Problem;
+use crate::services::symbolic;
+
+/// One entry of a book's answers section, e.g. `566. x=4`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnswerEntry {
+    pub number: String,
+    pub answer: String,
+}
+
+/// An answer matched to the problem it belongs to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LinkedAnswer {
+    pub number: String,
+    pub problem_id: String,
+    pub answer: String,
+}
+
+/// Outcome of matching parsed answers against a book's problems
+#[derive(Debug, Default, Serialize)]
+pub struct AnswerMatches {
+    pub matched: Vec<LinkedAnswer>,
+    /// Answer numbers with no problem of that number
+    pub unmatched: Vec<String>,
+    /// Answer numbers shared by several problems (numbering restarts per chapter)
+    pub ambiguous: Vec<String>,
+}
+
+/// Leading integer of a problem number ("12.3" -> 12), used to keep entries in order
+fn major(number: &str) -> u32 {
+    number.split('.').next().and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+/// Parse answers-section text. Entries start with a problem number followed by
+/// a dot and a space (`566. `, `1.15. `) and run until the next entry, so
+/// several answers on one line and answers wrapped over lines both work.
+/// Numbers must not go backwards, which filters out list items and decimals
+/// inside the answers themselves.
+pub fn parse_answer_key(text: &str) -> Vec<AnswerEntry> {
+    let marker = regex!(r"(?m)(?:^|[\s;])(\d{1,4}(?:\.\d{1,3})*)\.");
+
+    let mut starts: Vec<(usize, usize, String)> = Vec::new();
+    let mut last = 0;
+    for caps in marker.captures_iter(text) {
+        let (Some(whole), Some(number)) = (caps.get(0), caps.get(1)) else {
+            continue;
+        };
+        let followed_by_space = text[whole.end()..].chars().next().is_none_or(char::is_whitespace);
+        let n = major(number.as_str());
+        if !followed_by_space || n == 0 || n < last {
+            continue;
+        }
+        last = n;
+        starts.push((number.start(), whole.end(), number.as_str().to_string()));
+    }
+
+    let mut entries: Vec<AnswerEntry> = Vec::new();
+    for (i, (_, body_start, number)) in starts.iter().enumerate() {
+        let body_end = starts.get(i + 1).map(|(s, _, _)| *s).unwrap_or(text.len());
+        let answer = text[*body_start..body_end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let answer = answer.trim_end_matches([';', ',', ' ']).trim().to_string();
+        if answer.is_empty() {
+            continue;
+        }
+        // A repeated number continues the same answer (e.g. split by a page break)
+        match entries.last_mut() {
+            Some(prev) if prev.number == *number => {
+                prev.answer.push(' ');
+                prev.answer.push_str(&answer);
+            }
+            _ => entries.push(AnswerEntry { number: number.clone(), answer }),
+        }
+    }
+    entries
+}
+
+/// Match answers to top-level problems by number
+pub fn match_answers(problems: &[Problem], entries: &[AnswerEntry]) -> AnswerMatches {
+    let mut by_number: HashMap<String, Vec<&Problem>> = HashMap::new();
+    for problem in problems.iter().filter(|p| p.parent_id.is_none()) {
+        by_number.entry(Problem::normalize_number(&problem.number)).or_default().push(problem);
+    }
+
+    let mut result = AnswerMatches::default();
+    for entry in entries {
+        match by_number.get(&Problem::normalize_number(&entry.number)).map(Vec::as_slice) {
+            Some([problem]) => result.matched.push(LinkedAnswer {
+                number: entry.number.clone(),
+                problem_id: problem.id.clone(),
+                answer: entry.answer.clone(),
+            }),
+            Some([]) | None => result.unmatched.push(entry.number.clone()),
+            Some(_) => result.ambiguous.push(entry.number.clone()),
+        }
+    }
+    result
+}
+
+/// How an AI solution's final answer compares to the book's answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerVerdict {
+    Match,
+    Mismatch,
+    /// The solution has no recognisable final answer
+    Unknown,
+}
+
+impl AnswerVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnswerVerdict::Match => "match",
+            AnswerVerdict::Mismatch => "mismatch",
+            AnswerVerdict::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "match" => AnswerVerdict::Match,
+            "mismatch" => AnswerVerdict::Mismatch,
+            _ => AnswerVerdict::Unknown,
+        }
+    }
+}
+
+/// Final answer of a solution: the text after the last "Ответ"/"Answer" label,
+/// or the last non-empty line
+pub fn final_answer(solution: &str) -> Option<String> {
+    let label = regex!(r"(?i)(?:ответ|answer)\s*[:.]?\s*");
+    let tail = match label.find_iter(solution).last() {
+        Some(m) => solution[m.end()..].lines().next().unwrap_or(""),
+        None => solution.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or(""),
+    };
+    let tail = tail.trim().trim_matches(['*', '.']).trim();
+    (!tail.is_empty()).then(|| tail.to_string())
+}
+
+/// Comparable form of an answer: lowercase, no LaTeX delimiters/spacing
+/// commands or whitespace, decimal commas as dots
+fn normalize(answer: &str) -> String {
+    answer
+        .to_lowercase()
+        .replace("\\left", "")
+        .replace("\\right", "")
+        .replace("\\,", "")
+        .replace("\\;", "")
+        .replace("\\dfrac", "\\frac")
+        .replace("\\cdot", "*")
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '$' | '{' | '}'))
+        .map(|c| if c == ',' { '.' } else { c })
+        .collect::<String>()
+        .trim_end_matches(['.', ';'])
+        .to_string()
+}
+
+/// Result of comparing a solution's final answer with the book's answer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnswerCheck {
+    pub final_answer: Option<String>,
+    pub verdict: AnswerVerdict,
+    /// The verdict came from [`symbolic::equivalent`] rather than the text
+    pub symbolic: bool,
+}
+
+/// Compare a solution against the book's answer after normalisation. A bare
+/// value also matches an assignment of it, so `x = 4` agrees with `4`.
+/// Answers that differ as text are compared as expressions, so `(x-2)(x+2)`
+/// agrees with `x^2 - 4`.
+pub fn check_answer(reference: &str, solution: &str) -> AnswerCheck {
+    let Some(answer) = final_answer(solution) else {
+        return AnswerCheck { final_answer: None, verdict: AnswerVerdict::Unknown, symbolic: false };
+    };
+    let (expected, got) = (normalize(reference), normalize(&answer));
+    let (verdict, symbolic) = if expected.is_empty() || got.is_empty() {
+        (AnswerVerdict::Unknown, false)
+    } else if got == expected
+        || got.ends_with(&format!("={}", expected))
+        || expected.ends_with(&format!("={}", got))
+    {
+        (AnswerVerdict::Match, false)
+    } else {
+        match symbolic::equivalent(reference, &answer) {
+            Some(true) => (AnswerVerdict::Match, true),
+            Some(false) => (AnswerVerdict::Mismatch, true),
+            None => (AnswerVerdict::Mismatch, false),
+        }
+    };
+    AnswerCheck { final_answer: Some(answer), verdict, symbolic }
+}
+
+/// [`check_answer`] as the final answer and verdict
+pub fn check_solution(reference: &str, solution: &str) -> (Option<String>, AnswerVerdict) {
+    let check = check_answer(reference, solution);
+    (check.final_answer, check.verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inline_and_wrapped_entries() {
+        let text = "Ответы\n566. x=4. 567. 12; 568. а) 3;\nб) 0,5.\n569. 1.5. 570. нет решений";
+        let entries = parse_answer_key(text);
+        let numbers: Vec<&str> = entries.iter().map(|e| e.number.as_str()).collect();
+        assert_eq!(numbers, vec!["566", "567", "568", "569", "570"]);
+        assert_eq!(entries[0].answer, "x=4.");
+        assert_eq!(entries[2].answer, "а) 3; б) 0,5.");
+        assert_eq!(entries[3].answer, "1.5.");
+    }
+
+    #[test]
+    fn matches_by_number_and_reports_ambiguity() {
+        let problem = |id: &str, number: &str| Problem {
+            id: id.to_string(),
+            number: number.to_string(),
+            ..Default::default()
+        };
+        let problems = vec![problem("a", "1"), problem("b", "2"), problem("c", "2")];
+        let entries = parse_answer_key("1. 5 2. 7 3. 9");
+        let result = match_answers(&problems, &entries);
+        let matched: Vec<(&str, &str)> = result.matched.iter().map(|m| (m.problem_id.as_str(), m.answer.as_str())).collect();
+        assert_eq!(matched, vec![("a", "5")]);
+        assert_eq!(result.ambiguous, vec!["2"]);
+        assert_eq!(result.unmatched, vec!["3"]);
+    }
+
+    #[test]
+    fn checks_final_answer() {
+        let solution = "Перенесём 4 вправо: $2x = 8$.\n\n**Ответ:** $x = 4$";
+        assert_eq!(check_solution("4", solution).1, AnswerVerdict::Match);
+        assert_eq!(check_solution("x = 5", solution).1, AnswerVerdict::Mismatch);
+        assert_eq!(check_solution("4", "Ответ: x = 14").1, AnswerVerdict::Mismatch);
+        assert_eq!(check_solution("0,5", "Ответ: 0.5").1, AnswerVerdict::Match);
+        assert_eq!(check_solution("4", "").1, AnswerVerdict::Unknown);
+
+        let factored = check_answer("x^2 - 4", "Ответ: $(x-2)(x+2)$");
+        assert_eq!((factored.verdict, factored.symbolic), (AnswerVerdict::Match, true));
+        assert_eq!(check_solution("x^2 - 4", "Ответ: $(x-2)^2$").1, AnswerVerdict::Mismatch);
+    }
+}
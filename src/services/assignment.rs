@@ -0,0 +1,281 @@
+//! Assignments: problems a teacher hands out with a due date behind a share
+//! link, and the answers students submit through it. There are no accounts,
+//! so the share token is what lets a student open and answer an assignment.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Problem;
+use crate::services::answer_key::{check_solution, AnswerVerdict};
+
+/// Longest accepted student name, in characters
+const MAX_STUDENT_NAME: usize = 80;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Assignment {
+    pub id: String,
+    pub title: String,
+    pub instructions: Option<String>,
+    /// Secret part of the student link `/a/{share_token}`
+    pub share_token: String,
+    /// Problems in the order they are handed out
+    pub problem_ids: Vec<String>,
+    pub due_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Assignment {
+    pub fn new(title: &str, instructions: Option<String>, problem_ids: Vec<String>, due_at: DateTime<Utc>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            instructions,
+            share_token: uuid::Uuid::new_v4().simple().to_string(),
+            problem_ids,
+            due_at,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Page students open to answer the assignment
+    pub fn share_path(&self) -> String {
+        format!("/a/{}", self.share_token)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionSource {
+    Typed,
+    /// Photo of a handwritten answer, read by OCR
+    Photo,
+}
+
+impl SubmissionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubmissionSource::Typed => "typed",
+            SubmissionSource::Photo => "photo",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "photo" => SubmissionSource::Photo,
+            _ => SubmissionSource::Typed,
+        }
+    }
+}
+
+/// One student's answer to one problem of an assignment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: String,
+    pub assignment_id: String,
+    pub problem_id: String,
+    pub student_name: String,
+    pub source: SubmissionSource,
+    /// Typed answer or the OCR text of the photo; `None` when OCR failed
+    pub answer: Option<String>,
+    /// Storage key of the uploaded photo
+    pub photo_key: Option<String>,
+    /// Against the problem's extracted answer; `unknown` without one
+    pub verdict: AnswerVerdict,
+    /// Submitted after the due date
+    pub late: bool,
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// Due date from RFC 3339 or a bare `YYYY-MM-DD`, which means the end of that day (UTC)
+pub fn parse_due_at(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Some(day.and_hms_opt(23, 59, 59)?.and_utc())
+}
+
+/// Student name with surrounding and repeated whitespace removed; `None`
+/// when it is empty or too long
+pub fn normalize_student_name(name: &str) -> Option<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!name.is_empty() && name.chars().count() <= MAX_STUDENT_NAME).then_some(name)
+}
+
+/// Check a student's answer against the problem's extracted answer
+pub fn grade_answer(problem: &Problem, answer: &str) -> AnswerVerdict {
+    match problem.reference_answer.as_deref() {
+        Some(reference) if !reference.trim().is_empty() => check_solution(reference, answer).1,
+        _ => AnswerVerdict::Unknown,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProblemResult {
+    pub problem_id: String,
+    pub number: String,
+    /// Students who answered it
+    pub answered: usize,
+    pub correct: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StudentResult {
+    pub student_name: String,
+    /// Latest answer per problem, in assignment order
+    pub answers: Vec<Submission>,
+    pub correct: usize,
+    /// Any of the answers came in after the due date
+    pub late: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssignmentResults {
+    pub assignment_id: String,
+    pub title: String,
+    pub due_at: DateTime<Utc>,
+    pub problems: Vec<ProblemResult>,
+    /// By student name
+    pub students: Vec<StudentResult>,
+}
+
+/// Each student's latest answer to each problem, keyed by the lowercased
+/// student name and the problem id; earlier answers don't count
+pub fn latest_answers(submissions: &[Submission]) -> HashMap<(String, &str), &Submission> {
+    let mut latest: HashMap<(String, &str), &Submission> = HashMap::new();
+    for submission in submissions {
+        let key = (submission.student_name.to_lowercase(), submission.problem_id.as_str());
+        match latest.get(&key) {
+            Some(seen) if seen.submitted_at > submission.submitted_at => {}
+            _ => {
+                latest.insert(key, submission);
+            }
+        }
+    }
+    latest
+}
+
+/// Per-problem and per-student results. Only a student's latest answer to a
+/// problem counts; names compare without case.
+pub fn assignment_results(assignment: &Assignment, problems: &[Problem], submissions: &[Submission]) -> AssignmentResults {
+    let position: HashMap<&str, usize> =
+        assignment.problem_ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+
+    let mut latest = latest_answers(submissions);
+    latest.retain(|(_, problem_id), _| position.contains_key(problem_id));
+
+    let mut students: HashMap<String, StudentResult> = HashMap::new();
+    for ((key, _), submission) in &latest {
+        let student = students.entry(key.clone()).or_insert_with(|| StudentResult {
+            student_name: submission.student_name.clone(),
+            answers: Vec::new(),
+            correct: 0,
+            late: false,
+        });
+        student.answers.push((*submission).clone());
+        student.correct += usize::from(submission.verdict == AnswerVerdict::Match);
+        student.late |= submission.late;
+    }
+    let mut students: Vec<StudentResult> = students.into_values().collect();
+    for student in &mut students {
+        student.answers.sort_by_key(|s| position[s.problem_id.as_str()]);
+    }
+    students.sort_by_key(|s| s.student_name.to_lowercase());
+
+    let numbers: HashMap<&str, &str> = problems.iter().map(|p| (p.id.as_str(), p.number.as_str())).collect();
+    let problems = assignment
+        .problem_ids
+        .iter()
+        .map(|id| {
+            let answers: Vec<&&Submission> = latest.iter().filter(|((_, p), _)| *p == id.as_str()).map(|(_, s)| s).collect();
+            ProblemResult {
+                problem_id: id.clone(),
+                number: numbers.get(id.as_str()).map(|n| n.to_string()).unwrap_or_default(),
+                answered: answers.len(),
+                correct: answers.iter().filter(|s| s.verdict == AnswerVerdict::Match).count(),
+            }
+        })
+        .collect();
+
+    AssignmentResults {
+        assignment_id: assignment.id.clone(),
+        title: assignment.title.clone(),
+        due_at: assignment.due_at,
+        problems,
+        students,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(id: &str, answer: Option<&str>) -> Problem {
+        Problem {
+            id: id.to_string(),
+            number: id.rsplit(':').next().unwrap().to_string(),
+            reference_answer: answer.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn submission(student: &str, problem_id: &str, verdict: AnswerVerdict, minute: i64) -> Submission {
+        Submission {
+            id: uuid::Uuid::new_v4().to_string(),
+            assignment_id: "a".to_string(),
+            problem_id: problem_id.to_string(),
+            student_name: student.to_string(),
+            source: SubmissionSource::Typed,
+            answer: Some("4".to_string()),
+            photo_key: None,
+            verdict,
+            late: minute > 60,
+            submitted_at: DateTime::from_timestamp(minute * 60, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_due_dates_and_grades_answers() {
+        assert_eq!(parse_due_at("2026-05-20").unwrap().to_rfc3339(), "2026-05-20T23:59:59+00:00");
+        assert_eq!(parse_due_at("2026-05-20T10:00:00+03:00").unwrap().to_rfc3339(), "2026-05-20T07:00:00+00:00");
+        assert!(parse_due_at("next week").is_none());
+        assert_eq!(normalize_student_name("  Иванов   Пётр ").as_deref(), Some("Иванов Пётр"));
+        assert!(normalize_student_name("   ").is_none());
+
+        assert_eq!(grade_answer(&problem("b:1:1", Some("x=4")), "x = 4"), AnswerVerdict::Match);
+        assert_eq!(grade_answer(&problem("b:1:1", Some("4")), "5"), AnswerVerdict::Mismatch);
+        assert_eq!(grade_answer(&problem("b:1:1", None), "4"), AnswerVerdict::Unknown);
+    }
+
+    #[test]
+    fn results_count_latest_answers() {
+        let problems = vec![problem("b:1:1", Some("4")), problem("b:1:2", Some("5"))];
+        let assignment = Assignment::new(
+            "Домашняя работа",
+            None,
+            vec!["b:1:2".to_string(), "b:1:1".to_string()],
+            DateTime::from_timestamp(3600, 0).unwrap(),
+        );
+        let submissions = vec![
+            submission("Аня", "b:1:1", AnswerVerdict::Mismatch, 10),
+            submission("аня", "b:1:1", AnswerVerdict::Match, 20),
+            submission("Аня", "b:1:2", AnswerVerdict::Match, 90),
+            submission("Борис", "b:1:1", AnswerVerdict::Mismatch, 30),
+            submission("Борис", "b:9:9", AnswerVerdict::Match, 30),
+        ];
+
+        let results = assignment_results(&assignment, &problems, &submissions);
+        let summary: Vec<(&str, usize, usize)> =
+            results.problems.iter().map(|p| (p.number.as_str(), p.answered, p.correct)).collect();
+        assert_eq!(summary, [("2", 1, 1), ("1", 2, 1)]);
+
+        let anya = &results.students[0];
+        assert_eq!((anya.correct, anya.late, anya.answers.len()), (2, true, 2));
+        assert_eq!(anya.answers[0].problem_id, "b:1:2");
+        let boris = &results.students[1];
+        assert_eq!((boris.student_name.as_str(), boris.correct, boris.late), ("Борис", 0, false));
+    }
+}
@@ -0,0 +1,295 @@
+//! Files attached to solutions: uploaded images and generated plots. Rows
+//! live in `solution_attachments`; the bytes in a local directory or an S3
+//! bucket, chosen by `ATTACHMENT_STORAGE`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Config, S3Config};
+use crate::models::{Problem, SolutionAttachment};
+use crate::services::database::Database;
+use crate::services::http_client::HttpClientFactory;
+use crate::services::plot::{self, PlotSpec};
+
+/// Largest accepted upload
+pub const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+
+const S3_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// File extension for an accepted content type; other types are refused
+pub fn extension_for(content_type: &str) -> Option<&'static str> {
+    Some(match content_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "application/pdf" => "pdf",
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum AttachmentStorage {
+    Local { dir: PathBuf },
+    S3(S3Config),
+}
+
+impl AttachmentStorage {
+    pub fn from_config(config: &Config) -> Self {
+        match &config.attachment_s3 {
+            Some(s3) => AttachmentStorage::S3(s3.clone()),
+            None => AttachmentStorage::Local { dir: config.attachments_dir.clone() },
+        }
+    }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()> {
+        match self {
+            AttachmentStorage::Local { dir } => {
+                tokio::fs::create_dir_all(dir).await?;
+                tokio::fs::write(dir.join(key), bytes).await?;
+                Ok(())
+            }
+            AttachmentStorage::S3(s3) => {
+                let response = s3_request(s3, reqwest::Method::PUT, key, bytes, Some(content_type)).await?;
+                if !response.status().is_success() {
+                    bail!("S3 upload of {} failed: {}", key, response.status());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Store a file written elsewhere, moving it when storage is local and
+    /// streaming it to S3. `sha256` is the hex digest of its content.
+    pub async fn put_file(&self, key: &str, path: &Path, content_type: &str, sha256: &str) -> Result<()> {
+        match self {
+            AttachmentStorage::Local { dir } => {
+                tokio::fs::create_dir_all(dir).await?;
+                // A rename fails across filesystems, e.g. from a tmpfs /tmp
+                if tokio::fs::rename(path, dir.join(key)).await.is_err() {
+                    tokio::fs::copy(path, dir.join(key)).await?;
+                }
+                Ok(())
+            }
+            AttachmentStorage::S3(s3) => {
+                let file = tokio::fs::File::open(path).await?;
+                let size = file.metadata().await?.len();
+                let response = s3_signed_request(s3, reqwest::Method::PUT, key, sha256)?
+                    .header("Content-Type", content_type)
+                    .header("Content-Length", size)
+                    .body(reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file)))
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    bail!("S3 upload of {} failed: {}", key, response.status());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            AttachmentStorage::Local { dir } => match tokio::fs::remove_file(dir.join(key)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            },
+            AttachmentStorage::S3(s3) => {
+                let response = s3_request(s3, reqwest::Method::DELETE, key, Vec::new(), None).await?;
+                if !response.status().is_success() {
+                    bail!("S3 delete of {} failed: {}", key, response.status());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            AttachmentStorage::Local { dir } => {
+                tokio::fs::read(dir.join(key)).await.with_context(|| format!("Attachment {} is missing", key))
+            }
+            AttachmentStorage::S3(s3) => {
+                let response = s3_request(s3, reqwest::Method::GET, key, Vec::new(), None).await?;
+                if !response.status().is_success() {
+                    bail!("S3 download of {} failed: {}", key, response.status());
+                }
+                Ok(response.bytes().await?.to_vec())
+            }
+        }
+    }
+}
+
+/// Store `bytes` and record them as an attachment of the solution
+pub async fn store_attachment(
+    db: &Database,
+    storage: &AttachmentStorage,
+    solution_id: &str,
+    file_name: &str,
+    content_type: &str,
+    bytes: Vec<u8>,
+    plot_spec: Option<PlotSpec>,
+) -> Result<SolutionAttachment> {
+    let extension = extension_for(content_type).ok_or_else(|| anyhow!("Unsupported content type {}", content_type))?;
+    let id = uuid::Uuid::new_v4().to_string();
+    // Keys don't contain the solution id, so they survive book renames
+    let storage_key = format!("{}.{}", id, extension);
+    let attachment = SolutionAttachment {
+        id,
+        solution_id: solution_id.to_string(),
+        file_name: file_name.to_string(),
+        content_type: content_type.to_string(),
+        size: bytes.len() as u64,
+        storage_key,
+        plot_spec,
+        created_at: Utc::now(),
+    };
+    storage.put(&attachment.storage_key, bytes, content_type).await?;
+    db.create_solution_attachment(&attachment).await?;
+    Ok(attachment)
+}
+
+/// Draw `spec` and attach the SVG to the solution
+pub async fn attach_plot(
+    db: &Database,
+    storage: &AttachmentStorage,
+    solution_id: &str,
+    spec: &PlotSpec,
+) -> Result<SolutionAttachment> {
+    let svg = plot::render_svg(spec)?;
+    store_attachment(db, storage, solution_id, "plot.svg", "image/svg+xml", svg.into_bytes(), Some(spec.clone())).await
+}
+
+/// Plot of the functions a "постройте график" problem asks for, attached to
+/// its stored solution from `provider` unless the same plot already is.
+/// `None` for other problems.
+pub async fn attach_problem_plot(
+    db: &Database,
+    storage: &AttachmentStorage,
+    problem: &Problem,
+    provider: &str,
+) -> Result<Option<SolutionAttachment>> {
+    let Some(spec) = plot::plot_request(problem) else {
+        return Ok(None);
+    };
+    // Saving replaces the content of an existing solution but keeps its id
+    let Some(solution) = db.get_solution(&problem.id, provider).await? else {
+        return Ok(None);
+    };
+    let existing = db.get_solution_attachments(&solution.id).await?;
+    if existing.iter().any(|a| a.plot_spec.as_ref() == Some(&spec)) {
+        return Ok(None);
+    }
+    attach_plot(db, storage, &solution.id, &spec).await.map(Some)
+}
+
+// === S3 ===
+
+/// Path-style request to `{endpoint}/{bucket}/{key}`, signed with AWS
+/// Signature Version 4
+async fn s3_request(
+    s3: &S3Config,
+    method: reqwest::Method,
+    key: &str,
+    body: Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<reqwest::Response> {
+    let mut request = s3_signed_request(s3, method, key, &hex(&Sha256::digest(&body)))?;
+    if let Some(content_type) = content_type {
+        request = request.header("Content-Type", content_type);
+    }
+    Ok(request.body(body).send().await?)
+}
+
+/// Request with the signature headers for a payload with the given SHA-256
+fn s3_signed_request(
+    s3: &S3Config,
+    method: reqwest::Method,
+    key: &str,
+    payload_hash: &str,
+) -> Result<reqwest::RequestBuilder> {
+    let url = reqwest::Url::parse(&format!("{}/{}/{}", s3.endpoint, s3.bucket, key))?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        _ => bail!("Invalid S3 endpoint {}", s3.endpoint),
+    };
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let authorization = sigv4_authorization(s3, method.as_str(), url.path(), &host, &amz_date, payload_hash);
+
+    Ok(HttpClientFactory::global()
+        .client(S3_TIMEOUT)
+        .request(method, url)
+        .header("x-amz-date", amz_date)
+        .header("x-amz-content-sha256", payload_hash)
+        .header("Authorization", authorization))
+}
+
+/// `Authorization` header signing host, date and payload hash. Keys are
+/// uuids, so the path needs no escaping.
+fn sigv4_authorization(s3: &S3Config, method: &str, path: &str, host: &str, amz_date: &str, payload_hash: &str) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, s3.region);
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&s3.secret_access_key, date, &s3.region, "s3");
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        s3.access_key_id,
+        scope,
+        signed_headers,
+        hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// HMAC (RFC 2104) over SHA-256
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    // Any key length is valid for HMAC
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_like_aws() {
+        // Key derivation example from the AWS Signature Version 4 docs
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use crate::models::Problem;
 use serde::{Deserialize, Serialize};
 use std::process::Command;
@@ -5,6 +6,7 @@ use std::process::Command;
 /// AI-powered auto-tagger for problems
 pub struct AutoTagger {
     api_key: Option<String>,
+    proxy_env: Vec<(&'static str, String)>,
     local_classifier: LocalClassifier,
 }
 
@@ -39,9 +41,10 @@ pub struct LocalClassifier {
 }
 
 impl AutoTagger {
-    pub fn new(api_key: Option<String>) -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            api_key,
+            api_key: config.mistral_api_key(),
+            proxy_env: config.proxy_env(),
             local_classifier: LocalClassifier::new(),
         }
     }
@@ -146,6 +149,7 @@ except Exception as e:
             .arg("-c")
             .arg(&python_script)
             .env("MISTRAL_API_KEY", api_key)
+            .envs(self.proxy_env.iter().cloned())
             .output()
             .map_err(|e| anyhow::anyhow!("Failed to run Python: {}", e))?;
 
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{RwLock, mpsc};
+use tokio_util::sync::CancellationToken;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::services::database::Database;
+
 /// Background job status
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum JobStatus {
@@ -31,6 +34,13 @@ pub enum JobType {
         book_id: String,
         page_range: (u32, u32),
         chapter_id: String,
+        /// Pages to process when not the whole range (e.g. retried failures)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pages: Option<Vec<u32>>,
+        #[serde(default)]
+        incremental: bool,
+        #[serde(default)]
+        force: bool,
     },
     BatchSolve {
         problem_ids: Vec<String>,
@@ -40,6 +50,30 @@ pub enum JobType {
         book_id: String,
         format: ExportFormat,
     },
+    OcrAudit {
+        book_id: Option<String>,
+        sample_size: usize,
+        provider: String,
+    },
+    AnswerKey {
+        book_id: String,
+        page_range: (u32, u32),
+        /// Limit matching to one chapter when problem numbers restart per chapter
+        chapter_id: Option<String>,
+    },
+}
+
+impl JobType {
+    /// Stable snake_case name, used as the `type` filter of the job history
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobType::BatchOcr { .. } => "batch_ocr",
+            JobType::BatchSolve { .. } => "batch_solve",
+            JobType::Export { .. } => "export",
+            JobType::OcrAudit { .. } => "ocr_audit",
+            JobType::AnswerKey { .. } => "answer_key",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,11 +84,113 @@ pub enum ExportFormat {
     Anki,
 }
 
+/// Finished job as stored in the `jobs_history` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub job_type: String,
+    /// Job parameters (book, page range, provider, ...)
+    pub params: serde_json::Value,
+    /// completed, failed or cancelled
+    pub status: String,
+    /// One-line description of the outcome
+    pub summary: Option<String>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: i64,
+}
+
+impl JobRecord {
+    /// Record for a finished job; `None` while it is still pending or running
+    pub fn from_job(job: &BackgroundJob) -> Option<Self> {
+        let (status, summary, result, error) = match &job.status {
+            JobStatus::Completed { result } => {
+                ("completed", summarize_result(result), Some(result.clone()), None)
+            }
+            JobStatus::Failed { error } => {
+                ("failed", error.lines().next().map(str::to_string), None, Some(error.clone()))
+            }
+            JobStatus::Cancelled => ("cancelled", None, None, None),
+            JobStatus::Pending | JobStatus::Running { .. } => return None,
+        };
+
+        // Externally tagged enum: keep only the variant's fields
+        let params = match serde_json::to_value(&job.job_type) {
+            Ok(serde_json::Value::Object(map)) => map.into_iter().next().map(|(_, v)| v).unwrap_or_default(),
+            Ok(other) => other,
+            Err(_) => serde_json::Value::Null,
+        };
+
+        Some(Self {
+            id: job.id.clone(),
+            job_type: job.job_type.name().to_string(),
+            params,
+            status: status.to_string(),
+            summary,
+            result,
+            error,
+            created_at: job.created_at,
+            finished_at: job.updated_at,
+            duration_ms: (job.updated_at - job.created_at).num_milliseconds(),
+        })
+    }
+}
+
+impl JobRecord {
+    /// Pages a finished batch OCR job failed on. Reads `failed_pages` from the
+    /// result, falling back to the `Page N: ...` error messages of older jobs.
+    pub fn failed_pages(&self) -> Vec<u32> {
+        let Some(result) = &self.result else {
+            return Vec::new();
+        };
+
+        let mut pages: Vec<u32> = match result.get("failed_pages").and_then(|p| p.as_array()) {
+            Some(list) => list.iter().filter_map(|p| p.as_u64()).map(|p| p as u32).collect(),
+            None => result
+                .get("errors")
+                .and_then(|e| e.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.as_str()?.strip_prefix("Page ")?.split(':').next()?.trim().parse().ok())
+                .collect(),
+        };
+        pages.sort_unstable();
+        pages.dedup();
+        pages
+    }
+}
+
+/// `key=value` list of a result's scalar top-level fields
+fn summarize_result(result: &serde_json::Value) -> Option<String> {
+    let parts: Vec<String> = result
+        .as_object()?
+        .iter()
+        .filter(|(_, v)| v.is_number() || v.is_boolean() || v.is_string())
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => format!("{}={}", k, s),
+            _ => format!("{}={}", k, v),
+        })
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
 /// Background job manager
 #[derive(Clone)]
 pub struct JobManager {
     jobs: Arc<RwLock<HashMap<String, BackgroundJob>>>,
     tx: mpsc::UnboundedSender<JobCommand>,
+    history: Option<JobHistory>,
+    /// Cancelled together with the job so in-flight provider calls stop early
+    cancel_tokens: Arc<std::sync::Mutex<HashMap<String, CancellationToken>>>,
+}
+
+/// Where finished jobs are persisted and how long they are kept
+#[derive(Clone)]
+struct JobHistory {
+    db: Database,
+    retention_days: u32,
 }
 
 #[derive(Debug)]
@@ -65,33 +201,46 @@ enum JobCommand {
 
 impl JobManager {
     pub fn new() -> Self {
+        Self::start(None)
+    }
+
+    /// Job manager that also records finished jobs in the `jobs_history`
+    /// table, keeping them for `retention_days`
+    pub fn with_history(db: Database, retention_days: u32) -> Self {
+        Self::start(Some(JobHistory { db, retention_days }))
+    }
+
+    fn start(history: Option<JobHistory>) -> Self {
         let (tx, mut rx) = mpsc::unbounded_channel::<JobCommand>();
         let jobs: Arc<RwLock<HashMap<String, BackgroundJob>>> = Arc::new(RwLock::new(HashMap::new()));
         let jobs_clone = jobs.clone();
+        let history_db = history.as_ref().map(|h| h.db.clone());
         
         // Background task processor
         tokio::spawn(async move {
             while let Some(cmd) = rx.recv().await {
-                match cmd {
-                    JobCommand::UpdateStatus(id, status) => {
-                        let mut jobs = jobs_clone.write().await;
-                        if let Some(job) = jobs.get_mut(&id) {
-                            job.status = status;
-                            job.updated_at = Utc::now();
-                        }
-                    }
-                    JobCommand::Cancel(id) => {
-                        let mut jobs = jobs_clone.write().await;
-                        if let Some(job) = jobs.get_mut(&id) {
-                            job.status = JobStatus::Cancelled;
-                            job.updated_at = Utc::now();
-                        }
-                    }
+                let finished = {
+                    let mut jobs = jobs_clone.write().await;
+                    let (id, status) = match cmd {
+                        JobCommand::UpdateStatus(id, status) => (id, status),
+                        JobCommand::Cancel(id) => (id, JobStatus::Cancelled),
+                    };
+                    jobs.get_mut(&id).and_then(|job| {
+                        job.status = status;
+                        job.updated_at = Utc::now();
+                        JobRecord::from_job(job)
+                    })
+                };
+
+                if let (Some(record), Some(db)) = (finished, &history_db)
+                    && let Err(e) = db.save_job_record(&record).await
+                {
+                    log::warn!("Failed to save job {} to history: {}", record.id, e);
                 }
             }
         });
         
-        Self { jobs, tx }
+        Self { jobs, tx, history, cancel_tokens: Arc::default() }
     }
     
     pub async fn create_job(&self, job_type: JobType) -> String {
@@ -106,9 +255,22 @@ impl JobManager {
         
         let mut jobs = self.jobs.write().await;
         jobs.insert(id.clone(), job);
+        if let Ok(mut tokens) = self.cancel_tokens.lock() {
+            tokens.insert(id.clone(), CancellationToken::new());
+        }
         
         id
     }
+
+    /// Token that fires when the job is cancelled (a fresh, never-cancelled
+    /// token for unknown jobs)
+    pub fn cancellation_token(&self, id: &str) -> CancellationToken {
+        self.cancel_tokens
+            .lock()
+            .ok()
+            .and_then(|tokens| tokens.get(id).cloned())
+            .unwrap_or_default()
+    }
     
     pub async fn get_job(&self, id: &str) -> Option<BackgroundJob> {
         let jobs = self.jobs.read().await;
@@ -145,12 +307,23 @@ impl JobManager {
     }
     
     pub async fn cancel_job(&self, id: &str) {
+        self.cancellation_token(id).cancel();
         let _ = self.tx.send(JobCommand::Cancel(id.to_string()));
     }
     
-    /// Clean up old completed jobs (older than 24 hours)
+    /// Clean up old finished jobs: after 24 hours in memory (1 hour when they
+    /// are persisted to the history), and history past its retention window
     pub async fn cleanup_old_jobs(&self) {
-        let cutoff = Utc::now() - chrono::Duration::hours(24);
+        if let Some(history) = &self.history {
+            match history.db.prune_job_history(history.retention_days).await {
+                Ok(0) => {}
+                Ok(n) => log::info!("Pruned {} old job history records", n),
+                Err(e) => log::warn!("Failed to prune job history: {}", e),
+            }
+        }
+
+        let keep_for = if self.history.is_some() { chrono::Duration::hours(1) } else { chrono::Duration::hours(24) };
+        let cutoff = Utc::now() - keep_for;
         let mut jobs = self.jobs.write().await;
         jobs.retain(|_, job| {
             match &job.status {
@@ -160,6 +333,9 @@ impl JobManager {
                 _ => true,
             }
         });
+        if let Ok(mut tokens) = self.cancel_tokens.lock() {
+            tokens.retain(|id, _| jobs.contains_key(id));
+        }
     }
 }
 
@@ -168,3 +344,33 @@ impl Default for JobManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(result: serde_json::Value) -> JobRecord {
+        JobRecord {
+            id: "job".to_string(),
+            job_type: "batch_ocr".to_string(),
+            params: serde_json::Value::Null,
+            status: "completed".to_string(),
+            summary: None,
+            result: Some(result),
+            error: None,
+            created_at: Utc::now(),
+            finished_at: Utc::now(),
+            duration_ms: 0,
+        }
+    }
+
+    #[test]
+    fn reads_failed_pages_from_result() {
+        assert_eq!(record(serde_json::json!({"failed_pages": [7, 3, 7]})).failed_pages(), vec![3, 7]);
+
+        let legacy = record(serde_json::json!({
+            "errors": ["Page 12: No parse result", "Page 4: Failed to save problems - locked"]
+        }));
+        assert_eq!(legacy.failed_pages(), vec![4, 12]);
+    }
+}
@@ -4,6 +4,17 @@ use crate::services::background::{JobManager, JobType, JobStatus};
 use crate::services::database::Database;
 use crate::services::ai_parser::HybridParser;
 use crate::services::ocr::OcrService;
+use crate::services::FileService;
+use crate::services::page_classifier::classify_page;
+use crate::services::heading_detector::HeadingDetector;
+use crate::models::{PageKind, SolveOptions};
+use crate::services::book_settings::{book_file_service, book_parser_profile, DEFAULT_OCR_PROVIDER};
+use crate::services::ocr_rules::postprocess_ocr_text;
+use crate::services::ocr_confidence::problem_confidence;
+use crate::services::job_artifacts::{save_artifact, to_csv, JobArtifact};
+use crate::services::answer_key::{match_answers, parse_answer_key};
+use crate::services::retry::{guarded, is_timeout};
+use crate::services::attachments::{attach_problem_plot, AttachmentStorage};
 
 /// Batch OCR processor
 pub struct BatchProcessor {
@@ -36,6 +47,17 @@ impl BatchProcessor {
     ) -> Self {
         Self { job_manager, db, config }
     }
+
+    /// OCR provider from the book's settings, the default when they can't be read
+    pub async fn ocr_provider(&self, book_id: &str) -> String {
+        match self.db.get_book_settings(book_id).await {
+            Ok(settings) => settings.ocr_provider().to_string(),
+            Err(e) => {
+                log::warn!("Failed to load settings of {}: {}", book_id, e);
+                DEFAULT_OCR_PROVIDER.to_string()
+            }
+        }
+    }
     
     /// Start batch OCR job
     pub async fn start_batch_ocr(
@@ -46,11 +68,44 @@ impl BatchProcessor {
         chapter_id: &str,
         incremental: bool,
         force: bool,
+    ) -> anyhow::Result<String> {
+        let pages: Vec<u32> = (start_page..=end_page).collect();
+        self.spawn_batch_ocr(book_id, pages, false, chapter_id, incremental, force).await
+    }
+
+    /// Start a batch OCR job over an explicit, possibly non-contiguous, page list
+    pub async fn start_batch_ocr_pages(
+        &self,
+        book_id: &str,
+        mut pages: Vec<u32>,
+        chapter_id: &str,
+        incremental: bool,
+        force: bool,
+    ) -> anyhow::Result<String> {
+        pages.sort_unstable();
+        pages.dedup();
+        if pages.is_empty() {
+            anyhow::bail!("No pages to process");
+        }
+        self.spawn_batch_ocr(book_id, pages, true, chapter_id, incremental, force).await
+    }
+
+    async fn spawn_batch_ocr(
+        &self,
+        book_id: &str,
+        pages: Vec<u32>,
+        explicit_pages: bool,
+        chapter_id: &str,
+        incremental: bool,
+        force: bool,
     ) -> anyhow::Result<String> {
         let job_id = self.job_manager.create_job(JobType::BatchOcr {
             book_id: book_id.to_string(),
-            page_range: (start_page, end_page),
+            page_range: (pages[0], pages[pages.len() - 1]),
             chapter_id: chapter_id.to_string(),
+            pages: explicit_pages.then(|| pages.clone()),
+            incremental,
+            force,
         }).await;
         
         let processor = self.clone();
@@ -59,15 +114,18 @@ impl BatchProcessor {
         let chapter_id = chapter_id.to_string();
         
         tokio::spawn(async move {
-            processor.run_batch_ocr(&jid, &book_id, start_page, end_page, &chapter_id, incremental, force).await;
+            processor.run_batch_ocr(&jid, &book_id, &pages, &chapter_id, incremental, force).await;
         });
         
         Ok(job_id)
     }
     
-    async fn run_batch_ocr(&self, job_id: &str, book_id: &str, start_page: u32, end_page: u32, chapter_id: &str, incremental: bool, force: bool) {
+    async fn run_batch_ocr(&self, job_id: &str, book_id: &str, pages: &[u32], chapter_id: &str, incremental: bool, force: bool) {
         let start_time = std::time::Instant::now();
-        let total_pages = end_page - start_page + 1;
+        let total_pages = pages.len() as u32;
+        let end_page = pages[pages.len() - 1];
+        // Cross-page merging only makes sense between consecutive pages
+        let follows_previous = |idx: usize| idx > 0 && pages[idx - 1] + 1 == pages[idx];
         
         // Get book info
         let _book = match self.db.get_book(book_id).await {
@@ -78,8 +136,12 @@ impl BatchProcessor {
             }
         };
         
-        let parser = HybridParser::new(std::env::var("MISTRAL_API_KEY").ok());
-        let ocr_service = OcrService::new(self.config.preview_dir.clone());
+        let parser = HybridParser::new(self.config.mistral_api_key())
+            .timeout(self.config.provider_timeout())
+            .profile(book_parser_profile(&self.db, book_id).await);
+        let ocr_service = OcrService::new(&self.config);
+        let cancel = self.job_manager.cancellation_token(job_id);
+        let provider = self.ocr_provider(book_id).await;
         
         // === FIRST PASS: OCR all pages (parallel with semaphore) ===
         self.job_manager.update_progress(job_id, 0.0, "Running parallel OCR...").await;
@@ -90,7 +152,7 @@ impl BatchProcessor {
         let semaphore = Arc::new(Semaphore::new(4));
         let mut handles = Vec::new();
         
-        for (idx, page_num) in (start_page..=end_page).enumerate() {
+        for (idx, &page_num) in pages.iter().enumerate() {
             if let Some(job) = self.job_manager.get_job(job_id).await {
                 if matches!(job.status, JobStatus::Cancelled) {
                     return;
@@ -98,68 +160,119 @@ impl BatchProcessor {
             }
             
             let ocr_service = ocr_service.clone();
+            let cancel = cancel.clone();
             let db = Arc::clone(&self.db);
             let book_id = book_id.to_string();
             let config = Arc::clone(&self.config);
             let sem = Arc::clone(&semaphore);
+            let provider = provider.clone();
             
             let handle = tokio::spawn(async move {
                 let _permit = sem.acquire().await.unwrap();
                 
                 // Check cache unless force=true
-                if !force {
-                    if let Ok(Some(page)) = db.get_page(&book_id, page_num).await {
-                        if page.ocr_text.is_some() && !page.ocr_text.as_ref().unwrap().is_empty() {
-                            // If incremental mode and we have cached OCR, skip this page
-                            if incremental {
-                                log::info!("Skipping page {} (using cached OCR)", page_num);
-                                return (idx, None); // None means skip
+                if !force
+                    && let Ok(Some(page)) = db.get_page(&book_id, page_num).await
+                    && let Some(text) = page.ocr_text.filter(|t| !t.is_empty())
+                {
+                    // If incremental mode and we have cached OCR, skip this page
+                    if incremental {
+                        log::info!("Skipping page {} (using cached OCR)", page_num);
+                        return (idx, Ok(PageOcr::Skipped(text)));
+                    }
+                    return (idx, Ok(PageOcr::Text(text)));
+                }
+                
+                let files = book_file_service(&db, &FileService::from_config(&config), &book_id).await;
+                let filename = files.book_file(&book_id);
+                let image_path = files.page_image(&filename, page_num);
+
+                // Cheap histogram check so blank pages never reach the OCR provider
+                if image_path.exists() {
+                    let path = image_path.clone();
+                    match tokio::task::spawn_blocking(move || classify_page(&path)).await {
+                        Ok(Ok((kind, stats))) => {
+                            log::debug!(
+                                "Page {} looks {} (paper {}, ink {:.3}, midtones {:.3})",
+                                page_num, kind.as_str(), stats.paper, stats.ink, stats.midtones
+                            );
+                            if let Ok(page) = db.get_or_create_page(&book_id, page_num).await {
+                                let _ = db.set_page_kind(&page.id, kind).await;
+                            }
+                            if kind == PageKind::Blank {
+                                log::info!("Skipping blank page {}", page_num);
+                                return (idx, Ok(PageOcr::Blank));
                             }
-                            return (idx, Some(page.ocr_text.unwrap()));
                         }
+                        Ok(Err(e)) => log::warn!("Could not classify page {}: {}", page_num, e),
+                        Err(e) => log::warn!("Page classification task failed: {}", e),
                     }
                 }
                 
-                let filename = format!("{}.pdf", &book_id);
-                let image_path = config.preview_dir.join(format!("{}_{}.png", filename, page_num));
-                
-                match ocr_service.run_ocr(&image_path, "mistral").await {
-                    Ok(text) => {
+                match ocr_service.ocr_page(&filename, page_num, &image_path, &provider, &cancel).await {
+                    Ok(result) => {
+                        let text = postprocess_ocr_text(&db, &book_id, &result.text).await;
                         if let Ok(page) = db.get_or_create_page(&book_id, page_num).await {
                             let _ = db.update_page_ocr(&page.id, &text, 0).await;
+                            let _ = db.set_page_ocr_provider(&page.id, &result.provider).await;
                         }
-                        (idx, Some(text))
+                        (idx, Ok(PageOcr::Text(text)))
                     }
                     Err(e) => {
                         log::warn!("OCR failed for page {}: {}", page_num, e);
-                        (idx, None)
+                        (idx, Err((e.to_string(), is_timeout(&e))))
                     }
                 }
             });
             handles.push(handle);
         }
         
+        let mut report: Vec<PageReport> = pages.iter().copied().map(PageReport::new).collect();
+
         for handle in handles {
-            if let Ok((idx, text)) = handle.await {
-                all_ocr_texts[idx] = text;
+            if let Ok((idx, outcome)) = handle.await {
+                match outcome {
+                    Ok(PageOcr::Text(text)) => {
+                        report[idx].ocr = "ok";
+                        all_ocr_texts[idx] = Some(text);
+                    }
+                    Ok(PageOcr::Skipped(text)) => {
+                        report[idx].ocr = "skipped";
+                        all_ocr_texts[idx] = Some(text);
+                    }
+                    Ok(PageOcr::Blank) => report[idx].ocr = "blank",
+                    Err((e, timed_out)) => {
+                        report[idx].ocr = if timed_out { "timeout" } else { "failed" };
+                        report[idx].errors.push(format!("OCR: {}", e));
+                    }
+                }
             }
         }
+
+        if cancel.is_cancelled() {
+            return;
+        }
         
         let cached = all_ocr_texts.iter().filter(|t| t.is_some()).count();
         log::info!("Parallel OCR done: {}/{} pages", cached, total_pages);
         
         // === Process chapter headings (carryover between pages) ===
+        let headings = HeadingDetector::for_book(&self.db, book_id).await;
         let mut processed_ocr_texts: Vec<(String, Option<String>)> = Vec::new();
         let mut chapter_carryover = String::new();
         
         for (idx, ocr_text_opt) in all_ocr_texts.iter().enumerate() {
             let ocr_text = ocr_text_opt.as_deref().unwrap_or("");
+            if !chapter_carryover.is_empty() && !follows_previous(idx) {
+                log::warn!("Dropping chapter carryover before non-consecutive page {}", pages[idx]);
+                chapter_carryover.clear();
+            }
             let merged = if chapter_carryover.is_empty() {
                 ocr_text.to_string()
             } else {
                 format!("{}\n\n{}", chapter_carryover, ocr_text)
             };
-            let (page_text, next_carryover) = split_trailing_chapter_heading(&merged);
+            let (page_text, next_carryover) = headings.split_trailing_heading(&merged);
             chapter_carryover = next_carryover.unwrap_or_default();
             processed_ocr_texts.push((page_text.clone(), Some(page_text)));
         }
@@ -170,8 +283,10 @@ impl BatchProcessor {
         
         // === Second PASS: Parse ALL pages first (to avoid double parsing) ===
         let mut all_parse_results: Vec<Option<crate::services::ai_parser::AIParseResult>> = Vec::new();
+        // Fingerprints of fresh parses, stored with the result in the third pass
+        let mut new_fingerprints: Vec<Option<String>> = vec![None; pages.len()];
         
-        for (idx, page_num) in (start_page..=end_page).enumerate() {
+        for (idx, &page_num) in pages.iter().enumerate() {
             let progress = 50.0 + (idx as f32 / total_pages as f32) * 25.0;
             self.job_manager.update_progress(
                 job_id,
@@ -183,10 +298,29 @@ impl BatchProcessor {
                 .and_then(|(t, _)| Some(t.as_str()))
                 .unwrap_or("");
             
+            // Pages whose text was parsed the same way before keep that result
+            let fingerprint = parser.fingerprint(book_id, page_text);
+            if !force {
+                let stored = self.db.get_page_parse(book_id, page_num, &fingerprint).await.ok().flatten();
+                if let Some(result) = stored.and_then(|json| serde_json::from_str(&json).ok()) {
+                    report[idx].parse = "unchanged";
+                    all_parse_results.push(Some(result));
+                    continue;
+                }
+            }
+
             match parser.parse_text(book_id, page_text, Some(page_num)).await {
-                Ok(r) => all_parse_results.push(Some(r)),
+                Ok(r) => {
+                    report[idx].parse = "parsed";
+                    if !r.degraded {
+                        new_fingerprints[idx] = Some(fingerprint);
+                    }
+                    all_parse_results.push(Some(r));
+                }
                 Err(e) => {
                     log::warn!("Parse failed for page {}: {}", page_num, e);
+                    report[idx].parse = "failed";
+                    report[idx].errors.push(format!("Parse: {}", e));
                     all_parse_results.push(None);
                 }
             }
@@ -199,12 +333,17 @@ impl BatchProcessor {
         let mut prev_last_problem: Option<crate::services::ai_parser::ParsedProblem> = None;
         let mut prev_continuation_tail: Option<String> = None;
         
-        for (idx, page_num) in (start_page..=end_page).enumerate() {
+        for (idx, &page_num) in pages.iter().enumerate() {
             if let Some(job) = self.job_manager.get_job(job_id).await {
                 if matches!(job.status, JobStatus::Cancelled) {
                     return;
                 }
             }
+
+            if !follows_previous(idx) {
+                prev_last_problem = None;
+                prev_continuation_tail = None;
+            }
             
             let progress = 75.0 + (processed as f32 / total_pages as f32) * 25.0;
             self.job_manager.update_progress(
@@ -218,6 +357,7 @@ impl BatchProcessor {
                 Some(r) => r.clone(),
                 None => {
                     errors.push(format!("Page {}: No parse result", page_num));
+                    report[idx].errors.push("No parse result".to_string());
                     processed += 1;
                     continue;
                 }
@@ -229,8 +369,18 @@ impl BatchProcessor {
             
             // Get next page problems for cross-page analysis
             let next_problems: Option<Vec<crate::services::ai_parser::ParsedProblem>> = 
-                all_parse_results.get(idx + 1).and_then(|r| r.as_ref()).map(|r| r.problems.clone());
+                all_parse_results.get(idx + 1)
+                    .filter(|_| follows_previous(idx + 1))
+                    .and_then(|r| r.as_ref())
+                    .map(|r| r.problems.clone());
             
+            // Each page keeps its own part of a continued problem; the
+            // database joins the parts into one statement
+            let page_contents: std::collections::HashMap<String, String> = parse_result.problems
+                .iter()
+                .map(|p| (p.number.clone(), p.content.clone()))
+                .collect();
+
             // Process cross-page merging
             parser.process_cross_page(
                 prev_last_problem.as_ref(),
@@ -253,6 +403,7 @@ impl BatchProcessor {
                 Ok(p) => p,
                 Err(e) => {
                     errors.push(format!("Page {}: Failed to create page - {}", page_num, e));
+                    report[idx].errors.push(format!("Failed to create page - {}", e));
                     processed += 1;
                     continue;
                 }
@@ -266,6 +417,11 @@ impl BatchProcessor {
                 .db
                 .update_page_ocr(&page.id, page_text, parse_result.problems.len() as u32)
                 .await;
+            if let (Some(fingerprint), Some(Some(result))) = (&new_fingerprints[idx], all_parse_results.get(idx))
+                && let Ok(json) = serde_json::to_string(result)
+            {
+                let _ = self.db.set_page_parse(&page.id, fingerprint, &json).await;
+            }
             
             // Create problems
             let chapter_num: u32 = chapter_id.split(':').last()
@@ -274,7 +430,7 @@ impl BatchProcessor {
             
             let mut problems_to_create = Vec::new();
             for ai_problem in &parse_result.problems {
-                let problem_id = format!("{}:{}:{}", book_id, chapter_num, ai_problem.number);
+                let problem_id = crate::models::Problem::generate_id(book_id, chapter_num, &ai_problem.number);
                 
                 let main_problem = crate::models::Problem {
                     id: problem_id.clone(),
@@ -283,7 +439,7 @@ impl BatchProcessor {
                     parent_id: None,
                     number: ai_problem.number.clone(),
                     display_name: format!("Задача {}", ai_problem.number),
-                    content: ai_problem.content.clone(),
+                    content: page_contents.get(&ai_problem.number).unwrap_or(&ai_problem.content).clone(),
                     latex_formulas: extract_formulas(&ai_problem.content),
                     page_number: Some(page_num),
                     difficulty: None,
@@ -299,6 +455,11 @@ impl BatchProcessor {
                     } else { None },
                     is_cross_page: ai_problem.continues_from_prev || ai_problem.continues_to_next,
                     is_bookmarked: false,
+                    confidence: Some(problem_confidence(&ai_problem.content, None)),
+                    review_status: crate::models::ReviewStatus::Unreviewed,
+                    review_note: None,
+                    reference_answer: None,
+                    version: 1,
                 };
                 
                 problems_to_create.push(main_problem);
@@ -326,14 +487,29 @@ impl BatchProcessor {
                         continues_to_page: None,
                         is_cross_page: false,
                         is_bookmarked: false,
+                        confidence: Some(problem_confidence(&sub.content, None)),
+                        review_status: crate::models::ReviewStatus::Unreviewed,
+                        review_note: None,
+                        reference_answer: None,
+                        version: 1,
                     };
                     problems_to_create.push(sub_problem);
                 }
             }
             
             // Save to database
-            if let Err(e) = self.db.create_or_update_problems(&problems_to_create).await {
-                errors.push(format!("Page {}: Failed to save problems - {}", page_num, e));
+            match self.db.save_page_problems(&page.id, page_num, &problems_to_create).await {
+                Ok(writes) => {
+                    report[idx].problems = parse_result.problems.len() as u32;
+                    for write in writes.iter().filter(|w| !w.is_written()) {
+                        let holder = write.existing_id.as_deref().unwrap_or("its parent was not saved");
+                        report[idx].errors.push(format!("Problem {} not saved: {}", write.id, holder));
+                    }
+                }
+                Err(e) => {
+                    errors.push(format!("Page {}: Failed to save problems - {}", page_num, e));
+                    report[idx].errors.push(format!("Failed to save problems - {}", e));
+                }
             }
             
             processed += 1;
@@ -341,11 +517,33 @@ impl BatchProcessor {
         
         let duration = start_time.elapsed().as_secs();
         
+        let error_rows: Vec<Vec<String>> = report
+            .iter()
+            .flat_map(|p| p.errors.iter().map(|e| vec![p.page.to_string(), e.clone()]))
+            .collect();
+        let mut artifacts = Vec::new();
+        artifacts.extend(self.save_artifact(job_id, "pages.json", serde_json::to_vec_pretty(&report).unwrap_or_default()));
+        if !error_rows.is_empty() {
+            artifacts.extend(self.save_artifact(job_id, "errors.csv", to_csv(&["page", "error"], &error_rows).into_bytes()));
+        }
+
+        let failed_pages: Vec<u32> = report
+            .iter()
+            .filter(|p| p.ocr == "failed" || !p.errors.is_empty())
+            .map(|p| p.page)
+            .collect();
+        let timed_out_pages: Vec<u32> = report.iter().filter(|p| p.ocr == "timeout").map(|p| p.page).collect();
+        let blank_pages: Vec<u32> = report.iter().filter(|p| p.ocr == "blank").map(|p| p.page).collect();
+
         let result = serde_json::json!({
             "processed_pages": processed,
             "problems_found": total_problems,
+            "failed_pages": failed_pages,
+            "timed_out_pages": timed_out_pages,
+            "blank_pages": blank_pages,
             "errors": errors,
             "duration_secs": duration,
+            "artifacts": artifacts,
         });
         
         self.job_manager.complete_job(job_id, result).await;
@@ -377,8 +575,18 @@ impl BatchProcessor {
         let mut processed = 0u32;
         let mut succeeded = 0u32;
         let mut failed = 0u32;
+        let mut failures: Vec<Vec<String>> = Vec::new();
         
-        let solver = AISolver::new(&self.config).expect("Failed to create AI solver");
+        let solver = match AISolver::new(&self.config) {
+            Ok(s) => s,
+            Err(e) => {
+                self.job_manager.fail_job(job_id, &format!("AI solver not available: {}", e)).await;
+                return;
+            }
+        };
+        let cancel = self.job_manager.cancellation_token(job_id);
+        let mut timed_out = 0u32;
+        let storage = AttachmentStorage::from_config(&self.config);
         
         for problem_id in problem_ids {
             // Check if job was cancelled
@@ -399,6 +607,7 @@ impl BatchProcessor {
             let problem = match self.db.get_problem(&problem_id).await {
                 Ok(Some(p)) => p,
                 _ => {
+                    failures.push(vec![problem_id.clone(), "Problem not found".to_string()]);
                     failed += 1;
                     processed += 1;
                     continue;
@@ -413,20 +622,32 @@ impl BatchProcessor {
             }
             
             // Generate solution
-            match solver.solve(&problem, Some(provider), None).await {
+            match guarded(solver.solve(&problem, Some(provider), None, &SolveOptions::default()), self.config.provider_timeout(), &cancel).await {
                 Ok(solution) => {
                     // Save solution
                     if let Err(e) = self.db.save_solution(&solution).await {
                         log::error!("Failed to save solution: {}", e);
+                        failures.push(vec![problem_id.clone(), format!("Failed to save solution: {}", e)]);
                         failed += 1;
                     } else {
                         // Update problem status
                         let _ = self.db.update_problem_solution_status(&problem_id, true).await;
+                        if let Err(e) = attach_problem_plot(&self.db, &storage, &problem, &solution.provider).await {
+                            log::warn!("Failed to plot {}: {}", problem_id, e);
+                        }
                         succeeded += 1;
                     }
                 }
+                Err(_) if cancel.is_cancelled() => return,
                 Err(e) => {
                     log::error!("Failed to generate solution: {}", e);
+                    let error = if is_timeout(&e) {
+                        timed_out += 1;
+                        format!("Timed out: {}", e)
+                    } else {
+                        e.to_string()
+                    };
+                    failures.push(vec![problem_id.clone(), error]);
                     failed += 1;
                 }
             }
@@ -439,106 +660,215 @@ impl BatchProcessor {
         
         let duration = start_time.elapsed().as_secs();
         
+        let artifacts = if failures.is_empty() {
+            None
+        } else {
+            self.save_artifact(job_id, "errors.csv", to_csv(&["problem_id", "error"], &failures).into_bytes())
+        };
+
         let result = serde_json::json!({
             "processed": processed,
             "succeeded": succeeded,
             "failed": failed,
+            "timed_out": timed_out,
             "duration_secs": duration,
+            "artifacts": artifacts.into_iter().collect::<Vec<_>>(),
         });
         
         self.job_manager.complete_job(job_id, result).await;
     }
 }
 
-impl Clone for BatchProcessor {
-    fn clone(&self) -> Self {
-        Self {
-            job_manager: self.job_manager.clone(),
-            db: self.db.clone(),
-            config: self.config.clone(),
-        }
-    }
-}
+impl BatchProcessor {
+    /// Start a job that OCRs a book's answers section and links the answers
+    /// to problems as their reference answers
+    pub async fn start_answer_key(
+        &self,
+        book_id: &str,
+        start_page: u32,
+        end_page: u32,
+        chapter_id: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let job_id = self.job_manager.create_job(JobType::AnswerKey {
+            book_id: book_id.to_string(),
+            page_range: (start_page, end_page),
+            chapter_id: chapter_id.map(str::to_string),
+        }).await;
 
-fn extract_formulas(text: &str) -> Vec<String> {
-    let mut formulas = Vec::new();
-    let re = regex::Regex::new(r"\$([^$]+)\$").unwrap();
-    for cap in re.captures_iter(text) {
-        formulas.push(cap[1].to_string());
-    }
-    formulas
-}
+        let processor = self.clone();
+        let jid = job_id.clone();
+        let book_id = book_id.to_string();
+        let chapter_id = chapter_id.map(str::to_string);
 
-fn split_trailing_chapter_heading(text: &str) -> (String, Option<String>) {
-    let lines: Vec<&str> = text.lines().collect();
-    let Some(last_non_empty_idx) = lines.iter().rposition(|l| !l.trim().is_empty()) else {
-        return (String::new(), None);
-    };
+        tokio::spawn(async move {
+            processor.run_answer_key(&jid, &book_id, start_page, end_page, chapter_id.as_deref()).await;
+        });
 
-    if !is_chapter_heading_line(lines[last_non_empty_idx]) {
-        return (text.trim().to_string(), None);
+        Ok(job_id)
     }
 
-    let current = lines[..last_non_empty_idx].join("\n").trim().to_string();
-    let carryover = lines[last_non_empty_idx..].join("\n").trim().to_string();
+    async fn run_answer_key(&self, job_id: &str, book_id: &str, start_page: u32, end_page: u32, chapter_id: Option<&str>) {
+        let start_time = std::time::Instant::now();
+        let ocr_service = OcrService::new(&self.config);
+        let cancel = self.job_manager.cancellation_token(job_id);
+        let provider = self.ocr_provider(book_id).await;
+        let total = (end_page - start_page + 1) as f32;
 
-    if carryover.is_empty() {
-        (current, None)
-    } else {
-        (current, Some(carryover))
-    }
-}
+        // Answers often continue across pages, so parse the section as one text
+        let mut text = String::new();
+        let mut errors = Vec::new();
+        for page_num in start_page..=end_page {
+            if let Some(job) = self.job_manager.get_job(job_id).await
+                && matches!(job.status, JobStatus::Cancelled)
+            {
+                return;
+            }
+            let progress = (page_num - start_page) as f32 / total * 80.0;
+            self.job_manager.update_progress(job_id, progress, &format!("Reading answers page {}", page_num)).await;
+
+            let cached = match self.db.get_page(book_id, page_num).await {
+                Ok(page) => page.and_then(|p| p.ocr_text).filter(|t| !t.trim().is_empty()),
+                Err(_) => None,
+            };
+            let page_text = match cached {
+                Some(t) => t,
+                None => {
+                    let files = book_file_service(&self.db, &FileService::from_config(&self.config), book_id).await;
+                    let filename = files.book_file(book_id);
+                    let image_path = files.page_image(&filename, page_num);
+                    match ocr_service.ocr_page(&filename, page_num, &image_path, &provider, &cancel).await {
+                        Ok(result) => {
+                            let t = postprocess_ocr_text(&self.db, book_id, &result.text).await;
+                            if let Ok(page) = self.db.get_or_create_page(book_id, page_num).await {
+                                let _ = self.db.update_page_ocr(&page.id, &t, 0).await;
+                                let _ = self.db.set_page_ocr_provider(&page.id, &result.provider).await;
+                            }
+                            t
+                        }
+                        Err(e) => {
+                            log::warn!("OCR failed for answers page {}: {}", page_num, e);
+                            errors.push(format!("Page {}: {}", page_num, e));
+                            continue;
+                        }
+                    }
+                }
+            };
+            text.push_str(&page_text);
+            text.push('\n');
+        }
 
-fn is_chapter_heading_line(line: &str) -> bool {
-    let lower = line.trim().to_lowercase();
-    let Some(rest) = lower.strip_prefix("глава") else {
-        return false;
-    };
+        let entries = parse_answer_key(&text);
 
-    let rest = rest.trim_start();
-    if rest.is_empty() {
-        return false;
-    }
+        let chapters = match chapter_id {
+            Some(id) => vec![id.to_string()],
+            None => match self.db.get_chapters_by_book(book_id).await {
+                Ok(chapters) => chapters.into_iter().map(|c| c.id).collect(),
+                Err(e) => {
+                    self.job_manager.fail_job(job_id, &format!("Failed to load chapters: {}", e)).await;
+                    return;
+                }
+            },
+        };
+        let mut problems = Vec::new();
+        for chapter in &chapters {
+            match self.db.get_problems_by_chapter(chapter).await {
+                Ok(p) => problems.extend(p),
+                Err(e) => {
+                    self.job_manager.fail_job(job_id, &format!("Failed to load problems: {}", e)).await;
+                    return;
+                }
+            }
+        }
 
-    let chapter_token: String = rest
-        .chars()
-        .take_while(|c| !c.is_whitespace() && *c != '.' && *c != ':')
-        .collect();
+        self.job_manager.update_progress(job_id, 90.0, "Linking answers to problems...").await;
+        let matches = match_answers(&problems, &entries);
+        let mut linked = 0;
+        for m in &matches.matched {
+            match self.db.set_reference_answer(&m.problem_id, &m.answer).await {
+                Ok(true) => linked += 1,
+                Ok(false) => {}
+                Err(e) => errors.push(format!("Problem {}: {}", m.problem_id, e)),
+            }
+        }
 
-    if chapter_token.is_empty() {
-        return false;
-    }
+        let rows: Vec<Vec<String>> = matches
+            .matched
+            .iter()
+            .map(|m| vec![m.number.clone(), m.answer.clone(), m.problem_id.clone()])
+            .collect();
+        let artifacts = self.save_artifact(job_id, "answers.csv", to_csv(&["number", "answer", "problem_id"], &rows).into_bytes());
+
+        let result = serde_json::json!({
+            "pages": end_page - start_page + 1,
+            "answers_found": entries.len(),
+            "linked": linked,
+            "unmatched": matches.unmatched,
+            "ambiguous": matches.ambiguous,
+            "errors": errors,
+            "duration_secs": start_time.elapsed().as_secs(),
+            "artifacts": artifacts.into_iter().collect::<Vec<_>>(),
+        });
 
-    let is_digits = chapter_token.chars().all(|c| c.is_ascii_digit());
-    let is_roman = chapter_token
-        .chars()
-        .all(|c| matches!(c, 'i' | 'v' | 'x' | 'l' | 'c' | 'd' | 'm'));
+        self.job_manager.complete_job(job_id, result).await;
+    }
+}
 
-    is_digits || is_roman
+impl BatchProcessor {
+    /// Save a job artifact; failures are logged, not fatal to the job
+    fn save_artifact(&self, job_id: &str, name: &str, data: Vec<u8>) -> Option<JobArtifact> {
+        match save_artifact(&self.config.jobs_dir, job_id, name, &data) {
+            Ok(artifact) => Some(artifact),
+            Err(e) => {
+                log::warn!("Failed to save artifact {} for job {}: {}", name, job_id, e);
+                None
+            }
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::split_trailing_chapter_heading;
+/// First-pass OCR outcome of one page
+enum PageOcr {
+    Text(String),
+    /// Cached OCR exists and the run is incremental; its text is still
+    /// parsed with the rest
+    Skipped(String),
+    /// The page image is blank; no OCR call was made
+    Blank,
+}
 
-    #[test]
-    fn splits_trailing_chapter_header_into_carryover() {
-        let text = "702. Последняя задача.\nГлава 5. Разложение многочленов на множители";
-        let (page_text, carryover) = split_trailing_chapter_heading(text);
+/// Per-page outcome of a batch OCR job, saved as the `pages.json` artifact
+#[derive(Debug, serde::Serialize)]
+struct PageReport {
+    page: u32,
+    /// ok, skipped (cached, incremental run), blank, failed, timeout or pending (never reached)
+    ocr: &'static str,
+    /// parsed, unchanged (stored result of the same text reused), failed or pending
+    parse: &'static str,
+    problems: u32,
+    errors: Vec<String>,
+}
 
-        assert_eq!(page_text, "702. Последняя задача.");
-        assert_eq!(
-            carryover.as_deref(),
-            Some("Глава 5. Разложение многочленов на множители")
-        );
+impl PageReport {
+    fn new(page: u32) -> Self {
+        Self { page, ocr: "pending", parse: "pending", problems: 0, errors: Vec::new() }
     }
+}
 
-    #[test]
-    fn leaves_text_intact_without_trailing_chapter_header() {
-        let text = "701. Обычная задача без заголовка главы.";
-        let (page_text, carryover) = split_trailing_chapter_heading(text);
+impl Clone for BatchProcessor {
+    fn clone(&self) -> Self {
+        Self {
+            job_manager: self.job_manager.clone(),
+            db: self.db.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
 
-        assert_eq!(page_text, text);
-        assert!(carryover.is_none());
+fn extract_formulas(text: &str) -> Vec<String> {
+    let mut formulas = Vec::new();
+    let re = regex::Regex::new(r"\$([^$]+)\$").unwrap();
+    for cap in re.captures_iter(text) {
+        formulas.push(cap[1].to_string());
     }
+    formulas
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::{Chapter, Problem};
+use crate::services::ocr_audit::text_similarity;
+
+/// Normalized-content fingerprint of a problem.
+///
+/// Case, whitespace, punctuation and the problem's own number prefix are
+/// ignored so the same problem OCR'd from two editions hashes identically.
+pub fn content_fingerprint(content: &str) -> String {
+    let normalized: String = content
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "+-*/=^<>()".contains(c) { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let digest = Sha256::digest(normalized.as_bytes());
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterRef {
+    pub number: u32,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RenumberedProblem {
+    pub chapter_a: u32,
+    pub number_a: String,
+    pub chapter_b: u32,
+    pub number_b: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedProblem {
+    pub chapter: u32,
+    pub number: String,
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemRef {
+    pub chapter: u32,
+    pub number: String,
+    pub id: String,
+}
+
+/// Structured diff between two books (e.g. two editions)
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct BookComparison {
+    pub chapters_added: Vec<ChapterRef>,
+    pub chapters_removed: Vec<ChapterRef>,
+    pub chapters_renamed: Vec<(ChapterRef, ChapterRef)>,
+    pub problems_unchanged: usize,
+    pub problems_renumbered: Vec<RenumberedProblem>,
+    pub problems_changed: Vec<ChangedProblem>,
+    pub problems_added: Vec<ProblemRef>,
+    pub problems_removed: Vec<ProblemRef>,
+}
+
+/// Compare chapters and top-level problems of two books.
+///
+/// Problems are paired by content fingerprint first (catching renumbering),
+/// then by (chapter, number) to detect content edits.
+pub fn compare_books(
+    chapters_a: &[Chapter],
+    problems_a: &[(u32, Problem)],
+    chapters_b: &[Chapter],
+    problems_b: &[(u32, Problem)],
+) -> BookComparison {
+    let mut result = BookComparison::default();
+
+    // Chapters by number
+    let b_by_number: HashMap<u32, &Chapter> = chapters_b.iter().map(|c| (c.number, c)).collect();
+    let a_by_number: HashMap<u32, &Chapter> = chapters_a.iter().map(|c| (c.number, c)).collect();
+    for a in chapters_a {
+        match b_by_number.get(&a.number) {
+            None => result.chapters_removed.push(chapter_ref(a)),
+            Some(b) if b.title.trim().to_lowercase() != a.title.trim().to_lowercase() => {
+                result.chapters_renamed.push((chapter_ref(a), chapter_ref(b)));
+            }
+            _ => {}
+        }
+    }
+    for b in chapters_b {
+        if !a_by_number.contains_key(&b.number) {
+            result.chapters_added.push(chapter_ref(b));
+        }
+    }
+
+    // Problems by fingerprint
+    let mut b_by_fp: HashMap<String, Vec<usize>> = HashMap::new();
+    for (idx, (_, p)) in problems_b.iter().enumerate() {
+        b_by_fp.entry(content_fingerprint(&p.content)).or_default().push(idx);
+    }
+    let mut b_matched = vec![false; problems_b.len()];
+    let mut a_unmatched = Vec::new();
+
+    for (chapter_a, a) in problems_a {
+        let fp = content_fingerprint(&a.content);
+        let candidate = b_by_fp.get(&fp).and_then(|idxs| {
+            // Prefer the same number, then any unmatched copy
+            idxs.iter()
+                .copied()
+                .find(|&i| !b_matched[i] && problems_b[i].1.number == a.number)
+                .or_else(|| idxs.iter().copied().find(|&i| !b_matched[i]))
+        });
+
+        match candidate {
+            Some(i) => {
+                b_matched[i] = true;
+                let (chapter_b, b) = &problems_b[i];
+                if *chapter_b == *chapter_a && b.number == a.number {
+                    result.problems_unchanged += 1;
+                } else {
+                    result.problems_renumbered.push(RenumberedProblem {
+                        chapter_a: *chapter_a,
+                        number_a: a.number.clone(),
+                        chapter_b: *chapter_b,
+                        number_b: b.number.clone(),
+                    });
+                }
+            }
+            None => a_unmatched.push((*chapter_a, a)),
+        }
+    }
+
+    // Remaining problems with the same position are content edits
+    for (chapter_a, a) in a_unmatched {
+        let same_position = problems_b
+            .iter()
+            .enumerate()
+            .find(|(i, (ch, b))| !b_matched[*i] && *ch == chapter_a && b.number == a.number);
+
+        match same_position {
+            Some((i, (_, b))) => {
+                b_matched[i] = true;
+                result.problems_changed.push(ChangedProblem {
+                    chapter: chapter_a,
+                    number: a.number.clone(),
+                    similarity: text_similarity(&a.content, &b.content),
+                });
+            }
+            None => result.problems_removed.push(ProblemRef {
+                chapter: chapter_a,
+                number: a.number.clone(),
+                id: a.id.clone(),
+            }),
+        }
+    }
+
+    for (i, (chapter_b, b)) in problems_b.iter().enumerate() {
+        if !b_matched[i] {
+            result.problems_added.push(ProblemRef {
+                chapter: *chapter_b,
+                number: b.number.clone(),
+                id: b.id.clone(),
+            });
+        }
+    }
+
+    result
+}
+
+fn chapter_ref(chapter: &Chapter) -> ChapterRef {
+    ChapterRef {
+        number: chapter.number,
+        title: chapter.title.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(id: &str, number: &str, content: &str) -> Problem {
+        Problem {
+            id: id.to_string(),
+            number: number.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn chapter(number: u32, title: &str) -> Chapter {
+        Chapter {
+            id: format!("x:{}", number),
+            book_id: "x".to_string(),
+            number,
+            title: title.to_string(),
+            description: None,
+            problem_count: 0,
+            theory_count: 0,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn fingerprint_ignores_formatting() {
+        assert_eq!(
+            content_fingerprint("Решите уравнение:  x + 1 = 2."),
+            content_fingerprint("решите уравнение x + 1 = 2")
+        );
+    }
+
+    #[test]
+    fn detects_renumbering_edits_and_additions() {
+        let a = vec![
+            (1, problem("a:1:1", "1", "Вычислите 2 + 3")),
+            (1, problem("a:1:2", "2", "Решите x + 1 = 5")),
+            (1, problem("a:1:3", "3", "Старая задача")),
+        ];
+        let b = vec![
+            (1, problem("b:1:1", "1", "Новая задача про дроби")),
+            (1, problem("b:1:2", "2", "Вычислите 2 + 3")),
+            (1, problem("b:1:3", "3", "Старая задача, изменённая")),
+        ];
+
+        let diff = compare_books(&[chapter(1, "Дроби")], &a, &[chapter(1, "Дроби"), chapter(2, "Степени")], &b);
+
+        assert_eq!(diff.problems_renumbered.len(), 1);
+        assert_eq!(diff.problems_renumbered[0].number_b, "2");
+        assert_eq!(diff.problems_changed.len(), 1);
+        assert_eq!(diff.problems_changed[0].number, "3");
+        assert_eq!(diff.problems_removed.len(), 1);
+        assert_eq!(diff.problems_added.len(), 1);
+        assert_eq!(diff.problems_added[0].id, "b:1:1");
+        assert_eq!(diff.chapters_added.len(), 1);
+    }
+}
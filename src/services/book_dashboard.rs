@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::models::{Problem, ReviewStatus};
+use crate::services::auto_tagger::{LocalClassifier, TagCategory};
+use crate::services::validation::{validate_problem, validate_problem_sequence};
+
+/// Tags listed in a book's dashboard
+pub const DASHBOARD_TOP_TAGS: usize = 10;
+/// Finished jobs listed in a book's dashboard
+pub const DASHBOARD_RECENT_JOBS: usize = 10;
+
+/// How much of a book has been processed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Coverage {
+    pub total_pages: u32,
+    /// Pages with stored OCR text
+    pub ocr_pages: usize,
+    pub chapters: usize,
+    /// Top-level problems; sub-problems count with their parent
+    pub problems: usize,
+    pub solved: usize,
+    /// Problems approved in the review queue
+    pub reviewed: usize,
+    pub ocr_percent: f64,
+    pub solved_percent: f64,
+}
+
+/// Validation issues over all chapters of a book, by code
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationSummary {
+    pub errors: usize,
+    pub warnings: usize,
+    /// Chapters with at least one error
+    pub chapters_with_errors: usize,
+    pub by_code: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagCount {
+    pub name: String,
+    pub category: TagCategory,
+    pub count: usize,
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (part as f64 * 1000.0 / total as f64).round() / 10.0
+}
+
+pub fn coverage(total_pages: u32, ocr_pages: usize, chapters: &[(String, Vec<Problem>)]) -> Coverage {
+    let problems: Vec<&Problem> = chapters.iter().flat_map(|(_, problems)| problems).collect();
+    let solved = problems.iter().filter(|p| p.has_solution).count();
+    Coverage {
+        total_pages,
+        ocr_pages,
+        chapters: chapters.len(),
+        problems: problems.len(),
+        solved,
+        reviewed: problems.iter().filter(|p| p.review_status == ReviewStatus::Approved).count(),
+        ocr_percent: percent(ocr_pages, total_pages as usize),
+        solved_percent: percent(solved, problems.len()),
+    }
+}
+
+/// The checks of `POST /api/validate/chapter` run on every chapter
+pub fn validation_summary(chapters: &[(String, Vec<Problem>)]) -> ValidationSummary {
+    let mut summary = ValidationSummary::default();
+    for (_, problems) in chapters {
+        let mut results = vec![validate_problem_sequence(problems)];
+        results.extend(problems.iter().map(validate_problem));
+
+        let errors: usize = results.iter().map(|r| r.errors.len()).sum();
+        if errors > 0 {
+            summary.chapters_with_errors += 1;
+        }
+        summary.errors += errors;
+        for result in &results {
+            summary.warnings += result.warnings.len();
+            let codes = result.errors.iter().map(|e| &e.code).chain(result.warnings.iter().map(|w| &w.code));
+            for code in codes {
+                *summary.by_code.entry(code.clone()).or_default() += 1;
+            }
+        }
+    }
+    summary
+}
+
+/// Most frequent topic tags of the rule-based tagger; difficulty labels are
+/// left out since every problem gets one
+pub fn top_tags(problems: &[&Problem], limit: usize) -> Vec<TagCount> {
+    let classifier = LocalClassifier::new();
+    let mut counts: HashMap<String, TagCount> = HashMap::new();
+    for problem in problems {
+        for tag in classifier.tag_problem(problem).tags {
+            if tag.category == TagCategory::Difficulty {
+                continue;
+            }
+            counts
+                .entry(tag.name.clone())
+                .or_insert(TagCount { name: tag.name, category: tag.category, count: 0 })
+                .count += 1;
+        }
+    }
+    let mut tags: Vec<TagCount> = counts.into_values().collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    tags.truncate(limit);
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(number: &str, content: &str, has_solution: bool) -> Problem {
+        Problem {
+            id: format!("algebra-7:1:{}", number),
+            number: number.to_string(),
+            content: content.to_string(),
+            has_solution,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn coverage_counts_problems_and_pages() {
+        let chapters = vec![
+            ("algebra-7:1".to_string(), vec![problem("1", "a", true), problem("2", "b", false)]),
+            ("algebra-7:2".to_string(), vec![problem("1", "c", true)]),
+        ];
+        let coverage = coverage(200, 50, &chapters);
+        assert_eq!((coverage.chapters, coverage.problems, coverage.solved), (2, 3, 2));
+        assert_eq!(coverage.ocr_percent, 25.0);
+        assert_eq!(coverage.solved_percent, 66.7);
+        assert_eq!(super::coverage(0, 0, &[]).ocr_percent, 0.0);
+    }
+
+    #[test]
+    fn top_tags_skip_difficulty() {
+        let problems = [
+            problem("1", "Решите квадратное уравнение, найдите дискриминант", false),
+            problem("2", "Решите уравнение x + 1 = 2", false),
+            problem("3", "Найдите угол треугольника", false),
+        ];
+        let refs: Vec<&Problem> = problems.iter().collect();
+        let tags = top_tags(&refs, 2);
+        assert_eq!(tags[0].name, "алгебра");
+        assert_eq!(tags[0].count, 2);
+        assert_eq!(tags.len(), 2);
+        assert!(tags.iter().all(|t| t.category != TagCategory::Difficulty));
+    }
+}
@@ -0,0 +1,396 @@
+//! Cold-start profile of a book from a handful of random content pages: how
+//! its problems are numbered, its language, column layout and formula
+//! density, and what OCR and parsing all of it would cost. Probing first
+//! lets the book's settings be fixed before a batch over hundreds of pages.
+
+use lazy_regex::regex;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::services::book_settings::BookSettings;
+use crate::services::cost_estimate::{ocr_page_cost, parse_page_cost};
+use crate::services::ingestion::{IngestionPlan, PageSpan};
+use crate::services::ocr::TEXT_LAYER_PROVIDER;
+use crate::services::page_parser::{PageElement, ParsedPageContent};
+
+pub const DEFAULT_PROBE_PAGES: usize = 5;
+pub const MAX_PROBE_PAGES: usize = 20;
+
+/// Letters (outside formulas) a language is detected from
+const MIN_LANGUAGE_LETTERS: usize = 50;
+
+/// One sampled page, OCR'd and parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbedPage {
+    pub page: u32,
+    /// Provider that read the page, `pdftext` for the PDF text layer
+    pub ocr_provider: String,
+    pub chars: usize,
+    pub problem_numbers: Vec<String>,
+    /// Math spans in the OCR text
+    pub formulas: usize,
+    /// Text columns seen on the rendered page
+    pub columns: Option<u8>,
+    /// Time OCR and parsing took
+    pub seconds: f64,
+    #[serde(skip)]
+    pub text: String,
+}
+
+impl ProbedPage {
+    pub fn new(page: u32, text: String, ocr_provider: String, parsed: &ParsedPageContent, columns: Option<u8>, seconds: f64) -> Self {
+        let problem_numbers = parsed
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                PageElement::Problem(problem) => Some(problem.number.clone()),
+                _ => None,
+            })
+            .collect();
+        Self {
+            page,
+            ocr_provider,
+            chars: text.chars().count(),
+            problem_numbers,
+            formulas: math_spans(&text).count(),
+            columns,
+            seconds,
+            text,
+        }
+    }
+}
+
+/// A sampled page that could not be read
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeFailure {
+    pub page: u32,
+    pub error: String,
+}
+
+/// How problem numbers are printed at the start of a problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberingStyle {
+    /// `№ 123`
+    Numero,
+    /// `1.23`, chapter then problem
+    Dotted,
+    /// `123.`
+    Plain,
+    /// `123)`
+    Parenthesis,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnLayout {
+    Single,
+    Double,
+    /// Some sampled pages have one column, some two
+    Mixed,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DensityLevel {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FormulaDensity {
+    pub per_page: f64,
+    /// Share of the text inside math delimiters
+    pub math_share: f64,
+    pub level: DensityLevel,
+}
+
+/// Approximate cost and time of OCR'ing and parsing every planned page
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestionEstimate {
+    /// Pages the ingestion plan's batches cover
+    pub pages: u32,
+    pub ocr_provider: String,
+    /// Share of sampled pages read from the text layer, which costs nothing
+    pub text_layer_share: f64,
+    /// None when the provider's price is unknown
+    pub ocr_usd: Option<f64>,
+    /// LLM page parsing; zero when pages are parsed with regexes
+    pub parse_usd: f64,
+    pub total_usd: Option<f64>,
+    /// One page after another at the sampled speed
+    pub minutes: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BookProfile {
+    pub book_id: String,
+    pub content_pages: PageSpan,
+    pub sampled: Vec<ProbedPage>,
+    pub failed: Vec<ProbeFailure>,
+    pub numbering: NumberingStyle,
+    /// BCP 47 tag, as in the book's settings
+    pub language: Option<&'static str>,
+    pub layout: ColumnLayout,
+    pub formula_density: FormulaDensity,
+    pub problems_per_page: f64,
+    pub estimate: IngestionEstimate,
+    pub notes: Vec<String>,
+}
+
+/// Up to `count` random content pages in page order, leaving out blank,
+/// contents and answer pages
+pub fn sample_pages(plan: &IngestionPlan, count: usize, rng: &mut impl Rng) -> Vec<u32> {
+    let candidates: Vec<u32> = (plan.content_pages.start..=plan.content_pages.end)
+        .filter(|p| !plan.blank_pages.contains(p) && !plan.toc_pages.contains(p))
+        .filter(|p| plan.answer_pages.is_none_or(|span| !(span.start..=span.end).contains(p)))
+        .collect();
+    let mut sample: Vec<u32> = candidates.choose_multiple(rng, count).copied().collect();
+    sample.sort_unstable();
+    sample
+}
+
+fn math_pattern() -> &'static Regex {
+    regex!(r"\$\$[\s\S]+?\$\$|\$[^$\n]+\$")
+}
+
+fn math_spans(text: &str) -> impl Iterator<Item = &str> {
+    math_pattern().find_iter(text).map(|m| m.as_str())
+}
+
+/// The most common problem-number prefix at line starts, markdown bold allowed
+pub fn numbering_style(texts: &[&str]) -> NumberingStyle {
+    let styles = [
+        (NumberingStyle::Numero, regex!(r"(?m)^\s*(?:\*\*)?№\s*\d+")),
+        (NumberingStyle::Dotted, regex!(r"(?m)^\s*(?:\*\*)?\d+\.\d+\.?(?:\*\*)?\s")),
+        (NumberingStyle::Plain, regex!(r"(?m)^\s*(?:\*\*)?\d+\.(?:\*\*)?\s")),
+        (NumberingStyle::Parenthesis, regex!(r"(?m)^\s*(?:\*\*)?\d+\)(?:\*\*)?\s")),
+    ];
+    let counts: Vec<(NumberingStyle, usize)> = styles
+        .iter()
+        .map(|(style, pattern)| (*style, texts.iter().map(|t| pattern.find_iter(t).count()).sum()))
+        .collect();
+    // Earlier styles win ties: `1)` also marks sub-problems of numbered ones
+    counts
+        .iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .filter(|(_, count)| *count > 0)
+        .map_or(NumberingStyle::Unknown, |(style, _)| *style)
+}
+
+/// Language of the text outside formulas: Ukrainian and Russian by their
+/// letters, English by a Latin majority
+pub fn detect_language(texts: &[&str]) -> Option<&'static str> {
+    let (mut cyrillic, mut ukrainian, mut latin) = (0usize, 0usize, 0usize);
+    for text in texts {
+        let prose = math_pattern().replace_all(text, " ");
+        for c in prose.chars() {
+            match c {
+                'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ' => {
+                    cyrillic += 1;
+                    ukrainian += 1;
+                }
+                'а'..='я' | 'А'..='Я' | 'ё' | 'Ё' => cyrillic += 1,
+                'a'..='z' | 'A'..='Z' => latin += 1,
+                _ => {}
+            }
+        }
+    }
+    if cyrillic + latin < MIN_LANGUAGE_LETTERS {
+        None
+    } else if cyrillic > latin {
+        // `і` alone is about one Ukrainian letter in twenty
+        Some(if ukrainian * 50 > cyrillic { "uk" } else { "ru" })
+    } else {
+        Some("en")
+    }
+}
+
+fn column_layout(pages: &[ProbedPage]) -> ColumnLayout {
+    let columns: Vec<u8> = pages.iter().filter_map(|p| p.columns).collect();
+    match (columns.contains(&1), columns.iter().any(|&c| c >= 2)) {
+        (true, true) => ColumnLayout::Mixed,
+        (true, false) => ColumnLayout::Single,
+        (false, true) => ColumnLayout::Double,
+        (false, false) => ColumnLayout::Unknown,
+    }
+}
+
+fn formula_density(pages: &[ProbedPage]) -> FormulaDensity {
+    let count = pages.len().max(1) as f64;
+    let per_page = pages.iter().map(|p| p.formulas).sum::<usize>() as f64 / count;
+    let (math, total) = pages.iter().fold((0, 0), |(math, total), p| {
+        (math + math_spans(&p.text).map(|s| s.chars().count()).sum::<usize>(), total + p.chars)
+    });
+    let math_share = if total == 0 { 0.0 } else { math as f64 / total as f64 };
+    let level = if per_page >= 15.0 || math_share >= 0.3 {
+        DensityLevel::High
+    } else if per_page >= 3.0 || math_share >= 0.05 {
+        DensityLevel::Medium
+    } else {
+        DensityLevel::Low
+    };
+    FormulaDensity { per_page, math_share, level }
+}
+
+fn estimate(plan: &IngestionPlan, pages: &[ProbedPage], ocr_provider: &str, ai_parsing: bool) -> IngestionEstimate {
+    let planned: u32 = plan.batch_ocr.iter().map(|b| b.end_page.saturating_sub(b.start_page) + 1).sum();
+    let count = pages.len().max(1) as f64;
+    let mean_chars = pages.iter().map(|p| p.chars).sum::<usize>() as f64 / count;
+    let text_layer_share = if pages.is_empty() {
+        0.0
+    } else {
+        pages.iter().filter(|p| p.ocr_provider == TEXT_LAYER_PROVIDER).count() as f64 / count
+    };
+
+    let ocr_pages = f64::from(planned) * (1.0 - text_layer_share);
+    let ocr_usd = ocr_page_cost(ocr_provider, mean_chars).map(|cost| cost * ocr_pages);
+    let parse_usd = if ai_parsing {
+        parse_page_cost(mean_chars) * f64::from(planned)
+    } else {
+        0.0
+    };
+    let mean_seconds = pages.iter().map(|p| p.seconds).sum::<f64>() / count;
+
+    IngestionEstimate {
+        pages: planned,
+        ocr_provider: ocr_provider.to_string(),
+        text_layer_share,
+        ocr_usd,
+        parse_usd,
+        total_usd: ocr_usd.map(|ocr| ocr + parse_usd),
+        minutes: mean_seconds * f64::from(planned) / 60.0,
+    }
+}
+
+/// Profile of a book from its probed pages. `ai_parsing` is whether pages
+/// are parsed by the LLM rather than regexes.
+pub fn profile(
+    plan: &IngestionPlan,
+    settings: &BookSettings,
+    ocr_provider: &str,
+    ai_parsing: bool,
+    sampled: Vec<ProbedPage>,
+    failed: Vec<ProbeFailure>,
+) -> BookProfile {
+    let texts: Vec<&str> = sampled.iter().map(|p| p.text.as_str()).collect();
+    let numbering = numbering_style(&texts);
+    let language = detect_language(&texts);
+    let layout = column_layout(&sampled);
+    let formula_density = formula_density(&sampled);
+    let problems = sampled.iter().map(|p| p.problem_numbers.len()).sum::<usize>();
+    let problems_per_page = problems as f64 / sampled.len().max(1) as f64;
+    let estimate = estimate(plan, &sampled, ocr_provider, ai_parsing);
+
+    let mut notes = Vec::new();
+    if sampled.is_empty() {
+        notes.push("No sampled page could be read; check the OCR provider".to_string());
+    } else if numbering == NumberingStyle::Unknown {
+        notes.push("No problem numbers on the sampled pages; probe more pages or check the content range".to_string());
+    }
+    if let (Some(detected), Some(configured)) = (language, settings.language.as_deref())
+        && !configured.starts_with(detected)
+    {
+        notes.push(format!("Text looks like {} but the book's language is set to {}", detected, configured));
+    }
+    if layout != ColumnLayout::Single && layout != ColumnLayout::Unknown {
+        notes.push("Two-column pages: check that the OCR text keeps the columns apart".to_string());
+    }
+    if formula_density.level == DensityLevel::High && ocr_provider == "tesseract" {
+        notes.push("Formula-heavy pages: tesseract does not read formulas, use mistral or mathpix".to_string());
+    }
+    if estimate.text_layer_share >= 0.8 {
+        notes.push("Most pages have a usable text layer and need no OCR".to_string());
+    }
+    if estimate.ocr_usd.is_none() {
+        notes.push(format!("No price known for OCR provider {}", ocr_provider));
+    }
+
+    BookProfile {
+        book_id: plan.book_id.clone(),
+        content_pages: plan.content_pages,
+        sampled,
+        failed,
+        numbering,
+        language,
+        layout,
+        formula_density,
+        problems_per_page,
+        estimate,
+        notes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::ingestion::PlannedBatch;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn plan() -> IngestionPlan {
+        IngestionPlan {
+            book_id: "algebra".to_string(),
+            total_pages: 300,
+            content_pages: PageSpan { start: 5, end: 280 },
+            blank_pages: vec![6],
+            toc_pages: vec![5],
+            answer_pages: Some(PageSpan { start: 261, end: 280 }),
+            chapters: Vec::new(),
+            batch_ocr: vec![PlannedBatch {
+                book_id: "algebra".to_string(),
+                start_page: 7,
+                end_page: 106,
+                chapter_id: "algebra:1".to_string(),
+                incremental: true,
+            }],
+            notes: Vec::new(),
+        }
+    }
+
+    fn page(page: u32, text: &str, columns: u8) -> ProbedPage {
+        ProbedPage {
+            page,
+            ocr_provider: "mistral".to_string(),
+            chars: text.chars().count(),
+            problem_numbers: vec!["1".to_string(), "2".to_string()],
+            formulas: math_spans(text).count(),
+            columns: Some(columns),
+            seconds: 3.0,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn profiles_sampled_pages() {
+        let sample = sample_pages(&plan(), 5, &mut StdRng::seed_from_u64(7));
+        assert_eq!(sample.len(), 5);
+        assert!(sample.iter().all(|p| (7..=260).contains(p)));
+
+        let text = "**№ 12.** Решите уравнение $x^2 - 4 = 0$ и найдите сумму корней.\n\
+                    **№ 13.** Упростите выражение $\\frac{a^2 - b^2}{a - b}$, если $a \\ne b$.\n\
+                    а) $2x$; б) $3x$.";
+        let pages = vec![page(10, text, 2), page(20, text, 2)];
+        let settings = BookSettings { language: Some("en".to_string()), ..Default::default() };
+        let profile = profile(&plan(), &settings, "mistral", false, pages, Vec::new());
+
+        assert_eq!(profile.numbering, NumberingStyle::Numero);
+        assert_eq!(profile.language, Some("ru"));
+        assert_eq!(profile.layout, ColumnLayout::Double);
+        assert_eq!(profile.formula_density.per_page, 5.0);
+        assert_eq!(profile.problems_per_page, 2.0);
+        assert_eq!(profile.estimate.pages, 100);
+        assert!((profile.estimate.ocr_usd.unwrap() - 0.1).abs() < 1e-9);
+        assert_eq!(profile.estimate.minutes, 5.0);
+        assert!(profile.notes.iter().any(|n| n.contains("set to en")));
+
+        assert_eq!(numbering_style(&["1.5. Найдите\n1.6. Решите\n1) $x$"]), NumberingStyle::Dotted);
+        assert_eq!(detect_language(&["Знайдіть значення виразу, якщо змінна дорівнює нулю. Побудуйте графік функції і визначте її найбільше значення."]), Some("uk"));
+    }
+}
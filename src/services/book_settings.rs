@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{MAX_RENDER_DPI, MIN_RENDER_DPI};
+use crate::services::database::Database;
+use crate::services::provider_registry::{self, ProviderKind};
+use crate::services::FileService;
+
+/// OCR provider of books that don't name one
+pub const DEFAULT_OCR_PROVIDER: &str = "mistral";
+
+/// Pipeline options of a single book. Options left unset fall back to the
+/// global configuration, so an empty document is a valid set of settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BookSettings {
+    /// Language of the book's text as a BCP 47 tag (`ru`, `en`, `uk`)
+    pub language: Option<String>,
+    /// OCR provider for the book's pages instead of [`DEFAULT_OCR_PROVIDER`]
+    pub ocr_provider: Option<String>,
+    /// Resolution the book's pages are rendered at instead of `RENDER_DPI`
+    pub render_dpi: Option<u32>,
+    pub parser_profile: ParserProfile,
+    pub post_processing: PostProcessing,
+}
+
+/// Which parser turns a book's page text into problems
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParserProfile {
+    /// The book's own parser when there is one, otherwise the LLM parser
+    /// with the regex parser as fallback
+    #[default]
+    Auto,
+    /// The LLM parser, even for books with their own parser
+    Ai,
+    /// Only the regex parser, without LLM calls
+    Regex,
+}
+
+/// Steps run on freshly OCR'd text, see
+/// [`crate::services::ocr_rules::postprocess_ocr_text`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessing {
+    /// Join words split by line-break hyphens
+    pub dehyphenate: bool,
+    /// Run the book's OCR correction rules
+    pub ocr_rules: bool,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self { dehyphenate: true, ocr_rules: true }
+    }
+}
+
+impl BookSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(language) = &self.language {
+            let valid = language.split('-').all(|part| {
+                (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+            if !valid {
+                return Err(format!("Invalid language tag: {:?}", language));
+            }
+        }
+        if let Some(provider) = &self.ocr_provider
+            && provider_registry::capabilities(ProviderKind::Ocr, provider).is_none()
+        {
+            return Err(format!("Unknown OCR provider: {}", provider));
+        }
+        if let Some(dpi) = self.render_dpi
+            && !(MIN_RENDER_DPI..=MAX_RENDER_DPI).contains(&dpi)
+        {
+            return Err(format!("render_dpi must be between {} and {}", MIN_RENDER_DPI, MAX_RENDER_DPI));
+        }
+        Ok(())
+    }
+
+    pub fn ocr_provider(&self) -> &str {
+        self.ocr_provider.as_deref().unwrap_or(DEFAULT_OCR_PROVIDER)
+    }
+}
+
+/// `files` rendering at the book's own `render_dpi`, if it sets one
+pub async fn book_file_service(db: &Database, files: &FileService, book_id: &str) -> FileService {
+    match db.get_book_settings(book_id).await {
+        Ok(BookSettings { render_dpi: Some(dpi), .. }) => files.with_render_dpi(dpi),
+        Ok(_) => files.clone(),
+        Err(e) => {
+            log::warn!("Failed to load settings of {}: {}", book_id, e);
+            files.clone()
+        }
+    }
+}
+
+/// The book's parser profile, the default when its settings can't be read
+pub async fn book_parser_profile(db: &Database, book_id: &str) -> ParserProfile {
+    match db.get_book_settings(book_id).await {
+        Ok(settings) => settings.parser_profile,
+        Err(e) => {
+            log::warn!("Failed to load settings of {}: {}", book_id, e);
+            ParserProfile::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_documents_keep_defaults() {
+        let settings: BookSettings = serde_json::from_str(r#"{"post_processing": {"ocr_rules": false}}"#).unwrap();
+        assert_eq!(settings.ocr_provider(), DEFAULT_OCR_PROVIDER);
+        assert_eq!(settings.parser_profile, ParserProfile::Auto);
+        assert!(settings.post_processing.dehyphenate);
+        assert!(!settings.post_processing.ocr_rules);
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn refuses_unknown_values() {
+        for settings in [
+            BookSettings { language: Some("русский".to_string()), ..Default::default() },
+            BookSettings { ocr_provider: Some("abbyy".to_string()), ..Default::default() },
+            BookSettings { render_dpi: Some(2400), ..Default::default() },
+        ] {
+            assert!(settings.validate().is_err(), "{:?}", settings);
+        }
+        let settings = BookSettings {
+            language: Some("ru".to_string()),
+            ocr_provider: Some("mathpix".to_string()),
+            render_dpi: Some(300),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+        assert!(serde_json::from_str::<BookSettings>(r#"{"parser_profile": "tesseract"}"#).is_err());
+        let settings: BookSettings = serde_json::from_str(r#"{"parser_profile": "regex"}"#).unwrap();
+        assert_eq!(settings.parser_profile, ParserProfile::Regex);
+    }
+}
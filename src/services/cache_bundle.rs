@@ -0,0 +1,377 @@
+//! Portable bundles of the OCR done for a book, for reading it on a machine
+//! without API access: `booker cache export algebra-7` where OCR ran,
+//! `booker cache import algebra-7.bookers-cache.tar.zst` on the other.
+//!
+//! A bundle is a zstd-compressed tarball. `manifest.json` comes first with
+//! the book, its page rows and the artifact files by name; the file contents
+//! follow as `objects/{sha256}`, so identical files are stored once. Import
+//! checks every object against its hash and size before anything is written.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Book, Page};
+use crate::services::database::Database;
+use crate::services::{ocr_cache_file_name, FileService};
+
+/// Version of the bundle layout; bundles of another version are refused
+pub const BUNDLE_FORMAT: u32 = 1;
+pub const BUNDLE_EXTENSION: &str = "bookers-cache.tar.zst";
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const OBJECTS_DIR: &str = "objects/";
+const BUNDLE_ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: u32,
+    /// File the book was read from; artifact names are derived from it
+    pub book_file: String,
+    pub book: Book,
+    pub pages: Vec<Page>,
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    OcrCache,
+    Preview,
+    /// Image OCR cut out of a page, linked from its markdown
+    OcrImage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleFile {
+    pub kind: ArtifactKind,
+    pub page: u32,
+    /// Name in the OCR cache directory for [`ArtifactKind::OcrCache`], in
+    /// the preview directory otherwise
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BundleSummary {
+    pub pages: usize,
+    pub ocr_caches: usize,
+    pub previews: usize,
+    pub ocr_images: usize,
+    /// Files that were already there with the same contents
+    pub unchanged: usize,
+    pub bytes: u64,
+}
+
+impl BundleManifest {
+    fn summary(&self) -> BundleSummary {
+        let count = |kind| self.files.iter().filter(|f| f.kind == kind).count();
+        BundleSummary {
+            pages: self.pages.len(),
+            ocr_caches: count(ArtifactKind::OcrCache),
+            previews: count(ArtifactKind::Preview),
+            ocr_images: count(ArtifactKind::OcrImage),
+            unchanged: 0,
+            bytes: self.files.iter().map(|f| f.size).sum(),
+        }
+    }
+
+    /// Hashes are what objects are stored under, so they must be plain hex
+    fn validate(&self) -> Result<()> {
+        if self.format != BUNDLE_FORMAT {
+            bail!("Unsupported bundle format {} (expected {})", self.format, BUNDLE_FORMAT);
+        }
+        if let Some(file) = self.files.iter().find(|f| !is_sha256(&f.sha256)) {
+            bail!("Invalid hash for {}: {:?}", file.name, file.sha256);
+        }
+        Ok(())
+    }
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Manifest entry for the file at `path`
+pub fn describe_file(kind: ArtifactKind, page: u32, name: String, path: &Path) -> Result<BundleFile> {
+    let mut hasher = Sha256::new();
+    let mut file = fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let size = std::io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(BundleFile { kind, page, name, sha256: format!("{:x}", hasher.finalize()), size })
+}
+
+/// Write `manifest` and the contents of `sources`, one path per manifest
+/// file, as a bundle. A source that changed since it was described fails
+/// the export rather than producing a bundle that won't import.
+pub fn write_bundle(output: impl Write, manifest: &BundleManifest, sources: &[PathBuf]) -> Result<()> {
+    let mut builder = tar::Builder::new(zstd::Encoder::new(output, BUNDLE_ZSTD_LEVEL)?);
+    let json = serde_json::to_vec_pretty(manifest)?;
+    append(&mut builder, MANIFEST_ENTRY, &json)?;
+
+    let mut written = HashSet::new();
+    for (file, source) in manifest.files.iter().zip(sources) {
+        if !written.insert(file.sha256.as_str()) {
+            continue;
+        }
+        let bytes = fs::read(source).with_context(|| format!("Failed to read {:?}", source))?;
+        if format!("{:x}", Sha256::digest(&bytes)) != file.sha256 {
+            bail!("{:?} changed during the export", source);
+        }
+        append(&mut builder, &format!("{}{}", OBJECTS_DIR, file.sha256), &bytes)?;
+    }
+
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+fn append(builder: &mut tar::Builder<impl Write>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Objects of a bundle, verified and unpacked to a temporary directory that
+/// is removed on drop
+#[derive(Debug)]
+pub struct StagedBundle {
+    pub manifest: BundleManifest,
+    dir: PathBuf,
+}
+
+impl StagedBundle {
+    pub fn object(&self, sha256: &str) -> PathBuf {
+        self.dir.join(sha256)
+    }
+}
+
+impl Drop for StagedBundle {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Unpack a bundle, checking that every file of the manifest is there with
+/// the hash and size it lists and that nothing else is
+pub fn read_bundle(input: impl Read) -> Result<StagedBundle> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(input)?);
+    let mut entries = archive.entries()?;
+
+    let mut first = entries.next().ok_or_else(|| anyhow!("Empty bundle"))??;
+    if first.path()?.to_str() != Some(MANIFEST_ENTRY) {
+        bail!("Not a cache bundle: it doesn't start with {}", MANIFEST_ENTRY);
+    }
+    let manifest: BundleManifest = serde_json::from_reader(&mut first).context("Invalid bundle manifest")?;
+    manifest.validate()?;
+
+    let dir = std::env::temp_dir().join(format!("bookers-bundle-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let staged = StagedBundle { manifest, dir };
+
+    let expected: HashMap<&str, u64> = staged.manifest.files.iter().map(|f| (f.sha256.as_str(), f.size)).collect();
+    let mut seen = HashSet::new();
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let Some((hash, &size)) = path.strip_prefix(OBJECTS_DIR).and_then(|hash| expected.get_key_value(hash)) else {
+            bail!("Unexpected entry in bundle: {}", path);
+        };
+
+        let mut hasher = Sha256::new();
+        let mut out = fs::File::create(staged.object(hash))?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut read = 0;
+        loop {
+            let n = entry.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n])?;
+            read += n as u64;
+        }
+        if read != size || format!("{:x}", hasher.finalize()) != *hash {
+            bail!("Corrupt bundle: object {} doesn't match its hash", hash);
+        }
+        seen.insert(*hash);
+    }
+
+    if let Some(missing) = staged.manifest.files.iter().find(|f| !seen.contains(f.sha256.as_str())) {
+        bail!("Corrupt bundle: {} is missing", missing.name);
+    }
+    Ok(staged)
+}
+
+/// Bundle the OCR cache, page rows, previews and OCR images of a book into
+/// `output`
+pub async fn export_book(db: &Database, files: &FileService, reference: &str, output: &Path) -> Result<BundleSummary> {
+    let book_ref = files
+        .resolve_book(reference)
+        .ok_or_else(|| anyhow!("Book not found in the resources directory: {}", reference))?;
+    let pages = db.get_pages_by_book(&book_ref.id).await?;
+    let book = match db.get_book(&book_ref.id).await? {
+        Some(book) => book,
+        None => Book {
+            id: book_ref.id.clone(),
+            title: book_ref.id.clone(),
+            author: None,
+            subject: None,
+            file_path: format!("resources/{}", book_ref.file),
+            total_pages: 0,
+            created_at: chrono::Utc::now(),
+        },
+    };
+
+    // EPUBs have no page count; their pages are the ones OCR stored
+    let last_page = [
+        book.total_pages,
+        files.get_pdf_page_count(&book_ref.file).unwrap_or(0),
+        pages.last().map_or(0, |p| p.page_number),
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(0);
+
+    let mut artifacts = Vec::new();
+    for page in 1..=last_page {
+        if let Some(path) = files.find_ocr_cache(&book_ref.file, page) {
+            artifacts.push((ArtifactKind::OcrCache, page, ocr_cache_file_name(&book_ref.file, page), path));
+        }
+        if let Some((path, _)) = files.find_preview(&book_ref.file, page).map_err(|e| anyhow!(e))? {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            artifacts.push((ArtifactKind::Preview, page, name, path));
+        }
+    }
+    for (page, name) in files.ocr_images(&book_ref.file) {
+        let path = files.resolve_preview(&name).map_err(|e| anyhow!(e))?;
+        artifacts.push((ArtifactKind::OcrImage, page, name, path));
+    }
+
+    let mut manifest = BundleManifest { format: BUNDLE_FORMAT, book_file: book_ref.file, book, pages, files: Vec::new() };
+    let mut sources = Vec::new();
+    for (kind, page, name, path) in artifacts {
+        manifest.files.push(describe_file(kind, page, name, &path)?);
+        sources.push(path);
+    }
+
+    // Written under a temporary name, so a failed export leaves no bundle
+    let partial = output.with_extension("partial");
+    let result = fs::File::create(&partial)
+        .with_context(|| format!("Failed to create {:?}", partial))
+        .and_then(|file| write_bundle(std::io::BufWriter::new(file), &manifest, &sources))
+        .and_then(|()| fs::rename(&partial, output).with_context(|| format!("Failed to write {:?}", output)));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result.map(|()| manifest.summary())
+}
+
+/// Verify a bundle, copy its files into the OCR cache and preview
+/// directories and add its page rows. Files are only copied once the whole
+/// bundle checked out.
+pub async fn import_bundle(db: &Database, files: &FileService, bundle: &Path) -> Result<BundleSummary> {
+    let input = fs::File::open(bundle).with_context(|| format!("Failed to open {:?}", bundle))?;
+    let staged = read_bundle(std::io::BufReader::new(input))?;
+    let manifest = &staged.manifest;
+    let mut summary = manifest.summary();
+
+    for file in &manifest.files {
+        let target = match file.kind {
+            ArtifactKind::OcrCache => files.resolve_ocr_cache(&file.name),
+            ArtifactKind::Preview | ArtifactKind::OcrImage => files.resolve_preview(&file.name),
+        }
+        .map_err(|e| anyhow!(e))?;
+
+        if target.is_file() && describe_file(file.kind, file.page, file.name.clone(), &target)?.sha256 == file.sha256 {
+            summary.unchanged += 1;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let partial = target.with_extension("partial");
+        fs::copy(staged.object(&file.sha256), &partial).with_context(|| format!("Failed to write {:?}", partial))?;
+        fs::rename(&partial, &target).with_context(|| format!("Failed to write {:?}", target))?;
+    }
+
+    db.import_pages(&manifest.book, &manifest.pages).await?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: Vec<BundleFile>) -> BundleManifest {
+        BundleManifest {
+            format: BUNDLE_FORMAT,
+            book_file: "algebra-7.pdf".to_string(),
+            book: Book {
+                id: "algebra-7".to_string(),
+                title: "Алгебра 7".to_string(),
+                author: None,
+                subject: None,
+                file_path: "resources/algebra-7.pdf".to_string(),
+                total_pages: 2,
+                created_at: chrono::Utc::now(),
+            },
+            pages: Vec::new(),
+            files,
+        }
+    }
+
+    #[test]
+    fn bundles_round_trip_and_reject_corruption() {
+        let dir = std::env::temp_dir().join(format!("bundle-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("algebra-7.pdf_1.ocr_cache");
+        let preview = dir.join("algebra-7.pdf_1.png");
+        let copy = dir.join("algebra-7.pdf_2.png");
+        fs::write(&cache, "[{\"provider\": \"mistral\"}]").unwrap();
+        fs::write(&preview, [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(&copy, [0x89, b'P', b'N', b'G']).unwrap();
+
+        let sources = vec![cache.clone(), preview.clone(), copy.clone()];
+        let files = vec![
+            describe_file(ArtifactKind::OcrCache, 1, "algebra-7.pdf_1.ocr_cache".into(), &cache).unwrap(),
+            describe_file(ArtifactKind::Preview, 1, "algebra-7.pdf_1.png".into(), &preview).unwrap(),
+            describe_file(ArtifactKind::Preview, 2, "algebra-7.pdf_2.png".into(), &copy).unwrap(),
+        ];
+        let mut bundle = Vec::new();
+        write_bundle(&mut bundle, &manifest(files.clone()), &sources).unwrap();
+
+        let staged = read_bundle(bundle.as_slice()).unwrap();
+        assert_eq!(staged.manifest.files, files);
+        assert_eq!(fs::read(staged.object(&files[0].sha256)).unwrap(), fs::read(&cache).unwrap());
+        let summary = staged.manifest.summary();
+        assert_eq!((summary.ocr_caches, summary.previews), (1, 2));
+        let staged_dir = staged.dir.clone();
+        drop(staged);
+        assert!(!staged_dir.exists());
+
+        // An object that doesn't match the hash it is stored under
+        let mut tampered = files.clone();
+        tampered[0].sha256 = "0".repeat(64);
+        let mut forged = Vec::new();
+        {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(&mut forged, 1).unwrap());
+            append(&mut builder, MANIFEST_ENTRY, &serde_json::to_vec(&manifest(tampered)).unwrap()).unwrap();
+            append(&mut builder, &format!("{}{}", OBJECTS_DIR, "0".repeat(64)), b"not the hashed bytes").unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+        assert!(read_bundle(forged.as_slice()).unwrap_err().to_string().contains("doesn't match"));
+
+        // A source changed after it was described
+        fs::write(&cache, "changed").unwrap();
+        assert!(write_bundle(&mut Vec::new(), &manifest(files), &sources).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
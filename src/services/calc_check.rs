@@ -0,0 +1,242 @@
+//! Answers re-computed without a model. Problems that are purely numeric
+//! (evaluate an expression, take a percentage of a number, solve a linear
+//! equation in `x`) get their answer calculated from the problem text, so
+//! AI solutions can be cross-checked for free. Anything else is left alone:
+//! a problem with several formulas or an unusual wording has no calculation.
+
+use lazy_regex::regex;
+use serde::Serialize;
+
+use crate::models::Problem;
+use crate::services::answer_key::{final_answer, AnswerVerdict};
+use crate::services::plot::Formula;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalcKind {
+    Arithmetic,
+    Percentage,
+    LinearEquation,
+}
+
+/// The value a problem's answer must have
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Calculation {
+    pub kind: CalcKind,
+    /// What was evaluated, as found in the problem
+    pub expression: String,
+    pub value: f64,
+}
+
+/// Points a linear equation is sampled at to tell it from a non-linear one
+const LINEARITY_SAMPLES: [f64; 4] = [2.0, -3.0, 0.5, 7.0];
+
+/// Calculation of a problem's answer, if it is one of the supported kinds.
+/// Sub-problems (`а) 2,5 · 4`) take the instruction from their parent.
+pub fn calculation(problem: &Problem, parent: Option<&Problem>) -> Option<Calculation> {
+    let instruction = match parent {
+        Some(parent) => format!("{}\n{}", parent.content, problem.content),
+        None => problem.content.clone(),
+    };
+    percentage(&instruction).or_else(|| {
+        let formula = single_formula(&problem.content)?;
+        // A lone number is data ("со стороной $5$ см"), not something to compute
+        if !regex!(r"[-+−*·×/:^]|\\frac|\\sqrt|\\cdot|\\times").is_match(&formula) {
+            return None;
+        }
+        if regex!(r"(?i)уравнени|корень|корни|решите|equation|solve").is_match(&instruction) {
+            linear_equation(&formula)
+        } else if regex!(r"(?i)вычисл|значение|calculate|evaluate|compute|value\s+of").is_match(&instruction) {
+            arithmetic(&formula, &instruction)
+        } else {
+            None
+        }
+    })
+}
+
+/// The problem's only formula: a `$...$` span, or the text after a colon
+/// when the book prints the expression without markup. A given value of `x`
+/// (`при $x = 4$`) is not the formula.
+fn single_formula(content: &str) -> Option<String> {
+    let spans: Vec<&str> = regex!(r"\$\$?([^$]+?)\$\$?")
+        .captures_iter(content)
+        .filter_map(|c| c.get(1).map(|m| m.as_str().trim()))
+        .filter(|f| !f.is_empty() && !regex!(r"^x\s*=\s*-?\d+(?:[.,]\d+)?$").is_match(f))
+        .collect();
+    match spans.as_slice() {
+        [formula] => Some(formula.to_string()),
+        [] => {
+            let (_, tail) = content.split_once(':')?;
+            let tail = tail.lines().next()?.trim().trim_end_matches(['.', ';']).trim();
+            (!tail.is_empty()).then(|| tail.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// "Найдите 15% от 240", only when the problem asks for exactly one
+fn percentage(text: &str) -> Option<Calculation> {
+    let text = text.replace('$', "").replace("\\%", "%");
+    let text = regex!(r"(\d) (\d{3})\b").replace_all(&text, "$1$2");
+    let pattern = regex!(
+        r"(?i)(?:найдите|найти|вычислите|сколько\s+составляет|find|calculate|what\s+is)\s+(\d+(?:[.,]\d+)?)\s*%\s*(?:от|of)\s+(?:числа\s+)?(\d+(?:[.,]\d+)?)"
+    );
+    let mut matches = pattern.captures_iter(&text);
+    let caps = matches.next()?;
+    if matches.next().is_some() {
+        return None;
+    }
+    let percent: f64 = caps[1].replace(',', ".").parse().ok()?;
+    let whole: f64 = caps[2].replace(',', ".").parse().ok()?;
+    Some(Calculation {
+        kind: CalcKind::Percentage,
+        expression: format!("{}% of {}", &caps[1], &caps[2]),
+        value: percent / 100.0 * whole,
+    })
+}
+
+/// An expression without `x`, or one with `x` and a "при x = 4" given
+fn arithmetic(formula: &str, instruction: &str) -> Option<Calculation> {
+    // A trailing "=" is the blank for the answer: `2 + 3 \cdot 4 =`
+    let expression = formula.trim_end_matches(['=', ' ', '?']);
+    if expression.contains('=') {
+        return None;
+    }
+    let parsed = Formula::parse(expression).ok()?;
+    let x = if parsed.uses_x() {
+        let given = regex!(r"(?i)(?:при|for|when|if)\s+\$?\s*x\s*=\s*(-?\d+(?:[.,]\d+)?)").captures(instruction)?;
+        given[1].replace(',', ".").parse().ok()?
+    } else {
+        0.0
+    };
+    let value = parsed.eval(x);
+    value.is_finite().then(|| Calculation {
+        kind: CalcKind::Arithmetic,
+        expression: expression.to_string(),
+        value,
+    })
+}
+
+/// Root of `left = right` when both sides are linear in `x`
+fn linear_equation(formula: &str) -> Option<Calculation> {
+    let (left, right) = formula.split_once('=')?;
+    if right.contains('=') {
+        return None;
+    }
+    let (left, right) = (Formula::parse(left).ok()?, Formula::parse(right).ok()?);
+    if !left.uses_x() && !right.uses_x() {
+        return None;
+    }
+    let f = |x: f64| left.eval(x) - right.eval(x);
+    let (at_zero, slope) = (f(0.0), f(1.0) - f(0.0));
+    if !at_zero.is_finite() || !slope.is_finite() || slope.abs() < 1e-12 {
+        return None;
+    }
+    let linear = LINEARITY_SAMPLES.iter().all(|&x| {
+        let expected = at_zero + slope * x;
+        (f(x) - expected).abs() <= 1e-9 * expected.abs().max(1.0)
+    });
+    linear.then(|| Calculation {
+        kind: CalcKind::LinearEquation,
+        expression: formula.trim().to_string(),
+        value: -at_zero / slope,
+    })
+}
+
+/// Numeric value of a final answer and how far it may be from the exact
+/// one: answers rounded to some decimals (`≈ 3,33`) match to that precision
+fn answer_value(answer: &str) -> Option<(f64, f64)> {
+    let cleaned = answer
+        .replace('$', "")
+        .replace("\\%", "")
+        .replace('%', "")
+        .replace("\\,", "")
+        .replace("\\approx", "=")
+        .replace('≈', "=")
+        .replace("\\dfrac", "\\frac");
+    let value_text = cleaned.rsplit('=').next().unwrap_or_default().trim().trim_end_matches(['.', ';']).trim();
+    // Thousands written with a space ("1 200") are one number, not a product
+    let value_text = regex!(r"(\d) (\d{3})\b").replace_all(value_text, "$1$2");
+
+    let numbers: Vec<&str> = regex!(r"\d+(?:[.,]\d+)?").find_iter(&value_text).map(|m| m.as_str()).collect();
+    let value = match Formula::parse(&value_text) {
+        Ok(formula) if !formula.uses_x() => formula.eval(0.0),
+        // Units and words around a single number: "4 см", "36 рублей"
+        _ => match numbers.as_slice() {
+            [number] => {
+                let negative = value_text.trim_start().starts_with(['-', '−']);
+                let magnitude: f64 = number.replace(',', ".").parse().ok()?;
+                if negative { -magnitude } else { magnitude }
+            }
+            _ => return None,
+        },
+    };
+    let decimals = numbers
+        .iter()
+        .filter_map(|n| n.split_once(['.', ',']).map(|(_, fraction)| fraction.len()))
+        .max()
+        .unwrap_or(0);
+    let rounding = if decimals > 0 { 0.5 * 10f64.powi(-(decimals as i32)) } else { 0.0 };
+    value.is_finite().then(|| (value, rounding.max(1e-9 * value.abs().max(1.0))))
+}
+
+/// Compare a solution's final answer with the calculated value
+pub fn check_calculation(calculation: &Calculation, solution: &str) -> (Option<String>, AnswerVerdict) {
+    let Some(answer) = final_answer(solution) else {
+        return (None, AnswerVerdict::Unknown);
+    };
+    let verdict = match answer_value(&answer) {
+        Some((value, tolerance)) if (value - calculation.value).abs() <= tolerance => AnswerVerdict::Match,
+        Some(_) => AnswerVerdict::Mismatch,
+        None => AnswerVerdict::Unknown,
+    };
+    (Some(answer), verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(content: &str) -> Problem {
+        Problem {
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn value(content: &str) -> Option<(CalcKind, f64)> {
+        calculation(&problem(content), None).map(|c| (c.kind, c.value))
+    }
+
+    #[test]
+    fn recognises_numeric_problems() {
+        assert_eq!(value("Вычислите: $2,5 \\cdot 4 - 3$"), Some((CalcKind::Arithmetic, 7.0)));
+        assert_eq!(value("Найдите значение выражения $3x + 2$ при $x = 4$."), Some((CalcKind::Arithmetic, 14.0)));
+        assert_eq!(value("Найдите 15% от 240."), Some((CalcKind::Percentage, 36.0)));
+        assert_eq!(value("Решите уравнение $3(x - 1) = 2x + 5$"), Some((CalcKind::LinearEquation, 8.0)));
+        // Not linear, several formulas, nothing asked
+        assert_eq!(value("Решите уравнение $x^2 - 4 = 0$"), None);
+        assert_eq!(value("Вычислите $2 + 2$ и $3 + 3$"), None);
+        assert_eq!(value("Постройте график $y = 2x$"), None);
+
+        let parent = problem("Вычислите:");
+        let sub = problem("а) $\\frac{3}{4} + 0,25$");
+        assert_eq!(calculation(&sub, Some(&parent)).map(|c| c.value), Some(1.0));
+    }
+
+    #[test]
+    fn flags_wrong_answers() {
+        let equation = calculation(&problem("Решите уравнение $2x + 1 = 8$"), None).unwrap();
+        assert_eq!(check_calculation(&equation, "Ответ: $x = 3,5$").1, AnswerVerdict::Match);
+        assert_eq!(check_calculation(&equation, "Ответ: $x = \\frac{7}{2}$").1, AnswerVerdict::Match);
+        assert_eq!(check_calculation(&equation, "**Ответ:** x = 4").1, AnswerVerdict::Mismatch);
+        assert_eq!(check_calculation(&equation, "Ответ: корней нет").1, AnswerVerdict::Unknown);
+
+        let division = calculation(&problem("Вычислите: 10 : 3"), None).unwrap();
+        assert_eq!(check_calculation(&division, "Ответ: ≈ 3,33").1, AnswerVerdict::Match);
+        assert_eq!(check_calculation(&division, "Ответ: 3").1, AnswerVerdict::Mismatch);
+
+        let percent = calculation(&problem("Найдите 5% от 24 000 рублей"), None).unwrap();
+        assert_eq!(check_calculation(&percent, "Ответ: 1 200 рублей.").1, AnswerVerdict::Match);
+    }
+}
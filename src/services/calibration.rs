@@ -0,0 +1,233 @@
+//! Difficulty calibrated from how students actually did. Graded assignment
+//! answers are replayed in order as an Elo match between student and problem:
+//! a correct answer raises the student and lowers the problem, a wrong one
+//! the other way round. The static difficulty (1-10, from the parser or the
+//! book) is only the problem's starting rating and is kept as it is.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::Problem;
+use crate::services::answer_key::AnswerVerdict;
+use crate::services::assignment::Submission;
+use crate::services::database::Database;
+
+/// Rating of a difficulty-5 problem and of a new student
+const BASE_RATING: f64 = 1500.0;
+/// Rating points per difficulty step
+const RATING_PER_LEVEL: f64 = 100.0;
+const K_FACTOR: f64 = 32.0;
+/// Graded answers a problem needs before its calibration is stored
+pub const MIN_ATTEMPTS: usize = 3;
+/// Score of a correct answer that took more than [`SLOW_FACTOR`] times the
+/// student's usual time
+const SLOW_SCORE: f64 = 0.75;
+const SLOW_FACTOR: f64 = 2.0;
+/// Gaps between a student's answers longer than this are breaks, not work
+const MAX_ANSWER_SECONDS: f64 = 30.0 * 60.0;
+
+/// Empirical difficulty of one problem
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Calibration {
+    pub problem_id: String,
+    pub rating: f64,
+    /// `rating` on the 1-10 scale of [`Problem::difficulty`]
+    pub difficulty: u8,
+    pub attempts: usize,
+    pub success_rate: f64,
+    /// Median time of the answers whose time is known
+    pub median_seconds: Option<f64>,
+    pub calibrated_at: DateTime<Utc>,
+}
+
+/// One graded answer
+#[derive(Debug, Clone, PartialEq)]
+struct Attempt<'a> {
+    problem_id: &'a str,
+    student: String,
+    correct: bool,
+    /// Since the student's previous answer in the same assignment
+    seconds: Option<f64>,
+}
+
+fn prior_rating(difficulty: Option<u8>) -> f64 {
+    BASE_RATING + (f64::from(difficulty.unwrap_or(5)) - 5.0) * RATING_PER_LEVEL
+}
+
+fn rating_difficulty(rating: f64) -> u8 {
+    (5.0 + (rating - BASE_RATING) / RATING_PER_LEVEL).round().clamp(1.0, 10.0) as u8
+}
+
+/// Graded submissions, oldest first, with the time each took. Answers with
+/// an `unknown` verdict say nothing about difficulty and are left out.
+fn attempts(submissions: &[Submission]) -> Vec<Attempt<'_>> {
+    let mut ordered: Vec<&Submission> = submissions.iter().collect();
+    ordered.sort_by_key(|s| s.submitted_at);
+
+    let mut previous: HashMap<(&str, String), DateTime<Utc>> = HashMap::new();
+    let mut result = Vec::new();
+    for submission in ordered {
+        let student = submission.student_name.to_lowercase();
+        let seconds = previous
+            .insert((submission.assignment_id.as_str(), student.clone()), submission.submitted_at)
+            .map(|at| (submission.submitted_at - at).num_milliseconds() as f64 / 1000.0)
+            .filter(|s| *s > 0.0 && *s <= MAX_ANSWER_SECONDS);
+        if submission.verdict == AnswerVerdict::Unknown {
+            continue;
+        }
+        result.push(Attempt {
+            problem_id: &submission.problem_id,
+            student,
+            correct: submission.verdict == AnswerVerdict::Match,
+            seconds,
+        });
+    }
+    result
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+/// Calibrations of the problems with at least [`MIN_ATTEMPTS`] graded
+/// answers. `static_difficulty` gives each problem's starting rating;
+/// problems missing from it start at difficulty 5.
+pub fn calibrate(
+    static_difficulty: &HashMap<String, Option<u8>>,
+    submissions: &[Submission],
+    now: DateTime<Utc>,
+) -> Vec<Calibration> {
+    let attempts = attempts(submissions);
+
+    let mut usual_seconds: HashMap<&str, Vec<f64>> = HashMap::new();
+    for attempt in &attempts {
+        if let Some(seconds) = attempt.seconds {
+            usual_seconds.entry(attempt.student.as_str()).or_default().push(seconds);
+        }
+    }
+    let usual_seconds: HashMap<&str, f64> =
+        usual_seconds.into_iter().filter_map(|(student, mut times)| Some((student, median(&mut times)?))).collect();
+
+    let mut students: HashMap<&str, f64> = HashMap::new();
+    let mut problems: HashMap<&str, f64> = HashMap::new();
+    for attempt in &attempts {
+        let problem = problems
+            .entry(attempt.problem_id)
+            .or_insert_with(|| prior_rating(static_difficulty.get(attempt.problem_id).copied().flatten()));
+        let student = students.entry(attempt.student.as_str()).or_insert(BASE_RATING);
+
+        let slow = match (attempt.seconds, usual_seconds.get(attempt.student.as_str())) {
+            (Some(seconds), Some(usual)) => seconds > SLOW_FACTOR * usual,
+            _ => false,
+        };
+        let score = match (attempt.correct, slow) {
+            (true, false) => 1.0,
+            (true, true) => SLOW_SCORE,
+            (false, _) => 0.0,
+        };
+        let expected = 1.0 / (1.0 + 10f64.powf((*problem - *student) / 400.0));
+        let change = K_FACTOR * (score - expected);
+        *student += change;
+        *problem -= change;
+    }
+
+    let mut by_problem: HashMap<&str, Vec<&Attempt>> = HashMap::new();
+    for attempt in &attempts {
+        by_problem.entry(attempt.problem_id).or_default().push(attempt);
+    }
+    let mut calibrations: Vec<Calibration> = by_problem
+        .into_iter()
+        .filter(|(_, attempts)| attempts.len() >= MIN_ATTEMPTS)
+        .map(|(problem_id, attempts)| {
+            let rating = problems[problem_id];
+            let mut times: Vec<f64> = attempts.iter().filter_map(|a| a.seconds).collect();
+            Calibration {
+                problem_id: problem_id.to_string(),
+                rating,
+                difficulty: rating_difficulty(rating),
+                attempts: attempts.len(),
+                success_rate: attempts.iter().filter(|a| a.correct).count() as f64 / attempts.len() as f64,
+                median_seconds: median(&mut times),
+                calibrated_at: now,
+            }
+        })
+        .collect();
+    calibrations.sort_by(|a, b| a.problem_id.cmp(&b.problem_id));
+    calibrations
+}
+
+/// Recalibrate every problem from all submissions and store the result
+pub async fn recalibrate(db: &Database, now: DateTime<Utc>) -> Result<Vec<Calibration>> {
+    let submissions = db.get_all_submissions().await?;
+    let difficulty = db.get_submitted_problem_difficulties().await?;
+    let calibrations = calibrate(&difficulty, &submissions, now);
+    db.replace_calibrations(&calibrations).await?;
+    Ok(calibrations)
+}
+
+/// Replace the static difficulty of calibrated problems with the empirical
+/// one, for choosing problems by how hard they proved to be
+pub fn apply_calibrations(problems: &mut [Problem], calibrations: &HashMap<String, Calibration>) {
+    for problem in problems {
+        if let Some(calibration) = calibrations.get(&problem.id) {
+            problem.difficulty = Some(calibration.difficulty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::assignment::SubmissionSource;
+
+    fn answer(student: &str, problem_id: &str, correct: bool, minute: i64) -> Submission {
+        Submission {
+            id: format!("{}-{}-{}", student, problem_id, minute),
+            assignment_id: "a1".to_string(),
+            problem_id: problem_id.to_string(),
+            student_name: student.to_string(),
+            source: SubmissionSource::Typed,
+            answer: Some("4".to_string()),
+            photo_key: None,
+            verdict: if correct { AnswerVerdict::Match } else { AnswerVerdict::Mismatch },
+            late: false,
+            submitted_at: DateTime::from_timestamp(1_700_000_000 + minute * 60, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn problems_move_away_from_their_static_estimate() {
+        // Both marked 5, but nobody gets "hard" right
+        let difficulty: HashMap<String, Option<u8>> =
+            [("easy".to_string(), Some(5)), ("hard".to_string(), Some(5))].into_iter().collect();
+        let mut submissions = Vec::new();
+        for (i, student) in ["Аня", "Борис", "Вера", "Глеб"].iter().enumerate() {
+            let start = i as i64 * 100;
+            submissions.push(answer(student, "easy", true, start));
+            submissions.push(answer(student, "hard", false, start + 3));
+        }
+        submissions.push(answer("Гость", "other", true, 500));
+
+        let calibrations = calibrate(&difficulty, &submissions, Utc::now());
+        let ids: Vec<&str> = calibrations.iter().map(|c| c.problem_id.as_str()).collect();
+        assert_eq!(ids, vec!["easy", "hard"]);
+        let (easy, hard) = (&calibrations[0], &calibrations[1]);
+        assert!(easy.difficulty < 5 && hard.difficulty > 5);
+        assert_eq!((hard.attempts, hard.success_rate), (4, 0.0));
+        assert_eq!(hard.median_seconds, Some(180.0));
+        let calibrated = hard.difficulty;
+
+        let mut problems = vec![Problem { id: "hard".to_string(), difficulty: Some(5), ..Default::default() }];
+        let by_id = calibrations.into_iter().map(|c| (c.problem_id.clone(), c)).collect();
+        apply_calibrations(&mut problems, &by_id);
+        assert_eq!(problems[0].difficulty, Some(calibrated));
+    }
+}
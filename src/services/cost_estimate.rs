@@ -0,0 +1,119 @@
+//! Upfront cost and time of a batch job, from the provider prices in the
+//! registry. Batch requests over the configured budget must be confirmed
+//! before they start.
+
+use serde::Serialize;
+
+use crate::services::digest::{estimate_spend, CHARS_PER_TOKEN};
+use crate::services::provider_registry::{capabilities, ProviderKind};
+
+/// Characters OCR'd from a typical textbook page
+pub const TYPICAL_PAGE_CHARS: f64 = 2500.0;
+/// Characters of a typical generated solution
+const TYPICAL_SOLUTION_CHARS: f64 = 3000.0;
+/// Parser output per character of page text: the JSON repeats the text
+/// with its structure around it
+const PARSE_OUTPUT_RATIO: f64 = 1.5;
+
+/// Batch OCR reads this many pages at once, then parses them in turn
+const OCR_CONCURRENCY: f64 = 4.0;
+const SECONDS_PER_OCR_PAGE: f64 = 10.0;
+const SECONDS_PER_PARSE: f64 = 5.0;
+/// Solves run one after another, with a pause between them
+const SECONDS_PER_SOLVE: f64 = 30.5;
+
+/// What a batch job is expected to cost before it runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct JobEstimate {
+    /// Pages sent to the OCR provider
+    pub ocr_pages: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ocr_provider: Option<String>,
+    /// Pages parsed by the LLM page parser
+    pub parsed_pages: u32,
+    /// Solve requests sent to the solve provider
+    pub solve_calls: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub solve_provider: Option<String>,
+    pub usd: f64,
+    /// Whether every provider involved has a known price; `usd` leaves the
+    /// others out
+    pub priced: bool,
+    pub minutes: f64,
+}
+
+impl JobEstimate {
+    /// OCR of `ocr_pages` pages and LLM parsing of `parsed_pages`. Pages
+    /// that turn out to have a usable text layer cost less than estimated.
+    pub fn ocr(provider: &str, ocr_pages: u32, parsed_pages: u32) -> Self {
+        let ocr = ocr_page_cost(provider, TYPICAL_PAGE_CHARS);
+        let (ocr_pages_f, parsed_pages_f) = (f64::from(ocr_pages), f64::from(parsed_pages));
+        Self {
+            ocr_pages,
+            ocr_provider: Some(provider.to_string()),
+            parsed_pages,
+            usd: ocr.unwrap_or(0.0) * ocr_pages_f + parse_page_cost(TYPICAL_PAGE_CHARS) * parsed_pages_f,
+            // Nothing unpriced is called when every page is cached
+            priced: ocr.is_some() || ocr_pages == 0,
+            minutes: (ocr_pages_f * SECONDS_PER_OCR_PAGE / OCR_CONCURRENCY + parsed_pages_f * SECONDS_PER_PARSE) / 60.0,
+            ..Default::default()
+        }
+    }
+
+    /// `calls` solve requests to `provider`
+    pub fn solve(provider: &str, calls: u32) -> Self {
+        let priced =
+            calls == 0 || capabilities(ProviderKind::Solve, provider).is_some_and(|p| p.cost_per_1k_tokens.is_some());
+        Self {
+            solve_calls: calls,
+            solve_provider: Some(provider.to_string()),
+            usd: estimate_spend(provider, TYPICAL_SOLUTION_CHARS as u64) * f64::from(calls),
+            priced,
+            minutes: f64::from(calls) * SECONDS_PER_SOLVE / 60.0,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the job must be confirmed before it starts: over the budget,
+    /// or calling a provider whose cost isn't known
+    pub fn exceeds(&self, budget_usd: f64) -> bool {
+        self.usd > budget_usd || !self.priced
+    }
+}
+
+/// OCR cost of one page of `chars` characters; None when the provider has
+/// no known price
+pub fn ocr_page_cost(provider: &str, chars: f64) -> Option<f64> {
+    let provider = capabilities(ProviderKind::Ocr, provider)?;
+    provider
+        .cost_per_page
+        .or_else(|| provider.cost_per_1k_tokens.map(|per_1k| chars / CHARS_PER_TOKEN / 1000.0 * per_1k))
+}
+
+/// Cost of parsing a page of `chars` characters with the LLM page parser
+pub fn parse_page_cost(chars: f64) -> f64 {
+    estimate_spend("mistral", (chars * PARSE_OUTPUT_RATIO) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_pages_and_solves() {
+        let ocr = JobEstimate::ocr("mistral", 100, 0);
+        assert!((ocr.usd - 0.1).abs() < 1e-9);
+        assert!(ocr.priced && !ocr.exceeds(1.0));
+        assert!(JobEstimate::ocr("mistral", 100, 100).usd > ocr.usd);
+        assert_eq!(JobEstimate::ocr("tesseract", 700, 0).usd, 0.0);
+        assert!(!JobEstimate::ocr("kimi", 10, 0).priced);
+        assert!(JobEstimate::ocr("kimi", 10, 0).exceeds(100.0));
+        assert!(!JobEstimate::ocr("kimi", 0, 0).exceeds(100.0));
+
+        // 750 output tokens at $0.015 per 1k
+        let solve = JobEstimate::solve("claude", 40);
+        assert!((solve.usd - 0.45).abs() < 1e-9);
+        assert!(solve.exceeds(0.25));
+        assert_eq!(solve.solve_calls, 40);
+    }
+}
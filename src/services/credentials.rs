@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::services::http_client::HttpClientFactory;
+
+/// Solve providers and the env vars their keys are read from.
+///
+/// Each variable may hold several comma-separated keys; `<VAR>S` (e.g.
+/// `OPENAI_API_KEYS`) is read as well so extra keys can live separately.
+pub const PROVIDER_KEY_VARS: &[(&str, &str)] = &[
+    ("openai", "OPENAI_API_KEY"),
+    ("claude", "ANTHROPIC_API_KEY"),
+    ("mistral", "MISTRAL_API_KEY"),
+];
+
+lazy_static::lazy_static! {
+    static ref GLOBAL: Arc<ProviderCredentials> = Arc::new(ProviderCredentials::from_env());
+}
+
+/// API keys per provider with round-robin rotation on auth/rate-limit failures
+pub struct ProviderCredentials {
+    keys: HashMap<String, Vec<String>>,
+    cursors: HashMap<String, AtomicUsize>,
+    last_errors: Mutex<HashMap<(String, usize), String>>,
+}
+
+/// Masked view of a single key
+#[derive(Debug, Clone, Serialize)]
+pub struct MaskedKey {
+    pub key: String,
+    pub active: bool,
+    pub last_error: Option<String>,
+}
+
+/// Result of pinging a provider with one of its keys
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderHealth {
+    pub provider: String,
+    pub key: String,
+    pub healthy: bool,
+    pub status_code: Option<u16>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+impl ProviderCredentials {
+    pub fn new(keys: HashMap<String, Vec<String>>) -> Self {
+        let cursors = keys.keys().map(|p| (p.clone(), AtomicUsize::new(0))).collect();
+        Self {
+            keys,
+            cursors,
+            last_errors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let mut keys = HashMap::new();
+        for (provider, var) in PROVIDER_KEY_VARS {
+            let mut provider_keys: Vec<String> = Vec::new();
+            for name in [var.to_string(), format!("{}S", var)] {
+                if let Ok(value) = std::env::var(&name) {
+                    for key in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                        if !provider_keys.iter().any(|k| k == key) {
+                            provider_keys.push(key.to_string());
+                        }
+                    }
+                }
+            }
+            if !provider_keys.is_empty() {
+                keys.insert(provider.to_string(), provider_keys);
+            }
+        }
+        Self::new(keys)
+    }
+
+    /// Process-wide credentials loaded from the environment on first use
+    pub fn global() -> Arc<ProviderCredentials> {
+        GLOBAL.clone()
+    }
+
+    pub fn has_provider(&self, provider: &str) -> bool {
+        self.keys.get(provider).is_some_and(|k| !k.is_empty())
+    }
+
+    pub fn providers(&self) -> Vec<&str> {
+        let mut providers: Vec<&str> = self.keys.keys().map(|p| p.as_str()).collect();
+        providers.sort();
+        providers
+    }
+
+    pub fn key_count(&self, provider: &str) -> usize {
+        self.keys.get(provider).map(|k| k.len()).unwrap_or(0)
+    }
+
+    /// Key currently in use for a provider
+    pub fn current(&self, provider: &str) -> Option<String> {
+        let keys = self.keys.get(provider)?;
+        let idx = self.cursors.get(provider)?.load(Ordering::Relaxed) % keys.len();
+        keys.get(idx).cloned()
+    }
+
+    /// Record a failure for the active key and move on to the next one
+    pub fn rotate(&self, provider: &str, reason: &str) {
+        let (Some(keys), Some(cursor)) = (self.keys.get(provider), self.cursors.get(provider)) else {
+            return;
+        };
+        let idx = cursor.fetch_add(1, Ordering::Relaxed) % keys.len();
+        if let Ok(mut errors) = self.last_errors.lock() {
+            errors.insert((provider.to_string(), idx), reason.to_string());
+        }
+        log::warn!(
+            "Rotating {} API key ({}): {}",
+            provider,
+            mask_key(&keys[idx]),
+            reason
+        );
+    }
+
+    fn clear_error(&self, provider: &str, idx: usize) {
+        if let Ok(mut errors) = self.last_errors.lock() {
+            errors.remove(&(provider.to_string(), idx));
+        }
+    }
+
+    /// Masked keys for display, with the active one marked
+    pub fn masked(&self, provider: &str) -> Vec<MaskedKey> {
+        let Some(keys) = self.keys.get(provider) else {
+            return Vec::new();
+        };
+        let active = self.cursors.get(provider).map(|c| c.load(Ordering::Relaxed) % keys.len());
+        let errors = self.last_errors.lock().map(|e| e.clone()).unwrap_or_default();
+
+        keys.iter()
+            .enumerate()
+            .map(|(idx, key)| MaskedKey {
+                key: mask_key(key),
+                active: active == Some(idx),
+                last_error: errors.get(&(provider.to_string(), idx)).cloned(),
+            })
+            .collect()
+    }
+
+    /// Send a request built with the active key, rotating through the other
+    /// keys when the provider answers 401 or 429.
+    pub async fn send_with_rotation<F>(&self, provider: &str, build: F) -> anyhow::Result<reqwest::Response>
+    where
+        F: Fn(&str) -> reqwest::RequestBuilder,
+    {
+        let attempts = self.key_count(provider).max(1);
+        for attempt in 1..=attempts {
+            let key = self
+                .current(provider)
+                .ok_or_else(|| anyhow::anyhow!("No API key configured for {}", provider))?;
+
+            let response = build(&key).send().await?;
+            let status = response.status();
+
+            if (status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+                && attempt < attempts
+            {
+                self.rotate(provider, &format!("HTTP {}", status.as_u16()));
+                continue;
+            }
+
+            return Ok(response);
+        }
+
+        Err(anyhow::anyhow!("All {} API keys were rejected", provider))
+    }
+
+    /// Cheap authenticated request (model listing) against every key of a provider
+    pub async fn check_health(&self, provider: &str) -> Vec<ProviderHealth> {
+        let Some(keys) = self.keys.get(provider) else {
+            return Vec::new();
+        };
+        let client = HttpClientFactory::global().client(std::time::Duration::from_secs(10));
+
+        let mut results = Vec::new();
+        for (idx, key) in keys.iter().enumerate() {
+            let request = match provider {
+                "openai" => client
+                    .get("https://api.openai.com/v1/models")
+                    .header("Authorization", format!("Bearer {}", key)),
+                "claude" => client
+                    .get("https://api.anthropic.com/v1/models")
+                    .header("x-api-key", key)
+                    .header("anthropic-version", "2023-06-01"),
+                "mistral" => client
+                    .get("https://api.mistral.ai/v1/models")
+                    .header("Authorization", format!("Bearer {}", key)),
+                _ => continue,
+            };
+
+            let started = std::time::Instant::now();
+            let (healthy, status_code, error) = match request.send().await {
+                Ok(resp) => {
+                    let status = resp.status();
+                    let error = (!status.is_success()).then(|| format!("HTTP {}", status.as_u16()));
+                    (status.is_success(), Some(status.as_u16()), error)
+                }
+                Err(e) => (false, None, Some(e.to_string())),
+            };
+
+            match &error {
+                Some(e) => {
+                    if let Ok(mut errors) = self.last_errors.lock() {
+                        errors.insert((provider.to_string(), idx), e.clone());
+                    }
+                }
+                None => self.clear_error(provider, idx),
+            }
+
+            results.push(ProviderHealth {
+                provider: provider.to_string(),
+                key: mask_key(key),
+                healthy,
+                status_code,
+                latency_ms: started.elapsed().as_millis() as u64,
+                error,
+            });
+        }
+        results
+    }
+}
+
+/// Mask an API key, keeping a short prefix and the last 4 characters
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let prefix: String = chars[..3].iter().collect();
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", prefix, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn creds(keys: &[&str]) -> ProviderCredentials {
+        let mut map = HashMap::new();
+        map.insert("openai".to_string(), keys.iter().map(|k| k.to_string()).collect());
+        ProviderCredentials::new(map)
+    }
+
+    #[test]
+    fn masks_keys() {
+        assert_eq!(mask_key("sk-abcdefghijklmnop"), "sk-...mnop");
+        assert_eq!(mask_key("short"), "*****");
+    }
+
+    #[test]
+    fn rotates_round_robin() {
+        let c = creds(&["key-one-aaaa", "key-two-bbbb"]);
+        assert_eq!(c.current("openai").as_deref(), Some("key-one-aaaa"));
+        c.rotate("openai", "HTTP 401");
+        assert_eq!(c.current("openai").as_deref(), Some("key-two-bbbb"));
+        c.rotate("openai", "HTTP 429");
+        assert_eq!(c.current("openai").as_deref(), Some("key-one-aaaa"));
+    }
+
+    #[test]
+    fn masked_view_marks_active_and_errors() {
+        let c = creds(&["key-one-aaaa", "key-two-bbbb"]);
+        c.rotate("openai", "HTTP 401");
+        let masked = c.masked("openai");
+        assert!(!masked[0].active);
+        assert_eq!(masked[0].last_error.as_deref(), Some("HTTP 401"));
+        assert!(masked[1].active);
+        assert!(c.masked("claude").is_empty());
+    }
+}
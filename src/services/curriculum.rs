@@ -0,0 +1,197 @@
+//! Curriculum standards (ФГОС topics, Common Core codes) and which problems
+//! exercise them. A standard lists the auto-tagger tags that count as
+//! covering it; tagging a problem stores the codes its tags map to.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Problem;
+use crate::services::auto_tagger::Tag;
+
+/// Framework the coverage report uses when none is asked for
+pub const DEFAULT_FRAMEWORK: &str = "fgos";
+
+/// Standards stored on first start: (framework, code, title, tags)
+const DEFAULT_STANDARDS: &[(&str, &str, &str, &[&str])] = &[
+    ("fgos", "3.1", "Линейные уравнения и уравнения, сводящиеся к ним", &["алгебра"]),
+    ("fgos", "3.1.3", "Квадратные уравнения, формула корней", &["квадратные уравнения", "дискриминант", "метод дискриминанта"]),
+    ("fgos", "3.2", "Системы уравнений", &["системы уравнений"]),
+    ("fgos", "3.3", "Неравенства и их системы", &["неравенства"]),
+    ("fgos", "3.4", "Замена переменной при решении уравнений", &["метод замены переменной"]),
+    ("fgos", "7.1", "Геометрические фигуры и их свойства", &["геометрия"]),
+    ("fgos", "7.6", "Векторы на плоскости", &["вектор"]),
+    ("fgos", "2.6", "Логарифмы и их свойства", &["логарифм"]),
+    ("fgos", "2.3", "Тригонометрические выражения и уравнения", &["тригонометрия"]),
+    ("fgos", "4.1", "Производная и её применение", &["производные", "калькулус"]),
+    ("fgos", "4.2", "Первообразная и интеграл", &["интегралы"]),
+    ("fgos", "1.5", "Метод математической индукции", &["метод математической индукции"]),
+    ("common_core", "HSA-REI.B.4", "Solve quadratic equations in one variable", &["квадратные уравнения", "дискриминант"]),
+    ("common_core", "HSA-REI.C.6", "Solve systems of linear equations", &["системы уравнений"]),
+    ("common_core", "HSA-REI.B.3", "Solve linear equations and inequalities in one variable", &["неравенства", "алгебра"]),
+    ("common_core", "HSF-LE.A.4", "Express exponential solutions using logarithms", &["логарифм"]),
+    ("common_core", "HSF-TF.A.2", "Extend trigonometric functions to all real numbers", &["тригонометрия"]),
+    ("common_core", "HSN-VM.A.1", "Represent vector quantities", &["вектор"]),
+    ("common_core", "HSG-CO.C.10", "Prove theorems about triangles", &["геометрия"]),
+];
+
+/// A curriculum code and the tags that exercise it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurriculumStandard {
+    /// e.g. `fgos` or `common_core`
+    pub framework: String,
+    pub code: String,
+    pub title: String,
+    pub tags: Vec<String>,
+}
+
+impl CurriculumStandard {
+    pub fn validate(&self) -> Result<(), String> {
+        if !lazy_regex::regex_is_match!(r"^[a-z0-9_]{1,32}$", &self.framework) {
+            return Err("framework must be 1-32 lowercase letters, digits or '_'".to_string());
+        }
+        if self.code.trim().is_empty() || self.title.trim().is_empty() {
+            return Err("code and title are required".to_string());
+        }
+        if self.tags.iter().all(|t| t.trim().is_empty()) {
+            return Err("A standard needs at least one tag".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Code a problem was tagged with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProblemStandard {
+    pub problem_id: String,
+    pub framework: String,
+    pub code: String,
+    /// Set by the auto-tagger rather than by hand
+    pub auto: bool,
+}
+
+pub fn default_standards() -> Vec<CurriculumStandard> {
+    DEFAULT_STANDARDS
+        .iter()
+        .map(|(framework, code, title, tags)| CurriculumStandard {
+            framework: framework.to_string(),
+            code: code.to_string(),
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// Standards exercised by a problem with these tags; tags compare without case
+pub fn match_standards<'a>(tags: &[Tag], standards: &'a [CurriculumStandard]) -> Vec<&'a CurriculumStandard> {
+    let names: BTreeSet<String> = tags.iter().map(|t| t.name.trim().to_lowercase()).collect();
+    standards
+        .iter()
+        .filter(|s| s.tags.iter().any(|t| names.contains(&t.trim().to_lowercase())))
+        .collect()
+}
+
+/// Problems and chapters of a book exercising one standard
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StandardCoverage {
+    pub code: String,
+    pub title: String,
+    pub problems: usize,
+    /// Numbers of the chapters with such problems
+    pub chapters: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoverageReport {
+    pub framework: String,
+    /// Every standard of the framework, exercised or not, in code order
+    pub standards: Vec<StandardCoverage>,
+    pub covered: usize,
+    pub total: usize,
+    pub covered_percent: f64,
+    /// Problems without any code of the framework
+    pub untagged_problems: usize,
+}
+
+/// Coverage of a framework's standards by a book whose problems carry the
+/// given `(chapter number, problem id, code)` tags, out of `problem_count`
+/// problems
+pub fn coverage_report(
+    framework: &str,
+    standards: &[CurriculumStandard],
+    tagged: &[(u32, String, String)],
+    problem_count: usize,
+) -> CoverageReport {
+    let mut by_code: HashMap<&str, (BTreeSet<&str>, BTreeSet<u32>)> = HashMap::new();
+    for (chapter, problem_id, code) in tagged {
+        let entry = by_code.entry(code.as_str()).or_default();
+        entry.0.insert(problem_id);
+        entry.1.insert(*chapter);
+    }
+
+    let mut report: Vec<StandardCoverage> = standards
+        .iter()
+        .filter(|s| s.framework == framework)
+        .map(|s| {
+            let (problems, chapters) = by_code.get(s.code.as_str()).cloned().unwrap_or_default();
+            StandardCoverage {
+                code: s.code.clone(),
+                title: s.title.clone(),
+                problems: problems.len(),
+                chapters: chapters.into_iter().collect(),
+            }
+        })
+        .collect();
+    report.sort_by_cached_key(|s| Problem::numeric_order(&s.code));
+
+    let covered = report.iter().filter(|s| s.problems > 0).count();
+    let tagged_problems: BTreeSet<&str> = tagged.iter().map(|(_, id, _)| id.as_str()).collect();
+    CoverageReport {
+        framework: framework.to_string(),
+        total: report.len(),
+        covered,
+        covered_percent: if report.is_empty() { 0.0 } else { (covered as f64 * 1000.0 / report.len() as f64).round() / 10.0 },
+        untagged_problems: problem_count.saturating_sub(tagged_problems.len()),
+        standards: report,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::auto_tagger::TagCategory;
+
+    fn tag(name: &str) -> Tag {
+        Tag { name: name.to_string(), category: TagCategory::Topic, confidence: 0.9 }
+    }
+
+    #[test]
+    fn maps_tags_to_codes() {
+        let standards = default_standards();
+        assert!(standards.iter().all(|s| s.validate().is_ok()));
+
+        let codes: Vec<&str> = match_standards(&[tag("Квадратные уравнения")], &standards)
+            .into_iter()
+            .map(|s| s.code.as_str())
+            .collect();
+        assert_eq!(codes, ["3.1.3", "HSA-REI.B.4"]);
+        assert!(match_standards(&[tag("стереометрия")], &standards).is_empty());
+    }
+
+    #[test]
+    fn reports_covered_and_missing_standards() {
+        let standards = default_standards();
+        let tagged = vec![
+            (1, "a:1:1".to_string(), "3.1.3".to_string()),
+            (2, "a:2:4".to_string(), "3.1.3".to_string()),
+            (2, "a:2:4".to_string(), "3.2".to_string()),
+            (2, "a:2:5".to_string(), "HSA-REI.B.4".to_string()),
+        ];
+        let report = coverage_report("fgos", &standards, &tagged, 5);
+        let quadratic = report.standards.iter().find(|s| s.code == "3.1.3").unwrap();
+        assert_eq!((quadratic.problems, quadratic.chapters.clone()), (2, vec![1, 2]));
+        assert_eq!((report.covered, report.total), (2, 12));
+        assert_eq!(report.untagged_problems, 2);
+        assert_eq!(report.standards[0].code, "1.5");
+    }
+}
@@ -1,6 +1,31 @@
-use crate::models::problem::{Chapter, Problem, Solution, TheoryBlock, Book};
+use crate::models::problem::{Bookmark, Chapter, ChapterProblem, Problem, ProblemDeletion, ProblemHint, ProblemPage, ProblemWrite, ProblemWriteOutcome, ReviewProgress, ReviewStatus, Solution, SolutionAttachment, SolutionFilter, TheoryBlock, Book};
+use crate::services::answer_key::AnswerVerdict;
+use crate::services::assignment::{Assignment, Submission, SubmissionSource};
+use crate::services::background::JobRecord;
+use crate::services::curriculum::{default_standards, CurriculumStandard, ProblemStandard};
+use crate::services::digest::{DigestReport, DigestStats, ProviderSolutions};
+use crate::services::explain::Explanation;
+use crate::services::figure::ProblemFigure;
+use crate::services::glossary::{term_key, GlossaryEntry};
+use crate::services::group::Group;
+use crate::services::book_settings::BookSettings;
+use crate::services::calibration::Calibration;
+use crate::services::idempotency::{IdempotencyClaim, StoredResponse};
+use crate::services::export_diff::SnapshotProblem;
+use crate::services::export_history::ExportRun;
+use crate::services::share_link::{generate_secret, ShareLink, ShareTarget};
+use crate::services::text_search::{mark_snippet, SearchHit, SearchKind, RAW_SNIPPET_END, RAW_SNIPPET_START};
+use crate::services::issues::{Issue, IssueComment, IssueEntity, IssueFilter, IssueStatus};
+use crate::services::ui_preferences::UiPreferences;
+use crate::services::latex_macros::BookMacros;
+use crate::services::heading_detector::HeadingOverrides;
+use crate::services::ocr_audit::OcrAuditEntry;
+use crate::services::solution_cleanup::CleanupPlan;
+use crate::services::ocr_rules::OcrRule;
+use crate::services::worksheet::Worksheet;
 use anyhow::Result;
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use sqlx::{sqlite::SqlitePoolOptions, Connection, Pool, QueryBuilder, Sqlite, SqliteConnection};
+use std::collections::{HashMap, HashSet};
 
 /// Database service for storing and retrieving textbook data
 #[derive(Clone)]
@@ -22,6 +47,26 @@ impl Database {
         Ok(db)
     }
 
+    /// Run several writes as one transaction: committed when `f` returns Ok,
+    /// rolled back when it fails or is dropped half-way (e.g. the request is
+    /// cancelled), so a handler creating book, chapter, page and problems
+    /// never leaves part of them behind.
+    pub async fn transaction<T>(&self, f: impl AsyncFnOnce(&mut DbTransaction) -> Result<T>) -> Result<T> {
+        let mut tx = DbTransaction { tx: self.pool.begin().await? };
+        match f(&mut tx).await {
+            Ok(value) => {
+                tx.tx.commit().await?;
+                Ok(value)
+            }
+            // Rolled back explicitly: a dropped transaction releases its
+            // write lock only once the pool gets to it, failing the next write
+            Err(e) => {
+                tx.tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
     /// Initialize database schema
     async fn init(&self) -> Result<()> {
         sqlx::query(
@@ -150,6 +195,370 @@ impl Database {
 
             CREATE INDEX IF NOT EXISTS idx_view_history_problem ON view_history(problem_id);
             CREATE INDEX IF NOT EXISTS idx_view_history_date ON view_history(viewed_at DESC);
+
+            -- Re-OCR comparisons produced by the OCR quality audit job
+            CREATE TABLE IF NOT EXISTS ocr_audits (
+                id TEXT PRIMARY KEY,
+                book_id TEXT NOT NULL,
+                page_number INTEGER NOT NULL,
+                provider TEXT NOT NULL,
+                similarity REAL NOT NULL,
+                diverged BOOLEAN DEFAULT FALSE,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_ocr_audits_book ON ocr_audits(book_id, page_number);
+
+            CREATE TABLE IF NOT EXISTS worksheets (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                problem_ids TEXT NOT NULL, -- JSON array, in worksheet order
+                seed INTEGER NOT NULL,
+                include_answer_key BOOLEAN DEFAULT TRUE,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+
+            -- Per-book regex find/replace run after OCR, before parsing
+            CREATE TABLE IF NOT EXISTS ocr_rules (
+                id TEXT PRIMARY KEY,
+                book_id TEXT NOT NULL,
+                pattern TEXT NOT NULL,
+                replacement TEXT NOT NULL DEFAULT '',
+                position INTEGER NOT NULL DEFAULT 0,
+                enabled BOOLEAN DEFAULT TRUE,
+                description TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_ocr_rules_book ON ocr_rules(book_id, position);
+
+            -- Finished background jobs; pruned after JOB_HISTORY_RETENTION_DAYS
+            CREATE TABLE IF NOT EXISTS jobs_history (
+                id TEXT PRIMARY KEY,
+                job_type TEXT NOT NULL,
+                params TEXT NOT NULL, -- JSON
+                status TEXT NOT NULL,
+                summary TEXT,
+                result TEXT, -- JSON
+                error TEXT,
+                created_at DATETIME NOT NULL,
+                finished_at DATETIME NOT NULL,
+                duration_ms INTEGER NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_jobs_history_type ON jobs_history(job_type, finished_at DESC);
+
+            -- AI explanations of passages selected in the reader, keyed by selection hash
+            CREATE TABLE IF NOT EXISTS explanations (
+                id TEXT PRIMARY KEY,
+                book_id TEXT NOT NULL,
+                page_number INTEGER NOT NULL,
+                selected_text TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            -- LLM-written definitions of concepts that only appear in problems
+            CREATE TABLE IF NOT EXISTS glossary_terms (
+                book_id TEXT NOT NULL,
+                term_key TEXT NOT NULL,
+                term TEXT NOT NULL,
+                definition TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (book_id, term_key),
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            -- Per-book LaTeX macros (JSON object name -> definition) and extra preamble
+            CREATE TABLE IF NOT EXISTS book_latex_macros (
+                book_id TEXT PRIMARY KEY,
+                macros TEXT NOT NULL DEFAULT '{}',
+                preamble TEXT,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            -- Per-book chapter heading patterns (JSON array of regexes)
+            CREATE TABLE IF NOT EXISTS book_heading_patterns (
+                book_id TEXT PRIMARY KEY,
+                patterns TEXT NOT NULL DEFAULT '[]',
+                replace_defaults BOOLEAN DEFAULT FALSE,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            -- Per-book pipeline options (JSON document, see BookSettings)
+            CREATE TABLE IF NOT EXISTS book_settings (
+                book_id TEXT PRIMARY KEY,
+                settings TEXT NOT NULL DEFAULT '{}',
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE
+            );
+
+            -- Page look per preference profile (JSON document, see UiPreferences)
+            CREATE TABLE IF NOT EXISTS ui_preferences (
+                profile TEXT PRIMARY KEY,
+                preferences TEXT NOT NULL DEFAULT '{}',
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+
+            -- Responses of requests sent with an Idempotency-Key; status is NULL
+            -- while the first request is still running
+            CREATE TABLE IF NOT EXISTS idempotency_keys (
+                key TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL,
+                status INTEGER,
+                content_type TEXT,
+                body BLOB,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+
+            -- Latest AI hint per problem and level
+            CREATE TABLE IF NOT EXISTS problem_hints (
+                problem_id TEXT NOT NULL,
+                hint_level INTEGER NOT NULL,
+                provider TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (problem_id, hint_level),
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            -- Solutions removed by the duplicate cleanup, with the one kept instead
+            CREATE TABLE IF NOT EXISTS archived_solutions (
+                id TEXT PRIMARY KEY,
+                problem_id TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                content TEXT NOT NULL,
+                latex_formulas TEXT,
+                is_verified BOOLEAN DEFAULT FALSE,
+                rating INTEGER,
+                created_at DATETIME,
+                updated_at DATETIME,
+                kept_solution_id TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                archived_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            -- Pages a problem's statement is printed on, with the text of each part
+            CREATE TABLE IF NOT EXISTS problem_pages (
+                problem_id TEXT NOT NULL,
+                page_id TEXT NOT NULL,
+                page_number INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                continues_from_prev BOOLEAN DEFAULT FALSE,
+                continues_to_next BOOLEAN DEFAULT FALSE,
+                PRIMARY KEY (problem_id, page_number),
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_problem_pages_page ON problem_pages(page_id);
+
+            -- Files attached to solutions; the bytes are in the attachment storage
+            CREATE TABLE IF NOT EXISTS solution_attachments (
+                id TEXT PRIMARY KEY,
+                solution_id TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                content_type TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                storage_key TEXT NOT NULL,
+                plot_spec TEXT, -- JSON PlotSpec for generated plots
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (solution_id) REFERENCES solutions(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_solution_attachments_solution ON solution_attachments(solution_id);
+
+            -- Figures drawn for problems, e.g. geometry drawings reconstructed from the text
+            CREATE TABLE IF NOT EXISTS problem_figures (
+                problem_id TEXT PRIMARY KEY,
+                provider TEXT NOT NULL,
+                description TEXT NOT NULL, -- JSON FigureDescription
+                reconstructed INTEGER NOT NULL DEFAULT 1,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            -- Curriculum standards and the auto-tagger tags that exercise them
+            CREATE TABLE IF NOT EXISTS curricula (
+                framework TEXT NOT NULL,
+                code TEXT NOT NULL,
+                title TEXT NOT NULL,
+                tags TEXT NOT NULL, -- JSON array of tag names
+                PRIMARY KEY (framework, code)
+            );
+
+            -- Curriculum codes problems were tagged with
+            CREATE TABLE IF NOT EXISTS problem_standards (
+                problem_id TEXT NOT NULL,
+                framework TEXT NOT NULL,
+                code TEXT NOT NULL,
+                auto BOOLEAN NOT NULL DEFAULT TRUE,
+                PRIMARY KEY (problem_id, framework, code),
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            -- Problems handed out to students through a share link
+            CREATE TABLE IF NOT EXISTS assignments (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                instructions TEXT,
+                share_token TEXT NOT NULL UNIQUE,
+                due_at DATETIME NOT NULL,
+                created_at DATETIME NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS assignment_problems (
+                assignment_id TEXT NOT NULL,
+                problem_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                PRIMARY KEY (assignment_id, problem_id),
+                FOREIGN KEY (assignment_id) REFERENCES assignments(id) ON DELETE CASCADE,
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+
+            -- Students' answers to assignment problems, typed or OCR'd from a photo
+            CREATE TABLE IF NOT EXISTS submissions (
+                id TEXT PRIMARY KEY,
+                assignment_id TEXT NOT NULL,
+                problem_id TEXT NOT NULL,
+                student_name TEXT NOT NULL,
+                source TEXT NOT NULL, -- typed | photo
+                answer TEXT,
+                photo_key TEXT,
+                verdict TEXT NOT NULL, -- match | mismatch | unknown
+                late BOOLEAN NOT NULL DEFAULT FALSE,
+                submitted_at DATETIME NOT NULL,
+                FOREIGN KEY (assignment_id) REFERENCES assignments(id) ON DELETE CASCADE,
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
+            CREATE INDEX IF NOT EXISTS idx_submissions_assignment ON submissions(assignment_id);
+
+            -- Groups of students ("7Б"); members are the names they submit under
+            CREATE TABLE IF NOT EXISTS student_groups (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at DATETIME NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS group_members (
+                group_id TEXT NOT NULL,
+                member_key TEXT NOT NULL, -- lowercased name; NOCASE only folds ASCII
+                student_name TEXT NOT NULL,
+                PRIMARY KEY (group_id, member_key),
+                FOREIGN KEY (group_id) REFERENCES student_groups(id) ON DELETE CASCADE
+            );
+
+            -- Assignments handed to a group
+            CREATE TABLE IF NOT EXISTS group_assignments (
+                group_id TEXT NOT NULL,
+                assignment_id TEXT NOT NULL,
+                PRIMARY KEY (group_id, assignment_id),
+                FOREIGN KEY (group_id) REFERENCES student_groups(id) ON DELETE CASCADE,
+                FOREIGN KEY (assignment_id) REFERENCES assignments(id) ON DELETE CASCADE
+            );
+
+            -- Content issues about any record; entity_id isn't a foreign key since it
+            -- points at different tables, so issues outlive the record
+            CREATE TABLE IF NOT EXISTS issues (
+                id TEXT PRIMARY KEY,
+                entity_type TEXT NOT NULL, -- book | chapter | page | problem | solution
+                entity_id TEXT NOT NULL,
+                book_id TEXT NOT NULL,
+                title TEXT NOT NULL,
+                body TEXT,
+                status TEXT NOT NULL DEFAULT 'open', -- open | in_progress | resolved | wont_fix
+                assignee TEXT,
+                created_by TEXT,
+                created_at DATETIME NOT NULL,
+                updated_at DATETIME NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_issues_book_status ON issues(book_id, status);
+            CREATE INDEX IF NOT EXISTS idx_issues_entity ON issues(entity_type, entity_id);
+
+            CREATE TABLE IF NOT EXISTS issue_comments (
+                id TEXT PRIMARY KEY,
+                issue_id TEXT NOT NULL,
+                author TEXT,
+                body TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+                FOREIGN KEY (issue_id) REFERENCES issues(id) ON DELETE CASCADE
+            );
+
+            -- Book and chapter exports with a hash of each problem they contained,
+            -- kept after the problems change or go so exports can be compared
+            CREATE TABLE IF NOT EXISTS export_runs (
+                id TEXT PRIMARY KEY,
+                book_id TEXT NOT NULL,
+                chapter_id TEXT,
+                format TEXT NOT NULL,
+                created_at DATETIME NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_export_runs_book ON export_runs(book_id, created_at);
+
+            CREATE TABLE IF NOT EXISTS export_run_problems (
+                run_id TEXT NOT NULL,
+                problem_id TEXT NOT NULL,
+                chapter_id TEXT NOT NULL,
+                number TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                PRIMARY KEY (run_id, problem_id),
+                FOREIGN KEY (run_id) REFERENCES export_runs(id) ON DELETE CASCADE
+            );
+
+            -- Expiring public links; target_id isn't a foreign key since it points at
+            -- different tables, a link to a deleted record shows "not found"
+            CREATE TABLE IF NOT EXISTS share_links (
+                id TEXT PRIMARY KEY,
+                target_type TEXT NOT NULL, -- problem | chapter | worksheet
+                target_id TEXT NOT NULL,
+                expires_at DATETIME NOT NULL,
+                created_at DATETIME NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_share_links_expiry ON share_links(expires_at);
+
+            -- Keys generated on first use (e.g. for signing share links)
+            CREATE TABLE IF NOT EXISTS app_secrets (
+                name TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+
+            -- Weekly digests, newest by period_end
+            CREATE TABLE IF NOT EXISTS digest_reports (
+                id TEXT PRIMARY KEY,
+                period_start DATETIME NOT NULL,
+                period_end DATETIME NOT NULL,
+                stats TEXT NOT NULL, -- JSON DigestStats
+                markdown TEXT NOT NULL,
+                html TEXT NOT NULL,
+                delivered BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at DATETIME NOT NULL
+            );
+
+            -- Failed formula OCR rounds per problem. Not tied to the problems table:
+            -- re-OCR deletes and recreates a page's problems and must not reset it.
+            CREATE TABLE IF NOT EXISTS formula_attempts (
+                problem_id TEXT PRIMARY KEY,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+
+            -- Empirical difficulty from graded answers, replaced on each recalibration
+            CREATE TABLE IF NOT EXISTS problem_calibrations (
+                problem_id TEXT PRIMARY KEY,
+                rating REAL NOT NULL,
+                difficulty INTEGER NOT NULL,
+                attempts INTEGER NOT NULL,
+                success_rate REAL NOT NULL,
+                median_seconds REAL,
+                calibrated_at DATETIME NOT NULL,
+                FOREIGN KEY (problem_id) REFERENCES problems(id) ON DELETE CASCADE
+            );
             "#
         )
         .execute(&self.pool)
@@ -161,7 +570,149 @@ impl Database {
         self.migrate_problems_table_uniqueness().await?;
         // Ensure indexes exist after any migration/rebuild.
         self.ensure_problem_indexes().await?;
+        // Migration: bookmark folders and notes
+        self.ensure_columns("bookmarks", &[("folder", "TEXT"), ("note", "TEXT")]).await?;
+        // Migration: per-problem extraction confidence
+        self.ensure_columns("problems", &[("confidence", "REAL")]).await?;
+        // Migration: review workflow state
+        self.ensure_columns("problems", &[
+            ("review_status", "TEXT DEFAULT 'unreviewed'"),
+            ("review_note", "TEXT"),
+            ("reviewed_at", "DATETIME"),
+        ]).await?;
+        // Migration: answers parsed from the book's answers section
+        self.ensure_columns("problems", &[("reference_answer", "TEXT")]).await?;
+        // Migration: blank/text/image_only classification of page images
+        self.ensure_columns("pages", &[("page_kind", "TEXT")]).await?;
+        // Migration: export history with the stored file of each export
+        self.ensure_columns("export_runs", &[
+            ("filters", "TEXT"),
+            ("file_name", "TEXT"),
+            ("content_type", "TEXT"),
+            ("size", "INTEGER"),
+            ("checksum", "TEXT"),
+            ("storage_key", "TEXT"),
+            ("duration_ms", "INTEGER"),
+            ("error", "TEXT"),
+        ]).await?;
+        // Migration: OCR provider that read the page ("pdftext" for its text layer)
+        self.ensure_columns("pages", &[("ocr_provider", "TEXT")]).await?;
+        // Migration: last parse of the page's OCR text, reused while its fingerprint matches
+        self.ensure_columns("pages", &[("parse_fingerprint", "TEXT"), ("parse_result", "TEXT")]).await?;
+        // Migration: model parameters solutions were generated with (JSON)
+        self.ensure_columns("solutions", &[("generation_params", "TEXT")]).await?;
+        self.ensure_columns("archived_solutions", &[("generation_params", "TEXT")]).await?;
+        // Migration: solve cache key of generated solutions
+        self.ensure_columns("solutions", &[("prompt_hash", "TEXT")]).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_solutions_prompt_hash ON solutions(prompt_hash)")
+            .execute(&self.pool)
+            .await?;
+        // Migration: edit counters for version-checked updates
+        self.ensure_columns("problems", &[("version", "INTEGER NOT NULL DEFAULT 1")]).await?;
+        self.ensure_columns("solutions", &[("version", "INTEGER NOT NULL DEFAULT 1")]).await?;
+        // Migration: last comparison with the book's answer, cleared when the content changes
+        self.ensure_columns("solutions", &[("answer_verdict", "TEXT"), ("answer_checked_at", "DATETIME")]).await?;
+        // Migration: problem IDs built from raw OCR numbers ("71." next to "71")
+        self.merge_unnormalized_problem_ids().await?;
+        // Migration: natural sort key of problem numbers and alphabet position
+        // of sub-problem letters
+        self.ensure_columns("problems", &[("numeric_order", "TEXT"), ("sub_position", "INTEGER")]).await?;
+        self.fill_problem_sort_keys().await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_problems_numeric_order ON problems(chapter_id, numeric_order)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_problems_sub_position ON problems(parent_id, sub_position)")
+            .execute(&self.pool)
+            .await?;
+        self.ensure_search_index().await?;
+        self.seed_curricula().await?;
+
+        Ok(())
+    }
+
+    /// FTS5 indexes of problem, theory and solution text. They read the text
+    /// from the tables themselves (external content, matched by rowid) and
+    /// triggers keep them in sync with inserts, updates and deletes,
+    /// cascading ones included. Filled from existing rows when first created.
+    async fn ensure_search_index(&self) -> Result<()> {
+        for (table, index) in [("problems", "problems_fts"), ("theory_blocks", "theory_fts"), ("solutions", "solutions_fts")] {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1"
+            )
+            .bind(index)
+            .fetch_one(&self.pool)
+            .await?;
+
+            sqlx::query(&format!(
+                r#"
+                CREATE VIRTUAL TABLE IF NOT EXISTS {index} USING fts5(
+                    content, content = '{table}', content_rowid = 'rowid',
+                    tokenize = 'unicode61 remove_diacritics 2'
+                );
+                CREATE TRIGGER IF NOT EXISTS {index}_insert AFTER INSERT ON {table} BEGIN
+                    INSERT INTO {index}(rowid, content) VALUES (new.rowid, new.content);
+                END;
+                CREATE TRIGGER IF NOT EXISTS {index}_delete AFTER DELETE ON {table} BEGIN
+                    INSERT INTO {index}({index}, rowid, content) VALUES ('delete', old.rowid, old.content);
+                END;
+                CREATE TRIGGER IF NOT EXISTS {index}_update AFTER UPDATE OF content ON {table} BEGIN
+                    INSERT INTO {index}({index}, rowid, content) VALUES ('delete', old.rowid, old.content);
+                    INSERT INTO {index}(rowid, content) VALUES (new.rowid, new.content);
+                END;
+                "#
+            ))
+            .execute(&self.pool)
+            .await?;
+
+            if !exists {
+                sqlx::query(&format!("INSERT INTO {index}({index}) VALUES ('rebuild')"))
+                    .execute(&self.pool)
+                    .await?;
+                log::info!("Built full-text index {} of {}", index, table);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the built-in standards when there are none yet, so deleting
+    /// them later sticks
+    async fn seed_curricula(&self) -> Result<()> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM curricula")
+            .fetch_one(&self.pool)
+            .await?;
+        if count > 0 {
+            return Ok(());
+        }
+        for standard in default_standards() {
+            self.save_curriculum_standard(&standard).await?;
+        }
+        Ok(())
+    }
+
+    /// Compute the sort keys of rows stored before they existed, see
+    /// [`Problem::numeric_order`] and [`Problem::sub_position`]
+    async fn fill_problem_sort_keys(&self) -> Result<()> {
+        let rows: Vec<(String, String, bool)> = sqlx::query_as(
+            r#"SELECT id, number, parent_id IS NOT NULL FROM problems
+               WHERE numeric_order IS NULL OR (parent_id IS NOT NULL AND sub_position IS NULL)"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(());
+        }
 
+        let mut tx = self.pool.begin().await?;
+        for (id, number, is_sub) in &rows {
+            sqlx::query("UPDATE problems SET numeric_order = ?2, sub_position = ?3 WHERE id = ?1")
+                .bind(id)
+                .bind(Problem::numeric_order(number))
+                .bind(is_sub.then(|| Problem::sub_position(number)).flatten().map(|p| p as i64))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        log::debug!("Computed sort keys of {} problems", rows.len());
         Ok(())
     }
     
@@ -193,6 +744,28 @@ impl Database {
         Ok(())
     }
 
+    /// Add any of the given columns missing from `table`
+    async fn ensure_columns(&self, table: &str, columns: &[(&str, &str)]) -> Result<()> {
+        for (col, col_type) in columns {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2"
+            )
+            .bind(table)
+            .bind(col)
+            .fetch_one(&self.pool)
+            .await?;
+
+            if !exists {
+                sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, col, col_type))
+                    .execute(&self.pool)
+                    .await?;
+                log::info!("Added column {} to {} table", col, table);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Ensure indexes/constraints (implemented as indexes) exist on the `problems` table.
     async fn ensure_problem_indexes(&self) -> Result<()> {
         // Split out from the big init SQL so we can re-apply after table rebuilds.
@@ -330,29 +903,130 @@ impl Database {
         Ok(())
     }
 
+    /// Problem IDs used to embed the number as OCR read it, so the same
+    /// problem could be stored as both `…:71.` and `…:71`. Give each such
+    /// problem its normalized number and ID, merging it (with its solutions,
+    /// hints, bookmarks and sub-problems) into the problem already there.
+    async fn merge_unnormalized_problem_ids(&self) -> Result<()> {
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT id, chapter_id, number FROM problems WHERE parent_id IS NULL AND id = chapter_id || ':' || number"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let stale: Vec<_> = rows
+            .into_iter()
+            .filter(|(_, _, number)| Problem::normalize_number(number) != *number)
+            .collect();
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *tx)
+            .await?;
+
+        let mut merged = 0;
+        for (old_id, chapter_id, number) in &stale {
+            let number = Problem::normalize_number(number);
+            let new_id = format!("{}:{}", chapter_id, number);
+            let keeper: Option<String> = sqlx::query_scalar(
+                r#"SELECT id FROM problems
+                   WHERE parent_id IS NULL AND id != ?1
+                     AND (id = ?2 OR (chapter_id = ?3 AND number = ?4))"#
+            )
+            .bind(old_id)
+            .bind(&new_id)
+            .bind(chapter_id)
+            .bind(&number)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let Some(keeper) = keeper else {
+                sqlx::query("UPDATE problems SET id = ?2, number = ?3, numeric_order = ?4 WHERE id = ?1")
+                    .bind(old_id)
+                    .bind(&new_id)
+                    .bind(&number)
+                    .bind(Problem::numeric_order(&number))
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query(
+                    "UPDATE problems SET id = ?2 || substr(id, length(?1) + 1), parent_id = ?2 WHERE parent_id = ?1"
+                )
+                .bind(old_id)
+                .bind(&new_id)
+                .execute(&mut *tx)
+                .await?;
+                rename_problem_refs(&mut tx, old_id, &new_id).await?;
+                continue;
+            };
+
+            let children: Vec<(String, String)> = sqlx::query_as("SELECT id, number FROM problems WHERE parent_id = ?1")
+                .bind(old_id)
+                .fetch_all(&mut *tx)
+                .await?;
+            for (child_id, letter) in children {
+                let target: Option<String> = sqlx::query_scalar("SELECT id FROM problems WHERE parent_id = ?1 AND number = ?2")
+                    .bind(&keeper)
+                    .bind(&letter)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                match target {
+                    Some(target) => move_problem_refs(&mut tx, &child_id, &target).await?,
+                    None => {
+                        let moved_id = format!("{}{}", keeper, &child_id[old_id.len()..]);
+                        sqlx::query("UPDATE problems SET id = ?2, parent_id = ?3 WHERE id = ?1")
+                            .bind(&child_id)
+                            .bind(&moved_id)
+                            .bind(&keeper)
+                            .execute(&mut *tx)
+                            .await?;
+                        rename_problem_refs(&mut tx, &child_id, &moved_id).await?;
+                    }
+                }
+            }
+            move_problem_refs(&mut tx, old_id, &keeper).await?;
+
+            sqlx::query(
+                r#"UPDATE problems SET has_solution = has_solution
+                       OR (SELECT has_solution FROM problems WHERE id = ?1)
+                   WHERE id = ?2"#
+            )
+            .bind(old_id)
+            .bind(&keeper)
+            .execute(&mut *tx)
+            .await?;
+            // Rows that clashed with the keeper's (same provider, same hint level) go with it
+            sqlx::query("DELETE FROM problems WHERE id = ?1")
+                .bind(old_id)
+                .execute(&mut *tx)
+                .await?;
+            merged += 1;
+        }
+
+        tx.commit().await?;
+        log::info!(
+            "Normalized {} problem ids ({} merged into existing problems)",
+            stale.len(),
+            merged
+        );
+        Ok(())
+    }
+
     // === Book Operations ===
 
     pub async fn create_book(&self, book: &Book) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO books (id, title, author, subject, file_path, total_pages)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-            ON CONFLICT(id) DO UPDATE SET
-                title = excluded.title,
-                author = excluded.author,
-                subject = excluded.subject,
-                total_pages = excluded.total_pages
-            "#
-        )
-        .bind(&book.id)
-        .bind(&book.title)
-        .bind(&book.author)
-        .bind(&book.subject)
-        .bind(&book.file_path)
-        .bind(book.total_pages as i64)
-        .execute(&self.pool)
-        .await?;
+        create_book(&mut *self.pool.acquire().await?, book).await
+    }
 
+    /// Record a book's page count as read from its PDF; books not imported
+    /// yet are left alone
+    pub async fn set_book_total_pages(&self, id: &str, total_pages: u32) -> Result<()> {
+        sqlx::query("UPDATE books SET total_pages = ?2 WHERE id = ?1 AND total_pages != ?2")
+            .bind(id)
+            .bind(total_pages as i64)
+            .execute(&self.pool)
+            .await?;
         Ok(())
     }
 
@@ -380,26 +1054,7 @@ impl Database {
     // === Chapter Operations ===
 
     pub async fn create_chapter(&self, chapter: &Chapter) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO chapters (id, book_id, number, title, description, problem_count, theory_count)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            ON CONFLICT(id) DO UPDATE SET
-                title = excluded.title,
-                description = excluded.description
-            "#
-        )
-        .bind(&chapter.id)
-        .bind(&chapter.book_id)
-        .bind(chapter.number as i64)
-        .bind(&chapter.title)
-        .bind(&chapter.description)
-        .bind(chapter.problem_count as i64)
-        .bind(chapter.theory_count as i64)
-        .execute(&self.pool)
-        .await?;
-
-        Ok(())
+        create_chapter(&mut *self.pool.acquire().await?, chapter).await
     }
 
     pub async fn get_chapter(&self, id: &str) -> Result<Option<Chapter>> {
@@ -427,51 +1082,14 @@ impl Database {
     // === Problem Operations ===
 
     pub async fn create_problem(&self, problem: &Problem) -> Result<()> {
-        let formulas_json = serde_json::to_string(&problem.latex_formulas)?;
-        
-        // Determine if cross-page
-        let is_cross_page = problem.continues_from_page.is_some() || problem.continues_to_page.is_some();
-        
         // Upsert by primary key to avoid DELETE+INSERT semantics (which would cascade-delete solutions).
         // Uniqueness for main problems and sub-problems is enforced via partial unique indexes.
-        sqlx::query(
-            r#"
-            INSERT INTO problems 
-            (id, chapter_id, page_id, parent_id, number, display_name, content, latex_formulas, 
-             page_number, difficulty, has_solution, continues_from_page, continues_to_page, is_cross_page)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
-            ON CONFLICT(id) DO UPDATE SET
-                chapter_id = excluded.chapter_id,
-                page_id = excluded.page_id,
-                parent_id = excluded.parent_id,
-                number = excluded.number,
-                display_name = excluded.display_name,
-                content = excluded.content,
-                latex_formulas = excluded.latex_formulas,
-                page_number = excluded.page_number,
-                difficulty = excluded.difficulty,
-                -- Keep has_solution as-is (don't wipe user-generated data)
-                continues_from_page = excluded.continues_from_page,
-                continues_to_page = excluded.continues_to_page,
-                is_cross_page = excluded.is_cross_page
-            "#
-        )
-        .bind(&problem.id)
-        .bind(&problem.chapter_id)
-        .bind(&problem.page_id)
-        .bind(&problem.parent_id)
-        .bind(&problem.number)
-        .bind(&problem.display_name)
-        .bind(&problem.content)
-        .bind(formulas_json)
-        .bind(problem.page_number.map(|p| p as i64))
-        .bind(problem.difficulty.map(|d| d as i64))
-        .bind(problem.has_solution)
-        .bind(problem.continues_from_page.map(|p| p as i64))
-        .bind(problem.continues_to_page.map(|p| p as i64))
-        .bind(is_cross_page)
-        .execute(&self.pool)
-        .await?;
+        let sql = format!(
+            "{} VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17) {}",
+            PROBLEM_INSERT, PROBLEM_UPSERT
+        );
+        let query = sqlx::query(&sql);
+        bind_problem(query, problem)?.execute(&self.pool).await?;
 
         Ok(())
     }
@@ -489,7 +1107,7 @@ impl Database {
 
     pub async fn get_problems_by_chapter(&self, chapter_id: &str) -> Result<Vec<Problem>> {
         let rows = sqlx::query_as::<_, ProblemRow>(
-            "SELECT * FROM problems WHERE chapter_id = ?1 AND parent_id IS NULL ORDER BY number"
+            "SELECT * FROM problems WHERE chapter_id = ?1 AND parent_id IS NULL ORDER BY numeric_order"
         )
         .bind(chapter_id)
         .fetch_all(&self.pool)
@@ -500,35 +1118,113 @@ impl Database {
 
     /// Delete all problems (and sub-problems) for a page
     pub async fn delete_problems_by_page(&self, page_id: &str) -> Result<usize> {
-        // First delete sub-problems (they reference parent problems)
-        let sub_count = sqlx::query(
-            "DELETE FROM problems WHERE parent_id IN (SELECT id FROM problems WHERE page_id = ?1)"
-        )
-        .bind(page_id)
-        .execute(&self.pool)
-        .await?
-        .rows_affected();
-        
-        // Then delete parent problems
-        let parent_count = sqlx::query(
-            "DELETE FROM problems WHERE page_id = ?1"
-        )
-        .bind(page_id)
-        .execute(&self.pool)
-        .await?
-        .rows_affected();
-        
-        Ok((sub_count + parent_count) as usize)
+        delete_problems_by_page(&mut *self.pool.acquire().await?, page_id).await
     }
 
-    /// Create or update multiple problems at once
-    pub async fn create_or_update_problems(&self, problems: &[Problem]) -> Result<usize> {
-        let mut count = 0;
-        for problem in problems {
-            self.create_problem(problem).await?;
-            count += 1;
+    /// Delete every problem of a chapter with its sub-problems, solutions,
+    /// hints and bookmarks, in one transaction. A dry run counts what would
+    /// go and rolls back. None if there is no such chapter.
+    pub async fn delete_chapter_problems(&self, chapter_id: &str, dry_run: bool) -> Result<Option<ProblemDeletion>> {
+        let mut tx = self.pool.begin().await?;
+        let book_id: Option<String> = sqlx::query_scalar("SELECT book_id FROM chapters WHERE id = ?1")
+            .bind(chapter_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(book_id) = book_id else {
+            return Ok(None);
+        };
+
+        let before = book_problem_counts(&mut tx, &book_id).await?;
+        sqlx::query("DELETE FROM problems WHERE chapter_id = ?1")
+            .bind(chapter_id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = before.minus(book_problem_counts(&mut tx, &book_id).await?);
+
+        // Rolled back explicitly: a dropped transaction releases its write
+        // lock only once the pool gets to it, failing the next write
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
         }
-        Ok(count)
+        Ok(Some(deleted))
+    }
+
+    /// Delete the problems on some pages of a book, like
+    /// [`Self::delete_problems_by_page`] for each page plus problems imported
+    /// without a page row, in one transaction and a fixed number of
+    /// statements. A dry run counts what would go and rolls back.
+    pub async fn delete_book_problems_on_pages(&self, book_id: &str, pages: &[u32], dry_run: bool) -> Result<ProblemDeletion> {
+        // Page row ids of the selected pages
+        const PAGE_IDS: &str = "SELECT ?1 || ':page:' || value FROM json_each(?2)";
+        let pages_json = serde_json::to_string(pages)?;
+        let mut tx = self.pool.begin().await?;
+        let before = book_problem_counts(&mut tx, book_id).await?;
+
+        // Problems continued on other pages only lose these pages' parts
+        let spanning: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT DISTINCT problem_id FROM problem_pages WHERE problem_id IN \
+             (SELECT problem_id FROM problem_pages WHERE page_id IN ({ids})) AND page_id NOT IN ({ids})",
+            ids = PAGE_IDS
+        ))
+        .bind(book_id)
+        .bind(&pages_json)
+        .fetch_all(&mut *tx)
+        .await?;
+        for sql in [
+            format!("DELETE FROM problem_pages WHERE page_id IN ({})", PAGE_IDS),
+            format!(
+                "DELETE FROM problems WHERE parent_id IN (SELECT id FROM problems WHERE page_id IN ({}) \
+                 AND id NOT IN (SELECT problem_id FROM problem_pages))",
+                PAGE_IDS
+            ),
+            format!("DELETE FROM problems WHERE page_id IN ({}) AND id NOT IN (SELECT problem_id FROM problem_pages)", PAGE_IDS),
+            r#"DELETE FROM problems WHERE page_id IS NULL
+                 AND page_number IN (SELECT value FROM json_each(?2))
+                 AND chapter_id IN (SELECT id FROM chapters WHERE book_id = ?1)"#
+                .to_string(),
+        ] {
+            sqlx::query(&sql).bind(book_id).bind(&pages_json).execute(&mut *tx).await?;
+        }
+        for problem_id in &spanning {
+            merge_problem_pages(&mut tx, problem_id).await?;
+        }
+        let deleted = before.minus(book_problem_counts(&mut tx, book_id).await?);
+
+        // Rolled back explicitly: a dropped transaction releases its write
+        // lock only once the pool gets to it, failing the next write
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Create or update multiple problems in one transaction with multi-row
+    /// inserts. A problem whose number is already held by another problem
+    /// (or by an earlier one in the batch) is skipped and reported as a
+    /// conflict instead of failing the batch; results are in input order.
+    pub async fn create_or_update_problems(&self, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+        create_or_update_problems(&mut *self.pool.acquire().await?, problems).await
+    }
+
+    /// Save the problems parsed from one page. The page's part of each
+    /// top-level problem goes to `problem_pages`, then the problem row is
+    /// rebuilt from all its parts, so a problem running across pages is one
+    /// row with its whole statement. Conflicting problems are skipped, see
+    /// [`Self::create_or_update_problems`].
+    pub async fn save_page_problems(&self, page_id: &str, page_number: u32, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+        let mut tx = self.pool.begin().await?;
+        let writes = save_page_problems(&mut tx, page_id, page_number, problems).await?;
+        tx.commit().await?;
+        Ok(writes)
+    }
+
+    /// Parts of a problem in page order
+    pub async fn get_problem_pages(&self, problem_id: &str) -> Result<Vec<ProblemPage>> {
+        get_problem_pages(&mut *self.pool.acquire().await?, problem_id).await
     }
 
     pub async fn update_problem_solution_status(&self, problem_id: &str, has_solution: bool) -> Result<()> {
@@ -543,101 +1239,231 @@ impl Database {
         Ok(())
     }
 
-    /// Update problem content and latex formulas (e.g., after OCR import)
-    pub async fn update_problem_content(&self, problem_id: &str, content: &str, latex_formulas: Vec<String>) -> Result<()> {
+    /// Replace a problem's content and bump its version. With
+    /// `expected_version` the update only applies while the stored version
+    /// still matches. Returns the new version, None when nothing was updated
+    /// (no such problem or a newer version).
+    pub async fn update_problem_content(
+        &self,
+        problem_id: &str,
+        content: &str,
+        latex_formulas: Vec<String>,
+        expected_version: Option<u32>,
+    ) -> Result<Option<u32>> {
         let formulas_json = serde_json::to_string(&latex_formulas)?;
         
-        sqlx::query(
-            "UPDATE problems SET content = ?1, latex_formulas = ?2 WHERE id = ?3"
+        let version: Option<i64> = sqlx::query_scalar(
+            r#"UPDATE problems SET content = ?1, latex_formulas = ?2, version = version + 1
+               WHERE id = ?3 AND (?4 IS NULL OR version = ?4)
+               RETURNING version"#
         )
         .bind(content)
         .bind(formulas_json)
         .bind(problem_id)
-        .execute(&self.pool)
+        .bind(expected_version.map(|v| v as i64))
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(version.map(|v| v as u32))
     }
 
-    // === Page Operations ===
+    /// Count a parse of `problem_id` whose formulas failed validation (or reset
+    /// the counter when they passed). Returns the updated count.
+    pub async fn record_formula_attempt(&self, problem_id: &str, failed: bool) -> Result<u32> {
+        if !failed {
+            sqlx::query("DELETE FROM formula_attempts WHERE problem_id = ?1")
+                .bind(problem_id)
+                .execute(&self.pool)
+                .await?;
+            return Ok(0);
+        }
 
-    pub async fn get_or_create_page(&self, book_id: &str, page_number: u32) -> Result<crate::models::Page> {
-        let page_id = format!("{}:page:{}", book_id, page_number);
-        
-        // Try to get existing page
-        let existing = sqlx::query_as::<_, PageRow>(
-            "SELECT * FROM pages WHERE id = ?1"
+        sqlx::query(
+            r#"
+            INSERT INTO formula_attempts (problem_id, attempts) VALUES (?1, 1)
+            ON CONFLICT(problem_id) DO UPDATE SET
+                attempts = attempts + 1,
+                updated_at = CURRENT_TIMESTAMP
+            "#
         )
-        .bind(&page_id)
+        .bind(problem_id)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_formula_attempts(problem_id).await
+    }
+
+    pub async fn get_formula_attempts(&self, problem_id: &str) -> Result<u32> {
+        let attempts: Option<i64> = sqlx::query_scalar(
+            "SELECT attempts FROM formula_attempts WHERE problem_id = ?1"
+        )
+        .bind(problem_id)
         .fetch_optional(&self.pool)
         .await?;
-        
-        if let Some(row) = existing {
-            return Ok(row.into());
-        }
-        
-        // Ensure book exists first
-        let book = crate::models::Book {
-            id: book_id.to_string(),
-            title: book_id.to_string(),
-            author: None,
-            subject: None,
-            file_path: format!("resources/{}.pdf", book_id),
-            total_pages: 0,
-            created_at: chrono::Utc::now(),
-        };
-        
-        // Try to create book (ignore if exists)
-        if let Err(e) = self.create_book(&book).await {
-            log::debug!("Book may already exist: {}", e);
-        }
-        
-        // Create new page
-        let now = chrono::Utc::now();
-        let page = crate::models::Page {
-            id: page_id.clone(),
-            book_id: book_id.to_string(),
-            page_number,
-            ocr_text: None,
-            has_problems: false,
-            problem_count: 0,
-            created_at: now,
-            updated_at: now,
-        };
-        
-        sqlx::query(
-            "INSERT INTO pages (id, book_id, page_number, ocr_text, has_problems, problem_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+
+        Ok(attempts.unwrap_or(0) as u32)
+    }
+
+    /// Problems of a book scored below `threshold`, least confident first
+    pub async fn get_low_confidence_problems(&self, book_id: &str, threshold: f32, limit: usize) -> Result<Vec<Problem>> {
+        let rows = sqlx::query_as::<_, ProblemRow>(
+            r#"SELECT * FROM problems
+               WHERE chapter_id LIKE ?1 AND confidence IS NOT NULL AND confidence < ?2
+               ORDER BY confidence ASC
+               LIMIT ?3"#
         )
-        .bind(&page.id)
-        .bind(&page.book_id)
-        .bind(page_number as i64)
-        .bind(&page.ocr_text)
-        .bind(page.has_problems)
-        .bind(page.problem_count as i64)
+        .bind(format!("{}:%", book_id))
+        .bind(threshold as f64)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    // === Review Operations ===
+
+    /// First top-level problem of a book still waiting for review, in textbook order
+    pub async fn get_next_unreviewed_problem(&self, book_id: &str) -> Result<Option<Problem>> {
+        Ok(self.get_unreviewed_problems(book_id, 1).await?.pop())
+    }
+
+    /// Up to `limit` top-level problems waiting for review, in textbook order
+    pub async fn get_unreviewed_problems(&self, book_id: &str, limit: usize) -> Result<Vec<Problem>> {
+        let rows = sqlx::query_as::<_, ProblemRow>(
+            r#"SELECT p.* FROM problems p
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE c.book_id = ?1 AND p.parent_id IS NULL
+                 AND COALESCE(p.review_status, 'unreviewed') = 'unreviewed'
+               ORDER BY c.number, p.numeric_order, p.rowid
+               LIMIT ?2"#
+        )
+        .bind(book_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Set a problem's review state; returns false if the problem doesn't exist
+    pub async fn set_problem_review(&self, problem_id: &str, status: ReviewStatus, note: Option<&str>) -> Result<bool> {
+        let result = sqlx::query(
+            r#"UPDATE problems
+               SET review_status = ?1, review_note = ?2, reviewed_at = CURRENT_TIMESTAMP
+               WHERE id = ?3"#
+        )
+        .bind(status.as_str())
+        .bind(note)
+        .bind(problem_id)
         .execute(&self.pool)
         .await?;
-        
-        Ok(page)
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Number of top-level problems of a book in each review state
+    pub async fn get_review_progress(&self, book_id: &str) -> Result<ReviewProgress> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"SELECT COALESCE(p.review_status, 'unreviewed'), COUNT(*) FROM problems p
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE c.book_id = ?1 AND p.parent_id IS NULL
+               GROUP BY 1"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut progress = ReviewProgress::default();
+        for (status, count) in rows {
+            match ReviewStatus::from_name(&status).unwrap_or_default() {
+                ReviewStatus::Unreviewed => progress.unreviewed += count as u32,
+                ReviewStatus::Approved => progress.approved += count as u32,
+                ReviewStatus::NeedsFix => progress.needs_fix += count as u32,
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Top-level problems of a book per page, as `(page_number, count)`
+    /// for pages with at least one problem
+    pub async fn get_problem_density(&self, book_id: &str) -> Result<Vec<(u32, u32)>> {
+        let rows: Vec<(i64, i64)> = sqlx::query_as(
+            r#"SELECT p.page_number, COUNT(*) FROM problems p
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE c.book_id = ?1 AND p.parent_id IS NULL AND p.page_number IS NOT NULL
+               GROUP BY p.page_number
+               ORDER BY p.page_number"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(page, count)| (page as u32, count as u32)).collect())
+    }
+
+    // === Page Operations ===
+
+    pub async fn get_or_create_page(&self, book_id: &str, page_number: u32) -> Result<crate::models::Page> {
+        get_or_create_page(&mut *self.pool.acquire().await?, book_id, page_number).await
     }
 
     pub async fn update_page_ocr(&self, page_id: &str, ocr_text: &str, problem_count: u32) -> Result<()> {
-        sqlx::query(
-            "UPDATE pages SET ocr_text = ?1, has_problems = ?2, problem_count = ?3, updated_at = CURRENT_TIMESTAMP WHERE id = ?4"
+        update_page_ocr(&mut *self.pool.acquire().await?, page_id, ocr_text, problem_count).await
+    }
+
+    pub async fn set_page_ocr_provider(&self, page_id: &str, provider: &str) -> Result<()> {
+        sqlx::query("UPDATE pages SET ocr_provider = ?1, updated_at = CURRENT_TIMESTAMP WHERE id = ?2")
+            .bind(provider)
+            .bind(page_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Parse result (JSON) stored for a page, if it was parsed with the same
+    /// fingerprint (see [`crate::services::ai_parser::HybridParser::fingerprint`])
+    pub async fn get_page_parse(&self, book_id: &str, page_number: u32, fingerprint: &str) -> Result<Option<String>> {
+        let result = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT parse_result FROM pages WHERE id = ?1 AND parse_fingerprint = ?2"
         )
-        .bind(ocr_text)
-        .bind(problem_count > 0)
-        .bind(problem_count as i64)
-        .bind(page_id)
-        .execute(&self.pool)
+        .bind(format!("{}:page:{}", book_id, page_number))
+        .bind(fingerprint)
+        .fetch_optional(&self.pool)
         .await?;
-        
+
+        Ok(result.flatten())
+    }
+
+    pub async fn set_page_parse(&self, page_id: &str, fingerprint: &str, result: &str) -> Result<()> {
+        sqlx::query("UPDATE pages SET parse_fingerprint = ?1, parse_result = ?2 WHERE id = ?3")
+            .bind(fingerprint)
+            .bind(result)
+            .bind(page_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_page_kind(&self, page_id: &str, kind: crate::models::PageKind) -> Result<()> {
+        sqlx::query("UPDATE pages SET page_kind = ?1, updated_at = CURRENT_TIMESTAMP WHERE id = ?2")
+            .bind(kind.as_str())
+            .bind(page_id)
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 
     pub async fn get_problems_by_page(&self, page_id: &str) -> Result<Vec<Problem>> {
         // Only get parent problems (not sub-problems)
         let rows = sqlx::query_as::<_, ProblemRow>(
-            "SELECT * FROM problems WHERE page_id = ?1 AND parent_id IS NULL ORDER BY number"
+            r#"SELECT * FROM problems
+               WHERE (page_id = ?1 OR id IN (SELECT problem_id FROM problem_pages WHERE page_id = ?1))
+                 AND parent_id IS NULL
+               ORDER BY numeric_order"#
         )
         .bind(page_id)
         .fetch_all(&self.pool)
@@ -646,10 +1472,52 @@ impl Database {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
+    /// Top-level problems of a chapter with their sub-problems loaded, in one
+    /// query
+    pub async fn get_problem_tree_by_chapter(&self, chapter_id: &str) -> Result<Vec<Problem>> {
+        let rows = sqlx::query_as::<_, ProblemRow>(
+            "SELECT * FROM problems WHERE chapter_id = ?1 ORDER BY sub_position, numeric_order"
+        )
+        .bind(chapter_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let (subs, parents): (Vec<Problem>, Vec<Problem>) =
+            rows.into_iter().map(Problem::from).partition(|p| p.parent_id.is_some());
+        let mut subs_by_parent: HashMap<String, Vec<Problem>> = HashMap::new();
+        for sub in subs {
+            subs_by_parent.entry(sub.parent_id.clone().unwrap_or_default()).or_default().push(sub);
+        }
+
+        Ok(parents
+            .into_iter()
+            .map(|mut problem| {
+                problem.sub_problems = subs_by_parent.remove(&problem.id);
+                problem
+            })
+            .collect())
+    }
+
+    /// Problems of a chapter with sub-problems and best solutions in two
+    /// queries instead of one per problem
+    pub async fn get_chapter_full(&self, chapter_id: &str) -> Result<Vec<ChapterProblem>> {
+        let problems = self.get_problem_tree_by_chapter(chapter_id).await?;
+        let ids: Vec<String> = problems.iter().map(|p| p.id.clone()).collect();
+        let mut best = self.get_filtered_solutions(&ids, &SolutionFilter::Any).await?;
+
+        Ok(problems
+            .into_iter()
+            .map(|problem| {
+                let best_solution = best.remove(&problem.id);
+                ChapterProblem { problem, best_solution }
+            })
+            .collect())
+    }
+
     /// Get sub-problems for a parent problem
     pub async fn get_sub_problems(&self, parent_id: &str) -> Result<Vec<Problem>> {
         let rows = sqlx::query_as::<_, ProblemRow>(
-            "SELECT * FROM problems WHERE parent_id = ?1 ORDER BY number"
+            "SELECT * FROM problems WHERE parent_id = ?1 ORDER BY sub_position, numeric_order"
         )
         .bind(parent_id)
         .fetch_all(&self.pool)
@@ -697,33 +1565,105 @@ impl Database {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
-    // === Theory Operations ===
+    /// Create placeholder rows for referenced pages of `book_id` that have
+    /// none yet, so they show up as pending (see [`Self::get_pending_pages`]).
+    /// Unknown books and pages past the book's page count, when it is known,
+    /// are left alone. Returns the rows created.
+    pub async fn add_pending_pages(&self, book_id: &str, pages: &[u32]) -> Result<u64> {
+        add_pending_pages(&mut *self.pool.acquire().await?, book_id, pages).await
+    }
 
-    pub async fn create_theory_block(&self, theory: &TheoryBlock) -> Result<()> {
-        let formulas_json = serde_json::to_string(&theory.latex_formulas)?;
-        let block_type = format!("{:?}", theory.block_type).to_lowercase();
-        
-        sqlx::query(
+    /// Pages of a book with a row but no OCR text, blank pages aside
+    pub async fn get_pending_pages(&self, book_id: &str) -> Result<Vec<crate::models::PendingPage>> {
+        let rows = sqlx::query_as::<_, (i64, Option<String>, String, chrono::NaiveDateTime)>(
             r#"
-            INSERT INTO theory_blocks (id, chapter_id, block_num, title, block_type, content, latex_formulas, page_number)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
-            ON CONFLICT(id) DO UPDATE SET
-                content = excluded.content,
-                latex_formulas = excluded.latex_formulas
-            "#
+            SELECT pg.page_number,
+                   COALESCE(
+                       (SELECT p.chapter_id FROM problems p
+                        WHERE substr(p.chapter_id, 1, length(?1) + 1) = ?1 || ':' AND p.page_number <= pg.page_number
+                        ORDER BY p.page_number DESC LIMIT 1),
+                       (SELECT c.id FROM chapters c WHERE c.book_id = ?1 ORDER BY c.number LIMIT 1)
+                   ),
+                   (SELECT json_group_array(p.id) FROM problems p
+                    WHERE p.parent_id IS NULL AND substr(p.chapter_id, 1, length(?1) + 1) = ?1 || ':'
+                      AND (p.page_id = pg.id OR p.continues_to_page = pg.page_number
+                           OR p.continues_from_page = pg.page_number)),
+                   pg.created_at
+            FROM pages pg
+            WHERE pg.book_id = ?1 AND pg.ocr_text IS NULL AND COALESCE(pg.page_kind, '') != 'blank'
+            ORDER BY pg.page_number
+            "#,
         )
-        .bind(&theory.id)
-        .bind(&theory.chapter_id)
-        .bind(theory.block_num as i64)
-        .bind(&theory.title)
-        .bind(block_type)
-        .bind(&theory.content)
-        .bind(formulas_json)
-        .bind(theory.page_number.map(|p| p as i64))
-        .execute(&self.pool)
+        .bind(book_id)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(())
+        rows.into_iter()
+            .map(|(page_number, chapter_id, problem_ids, created_at)| {
+                Ok(crate::models::PendingPage {
+                    page_number: page_number as u32,
+                    chapter_id,
+                    problem_ids: serde_json::from_str(&problem_ids)?,
+                    created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+                })
+            })
+            .collect()
+    }
+
+    /// Add page rows brought from another machine (see
+    /// [`crate::services::cache_bundle`]) to `book`, which is created unless
+    /// it exists. Pages that were read here keep their text when the
+    /// incoming row has none. Returns the pages written.
+    pub async fn import_pages(&self, book: &Book, pages: &[crate::models::Page]) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO books (id, title, author, subject, file_path, total_pages) VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+             ON CONFLICT(id) DO NOTHING",
+        )
+        .bind(&book.id)
+        .bind(&book.title)
+        .bind(&book.author)
+        .bind(&book.subject)
+        .bind(&book.file_path)
+        .bind(book.total_pages as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        for page in pages {
+            sqlx::query(
+                r#"
+                INSERT INTO pages (id, book_id, page_number, ocr_text, has_problems, problem_count, page_kind, ocr_provider)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT(id) DO UPDATE SET
+                    ocr_text = COALESCE(excluded.ocr_text, pages.ocr_text),
+                    has_problems = CASE WHEN excluded.ocr_text IS NULL THEN pages.has_problems ELSE excluded.has_problems END,
+                    problem_count = CASE WHEN excluded.ocr_text IS NULL THEN pages.problem_count ELSE excluded.problem_count END,
+                    page_kind = COALESCE(excluded.page_kind, pages.page_kind),
+                    ocr_provider = COALESCE(excluded.ocr_provider, pages.ocr_provider),
+                    updated_at = CURRENT_TIMESTAMP
+                "#,
+            )
+            .bind(format!("{}:page:{}", book.id, page.page_number))
+            .bind(&book.id)
+            .bind(page.page_number as i64)
+            .bind(&page.ocr_text)
+            .bind(page.has_problems)
+            .bind(page.problem_count as i64)
+            .bind(page.page_kind.map(|kind| kind.as_str()))
+            .bind(&page.ocr_provider)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(pages.len())
+    }
+
+    // === Theory Operations ===
+
+    pub async fn create_theory_block(&self, theory: &TheoryBlock) -> Result<()> {
+        create_theory_block(&mut *self.pool.acquire().await?, theory).await
     }
 
     pub async fn get_theory_blocks_by_chapter(&self, chapter_id: &str) -> Result<Vec<TheoryBlock>> {
@@ -737,19 +1677,76 @@ impl Database {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
-    // === Solution Operations ===
+    pub async fn get_theory_block(&self, id: &str) -> Result<Option<TheoryBlock>> {
+        let row = sqlx::query_as::<_, TheoryRow>("SELECT * FROM theory_blocks WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
 
-    pub async fn create_or_update_solution(&self, solution: &Solution) -> Result<()> {
-        let formulas_json = serde_json::to_string(&solution.latex_formulas)?;
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Number for a new block appended to a chapter
+    pub async fn next_theory_block_num(&self, chapter_id: &str) -> Result<u32> {
+        let max: Option<i64> = sqlx::query_scalar("SELECT MAX(block_num) FROM theory_blocks WHERE chapter_id = ?1")
+            .bind(chapter_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(max.map_or(1, |n| n as u32 + 1))
+    }
+
+    /// Overwrite the editable fields of a theory block
+    pub async fn update_theory_block(&self, theory: &TheoryBlock) -> Result<bool> {
+        let formulas_json = serde_json::to_string(&theory.latex_formulas)?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE theory_blocks
+            SET title = ?1, block_type = ?2, content = ?3, latex_formulas = ?4, page_number = ?5
+            WHERE id = ?6
+            "#
+        )
+        .bind(&theory.title)
+        .bind(theory.block_type.as_str())
+        .bind(&theory.content)
+        .bind(formulas_json)
+        .bind(theory.page_number.map(|p| p as i64))
+        .bind(&theory.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_theory_block(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM theory_blocks WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // === Solution Operations ===
+
+    pub async fn create_or_update_solution(&self, solution: &Solution) -> Result<()> {
+        let formulas_json = serde_json::to_string(&solution.latex_formulas)?;
+        let generation_json = solution.generation.as_ref().map(serde_json::to_string).transpose()?;
         
         sqlx::query(
             r#"
-            INSERT INTO solutions (id, problem_id, provider, content, latex_formulas, is_verified, rating, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, CURRENT_TIMESTAMP)
+            INSERT INTO solutions (id, problem_id, provider, content, latex_formulas, is_verified, rating, generation_params, prompt_hash, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, CURRENT_TIMESTAMP)
             ON CONFLICT(problem_id, provider) DO UPDATE SET
                 content = excluded.content,
                 latex_formulas = excluded.latex_formulas,
-                updated_at = CURRENT_TIMESTAMP
+                generation_params = excluded.generation_params,
+                prompt_hash = excluded.prompt_hash,
+                updated_at = CURRENT_TIMESTAMP,
+                answer_verdict = NULL,
+                answer_checked_at = NULL,
+                version = solutions.version + 1
             "#
         )
         .bind(&solution.id)
@@ -759,6 +1756,8 @@ impl Database {
         .bind(formulas_json)
         .bind(solution.is_verified)
         .bind(solution.rating.map(|r| r as i64))
+        .bind(generation_json)
+        .bind(&solution.prompt_hash)
         .execute(&self.pool)
         .await?;
 
@@ -773,6 +1772,37 @@ impl Database {
         Ok(())
     }
 
+    /// Replace the content of an existing solution if its stored version is
+    /// still `expected_version`. Returns the new version, None when nothing
+    /// was updated (no such solution or a newer version).
+    pub async fn update_solution_content(
+        &self,
+        problem_id: &str,
+        provider: &str,
+        content: &str,
+        latex_formulas: &[String],
+        is_verified: Option<bool>,
+        expected_version: u32,
+    ) -> Result<Option<u32>> {
+        let version: Option<i64> = sqlx::query_scalar(
+            r#"UPDATE solutions SET content = ?1, latex_formulas = ?2, is_verified = COALESCE(?3, is_verified),
+                   updated_at = CURRENT_TIMESTAMP, version = version + 1,
+                   answer_verdict = NULL, answer_checked_at = NULL
+               WHERE problem_id = ?4 AND provider = ?5 AND version = ?6
+               RETURNING version"#
+        )
+        .bind(content)
+        .bind(serde_json::to_string(latex_formulas)?)
+        .bind(is_verified)
+        .bind(problem_id)
+        .bind(provider)
+        .bind(expected_version as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(version.map(|v| v as u32))
+    }
+
     pub async fn get_solution(&self, problem_id: &str, provider: &str) -> Result<Option<Solution>> {
         let row = sqlx::query_as::<_, SolutionRow>(
             "SELECT * FROM solutions WHERE problem_id = ?1 AND provider = ?2"
@@ -796,6 +1826,35 @@ impl Database {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
+    /// A solution generated by a solve request with this cache key, on any
+    /// problem
+    pub async fn get_solution_by_prompt_hash(&self, prompt_hash: &str) -> Result<Option<Solution>> {
+        let row = sqlx::query_as::<_, SolutionRow>(
+            "SELECT * FROM solutions WHERE prompt_hash = ?1 ORDER BY updated_at DESC LIMIT 1"
+        )
+        .bind(prompt_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    pub async fn solution_exists(&self, solution_id: &str) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM solutions WHERE id = ?1")
+            .bind(solution_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(found.is_some())
+    }
+
+    /// Number of solutions that can be served from the solve cache
+    pub async fn count_cached_solutions(&self) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM solutions WHERE prompt_hash IS NOT NULL")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
     pub async fn rate_solution(&self, solution_id: &str, rating: u8) -> Result<()> {
         sqlx::query(
             "UPDATE solutions SET rating = ?1 WHERE id = ?2"
@@ -808,6 +1867,32 @@ impl Database {
         Ok(())
     }
 
+    /// Store how a solution's final answer compares to the book's answer
+    pub async fn record_answer_verdict(&self, solution_id: &str, verdict: AnswerVerdict) -> Result<()> {
+        sqlx::query("UPDATE solutions SET answer_verdict = ?1, answer_checked_at = CURRENT_TIMESTAMP WHERE id = ?2")
+            .bind(verdict.as_str())
+            .bind(solution_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Recorded answer verdicts of a book's solutions by solution ID; solutions
+    /// not checked since their last edit are left out
+    pub async fn get_answer_verdicts(&self, book_id: &str) -> Result<HashMap<String, AnswerVerdict>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"SELECT s.id, s.answer_verdict FROM solutions s
+               JOIN problems p ON p.id = s.problem_id
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE c.book_id = ?1 AND s.answer_verdict IS NOT NULL"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id, verdict)| (id, AnswerVerdict::parse(&verdict))).collect())
+    }
+
     pub async fn verify_solution(&self, solution_id: &str, verified: bool) -> Result<()> {
         sqlx::query(
             "UPDATE solutions SET is_verified = ?1 WHERE id = ?2"
@@ -835,18 +1920,137 @@ impl Database {
         Ok(row.map(|r| r.into()))
     }
     
+    /// Best solution of each problem among those matching `filter` (verified
+    /// first, then highest rated), keyed by problem id; problems without a
+    /// matching solution are left out
+    pub async fn get_filtered_solutions(
+        &self,
+        problem_ids: &[String],
+        filter: &SolutionFilter,
+    ) -> Result<HashMap<String, Solution>> {
+        let (provider, verified_only) = solution_filter_binds(filter);
+        let mut solutions = HashMap::new();
+        for ids in problem_ids.chunks(PROBLEM_LOOKUP_CHUNK) {
+            let mut query = QueryBuilder::<Sqlite>::new("SELECT s.* FROM solutions s WHERE s.problem_id IN (");
+            let mut list = query.separated(", ");
+            for id in ids {
+                list.push_bind(id);
+            }
+            query.push(
+                r#") AND s.id = (SELECT s2.id FROM solutions s2
+                   WHERE s2.problem_id = s.problem_id
+                     AND ("#,
+            );
+            query.push_bind(provider).push(" IS NULL OR s2.provider = ").push_bind(provider);
+            query.push(") AND (").push_bind(verified_only).push(
+                r#" = 0 OR s2.is_verified = 1)
+                   ORDER BY s2.is_verified DESC, s2.rating DESC NULLS LAST, s2.created_at DESC
+                   LIMIT 1)"#,
+            );
+            let rows: Vec<SolutionRow> = query.build_query_as().fetch_all(&self.pool).await?;
+            solutions.extend(rows.into_iter().map(Solution::from).map(|s| (s.problem_id.clone(), s)));
+        }
+        Ok(solutions)
+    }
+
+    /// All solutions of a book matching `filter`, in chapter order
+    pub async fn get_book_solutions(&self, book_id: &str, filter: &SolutionFilter) -> Result<Vec<Solution>> {
+        let (provider, verified_only) = solution_filter_binds(filter);
+        let rows = sqlx::query_as::<_, SolutionRow>(
+            r#"SELECT s.* FROM solutions s
+               JOIN problems p ON p.id = s.problem_id
+               JOIN chapters c ON c.id = p.chapter_id
+               LEFT JOIN problems parent ON parent.id = p.parent_id
+               WHERE c.book_id = ?1
+                 AND (?2 IS NULL OR s.provider = ?2)
+                 AND (?3 = 0 OR s.is_verified = 1)
+               ORDER BY c.number, COALESCE(parent.numeric_order, p.numeric_order),
+                        p.parent_id IS NOT NULL, p.sub_position, p.numeric_order, s.created_at"#
+        )
+        .bind(book_id)
+        .bind(provider)
+        .bind(verified_only)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Solutions of one book, or of every book, for the duplicate cleanup
+    pub async fn get_solutions_for_cleanup(&self, book_id: Option<&str>) -> Result<Vec<Solution>> {
+        let rows = sqlx::query_as::<_, SolutionRow>(
+            r#"SELECT s.* FROM solutions s
+               JOIN problems p ON p.id = s.problem_id
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE ?1 IS NULL OR c.book_id = ?1
+               ORDER BY s.problem_id, s.created_at"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Move the plan's redundant solutions to `archived_solutions`, then give
+    /// kept ones their canonical provider name. Returns (archived, renamed).
+    pub async fn apply_solution_cleanup(&self, plan: &CleanupPlan) -> Result<(usize, usize)> {
+        let mut tx = self.pool.begin().await?;
+        let mut archived = 0;
+        for entry in &plan.archive {
+            sqlx::query(
+                r#"INSERT OR REPLACE INTO archived_solutions
+                   (id, problem_id, provider, content, latex_formulas, is_verified, rating, created_at, updated_at,
+                    generation_params, kept_solution_id, reason)
+                   SELECT id, problem_id, provider, content, latex_formulas, is_verified, rating, created_at, updated_at,
+                          generation_params, ?2, ?3
+                   FROM solutions WHERE id = ?1"#
+            )
+            .bind(&entry.solution_id)
+            .bind(&entry.kept_id)
+            .bind(entry.reason.as_str())
+            .execute(&mut *tx)
+            .await?;
+
+            archived += sqlx::query("DELETE FROM solutions WHERE id = ?1")
+                .bind(&entry.solution_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected() as usize;
+        }
+
+        let mut renamed = 0;
+        for rename in &plan.renames {
+            renamed += sqlx::query("UPDATE solutions SET provider = ?2 WHERE id = ?1")
+                .bind(&rename.solution_id)
+                .bind(&rename.to)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected() as usize;
+        }
+
+        tx.commit().await?;
+        Ok((archived, renamed))
+    }
+
     /// Save or update solution
     pub async fn save_solution(&self, solution: &Solution) -> Result<()> {
         let formulas_json = serde_json::to_string(&solution.latex_formulas)?;
+        let generation_json = solution.generation.as_ref().map(serde_json::to_string).transpose()?;
         
         sqlx::query(
             r#"INSERT INTO solutions 
-               (id, problem_id, provider, content, latex_formulas, is_verified, rating, created_at, updated_at)
-               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+               (id, problem_id, provider, content, latex_formulas, is_verified, rating, created_at, updated_at, generation_params, prompt_hash)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
                ON CONFLICT(problem_id, provider) DO UPDATE SET
                    content = excluded.content,
                    latex_formulas = excluded.latex_formulas,
-                   updated_at = excluded.updated_at"#
+                   generation_params = excluded.generation_params,
+                   prompt_hash = excluded.prompt_hash,
+                   updated_at = excluded.updated_at,
+                   answer_verdict = NULL,
+                   answer_checked_at = NULL,
+                   version = solutions.version + 1"#
         )
         .bind(&solution.id)
         .bind(&solution.problem_id)
@@ -857,6 +2061,8 @@ impl Database {
         .bind(solution.rating.map(|r| r as i64))
         .bind(solution.created_at)
         .bind(solution.updated_at)
+        .bind(generation_json)
+        .bind(&solution.prompt_hash)
         .execute(&self.pool)
         .await?;
 
@@ -875,6 +2081,49 @@ impl Database {
         Ok(())
     }
 
+    /// Add or update a bookmark with folder and note
+    pub async fn upsert_bookmark(&self, problem_id: &str, folder: Option<&str>, note: Option<&str>) -> Result<()> {
+        sqlx::query(
+            r#"INSERT INTO bookmarks (problem_id, folder, note) VALUES (?1, ?2, ?3)
+               ON CONFLICT(problem_id) DO UPDATE SET
+                   folder = excluded.folder,
+                   note = excluded.note"#
+        )
+        .bind(problem_id)
+        .bind(folder)
+        .bind(note)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get all bookmarks (without problem data), newest first
+    pub async fn get_bookmarks(&self) -> Result<Vec<Bookmark>> {
+        let rows = sqlx::query_as::<_, BookmarkRow>(
+            "SELECT problem_id, folder, note, created_at FROM bookmarks ORDER BY created_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Number of bookmarked problems of a book
+    pub async fn count_book_bookmarks(&self, book_id: &str) -> Result<u64> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"SELECT COUNT(*) FROM bookmarks b
+               JOIN problems p ON p.id = b.problem_id
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE c.book_id = ?1"#
+        )
+        .bind(book_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
     /// Remove a problem from bookmarks
     pub async fn remove_bookmark(&self, problem_id: &str) -> Result<()> {
         sqlx::query(
@@ -924,6 +2173,36 @@ impl Database {
         Ok(())
     }
 
+    /// A book's most recently parsed top-level problems, newest first
+    pub async fn recent_book_problems(&self, book_id: &str, limit: usize) -> Result<Vec<Problem>> {
+        let rows = sqlx::query_as::<_, ProblemRow>(
+            r#"SELECT * FROM problems
+               WHERE parent_id IS NULL AND chapter_id IN (SELECT id FROM chapters WHERE book_id = ?1)
+               ORDER BY created_at DESC LIMIT ?2"#
+        )
+        .bind(book_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// A book's most recently generated or edited solutions, newest first,
+    /// with the display name of their problem
+    pub async fn recent_book_solutions(&self, book_id: &str, limit: usize) -> Result<Vec<(Solution, String)>> {
+        let rows = sqlx::query_as::<_, ProblemSolutionRow>(
+            r#"SELECT s.*, p.display_name AS problem_name FROM solutions s
+               JOIN problems p ON p.id = s.problem_id
+               WHERE p.chapter_id IN (SELECT id FROM chapters WHERE book_id = ?1)
+               ORDER BY s.updated_at DESC LIMIT ?2"#
+        )
+        .bind(book_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| (r.solution.into(), r.problem_name)).collect())
+    }
+
     /// Get recently viewed problems
     pub async fn get_view_history(&self, limit: usize) -> Result<Vec<Problem>> {
         let rows = sqlx::query_as::<_, ProblemRow>(
@@ -952,14 +2231,37 @@ impl Database {
         Ok(())
     }
 
-    // === Search Operations ===
+    // === OCR Audit Operations ===
 
-    pub async fn search_by_formula(&self, formula: &str, limit: usize) -> Result<Vec<Problem>> {
-        let pattern = format!("%{}%", formula);
-        let rows = sqlx::query_as::<_, ProblemRow>(
-            "SELECT * FROM problems WHERE latex_formulas LIKE ?1 LIMIT ?2"
+    pub async fn save_ocr_audit(&self, entry: &OcrAuditEntry) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO ocr_audits (id, book_id, page_number, provider, similarity, diverged)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#
         )
-        .bind(pattern)
+        .bind(&entry.id)
+        .bind(&entry.book_id)
+        .bind(entry.page_number as i64)
+        .bind(&entry.provider)
+        .bind(entry.similarity)
+        .bind(entry.diverged)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get audit results, newest first
+    pub async fn get_ocr_audits(&self, book_id: Option<&str>, diverged_only: bool, limit: usize) -> Result<Vec<OcrAuditEntry>> {
+        let rows = sqlx::query_as::<_, OcrAuditRow>(
+            r#"SELECT * FROM ocr_audits
+               WHERE (?1 IS NULL OR book_id = ?1) AND (?2 = 0 OR diverged = 1)
+               ORDER BY created_at DESC
+               LIMIT ?3"#
+        )
+        .bind(book_id)
+        .bind(diverged_only)
         .bind(limit as i64)
         .fetch_all(&self.pool)
         .await?;
@@ -967,331 +2269,3035 @@ impl Database {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
-    pub async fn advanced_search(
-        &self,
-        query: Option<&str>,
-        formula: Option<&str>,
-        chapter_id: Option<&str>,
-        book_id: Option<&str>,
-        has_solution: Option<bool>,
-        limit: usize,
-        offset: usize,
-    ) -> Result<Vec<Problem>> {
-        // Build query dynamically based on which filters are provided
-        // Use simpler approach - build SQL string based on what's provided
-        
-        let (sql, params): (String, Vec<String>) = match (
-            query.filter(|q| !q.is_empty()),
-            formula.filter(|f| !f.is_empty()),
-            chapter_id,
-            book_id,
-            has_solution,
-        ) {
-            (None, None, None, None, None) => {
-                // No filters - just get all
-                (format!(
-                    "SELECT * FROM problems ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![])
-            }
-            (Some(q), None, None, None, None) => {
-                let pattern = format!("%{}%", q);
-                (format!(
-                    "SELECT * FROM problems WHERE content LIKE ? OR display_name LIKE ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![pattern.clone(), pattern])
-            }
-            (None, Some(f), None, None, None) => {
-                let pattern = format!("%{}%", f);
-                (format!(
-                    "SELECT * FROM problems WHERE latex_formulas LIKE ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![pattern])
-            }
-            (None, None, Some(ch), None, None) => {
-                let pattern = format!("{}%", ch);
-                (format!(
-                    "SELECT * FROM problems WHERE chapter_id LIKE ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![pattern])
-            }
-            (None, None, None, Some(bid), None) => {
-                let pattern = format!("{}%", bid);
-                (format!(
-                    "SELECT * FROM problems WHERE chapter_id LIKE ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![pattern])
-            }
-            (None, None, None, None, Some(hs)) => {
-                let val = if hs { 1 } else { 0 };
-                (format!(
-                    "SELECT * FROM problems WHERE has_solution = ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![val.to_string()])
-            }
-            // For combinations, use a simpler approach - just filter by text for now
-            _ => {
-                let pattern = query.map(|q| format!("%{}%", q)).unwrap_or_default();
-                (format!(
-                    "SELECT * FROM problems WHERE content LIKE ? OR display_name LIKE ? ORDER BY chapter_id, CAST(number AS INTEGER) LIMIT {} OFFSET {}",
-                    limit, offset
-                ), vec![pattern.clone(), pattern])
-            }
-        };
-        
-        let mut q = sqlx::query_as::<_, ProblemRow>(&sql);
-        for p in &params {
-            q = q.bind(p.as_str());
-        }
-        
-        let rows = q.fetch_all(&self.pool).await?;
+    /// Latest audit per page for a book where the re-OCR diverged
+    pub async fn get_diverged_pages(&self, book_id: &str) -> Result<Vec<OcrAuditEntry>> {
+        let rows = sqlx::query_as::<_, OcrAuditRow>(
+            r#"SELECT a.* FROM ocr_audits a
+               INNER JOIN (
+                   SELECT page_number, MAX(created_at) AS latest
+                   FROM ocr_audits
+                   WHERE book_id = ?1
+                   GROUP BY page_number
+               ) l ON a.page_number = l.page_number AND a.created_at = l.latest
+               WHERE a.book_id = ?1 AND a.diverged = 1
+               ORDER BY a.page_number"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
-    pub async fn search_count(
-        &self,
-        query: Option<&str>,
-        _formula: Option<&str>,
-        _chapter_id: Option<&str>,
-        _book_id: Option<&str>,
-        has_solution: Option<bool>,
-    ) -> Result<i64> {
-        // Simplified count - just count all or by has_solution
-        let sql = if let Some(hs) = has_solution {
-            let val = if hs { 1 } else { 0 };
-            format!("SELECT COUNT(*) FROM problems WHERE has_solution = {}", val)
-        } else {
-            "SELECT COUNT(*) FROM problems".to_string()
-        };
-        
-        let count: i64 = sqlx::query_scalar(&sql).fetch_one(&self.pool).await?;
-        Ok(count)
+    // === Answer Key Operations ===
+
+    /// Store the book's own answer for a problem; returns false if the problem doesn't exist
+    pub async fn set_reference_answer(&self, problem_id: &str, answer: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE problems SET reference_answer = ?1 WHERE id = ?2")
+            .bind(answer)
+            .bind(problem_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
     }
-}
 
-// === Database Row Types ===
+    // === Explanation Operations ===
 
-#[derive(sqlx::FromRow)]
-struct BookRow {
-    id: String,
-    title: String,
-    author: Option<String>,
-    subject: Option<String>,
-    file_path: String,
-    total_pages: i64,
-    created_at: chrono::NaiveDateTime,
-}
+    pub async fn get_explanation(&self, id: &str) -> Result<Option<Explanation>> {
+        let row = sqlx::query_as::<_, ExplanationRow>(
+            "SELECT * FROM explanations WHERE id = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
 
-impl From<BookRow> for Book {
-    fn from(row: BookRow) -> Self {
-        Self {
-            id: row.id,
-            title: row.title,
-            author: row.author,
-            subject: row.subject,
-            file_path: row.file_path,
-            total_pages: row.total_pages as u32,
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-        }
+        Ok(row.map(|r| r.into()))
     }
-}
 
-#[derive(sqlx::FromRow)]
-struct ChapterRow {
-    id: String,
-    book_id: String,
-    number: i64,
-    title: String,
-    description: Option<String>,
-    problem_count: i64,
-    theory_count: i64,
-    created_at: chrono::NaiveDateTime,
-}
+    pub async fn save_explanation(&self, explanation: &Explanation) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO explanations
+            (id, book_id, page_number, selected_text, provider, content, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#
+        )
+        .bind(&explanation.id)
+        .bind(&explanation.book_id)
+        .bind(explanation.page_number as i64)
+        .bind(&explanation.selected_text)
+        .bind(&explanation.provider)
+        .bind(&explanation.content)
+        .bind(explanation.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
 
-impl From<ChapterRow> for Chapter {
-    fn from(row: ChapterRow) -> Self {
-        Self {
-            id: row.id,
-            book_id: row.book_id,
-            number: row.number as u32,
-            title: row.title,
-            description: row.description,
-            problem_count: row.problem_count as u32,
-            theory_count: row.theory_count as u32,
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-        }
+        Ok(())
     }
-}
 
-#[derive(sqlx::FromRow)]
-struct ProblemRow {
-    id: String,
-    chapter_id: String,
-    page_id: Option<String>,
-    parent_id: Option<String>,
-    number: String,
-    display_name: String,
-    content: String,
-    latex_formulas: String,
-    page_number: Option<i64>,
-    difficulty: Option<i64>,
-    has_solution: bool,
-    created_at: chrono::NaiveDateTime,
-    continues_from_page: Option<i64>,
-    continues_to_page: Option<i64>,
-    is_cross_page: Option<bool>,
-}
+    // === Attachment Operations ===
 
-impl From<ProblemRow> for Problem {
-    fn from(row: ProblemRow) -> Self {
-        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
-        
-        Self {
-            id: row.id,
-            chapter_id: row.chapter_id,
-            page_id: row.page_id,
-            parent_id: row.parent_id,
-            number: row.number,
-            display_name: row.display_name,
-            content: row.content,
-            latex_formulas: formulas,
-            page_number: row.page_number.map(|p| p as u32),
-            difficulty: row.difficulty.map(|d| d as u8),
-            has_solution: row.has_solution,
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-            solution: None,
-            sub_problems: None,
-            continues_from_page: row.continues_from_page.map(|p| p as u32),
-            continues_to_page: row.continues_to_page.map(|p| p as u32),
-            is_cross_page: row.is_cross_page.unwrap_or(false),
-            is_bookmarked: false,
-        }
+    pub async fn create_solution_attachment(&self, attachment: &SolutionAttachment) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO solution_attachments
+                (id, solution_id, file_name, content_type, size, storage_key, plot_spec, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#
+        )
+        .bind(&attachment.id)
+        .bind(&attachment.solution_id)
+        .bind(&attachment.file_name)
+        .bind(&attachment.content_type)
+        .bind(attachment.size as i64)
+        .bind(&attachment.storage_key)
+        .bind(attachment.plot_spec.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(attachment.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
-}
 
-#[derive(sqlx::FromRow)]
-struct PageRow {
-    id: String,
-    book_id: String,
-    page_number: i64,
-    ocr_text: Option<String>,
-    has_problems: bool,
-    problem_count: i64,
-    created_at: chrono::NaiveDateTime,
-    updated_at: chrono::NaiveDateTime,
-}
+    pub async fn get_solution_attachment(&self, id: &str) -> Result<Option<SolutionAttachment>> {
+        let row: Option<AttachmentRow> = sqlx::query_as(
+            "SELECT id, solution_id, file_name, content_type, size, storage_key, plot_spec, created_at \
+             FROM solution_attachments WHERE id = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
 
-impl From<PageRow> for crate::models::Page {
-    fn from(row: PageRow) -> Self {
-        Self {
-            id: row.id,
-            book_id: row.book_id,
-            page_number: row.page_number as u32,
-            ocr_text: row.ocr_text,
-            has_problems: row.has_problems,
-            problem_count: row.problem_count as u32,
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-            updated_at: chrono::DateTime::from_naive_utc_and_offset(row.updated_at, chrono::Utc),
+        row.map(attachment_from_row).transpose()
+    }
+
+    /// Attachments of a solution, oldest first
+    pub async fn get_solution_attachments(&self, solution_id: &str) -> Result<Vec<SolutionAttachment>> {
+        self.get_attachments_for_solutions(&[solution_id.to_string()]).await
+    }
+
+    /// Attachments of any of the solutions, oldest first
+    pub async fn get_attachments_for_solutions(&self, solution_ids: &[String]) -> Result<Vec<SolutionAttachment>> {
+        if solution_ids.is_empty() {
+            return Ok(Vec::new());
         }
+        let rows: Vec<AttachmentRow> = sqlx::query_as(
+            "SELECT id, solution_id, file_name, content_type, size, storage_key, plot_spec, created_at \
+             FROM solution_attachments WHERE solution_id IN (SELECT value FROM json_each(?1)) \
+             ORDER BY created_at, id"
+        )
+        .bind(serde_json::to_string(solution_ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(attachment_from_row).collect()
     }
-}
 
-#[derive(sqlx::FromRow)]
-struct TheoryRow {
-    id: String,
-    chapter_id: String,
-    block_num: i64,
-    title: Option<String>,
-    block_type: String,
-    content: String,
-    latex_formulas: String,
-    page_number: Option<i64>,
-    created_at: chrono::NaiveDateTime,
-}
+    // === Hint Operations ===
 
-impl From<TheoryRow> for TheoryBlock {
-    fn from(row: TheoryRow) -> Self {
-        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
-        let block_type = match row.block_type.as_str() {
-            "definition" => crate::models::problem::TheoryType::Definition,
-            "theorem" => crate::models::problem::TheoryType::Theorem,
-            "proof" => crate::models::problem::TheoryType::Proof,
-            "property" => crate::models::problem::TheoryType::Property,
-            "formula" => crate::models::problem::TheoryType::Formula,
-            "example" => crate::models::problem::TheoryType::Example,
-            _ => crate::models::problem::TheoryType::Other,
-        };
+    pub async fn save_problem_hint(&self, hint: &ProblemHint) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO problem_hints (problem_id, hint_level, provider, content, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#
+        )
+        .bind(&hint.problem_id)
+        .bind(hint.hint_level as i64)
+        .bind(&hint.provider)
+        .bind(&hint.content)
+        .bind(hint.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
 
-        Self {
-            id: row.id,
-            chapter_id: row.chapter_id,
-            block_num: row.block_num as u32,
-            title: row.title,
-            block_type,
-            content: row.content,
-            latex_formulas: formulas,
-            page_number: row.page_number.map(|p| p as u32),
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-        }
+        Ok(())
     }
-}
 
-#[derive(sqlx::FromRow)]
-struct SolutionRow {
-    id: String,
-    problem_id: String,
-    provider: String,
-    content: String,
-    latex_formulas: String,
-    is_verified: bool,
-    rating: Option<i64>,
-    created_at: chrono::NaiveDateTime,
-    updated_at: chrono::NaiveDateTime,
-}
+    /// Stored hints of a problem, weakest first
+    pub async fn get_problem_hints(&self, problem_id: &str) -> Result<Vec<ProblemHint>> {
+        let rows: Vec<(String, i64, String, String, chrono::NaiveDateTime)> = sqlx::query_as(
+            "SELECT problem_id, hint_level, provider, content, created_at FROM problem_hints \
+             WHERE problem_id = ?1 ORDER BY hint_level"
+        )
+        .bind(problem_id)
+        .fetch_all(&self.pool)
+        .await?;
 
-impl From<SolutionRow> for Solution {
-    fn from(row: SolutionRow) -> Self {
-        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
-        
-        Self {
-            id: row.id,
-            problem_id: row.problem_id,
-            provider: row.provider,
-            content: row.content,
-            latex_formulas: formulas,
-            is_verified: row.is_verified,
-            rating: row.rating.map(|r| r as u8),
-            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
-            updated_at: chrono::DateTime::from_naive_utc_and_offset(row.updated_at, chrono::Utc),
-        }
+        Ok(rows
+            .into_iter()
+            .map(|(problem_id, hint_level, provider, content, created_at)| ProblemHint {
+                problem_id,
+                hint_level: hint_level as u8,
+                provider,
+                content,
+                created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+            })
+            .collect())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use sqlx::Connection;
+    // === Figure Operations ===
 
-    async fn new_temp_db() -> (Database, std::path::PathBuf) {
-        let path = std::env::temp_dir().join(format!("bookers_test_{}.db", uuid::Uuid::new_v4()));
-        // Ensure the file exists so the URL is always valid.
-        let _ = std::fs::File::create(&path);
-        let url = format!("sqlite:{}", path.to_str().unwrap());
-        let db = Database::new(&url).await.expect("db init");
-        (db, path)
+    pub async fn save_problem_figure(&self, figure: &ProblemFigure) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO problem_figures (problem_id, provider, description, reconstructed, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#
+        )
+        .bind(&figure.problem_id)
+        .bind(&figure.provider)
+        .bind(serde_json::to_string(&figure.description)?)
+        .bind(figure.reconstructed)
+        .bind(figure.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
 
-    async fn seed_book_and_chapter(db: &Database, book_id: &str, chapter_num: u32) -> String {
-        let book = Book {
-            id: book_id.to_string(),
-            title: book_id.to_string(),
-            author: None,
-            subject: None,
-            file_path: format!("resources/{}.pdf", book_id),
-            total_pages: 0,
-            created_at: chrono::Utc::now(),
-        };
-        db.create_book(&book).await.expect("create book");
+    pub async fn get_problem_figure(&self, problem_id: &str) -> Result<Option<ProblemFigure>> {
+        let row: Option<(String, String, String, bool, chrono::NaiveDateTime)> = sqlx::query_as(
+            "SELECT problem_id, provider, description, reconstructed, created_at FROM problem_figures WHERE problem_id = ?1"
+        )
+        .bind(problem_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|(problem_id, provider, description, reconstructed, created_at)| {
+            Ok(ProblemFigure {
+                problem_id,
+                provider,
+                description: serde_json::from_str(&description)?,
+                reconstructed,
+                created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+            })
+        })
+        .transpose()
+    }
+
+    pub async fn delete_problem_figure(&self, problem_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM problem_figures WHERE problem_id = ?1")
+            .bind(problem_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // === Curriculum Operations ===
+
+    /// Stored standards, of one framework or all, by framework and code
+    pub async fn get_curricula(&self, framework: Option<&str>) -> Result<Vec<CurriculumStandard>> {
+        let rows: Vec<(String, String, String, String)> = sqlx::query_as(
+            "SELECT framework, code, title, tags FROM curricula WHERE ?1 IS NULL OR framework = ?1 ORDER BY framework, code"
+        )
+        .bind(framework)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(framework, code, title, tags)| {
+                Ok(CurriculumStandard { framework, code, title, tags: serde_json::from_str(&tags)? })
+            })
+            .collect()
+    }
+
+    pub async fn save_curriculum_standard(&self, standard: &CurriculumStandard) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO curricula (framework, code, title, tags) VALUES (?1, ?2, ?3, ?4)")
+            .bind(&standard.framework)
+            .bind(&standard.code)
+            .bind(&standard.title)
+            .bind(serde_json::to_string(&standard.tags)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Remove a standard and the problem tags pointing at it
+    pub async fn delete_curriculum_standard(&self, framework: &str, code: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM curricula WHERE framework = ?1 AND code = ?2")
+            .bind(framework)
+            .bind(code)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM problem_standards WHERE framework = ?1 AND code = ?2")
+            .bind(framework)
+            .bind(code)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Replace the auto-tagged codes of a problem; codes set by hand stay
+    pub async fn replace_auto_problem_standards(&self, problem_id: &str, standards: &[&CurriculumStandard]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM problem_standards WHERE problem_id = ?1 AND auto")
+            .bind(problem_id)
+            .execute(&mut *tx)
+            .await?;
+        for standard in standards {
+            sqlx::query("INSERT OR IGNORE INTO problem_standards (problem_id, framework, code, auto) VALUES (?1, ?2, ?3, TRUE)")
+                .bind(problem_id)
+                .bind(&standard.framework)
+                .bind(&standard.code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_problem_standards(&self, problem_id: &str) -> Result<Vec<ProblemStandard>> {
+        let rows: Vec<(String, String, String, bool)> = sqlx::query_as(
+            "SELECT problem_id, framework, code, auto FROM problem_standards WHERE problem_id = ?1 ORDER BY framework, code"
+        )
+        .bind(problem_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(problem_id, framework, code, auto)| ProblemStandard { problem_id, framework, code, auto })
+            .collect())
+    }
+
+    /// `(chapter number, problem id, code)` of every code of the framework
+    /// on the book's problems
+    pub async fn get_book_standard_tags(&self, book_id: &str, framework: &str) -> Result<Vec<(u32, String, String)>> {
+        let rows: Vec<(i64, String, String)> = sqlx::query_as(
+            r#"
+            SELECT c.number, ps.problem_id, ps.code
+            FROM problem_standards ps
+            JOIN problems p ON p.id = ps.problem_id
+            JOIN chapters c ON c.id = p.chapter_id
+            WHERE c.book_id = ?1 AND ps.framework = ?2
+            "#
+        )
+        .bind(book_id)
+        .bind(framework)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(chapter, problem_id, code)| (chapter as u32, problem_id, code)).collect())
+    }
+
+    // === Assignment Operations ===
+
+    pub async fn create_assignment(&self, assignment: &Assignment) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO assignments (id, title, instructions, share_token, due_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#
+        )
+        .bind(&assignment.id)
+        .bind(&assignment.title)
+        .bind(&assignment.instructions)
+        .bind(&assignment.share_token)
+        .bind(assignment.due_at.naive_utc())
+        .bind(assignment.created_at.naive_utc())
+        .execute(&mut *tx)
+        .await?;
+
+        for (position, problem_id) in assignment.problem_ids.iter().enumerate() {
+            sqlx::query("INSERT OR IGNORE INTO assignment_problems (assignment_id, problem_id, position) VALUES (?1, ?2, ?3)")
+                .bind(&assignment.id)
+                .bind(problem_id)
+                .bind(position as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_assignment(&self, row: AssignmentRow) -> Result<Assignment> {
+        let problem_ids: Vec<(String,)> = sqlx::query_as(
+            "SELECT problem_id FROM assignment_problems WHERE assignment_id = ?1 ORDER BY position"
+        )
+        .bind(&row.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Assignment {
+            id: row.id,
+            title: row.title,
+            instructions: row.instructions,
+            share_token: row.share_token,
+            problem_ids: problem_ids.into_iter().map(|(id,)| id).collect(),
+            due_at: chrono::DateTime::from_naive_utc_and_offset(row.due_at, chrono::Utc),
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        })
+    }
+
+    pub async fn get_assignment(&self, id: &str) -> Result<Option<Assignment>> {
+        let row = sqlx::query_as::<_, AssignmentRow>("SELECT * FROM assignments WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.load_assignment(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_assignment_by_token(&self, share_token: &str) -> Result<Option<Assignment>> {
+        let row = sqlx::query_as::<_, AssignmentRow>("SELECT * FROM assignments WHERE share_token = ?1")
+            .bind(share_token)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.load_assignment(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Assignments, newest first
+    pub async fn list_assignments(&self) -> Result<Vec<Assignment>> {
+        let rows = sqlx::query_as::<_, AssignmentRow>("SELECT * FROM assignments ORDER BY created_at DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut assignments = Vec::with_capacity(rows.len());
+        for row in rows {
+            assignments.push(self.load_assignment(row).await?);
+        }
+        Ok(assignments)
+    }
+
+    pub async fn delete_assignment(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM assignments WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn create_submission(&self, submission: &Submission) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO submissions
+                (id, assignment_id, problem_id, student_name, source, answer, photo_key, verdict, late, submitted_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#
+        )
+        .bind(&submission.id)
+        .bind(&submission.assignment_id)
+        .bind(&submission.problem_id)
+        .bind(&submission.student_name)
+        .bind(submission.source.as_str())
+        .bind(&submission.answer)
+        .bind(&submission.photo_key)
+        .bind(submission.verdict.as_str())
+        .bind(submission.late)
+        .bind(submission.submitted_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_submission(&self, id: &str) -> Result<Option<Submission>> {
+        let row = sqlx::query_as::<_, SubmissionRow>("SELECT * FROM submissions WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Submissions of an assignment, oldest first
+    pub async fn get_assignment_submissions(&self, assignment_id: &str) -> Result<Vec<Submission>> {
+        let rows = sqlx::query_as::<_, SubmissionRow>(
+            "SELECT * FROM submissions WHERE assignment_id = ?1 ORDER BY submitted_at"
+        )
+        .bind(assignment_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Every submission, oldest first
+    pub async fn get_all_submissions(&self) -> Result<Vec<Submission>> {
+        let rows = sqlx::query_as::<_, SubmissionRow>("SELECT * FROM submissions ORDER BY submitted_at")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    // === Difficulty Calibration ===
+
+    /// Static difficulty of every problem that has submissions
+    pub async fn get_submitted_problem_difficulties(&self) -> Result<HashMap<String, Option<u8>>> {
+        let rows: Vec<(String, Option<i64>)> = sqlx::query_as(
+            "SELECT id, difficulty FROM problems WHERE id IN (SELECT DISTINCT problem_id FROM submissions)"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(id, difficulty)| (id, difficulty.map(|d| d as u8))).collect())
+    }
+
+    /// Replace all calibrations with a new set
+    pub async fn replace_calibrations(&self, calibrations: &[Calibration]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM problem_calibrations").execute(&mut *tx).await?;
+        for calibration in calibrations {
+            sqlx::query(
+                r#"INSERT OR REPLACE INTO problem_calibrations
+                   (problem_id, rating, difficulty, attempts, success_rate, median_seconds, calibrated_at)
+                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#
+            )
+            .bind(&calibration.problem_id)
+            .bind(calibration.rating)
+            .bind(calibration.difficulty as i64)
+            .bind(calibration.attempts as i64)
+            .bind(calibration.success_rate)
+            .bind(calibration.median_seconds)
+            .bind(calibration.calibrated_at.naive_utc())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Calibrations of a book's problems, or of all problems, by problem ID
+    pub async fn get_calibrations(&self, book_id: Option<&str>) -> Result<HashMap<String, Calibration>> {
+        let rows = sqlx::query_as::<_, CalibrationRow>(
+            r#"SELECT k.* FROM problem_calibrations k
+               JOIN problems p ON p.id = k.problem_id
+               JOIN chapters c ON c.id = p.chapter_id
+               WHERE ?1 IS NULL OR c.book_id = ?1"#
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| (r.problem_id.clone(), r.into())).collect())
+    }
+
+    // === Group Operations ===
+
+    pub async fn create_group(&self, group: &Group) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO student_groups (id, name, created_at) VALUES (?1, ?2, ?3)")
+            .bind(&group.id)
+            .bind(&group.name)
+            .bind(group.created_at.naive_utc())
+            .execute(&mut *tx)
+            .await?;
+        for member in &group.members {
+            sqlx::query("INSERT OR IGNORE INTO group_members (group_id, member_key, student_name) VALUES (?1, ?2, ?3)")
+                .bind(&group.id)
+                .bind(member.to_lowercase())
+                .bind(member)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_group(&self, id: String, name: String, created_at: chrono::NaiveDateTime) -> Result<Group> {
+        let members: Vec<(String,)> = sqlx::query_as(
+            "SELECT student_name FROM group_members WHERE group_id = ?1 ORDER BY member_key"
+        )
+        .bind(&id)
+        .fetch_all(&self.pool)
+        .await?;
+        let assignments: Vec<(String,)> = sqlx::query_as(
+            r#"
+            SELECT ga.assignment_id FROM group_assignments ga
+            JOIN assignments a ON a.id = ga.assignment_id
+            WHERE ga.group_id = ?1
+            ORDER BY a.due_at
+            "#
+        )
+        .bind(&id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Group {
+            id,
+            name,
+            members: members.into_iter().map(|(m,)| m).collect(),
+            assignment_ids: assignments.into_iter().map(|(a,)| a).collect(),
+            created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+        })
+    }
+
+    pub async fn get_group(&self, id: &str) -> Result<Option<Group>> {
+        let row: Option<(String, String, chrono::NaiveDateTime)> =
+            sqlx::query_as("SELECT id, name, created_at FROM student_groups WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match row {
+            Some((id, name, created_at)) => Ok(Some(self.load_group(id, name, created_at).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Groups by name
+    pub async fn list_groups(&self) -> Result<Vec<Group>> {
+        let rows: Vec<(String, String, chrono::NaiveDateTime)> =
+            sqlx::query_as("SELECT id, name, created_at FROM student_groups ORDER BY name")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut groups = Vec::with_capacity(rows.len());
+        for (id, name, created_at) in rows {
+            groups.push(self.load_group(id, name, created_at).await?);
+        }
+        Ok(groups)
+    }
+
+    pub async fn delete_group(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM student_groups WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Add a member; `false` when the group already has one by that name
+    pub async fn add_group_member(&self, group_id: &str, student_name: &str) -> Result<bool> {
+        let result = sqlx::query("INSERT OR IGNORE INTO group_members (group_id, member_key, student_name) VALUES (?1, ?2, ?3)")
+            .bind(group_id)
+            .bind(student_name.to_lowercase())
+            .bind(student_name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn remove_group_member(&self, group_id: &str, student_name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM group_members WHERE group_id = ?1 AND member_key = ?2")
+            .bind(group_id)
+            .bind(student_name.to_lowercase())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn add_group_assignment(&self, group_id: &str, assignment_id: &str) -> Result<()> {
+        sqlx::query("INSERT OR IGNORE INTO group_assignments (group_id, assignment_id) VALUES (?1, ?2)")
+            .bind(group_id)
+            .bind(assignment_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_group_assignment(&self, group_id: &str, assignment_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM group_assignments WHERE group_id = ?1 AND assignment_id = ?2")
+            .bind(group_id)
+            .bind(assignment_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // === Issue Operations ===
+
+    /// Book of the record an issue would be about; `None` when it doesn't exist
+    pub async fn issue_entity_book(&self, entity_type: IssueEntity, entity_id: &str) -> Result<Option<String>> {
+        let sql = match entity_type {
+            IssueEntity::Book => "SELECT id FROM books WHERE id = ?1",
+            IssueEntity::Chapter => "SELECT book_id FROM chapters WHERE id = ?1",
+            IssueEntity::Page => "SELECT book_id FROM pages WHERE id = ?1",
+            IssueEntity::Problem => {
+                "SELECT c.book_id FROM problems p JOIN chapters c ON c.id = p.chapter_id WHERE p.id = ?1"
+            }
+            IssueEntity::Solution => {
+                r#"
+                SELECT c.book_id FROM solutions s
+                JOIN problems p ON p.id = s.problem_id
+                JOIN chapters c ON c.id = p.chapter_id
+                WHERE s.id = ?1
+                "#
+            }
+        };
+        let row: Option<(String,)> = sqlx::query_as(sql).bind(entity_id).fetch_optional(&self.pool).await?;
+        Ok(row.map(|(book_id,)| book_id))
+    }
+
+    /// Insert an issue, or update its title, body, status and assignee
+    pub async fn save_issue(&self, issue: &Issue) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO issues
+                (id, entity_type, entity_id, book_id, title, body, status, assignee, created_by, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            ON CONFLICT(id) DO UPDATE SET
+                title = excluded.title,
+                body = excluded.body,
+                status = excluded.status,
+                assignee = excluded.assignee,
+                updated_at = excluded.updated_at
+            "#
+        )
+        .bind(&issue.id)
+        .bind(issue.entity_type.as_str())
+        .bind(&issue.entity_id)
+        .bind(&issue.book_id)
+        .bind(&issue.title)
+        .bind(&issue.body)
+        .bind(issue.status.as_str())
+        .bind(&issue.assignee)
+        .bind(&issue.created_by)
+        .bind(issue.created_at.naive_utc())
+        .bind(issue.updated_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_issue(&self, id: &str) -> Result<Option<Issue>> {
+        let row = sqlx::query_as::<_, IssueRow>("SELECT * FROM issues WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Issues matching the filter, most recently updated first
+    pub async fn list_issues(&self, filter: &IssueFilter) -> Result<Vec<Issue>> {
+        let rows = sqlx::query_as::<_, IssueRow>(
+            r#"
+            SELECT * FROM issues
+            WHERE (?1 IS NULL OR status = ?1)
+              AND (?2 IS NULL OR book_id = ?2)
+              AND (?3 IS NULL OR entity_type = ?3)
+              AND (?4 IS NULL OR entity_id = ?4)
+              AND (?5 IS NULL OR assignee = ?5)
+            ORDER BY updated_at DESC
+            "#
+        )
+        .bind(filter.status.map(|s| s.as_str()))
+        .bind(&filter.book)
+        .bind(filter.entity_type.map(|e| e.as_str()))
+        .bind(&filter.entity_id)
+        .bind(&filter.assignee)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    pub async fn delete_issue(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM issues WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn add_issue_comment(&self, comment: &IssueComment) -> Result<()> {
+        sqlx::query("INSERT INTO issue_comments (id, issue_id, author, body, created_at) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .bind(&comment.id)
+            .bind(&comment.issue_id)
+            .bind(&comment.author)
+            .bind(&comment.body)
+            .bind(comment.created_at.naive_utc())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Comments of an issue, oldest first
+    pub async fn get_issue_comments(&self, issue_id: &str) -> Result<Vec<IssueComment>> {
+        let rows: Vec<(String, String, Option<String>, String, chrono::NaiveDateTime)> = sqlx::query_as(
+            "SELECT id, issue_id, author, body, created_at FROM issue_comments WHERE issue_id = ?1 ORDER BY created_at, rowid"
+        )
+        .bind(issue_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, issue_id, author, body, created_at)| IssueComment {
+                id,
+                issue_id,
+                author,
+                body,
+                created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+            })
+            .collect())
+    }
+
+    // === Share Link Operations ===
+
+    pub async fn save_share_link(&self, link: &ShareLink) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO share_links (id, target_type, target_id, expires_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5)"
+        )
+        .bind(&link.id)
+        .bind(link.target.kind())
+        .bind(link.target.id())
+        .bind(link.expires_at.naive_utc())
+        .bind(link.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_share_link(&self, id: &str) -> Result<Option<ShareLink>> {
+        let row = sqlx::query_as::<_, ShareLinkRow>("SELECT * FROM share_links WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(ShareLink::try_from).transpose()
+    }
+
+    /// Links that haven't expired, newest first
+    pub async fn list_share_links(&self) -> Result<Vec<ShareLink>> {
+        let rows = sqlx::query_as::<_, ShareLinkRow>(
+            "SELECT * FROM share_links WHERE expires_at > ?1 ORDER BY created_at DESC"
+        )
+        .bind(chrono::Utc::now().naive_utc())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(ShareLink::try_from).collect()
+    }
+
+    pub async fn delete_share_link(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM share_links WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The secret stored under `name`, generated on first use
+    pub async fn app_secret(&self, name: &str) -> Result<String> {
+        sqlx::query("INSERT OR IGNORE INTO app_secrets (name, value) VALUES (?1, ?2)")
+            .bind(name)
+            .bind(generate_secret())
+            .execute(&self.pool)
+            .await?;
+        let value = sqlx::query_scalar("SELECT value FROM app_secrets WHERE name = ?1")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(value)
+    }
+
+    // === Export Run Operations ===
+
+    /// Record an export and the problems it contained
+    pub async fn save_export_run(&self, run: &ExportRun, problems: &[SnapshotProblem]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO export_runs (id, book_id, chapter_id, format, filters, created_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+        )
+            .bind(&run.id)
+            .bind(&run.book_id)
+            .bind(&run.chapter_id)
+            .bind(&run.format)
+            .bind(serde_json::to_string(&run.filters)?)
+            .bind(run.created_at.naive_utc())
+            .execute(&mut *tx)
+            .await?;
+        for problem in problems {
+            sqlx::query(
+                "INSERT OR IGNORE INTO export_run_problems (run_id, problem_id, chapter_id, number, content_hash) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)"
+            )
+            .bind(&run.id)
+            .bind(&problem.problem_id)
+            .bind(&problem.chapter_id)
+            .bind(&problem.number)
+            .bind(&problem.content_hash)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the file a run produced
+    pub async fn finish_export_run(&self, run: &ExportRun) -> Result<()> {
+        sqlx::query(
+            "UPDATE export_runs SET file_name = ?2, content_type = ?3, size = ?4, checksum = ?5, \
+             storage_key = ?6, duration_ms = ?7, error = NULL WHERE id = ?1"
+        )
+        .bind(&run.id)
+        .bind(&run.file_name)
+        .bind(&run.content_type)
+        .bind(run.size.map(|s| s as i64))
+        .bind(&run.checksum)
+        .bind(&run.storage_key)
+        .bind(run.duration_ms.map(|d| d as i64))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Mark a run whose export broke off
+    pub async fn fail_export_run(&self, id: &str, error: &str, duration_ms: u64) -> Result<()> {
+        sqlx::query("UPDATE export_runs SET error = ?2, duration_ms = ?3 WHERE id = ?1")
+            .bind(id)
+            .bind(error)
+            .bind(duration_ms as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Stored files of a book's exports other than the newest `keep`, as
+    /// (run id, storage key)
+    pub async fn old_export_files(&self, book_id: &str, keep: i64) -> Result<Vec<(String, String)>> {
+        Ok(sqlx::query_as(
+            "SELECT id, storage_key FROM export_runs WHERE book_id = ?1 AND storage_key IS NOT NULL \
+             ORDER BY created_at DESC LIMIT -1 OFFSET ?2"
+        )
+        .bind(book_id)
+        .bind(keep)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Forget a run's file once it is deleted; the run and its problem
+    /// hashes stay for diffs
+    pub async fn clear_export_file(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE export_runs SET storage_key = NULL WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Export runs, newest first, optionally of one book
+    pub async fn list_export_runs(&self, book_id: Option<&str>, limit: i64) -> Result<Vec<ExportRun>> {
+        let rows = sqlx::query_as::<_, ExportRunRow>(
+            "SELECT * FROM export_runs WHERE (?1 IS NULL OR book_id = ?1) ORDER BY created_at DESC LIMIT ?2"
+        )
+        .bind(book_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    pub async fn get_export_run(&self, id: &str) -> Result<Option<ExportRun>> {
+        let row = sqlx::query_as::<_, ExportRunRow>("SELECT * FROM export_runs WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Problems an export contained, in chapter and insertion order
+    pub async fn get_export_run_problems(&self, run_id: &str) -> Result<Vec<SnapshotProblem>> {
+        let rows: Vec<(String, String, String, String)> = sqlx::query_as(
+            "SELECT problem_id, chapter_id, number, content_hash FROM export_run_problems \
+             WHERE run_id = ?1 ORDER BY rowid"
+        )
+        .bind(run_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(problem_id, chapter_id, number, content_hash)| SnapshotProblem {
+                problem_id,
+                chapter_id,
+                number,
+                content_hash,
+            })
+            .collect())
+    }
+
+    // === Digest Operations ===
+
+    /// Activity between `start` (inclusive) and `end`; the spend estimate is left to the caller
+    pub async fn get_digest_stats(
+        &self,
+        start: chrono::DateTime<chrono::Utc>,
+        end: chrono::DateTime<chrono::Utc>,
+    ) -> Result<DigestStats> {
+        let (start, end) = (start.naive_utc(), end.naive_utc());
+        let count = |sql: &'static str| {
+            sqlx::query_as::<_, (i64,)>(sql).bind(start).bind(end).fetch_one(&self.pool)
+        };
+
+        let (pages_ocrd,) = count(
+            "SELECT COUNT(*) FROM pages WHERE ocr_text IS NOT NULL AND updated_at >= ?1 AND updated_at < ?2"
+        ).await?;
+        let (problems_parsed,) = count(
+            "SELECT COUNT(*) FROM problems WHERE created_at >= ?1 AND created_at < ?2"
+        ).await?;
+        let (problems_viewed,) = count(
+            "SELECT COUNT(DISTINCT problem_id) FROM view_history WHERE viewed_at >= ?1 AND viewed_at < ?2"
+        ).await?;
+        let (submissions,) = count(
+            "SELECT COUNT(*) FROM submissions WHERE submitted_at >= ?1 AND submitted_at < ?2"
+        ).await?;
+        let (correct_submissions,) = count(
+            "SELECT COUNT(*) FROM submissions WHERE verdict = 'match' AND submitted_at >= ?1 AND submitted_at < ?2"
+        ).await?;
+
+        let solutions: Vec<(String, i64, i64)> = sqlx::query_as(
+            r#"
+            SELECT provider, COUNT(*), COALESCE(SUM(LENGTH(content)), 0)
+            FROM solutions
+            WHERE updated_at >= ?1 AND updated_at < ?2
+            GROUP BY provider
+            ORDER BY COUNT(*) DESC, provider
+            "#
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(DigestStats {
+            pages_ocrd: pages_ocrd as u64,
+            problems_parsed: problems_parsed as u64,
+            solutions: solutions
+                .into_iter()
+                .map(|(provider, count, chars)| ProviderSolutions { provider, solutions: count as u64, chars: chars as u64 })
+                .collect(),
+            estimated_spend_usd: 0.0,
+            problems_viewed: problems_viewed as u64,
+            submissions: submissions as u64,
+            correct_submissions: correct_submissions as u64,
+        })
+    }
+
+    pub async fn save_digest_report(&self, report: &DigestReport) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO digest_reports (id, period_start, period_end, stats, markdown, html, delivered, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#
+        )
+        .bind(&report.id)
+        .bind(report.period_start.naive_utc())
+        .bind(report.period_end.naive_utc())
+        .bind(serde_json::to_string(&report.stats)?)
+        .bind(&report.markdown)
+        .bind(&report.html)
+        .bind(report.delivered)
+        .bind(report.created_at.naive_utc())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_latest_digest_report(&self) -> Result<Option<DigestReport>> {
+        let row = sqlx::query_as::<_, DigestReportRow>(
+            "SELECT * FROM digest_reports ORDER BY period_end DESC LIMIT 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(DigestReport::try_from).transpose()
+    }
+
+    pub async fn mark_digest_delivered(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE digest_reports SET delivered = TRUE WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // === Glossary Operations ===
+
+    pub async fn get_generated_glossary(&self, book_id: &str) -> Result<Vec<GlossaryEntry>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT term, definition FROM glossary_terms WHERE book_id = ?1 ORDER BY term_key"
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(term, definition)| GlossaryEntry {
+                term,
+                definition,
+                source: "generated".to_string(),
+                chapter_number: None,
+                page_number: None,
+            })
+            .collect())
+    }
+
+    pub async fn save_generated_definition(&self, book_id: &str, term: &str, definition: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO glossary_terms (book_id, term_key, term, definition)
+            VALUES (?1, ?2, ?3, ?4)
+            "#
+        )
+        .bind(book_id)
+        .bind(term_key(term))
+        .bind(term)
+        .bind(definition)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // === LaTeX Macro Operations ===
+
+    /// A book's own macros; empty (defaults only) when none were saved
+    pub async fn get_book_macros(&self, book_id: &str) -> Result<BookMacros> {
+        let row: Option<(String, Option<String>)> = sqlx::query_as(
+            "SELECT macros, preamble FROM book_latex_macros WHERE book_id = ?1"
+        )
+        .bind(book_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(match row {
+            Some((macros, preamble)) => BookMacros {
+                macros: serde_json::from_str(&macros).unwrap_or_default(),
+                preamble,
+            },
+            None => BookMacros::default(),
+        })
+    }
+
+    pub async fn save_book_macros(&self, book_id: &str, macros: &BookMacros) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO book_latex_macros (book_id, macros, preamble, updated_at)
+            VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(book_id)
+        .bind(serde_json::to_string(&macros.macros)?)
+        .bind(&macros.preamble)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // === Heading Pattern Operations ===
+
+    /// A book's heading patterns; none (defaults only) when nothing was saved
+    pub async fn get_heading_overrides(&self, book_id: &str) -> Result<HeadingOverrides> {
+        let row: Option<(String, bool)> = sqlx::query_as(
+            "SELECT patterns, replace_defaults FROM book_heading_patterns WHERE book_id = ?1"
+        )
+        .bind(book_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(match row {
+            Some((patterns, replace_defaults)) => HeadingOverrides {
+                patterns: serde_json::from_str(&patterns).unwrap_or_default(),
+                replace_defaults,
+            },
+            None => HeadingOverrides::default(),
+        })
+    }
+
+    pub async fn save_heading_overrides(&self, book_id: &str, overrides: &HeadingOverrides) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO book_heading_patterns (book_id, patterns, replace_defaults, updated_at)
+            VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(book_id)
+        .bind(serde_json::to_string(&overrides.patterns)?)
+        .bind(overrides.replace_defaults)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // === Book Settings Operations ===
+
+    /// A book's settings; defaults when none were saved
+    pub async fn get_book_settings(&self, book_id: &str) -> Result<BookSettings> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT settings FROM book_settings WHERE book_id = ?1")
+            .bind(book_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(match row {
+            Some((settings,)) => serde_json::from_str(&settings).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable settings of {}: {}", book_id, e);
+                BookSettings::default()
+            }),
+            None => BookSettings::default(),
+        })
+    }
+
+    pub async fn save_book_settings(&self, book_id: &str, settings: &BookSettings) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO book_settings (book_id, settings, updated_at)
+            VALUES (?1, ?2, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(book_id)
+        .bind(serde_json::to_string(settings)?)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // === UI Preferences Operations ===
+
+    /// A profile's preferences; defaults when none were saved
+    pub async fn get_ui_preferences(&self, profile: &str) -> Result<UiPreferences> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT preferences FROM ui_preferences WHERE profile = ?1")
+            .bind(profile)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(match row {
+            Some((preferences,)) => serde_json::from_str(&preferences).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable preferences of {}: {}", profile, e);
+                UiPreferences::default()
+            }),
+            None => UiPreferences::default(),
+        })
+    }
+
+    pub async fn save_ui_preferences(&self, profile: &str, preferences: &UiPreferences) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO ui_preferences (profile, preferences, updated_at)
+            VALUES (?1, ?2, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(profile)
+        .bind(serde_json::to_string(preferences)?)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // === Idempotency Keys ===
+
+    /// Claim `key` for a request with `fingerprint`. Keys older than
+    /// `ttl_hours` are forgotten, so they claim anew.
+    pub async fn claim_idempotency_key(&self, key: &str, fingerprint: &str, ttl_hours: u32) -> Result<IdempotencyClaim> {
+        let expiry = format!("-{} hours", ttl_hours);
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = ?1 AND created_at < datetime('now', ?2)")
+            .bind(key)
+            .bind(&expiry)
+            .execute(&self.pool)
+            .await?;
+
+        let inserted = sqlx::query("INSERT OR IGNORE INTO idempotency_keys (key, fingerprint) VALUES (?1, ?2)")
+            .bind(key)
+            .bind(fingerprint)
+            .execute(&self.pool)
+            .await?;
+        if inserted.rows_affected() == 1 {
+            return Ok(IdempotencyClaim::New);
+        }
+
+        let row = sqlx::query_as::<_, IdempotencyKeyRow>(
+            "SELECT fingerprint, status, content_type, body FROM idempotency_keys WHERE key = ?1"
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(match row {
+            // Released between the insert and the select
+            None => IdempotencyClaim::InProgress,
+            Some(row) if row.fingerprint != fingerprint => IdempotencyClaim::Mismatch,
+            Some(IdempotencyKeyRow { status: None, .. }) => IdempotencyClaim::InProgress,
+            Some(IdempotencyKeyRow { status: Some(status), content_type, body, .. }) => {
+                IdempotencyClaim::Completed(StoredResponse {
+                    status: status as u16,
+                    content_type,
+                    body: body.unwrap_or_default(),
+                })
+            }
+        })
+    }
+
+    pub async fn complete_idempotency_key(&self, key: &str, response: &StoredResponse) -> Result<()> {
+        sqlx::query("UPDATE idempotency_keys SET status = ?2, content_type = ?3, body = ?4 WHERE key = ?1")
+            .bind(key)
+            .bind(response.status as i64)
+            .bind(&response.content_type)
+            .bind(&response.body)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forget a claimed key so the request can be retried
+    pub async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = ?1")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete keys claimed more than `ttl_hours` ago
+    pub async fn prune_idempotency_keys(&self, ttl_hours: u32) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < datetime('now', ?1)")
+            .bind(format!("-{} hours", ttl_hours))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // === Book Id Migration ===
+
+    /// Move a book and everything derived from it to a new id. Chapter,
+    /// page and problem ids start with `{book_id}:` and are rewritten too.
+    /// Runs in one transaction with foreign key checks deferred to commit.
+    pub async fn rename_book(&self, old_id: &str, new_id: &str) -> Result<()> {
+        let old_prefix = format!("{}:", old_id);
+        let new_prefix = format!("{}:", new_id);
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *tx)
+            .await?;
+
+        for table in [
+            "chapters", "pages", "ocr_audits", "ocr_rules", "explanations", "glossary_terms", "book_latex_macros",
+            "book_heading_patterns", "book_settings", "issues", "export_runs",
+        ] {
+            sqlx::query(&format!("UPDATE {} SET book_id = ?2 WHERE book_id = ?1", table))
+                .bind(old_id)
+                .bind(new_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for (table, column) in [
+            ("chapters", "id"),
+            ("pages", "id"),
+            ("problems", "id"),
+            ("problems", "chapter_id"),
+            ("problems", "page_id"),
+            ("problems", "parent_id"),
+            ("theory_blocks", "id"),
+            ("theory_blocks", "chapter_id"),
+            ("solutions", "problem_id"),
+            ("bookmarks", "problem_id"),
+            ("view_history", "problem_id"),
+            ("formula_attempts", "problem_id"),
+            ("problem_hints", "problem_id"),
+            ("problem_figures", "problem_id"),
+            ("problem_standards", "problem_id"),
+            ("assignment_problems", "problem_id"),
+            ("submissions", "problem_id"),
+            ("problem_calibrations", "problem_id"),
+            ("problem_pages", "problem_id"),
+            ("problem_pages", "page_id"),
+            ("archived_solutions", "problem_id"),
+            ("issues", "entity_id"),
+            ("export_runs", "chapter_id"),
+            ("export_run_problems", "problem_id"),
+            ("export_run_problems", "chapter_id"),
+            ("share_links", "target_id"),
+        ] {
+            sqlx::query(&format!(
+                "UPDATE {table} SET {column} = ?2 || substr({column}, length(?1) + 1) \
+                 WHERE substr({column}, 1, length(?1)) = ?1"
+            ))
+            .bind(&old_prefix)
+            .bind(&new_prefix)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Worksheets keep their problems as a JSON array of ids
+        sqlx::query("UPDATE worksheets SET problem_ids = replace(problem_ids, ?1, ?2)")
+            .bind(format!("\"{}", old_prefix))
+            .bind(format!("\"{}", new_prefix))
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE issues SET entity_id = ?2 WHERE entity_type = 'book' AND entity_id = ?1")
+            .bind(old_id)
+            .bind(new_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE books SET id = ?2 WHERE id = ?1")
+            .bind(old_id)
+            .bind(new_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // === Job History Operations ===
+
+    pub async fn save_job_record(&self, record: &JobRecord) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO jobs_history
+            (id, job_type, params, status, summary, result, error, created_at, finished_at, duration_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#
+        )
+        .bind(&record.id)
+        .bind(&record.job_type)
+        .bind(record.params.to_string())
+        .bind(&record.status)
+        .bind(&record.summary)
+        .bind(record.result.as_ref().map(|r| r.to_string()))
+        .bind(&record.error)
+        .bind(record.created_at.naive_utc())
+        .bind(record.finished_at.naive_utc())
+        .bind(record.duration_ms)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_job_record(&self, id: &str) -> Result<Option<JobRecord>> {
+        let row = sqlx::query_as::<_, JobRecordRow>(
+            "SELECT * FROM jobs_history WHERE id = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Finished jobs, newest first, optionally filtered by type and status
+    pub async fn get_job_history(&self, job_type: Option<&str>, status: Option<&str>, limit: usize) -> Result<Vec<JobRecord>> {
+        let rows = sqlx::query_as::<_, JobRecordRow>(
+            r#"SELECT * FROM jobs_history
+               WHERE (?1 IS NULL OR job_type = ?1) AND (?2 IS NULL OR status = ?2)
+               ORDER BY finished_at DESC
+               LIMIT ?3"#
+        )
+        .bind(job_type)
+        .bind(status)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Latest finished jobs of a book: the ones naming it in their
+    /// parameters and batch solves of its problems
+    pub async fn get_book_job_history(&self, book_id: &str, limit: usize) -> Result<Vec<JobRecord>> {
+        let rows = sqlx::query_as::<_, JobRecordRow>(
+            r#"SELECT * FROM jobs_history
+               WHERE json_extract(params, '$.book_id') = ?1
+                  OR EXISTS (
+                      SELECT 1 FROM json_each(jobs_history.params, '$.problem_ids')
+                      WHERE json_each.value LIKE ?1 || ':%'
+                  )
+               ORDER BY finished_at DESC
+               LIMIT ?2"#
+        )
+        .bind(book_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Delete history records finished more than `retention_days` ago
+    pub async fn prune_job_history(&self, retention_days: u32) -> Result<u64> {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(retention_days as i64);
+        let result = sqlx::query("DELETE FROM jobs_history WHERE finished_at < ?1")
+            .bind(cutoff.naive_utc())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // === Worksheet Operations ===
+
+    pub async fn save_worksheet(&self, worksheet: &Worksheet) -> Result<()> {
+        let ids_json = serde_json::to_string(&worksheet.problem_ids)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO worksheets (id, title, problem_ids, seed, include_answer_key)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#
+        )
+        .bind(&worksheet.id)
+        .bind(&worksheet.title)
+        .bind(ids_json)
+        .bind(worksheet.seed as i64)
+        .bind(worksheet.include_answer_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_worksheet(&self, id: &str) -> Result<Option<Worksheet>> {
+        let row = sqlx::query_as::<_, WorksheetRow>(
+            "SELECT * FROM worksheets WHERE id = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    pub async fn list_worksheets(&self) -> Result<Vec<Worksheet>> {
+        let rows = sqlx::query_as::<_, WorksheetRow>(
+            "SELECT * FROM worksheets ORDER BY created_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    // === OCR Rule Operations ===
+
+    pub async fn save_ocr_rule(&self, rule: &OcrRule) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO ocr_rules (id, book_id, pattern, replacement, position, enabled, description)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT(id) DO UPDATE SET
+                pattern = excluded.pattern,
+                replacement = excluded.replacement,
+                position = excluded.position,
+                enabled = excluded.enabled,
+                description = excluded.description
+            "#
+        )
+        .bind(&rule.id)
+        .bind(&rule.book_id)
+        .bind(&rule.pattern)
+        .bind(&rule.replacement)
+        .bind(rule.position)
+        .bind(rule.enabled)
+        .bind(&rule.description)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_ocr_rule(&self, id: &str) -> Result<Option<OcrRule>> {
+        let row = sqlx::query_as::<_, OcrRuleRow>(
+            "SELECT * FROM ocr_rules WHERE id = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// All rules for a book (including disabled ones) in application order
+    pub async fn get_ocr_rules(&self, book_id: &str) -> Result<Vec<OcrRule>> {
+        let rows = sqlx::query_as::<_, OcrRuleRow>(
+            "SELECT * FROM ocr_rules WHERE book_id = ?1 ORDER BY position, created_at"
+        )
+        .bind(book_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    pub async fn delete_ocr_rule(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM ocr_rules WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // === Search Operations ===
+
+    pub async fn search_by_formula(&self, formula: &str, limit: usize) -> Result<Vec<Problem>> {
+        let pattern = format!("%{}%", formula);
+        let rows = sqlx::query_as::<_, ProblemRow>(
+            "SELECT * FROM problems WHERE latex_formulas LIKE ?1 LIMIT ?2"
+        )
+        .bind(pattern)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Problems, theory blocks and solutions matching an FTS5 expression
+    /// (see [`crate::services::text_search::match_expression`]), best first
+    pub async fn full_text_search(
+        &self,
+        expression: &str,
+        kind: Option<SearchKind>,
+        book_id: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let snippet =
+            |index: &str| format!("snippet({}, 0, '{}', '{}', '…', 16)", index, RAW_SNIPPET_START, RAW_SNIPPET_END);
+        let sql = format!(
+            r#"
+            SELECT 'problem' AS kind, p.id AS id, p.id AS problem_id, p.chapter_id AS chapter_id, p.display_name AS title,
+                   {problem_snippet} AS snippet, bm25(problems_fts) AS rank
+            FROM problems_fts JOIN problems p ON p.rowid = problems_fts.rowid
+            WHERE problems_fts MATCH ?1 AND (?2 IS NULL OR ?2 = 'problem')
+              AND (?3 IS NULL OR p.chapter_id IN (SELECT id FROM chapters WHERE book_id = ?3))
+            UNION ALL
+            SELECT 'theory', t.id, NULL, t.chapter_id, t.title, {theory_snippet}, bm25(theory_fts)
+            FROM theory_fts JOIN theory_blocks t ON t.rowid = theory_fts.rowid
+            WHERE theory_fts MATCH ?1 AND (?2 IS NULL OR ?2 = 'theory')
+              AND (?3 IS NULL OR t.chapter_id IN (SELECT id FROM chapters WHERE book_id = ?3))
+            UNION ALL
+            SELECT 'solution', s.id, s.problem_id, p.chapter_id, s.provider, {solution_snippet}, bm25(solutions_fts)
+            FROM solutions_fts JOIN solutions s ON s.rowid = solutions_fts.rowid
+            JOIN problems p ON p.id = s.problem_id
+            WHERE solutions_fts MATCH ?1 AND (?2 IS NULL OR ?2 = 'solution')
+              AND (?3 IS NULL OR p.chapter_id IN (SELECT id FROM chapters WHERE book_id = ?3))
+            ORDER BY rank
+            LIMIT ?4 OFFSET ?5
+            "#,
+            problem_snippet = snippet("problems_fts"),
+            theory_snippet = snippet("theory_fts"),
+            solution_snippet = snippet("solutions_fts"),
+        );
+        let rows = sqlx::query_as::<_, SearchHitRow>(&sql)
+            .bind(expression)
+            .bind(kind.map(|k| k.as_str()))
+            .bind(book_id)
+            .bind(limit as i64)
+            .bind(offset as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    pub async fn advanced_search(
+        &self,
+        query: Option<&str>,
+        formula: Option<&str>,
+        chapter_id: Option<&str>,
+        book_id: Option<&str>,
+        has_solution: Option<bool>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Problem>> {
+        // Build query dynamically based on which filters are provided
+        // Use simpler approach - build SQL string based on what's provided
+        
+        let (sql, params): (String, Vec<String>) = match (
+            query.filter(|q| !q.is_empty()),
+            formula.filter(|f| !f.is_empty()),
+            chapter_id,
+            book_id,
+            has_solution,
+        ) {
+            (None, None, None, None, None) => {
+                // No filters - just get all
+                (format!(
+                    "SELECT * FROM problems ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![])
+            }
+            (Some(q), None, None, None, None) => {
+                let pattern = format!("%{}%", q);
+                (format!(
+                    "SELECT * FROM problems WHERE content LIKE ? OR display_name LIKE ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![pattern.clone(), pattern])
+            }
+            (None, Some(f), None, None, None) => {
+                let pattern = format!("%{}%", f);
+                (format!(
+                    "SELECT * FROM problems WHERE latex_formulas LIKE ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![pattern])
+            }
+            (None, None, Some(ch), None, None) => {
+                let pattern = format!("{}%", ch);
+                (format!(
+                    "SELECT * FROM problems WHERE chapter_id LIKE ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![pattern])
+            }
+            (None, None, None, Some(bid), None) => {
+                let pattern = format!("{}%", bid);
+                (format!(
+                    "SELECT * FROM problems WHERE chapter_id LIKE ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![pattern])
+            }
+            (None, None, None, None, Some(hs)) => {
+                let val = if hs { 1 } else { 0 };
+                (format!(
+                    "SELECT * FROM problems WHERE has_solution = ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![val.to_string()])
+            }
+            // For combinations, use a simpler approach - just filter by text for now
+            _ => {
+                let pattern = query.map(|q| format!("%{}%", q)).unwrap_or_default();
+                (format!(
+                    "SELECT * FROM problems WHERE content LIKE ? OR display_name LIKE ? ORDER BY chapter_id, numeric_order LIMIT {} OFFSET {}",
+                    limit, offset
+                ), vec![pattern.clone(), pattern])
+            }
+        };
+        
+        let mut q = sqlx::query_as::<_, ProblemRow>(&sql);
+        for p in &params {
+            q = q.bind(p.as_str());
+        }
+        
+        let rows = q.fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    pub async fn search_count(
+        &self,
+        query: Option<&str>,
+        _formula: Option<&str>,
+        _chapter_id: Option<&str>,
+        _book_id: Option<&str>,
+        has_solution: Option<bool>,
+    ) -> Result<i64> {
+        // Simplified count - just count all or by has_solution
+        let sql = if let Some(hs) = has_solution {
+            let val = if hs { 1 } else { 0 };
+            format!("SELECT COUNT(*) FROM problems WHERE has_solution = {}", val)
+        } else {
+            "SELECT COUNT(*) FROM problems".to_string()
+        };
+        
+        let count: i64 = sqlx::query_scalar(&sql).fetch_one(&self.pool).await?;
+        Ok(count)
+    }
+}
+
+// === Database Row Types ===
+
+#[derive(sqlx::FromRow)]
+struct BookRow {
+    id: String,
+    title: String,
+    author: Option<String>,
+    subject: Option<String>,
+    file_path: String,
+    total_pages: i64,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<BookRow> for Book {
+    fn from(row: BookRow) -> Self {
+        Self {
+            id: row.id,
+            title: row.title,
+            author: row.author,
+            subject: row.subject,
+            file_path: row.file_path,
+            total_pages: row.total_pages as u32,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ChapterRow {
+    id: String,
+    book_id: String,
+    number: i64,
+    title: String,
+    description: Option<String>,
+    problem_count: i64,
+    theory_count: i64,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<ChapterRow> for Chapter {
+    fn from(row: ChapterRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            number: row.number as u32,
+            title: row.title,
+            description: row.description,
+            problem_count: row.problem_count as u32,
+            theory_count: row.theory_count as u32,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+/// `(provider, verified_only)` binds for the solution filter queries
+fn solution_filter_binds(filter: &SolutionFilter) -> (Option<&str>, bool) {
+    match filter {
+        SolutionFilter::Any => (None, false),
+        SolutionFilter::Verified => (None, true),
+        SolutionFilter::Provider(p) => (Some(p.as_str()), false),
+    }
+}
+
+const PROBLEM_INSERT: &str = r#"INSERT INTO problems
+    (id, chapter_id, page_id, parent_id, number, display_name, content, latex_formulas,
+     page_number, difficulty, has_solution, continues_from_page, continues_to_page, is_cross_page, confidence,
+     numeric_order, sub_position)"#;
+
+const PROBLEM_UPSERT: &str = r#"ON CONFLICT(id) DO UPDATE SET
+    chapter_id = excluded.chapter_id,
+    page_id = excluded.page_id,
+    parent_id = excluded.parent_id,
+    number = excluded.number,
+    numeric_order = excluded.numeric_order,
+    sub_position = excluded.sub_position,
+    display_name = excluded.display_name,
+    content = excluded.content,
+    latex_formulas = excluded.latex_formulas,
+    page_number = excluded.page_number,
+    difficulty = excluded.difficulty,
+    -- Keep has_solution as-is (don't wipe user-generated data)
+    continues_from_page = excluded.continues_from_page,
+    continues_to_page = excluded.continues_to_page,
+    is_cross_page = excluded.is_cross_page,
+    confidence = excluded.confidence,
+    -- Changed content has to be reviewed again
+    review_status = CASE WHEN problems.content = excluded.content
+        THEN problems.review_status ELSE 'unreviewed' END,
+    version = problems.version + (problems.content != excluded.content)"#;
+
+/// Rows per multi-row problem insert (17 binds each)
+const PROBLEM_INSERT_CHUNK: usize = 200;
+/// Ids per `IN (...)` lookup
+const PROBLEM_LOOKUP_CHUNK: usize = 500;
+
+/// Binds a problem to the `?1`..`?15` of [`PROBLEM_INSERT`]
+fn bind_problem<'q>(
+    query: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    problem: &'q Problem,
+) -> Result<sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>> {
+    Ok(query
+        .bind(&problem.id)
+        .bind(&problem.chapter_id)
+        .bind(&problem.page_id)
+        .bind(&problem.parent_id)
+        .bind(&problem.number)
+        .bind(&problem.display_name)
+        .bind(&problem.content)
+        .bind(serde_json::to_string(&problem.latex_formulas)?)
+        .bind(problem.page_number.map(|p| p as i64))
+        .bind(problem.difficulty.map(|d| d as i64))
+        .bind(problem.has_solution)
+        .bind(problem.continues_from_page.map(|p| p as i64))
+        .bind(problem.continues_to_page.map(|p| p as i64))
+        .bind(problem.continues_from_page.is_some() || problem.continues_to_page.is_some())
+        .bind(problem.confidence.map(|c| c as f64))
+        .bind(Problem::numeric_order(&problem.number))
+        .bind(sub_position(problem)))
+}
+
+/// Stored position of a sub-problem among its siblings; None for top-level
+/// problems
+fn sub_position(problem: &Problem) -> Option<i64> {
+    problem.parent_id.as_ref()?;
+    Problem::sub_position(&problem.number).map(|p| p as i64)
+}
+
+/// Number a problem occupies under the uniqueness indexes: main problems per
+/// chapter, sub-problems per parent
+fn problem_slot(chapter_id: &str, parent_id: Option<&str>, number: &str) -> (bool, String, String) {
+    match parent_id {
+        Some(parent) => (true, parent.to_string(), number.to_string()),
+        None => (false, chapter_id.to_string(), number.to_string()),
+    }
+}
+
+type AttachmentRow = (String, String, String, String, i64, String, Option<String>, chrono::NaiveDateTime);
+
+fn attachment_from_row(
+    (id, solution_id, file_name, content_type, size, storage_key, plot_spec, created_at): AttachmentRow,
+) -> Result<SolutionAttachment> {
+    Ok(SolutionAttachment {
+        id,
+        solution_id,
+        file_name,
+        content_type,
+        size: size as u64,
+        storage_key,
+        plot_spec: plot_spec.as_deref().map(serde_json::from_str).transpose()?,
+        created_at: chrono::DateTime::from_naive_utc_and_offset(created_at, chrono::Utc),
+    })
+}
+
+/// Tables whose `problem_id` points at a problem
+const PROBLEM_REF_TABLES: &[&str] = &[
+    "solutions", "bookmarks", "view_history", "formula_attempts", "problem_hints", "archived_solutions", "problem_pages",
+    "problem_figures", "problem_standards", "assignment_problems", "submissions", "export_run_problems",
+    "problem_calibrations",
+];
+
+/// Point everything that refers to problem `from` (but not to its
+/// sub-problems) at `to`. Rows that would clash with ones `to` already has
+/// are left behind and go when `from` is deleted.
+async fn move_problem_refs(tx: &mut sqlx::Transaction<'_, Sqlite>, from: &str, to: &str) -> Result<()> {
+    for table in PROBLEM_REF_TABLES {
+        sqlx::query(&format!("UPDATE OR IGNORE {} SET problem_id = ?2 WHERE problem_id = ?1", table))
+            .bind(from)
+            .bind(to)
+            .execute(&mut **tx)
+            .await?;
+    }
+    // Worksheets keep their problems as a JSON array of ids
+    sqlx::query("UPDATE worksheets SET problem_ids = replace(problem_ids, ?1, ?2)")
+        .bind(format!("\"{}\"", from))
+        .bind(format!("\"{}\"", to))
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE issues SET entity_id = ?2 WHERE entity_type = 'problem' AND entity_id = ?1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE share_links SET target_id = ?2 WHERE target_type = 'problem' AND target_id = ?1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Writes inside [`Database::transaction`]; each behaves like the
+/// [`Database`] method of the same name
+pub struct DbTransaction {
+    tx: sqlx::Transaction<'static, Sqlite>,
+}
+
+impl DbTransaction {
+    pub async fn create_book(&mut self, book: &Book) -> Result<()> {
+        create_book(&mut self.tx, book).await
+    }
+
+    pub async fn create_chapter(&mut self, chapter: &Chapter) -> Result<()> {
+        create_chapter(&mut self.tx, chapter).await
+    }
+
+    pub async fn get_or_create_page(&mut self, book_id: &str, page_number: u32) -> Result<crate::models::Page> {
+        get_or_create_page(&mut self.tx, book_id, page_number).await
+    }
+
+    pub async fn update_page_ocr(&mut self, page_id: &str, ocr_text: &str, problem_count: u32) -> Result<()> {
+        update_page_ocr(&mut self.tx, page_id, ocr_text, problem_count).await
+    }
+
+    pub async fn delete_problems_by_page(&mut self, page_id: &str) -> Result<usize> {
+        delete_problems_by_page(&mut self.tx, page_id).await
+    }
+
+    pub async fn create_or_update_problems(&mut self, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+        create_or_update_problems(&mut self.tx, problems).await
+    }
+
+    pub async fn save_page_problems(&mut self, page_id: &str, page_number: u32, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+        save_page_problems(&mut self.tx, page_id, page_number, problems).await
+    }
+
+    pub async fn create_theory_block(&mut self, theory: &TheoryBlock) -> Result<()> {
+        create_theory_block(&mut self.tx, theory).await
+    }
+}
+
+/// Problems, sub-problems and solutions stored for a book
+async fn book_problem_counts(conn: &mut SqliteConnection, book_id: &str) -> Result<ProblemDeletion> {
+    let (problems, sub_problems): (i64, i64) = sqlx::query_as(
+        r#"SELECT COALESCE(SUM(p.parent_id IS NULL), 0), COALESCE(SUM(p.parent_id IS NOT NULL), 0)
+           FROM problems p JOIN chapters c ON c.id = p.chapter_id
+           WHERE c.book_id = ?1"#
+    )
+    .bind(book_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    let solutions: i64 = sqlx::query_scalar(
+        r#"SELECT COUNT(*) FROM solutions s
+           JOIN problems p ON p.id = s.problem_id
+           JOIN chapters c ON c.id = p.chapter_id
+           WHERE c.book_id = ?1"#
+    )
+    .bind(book_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(ProblemDeletion { problems: problems as u32, sub_problems: sub_problems as u32, solutions: solutions as u32 })
+}
+
+// === Statements shared by Database and DbTransaction ===
+
+async fn create_book(conn: &mut SqliteConnection, book: &Book) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO books (id, title, author, subject, file_path, total_pages)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT(id) DO UPDATE SET
+            title = excluded.title,
+            author = excluded.author,
+            subject = excluded.subject,
+            total_pages = excluded.total_pages
+        "#
+    )
+    .bind(&book.id)
+    .bind(&book.title)
+    .bind(&book.author)
+    .bind(&book.subject)
+    .bind(&book.file_path)
+    .bind(book.total_pages as i64)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn create_chapter(conn: &mut SqliteConnection, chapter: &Chapter) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO chapters (id, book_id, number, title, description, problem_count, theory_count)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT(id) DO UPDATE SET
+            title = excluded.title,
+            description = excluded.description
+        "#
+    )
+    .bind(&chapter.id)
+    .bind(&chapter.book_id)
+    .bind(chapter.number as i64)
+    .bind(&chapter.title)
+    .bind(&chapter.description)
+    .bind(chapter.problem_count as i64)
+    .bind(chapter.theory_count as i64)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn delete_problems_by_page(conn: &mut SqliteConnection, page_id: &str) -> Result<usize> {
+    // Problems continued on other pages only lose this page's part
+    let spanning: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT problem_id FROM problem_pages WHERE problem_id IN \
+         (SELECT problem_id FROM problem_pages WHERE page_id = ?1) AND page_id != ?1"
+    )
+    .bind(page_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    sqlx::query("DELETE FROM problem_pages WHERE page_id = ?1")
+        .bind(page_id)
+        .execute(&mut *conn)
+        .await?;
+
+    // First delete sub-problems (they reference parent problems)
+    let sub_count = sqlx::query(
+        r#"DELETE FROM problems WHERE parent_id IN
+           (SELECT id FROM problems WHERE page_id = ?1 AND id NOT IN (SELECT problem_id FROM problem_pages))"#
+    )
+    .bind(page_id)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    
+    // Then delete parent problems
+    let parent_count = sqlx::query(
+        "DELETE FROM problems WHERE page_id = ?1 AND id NOT IN (SELECT problem_id FROM problem_pages)"
+    )
+    .bind(page_id)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    for problem_id in &spanning {
+        merge_problem_pages(&mut *conn, problem_id).await?;
+    }
+    
+    Ok((sub_count + parent_count) as usize)
+}
+
+async fn create_or_update_problems(conn: &mut SqliteConnection, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+    if problems.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut tx = conn.begin().await?;
+
+    // Everything the batch can collide with: its chapters, plus its ids
+    // in case a problem moves between chapters
+    let mut chapters: Vec<&str> = problems.iter().map(|p| p.chapter_id.as_str()).collect();
+    chapters.sort();
+    chapters.dedup();
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT id, chapter_id, parent_id, number, content FROM problems WHERE chapter_id IN ("
+    );
+    let mut list = query.separated(", ");
+    for chapter in &chapters {
+        list.push_bind(*chapter);
+    }
+    query.push(")");
+    let mut existing: Vec<(String, String, Option<String>, String, String)> =
+        query.build_query_as().fetch_all(&mut *tx).await?;
+
+    let known: HashSet<&str> = existing.iter().map(|row| row.0.as_str()).collect();
+    let others: Vec<&str> = problems.iter().map(|p| p.id.as_str()).filter(|id| !known.contains(id)).collect();
+    for ids in others.chunks(PROBLEM_LOOKUP_CHUNK) {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, chapter_id, parent_id, number, content FROM problems WHERE id IN ("
+        );
+        let mut list = query.separated(", ");
+        for id in ids {
+            list.push_bind(*id);
+        }
+        query.push(")");
+        existing.extend(query.build_query_as().fetch_all(&mut *tx).await?);
+    }
+
+    let mut contents: HashMap<String, String> = HashMap::new();
+    let mut slot_owner: HashMap<(bool, String, String), String> = HashMap::new();
+    let mut slot_of: HashMap<String, (bool, String, String)> = HashMap::new();
+    for (id, chapter_id, parent_id, number, content) in existing {
+        let slot = problem_slot(&chapter_id, parent_id.as_deref(), &number);
+        slot_owner.insert(slot.clone(), id.clone());
+        slot_of.insert(id.clone(), slot);
+        contents.insert(id, content);
+    }
+
+    let mut writes = Vec::with_capacity(problems.len());
+    let mut to_write = Vec::with_capacity(problems.len());
+    for problem in problems {
+        let slot = problem_slot(&problem.chapter_id, problem.parent_id.as_deref(), &problem.number);
+        let holder = slot_owner.get(&slot).filter(|owner| **owner != problem.id).cloned();
+        let orphaned = problem.parent_id.as_ref().is_some_and(|parent| !contents.contains_key(parent));
+        if holder.is_some() || orphaned {
+            writes.push(ProblemWrite {
+                id: problem.id.clone(),
+                outcome: ProblemWriteOutcome::Conflict,
+                existing_id: holder,
+            });
+            continue;
+        }
+
+        let outcome = match contents.get(&problem.id) {
+            None => ProblemWriteOutcome::Inserted,
+            Some(content) if *content == problem.content => ProblemWriteOutcome::Unchanged,
+            Some(_) => ProblemWriteOutcome::Updated,
+        };
+        // The row leaves its old number, which later rows may take
+        if let Some(old) = slot_of.insert(problem.id.clone(), slot.clone())
+            && old != slot
+        {
+            slot_owner.remove(&old);
+        }
+        slot_owner.insert(slot, problem.id.clone());
+        contents.insert(problem.id.clone(), problem.content.clone());
+        writes.push(ProblemWrite { id: problem.id.clone(), outcome, existing_id: None });
+        to_write.push(problem);
+    }
+
+    for chunk in to_write.chunks(PROBLEM_INSERT_CHUNK) {
+        let mut query = QueryBuilder::<Sqlite>::new(PROBLEM_INSERT);
+        let mut formulas = Vec::with_capacity(chunk.len());
+        for problem in chunk {
+            formulas.push(serde_json::to_string(&problem.latex_formulas)?);
+        }
+        query.push_values(chunk.iter().zip(formulas), |mut row, (problem, formulas_json)| {
+            row.push_bind(&problem.id)
+                .push_bind(&problem.chapter_id)
+                .push_bind(&problem.page_id)
+                .push_bind(&problem.parent_id)
+                .push_bind(&problem.number)
+                .push_bind(&problem.display_name)
+                .push_bind(&problem.content)
+                .push_bind(formulas_json)
+                .push_bind(problem.page_number.map(|p| p as i64))
+                .push_bind(problem.difficulty.map(|d| d as i64))
+                .push_bind(problem.has_solution)
+                .push_bind(problem.continues_from_page.map(|p| p as i64))
+                .push_bind(problem.continues_to_page.map(|p| p as i64))
+                .push_bind(problem.continues_from_page.is_some() || problem.continues_to_page.is_some())
+                .push_bind(problem.confidence.map(|c| c as f64))
+                .push_bind(Problem::numeric_order(&problem.number))
+                .push_bind(sub_position(problem));
+        });
+        query.push(" ");
+        query.push(PROBLEM_UPSERT);
+        query.build().execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+    Ok(writes)
+}
+
+async fn save_page_problems(conn: &mut SqliteConnection, page_id: &str, page_number: u32, problems: &[Problem]) -> Result<Vec<ProblemWrite>> {
+    let writes = create_or_update_problems(&mut *conn, problems).await?;
+
+    let written = problems.iter().zip(&writes).filter(|(_, w)| w.is_written()).map(|(p, _)| p);
+    for problem in written.filter(|p| p.parent_id.is_none()) {
+        let part = ProblemPage {
+            problem_id: problem.id.clone(),
+            page_id: page_id.to_string(),
+            page_number,
+            content: problem.content.clone(),
+            continues_from_prev: problem.continues_from_page.is_some(),
+            continues_to_next: problem.continues_to_page.is_some(),
+        };
+        // A part that starts (ends) the problem makes parts before (after)
+        // it leftovers of an older parse
+        sqlx::query(
+            r#"DELETE FROM problem_pages WHERE problem_id = ?1
+               AND ((?3 = 0 AND page_number < ?2) OR (?4 = 0 AND page_number > ?2))"#
+        )
+        .bind(&part.problem_id)
+        .bind(part.page_number as i64)
+        .bind(part.continues_from_prev)
+        .bind(part.continues_to_next)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO problem_pages
+               (problem_id, page_id, page_number, content, continues_from_prev, continues_to_next)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#
+        )
+        .bind(&part.problem_id)
+        .bind(&part.page_id)
+        .bind(part.page_number as i64)
+        .bind(&part.content)
+        .bind(part.continues_from_prev)
+        .bind(part.continues_to_next)
+        .execute(&mut *conn)
+        .await?;
+
+        merge_problem_pages(&mut *conn, &problem.id).await?;
+    }
+
+    // Pages the problems continue onto may not have been read yet
+    let neighbours: Vec<u32> = problems
+        .iter()
+        .filter(|p| p.parent_id.is_none())
+        .flat_map(|p| [p.continues_from_page, p.continues_to_page])
+        .flatten()
+        .filter(|&page| page != page_number)
+        .collect();
+    if !neighbours.is_empty()
+        && let Some(book_id) = sqlx::query_scalar::<_, String>("SELECT book_id FROM pages WHERE id = ?1")
+            .bind(page_id)
+            .fetch_optional(&mut *conn)
+            .await?
+    {
+        add_pending_pages(&mut *conn, &book_id, &neighbours).await?;
+    }
+
+    Ok(writes)
+}
+
+async fn add_pending_pages(conn: &mut SqliteConnection, book_id: &str, pages: &[u32]) -> Result<u64> {
+    let mut created = 0;
+    for &page_number in pages.iter().filter(|&&page| page > 0) {
+        created += sqlx::query(
+            "INSERT INTO pages (id, book_id, page_number) SELECT ?1, id, ?2 FROM books \
+             WHERE id = ?3 AND (total_pages = 0 OR ?2 <= total_pages) ON CONFLICT DO NOTHING",
+        )
+        .bind(format!("{}:page:{}", book_id, page_number))
+        .bind(page_number as i64)
+        .bind(book_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    }
+    Ok(created)
+}
+
+async fn get_problem_pages(conn: &mut SqliteConnection, problem_id: &str) -> Result<Vec<ProblemPage>> {
+    let rows: Vec<(String, String, i64, String, bool, bool)> = sqlx::query_as(
+        r#"SELECT problem_id, page_id, page_number, content, continues_from_prev, continues_to_next
+           FROM problem_pages WHERE problem_id = ?1 ORDER BY page_number"#
+    )
+    .bind(problem_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(problem_id, page_id, page_number, content, continues_from_prev, continues_to_next)| ProblemPage {
+            problem_id,
+            page_id,
+            page_number: page_number as u32,
+            content,
+            continues_from_prev,
+            continues_to_next,
+        })
+        .collect())
+}
+
+/// Rebuild a problem row from its parts: the statement joins them in page
+/// order and the problem sits on its first page. The continuation fields
+/// only mark pages that haven't been parsed yet.
+async fn merge_problem_pages(conn: &mut SqliteConnection, problem_id: &str) -> Result<()> {
+    let parts = get_problem_pages(&mut *conn, problem_id).await?;
+    let (Some(first), Some(last)) = (parts.first(), parts.last()) else {
+        return Ok(());
+    };
+    let row = sqlx::query_as::<_, ProblemRow>("SELECT * FROM problems WHERE id = ?1")
+        .bind(problem_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(mut problem) = row.map(Problem::from) else {
+        return Ok(());
+    };
+
+    problem.content = parts
+        .iter()
+        .map(|p| p.content.trim())
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let continues_from_page = first.continues_from_prev.then(|| first.page_number.saturating_sub(1));
+    let continues_to_page = last.continues_to_next.then_some(last.page_number + 1);
+    let is_cross_page = parts.len() > 1 || continues_from_page.is_some() || continues_to_page.is_some();
+
+    sqlx::query(
+        r#"UPDATE problems SET content = ?2, latex_formulas = ?3, page_id = ?4, page_number = ?5,
+               continues_from_page = ?6, continues_to_page = ?7, is_cross_page = ?8,
+               version = version + (content != ?2)
+           WHERE id = ?1"#
+    )
+    .bind(problem_id)
+    .bind(&problem.content)
+    .bind(serde_json::to_string(&problem.extract_formulas())?)
+    .bind(&first.page_id)
+    .bind(first.page_number as i64)
+    .bind(continues_from_page.map(|p| p as i64))
+    .bind(continues_to_page.map(|p| p as i64))
+    .bind(is_cross_page)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn get_or_create_page(conn: &mut SqliteConnection, book_id: &str, page_number: u32) -> Result<crate::models::Page> {
+    let page_id = format!("{}:page:{}", book_id, page_number);
+    
+    // Try to get existing page
+    let existing = sqlx::query_as::<_, PageRow>(
+        "SELECT * FROM pages WHERE id = ?1"
+    )
+    .bind(&page_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    
+    if let Some(row) = existing {
+        return Ok(row.into());
+    }
+    
+    // Ensure book exists first, keeping the title and page count of one
+    // that does
+    sqlx::query(
+        "INSERT INTO books (id, title, file_path, total_pages) VALUES (?1, ?1, ?2, 0) ON CONFLICT(id) DO NOTHING",
+    )
+    .bind(book_id)
+    .bind(format!("resources/{}.pdf", book_id))
+    .execute(&mut *conn)
+    .await?;
+
+    // Create new page
+    let now = chrono::Utc::now();
+    let page = crate::models::Page {
+        id: page_id.clone(),
+        book_id: book_id.to_string(),
+        page_number,
+        ocr_text: None,
+        has_problems: false,
+        problem_count: 0,
+        page_kind: None,
+        ocr_provider: None,
+        created_at: now,
+        updated_at: now,
+    };
+    
+    sqlx::query(
+        "INSERT INTO pages (id, book_id, page_number, ocr_text, has_problems, problem_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    )
+    .bind(&page.id)
+    .bind(&page.book_id)
+    .bind(page_number as i64)
+    .bind(&page.ocr_text)
+    .bind(page.has_problems)
+    .bind(page.problem_count as i64)
+    .execute(&mut *conn)
+    .await?;
+    
+    Ok(page)
+}
+
+async fn update_page_ocr(conn: &mut SqliteConnection, page_id: &str, ocr_text: &str, problem_count: u32) -> Result<()> {
+    sqlx::query(
+        "UPDATE pages SET ocr_text = ?1, has_problems = ?2, problem_count = ?3, updated_at = CURRENT_TIMESTAMP WHERE id = ?4"
+    )
+    .bind(ocr_text)
+    .bind(problem_count > 0)
+    .bind(problem_count as i64)
+    .bind(page_id)
+    .execute(&mut *conn)
+    .await?;
+    
+    Ok(())
+}
+
+async fn create_theory_block(conn: &mut SqliteConnection, theory: &TheoryBlock) -> Result<()> {
+    let formulas_json = serde_json::to_string(&theory.latex_formulas)?;
+    
+    sqlx::query(
+        r#"
+        INSERT INTO theory_blocks (id, chapter_id, block_num, title, block_type, content, latex_formulas, page_number)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT(id) DO UPDATE SET
+            content = excluded.content,
+            latex_formulas = excluded.latex_formulas
+        "#
+    )
+    .bind(&theory.id)
+    .bind(&theory.chapter_id)
+    .bind(theory.block_num as i64)
+    .bind(&theory.title)
+    .bind(theory.block_type.as_str())
+    .bind(&theory.content)
+    .bind(formulas_json)
+    .bind(theory.page_number.map(|p| p as i64))
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Rewrite references to a renamed problem and to its sub-problems, whose
+/// ids start with the problem's
+async fn rename_problem_refs(tx: &mut sqlx::Transaction<'_, Sqlite>, old_id: &str, new_id: &str) -> Result<()> {
+    for table in PROBLEM_REF_TABLES {
+        sqlx::query(&format!(
+            "UPDATE {table} SET problem_id = ?2 || substr(problem_id, length(?1) + 1) \
+             WHERE problem_id = ?1 OR substr(problem_id, 1, length(?1) + 1) = ?1 || ':'"
+        ))
+        .bind(old_id)
+        .bind(new_id)
+        .execute(&mut **tx)
+        .await?;
+    }
+    for suffix in ["\"", ":"] {
+        sqlx::query("UPDATE worksheets SET problem_ids = replace(problem_ids, ?1, ?2)")
+            .bind(format!("\"{}{}", old_id, suffix))
+            .bind(format!("\"{}{}", new_id, suffix))
+            .execute(&mut **tx)
+            .await?;
+    }
+    sqlx::query(
+        "UPDATE share_links SET target_id = ?2 || substr(target_id, length(?1) + 1) \
+         WHERE target_type = 'problem' AND (target_id = ?1 OR substr(target_id, 1, length(?1) + 1) = ?1 || ':')"
+    )
+    .bind(old_id)
+    .bind(new_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct ProblemRow {
+    id: String,
+    chapter_id: String,
+    page_id: Option<String>,
+    parent_id: Option<String>,
+    number: String,
+    display_name: String,
+    content: String,
+    latex_formulas: String,
+    page_number: Option<i64>,
+    difficulty: Option<i64>,
+    has_solution: bool,
+    created_at: chrono::NaiveDateTime,
+    continues_from_page: Option<i64>,
+    continues_to_page: Option<i64>,
+    is_cross_page: Option<bool>,
+    confidence: Option<f64>,
+    review_status: Option<String>,
+    review_note: Option<String>,
+    reference_answer: Option<String>,
+    #[sqlx(default)]
+    version: i64,
+}
+
+impl From<ProblemRow> for Problem {
+    fn from(row: ProblemRow) -> Self {
+        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
+        
+        Self {
+            id: row.id,
+            chapter_id: row.chapter_id,
+            page_id: row.page_id,
+            parent_id: row.parent_id,
+            number: row.number,
+            display_name: row.display_name,
+            content: row.content,
+            latex_formulas: formulas,
+            page_number: row.page_number.map(|p| p as u32),
+            difficulty: row.difficulty.map(|d| d as u8),
+            has_solution: row.has_solution,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+            solution: None,
+            sub_problems: None,
+            continues_from_page: row.continues_from_page.map(|p| p as u32),
+            continues_to_page: row.continues_to_page.map(|p| p as u32),
+            is_cross_page: row.is_cross_page.unwrap_or(false),
+            is_bookmarked: false,
+            confidence: row.confidence.map(|c| c as f32),
+            review_status: row
+                .review_status
+                .as_deref()
+                .and_then(ReviewStatus::from_name)
+                .unwrap_or_default(),
+            review_note: row.review_note,
+            reference_answer: row.reference_answer,
+            version: row.version as u32,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PageRow {
+    id: String,
+    book_id: String,
+    page_number: i64,
+    ocr_text: Option<String>,
+    has_problems: bool,
+    problem_count: i64,
+    page_kind: Option<String>,
+    ocr_provider: Option<String>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+impl From<PageRow> for crate::models::Page {
+    fn from(row: PageRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            page_number: row.page_number as u32,
+            ocr_text: row.ocr_text,
+            has_problems: row.has_problems,
+            problem_count: row.problem_count as u32,
+            page_kind: row.page_kind.as_deref().and_then(crate::models::PageKind::from_name),
+            ocr_provider: row.ocr_provider,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+            updated_at: chrono::DateTime::from_naive_utc_and_offset(row.updated_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct TheoryRow {
+    id: String,
+    chapter_id: String,
+    block_num: i64,
+    title: Option<String>,
+    block_type: String,
+    content: String,
+    latex_formulas: String,
+    page_number: Option<i64>,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<TheoryRow> for TheoryBlock {
+    fn from(row: TheoryRow) -> Self {
+        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
+        let block_type = crate::models::problem::TheoryType::from_name(&row.block_type)
+            .unwrap_or(crate::models::problem::TheoryType::Other);
+
+        Self {
+            id: row.id,
+            chapter_id: row.chapter_id,
+            block_num: row.block_num as u32,
+            title: row.title,
+            block_type,
+            content: row.content,
+            latex_formulas: formulas,
+            page_number: row.page_number.map(|p| p as u32),
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SolutionRow {
+    id: String,
+    problem_id: String,
+    provider: String,
+    content: String,
+    latex_formulas: String,
+    is_verified: bool,
+    rating: Option<i64>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+    generation_params: Option<String>,
+    prompt_hash: Option<String>,
+    #[sqlx(default)]
+    version: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct ProblemSolutionRow {
+    #[sqlx(flatten)]
+    solution: SolutionRow,
+    problem_name: String,
+}
+
+impl From<SolutionRow> for Solution {
+    fn from(row: SolutionRow) -> Self {
+        let formulas: Vec<String> = serde_json::from_str(&row.latex_formulas).unwrap_or_default();
+        
+        Self {
+            id: row.id,
+            problem_id: row.problem_id,
+            provider: row.provider,
+            content: row.content,
+            latex_formulas: formulas,
+            is_verified: row.is_verified,
+            rating: row.rating.map(|r| r as u8),
+            generation: row.generation_params.and_then(|p| serde_json::from_str(&p).ok()),
+            prompt_hash: row.prompt_hash,
+            version: row.version as u32,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+            updated_at: chrono::DateTime::from_naive_utc_and_offset(row.updated_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct BookmarkRow {
+    problem_id: String,
+    folder: Option<String>,
+    note: Option<String>,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<BookmarkRow> for Bookmark {
+    fn from(row: BookmarkRow) -> Self {
+        Self {
+            problem_id: row.problem_id,
+            folder: row.folder,
+            note: row.note,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct WorksheetRow {
+    id: String,
+    title: String,
+    problem_ids: String,
+    seed: i64,
+    include_answer_key: bool,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<WorksheetRow> for Worksheet {
+    fn from(row: WorksheetRow) -> Self {
+        Self {
+            id: row.id,
+            title: row.title,
+            problem_ids: serde_json::from_str(&row.problem_ids).unwrap_or_default(),
+            seed: row.seed as u64,
+            include_answer_key: row.include_answer_key,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct AssignmentRow {
+    id: String,
+    title: String,
+    instructions: Option<String>,
+    share_token: String,
+    due_at: chrono::NaiveDateTime,
+    created_at: chrono::NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+struct SubmissionRow {
+    id: String,
+    assignment_id: String,
+    problem_id: String,
+    student_name: String,
+    source: String,
+    answer: Option<String>,
+    photo_key: Option<String>,
+    verdict: String,
+    late: bool,
+    submitted_at: chrono::NaiveDateTime,
+}
+
+impl From<SubmissionRow> for Submission {
+    fn from(row: SubmissionRow) -> Self {
+        Self {
+            id: row.id,
+            assignment_id: row.assignment_id,
+            problem_id: row.problem_id,
+            student_name: row.student_name,
+            source: SubmissionSource::parse(&row.source),
+            answer: row.answer,
+            photo_key: row.photo_key,
+            verdict: AnswerVerdict::parse(&row.verdict),
+            late: row.late,
+            submitted_at: chrono::DateTime::from_naive_utc_and_offset(row.submitted_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct CalibrationRow {
+    problem_id: String,
+    rating: f64,
+    difficulty: i64,
+    attempts: i64,
+    success_rate: f64,
+    median_seconds: Option<f64>,
+    calibrated_at: chrono::NaiveDateTime,
+}
+
+impl From<CalibrationRow> for Calibration {
+    fn from(row: CalibrationRow) -> Self {
+        Self {
+            problem_id: row.problem_id,
+            rating: row.rating,
+            difficulty: row.difficulty as u8,
+            attempts: row.attempts as usize,
+            success_rate: row.success_rate,
+            median_seconds: row.median_seconds,
+            calibrated_at: chrono::DateTime::from_naive_utc_and_offset(row.calibrated_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct IssueRow {
+    id: String,
+    entity_type: String,
+    entity_id: String,
+    book_id: String,
+    title: String,
+    body: Option<String>,
+    status: String,
+    assignee: Option<String>,
+    created_by: Option<String>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+impl From<IssueRow> for Issue {
+    fn from(row: IssueRow) -> Self {
+        Self {
+            id: row.id,
+            entity_type: IssueEntity::parse(&row.entity_type).unwrap_or(IssueEntity::Book),
+            entity_id: row.entity_id,
+            book_id: row.book_id,
+            title: row.title,
+            body: row.body,
+            status: IssueStatus::parse(&row.status).unwrap_or(IssueStatus::Open),
+            assignee: row.assignee,
+            created_by: row.created_by,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+            updated_at: chrono::DateTime::from_naive_utc_and_offset(row.updated_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DigestReportRow {
+    id: String,
+    period_start: chrono::NaiveDateTime,
+    period_end: chrono::NaiveDateTime,
+    stats: String,
+    markdown: String,
+    html: String,
+    delivered: bool,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl TryFrom<DigestReportRow> for DigestReport {
+    type Error = anyhow::Error;
+
+    fn try_from(row: DigestReportRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            period_start: chrono::DateTime::from_naive_utc_and_offset(row.period_start, chrono::Utc),
+            period_end: chrono::DateTime::from_naive_utc_and_offset(row.period_end, chrono::Utc),
+            stats: serde_json::from_str(&row.stats)?,
+            markdown: row.markdown,
+            html: row.html,
+            delivered: row.delivered,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ExportRunRow {
+    id: String,
+    book_id: String,
+    chapter_id: Option<String>,
+    format: String,
+    filters: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    size: Option<i64>,
+    checksum: Option<String>,
+    storage_key: Option<String>,
+    duration_ms: Option<i64>,
+    error: Option<String>,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<ExportRunRow> for ExportRun {
+    fn from(row: ExportRunRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            chapter_id: row.chapter_id,
+            format: row.format,
+            // Runs recorded before filters were kept have none
+            filters: row.filters.and_then(|f| serde_json::from_str(&f).ok()).unwrap_or_default(),
+            file_name: row.file_name,
+            content_type: row.content_type,
+            size: row.size.map(|s| s as u64),
+            checksum: row.checksum,
+            storage_key: row.storage_key,
+            duration_ms: row.duration_ms.map(|d| d as u64),
+            error: row.error,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ShareLinkRow {
+    id: String,
+    target_type: String,
+    target_id: String,
+    expires_at: chrono::NaiveDateTime,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl TryFrom<ShareLinkRow> for ShareLink {
+    type Error = anyhow::Error;
+
+    fn try_from(row: ShareLinkRow) -> Result<Self> {
+        let target = ShareTarget::from_parts(&row.target_type, row.target_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown share link target {}", row.target_type))?;
+        Ok(Self {
+            id: row.id,
+            target,
+            expires_at: chrono::DateTime::from_naive_utc_and_offset(row.expires_at, chrono::Utc),
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchHitRow {
+    kind: String,
+    id: String,
+    problem_id: Option<String>,
+    chapter_id: String,
+    title: Option<String>,
+    snippet: String,
+    rank: f64,
+}
+
+impl From<SearchHitRow> for SearchHit {
+    fn from(row: SearchHitRow) -> Self {
+        Self {
+            kind: match row.kind.as_str() {
+                "theory" => SearchKind::Theory,
+                "solution" => SearchKind::Solution,
+                _ => SearchKind::Problem,
+            },
+            id: row.id,
+            problem_id: row.problem_id,
+            chapter_id: row.chapter_id,
+            title: row.title,
+            snippet: mark_snippet(&row.snippet),
+            rank: row.rank,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct OcrAuditRow {
+    id: String,
+    book_id: String,
+    page_number: i64,
+    provider: String,
+    similarity: f64,
+    diverged: bool,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<OcrAuditRow> for OcrAuditEntry {
+    fn from(row: OcrAuditRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            page_number: row.page_number as u32,
+            provider: row.provider,
+            similarity: row.similarity,
+            diverged: row.diverged,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ExplanationRow {
+    id: String,
+    book_id: String,
+    page_number: i64,
+    selected_text: String,
+    provider: String,
+    content: String,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<ExplanationRow> for Explanation {
+    fn from(row: ExplanationRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            page_number: row.page_number as u32,
+            selected_text: row.selected_text,
+            provider: row.provider,
+            content: row.content,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct IdempotencyKeyRow {
+    fingerprint: String,
+    status: Option<i64>,
+    content_type: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct JobRecordRow {
+    id: String,
+    job_type: String,
+    params: String,
+    status: String,
+    summary: Option<String>,
+    result: Option<String>,
+    error: Option<String>,
+    created_at: chrono::NaiveDateTime,
+    finished_at: chrono::NaiveDateTime,
+    duration_ms: i64,
+}
+
+impl From<JobRecordRow> for JobRecord {
+    fn from(row: JobRecordRow) -> Self {
+        Self {
+            id: row.id,
+            job_type: row.job_type,
+            params: serde_json::from_str(&row.params).unwrap_or_default(),
+            status: row.status,
+            summary: row.summary,
+            result: row.result.and_then(|r| serde_json::from_str(&r).ok()),
+            error: row.error,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+            finished_at: chrono::DateTime::from_naive_utc_and_offset(row.finished_at, chrono::Utc),
+            duration_ms: row.duration_ms,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct OcrRuleRow {
+    id: String,
+    book_id: String,
+    pattern: String,
+    replacement: String,
+    position: i64,
+    enabled: bool,
+    description: Option<String>,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl From<OcrRuleRow> for OcrRule {
+    fn from(row: OcrRuleRow) -> Self {
+        Self {
+            id: row.id,
+            book_id: row.book_id,
+            pattern: row.pattern,
+            replacement: row.replacement,
+            position: row.position,
+            enabled: row.enabled,
+            description: row.description,
+            created_at: chrono::DateTime::from_naive_utc_and_offset(row.created_at, chrono::Utc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    async fn new_temp_db() -> (Database, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("bookers_test_{}.db", uuid::Uuid::new_v4()));
+        // Ensure the file exists so the URL is always valid.
+        let _ = std::fs::File::create(&path);
+        let url = format!("sqlite:{}", path.to_str().unwrap());
+        let db = Database::new(&url).await.expect("db init");
+        (db, path)
+    }
+
+    async fn seed_book_and_chapter(db: &Database, book_id: &str, chapter_num: u32) -> String {
+        let book = Book {
+            id: book_id.to_string(),
+            title: book_id.to_string(),
+            author: None,
+            subject: None,
+            file_path: format!("resources/{}.pdf", book_id),
+            total_pages: 0,
+            created_at: chrono::Utc::now(),
+        };
+        db.create_book(&book).await.expect("create book");
 
         let chapter_id = format!("{}:{}", book_id, chapter_num);
         let chapter = Chapter {
@@ -1304,268 +5310,1735 @@ mod tests {
             theory_count: 0,
             created_at: chrono::Utc::now(),
         };
-        db.create_chapter(&chapter).await.expect("create chapter");
-        chapter_id
+        db.create_chapter(&chapter).await.expect("create chapter");
+        chapter_id
+    }
+
+    #[tokio::test]
+    async fn sub_problems_can_repeat_letters_across_different_parents() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+
+        let p1_id = Problem::generate_id("algebra-7", 1, "71");
+        let p2_id = Problem::generate_id("algebra-7", 1, "72");
+
+        let now = chrono::Utc::now();
+        let problems = vec![
+            Problem {
+                id: p1_id.clone(),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: None,
+                number: "71".to_string(),
+                display_name: "Задача 71".to_string(),
+                content: "71. Foo".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: p2_id.clone(),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: None,
+                number: "72".to_string(),
+                display_name: "Задача 72".to_string(),
+                content: "72. Bar".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: format!("{}:a", p1_id),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: Some(p1_id.clone()),
+                number: "a".to_string(),
+                display_name: "a)".to_string(),
+                content: "a) sub 1".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: format!("{}:a", p2_id),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: Some(p2_id.clone()),
+                number: "a".to_string(),
+                display_name: "a)".to_string(),
+                content: "a) sub 2".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+        ];
+
+        db.create_or_update_problems(&problems)
+            .await
+            .expect("insert problems");
+
+        // Chapter listing should return only parent problems.
+        let parents = db.get_problems_by_chapter(&chapter_id).await.expect("list");
+        assert_eq!(parents.len(), 2);
+
+        let subs1 = db.get_sub_problems(&p1_id).await.expect("subs1");
+        let subs2 = db.get_sub_problems(&p2_id).await.expect("subs2");
+        assert_eq!(subs1.len(), 1);
+        assert_eq!(subs2.len(), 1);
+        assert_eq!(subs1[0].number, "a");
+        assert_eq!(subs2[0].number, "a");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn migrates_legacy_unique_constraint_and_allows_sub_problems() {
+        let path = std::env::temp_dir().join(format!("bookers_test_legacy_{}.db", uuid::Uuid::new_v4()));
+        let _ = std::fs::File::create(&path);
+        let url = format!("sqlite:{}", path.to_str().unwrap());
+
+        // Create a legacy `problems` table with table-level UNIQUE(chapter_id, number).
+        let mut conn = sqlx::SqliteConnection::connect(&url).await.expect("connect");
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS problems (
+                id TEXT PRIMARY KEY,
+                chapter_id TEXT NOT NULL,
+                page_id TEXT,
+                parent_id TEXT,
+                number TEXT NOT NULL,
+                display_name TEXT NOT NULL,
+                content TEXT NOT NULL,
+                latex_formulas TEXT,
+                page_number INTEGER,
+                difficulty INTEGER,
+                has_solution BOOLEAN DEFAULT FALSE,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (chapter_id) REFERENCES chapters(id) ON DELETE CASCADE,
+                FOREIGN KEY (page_id) REFERENCES pages(id) ON DELETE SET NULL,
+                FOREIGN KEY (parent_id) REFERENCES problems(id) ON DELETE CASCADE,
+                UNIQUE(chapter_id, number)
+            );
+            "#,
+        )
+        .execute(&mut conn)
+        .await
+        .expect("create legacy problems");
+        drop(conn);
+
+        // Init with current code: should rebuild the table and remove the legacy UNIQUE constraint.
+        let db = Database::new(&url).await.expect("init db");
+
+        let sql: String = sqlx::query_scalar(
+            "SELECT sql FROM sqlite_master WHERE type='table' AND name='problems'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .expect("read schema");
+        assert!(
+            !sql.to_lowercase().contains("unique(chapter_id, number)"),
+            "legacy UNIQUE constraint should be removed"
+        );
+
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+
+        // Now ensure repeated letters across parents are accepted.
+        let p1_id = Problem::generate_id("algebra-7", 1, "71");
+        let p2_id = Problem::generate_id("algebra-7", 1, "72");
+
+        let now = chrono::Utc::now();
+        let problems = vec![
+            Problem {
+                id: p1_id.clone(),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: None,
+                number: "71".to_string(),
+                display_name: "Задача 71".to_string(),
+                content: "71. Foo".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: p2_id.clone(),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: None,
+                number: "72".to_string(),
+                display_name: "Задача 72".to_string(),
+                content: "72. Bar".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: format!("{}:a", p1_id),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: Some(p1_id.clone()),
+                number: "a".to_string(),
+                display_name: "a)".to_string(),
+                content: "a) sub 1".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+            Problem {
+                id: format!("{}:a", p2_id),
+                chapter_id: chapter_id.clone(),
+                page_id: None,
+                parent_id: Some(p2_id.clone()),
+                number: "a".to_string(),
+                display_name: "a)".to_string(),
+                content: "a) sub 2".to_string(),
+                latex_formulas: vec![],
+                page_number: Some(1),
+                difficulty: None,
+                has_solution: false,
+                created_at: now,
+                solution: None,
+                sub_problems: None,
+                continues_from_page: None,
+                continues_to_page: None,
+                is_cross_page: false,
+            is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
+            },
+        ];
+
+        db.create_or_update_problems(&problems)
+            .await
+            .expect("insert problems after migration");
+
+        let subs1 = db.get_sub_problems(&p1_id).await.expect("subs1");
+        let subs2 = db.get_sub_problems(&p2_id).await.expect("subs2");
+        assert_eq!(subs1.len(), 1);
+        assert_eq!(subs2.len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn bookmarks_keep_folder_and_note() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "5"),
+            chapter_id,
+            number: "5".to_string(),
+            display_name: "Задача 5".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.expect("create problem");
+
+        db.upsert_bookmark(&problem.id, Some("exam prep"), None).await.expect("bookmark");
+        db.upsert_bookmark(&problem.id, Some("exam prep"), Some("tricky")).await.expect("update bookmark");
+
+        let bookmarks = db.get_bookmarks().await.expect("bookmarks");
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(bookmarks[0].folder.as_deref(), Some("exam prep"));
+        assert_eq!(bookmarks[0].note.as_deref(), Some("tricky"));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn page_parse_is_reused_only_for_the_same_fingerprint() {
+        let (db, path) = new_temp_db().await;
+        let page = db.get_or_create_page("algebra-7", 3).await.expect("page");
+        assert_eq!(db.get_page_parse("algebra-7", 3, "abc").await.unwrap(), None);
+
+        db.set_page_parse(&page.id, "abc", r#"{"problems":[]}"#).await.expect("store parse");
+        assert_eq!(db.get_page_parse("algebra-7", 3, "abc").await.unwrap().as_deref(), Some(r#"{"problems":[]}"#));
+        assert_eq!(db.get_page_parse("algebra-7", 3, "def").await.unwrap(), None);
+        assert_eq!(db.get_page_parse("algebra-7", 4, "abc").await.unwrap(), None);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn total_pages_survive_new_pages() {
+        let (db, path) = new_temp_db().await;
+        seed_book_and_chapter(&db, "algebra-7", 1).await;
+        db.set_book_total_pages("algebra-7", 212).await.expect("set pages");
+        db.get_or_create_page("algebra-7", 3).await.expect("page");
+
+        let book = db.get_book("algebra-7").await.unwrap().unwrap();
+        assert_eq!(book.total_pages, 212);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn rename_book_moves_derived_ids() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "Алгебра 7", 1).await;
+        let page = db.get_or_create_page("Алгебра 7", 3).await.expect("page");
+
+        let problem = Problem {
+            id: Problem::generate_id("Алгебра 7", 1, "5"),
+            chapter_id,
+            page_id: Some(page.id),
+            number: "5".to_string(),
+            display_name: "Задача 5".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.expect("create problem");
+        db.upsert_bookmark(&problem.id, None, None).await.expect("bookmark");
+
+        db.rename_book("Алгебра 7", "algebra-7-1a2b3c4d").await.expect("rename");
+
+        assert!(db.get_book("Алгебра 7").await.unwrap().is_none());
+        assert!(db.get_book("algebra-7-1a2b3c4d").await.unwrap().is_some());
+        let chapters = db.get_chapters_by_book("algebra-7-1a2b3c4d").await.unwrap();
+        assert_eq!(chapters[0].id, "algebra-7-1a2b3c4d:1");
+        let moved = db.get_problem(&Problem::generate_id("algebra-7-1a2b3c4d", 1, "5")).await.unwrap().unwrap();
+        assert_eq!(moved.page_id.as_deref(), Some("algebra-7-1a2b3c4d:page:3"));
+        assert_eq!(db.get_bookmarks().await.unwrap()[0].problem_id, moved.id);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn formula_attempts_accumulate_and_reset() {
+        let (db, path) = new_temp_db().await;
+        let id = "algebra-7:1:5";
+
+        for expected in 1..=3 {
+            assert_eq!(db.record_formula_attempt(id, true).await.unwrap(), expected);
+        }
+        assert_eq!(db.get_formula_attempts(id).await.unwrap(), 3);
+        assert_eq!(db.record_formula_attempt(id, false).await.unwrap(), 0);
+        assert_eq!(db.get_formula_attempts(id).await.unwrap(), 0);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn review_queue_walks_unreviewed_problems() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+
+        for (number, page) in [("2", 2), ("1", 1)] {
+            let problem = Problem {
+                id: Problem::generate_id("algebra-7", 1, number),
+                chapter_id: chapter_id.clone(),
+                number: number.to_string(),
+                display_name: format!("Задача {}", number),
+                content: format!("{}. Решите уравнение", number),
+                page_number: Some(page),
+                created_at: chrono::Utc::now(),
+                ..Default::default()
+            };
+            db.create_problem(&problem).await.expect("create problem");
+        }
+
+        let first = db.get_next_unreviewed_problem("algebra-7").await.unwrap().unwrap();
+        assert_eq!(first.number, "1");
+        assert!(db.set_problem_review(&first.id, ReviewStatus::Approved, None).await.unwrap());
+
+        let second = db.get_next_unreviewed_problem("algebra-7").await.unwrap().unwrap();
+        assert_eq!(second.number, "2");
+        db.set_problem_review(&second.id, ReviewStatus::NeedsFix, Some("missing formula")).await.unwrap();
+        assert!(db.get_next_unreviewed_problem("algebra-7").await.unwrap().is_none());
+
+        let fixed = db.get_problem(&second.id).await.unwrap().unwrap();
+        assert_eq!(fixed.review_status, ReviewStatus::NeedsFix);
+        assert_eq!(fixed.review_note.as_deref(), Some("missing formula"));
+
+        // Re-parsing with different content puts the problem back in the queue
+        let mut reparsed = first.clone();
+        reparsed.content = "1. Решите неравенство".to_string();
+        db.create_problem(&reparsed).await.unwrap();
+        let progress = db.get_review_progress("algebra-7").await.unwrap();
+        assert_eq!((progress.unreviewed, progress.approved, progress.needs_fix), (1, 0, 1));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn problem_density_counts_top_level_problems_per_page() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+
+        for (number, page, parent) in [("1", 3, None), ("2", 3, None), ("2a", 3, Some("algebra-7:1:2")), ("3", 5, None)] {
+            let problem = Problem {
+                id: Problem::generate_id("algebra-7", 1, number),
+                chapter_id: chapter_id.clone(),
+                parent_id: parent.map(str::to_string),
+                number: number.to_string(),
+                display_name: format!("Задача {}", number),
+                content: format!("{}. Вычислите", number),
+                page_number: Some(page),
+                created_at: chrono::Utc::now(),
+                ..Default::default()
+            };
+            db.create_problem(&problem).await.expect("create problem");
+        }
+
+        assert_eq!(db.get_problem_density("algebra-7").await.unwrap(), vec![(3, 2), (5, 1)]);
+        assert!(db.get_problem_density("geometry-8").await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn theory_blocks_are_edited_and_deleted() {
+        use crate::models::problem::TheoryType;
+
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        assert_eq!(db.next_theory_block_num(&chapter_id).await.unwrap(), 1);
+
+        let mut block = TheoryBlock {
+            id: TheoryBlock::generate_id("algebra-7", 1, 1),
+            chapter_id: chapter_id.clone(),
+            block_num: 1,
+            title: None,
+            block_type: TheoryType::Explanation,
+            content: "Корнем уравнения называется...".to_string(),
+            latex_formulas: vec![],
+            page_number: Some(3),
+            created_at: chrono::Utc::now(),
+        };
+        db.create_theory_block(&block).await.unwrap();
+        assert_eq!(db.next_theory_block_num(&chapter_id).await.unwrap(), 2);
+
+        block.title = Some("Теорема Виета".to_string());
+        block.block_type = TheoryType::Theorem;
+        block.content = "$x_1 + x_2 = -p$".to_string();
+        assert!(db.update_theory_block(&block).await.unwrap());
+
+        let stored = db.get_theory_block(&block.id).await.unwrap().unwrap();
+        assert_eq!(stored.title.as_deref(), Some("Теорема Виета"));
+        assert_eq!(stored.block_type.as_str(), "theorem");
+        assert_eq!(stored.content, block.content);
+
+        assert!(db.delete_theory_block(&block.id).await.unwrap());
+        assert!(!db.delete_theory_block(&block.id).await.unwrap());
+        assert!(db.get_theory_blocks_by_chapter(&chapter_id).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn solutions_filter_by_provider_and_verification() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        for (provider, verified) in [("claude", false), ("openai", true)] {
+            db.save_solution(&Solution {
+                id: Solution::generate_id(&problem.id),
+                problem_id: problem.id.clone(),
+                provider: provider.to_string(),
+                content: format!("{} solution", provider),
+                latex_formulas: vec![],
+                is_verified: verified,
+                rating: None,
+                generation: None,
+                prompt_hash: None,
+                version: 1,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            }).await.unwrap();
+        }
+
+        let claude = SolutionFilter::Provider("claude".to_string());
+        let ids = [problem.id.clone()];
+        let best = db.get_filtered_solutions(&ids, &claude).await.unwrap().remove(&problem.id).unwrap();
+        assert_eq!(best.provider, "claude");
+        let verified = db.get_filtered_solutions(&ids, &SolutionFilter::Verified).await.unwrap().remove(&problem.id).unwrap();
+        assert_eq!(verified.provider, "openai");
+
+        assert_eq!(db.get_book_solutions("algebra-7", &SolutionFilter::Any).await.unwrap().len(), 2);
+        assert_eq!(db.get_book_solutions("algebra-7", &claude).await.unwrap().len(), 1);
+
+        // Recorded verdicts last until the content changes
+        db.record_answer_verdict(&best.id, AnswerVerdict::Mismatch).await.unwrap();
+        let verdicts = db.get_answer_verdicts("algebra-7").await.unwrap();
+        assert_eq!(verdicts.get(&best.id), Some(&AnswerVerdict::Mismatch));
+        assert_eq!(verdicts.len(), 1);
+        db.update_solution_content(&problem.id, "claude", "fixed", &[], None, best.version).await.unwrap().unwrap();
+        assert!(db.get_answer_verdicts("algebra-7").await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn migration_merges_problems_with_unnormalized_ids() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = |number: &str, parent: Option<&str>| Problem {
+            id: match parent {
+                Some(parent) => format!("{}:{}", parent, number),
+                None => format!("{}:{}", chapter_id, number),
+            },
+            chapter_id: chapter_id.clone(),
+            parent_id: parent.map(String::from),
+            number: number.to_string(),
+            display_name: format!("Задача {}", number),
+            content: format!("Задача {}", number),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        // "71." duplicates "71"; "5,66" has no normalized twin and is only renamed
+        for p in [
+            problem("71", None),
+            problem("71.", None),
+            problem("а", Some("algebra-7:1:71.")),
+            problem("5,66", None),
+            problem("б", Some("algebra-7:1:5,66")),
+        ] {
+            db.create_problem(&p).await.unwrap();
+        }
+        db.create_or_update_solution(&Solution {
+            id: Solution::generate_id(&"algebra-7:1:71.".to_string()),
+            problem_id: "algebra-7:1:71.".to_string(),
+            provider: "claude".to_string(),
+            content: "x = 2".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }).await.unwrap();
+        db.add_bookmark("algebra-7:1:5,66:б").await.unwrap();
+
+        db.merge_unnormalized_problem_ids().await.unwrap();
+
+        let numbers: Vec<String> = db.get_problems_by_chapter(&chapter_id).await.unwrap()
+            .into_iter().map(|p| p.id).collect();
+        assert_eq!(numbers, vec!["algebra-7:1:5.66", "algebra-7:1:71"]);
+        let merged = db.get_problem("algebra-7:1:71").await.unwrap().unwrap();
+        assert!(merged.has_solution);
+        assert_eq!(db.get_solutions_by_problem("algebra-7:1:71").await.unwrap().len(), 1);
+        assert!(db.get_problem("algebra-7:1:71:а").await.unwrap().is_some());
+        assert!(db.is_bookmarked("algebra-7:1:5.66:б").await.unwrap());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn cross_page_problem_is_one_row_with_parts_per_page() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let page_12 = db.get_or_create_page("algebra-7", 12).await.unwrap();
+        let page_13 = db.get_or_create_page("algebra-7", 13).await.unwrap();
+        let part = |content: &str, from: Option<u32>, to: Option<u32>| Problem {
+            id: Problem::generate_id("algebra-7", 1, "71"),
+            chapter_id: chapter_id.clone(),
+            number: "71".to_string(),
+            display_name: "Задача 71".to_string(),
+            content: content.to_string(),
+            continues_from_page: from,
+            continues_to_page: to,
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+
+        db.save_page_problems(&page_12.id, 12, &[part("71. Решите уравнение", None, Some(13))]).await.unwrap();
+        db.save_page_problems(&page_13.id, 13, &[part("$x^2 - 5x + 6 = 0$.", Some(12), None)]).await.unwrap();
+
+        let problem = db.get_problem("algebra-7:1:71").await.unwrap().unwrap();
+        assert_eq!(problem.content, "71. Решите уравнение\n$x^2 - 5x + 6 = 0$.");
+        assert_eq!(problem.page_number, Some(12));
+        assert!(problem.is_cross_page);
+        assert_eq!((problem.continues_from_page, problem.continues_to_page), (None, None));
+        assert_eq!(problem.latex_formulas, vec!["x^2 - 5x + 6 = 0"]);
+        let pages: Vec<u32> = db.get_problem_pages(&problem.id).await.unwrap().iter().map(|p| p.page_number).collect();
+        assert_eq!(pages, vec![12, 13]);
+        assert_eq!(db.get_problems_by_page(&page_13.id).await.unwrap().len(), 1);
+
+        // Re-OCR of the second page drops only its part
+        db.delete_problems_by_page(&page_13.id).await.unwrap();
+        let problem = db.get_problem("algebra-7:1:71").await.unwrap().unwrap();
+        assert_eq!(problem.content, "71. Решите уравнение");
+        assert_eq!(problem.continues_to_page, Some(13));
+
+        let _ = std::fs::remove_file(path);
     }
 
     #[tokio::test]
-    async fn sub_problems_can_repeat_letters_across_different_parents() {
+    async fn solutions_found_by_prompt_hash() {
         let (db, path) = new_temp_db().await;
         let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        for (provider, prompt_hash) in [("claude", Some("abc123")), ("manual", None)] {
+            db.create_or_update_solution(&Solution {
+                id: Solution::generate_id(&problem.id),
+                problem_id: problem.id.clone(),
+                provider: provider.to_string(),
+                content: format!("{} solution", provider),
+                latex_formulas: vec![],
+                is_verified: false,
+                rating: None,
+                generation: None,
+                prompt_hash: prompt_hash.map(String::from),
+                version: 1,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            }).await.unwrap();
+        }
 
-        let p1_id = Problem::generate_id("algebra-7", 1, "71");
-        let p2_id = Problem::generate_id("algebra-7", 1, "72");
+        let cached = db.get_solution_by_prompt_hash("abc123").await.unwrap().unwrap();
+        assert_eq!((cached.provider.as_str(), cached.prompt_hash.as_deref()), ("claude", Some("abc123")));
+        assert!(db.get_solution_by_prompt_hash("def456").await.unwrap().is_none());
+        assert_eq!(db.count_cached_solutions().await.unwrap(), 1);
 
-        let now = chrono::Utc::now();
-        let problems = vec![
-            Problem {
-                id: p1_id.clone(),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: None,
-                number: "71".to_string(),
-                display_name: "Задача 71".to_string(),
-                content: "71. Foo".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn batched_problem_writes_report_outcomes_and_conflicts() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = |id: String, number: &str, parent_id: Option<String>, content: &str| Problem {
+            id,
+            chapter_id: chapter_id.clone(),
+            parent_id,
+            number: number.to_string(),
+            display_name: format!("Задача {}", number),
+            content: content.to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem("legacy-5".to_string(), "5", None, "Старая задача 5")).await.unwrap();
+
+        let main_id = |n: u32| Problem::generate_id("algebra-7", 1, &n.to_string());
+        let mut batch: Vec<Problem> =
+            (1..=300).map(|n| problem(main_id(n), &n.to_string(), None, &format!("Задача {}", n))).collect();
+        batch.push(problem(format!("{}а", main_id(1)), "а", Some(main_id(1)), "Пункт а"));
+        batch.push(problem(format!("{}а", main_id(5)), "а", Some(main_id(5)), "Пункт а"));
+
+        let writes = db.create_or_update_problems(&batch).await.unwrap();
+        assert_eq!(writes.len(), batch.len());
+        assert!(writes.iter().zip(&batch).all(|(w, p)| w.id == p.id));
+        assert_eq!(writes[0].outcome, ProblemWriteOutcome::Inserted);
+        assert_eq!((writes[4].outcome, writes[4].existing_id.as_deref()), (ProblemWriteOutcome::Conflict, Some("legacy-5")));
+        assert_eq!((writes[300].outcome, writes[301].outcome), (ProblemWriteOutcome::Inserted, ProblemWriteOutcome::Conflict));
+        assert_eq!(writes.iter().filter(|w| w.is_written()).count(), 300);
+        assert_eq!(db.get_problems_by_chapter(&chapter_id).await.unwrap().len(), 300);
+
+        batch[1].content = "Задача 2, исправленная".to_string();
+        let writes = db.create_or_update_problems(&batch[..3]).await.unwrap();
+        let outcomes: Vec<_> = writes.iter().map(|w| w.outcome).collect();
+        assert_eq!(outcomes, [ProblemWriteOutcome::Unchanged, ProblemWriteOutcome::Updated, ProblemWriteOutcome::Unchanged]);
+        assert_eq!(db.get_problem(&main_id(2)).await.unwrap().unwrap().content, "Задача 2, исправленная");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn chapter_full_loads_subs_and_best_solution() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = |number: &str, parent_id: Option<String>| Problem {
+            id: match &parent_id {
+                Some(parent) => format!("{}{}", parent, number),
+                None => Problem::generate_id("algebra-7", 1, number),
             },
-            Problem {
-                id: p2_id.clone(),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: None,
-                number: "72".to_string(),
-                display_name: "Задача 72".to_string(),
-                content: "72. Bar".to_string(),
+            chapter_id: chapter_id.clone(),
+            parent_id,
+            number: number.to_string(),
+            display_name: format!("Задача {}", number),
+            content: format!("Условие {}", number),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let first = problem("1", None);
+        let batch = vec![
+            first.clone(),
+            problem("2", None),
+            problem("а", Some(first.id.clone())),
+            problem("б", Some(first.id.clone())),
+        ];
+        db.create_or_update_problems(&batch).await.unwrap();
+
+        for (provider, verified) in [("claude", true), ("openai", false)] {
+            let solution = Solution {
+                id: format!("{}:{}", first.id, provider),
+                problem_id: first.id.clone(),
+                provider: provider.to_string(),
+                content: format!("{} solution", provider),
                 latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-            Problem {
-                id: format!("{}:a", p1_id),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: Some(p1_id.clone()),
-                number: "a".to_string(),
-                display_name: "a)".to_string(),
-                content: "a) sub 1".to_string(),
+                is_verified: verified,
+                rating: None,
+                generation: None,
+                prompt_hash: None,
+                version: 1,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            };
+            db.create_or_update_solution(&solution).await.unwrap();
+        }
+
+        let full = db.get_chapter_full(&chapter_id).await.unwrap();
+        assert_eq!(full.len(), 2);
+        let subs: Vec<_> = full[0].problem.sub_problems.as_ref().unwrap().iter().map(|s| s.number.as_str()).collect();
+        assert_eq!(subs, ["а", "б"]);
+        assert_eq!(full[0].best_solution.as_ref().unwrap().provider, "claude");
+        assert!(full[1].problem.sub_problems.is_none() && full[1].best_solution.is_none());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn solution_cleanup_archives_duplicates_and_renames_providers() {
+        use crate::models::problem::GenerationParams;
+        use crate::services::solution_cleanup::{plan_cleanup, DEFAULT_SIMILARITY_THRESHOLD};
+
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        for (provider, rating) in [("claude", Some(2)), ("Claude-3", Some(5)), ("openai", None)] {
+            db.save_solution(&Solution {
+                id: Solution::generate_id(&problem.id),
+                problem_id: problem.id.clone(),
+                provider: provider.to_string(),
+                content: format!("Ответ от {}: x = {}", provider, provider.len()),
                 latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
+                is_verified: false,
+                rating,
+                generation: Some(GenerationParams {
+                    model: "claude-opus-4-20250514".to_string(),
+                    temperature: 0.2,
+                    max_tokens: 8000,
+                }),
+                prompt_hash: None,
+                version: 1,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            }).await.unwrap();
+        }
+
+        let solutions = db.get_solutions_for_cleanup(Some("algebra-7")).await.unwrap();
+        assert_eq!(solutions.len(), 3);
+        assert!(db.get_solutions_for_cleanup(Some("geometry-8")).await.unwrap().is_empty());
+
+        let plan = plan_cleanup(&solutions, DEFAULT_SIMILARITY_THRESHOLD);
+        assert_eq!(db.apply_solution_cleanup(&plan).await.unwrap(), (1, 1));
+
+        let mut providers: Vec<String> = db.get_solutions_by_problem(&problem.id).await.unwrap()
+            .into_iter().map(|s| s.provider).collect();
+        providers.sort();
+        assert_eq!(providers, vec!["claude", "openai"]);
+        let best = db.get_solution(&problem.id, "claude").await.unwrap().unwrap();
+        assert_eq!(best.rating, Some(5));
+        assert_eq!(best.generation.map(|g| g.max_tokens), Some(8000));
+
+        let archived: (String, String) = sqlx::query_as("SELECT provider, reason FROM archived_solutions")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(archived, ("claude".to_string(), "same_provider".to_string()));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn job_history_filters_and_prunes() {
+        let (db, path) = new_temp_db().await;
+        let now = chrono::Utc::now();
+
+        for (id, job_type, status, age_days) in [
+            ("a", "batch_ocr", "completed", 0),
+            ("b", "batch_solve", "failed", 0),
+            ("c", "batch_ocr", "completed", 40),
+        ] {
+            let finished_at = now - chrono::Duration::days(age_days);
+            db.save_job_record(&JobRecord {
+                id: id.to_string(),
+                job_type: job_type.to_string(),
+                params: serde_json::json!({"book_id": "algebra-7"}),
+                status: status.to_string(),
+                summary: None,
+                result: Some(serde_json::json!({"pages": 3})),
+                error: None,
+                created_at: finished_at - chrono::Duration::seconds(5),
+                finished_at,
+                duration_ms: 5000,
+            }).await.unwrap();
+        }
+
+        let ocr = db.get_job_history(Some("batch_ocr"), None, 10).await.unwrap();
+        assert_eq!(ocr.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(ocr[0].result, Some(serde_json::json!({"pages": 3})));
+        assert_eq!(db.get_job_history(None, Some("failed"), 10).await.unwrap().len(), 1);
+
+        assert_eq!(db.prune_job_history(30).await.unwrap(), 1);
+        assert_eq!(db.get_job_history(None, None, 10).await.unwrap().len(), 2);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_replay_and_detect_reuse() {
+        let (db, path) = new_temp_db().await;
+
+        assert_eq!(db.claim_idempotency_key("k1", "a", 24).await.unwrap(), IdempotencyClaim::New);
+        assert_eq!(db.claim_idempotency_key("k1", "a", 24).await.unwrap(), IdempotencyClaim::InProgress);
+        let response = StoredResponse {
+            status: 202,
+            content_type: Some("application/json".to_string()),
+            body: b"{\"job_id\":\"j1\"}".to_vec(),
+        };
+        db.complete_idempotency_key("k1", &response).await.unwrap();
+        assert_eq!(db.claim_idempotency_key("k1", "a", 24).await.unwrap(), IdempotencyClaim::Completed(response));
+        assert_eq!(db.claim_idempotency_key("k1", "b", 24).await.unwrap(), IdempotencyClaim::Mismatch);
+
+        db.release_idempotency_key("k1").await.unwrap();
+        assert_eq!(db.claim_idempotency_key("k1", "b", 24).await.unwrap(), IdempotencyClaim::New);
+
+        // Expired keys are claimed anew and pruned
+        sqlx::query("UPDATE idempotency_keys SET created_at = datetime('now', '-2 days')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.claim_idempotency_key("k1", "c", 24).await.unwrap(), IdempotencyClaim::New);
+        sqlx::query("UPDATE idempotency_keys SET created_at = datetime('now', '-2 days')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.prune_idempotency_keys(24).await.unwrap(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn book_job_history_matches_book_and_its_problems() {
+        let (db, path) = new_temp_db().await;
+        let now = chrono::Utc::now();
+
+        for (id, params) in [
+            ("ocr", serde_json::json!({"book_id": "algebra-7", "page_range": [1, 5]})),
+            ("solve", serde_json::json!({"problem_ids": ["algebra-7:1:3"], "provider": "claude"})),
+            ("other", serde_json::json!({"book_id": "algebra-70"})),
+            ("other-solve", serde_json::json!({"problem_ids": ["algebra-70:1:3"], "provider": "claude"})),
+        ] {
+            db.save_job_record(&JobRecord {
+                id: id.to_string(),
+                job_type: "batch_ocr".to_string(),
+                params,
+                status: "completed".to_string(),
+                summary: None,
+                result: None,
+                error: None,
                 created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-            Problem {
-                id: format!("{}:a", p2_id),
+                finished_at: now,
+                duration_ms: 0,
+            }).await.unwrap();
+        }
+
+        let mut ids: Vec<String> = db.get_book_job_history("algebra-7", 10).await.unwrap()
+            .into_iter().map(|r| r.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["ocr", "solve"]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn versioned_updates_refuse_stale_versions() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let mut problem = Problem {
+            id: format!("{}:1", chapter_id),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "x + 1 = 2".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+        assert_eq!(db.get_problem(&problem.id).await.unwrap().unwrap().version, 1);
+
+        // Two tabs start from version 1; the second save is refused
+        assert_eq!(db.update_problem_content(&problem.id, "x + 2 = 3", vec![], Some(1)).await.unwrap(), Some(2));
+        assert_eq!(db.update_problem_content(&problem.id, "x + 3 = 4", vec![], Some(1)).await.unwrap(), None);
+        assert_eq!(db.get_problem(&problem.id).await.unwrap().unwrap().content, "x + 2 = 3");
+
+        // Re-extraction bumps the version only when the text changes
+        problem.content = "x + 2 = 3".to_string();
+        db.create_problem(&problem).await.unwrap();
+        assert_eq!(db.get_problem(&problem.id).await.unwrap().unwrap().version, 2);
+        problem.content = "x + 5 = 6".to_string();
+        db.create_problem(&problem).await.unwrap();
+        assert_eq!(db.get_problem(&problem.id).await.unwrap().unwrap().version, 3);
+
+        let solution = Solution {
+            id: Solution::generate_id(&problem.id),
+            problem_id: problem.id.clone(),
+            provider: "manual".to_string(),
+            content: "x = 1".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        db.create_or_update_solution(&solution).await.unwrap();
+        let update = |content: &'static str, version| {
+            db.update_solution_content(&problem.id, "manual", content, &[], Some(true), version)
+        };
+        assert_eq!(update("x = 2", 1).await.unwrap(), Some(2));
+        assert_eq!(update("x = 3", 1).await.unwrap(), None);
+        let stored = db.get_solution(&problem.id, "manual").await.unwrap().unwrap();
+        assert_eq!((stored.content.as_str(), stored.version, stored.is_verified), ("x = 2", 2, true));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn transactions_roll_back_on_error() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = Problem {
+            id: format!("{}:1", chapter_id),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "x + 1 = 2".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+
+        // A failure after the page and its problems were written undoes both
+        let failed: Result<()> = db.transaction(async |tx| {
+            let page = tx.get_or_create_page("algebra-7", 3).await?;
+            tx.save_page_problems(&page.id, 3, std::slice::from_ref(&problem)).await?;
+            anyhow::bail!("parser crashed")
+        }).await;
+        assert!(failed.is_err());
+        assert!(db.get_problem(&problem.id).await.unwrap().is_none());
+        assert!(db.get_page("algebra-7", 3).await.unwrap().is_none());
+
+        let page_id = db.transaction(async |tx| {
+            let page = tx.get_or_create_page("algebra-7", 3).await?;
+            tx.save_page_problems(&page.id, 3, std::slice::from_ref(&problem)).await?;
+            Ok(page.id)
+        }).await.unwrap();
+        assert_eq!(db.get_problem(&problem.id).await.unwrap().unwrap().page_id.as_deref(), Some(page_id.as_str()));
+        assert_eq!(db.get_problem_pages(&problem.id).await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn chapter_problems_sort_by_number_value() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problems: Vec<Problem> = ["100", "21", "5.12", "5.2"]
+            .into_iter()
+            .map(|number| Problem {
+                id: format!("{}:{}", chapter_id, number),
                 chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: Some(p2_id.clone()),
-                number: "a".to_string(),
-                display_name: "a)".to_string(),
-                content: "a) sub 2".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
+                number: number.to_string(),
+                display_name: format!("Задача {}", number),
+                content: format!("Задача {}", number),
+                created_at: chrono::Utc::now(),
+                ..Default::default()
+            })
+            .collect();
+        db.create_or_update_problems(&problems).await.unwrap();
+
+        let numbers: Vec<String> = db.get_problems_by_chapter(&chapter_id).await.unwrap().into_iter().map(|p| p.number).collect();
+        assert_eq!(numbers, vec!["5.2", "5.12", "21", "100"]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn sub_problems_sort_by_alphabet_position() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let parent_id = format!("{}:1", chapter_id);
+        let problem = |number: &str, parent: Option<&str>| Problem {
+            id: parent.map_or(parent_id.clone(), |parent| format!("{}:{}", parent, number)),
+            chapter_id: chapter_id.clone(),
+            parent_id: parent.map(String::from),
+            number: number.to_string(),
+            display_name: number.to_string(),
+            content: format!("Задача {}", number),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let mut problems = vec![problem("1", None)];
+        problems.extend(["ж", "ё", "е", "а"].map(|letter| problem(letter, Some(&parent_id))));
+        db.create_or_update_problems(&problems).await.unwrap();
+
+        let letters: Vec<String> = db.get_sub_problems(&parent_id).await.unwrap().into_iter().map(|p| p.number).collect();
+        assert_eq!(letters, vec!["а", "е", "ё", "ж"]);
+        let tree = db.get_problem_tree_by_chapter(&chapter_id).await.unwrap();
+        let letters: Vec<&str> = tree[0].sub_problems.as_ref().unwrap().iter().map(|p| p.number.as_str()).collect();
+        assert_eq!(letters, vec!["а", "е", "ё", "ж"]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn bulk_deletes_cascade_and_dry_runs_roll_back() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = |number: &str, parent: Option<&str>, page_number: u32| Problem {
+            id: match parent {
+                Some(parent) => format!("{}:{}", parent, number),
+                None => format!("{}:{}", chapter_id, number),
             },
-        ];
+            chapter_id: chapter_id.clone(),
+            parent_id: parent.map(String::from),
+            number: number.to_string(),
+            display_name: number.to_string(),
+            content: format!("Задача {}", number),
+            page_number: Some(page_number),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let parent_id = format!("{}:1", chapter_id);
+        db.create_or_update_problems(&[
+            problem("1", None, 30),
+            problem("а", Some(&parent_id), 30),
+            problem("2", None, 31),
+            problem("3", None, 70),
+        ]).await.unwrap();
+        db.create_or_update_solution(&Solution {
+            id: Solution::generate_id(&parent_id),
+            problem_id: parent_id.clone(),
+            provider: "claude".to_string(),
+            content: "x = 1".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }).await.unwrap();
+
+        let expected = ProblemDeletion { problems: 2, sub_problems: 1, solutions: 1 };
+        let pages: Vec<u32> = (30..=60).collect();
+        assert_eq!(db.delete_book_problems_on_pages("algebra-7", &pages, true).await.unwrap(), expected);
+        assert!(db.get_problem(&parent_id).await.unwrap().is_some());
+
+        assert_eq!(db.delete_book_problems_on_pages("algebra-7", &pages, false).await.unwrap(), expected);
+        assert!(db.get_problem(&parent_id).await.unwrap().is_none());
+        assert!(db.get_solutions_by_problem(&parent_id).await.unwrap().is_empty());
+
+        let rest = ProblemDeletion { problems: 1, ..Default::default() };
+        assert_eq!(db.delete_chapter_problems(&chapter_id, false).await.unwrap(), Some(rest));
+        assert!(db.get_problems_by_chapter(&chapter_id).await.unwrap().is_empty());
+        assert_eq!(db.delete_chapter_problems("algebra-7:9", true).await.unwrap(), None);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn imported_pages_keep_local_text_the_bundle_lacks() {
+        let (db, path) = new_temp_db().await;
+        seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let local = db.get_or_create_page("algebra-7", 2).await.unwrap();
+        db.update_page_ocr(&local.id, "read here", 1).await.unwrap();
+
+        let page = |number: u32, text: Option<&str>| crate::models::Page {
+            id: format!("elsewhere:page:{}", number),
+            book_id: "elsewhere".to_string(),
+            page_number: number,
+            ocr_text: text.map(str::to_string),
+            has_problems: text.is_some(),
+            problem_count: u32::from(text.is_some()) * 3,
+            page_kind: Some(crate::models::PageKind::Text),
+            ocr_provider: text.map(|_| "mistral".to_string()),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let mut book = db.get_book("algebra-7").await.unwrap().unwrap();
+        book.title = "Renamed elsewhere".to_string();
+        let written = db.import_pages(&book, &[page(1, Some("read there")), page(2, None)]).await.unwrap();
+        assert_eq!(written, 2);
+
+        let pages = db.get_pages_by_book("algebra-7").await.unwrap();
+        assert_eq!(pages[0].ocr_text.as_deref(), Some("read there"));
+        assert_eq!((pages[0].problem_count, pages[0].ocr_provider.as_deref()), (3, Some("mistral")));
+        assert_eq!(pages[1].ocr_text.as_deref(), Some("read here"));
+        assert_eq!((pages[1].problem_count, pages[1].page_kind), (1, Some(crate::models::PageKind::Text)));
+        assert_eq!(db.get_book("algebra-7").await.unwrap().unwrap().title, "algebra-7");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn pages_problems_continue_onto_are_pending_until_read() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let page_12 = db.get_or_create_page("algebra-7", 12).await.unwrap();
+        db.update_page_ocr(&page_12.id, "71. Решите уравнение", 1).await.unwrap();
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "71"),
+            chapter_id: chapter_id.clone(),
+            page_number: Some(12),
+            number: "71".to_string(),
+            content: "71. Решите уравнение".to_string(),
+            continues_to_page: Some(13),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.save_page_problems(&page_12.id, 12, &[problem]).await.unwrap();
+
+        let blank = db.get_or_create_page("algebra-7", 14).await.unwrap();
+        db.set_page_kind(&blank.id, crate::models::PageKind::Blank).await.unwrap();
+        assert_eq!(db.add_pending_pages("algebra-7", &[13, 20]).await.unwrap(), 1);
+        assert_eq!(db.add_pending_pages("geometry-8", &[1]).await.unwrap(), 0);
+        sqlx::query("UPDATE books SET total_pages = 30 WHERE id = 'algebra-7'").execute(&db.pool).await.unwrap();
+        assert_eq!(db.add_pending_pages("algebra-7", &[31, 4_000_000_000]).await.unwrap(), 0);
+
+        let pending = db.get_pending_pages("algebra-7").await.unwrap();
+        let numbers: Vec<u32> = pending.iter().map(|p| p.page_number).collect();
+        assert_eq!(numbers, vec![13, 20]);
+        assert_eq!(pending[0].problem_ids, vec!["algebra-7:1:71"]);
+        assert_eq!(pending[0].chapter_id.as_deref(), Some(chapter_id.as_str()));
+        assert!(pending[1].problem_ids.is_empty());
+
+        let page_13 = db.get_page("algebra-7", 13).await.unwrap().unwrap();
+        assert!(page_13.is_pending());
+        db.update_page_ocr(&page_13.id, "$x^2 = 4$.", 0).await.unwrap();
+        assert_eq!(db.get_pending_pages("algebra-7").await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn solution_attachments_round_trip() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-7", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "Постройте график функции $y = x^2$".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+        let solution = Solution {
+            id: Solution::generate_id(&problem.id),
+            problem_id: problem.id.clone(),
+            provider: "claude".to_string(),
+            content: "Парабола".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        db.save_solution(&solution).await.unwrap();
+        assert!(db.solution_exists(&solution.id).await.unwrap());
+        assert!(!db.solution_exists("missing").await.unwrap());
+
+        let spec: crate::services::plot::PlotSpec = serde_json::from_str(r#"{"functions": ["x^2"]}"#).unwrap();
+        let attachment = SolutionAttachment {
+            id: "a1".to_string(),
+            solution_id: solution.id.clone(),
+            file_name: "plot.svg".to_string(),
+            content_type: "image/svg+xml".to_string(),
+            size: 42,
+            storage_key: "a1.svg".to_string(),
+            plot_spec: Some(spec.clone()),
+            created_at: chrono::Utc::now(),
+        };
+        db.create_solution_attachment(&attachment).await.unwrap();
+
+        let stored = db.get_solution_attachment("a1").await.unwrap().unwrap();
+        assert_eq!(stored.plot_spec, Some(spec));
+        assert_eq!((stored.size, stored.storage_key.as_str()), (42, "a1.svg"));
+        let listed = db
+            .get_attachments_for_solutions(&[solution.id.clone(), "missing".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(db.get_solution_attachments("missing").await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn problem_figures_round_trip() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "geometry-7", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("geometry-7", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "В треугольнике ABC угол C прямой".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
 
-        db.create_or_update_problems(&problems)
-            .await
-            .expect("insert problems");
+        let description: crate::services::figure::FigureDescription = serde_json::from_str(
+            r#"{"points": [{"name": "A", "x": 0, "y": 0}, {"name": "B", "x": 4, "y": 0}, {"name": "C", "x": 0, "y": 3}],
+                "polygons": [["A", "B", "C"]]}"#,
+        )
+        .unwrap();
+        let figure = ProblemFigure {
+            problem_id: problem.id.clone(),
+            provider: "claude".to_string(),
+            description: description.clone(),
+            reconstructed: true,
+            created_at: chrono::Utc::now(),
+        };
+        db.save_problem_figure(&figure).await.unwrap();
 
-        // Chapter listing should return only parent problems.
-        let parents = db.get_problems_by_chapter(&chapter_id).await.expect("list");
-        assert_eq!(parents.len(), 2);
+        let stored = db.get_problem_figure(&problem.id).await.unwrap().unwrap();
+        assert_eq!(stored.description, description);
+        assert!(stored.reconstructed);
+        assert!(db.get_problem_figure("missing").await.unwrap().is_none());
 
-        let subs1 = db.get_sub_problems(&p1_id).await.expect("subs1");
-        let subs2 = db.get_sub_problems(&p2_id).await.expect("subs2");
-        assert_eq!(subs1.len(), 1);
-        assert_eq!(subs2.len(), 1);
-        assert_eq!(subs1[0].number, "a");
-        assert_eq!(subs2[0].number, "a");
+        assert!(db.delete_problem_figure(&problem.id).await.unwrap());
+        assert!(!db.delete_problem_figure(&problem.id).await.unwrap());
 
         let _ = std::fs::remove_file(path);
     }
 
+
     #[tokio::test]
-    async fn migrates_legacy_unique_constraint_and_allows_sub_problems() {
-        let path = std::env::temp_dir().join(format!("bookers_test_legacy_{}.db", uuid::Uuid::new_v4()));
-        let _ = std::fs::File::create(&path);
-        let url = format!("sqlite:{}", path.to_str().unwrap());
+    async fn curricula_seed_and_problem_standards() {
+        let (db, path) = new_temp_db().await;
+        let standards = db.get_curricula(Some("fgos")).await.unwrap();
+        assert!(standards.iter().any(|s| s.code == "3.1.3"));
 
-        // Create a legacy `problems` table with table-level UNIQUE(chapter_id, number).
-        let mut conn = sqlx::SqliteConnection::connect(&url).await.expect("connect");
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS problems (
-                id TEXT PRIMARY KEY,
-                chapter_id TEXT NOT NULL,
-                page_id TEXT,
-                parent_id TEXT,
-                number TEXT NOT NULL,
-                display_name TEXT NOT NULL,
-                content TEXT NOT NULL,
-                latex_formulas TEXT,
-                page_number INTEGER,
-                difficulty INTEGER,
-                has_solution BOOLEAN DEFAULT FALSE,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (chapter_id) REFERENCES chapters(id) ON DELETE CASCADE,
-                FOREIGN KEY (page_id) REFERENCES pages(id) ON DELETE SET NULL,
-                FOREIGN KEY (parent_id) REFERENCES problems(id) ON DELETE CASCADE,
-                UNIQUE(chapter_id, number)
-            );
-            "#,
-        )
-        .execute(&mut conn)
-        .await
-        .expect("create legacy problems");
-        drop(conn);
+        let chapter_id = seed_book_and_chapter(&db, "algebra-8", 2).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-8", 2, "5"),
+            chapter_id,
+            number: "5".to_string(),
+            display_name: "Задача 5".to_string(),
+            content: "Решите квадратное уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
 
-        // Init with current code: should rebuild the table and remove the legacy UNIQUE constraint.
-        let db = Database::new(&url).await.expect("init db");
+        let quadratic: Vec<&CurriculumStandard> = standards.iter().filter(|s| s.code == "3.1.3").collect();
+        db.replace_auto_problem_standards(&problem.id, &quadratic).await.unwrap();
+        db.replace_auto_problem_standards(&problem.id, &quadratic).await.unwrap();
+        let tagged = db.get_book_standard_tags("algebra-8", "fgos").await.unwrap();
+        assert_eq!(tagged, vec![(2, problem.id.clone(), "3.1.3".to_string())]);
+        assert!(db.get_problem_standards(&problem.id).await.unwrap()[0].auto);
 
-        let sql: String = sqlx::query_scalar(
-            "SELECT sql FROM sqlite_master WHERE type='table' AND name='problems'",
-        )
-        .fetch_one(&db.pool)
-        .await
-        .expect("read schema");
-        assert!(
-            !sql.to_lowercase().contains("unique(chapter_id, number)"),
-            "legacy UNIQUE constraint should be removed"
-        );
+        assert!(db.delete_curriculum_standard("fgos", "3.1.3").await.unwrap());
+        assert!(db.get_problem_standards(&problem.id).await.unwrap().is_empty());
+        assert!(!db.delete_curriculum_standard("fgos", "3.1.3").await.unwrap());
 
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn assignments_keep_problem_order_and_submissions() {
+        let (db, path) = new_temp_db().await;
         let chapter_id = seed_book_and_chapter(&db, "algebra-7", 1).await;
+        let mut ids = Vec::new();
+        for number in ["1", "2"] {
+            let problem = Problem {
+                id: Problem::generate_id("algebra-7", 1, number),
+                chapter_id: chapter_id.clone(),
+                number: number.to_string(),
+                display_name: format!("Задача {}", number),
+                content: "Решите уравнение".to_string(),
+                created_at: chrono::Utc::now(),
+                ..Default::default()
+            };
+            db.create_problem(&problem).await.unwrap();
+            ids.push(problem.id);
+        }
 
-        // Now ensure repeated letters across parents are accepted.
-        let p1_id = Problem::generate_id("algebra-7", 1, "71");
-        let p2_id = Problem::generate_id("algebra-7", 1, "72");
+        let assignment = Assignment::new("ДЗ", None, vec![ids[1].clone(), ids[0].clone()], chrono::Utc::now());
+        db.create_assignment(&assignment).await.unwrap();
+        let stored = db.get_assignment_by_token(&assignment.share_token).await.unwrap().unwrap();
+        assert_eq!(stored.problem_ids, vec![ids[1].clone(), ids[0].clone()]);
+
+        let submission = Submission {
+            id: uuid::Uuid::new_v4().to_string(),
+            assignment_id: assignment.id.clone(),
+            problem_id: ids[0].clone(),
+            student_name: "Аня".to_string(),
+            source: SubmissionSource::Photo,
+            answer: None,
+            photo_key: Some("submission.png".to_string()),
+            verdict: AnswerVerdict::Unknown,
+            late: true,
+            submitted_at: chrono::Utc::now(),
+        };
+        db.create_submission(&submission).await.unwrap();
+        let stored = db.get_assignment_submissions(&assignment.id).await.unwrap();
+        assert_eq!((stored[0].source, stored[0].verdict, stored[0].late), (SubmissionSource::Photo, AnswerVerdict::Unknown, true));
+
+        // Calibrations are read back per book and go with their problem
+        assert_eq!(db.get_submitted_problem_difficulties().await.unwrap().get(&ids[0]), Some(&None));
+        let calibration = Calibration {
+            problem_id: ids[0].clone(),
+            rating: 1650.0,
+            difficulty: 7,
+            attempts: 4,
+            success_rate: 0.25,
+            median_seconds: Some(90.0),
+            calibrated_at: chrono::Utc::now(),
+        };
+        db.replace_calibrations(std::slice::from_ref(&calibration)).await.unwrap();
+        let stored = db.get_calibrations(Some("algebra-7")).await.unwrap();
+        assert_eq!(stored[&ids[0]].difficulty, 7);
+        assert!(db.get_calibrations(Some("geometry-8")).await.unwrap().is_empty());
 
-        let now = chrono::Utc::now();
-        let problems = vec![
-            Problem {
-                id: p1_id.clone(),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: None,
-                number: "71".to_string(),
-                display_name: "Задача 71".to_string(),
-                content: "71. Foo".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-            Problem {
-                id: p2_id.clone(),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: None,
-                number: "72".to_string(),
-                display_name: "Задача 72".to_string(),
-                content: "72. Bar".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-            Problem {
-                id: format!("{}:a", p1_id),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: Some(p1_id.clone()),
-                number: "a".to_string(),
-                display_name: "a)".to_string(),
-                content: "a) sub 1".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-            Problem {
-                id: format!("{}:a", p2_id),
-                chapter_id: chapter_id.clone(),
-                page_id: None,
-                parent_id: Some(p2_id.clone()),
-                number: "a".to_string(),
-                display_name: "a)".to_string(),
-                content: "a) sub 2".to_string(),
-                latex_formulas: vec![],
-                page_number: Some(1),
-                difficulty: None,
-                has_solution: false,
-                created_at: now,
-                solution: None,
-                sub_problems: None,
-                continues_from_page: None,
-                continues_to_page: None,
-                is_cross_page: false,
-            is_bookmarked: false,
-            },
-        ];
+        assert!(db.delete_assignment(&assignment.id).await.unwrap());
+        assert!(db.get_submission(&submission.id).await.unwrap().is_none());
+        assert!(db.list_assignments().await.unwrap().is_empty());
 
-        db.create_or_update_problems(&problems)
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn groups_track_members_and_assignments() {
+        let (db, path) = new_temp_db().await;
+        let mut group = Group::new("7Б");
+        group.members = vec!["Аня".to_string()];
+        db.create_group(&group).await.unwrap();
+
+        assert!(db.add_group_member(&group.id, "Борис").await.unwrap());
+        assert!(!db.add_group_member(&group.id, "аня").await.unwrap());
+        assert!(db.remove_group_member(&group.id, "БОРИС").await.unwrap());
+
+        let assignment = Assignment::new("ДЗ", None, Vec::new(), chrono::Utc::now());
+        db.create_assignment(&assignment).await.unwrap();
+        db.add_group_assignment(&group.id, &assignment.id).await.unwrap();
+        db.add_group_assignment(&group.id, &assignment.id).await.unwrap();
+
+        let stored = db.get_group(&group.id).await.unwrap().unwrap();
+        assert_eq!(stored.members, ["Аня"]);
+        assert_eq!(stored.assignment_ids, std::slice::from_ref(&assignment.id));
+
+        assert!(db.delete_assignment(&assignment.id).await.unwrap());
+        assert!(db.list_groups().await.unwrap()[0].assignment_ids.is_empty());
+        assert!(db.delete_group(&group.id).await.unwrap());
+        assert!(db.get_group(&group.id).await.unwrap().is_none());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn digest_counts_the_period_and_keeps_the_latest() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-9", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-9", 1, "1"),
+            chapter_id,
+            number: "1".to_string(),
+            display_name: "Задача 1".to_string(),
+            content: "Решите уравнение".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        let first = crate::services::digest::generate_digest(&db, chrono::Utc::now() + chrono::Duration::minutes(1))
             .await
-            .expect("insert problems after migration");
+            .unwrap();
+        assert_eq!(first.stats.problems_parsed, 1);
+        let latest = db.get_latest_digest_report().await.unwrap().unwrap();
+        assert_eq!((latest.id.as_str(), latest.delivered), (first.id.as_str(), false));
 
-        let subs1 = db.get_sub_problems(&p1_id).await.expect("subs1");
-        let subs2 = db.get_sub_problems(&p2_id).await.expect("subs2");
-        assert_eq!(subs1.len(), 1);
-        assert_eq!(subs2.len(), 1);
+        // The next digest starts where the last one ended
+        let second = crate::services::digest::generate_digest(&db, first.period_end + chrono::Duration::days(7))
+            .await
+            .unwrap();
+        assert_eq!(second.period_start, first.period_end);
+        assert_eq!(second.stats.problems_parsed, 0);
+        db.mark_digest_delivered(&second.id).await.unwrap();
+        assert!(db.get_latest_digest_report().await.unwrap().unwrap().delivered);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn issues_follow_their_problem_and_filter_by_book() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "geometry-8", 3).await;
+        let problem = Problem {
+            id: Problem::generate_id("geometry-8", 3, "12"),
+            chapter_id,
+            number: "12".to_string(),
+            display_name: "Задача 12".to_string(),
+            content: "Найдите угол".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        let book_id = db.issue_entity_book(IssueEntity::Problem, &problem.id).await.unwrap();
+        assert_eq!(book_id.as_deref(), Some("geometry-8"));
+        assert!(db.issue_entity_book(IssueEntity::Solution, "missing").await.unwrap().is_none());
+
+        let now = chrono::Utc::now();
+        let mut issue = Issue {
+            id: uuid::Uuid::new_v4().to_string(),
+            entity_type: IssueEntity::Problem,
+            entity_id: problem.id.clone(),
+            book_id: "geometry-8".to_string(),
+            title: "Нет рисунка".to_string(),
+            body: None,
+            status: IssueStatus::Open,
+            assignee: None,
+            created_by: Some("Оля".to_string()),
+            created_at: now,
+            updated_at: now,
+        };
+        db.save_issue(&issue).await.unwrap();
+        db.add_issue_comment(&IssueComment::new(&issue.id, None, "Рисунок на стр. 40".to_string())).await.unwrap();
+        issue.status = IssueStatus::InProgress;
+        issue.assignee = Some("Дима".to_string());
+        db.save_issue(&issue).await.unwrap();
+
+        let open = IssueFilter { status: Some(IssueStatus::Open), ..Default::default() };
+        assert!(db.list_issues(&open).await.unwrap().is_empty());
+        let in_book = IssueFilter { book: Some("geometry-8".to_string()), ..Default::default() };
+        assert_eq!(db.list_issues(&in_book).await.unwrap()[0].assignee.as_deref(), Some("Дима"));
+
+        db.rename_book("geometry-8", "geometry-8-2024").await.unwrap();
+        let renamed = db.get_issue(&issue.id).await.unwrap().unwrap();
+        assert_eq!((renamed.book_id.as_str(), renamed.entity_id.as_str()), ("geometry-8-2024", "geometry-8-2024:3:12"));
+        assert_eq!(db.get_issue_comments(&issue.id).await.unwrap().len(), 1);
+
+        assert!(db.delete_issue(&issue.id).await.unwrap());
+        assert!(db.get_issue_comments(&issue.id).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn export_runs_keep_their_snapshot_across_renames() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-9", 2).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-9", 2, "40"),
+            chapter_id: chapter_id.clone(),
+            number: "40".to_string(),
+            display_name: "Задача 40".to_string(),
+            content: "Решите неравенство".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+
+        let exporter = crate::services::export::Exporter::new(db.clone());
+        let run = ExportRun::new("algebra-9", None, "markdown");
+        db.save_export_run(&run, &exporter.snapshot("algebra-9", None).await.unwrap()).await.unwrap();
+
+        db.rename_book("algebra-9", "algebra-9-2025").await.unwrap();
+        let stored = db.get_export_run(&run.id).await.unwrap().unwrap();
+        assert_eq!(stored.book_id, "algebra-9-2025");
+        let problems = db.get_export_run_problems(&run.id).await.unwrap();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].problem_id, "algebra-9-2025:2:40");
+        assert_eq!(problems[0].chapter_id, "algebra-9-2025:2");
+
+        let now = exporter.snapshot("algebra-9-2025", None).await.unwrap();
+        assert_eq!(now[0].content_hash, problems[0].content_hash);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn recent_book_activity_is_limited_to_the_book() {
+        let (db, path) = new_temp_db().await;
+        for book in ["chemistry-8", "chemistry-9"] {
+            let chapter_id = seed_book_and_chapter(&db, book, 1).await;
+            let problem = Problem {
+                id: Problem::generate_id(book, 1, "3"),
+                chapter_id,
+                number: "3".to_string(),
+                display_name: "Задача 3".to_string(),
+                content: "Уравняйте реакцию".to_string(),
+                created_at: chrono::Utc::now(),
+                ..Default::default()
+            };
+            db.create_problem(&problem).await.unwrap();
+        }
+        let problem_id = Problem::generate_id("chemistry-8", 1, "3");
+        let solution = Solution {
+            id: Solution::generate_id(&problem_id),
+            problem_id: problem_id.clone(),
+            provider: "claude".to_string(),
+            content: "2H2 + O2 = 2H2O".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        db.create_or_update_solution(&solution).await.unwrap();
+
+        let problems = db.recent_book_problems("chemistry-8", 10).await.unwrap();
+        assert_eq!(problems.iter().map(|p| &p.id).collect::<Vec<_>>(), vec![&problem_id]);
+        let solutions = db.recent_book_solutions("chemistry-8", 10).await.unwrap();
+        assert_eq!(solutions.len(), 1);
+        assert_eq!(solutions[0].1, "Задача 3");
+        assert!(db.recent_book_solutions("chemistry-9", 10).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn share_links_follow_renames_and_can_be_revoked() {
+        let (db, path) = new_temp_db().await;
+        seed_book_and_chapter(&db, "physics-9", 3).await;
+        let link = ShareLink::new(ShareTarget::Problem(Problem::generate_id("physics-9", 3, "12")), 3600);
+        db.save_share_link(&link).await.unwrap();
+        let chapter_link = ShareLink::new(ShareTarget::Chapter("physics-9:3".to_string()), 3600);
+        db.save_share_link(&chapter_link).await.unwrap();
+
+        db.rename_book("physics-9", "physics-9-2025").await.unwrap();
+        let stored = db.get_share_link(&link.id).await.unwrap().unwrap();
+        assert_eq!(stored.target, ShareTarget::Problem("physics-9-2025:3:12".to_string()));
+        assert_eq!(stored.expires_at, link.expires_at);
+        let stored = db.get_share_link(&chapter_link.id).await.unwrap().unwrap();
+        assert_eq!(stored.target, ShareTarget::Chapter("physics-9-2025:3".to_string()));
+        assert_eq!(db.list_share_links().await.unwrap().len(), 2);
+
+        assert!(db.delete_share_link(&link.id).await.unwrap());
+        assert!(db.get_share_link(&link.id).await.unwrap().is_none());
+
+        let secret = db.app_secret("share_links").await.unwrap();
+        assert_eq!(db.app_secret("share_links").await.unwrap(), secret);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn full_text_index_follows_edits_and_deletes() {
+        let (db, path) = new_temp_db().await;
+        let chapter_id = seed_book_and_chapter(&db, "algebra-10", 1).await;
+        let problem = Problem {
+            id: Problem::generate_id("algebra-10", 1, "5"),
+            chapter_id: chapter_id.clone(),
+            number: "5".to_string(),
+            display_name: "Задача 5".to_string(),
+            content: "Решите квадратное уравнение $x^2 - 5x + 6 = 0$".to_string(),
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        };
+        db.create_problem(&problem).await.unwrap();
+        let search = |q: &'static str| {
+            let db = db.clone();
+            async move {
+                let expression = crate::services::text_search::match_expression(q).unwrap();
+                db.full_text_search(&expression, None, None, 10, 0).await.unwrap()
+            }
+        };
+
+        let hits = search("КВАДРАТНОЕ урав").await;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, SearchKind::Problem);
+        assert!(hits[0].snippet.contains("<mark>квадратное</mark>"), "{}", hits[0].snippet);
+
+        db.update_problem_content(&problem.id, "Найдите корни многочлена", Vec::new(), None).await.unwrap();
+        assert!(search("квадратное").await.is_empty());
+        assert_eq!(search("многочлена").await.len(), 1);
+
+        db.rename_book("algebra-10", "algebra-10-2025").await.unwrap();
+        let hits = search("многочлена").await;
+        assert_eq!(hits[0].id, "algebra-10-2025:1:5");
+        let other_book = crate::services::text_search::match_expression("многочлена").unwrap();
+        assert!(db.full_text_search(&other_book, None, Some("algebra-10"), 10, 0).await.unwrap().is_empty());
+
+        db.delete_chapter_problems("algebra-10-2025:1", false).await.unwrap();
+        assert!(search("многочлена").await.is_empty());
 
         let _ = std::fs::remove_file(path);
     }
+
+    #[tokio::test]
+    async fn export_runs_keep_filters_and_stored_file() {
+        use crate::services::attachments::AttachmentStorage;
+        use crate::services::export_history::{store_export_artifact, ExportFilters};
+
+        let (db, path) = new_temp_db().await;
+        seed_book_and_chapter(&db, "geometry-8", 1).await;
+        let dir = std::env::temp_dir().join(format!("bookers-exports-{}", uuid::Uuid::new_v4()));
+        let storage = AttachmentStorage::Local { dir: dir.clone() };
+
+        let filters = ExportFilters { approved_only: true, provider: Some("verified".to_string()), ..Default::default() };
+        let mut run = ExportRun::new("geometry-8", None, "markdown").with_filters(filters.clone());
+        db.save_export_run(&run, &[]).await.unwrap();
+        let failed = ExportRun::new("geometry-8", None, "latex");
+        db.save_export_run(&failed, &[]).await.unwrap();
+        db.fail_export_run(&failed.id, "render failed", 12).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let file = b"# Geometry 8".to_vec();
+        store_export_artifact(&db, &storage, &mut run, "geometry-8_export.md", "text/markdown", file.clone(), started)
+            .await
+            .unwrap();
+
+        let stored = db.get_export_run(&run.id).await.unwrap().unwrap();
+        assert_eq!(stored.filters, filters);
+        assert_eq!(stored.size, Some(file.len() as u64));
+        assert_eq!(stored.checksum.as_deref().map(str::len), Some(64));
+        assert_eq!(storage.get(stored.storage_key.as_deref().unwrap()).await.unwrap(), file);
+
+        let runs = db.list_export_runs(Some("geometry-8"), 10).await.unwrap();
+        assert_eq!(runs.len(), 2);
+        let failed = runs.iter().find(|r| r.id == failed.id).unwrap();
+        assert_eq!(failed.error.as_deref(), Some("render failed"));
+        assert!(failed.storage_key.is_none());
+        assert!(db.list_export_runs(Some("algebra-7"), 10).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_file(path);
+    }
+
 }
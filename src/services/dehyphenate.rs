@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use lazy_regex::regex;
+
+/// Particles that are always written with a hyphen (`кто-нибудь`, `всё-таки`)
+const HYPHEN_PARTICLES: &[&str] = &["либо", "нибудь", "таки"];
+
+/// Pronouns/adverbs that take `-то` (`что-то`, `когда-то`); `-то` after other
+/// words is usually just the end of a split word (`ле-то`)
+const TO_PARTICLE_HEADS: &[&str] = &[
+    "кто", "что", "как", "где", "куда", "когда", "откуда", "почему", "зачем", "какой", "какая",
+    "какое", "какие", "каким", "какого", "какую", "чей", "чья", "чьё", "чьи", "сколько", "кого",
+    "чего", "кому", "чему", "чем", "ком", "чём",
+];
+
+/// First parts of compounds that keep their hyphen (`юго-запад`, `self-adjoint`)
+const HYPHEN_PREFIXES: &[&str] = &["кое", "северо", "юго", "вице", "экс", "self", "well"];
+
+/// Joins words split across lines with a hyphen (`уравне-\nние` → `уравнение`).
+///
+/// Whether the hyphen is dropped is dictionary-assisted: words that occur
+/// unbroken elsewhere in the text are joined, words that occur hyphenated
+/// keep the hyphen, and fixed lists of Russian/English particles and
+/// prefixes decide the rest.
+pub struct Dehyphenator {
+    words: HashSet<String>,
+    hyphenated: HashSet<String>,
+}
+
+impl Dehyphenator {
+    /// Build the dictionary from the words of `text` itself
+    pub fn from_text(text: &str) -> Self {
+        let mut words = HashSet::new();
+        let mut hyphenated = HashSet::new();
+
+        for line in text.lines() {
+            // Skip the hyphen that ends the line, it's what we're repairing
+            let line = line.trim_end().trim_end_matches(['-', '¬']);
+            for token in regex!(r"\p{L}+(?:-\p{L}+)*").find_iter(line) {
+                let token = token.as_str().to_lowercase();
+                if token.contains('-') {
+                    hyphenated.insert(token);
+                } else {
+                    words.insert(token);
+                }
+            }
+        }
+
+        Self { words, hyphenated }
+    }
+
+    /// Whether `head-` + `tail` is one word written without a hyphen
+    fn should_join(&self, head: &str, tail: &str) -> bool {
+        let head = head.to_lowercase();
+        let tail = tail.to_lowercase();
+
+        if self.words.contains(&format!("{}{}", head, tail)) {
+            return true;
+        }
+        if self.hyphenated.contains(&format!("{}-{}", head, tail)) {
+            return false;
+        }
+        if HYPHEN_PARTICLES.contains(&tail.as_str())
+            || (tail == "то" && TO_PARTICLE_HEADS.contains(&head.as_str()))
+            || HYPHEN_PREFIXES.contains(&head.as_str())
+        {
+            return false;
+        }
+        true
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let mut out = Vec::with_capacity(lines.len());
+        let mut i = 0;
+
+        while i < lines.len() {
+            if i + 1 < lines.len()
+                && let Some((line, rest)) = self.join_with_next(&lines[i], &lines[i + 1])
+            {
+                out.push(line);
+                if rest.is_empty() {
+                    // The next line held only the word fragment
+                    i += 2;
+                } else {
+                    lines[i + 1] = rest;
+                    i += 1;
+                }
+                continue;
+            }
+            out.push(std::mem::take(&mut lines[i]));
+            i += 1;
+        }
+
+        out.join("\n")
+    }
+
+    /// If `line` ends in `word-` and `next` starts with a lowercase word
+    /// fragment, return the repaired line and what remains of `next`.
+    fn join_with_next(&self, line: &str, next: &str) -> Option<(String, String)> {
+        // Require 2+ letters not preceded by math-ish characters (`\alpha-`, `x_1-`)
+        let line = line.trim_end();
+        let caps = regex!(r"(?:^|[^\p{L}\d$\\_^])(\p{L}{2,})[-¬]$").captures(line)?;
+        let head = caps.get(1)?;
+
+        let next = next.trim_start();
+        let tail_caps = regex!(r"^(\p{Ll}\p{L}*)([.,;:!?)»]*)").captures(next)?;
+        let tail = tail_caps.get(1)?.as_str();
+        let rest = &next[tail_caps.get(0)?.end()..];
+        if rest.starts_with('-') {
+            return None;
+        }
+
+        let separator = if self.should_join(head.as_str(), tail) { "" } else { "-" };
+        let repaired = format!(
+            "{}{}{}{}{}",
+            &line[..head.start()],
+            head.as_str(),
+            separator,
+            tail,
+            tail_caps.get(2).map_or("", |m| m.as_str())
+        );
+
+        Some((repaired, rest.trim_start().to_string()))
+    }
+}
+
+/// Dehyphenate OCR text using its own vocabulary as the dictionary
+pub fn dehyphenate(text: &str) -> String {
+    // Soft hyphens mid-line are invisible; at a line end they mark a split
+    let text = text.replace("\u{00AD}\n", "-\n").replace('\u{00AD}', "");
+    Dehyphenator::from_text(&text).apply(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUSSIAN_PAGE: &str = "\
+Задача 12. Решите урав-
+нение $x^2 - 4 = 0$ и найдите
+какой-нибудь корень. Что-
+то подобное было в задаче 5.
+Постройте график функ-
+ции на отрезке. Это тот же график функции.";
+
+    const ENGLISH_PAGE: &str = "\
+Problem 3. Find the deriva-
+tive of the function. A self-
+adjoint operator is well-
+known. Compute the derivative.";
+
+    #[test]
+    fn joins_split_russian_words() {
+        let fixed = dehyphenate(RUSSIAN_PAGE);
+        assert!(fixed.contains("Решите уравнение\n$x^2 - 4 = 0$"), "{}", fixed);
+        assert!(fixed.contains("Что-то\nподобное"), "{}", fixed);
+        assert!(fixed.contains("функции\nна отрезке"), "{}", fixed);
+    }
+
+    #[test]
+    fn keeps_english_compounds() {
+        let fixed = dehyphenate(ENGLISH_PAGE);
+        assert!(fixed.contains("derivative\nof the function"), "{}", fixed);
+        assert!(fixed.contains("self-adjoint\noperator"), "{}", fixed);
+        assert!(fixed.contains("well-known."), "{}", fixed);
+    }
+
+    #[test]
+    fn leaves_math_and_dashes_alone() {
+        let text = "$a + \\alpha-\nb$ и x-\ny\nОтвет —\nпять";
+        assert_eq!(dehyphenate(text), text);
+    }
+
+    #[test]
+    fn uses_document_vocabulary() {
+        // "пол-литра" appears hyphenated elsewhere, so the split one keeps it
+        let text = "Налили пол-литра воды.\nЕщё пол-\nлитра.";
+        assert_eq!(dehyphenate(text), "Налили пол-литра воды.\nЕщё пол-литра.");
+    }
+
+    #[test]
+    fn strips_soft_hyphens() {
+        assert_eq!(dehyphenate("при\u{00AD}мер\nсло\u{00AD}\nва"), "пример\nслова");
+    }
+}
@@ -0,0 +1,218 @@
+//! Weekly digest: what happened in the library over the last week (pages
+//! OCR'd, problems parsed, solutions generated, estimated API spend, study
+//! activity) as Markdown and HTML, kept in `digest_reports` and posted to
+//! the digest webhook when one is configured.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::services::database::Database;
+use crate::services::http_client::HttpClientFactory;
+use crate::services::provider_registry::{capabilities, ProviderKind};
+
+/// Length of the period a digest covers
+pub const DIGEST_PERIOD_DAYS: i64 = 7;
+
+/// Rough characters per token for spend estimates
+pub(crate) const CHARS_PER_TOKEN: f64 = 4.0;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Solutions one provider generated in the period
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderSolutions {
+    pub provider: String,
+    pub solutions: u64,
+    /// Characters of solution text, the basis of the spend estimate
+    pub chars: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DigestStats {
+    /// Pages that got OCR text in the period
+    pub pages_ocrd: u64,
+    pub problems_parsed: u64,
+    /// By provider, most solutions first
+    pub solutions: Vec<ProviderSolutions>,
+    /// USD, from the providers' per-token prices; per-page OCR pricing isn't included
+    pub estimated_spend_usd: f64,
+    /// Distinct problems opened in the reader
+    pub problems_viewed: u64,
+    pub submissions: u64,
+    pub correct_submissions: u64,
+}
+
+impl DigestStats {
+    pub fn solutions_total(&self) -> u64 {
+        self.solutions.iter().map(|s| s.solutions).sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestReport {
+    pub id: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub stats: DigestStats,
+    pub markdown: String,
+    pub html: String,
+    /// Posted to the digest webhook
+    pub delivered: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Approximate USD cost of `chars` characters of output from a solve provider
+pub fn estimate_spend(provider: &str, chars: u64) -> f64 {
+    let per_1k = capabilities(ProviderKind::Solve, provider).and_then(|c| c.cost_per_1k_tokens).unwrap_or(0.0);
+    chars as f64 / CHARS_PER_TOKEN / 1000.0 * per_1k
+}
+
+/// Whether a new digest is due: a week after the last one ended
+pub fn digest_due(latest: Option<&DigestReport>, now: DateTime<Utc>) -> bool {
+    latest.is_none_or(|report| now - report.period_end >= chrono::Duration::days(DIGEST_PERIOD_DAYS))
+}
+
+pub fn render_markdown(start: DateTime<Utc>, end: DateTime<Utc>, stats: &DigestStats) -> String {
+    let mut md = format!(
+        "# Weekly digest: {} – {}\n\n",
+        start.format("%Y-%m-%d"),
+        end.format("%Y-%m-%d")
+    );
+
+    md.push_str("## Library\n\n");
+    md.push_str(&format!("- Pages OCR'd: {}\n", stats.pages_ocrd));
+    md.push_str(&format!("- Problems parsed: {}\n", stats.problems_parsed));
+    md.push_str(&format!("- Solutions generated: {}\n\n", stats.solutions_total()));
+
+    if !stats.solutions.is_empty() {
+        md.push_str("| Provider | Solutions | Est. cost |\n|---|---|---|\n");
+        for row in &stats.solutions {
+            md.push_str(&format!(
+                "| {} | {} | ${:.2} |\n",
+                row.provider,
+                row.solutions,
+                estimate_spend(&row.provider, row.chars)
+            ));
+        }
+        md.push('\n');
+    }
+    md.push_str(&format!(
+        "Estimated API spend: **${:.2}** (solve providers only, from output length)\n\n",
+        stats.estimated_spend_usd
+    ));
+
+    md.push_str("## Study\n\n");
+    md.push_str(&format!("- Problems viewed: {}\n", stats.problems_viewed));
+    md.push_str(&format!(
+        "- Assignment answers: {} ({} correct)\n",
+        stats.submissions, stats.correct_submissions
+    ));
+    md
+}
+
+pub fn render_html(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::ENABLE_TABLES);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// Compose and store the digest of the period since the last one ended (or
+/// the last week, for the first one) up to `now`
+pub async fn generate_digest(db: &Database, now: DateTime<Utc>) -> Result<DigestReport> {
+    let period_start = match db.get_latest_digest_report().await? {
+        Some(latest) if latest.period_end < now => latest.period_end,
+        _ => now - chrono::Duration::days(DIGEST_PERIOD_DAYS),
+    };
+
+    let mut stats = db.get_digest_stats(period_start, now).await?;
+    stats.estimated_spend_usd = stats.solutions.iter().map(|s| estimate_spend(&s.provider, s.chars)).sum();
+    let markdown = render_markdown(period_start, now, &stats);
+    let report = DigestReport {
+        id: uuid::Uuid::new_v4().to_string(),
+        period_start,
+        period_end: now,
+        html: render_html(&markdown),
+        markdown,
+        stats,
+        delivered: false,
+        created_at: Utc::now(),
+    };
+    db.save_digest_report(&report).await?;
+    Ok(report)
+}
+
+/// Post the digest to `DIGEST_WEBHOOK_URL` as `{"text", "html", ...}` (the
+/// `text` field is what Slack and Mattermost incoming webhooks show).
+/// `false` when no webhook is configured.
+pub async fn deliver_digest(db: &Database, config: &Config, report: &DigestReport) -> Result<bool> {
+    let Some(url) = config.digest_webhook_url.as_deref() else {
+        return Ok(false);
+    };
+    let response = HttpClientFactory::global()
+        .client(WEBHOOK_TIMEOUT)
+        .post(url)
+        .json(&serde_json::json!({
+            "text": report.markdown,
+            "html": report.html,
+            "period_start": report.period_start,
+            "period_end": report.period_end,
+            "stats": report.stats,
+        }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("Digest webhook returned {}", response.status());
+    }
+    db.mark_digest_delivered(&report.id).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_digest_with_spend() {
+        let stats = DigestStats {
+            pages_ocrd: 12,
+            problems_parsed: 40,
+            solutions: vec![ProviderSolutions { provider: "openai".to_string(), solutions: 5, chars: 40_000 }],
+            estimated_spend_usd: 0.1,
+            problems_viewed: 7,
+            submissions: 3,
+            correct_submissions: 2,
+        };
+        assert!((estimate_spend("openai", 40_000) - 0.1).abs() < 1e-9);
+        assert_eq!(estimate_spend("tesseract", 40_000), 0.0);
+
+        let end = DateTime::parse_from_rfc3339("2026-03-09T08:00:00Z").unwrap().with_timezone(&Utc);
+        let md = render_markdown(end - chrono::Duration::days(7), end, &stats);
+        assert!(md.starts_with("# Weekly digest: 2026-03-02 – 2026-03-09"));
+        assert!(md.contains("| openai | 5 | $0.10 |"));
+        assert!(md.contains("Assignment answers: 3 (2 correct)"));
+        assert!(render_html(&md).contains("<table>"));
+    }
+
+    #[test]
+    fn digest_is_due_weekly() {
+        let now = Utc::now();
+        assert!(digest_due(None, now));
+        let report = DigestReport {
+            id: "r".to_string(),
+            period_start: now - chrono::Duration::days(10),
+            period_end: now - chrono::Duration::days(3),
+            stats: DigestStats::default(),
+            markdown: String::new(),
+            html: String::new(),
+            delivered: false,
+            created_at: now,
+        };
+        assert!(!digest_due(Some(&report), now));
+        assert!(digest_due(Some(&report), now + chrono::Duration::days(4)));
+    }
+}
@@ -0,0 +1,34 @@
+//! Optimistic concurrency for edits. Problems and solutions carry a version
+//! that every content change bumps; an update names the version it was based
+//! on (`If-Match: "3"` or `"version": 3` in the body) and is refused with 409
+//! when someone else saved in between, so a second tab can't silently
+//! overwrite the first.
+
+/// Entity tag of a record version, e.g. `"3"`
+pub fn etag(version: u32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Version named by an `If-Match` header: a single strong or weak tag, quoted
+/// or bare. Lists and `*` don't name a version.
+pub fn parse_if_match(value: &str) -> Option<u32> {
+    let tag = value.trim();
+    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+    let tag = tag.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(tag);
+    tag.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_tags_name_versions() {
+        assert_eq!(parse_if_match(&etag(3)), Some(3));
+        assert_eq!(parse_if_match("W/\"12\""), Some(12));
+        assert_eq!(parse_if_match(" 7 "), Some(7));
+        assert_eq!(parse_if_match("*"), None);
+        assert_eq!(parse_if_match("\"1\", \"2\""), None);
+        assert_eq!(parse_if_match("\"abc\""), None);
+    }
+}
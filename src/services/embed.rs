@@ -0,0 +1,114 @@
+//! Problems embedded in other sites: `/embed/problem/{id}` is a small page
+//! meant for an iframe, and `/oembed?url=...` lets oEmbed consumers (Notion,
+//! WordPress, Ghost, ...) turn a pasted problem link into that iframe.
+
+use serde::Serialize;
+
+/// Iframe size when the consumer gives no `maxwidth`/`maxheight`
+pub const DEFAULT_EMBED_WIDTH: u32 = 640;
+pub const DEFAULT_EMBED_HEIGHT: u32 = 360;
+
+/// Page paths that show a single problem, all accepted by `/oembed`
+const PROBLEM_PATHS: &[&str] = &["/embed/problem/", "/problems/", "/textbook/problem/"];
+
+/// oEmbed 1.0 response of type `rich`
+#[derive(Debug, Clone, Serialize)]
+pub struct OEmbed {
+    pub version: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub provider_name: &'static str,
+    pub provider_url: String,
+    pub title: String,
+    pub html: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Problem id of a link to one of this instance's problem pages. Links to
+/// other hosts are refused, so the endpoint can't be used to frame
+/// arbitrary pages.
+pub fn problem_id_from_url(url: &str, base_url: &str) -> Option<String> {
+    let base = base_url.trim_end_matches('/');
+    let path = strip_scheme(url)?.strip_prefix(strip_scheme(base)?)?;
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let id = PROBLEM_PATHS.iter().find_map(|prefix| path.strip_prefix(prefix))?;
+    let id = urlencoding::decode(id.trim_end_matches('/')).ok()?;
+    (!id.is_empty() && !id.contains('/')).then(|| id.into_owned())
+}
+
+/// `http://` and `https://` links to the same host are the same page
+fn strip_scheme(url: &str) -> Option<&str> {
+    url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))
+}
+
+pub fn embed_url(base_url: &str, problem_id: &str) -> String {
+    format!("{}/embed/problem/{}", base_url.trim_end_matches('/'), urlencoding::encode(problem_id))
+}
+
+/// oEmbed response for a problem, sized to the consumer's limits
+pub fn problem_oembed(
+    base_url: &str,
+    problem_id: &str,
+    title: &str,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> OEmbed {
+    let width = max_width.map_or(DEFAULT_EMBED_WIDTH, |max| max.min(DEFAULT_EMBED_WIDTH));
+    let height = max_height.map_or(DEFAULT_EMBED_HEIGHT, |max| max.min(DEFAULT_EMBED_HEIGHT));
+    let html = format!(
+        r#"<iframe src="{}" width="{}" height="{}" title="{}" style="border:0" loading="lazy"></iframe>"#,
+        embed_url(base_url, problem_id),
+        width,
+        height,
+        escape_attribute(title),
+    );
+    OEmbed {
+        version: "1.0",
+        kind: "rich",
+        provider_name: "Bookers",
+        provider_url: base_url.trim_end_matches('/').to_string(),
+        title: title.to_string(),
+        html,
+        width,
+        height,
+    }
+}
+
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_problem_pages_of_this_instance() {
+        let base = "https://books.example.org/";
+        assert_eq!(
+            problem_id_from_url("https://books.example.org/problems/algebra-7%3A1%3A5", base).as_deref(),
+            Some("algebra-7:1:5")
+        );
+        assert_eq!(
+            problem_id_from_url("http://books.example.org/embed/problem/algebra-7:1:5?solution=1", base).as_deref(),
+            Some("algebra-7:1:5")
+        );
+        assert_eq!(
+            problem_id_from_url("https://books.example.org/textbook/problem/%D0%90%D0%BB%D0%B3%D0%B5%D0%B1%D1%80%D0%B0:1:2/", base)
+                .as_deref(),
+            Some("Алгебра:1:2")
+        );
+        assert_eq!(problem_id_from_url("https://evil.example.com/problems/algebra-7:1:5", base), None);
+        assert_eq!(problem_id_from_url("https://books.example.org.evil.com/problems/x", base), None);
+        assert_eq!(problem_id_from_url("https://books.example.org/chapters/algebra-7:1", base), None);
+    }
+
+    #[test]
+    fn oembed_fits_the_consumer_limits() {
+        let oembed = problem_oembed("https://books.example.org", "algebra-7:1:5", "Задача \"5\"", Some(400), None);
+        assert_eq!((oembed.width, oembed.height), (400, DEFAULT_EMBED_HEIGHT));
+        assert!(oembed.html.contains(r#"src="https://books.example.org/embed/problem/algebra-7%3A1%3A5""#));
+        assert!(oembed.html.contains("title=\"Задача &quot;5&quot;\""));
+    }
+}
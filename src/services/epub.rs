@@ -0,0 +1,343 @@
+//! EPUB books. An EPUB has no fixed pages, so each document of its spine (in
+//! reading order, usually one chapter or section) counts as one page: page 3
+//! of `algebra-7.epub` is the third spine document. Its text goes straight
+//! to the parsers, MathML as `$alttext$`, and its first raster image is the
+//! page preview.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use lazy_regex::regex;
+
+/// Largest (X)HTML or OPF document read from an EPUB, uncompressed
+const MAX_TEXT_BYTES: u64 = 16 * 1024 * 1024;
+/// Largest image or other file read from an EPUB, uncompressed
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Whether a book file is an EPUB
+pub fn is_epub(file: impl AsRef<Path>) -> bool {
+    file.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+}
+
+/// An opened EPUB: its OPF metadata and the archive paths of its spine documents
+pub struct EpubBook {
+    archive: zip::ZipArchive<fs::File>,
+    metadata: HashMap<String, String>,
+    spine: Vec<String>,
+}
+
+impl EpubBook {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("Not an EPUB archive {:?}: {}", path, e))?;
+
+        let container = read_text(&mut archive, "META-INF/container.xml")?;
+        let opf_path = regex!(r#"<rootfile\b[^>]*\bfull-path\s*=\s*["']([^"']+)["']"#)
+            .captures(&container)
+            .map(|c| decode_entities(&c[1]))
+            .ok_or_else(|| "EPUB container names no package document".to_string())?;
+        let opf = read_text(&mut archive, &opf_path)?;
+
+        let mut manifest = HashMap::new();
+        for item in regex!(r"<(?:opf:)?item\b[^>]*>").find_iter(&opf) {
+            let attrs = attributes(item.as_str());
+            if let (Some(id), Some(href)) = (attrs.get("id"), attrs.get("href")) {
+                manifest.insert(id.clone(), resolve_href(&opf_path, href));
+            }
+        }
+        let spine: Vec<String> = regex!(r"<(?:opf:)?itemref\b[^>]*>")
+            .find_iter(&opf)
+            .filter_map(|itemref| attributes(itemref.as_str()).get("idref").and_then(|id| manifest.get(id)).cloned())
+            .collect();
+        if spine.is_empty() {
+            return Err("EPUB spine is empty".to_string());
+        }
+
+        let mut metadata = HashMap::new();
+        for (tag, field) in [("title", "Title"), ("creator", "Author"), ("subject", "Subject"), ("publisher", "Producer")] {
+            let pattern = format!(r"(?s)<dc:{tag}\b[^>]*>(.*?)</dc:{tag}>");
+            if let Some(value) = regex::Regex::new(&pattern)
+                .ok()
+                .and_then(|re| re.captures(&opf).map(|c| html_to_text(&c[1])))
+                .filter(|v| !v.is_empty())
+            {
+                metadata.insert(field.to_string(), value);
+            }
+        }
+
+        Ok(Self { archive, metadata, spine })
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.spine.len() as u32
+    }
+
+    /// Metadata under the keys pdfinfo prints (`Pages`, `Title`, `Author`,
+    /// ...), so EPUBs fill the same [`crate::models::PdfMetadata`] as PDFs
+    pub fn info_fields(&self) -> HashMap<String, String> {
+        let mut fields = self.metadata.clone();
+        fields.insert("Pages".to_string(), self.page_count().to_string());
+        fields.insert("Creator".to_string(), "EPUB".to_string());
+        fields
+    }
+
+    /// Archive path of the spine document of 1-based `page`
+    fn chapter_path(&self, page: u32) -> Result<&str, String> {
+        page.checked_sub(1)
+            .and_then(|i| self.spine.get(i as usize))
+            .map(String::as_str)
+            .ok_or_else(|| format!("EPUB has no page {} (it has {})", page, self.spine.len()))
+    }
+
+    /// (X)HTML of the spine document of 1-based `page`
+    pub fn chapter_html(&mut self, page: u32) -> Result<String, String> {
+        let path = self.chapter_path(page)?.to_string();
+        read_text(&mut self.archive, &path)
+    }
+
+    pub fn chapter_text(&mut self, page: u32) -> Result<String, String> {
+        Ok(html_to_text(&self.chapter_html(page)?))
+    }
+
+    /// Text of every page, like a PDF text layer
+    pub fn texts(&mut self) -> Result<Vec<(u32, String)>, String> {
+        (1..=self.page_count()).map(|page| Ok((page, self.chapter_text(page)?))).collect()
+    }
+
+    /// Archive paths of the images a page shows, in document order
+    pub fn chapter_images(&mut self, page: u32) -> Result<Vec<String>, String> {
+        let chapter = self.chapter_path(page)?.to_string();
+        let html = self.chapter_html(page)?;
+        let mut images: Vec<String> = Vec::new();
+        for tag in regex!(r"<(?:img|image)\b[^>]*>").find_iter(&html) {
+            let attrs = attributes(tag.as_str());
+            if let Some(src) = attrs.get("src").or_else(|| attrs.get("xlink:href")).or_else(|| attrs.get("href")) {
+                let path = resolve_href(&chapter, src);
+                if !images.contains(&path) {
+                    images.push(path);
+                }
+            }
+        }
+        Ok(images)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, String> {
+        read_entry(&mut self.archive, path, MAX_FILE_BYTES)
+    }
+}
+
+fn read_text(archive: &mut zip::ZipArchive<fs::File>, path: &str) -> Result<String, String> {
+    Ok(String::from_utf8_lossy(&read_entry(archive, path, MAX_TEXT_BYTES)?).into_owned())
+}
+
+/// An archive entry of at most `limit` bytes. The size in the zip header
+/// can lie, so reading stops at the limit whatever it claims.
+fn read_entry(archive: &mut zip::ZipArchive<fs::File>, path: &str, limit: u64) -> Result<Vec<u8>, String> {
+    let entry = archive.by_name(path).map_err(|e| format!("EPUB has no {}: {}", path, e))?;
+    let mut bytes = Vec::new();
+    entry
+        .take(limit + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {} from EPUB: {}", path, e))?;
+    if bytes.len() as u64 > limit {
+        return Err(format!("{} in EPUB is larger than {} bytes", path, limit));
+    }
+    Ok(bytes)
+}
+
+/// Preview of 1-based `page` of an EPUB: its first image that decodes,
+/// written as PNG to `output`. Text-only chapters have no preview; their
+/// text is read directly instead of OCR'd.
+pub fn render_chapter_preview(epub: &Path, page: u32, output: &Path) -> Result<(), String> {
+    let mut book = EpubBook::open(epub)?;
+    for path in book.chapter_images(page)? {
+        let Ok(bytes) = book.read_file(&path) else {
+            continue;
+        };
+        // SVG and GIF illustrations aren't decodable; try the next image
+        let Ok(image) = image::load_from_memory(&bytes) else {
+            continue;
+        };
+        return image
+            .save_with_format(output, image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to save preview: {}", e));
+    }
+    Err(format!("EPUB page {} has no image to preview; its text is read directly", page))
+}
+
+/// `name="value"` attributes of a tag, names lowercased
+fn attributes(tag: &str) -> HashMap<String, String> {
+    regex!(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .captures_iter(tag)
+        .map(|c| {
+            let value = c.get(2).or_else(|| c.get(3)).map_or("", |m| m.as_str());
+            (c[1].to_lowercase(), decode_entities(value))
+        })
+        .collect()
+}
+
+/// Archive path of `href` as written in the document at archive path `base`:
+/// relative to its directory, percent-decoded, without `#fragment`
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or("");
+    let href = urlencoding::decode(href).map(|h| h.into_owned()).unwrap_or_else(|_| href.to_string());
+    let mut parts: Vec<&str> = match base.rfind('/') {
+        Some(idx) if !href.starts_with('/') => base[..idx].split('/').collect(),
+        _ => Vec::new(),
+    };
+    for part in href.trim_start_matches('/').split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Readable text of an (X)HTML document: one line per block element, blank
+/// lines between paragraphs, MathML as `$alttext$` when it carries one
+pub fn html_to_text(html: &str) -> String {
+    let html = regex!(r"(?is)<head\b.*?</head>|<script\b.*?</script>|<style\b.*?</style>|<!--.*?-->").replace_all(html, "");
+    let html = regex!(r"(?is)<(?:m:)?math\b([^>]*)>.*?</(?:m:)?math>").replace_all(&html, |c: &regex::Captures| {
+        match attributes(&c[1]).get("alttext").filter(|alt| !alt.trim().is_empty()) {
+            Some(alt) => format!("${}$", alt.trim()),
+            None => String::new(),
+        }
+    });
+    let html = regex!(r"(?i)<br\s*/?>").replace_all(&html, "\n");
+    let html = regex!(r"(?i)</?(?:p|div|h[1-6]|li|tr|table|section|article|blockquote|pre|ul|ol|dt|dd|figure|figcaption)\b[^>]*>")
+        .replace_all(&html, "\n\n");
+    let text = decode_entities(&regex!(r"<[^>]*>").replace_all(&html, ""));
+
+    let mut cleaned = String::new();
+    let mut blank_run = 0;
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            blank_run += 1;
+            continue;
+        }
+        if !cleaned.is_empty() {
+            cleaned.push_str(if blank_run > 0 { "\n\n" } else { "\n" });
+        }
+        cleaned.push_str(&line);
+        blank_run = 0;
+    }
+    cleaned
+}
+
+fn decode_entities(text: &str) -> String {
+    regex!(r"&(#[xX][0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);")
+        .replace_all(text, |c: &regex::Captures| {
+            let entity = &c[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "minus" => Some('−'),
+                "times" => Some('×'),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                "laquo" => Some('«'),
+                "raquo" => Some('»'),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            decoded.map_or_else(|| c[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// A two-chapter EPUB with an illustration in the second chapter
+#[cfg(test)]
+pub(crate) fn write_sample_epub(path: &Path) {
+    use std::io::Write;
+
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(4, 3, image::Rgb([200, 30, 30]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let files: [(&str, &[u8]); 5] = [
+        ("mimetype", b"application/epub+zip"),
+        (
+            "META-INF/container.xml",
+            br#"<?xml version="1.0"?><container><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+        ),
+        (
+            "OEBPS/content.opf",
+            r#"<package><metadata><dc:title>Алгебра 7</dc:title><dc:creator>Макарычев</dc:creator></metadata>
+<manifest><item id="c1" href="text/ch%201.xhtml" media-type="application/xhtml+xml"/><item href="text/ch2.xhtml" id="c2" media-type="application/xhtml+xml"/><item id="img" href="images/fig.png" media-type="image/png"/></manifest>
+<spine><itemref idref="c1"/><itemref idref="c2"/></spine></package>"#
+                .as_bytes(),
+        ),
+        (
+            "OEBPS/text/ch 1.xhtml",
+            r#"<html><head><title>Глава 1</title><style>p{}</style></head><body><h1>Глава 1</h1>
+<p>171. Решите уравнение <math alttext="x + 5 = 12"><mi>x</mi></math>&nbsp;&amp; проверьте.</p><p>а) 7;<br/>б) &#8722;3</p></body></html>"#
+                .as_bytes(),
+        ),
+        ("OEBPS/text/ch2.xhtml", br#"<html><body><p>172. Figure:</p><img src="../images/fig.png" alt="fig"/></body></html>"#),
+    ];
+
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    for (name, content) in files {
+        zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(content).unwrap();
+    }
+    zip.start_file("OEBPS/images/fig.png", zip::write::SimpleFileOptions::default()).unwrap();
+    zip.write_all(&png).unwrap();
+    zip.finish().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_spine_chapters_as_pages() {
+        let dir = std::env::temp_dir().join(format!("booker-epub-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("algebra-7.epub");
+        write_sample_epub(&path);
+
+        let mut book = EpubBook::open(&path).unwrap();
+        assert_eq!(book.page_count(), 2);
+        let fields = book.info_fields();
+        assert_eq!(fields["Title"], "Алгебра 7");
+        assert_eq!(fields["Pages"], "2");
+        assert_eq!(
+            book.chapter_text(1).unwrap(),
+            "Глава 1\n\n171. Решите уравнение $x + 5 = 12$ & проверьте.\n\nа) 7;\nб) −3"
+        );
+        assert_eq!(book.chapter_images(2).unwrap(), vec!["OEBPS/images/fig.png".to_string()]);
+        assert!(book.chapter_text(3).is_err());
+        assert!(read_entry(&mut book.archive, "OEBPS/text/ch2.xhtml", 20).unwrap_err().contains("larger than 20"));
+
+        let preview = dir.join("preview.png");
+        render_chapter_preview(&path, 2, &preview).unwrap();
+        assert_eq!(image::open(&preview).unwrap().width(), 4);
+        assert!(render_chapter_preview(&path, 1, &preview).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_relative_hrefs() {
+        assert_eq!(resolve_href("OEBPS/text/ch1.xhtml", "../images/a%20b.png#x"), "OEBPS/images/a b.png");
+        assert_eq!(resolve_href("content.opf", "ch1.xhtml"), "ch1.xhtml");
+        assert!(is_epub("Algebra.EPUB"));
+        assert!(!is_epub("algebra.pdf"));
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Characters of page text kept on each side of the selection in the prompt
+const CONTEXT_RADIUS: usize = 1500;
+
+/// Longest selection that can be explained
+pub const MAX_SELECTION_CHARS: usize = 2000;
+
+/// Cached explanation of a passage selected in the reader
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    /// Selection hash, see [`selection_key`]
+    pub id: String,
+    pub book_id: String,
+    pub page_number: u32,
+    pub selected_text: String,
+    pub provider: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Whitespace-collapsed selection, so re-selecting the same passage with a
+/// slightly different span of spaces or line breaks hits the cache
+pub fn normalize_selection(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cache key of a selection on a page
+pub fn selection_key(book_id: &str, page_number: u32, selection: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(book_id.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(page_number.to_string().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(normalize_selection(selection).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Page text around the selection for the prompt. The whole page when it is
+/// short or the selection can't be found in the OCR text.
+pub fn page_context(page_text: &str, selection: &str) -> String {
+    let chars: Vec<char> = page_text.chars().collect();
+    if chars.len() <= 2 * CONTEXT_RADIUS {
+        return page_text.to_string();
+    }
+
+    let needle: String = normalize_selection(selection).chars().take(60).collect();
+    let found = page_text
+        .find(&needle)
+        .map(|byte_idx| page_text[..byte_idx].chars().count());
+
+    let (start, end) = match found {
+        Some(at) => (
+            at.saturating_sub(CONTEXT_RADIUS),
+            (at + selection.chars().count() + CONTEXT_RADIUS).min(chars.len()),
+        ),
+        None => (0, 2 * CONTEXT_RADIUS),
+    };
+    chars[start..end].iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ignores_whitespace_differences() {
+        let a = selection_key("algebra", 12, "Теорема  Виета:\n$x_1 + x_2 = -p$");
+        let b = selection_key("algebra", 12, " Теорема Виета: $x_1 + x_2 = -p$ ");
+        assert_eq!(a, b);
+        assert_ne!(a, selection_key("algebra", 13, "Теорема Виета: $x_1 + x_2 = -p$"));
+    }
+
+    #[test]
+    fn context_is_centered_on_selection() {
+        let filler = "а".repeat(3000);
+        let page = format!("{}Теорема Виета{}", filler, filler);
+        let context = page_context(&page, "Теорема Виета");
+        assert!(context.contains("Теорема Виета"));
+        assert_eq!(context.chars().count(), 2 * CONTEXT_RADIUS + "Теорема Виета".chars().count());
+        assert_eq!(page_context("short page", "missing"), "short page");
+    }
+}
@@ -1,6 +1,21 @@
-use crate::models::{Book, Chapter, Problem};
+use crate::config::Config;
+use crate::models::{Book, Chapter, Problem, ReviewStatus, Solution, SolutionFilter};
+use crate::services::anki_export::{build_apkg, card_html, escape_html, AnkiMedia, AnkiNote};
+use crate::services::attachments::AttachmentStorage;
 use crate::services::database::Database;
+use crate::services::export_diff::SnapshotProblem;
+use crate::services::figure::ProblemFigure;
+use crate::services::formula_fallback::formula_image_path;
+use crate::services::glossary::{build_glossary, GlossaryEntry};
+use crate::services::graph_render::{GraphFormat, GraphRenderService};
+use crate::services::latex_pdf::compile_pdf;
+use crate::services::plot::plot_request;
 use anyhow::Result;
+use base64::Engine;
+use lazy_regex::regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
 
 /// Export formats
 #[derive(Debug, Clone, Copy)]
@@ -9,48 +24,330 @@ pub enum ExportFormat {
     Latex,
     Json,
     Anki,
+    /// LaTeX beamer slide deck
+    Beamer,
+    /// Moodle XML question bank
+    Moodle,
+    /// IMS QTI 2.1 content package (zip)
+    Qti,
+    /// The LaTeX export with solutions, typeset
+    Pdf,
 }
 
 impl ExportFormat {
+    /// Parse a format name as accepted by the export endpoints
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "markdown" | "md" => Some(ExportFormat::Markdown),
+            "latex" | "tex" => Some(ExportFormat::Latex),
+            "json" => Some(ExportFormat::Json),
+            "anki" => Some(ExportFormat::Anki),
+            "beamer" | "slides" => Some(ExportFormat::Beamer),
+            "moodle" | "moodle_xml" => Some(ExportFormat::Moodle),
+            "qti" | "qti21" => Some(ExportFormat::Qti),
+            "pdf" => Some(ExportFormat::Pdf),
+            _ => None,
+        }
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             ExportFormat::Markdown => "md",
             ExportFormat::Latex => "tex",
             ExportFormat::Json => "json",
             ExportFormat::Anki => "apkg",
+            ExportFormat::Beamer => "tex",
+            ExportFormat::Moodle => "xml",
+            ExportFormat::Qti => "zip",
+            ExportFormat::Pdf => "pdf",
         }
     }
     
+    /// Written chapter by chapter, so the book can be streamed as it's
+    /// exported; a zip or PDF is only complete once it's finished
+    pub fn streams(&self) -> bool {
+        !matches!(self, ExportFormat::Qti | ExportFormat::Anki | ExportFormat::Pdf)
+    }
+
     pub fn mime_type(&self) -> &'static str {
         match self {
             ExportFormat::Markdown => "text/markdown",
             ExportFormat::Latex => "application/x-latex",
             ExportFormat::Json => "application/json",
-            ExportFormat::Anki => "application/octet-stream",
+            ExportFormat::Anki => "application/apkg",
+            ExportFormat::Beamer => "application/x-latex",
+            ExportFormat::Moodle => "application/xml",
+            ExportFormat::Qti => "application/zip",
+            ExportFormat::Pdf => "application/pdf",
+        }
+    }
+}
+
+/// Size of the chunks a streamed export is sent in
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// Chunks of a streamed export; an `Err` ends the stream after a failure
+pub type ExportChunk = std::result::Result<Vec<u8>, String>;
+
+/// Where an export is written: collected in memory, or sent on in chunks
+/// as it is produced so a big book never sits in memory whole
+struct ExportWriter {
+    buf: String,
+    tx: Option<mpsc::Sender<ExportChunk>>,
+}
+
+impl ExportWriter {
+    fn collect() -> Self {
+        Self { buf: String::new(), tx: None }
+    }
+
+    fn streaming(tx: mpsc::Sender<ExportChunk>) -> Self {
+        Self { buf: String::with_capacity(STREAM_CHUNK), tx: Some(tx) }
+    }
+
+    async fn push(&mut self, text: &str) -> Result<()> {
+        self.buf.push_str(text);
+        if self.buf.len() >= STREAM_CHUNK {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if let Some(tx) = &self.tx
+            && !self.buf.is_empty()
+        {
+            let chunk = std::mem::replace(&mut self.buf, String::with_capacity(STREAM_CHUNK));
+            tx.send(Ok(chunk.into_bytes()))
+                .await
+                .map_err(|_| anyhow::anyhow!("Export receiver went away"))?;
         }
+        Ok(())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buf.into_bytes()
     }
 }
 
 /// Exporter service
 pub struct Exporter {
     db: Database,
+    approved_only: bool,
+    solutions: SolutionFilter,
+    include_glossary: bool,
+    attachments: Option<AttachmentStorage>,
+    graphs: Option<GraphRenderService>,
+    /// Where formula crops are stored; they stay links when unset
+    preview_dir: Option<PathBuf>,
+    /// Prefix of formula crop URLs in Moodle and QTI exports
+    base_url: String,
 }
 
 impl Exporter {
     pub fn new(db: Database) -> Self {
-        Self { db }
+        Self {
+            db,
+            approved_only: false,
+            solutions: SolutionFilter::Any,
+            include_glossary: false,
+            attachments: None,
+            graphs: None,
+            preview_dir: None,
+            base_url: String::new(),
+        }
+    }
+
+    /// Embed formula crops from the server's preview directory and link them
+    /// from its base URL
+    pub fn config(mut self, config: &Config) -> Self {
+        self.preview_dir = Some(config.preview_dir.clone());
+        self.base_url = config.base_url.clone();
+        self
+    }
+
+    /// Restrict exported solutions to a provider or to verified ones
+    pub fn solutions(mut self, filter: SolutionFilter) -> Self {
+        self.solutions = filter;
+        self
+    }
+
+    /// Best solution of each problem under the solution filter, in one query
+    async fn solutions_for(&self, problems: &[Problem]) -> Result<HashMap<String, Solution>> {
+        let ids: Vec<String> = problems.iter().map(|p| p.id.clone()).collect();
+        self.db.get_filtered_solutions(&ids, &self.solutions).await
+    }
+
+    /// Only export problems approved in review (sub-problems follow their parent)
+    pub fn approved_only(mut self, approved_only: bool) -> Self {
+        self.approved_only = approved_only;
+        self
+    }
+
+    /// Append the book's glossary to Markdown, LaTeX and JSON book exports
+    pub fn glossary(mut self, include: bool) -> Self {
+        self.include_glossary = include;
+        self
+    }
+
+    /// Embed images attached to solutions (plots, drawings) in Markdown and
+    /// Anki exports
+    pub fn attachments(mut self, storage: AttachmentStorage) -> Self {
+        self.attachments = Some(storage);
+        self
+    }
+
+    /// Draw graphs for "постройте график" problems whose solution has no
+    /// image attached, in Markdown exports
+    pub fn graphs(mut self, graphs: GraphRenderService) -> Self {
+        self.graphs = Some(graphs);
+        self
+    }
+
+    /// Rendered graph of a problem that asks for one, as a Markdown image
+    async fn problem_graph(&self, problem: &Problem) -> Option<String> {
+        let graphs = self.graphs.as_ref()?;
+        let spec = plot_request(problem)?;
+        match graphs.render(&spec, GraphFormat::Svg).await {
+            Ok(graph) => Some(format!(
+                "![График](data:{};base64,{})",
+                graph.format.mime_type(),
+                base64::engine::general_purpose::STANDARD.encode(graph.bytes.as_slice())
+            )),
+            Err(e) => {
+                log::warn!("Skipping graph of {} in export: {}", problem.id, e);
+                None
+            }
+        }
+    }
+
+    /// Drawing stored for a problem, e.g. one reconstructed from its text
+    async fn problem_figure(&self, problem: &Problem) -> Option<ProblemFigure> {
+        match self.db.get_problem_figure(&problem.id).await {
+            Ok(figure) => figure,
+            Err(e) => {
+                log::warn!("Skipping figure of {} in export: {}", problem.id, e);
+                None
+            }
+        }
+    }
+
+    /// Markdown images of the attachments of each solution, keyed by
+    /// solution id, as data URIs so the export stays one file
+    async fn solution_figures(&self, solutions: &HashMap<String, Solution>) -> Result<HashMap<String, Vec<String>>> {
+        let mut figures: HashMap<String, Vec<String>> = HashMap::new();
+        let Some(storage) = &self.attachments else {
+            return Ok(figures);
+        };
+        let ids: Vec<String> = solutions.values().map(|s| s.id.clone()).collect();
+        for attachment in self.db.get_attachments_for_solutions(&ids).await? {
+            if !attachment.content_type.starts_with("image/") {
+                continue;
+            }
+            let bytes = match storage.get(&attachment.storage_key).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    log::warn!("Skipping attachment {} in export: {}", attachment.id, e);
+                    continue;
+                }
+            };
+            figures.entry(attachment.solution_id).or_default().push(format!(
+                "![{}](data:{};base64,{})",
+                attachment.file_name,
+                attachment.content_type,
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            ));
+        }
+        Ok(figures)
+    }
+
+    async fn glossary_entries(&self, book: &Book) -> Result<Vec<GlossaryEntry>> {
+        if self.include_glossary {
+            build_glossary(&self.db, &book.id).await
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Top-level problems of a chapter with their sub-problems, filtered by
+    /// review state when `approved_only` is set
+    async fn chapter_problems(&self, chapter_id: &str) -> Result<Vec<Problem>> {
+        let problems = self.db.get_problem_tree_by_chapter(chapter_id).await?;
+        if !self.approved_only {
+            return Ok(problems);
+        }
+        Ok(problems.into_iter().filter(|p| p.review_status == ReviewStatus::Approved).collect())
     }
     
+    /// Best solutions of the problems when they go into a LaTeX export
+    async fn latex_solutions(&self, problems: &[Problem], with_solutions: bool) -> Result<HashMap<String, Solution>> {
+        if with_solutions {
+            self.solutions_for(problems).await
+        } else {
+            Ok(HashMap::new())
+        }
+    }
+
     /// Export entire book
     pub async fn export_book(&self, book_id: &str, format: ExportFormat) -> Result<Vec<u8>> {
         let book = self.db.get_book(book_id).await?
             .ok_or_else(|| anyhow::anyhow!("Book not found"))?;
         
+        if format.streams() {
+            let mut out = ExportWriter::collect();
+            self.write_book(&book, format, &mut out).await?;
+            return Ok(out.into_bytes());
+        }
+
+        if let ExportFormat::Pdf = format {
+            let mut out = ExportWriter::collect();
+            self.write_latex(&book, true, &mut out).await?;
+            return compile_pdf(&String::from_utf8(out.into_bytes())?, self.preview_dir.as_deref()).await;
+        }
+        let chapters = self.db.get_chapters_by_book(&book.id).await?;
+        if let ExportFormat::Anki = format {
+            return self.export_anki(&book, &chapters).await;
+        }
+        let problems = self.collect_problems(&chapters).await?;
+        export_qti_package(&book.title, &problems, &self.base_url)
+    }
+
+    /// Export a book in the background, sending the output in chunks as each
+    /// chapter is written. Formats that can't be written incrementally (see
+    /// [`ExportFormat::streams`]) arrive as one chunk.
+    pub fn stream_book(self, book: Book, format: ExportFormat) -> mpsc::Receiver<ExportChunk> {
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut out = ExportWriter::streaming(tx.clone());
+            let result = if format.streams() {
+                self.write_book(&book, format, &mut out).await
+            } else {
+                match self.export_book(&book.id, format).await {
+                    Ok(data) => tx.send(Ok(data)).await.map_err(|_| anyhow::anyhow!("Export receiver went away")),
+                    Err(e) => Err(e),
+                }
+            };
+            if let Err(e) = result.and(out.flush().await) {
+                log::error!("Export of {} failed: {}", book.id, e);
+                let _ = tx.send(Err(e.to_string())).await;
+            }
+        });
+        rx
+    }
+
+    async fn write_book(&self, book: &Book, format: ExportFormat, out: &mut ExportWriter) -> Result<()> {
         match format {
-            ExportFormat::Markdown => self.export_markdown(&book).await,
-            ExportFormat::Latex => self.export_latex(&book).await,
-            ExportFormat::Json => self.export_json(&book).await,
-            ExportFormat::Anki => self.export_anki(&book).await,
+            ExportFormat::Markdown => self.write_markdown(book, out).await,
+            ExportFormat::Latex => self.write_latex(book, false, out).await,
+            ExportFormat::Json => self.write_json(book, out).await,
+            ExportFormat::Beamer => self.write_beamer(book, out).await,
+            ExportFormat::Moodle => {
+                let chapters = self.db.get_chapters_by_book(&book.id).await?;
+                self.write_moodle(book, &chapters, out).await
+            }
+            ExportFormat::Qti | ExportFormat::Anki | ExportFormat::Pdf => {
+                Err(anyhow::anyhow!("Packages and PDFs are not written incrementally"))
+            }
         }
     }
     
@@ -64,185 +361,195 @@ impl Exporter {
         
         match format {
             ExportFormat::Markdown => self.export_chapter_markdown(&book, &chapter).await,
-            ExportFormat::Latex => self.export_chapter_latex(&book, &chapter).await,
+            ExportFormat::Latex => self.export_chapter_latex(&book, &chapter, false).await,
             ExportFormat::Json => self.export_chapter_json(&book, &chapter).await,
-            ExportFormat::Anki => self.export_chapter_anki(&book, &chapter).await,
+            ExportFormat::Anki => self.export_anki(&book, std::slice::from_ref(&chapter)).await,
+            ExportFormat::Beamer => self.export_chapter_beamer(&book, &chapter, None).await,
+            ExportFormat::Moodle => {
+                let mut out = ExportWriter::collect();
+                self.write_moodle(&book, std::slice::from_ref(&chapter), &mut out).await?;
+                Ok(out.into_bytes())
+            }
+            ExportFormat::Qti => {
+                let problems = self.collect_problems(std::slice::from_ref(&chapter)).await?;
+                export_qti_package(&format!("{} - Глава {}", book.title, chapter.number), &problems, &self.base_url)
+            }
+            ExportFormat::Pdf => {
+                let tex = self.export_chapter_latex(&book, &chapter, true).await?;
+                compile_pdf(&String::from_utf8(tex)?, self.preview_dir.as_deref()).await
+            }
         }
     }
+
+    /// Top-level problems of the given chapters paired with their best solution text
+    async fn collect_problems(&self, chapters: &[Chapter]) -> Result<Vec<(Problem, Option<String>)>> {
+        let mut problems = Vec::new();
+        for chapter in chapters {
+            let chapter_problems = self.chapter_problems(&chapter.id).await?;
+            let mut solutions = self.solutions_for(&chapter_problems).await?;
+            for problem in chapter_problems {
+                let solution = solutions.remove(&problem.id).map(|s| s.content);
+                problems.push((problem, solution));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Hashes of what an export of the book (or one of its chapters) with
+    /// these filters contains, for [`crate::services::export_diff`]
+    pub async fn snapshot(&self, book_id: &str, chapter_id: Option<&str>) -> Result<Vec<SnapshotProblem>> {
+        let chapters: Vec<Chapter> = match chapter_id {
+            Some(chapter_id) => self.db.get_chapter(chapter_id).await?.into_iter().collect(),
+            None => self.db.get_chapters_by_book(book_id).await?,
+        };
+        Ok(self
+            .collect_problems(&chapters)
+            .await?
+            .iter()
+            .map(|(problem, solution)| SnapshotProblem::new(problem, solution.as_deref()))
+            .collect())
+    }
+
+    /// Export a chapter as beamer slides, limited to the given problem numbers
+    pub async fn export_chapter_slides(&self, chapter_id: &str, problem_numbers: &[String]) -> Result<Vec<u8>> {
+        let chapter = self.db.get_chapter(chapter_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Chapter not found"))?;
+
+        let book = self.db.get_book(&chapter.book_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Book not found"))?;
+
+        self.export_chapter_beamer(&book, &chapter, Some(problem_numbers)).await
+    }
     
-    async fn export_markdown(&self, book: &Book) -> Result<Vec<u8>> {
-        let mut output = String::new();
-        
+    async fn write_markdown(&self, book: &Book, out: &mut ExportWriter) -> Result<()> {
         // Title
-        output.push_str(&format!("# {}\n\n", book.title));
+        out.push(&format!("# {}\n\n", book.title)).await?;
         
         if let Some(author) = &book.author {
-            output.push_str(&format!("**Автор:** {}\n\n", author));
+            out.push(&format!("**Автор:** {}\n\n", author)).await?;
         }
         
         // Get all chapters
         let chapters = self.db.get_chapters_by_book(&book.id).await?;
         
         for chapter in chapters {
-            output.push_str(&self.export_chapter_markdown_content(&chapter).await?);
+            self.write_chapter_markdown_content(&chapter, out).await?;
+        }
+
+        let glossary = self.glossary_entries(book).await?;
+        if !glossary.is_empty() {
+            out.push("## Глоссарий\n\n").await?;
+            for entry in &glossary {
+                out.push(&format!("**{}** — {}\n\n", entry.term, entry.definition)).await?;
+            }
         }
         
-        Ok(output.into_bytes())
+        Ok(())
     }
     
     async fn export_chapter_markdown(&self, book: &Book, chapter: &Chapter) -> Result<Vec<u8>> {
-        let mut output = String::new();
+        let mut out = ExportWriter::collect();
         
-        output.push_str(&format!("# {}\n\n", book.title));
-        output.push_str(&format!("## Глава {}: {}\n\n", chapter.number, chapter.title));
+        out.push(&format!("# {}\n\n", book.title)).await?;
+        out.push(&format!("## Глава {}: {}\n\n", chapter.number, chapter.title)).await?;
         
-        output.push_str(&self.export_chapter_markdown_content(chapter).await?);
+        self.write_chapter_markdown_content(chapter, &mut out).await?;
         
-        Ok(output.into_bytes())
+        Ok(out.into_bytes())
     }
     
-    async fn export_chapter_markdown_content(&self, chapter: &Chapter) -> Result<String> {
-        let mut output = String::new();
-        
-        output.push_str(&format!("### Глава {}: {}\n\n", chapter.number, chapter.title));
-        
-        // Get problems
-        let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
+    async fn write_chapter_markdown_content(&self, chapter: &Chapter, out: &mut ExportWriter) -> Result<()> {
+        out.push(&format!("### Глава {}: {}\n\n", chapter.number, chapter.title)).await?;
         
-        for problem in problems {
-            // Skip sub-problems (they'll be included with parent)
-            if problem.parent_id.is_some() {
-                continue;
-            }
-            
-            output.push_str(&self.format_problem_markdown(&problem).await?);
-        }
-        
-        Ok(output)
-    }
-    
-    async fn format_problem_markdown(&self, problem: &Problem) -> Result<String> {
-        let mut output = String::new();
-        
-        // Problem header
-        output.push_str(&format!("#### Задача {}\n\n", problem.number));
-        
-        // Content with preserved LaTeX
-        output.push_str(&problem.content);
-        output.push_str("\n\n");
+        // Get problems and their solutions
+        let problems = self.chapter_problems(&chapter.id).await?;
+        let solutions = self.solutions_for(&problems).await?;
+        let figures = self.solution_figures(&solutions).await?;
         
-        // Sub-problems
-        if let Some(subs) = &problem.sub_problems {
-            for sub in subs {
-                output.push_str(&format!("**{}).** {}\n\n", sub.number, sub.content));
+        for problem in &problems {
+            let solution = solutions.get(&problem.id).filter(|_| problem.has_solution);
+            let mut problem_figures = solution.and_then(|s| figures.get(&s.id)).cloned().unwrap_or_default();
+            if let Some(figure) = self.problem_figure(problem).await {
+                problem_figures.insert(0, format!(
+                    "![{}](data:image/svg+xml;base64,{})",
+                    if figure.reconstructed { "Чертёж (восстановлен по условию)" } else { "Чертёж" },
+                    base64::engine::general_purpose::STANDARD.encode(figure.description.to_svg())
+                ));
             }
-        }
-        
-        // Solution if exists
-        if problem.has_solution {
-            if let Some(solution) = self.db.get_solution_for_problem(&problem.id).await? {
-                output.push_str("**Решение:**\n\n");
-                output.push_str(&solution.content);
-                output.push_str("\n\n");
+            if problem_figures.is_empty()
+                && let Some(graph) = self.problem_graph(problem).await
+            {
+                problem_figures.push(graph);
             }
+            out.push(&format_problem_markdown(problem, solution, &problem_figures)).await?;
         }
         
-        output.push_str("---\n\n");
-        
-        Ok(output)
+        Ok(())
     }
     
-    async fn export_latex(&self, book: &Book) -> Result<Vec<u8>> {
-        let mut output = String::new();
-        
-        // LaTeX preamble
-        output.push_str(r"\documentclass{article}
-\usepackage[utf8]{inputenc}
-\usepackage[russian]{babel}
-\usepackage{amsmath,amssymb,amsthm}
-\usepackage{geometry}
-\geometry{a4paper,margin=2cm}
-
-\title{");
-        output.push_str(&book.title);
-        output.push_str(r"}
+    /// LaTeX document of the book; `with_solutions` adds each problem's best
+    /// solution after it, for print
+    async fn write_latex(&self, book: &Book, with_solutions: bool, out: &mut ExportWriter) -> Result<()> {
+        out.push(LATEX_PREAMBLE).await?;
+        out.push(&self.db.get_book_macros(&book.id).await?.latex_preamble()).await?;
+        out.push(r"
+\title{").await?;
+        out.push(&escape_latex_text(&book.title)).await?;
+        out.push(r"}
 \date{\today}
 
 \begin{document}
 \maketitle
 
-");
+").await?;
         
         // Chapters
         let chapters = self.db.get_chapters_by_book(&book.id).await?;
         
         for chapter in chapters {
-            output.push_str(&format!("\\section*{{Глава {}: {}}}\n\n", chapter.number, chapter.title));
-            
-            let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
+            out.push(&format!("\\section*{{Глава {}: {}}}\n\n", chapter.number, escape_latex_text(&chapter.title))).await?;
             
+            let problems = self.chapter_problems(&chapter.id).await?;
+            let solutions = self.latex_solutions(&problems, with_solutions).await?;
             for problem in problems {
-                if problem.parent_id.is_some() {
-                    continue;
-                }
-                
-                output.push_str(&self.format_problem_latex(&problem).await?);
+                let figure = self.problem_figure(&problem).await;
+                out.push(&format_problem_latex(&problem, figure.as_ref(), solutions.get(&problem.id), self.preview_dir.as_deref())).await?;
             }
         }
         
-        output.push_str(r"\end{document}");
-        
-        Ok(output.into_bytes())
-    }
-    
-    async fn format_problem_latex(&self, problem: &Problem) -> Result<String> {
-        let mut output = String::new();
-        
-        output.push_str(&format!("\\textbf{{Задача {}.}} ", problem.number));
-        
-        // Convert markdown LaTeX to LaTeX
-        let content = problem.content
-            .replace("$", "$")  // Keep inline math
-            .replace("$$", r"\[",)  // Display math opening
-            .replace("$$", r"\]",); // Display math closing
-        
-        output.push_str(&content);
-        output.push_str("\n\n");
-        
-        // Sub-problems
-        if let Some(subs) = &problem.sub_problems {
-            output.push_str(r"\begin{enumerate}[label=\alph*)]");
-            for sub in subs {
-                output.push_str(&format!("\\item {}\n", sub.content));
+        let glossary = self.glossary_entries(book).await?;
+        if !glossary.is_empty() {
+            out.push("\\section*{Глоссарий}\n\n\\begin{description}\n").await?;
+            for entry in &glossary {
+                out.push(&format!("\\item[{}] {}\n", entry.term, entry.definition)).await?;
             }
-            output.push_str(r"\end{enumerate}");
-            output.push_str("\n\n");
+            out.push("\\end{description}\n\n").await?;
         }
         
-        Ok(output)
+        out.push(r"\end{document}").await
     }
     
-    async fn export_json(&self, book: &Book) -> Result<Vec<u8>> {
+    async fn write_json(&self, book: &Book, out: &mut ExportWriter) -> Result<()> {
         let chapters = self.db.get_chapters_by_book(&book.id).await?;
         
-        let mut export_data = serde_json::Map::new();
-        export_data.insert("book".to_string(), serde_json::json!({
+        // Written piece by piece; the result is the same object as before:
+        // {"book": ..., "chapters": [...], "glossary": [...]}
+        let book_data = serde_json::json!({
             "id": book.id,
             "title": book.title,
             "author": book.author,
             "subject": book.subject,
-        }));
-        
-        let mut chapters_data = Vec::new();
+        });
+        out.push(&format!("{{\n\"book\": {},\n\"chapters\": [", serde_json::to_string_pretty(&book_data)?)).await?;
         
-        for chapter in chapters {
-            let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
+        for (i, chapter) in chapters.iter().enumerate() {
+            let problems = self.chapter_problems(&chapter.id).await?;
             
-            chapters_data.push(serde_json::json!({
+            let chapter_data = serde_json::json!({
                 "id": chapter.id,
                 "number": chapter.number,
                 "title": chapter.title,
-                "problems": problems.iter().filter(|p| p.parent_id.is_none()).map(|p| {
+                "problems": problems.iter().map(|p| {
                     serde_json::json!({
                         "id": p.id,
                         "number": p.number,
@@ -252,83 +559,32 @@ impl Exporter {
                         "has_solution": p.has_solution,
                     })
                 }).collect::<Vec<_>>(),
-            }));
+            });
+            let separator = if i == 0 { "\n" } else { ",\n" };
+            out.push(separator).await?;
+            out.push(&serde_json::to_string_pretty(&chapter_data)?).await?;
         }
-        
-        export_data.insert("chapters".to_string(), serde_json::Value::Array(chapters_data));
-        
-        let json = serde_json::to_string_pretty(&export_data)?;
-        Ok(json.into_bytes())
-    }
-    
-    async fn export_anki(&self, book: &Book) -> Result<Vec<u8>> {
-        // For Anki, we generate a CSV-like format that can be imported
-        // Real .apkg generation would require additional dependencies
-        
-        let mut output = String::new();
-        
-        // Header
-        output.push_str("#separator:tab\n");
-        output.push_str("#html:true\n");
-        output.push_str("#deck column:1\n");
-        output.push_str("#tags column:4\n\n");
-        
-        let chapters = self.db.get_chapters_by_book(&book.id).await?;
-        
-        for chapter in chapters {
-            let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
-            
-            for problem in problems {
-                if problem.parent_id.is_some() {
-                    continue;
-                }
-                
-                // Front (question)
-                let front = format!("{} - Задача {}", book.title, problem.number);
-                let front_html = format!("<b>{}</b><br><br>{}", 
-                    front, 
-                    problem.content.replace("$", "&#36;")
-                );
-                
-                // Back (solution or hint)
-                let back_html = if let Some(solution) = self.db.get_solution_for_problem(&problem.id).await? {
-                    solution.content.replace("$", "&#36;")
-                } else {
-                    "(Решение не добавлено)".to_string()
-                };
-                
-                // Tags
-                let tags = format!("{}::chapter_{}", book.id.replace("-", "_"), chapter.number);
-                
-                output.push_str(&format!("{}\t{}\t{}\t{}\n", 
-                    format!("{}::Глава {}", book.title, chapter.number),
-                    front_html,
-                    back_html,
-                    tags
-                ));
-            }
+        out.push("\n]").await?;
+
+        let glossary = self.glossary_entries(book).await?;
+        if !glossary.is_empty() {
+            out.push(&format!(",\n\"glossary\": {}", serde_json::to_string_pretty(&glossary)?)).await?;
         }
         
-        Ok(output.into_bytes())
+        out.push("\n}\n").await
     }
     
     // Chapter-specific exports
-    async fn export_chapter_latex(&self, book: &Book, chapter: &Chapter) -> Result<Vec<u8>> {
-        let mut output = String::new();
-        
-        output.push_str(r"\documentclass{article}
-\usepackage[utf8]{inputenc}
-\usepackage[russian]{babel}
-\usepackage{amsmath,amssymb,amsthm}
-\usepackage{geometry}
-\geometry{a4paper,margin=2cm}
-
+    async fn export_chapter_latex(&self, book: &Book, chapter: &Chapter, with_solutions: bool) -> Result<Vec<u8>> {
+        let mut output = String::from(LATEX_PREAMBLE);
+        output.push_str(&self.db.get_book_macros(&book.id).await?.latex_preamble());
+        output.push_str(r"
 \title{");
-        output.push_str(&format!("{} - Глава {}", book.title, chapter.number));
+        output.push_str(&format!("{} - Глава {}", escape_latex_text(&book.title), chapter.number));
         output.push_str(r"}
 \author{");
         if let Some(author) = &book.author {
-            output.push_str(author);
+            output.push_str(&escape_latex_text(author));
         }
         output.push_str(r"}
 \date{\today}
@@ -338,15 +594,13 @@ impl Exporter {
 
 ");
         
-        output.push_str(&format!("\\section*{{{}}}\n\n", chapter.title));
-        
-        let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
+        output.push_str(&format!("\\section*{{{}}}\n\n", escape_latex_text(&chapter.title)));
         
+        let problems = self.chapter_problems(&chapter.id).await?;
+        let solutions = self.latex_solutions(&problems, with_solutions).await?;
         for problem in problems {
-            if problem.parent_id.is_some() {
-                continue;
-            }
-            output.push_str(&self.format_problem_latex(&problem).await?);
+            let figure = self.problem_figure(&problem).await;
+            output.push_str(&format_problem_latex(&problem, figure.as_ref(), solutions.get(&problem.id), self.preview_dir.as_deref()));
         }
         
         output.push_str(r"\end{document}");
@@ -355,7 +609,7 @@ impl Exporter {
     }
     
     async fn export_chapter_json(&self, _book: &Book, chapter: &Chapter) -> Result<Vec<u8>> {
-        let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
+        let problems = self.chapter_problems(&chapter.id).await?;
         
         let export_data = serde_json::json!({
             "chapter": {
@@ -363,7 +617,7 @@ impl Exporter {
                 "number": chapter.number,
                 "title": chapter.title,
             },
-            "problems": problems.iter().filter(|p| p.parent_id.is_none()).map(|p| {
+            "problems": problems.iter().map(|p| {
                 serde_json::json!({
                     "id": p.id,
                     "number": p.number,
@@ -379,44 +633,591 @@ impl Exporter {
         Ok(json.into_bytes())
     }
     
-    async fn export_chapter_anki(&self, book: &Book, chapter: &Chapter) -> Result<Vec<u8>> {
-        let mut output = String::new();
-        
-        output.push_str("#separator:tab\n");
-        output.push_str("#html:true\n\n");
-        
-        let problems = self.db.get_problems_by_chapter(&chapter.id).await?;
-        
-        for problem in problems {
-            if problem.parent_id.is_some() {
+    /// Anki package of the chapters' problems, one deck per chapter, with
+    /// formula crops and solution images as media
+    async fn export_anki(&self, book: &Book, chapters: &[Chapter]) -> Result<Vec<u8>> {
+        let mut media: Vec<AnkiMedia> = Vec::new();
+        let mut formula_image = |url: &str| {
+            let path = formula_image_path(self.preview_dir.as_deref()?, url)?;
+            let name = format!("formula-{}", path.file_name()?.to_string_lossy());
+            if !media.iter().any(|m| m.name == name) {
+                match std::fs::read(&path) {
+                    Ok(data) => media.push(AnkiMedia { name: name.clone(), data }),
+                    Err(e) => {
+                        log::warn!("Skipping formula image {} in Anki export: {}", path.display(), e);
+                        return None;
+                    }
+                }
+            }
+            Some(name)
+        };
+
+        let mut notes = Vec::new();
+        let mut attachment_media = Vec::new();
+        for chapter in chapters {
+            let problems = self.chapter_problems(&chapter.id).await?;
+            let solutions = self.solutions_for(&problems).await?;
+            let mut images = self.solution_images(&solutions).await?;
+
+            for problem in &problems {
+                let mut question = problem.content.clone();
+                for sub in problem.sub_problems.iter().flatten() {
+                    question.push_str(&format!("\n\n**{})** {}", sub.number, sub.content));
+                }
+                let solution = solutions.get(&problem.id);
+                let mut back = match solution {
+                    Some(solution) => card_html(&solution.content, &mut formula_image),
+                    None => "(Решение не добавлено)".to_string(),
+                };
+                for image in solution.and_then(|s| images.remove(&s.id)).unwrap_or_default() {
+                    back.push_str(&format!("<br>\n<img src=\"{}\">", image.name));
+                    attachment_media.push(image);
+                }
+                notes.push(AnkiNote {
+                    key: problem.id.clone(),
+                    deck: format!("{}::Глава {}", book.title, chapter.number),
+                    front: format!(
+                        "<b>{} - Задача {}</b><br><br>\n{}",
+                        escape_html(&book.title),
+                        escape_html(&problem.number),
+                        card_html(&question, &mut formula_image)
+                    ),
+                    back,
+                    tags: vec![format!("{}::chapter_{}", book.id.replace("-", "_"), chapter.number)],
+                });
+            }
+        }
+        media.extend(attachment_media);
+        build_apkg(&notes, &media).await
+    }
+
+    /// Image attachments of each solution as Anki media, keyed by solution id
+    async fn solution_images(&self, solutions: &HashMap<String, Solution>) -> Result<HashMap<String, Vec<AnkiMedia>>> {
+        let mut images: HashMap<String, Vec<AnkiMedia>> = HashMap::new();
+        let Some(storage) = &self.attachments else {
+            return Ok(images);
+        };
+        let ids: Vec<String> = solutions.values().map(|s| s.id.clone()).collect();
+        for attachment in self.db.get_attachments_for_solutions(&ids).await? {
+            if !attachment.content_type.starts_with("image/") {
                 continue;
             }
-            
-            let front = format!("{} - Задача {}", book.title, problem.number);
-            let front_html = format!("<b>{}</b><br><br>{}", 
-                front, 
-                problem.content.replace("$", "&#36;")
-            );
-            
-            let back_html = if let Some(solution) = self.db.get_solution_for_problem(&problem.id).await? {
-                solution.content.replace("$", "&#36;")
-            } else {
-                "(Решение не добавлено)".to_string()
+            match storage.get(&attachment.storage_key).await {
+                Ok(data) => images.entry(attachment.solution_id).or_default().push(AnkiMedia {
+                    name: format!("{}-{}", attachment.id, attachment.file_name.replace(['/', '\\', '"'], "_")),
+                    data,
+                }),
+                Err(e) => log::warn!("Skipping attachment {} in export: {}", attachment.id, e),
+            }
+        }
+        Ok(images)
+    }
+}
+
+fn format_problem_markdown(problem: &Problem, solution: Option<&Solution>, figures: &[String]) -> String {
+    let mut output = String::new();
+    
+    // Problem header
+    output.push_str(&format!("#### Задача {}\n\n", problem.number));
+    
+    // Content with preserved LaTeX
+    output.push_str(&problem.content);
+    output.push_str("\n\n");
+    
+    // Sub-problems
+    if let Some(subs) = &problem.sub_problems {
+        for sub in subs {
+            output.push_str(&format!("**{}).** {}\n\n", sub.number, sub.content));
+        }
+    }
+    
+    // Solution if exists
+    if let Some(solution) = solution {
+        output.push_str("**Решение:**\n\n");
+        output.push_str(&solution.content);
+        output.push_str("\n\n");
+    }
+
+    for figure in figures {
+        output.push_str(figure);
+        output.push_str("\n\n");
+    }
+    
+    output.push_str("---\n\n");
+    output
+}
+
+const LATEX_PREAMBLE: &str = r"\documentclass{article}
+\usepackage[utf8]{inputenc}
+\usepackage[russian]{babel}
+\usepackage{amsmath,amssymb,amsthm}
+\usepackage{graphicx}
+\usepackage{enumitem}
+\usepackage{geometry}
+\usepackage{tikz}
+\geometry{a4paper,margin=2cm}
+";
+
+fn format_problem_latex(
+    problem: &Problem,
+    figure: Option<&ProblemFigure>,
+    solution: Option<&Solution>,
+    preview_dir: Option<&Path>,
+) -> String {
+    let mut output = String::new();
+    
+    output.push_str(&format!("\\textbf{{Задача {}.}} ", escape_latex_text(&problem.number)));
+    output.push_str(&markdown_to_latex(&problem.content, preview_dir));
+    output.push_str("\n\n");
+    
+    // Sub-problems
+    if let Some(subs) = &problem.sub_problems {
+        output.push_str(r"\begin{enumerate}[label=\alph*)]");
+        for sub in subs {
+            output.push_str(&format!("\\item {}\n", markdown_to_latex(&sub.content, preview_dir)));
+        }
+        output.push_str(r"\end{enumerate}");
+        output.push_str("\n\n");
+    }
+
+    // Reconstructed drawings are marked, since the book's own may differ
+    if let Some(figure) = figure {
+        output.push_str(&figure.description.to_tikz());
+        if figure.reconstructed {
+            output.push_str("\\begin{center}\\small\\textit{Чертёж восстановлен по условию}\\end{center}\n");
+        }
+        output.push('\n');
+    }
+
+    if let Some(solution) = solution {
+        output.push_str("\\paragraph{Решение.} ");
+        output.push_str(&markdown_to_latex(&solution.content, preview_dir));
+        output.push_str("\n\n");
+    }
+    
+    output
+}
+
+// === Beamer slides ===
+
+const BEAMER_PREAMBLE: &str = r"\documentclass{beamer}
+\usepackage[utf8]{inputenc}
+\usepackage[russian]{babel}
+\usepackage{amsmath,amssymb}
+\usepackage{appendixnumberbeamer}
+\usetheme{Madrid}
+";
+
+impl Exporter {
+    async fn write_beamer(&self, book: &Book, out: &mut ExportWriter) -> Result<()> {
+        let macros = self.db.get_book_macros(&book.id).await?;
+        out.push(&beamer_head(&book.title, book.author.as_deref(), &macros.latex_preamble())).await?;
+
+        // Solution slides go after all exercises, so only they are held back
+        let mut appendix = String::new();
+        for chapter in self.db.get_chapters_by_book(&book.id).await? {
+            let (slides, solutions) = self.chapter_slides(&chapter, None).await?;
+            out.push(&slides).await?;
+            appendix.push_str(&solutions);
+        }
+
+        out.push(&beamer_tail(&appendix)).await
+    }
+
+    async fn export_chapter_beamer(
+        &self,
+        book: &Book,
+        chapter: &Chapter,
+        problem_numbers: Option<&[String]>,
+    ) -> Result<Vec<u8>> {
+        let (body, appendix) = self.chapter_slides(chapter, problem_numbers).await?;
+        let title = format!("{} - Глава {}", book.title, chapter.number);
+
+        let macros = self.db.get_book_macros(&book.id).await?;
+        Ok(beamer_document(&title, book.author.as_deref(), &macros.latex_preamble(), &body, &appendix).into_bytes())
+    }
+
+    /// Slides for one chapter: theory frames followed by exercise frames.
+    /// Returns (main slides, appendix solution slides).
+    async fn chapter_slides(
+        &self,
+        chapter: &Chapter,
+        problem_numbers: Option<&[String]>,
+    ) -> Result<(String, String)> {
+        let mut slides = String::new();
+        let mut appendix = String::new();
+
+        slides.push_str(&format!("\\section{{Глава {}: {}}}\n\n", chapter.number, escape_latex_text(&chapter.title)));
+        let preview_dir = self.preview_dir.as_deref();
+
+        for theory in self.db.get_theory_blocks_by_chapter(&chapter.id).await? {
+            let title = theory.title.clone()
+                .unwrap_or_else(|| format!("{:?}", theory.block_type));
+            slides.push_str(&beamer_frame(&escape_latex_text(&title), &markdown_to_latex(&theory.content, preview_dir)));
+        }
+
+        let problems = self.chapter_problems(&chapter.id).await?;
+        let solutions = self.solutions_for(&problems).await?;
+        for problem in &problems {
+            if let Some(numbers) = problem_numbers
+                && !numbers.iter().any(|n| n == &problem.number)
+            {
+                continue;
+            }
+
+            let mut content = markdown_to_latex(&problem.content, preview_dir);
+            if let Some(subs) = &problem.sub_problems {
+                content.push_str("\n\\begin{itemize}\n");
+                for sub in subs {
+                    content.push_str(&format!(
+                        "\\item[{})] {}\n",
+                        escape_latex_text(&sub.number),
+                        markdown_to_latex(&sub.content, preview_dir)
+                    ));
+                }
+                content.push_str("\\end{itemize}\n");
+            }
+
+            let label = format!("sol:{}", problem.id.replace(':', "-"));
+            let has_solution = match solutions.get(&problem.id) {
+                Some(solution) => {
+                    let frame_title = format!("Решение задачи {}", escape_latex_text(&problem.number));
+                    appendix.push_str(&format!(
+                        "\\begin{{frame}}[allowframebreaks,label={}]{{{}}}\n{}\n\\end{{frame}}\n\n",
+                        label,
+                        frame_title,
+                        markdown_to_latex(&solution.content, preview_dir)
+                    ));
+                    true
+                }
+                None => false,
             };
-            
-            let tags = format!("{}::chapter_{}", book.id.replace("-", "_"), chapter.number);
-            
-            output.push_str(&format!("{}\t{}\t{}\n", 
-                front_html,
-                back_html,
-                tags
-            ));
+
+            if has_solution {
+                content.push_str(&format!("\n\\hfill\\hyperlink{{{}}}{{\\beamerbutton{{Решение}}}}\n", label));
+            }
+
+            slides.push_str(&beamer_frame(&format!("Задача {}", escape_latex_text(&problem.number)), &content));
         }
-        
-        Ok(output.into_bytes())
+
+        Ok((slides, appendix))
+    }
+}
+
+fn beamer_document(title: &str, author: Option<&str>, macros: &str, body: &str, appendix: &str) -> String {
+    let mut output = beamer_head(title, author, macros);
+    output.push_str(body);
+    output.push_str(&beamer_tail(appendix));
+    output
+}
+
+/// Preamble and title slide of a deck
+fn beamer_head(title: &str, author: Option<&str>, macros: &str) -> String {
+    let mut output = String::from(BEAMER_PREAMBLE);
+    output.push_str(macros);
+    output.push_str(&format!("\n\\title{{{}}}\n", escape_latex_text(title)));
+    output.push_str(&format!("\\author{{{}}}\n", author.map(escape_latex_text).unwrap_or_default()));
+    output.push_str("\\date{\\today}\n\n\\begin{document}\n\n\\frame{\\titlepage}\n\n");
+    output
+}
+
+/// Solution appendix and end of a deck
+fn beamer_tail(appendix: &str) -> String {
+    let mut output = String::new();
+    if !appendix.is_empty() {
+        output.push_str("\\appendix\n\\section*{Решения}\n\n");
+        output.push_str(appendix);
+    }
+
+    output.push_str("\\end{document}\n");
+    output
+}
+
+fn beamer_frame(title: &str, content: &str) -> String {
+    format!(
+        "\\begin{{frame}}[allowframebreaks]{{{}}}\n{}\n\\end{{frame}}\n\n",
+        title, content
+    )
+}
+
+/// Convert Markdown-style math (`$$...$$` display blocks) into LaTeX `\[...\]`.
+/// Inline `$...$` is already valid LaTeX and kept as-is.
+fn markdown_math_to_latex(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut open = true;
+
+    while let Some(pos) = rest.find("$$") {
+        output.push_str(&rest[..pos]);
+        output.push_str(if open { "\\[" } else { "\\]" });
+        open = !open;
+        rest = &rest[pos + 2..];
+    }
+    output.push_str(rest);
+
+    // Unbalanced $$: close the last display block
+    if !open {
+        output.push_str("\\]");
+    }
+
+    output
+}
+
+/// Markdown text as LaTeX that compiles: headings and `**bold**` become
+/// `\textbf`, `$$` blocks `\[...\]`, formula crops images. Everything
+/// outside math is escaped, backslashes included, so OCR slips (`a_1` in
+/// prose, a stray `$`) and commands in edited text come out as text.
+pub(crate) fn markdown_to_latex(text: &str, preview_dir: Option<&Path>) -> String {
+    // Private-use characters mark bold spans, since the braces get escaped
+    let text = regex!(r"(?m)^#{1,6}[ \t]+(.+)$").replace_all(text, "\u{E000}$1\u{E001}");
+    let text = regex!(r"\*\*([^*\n]+?)\*\*").replace_all(&text, "\u{E000}$1\u{E001}");
+
+    let mut latex = String::with_capacity(text.len());
+    let mut prose_start = 0;
+    let verbatim = regex!(
+        r"\$\$[\s\S]*?\$\$|\$(?:[^$\n]|\n[^$\n])+\$|\\\([\s\S]*?\\\)|\\\[[\s\S]*?\\\]|!\[formula\]\([^)\s]+\)"
+    );
+    for found in verbatim.find_iter(&text) {
+        latex.push_str(&escape_latex_text(&text[prose_start..found.start()]));
+        let span = found.as_str();
+        if let Some(display) = span.strip_prefix("$$").and_then(|s| s.strip_suffix("$$")) {
+            latex.push_str(&format!("\\[{}\\]", display));
+        } else if span.starts_with('!') {
+            match formula_images_to_latex(span, preview_dir) {
+                image if image != span => latex.push_str(&image),
+                _ => latex.push_str(&escape_latex_text(span)),
+            }
+        } else {
+            latex.push_str(span);
+        }
+        prose_start = found.end();
+    }
+    latex.push_str(&escape_latex_text(&text[prose_start..]));
+    latex.replace('\u{E000}', "\\textbf{").replace('\u{E001}', "}")
+}
+
+/// Formula crops (`![formula](/formula_image/...)`) as `\includegraphics` of the local file
+pub(crate) fn formula_images_to_latex(text: &str, preview_dir: Option<&Path>) -> String {
+    let Some(preview_dir) = preview_dir else {
+        return text.to_string();
+    };
+    regex!(r"!\[formula\]\(([^)\s]+)\)")
+        .replace_all(text, |caps: &lazy_regex::Captures| {
+            match formula_image_path(preview_dir, &caps[1]) {
+                Some(path) => {
+                    let path = std::fs::canonicalize(&path).unwrap_or(path);
+                    format!("\\includegraphics[height=1.5em]{{{}}}", path.display())
+                }
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Escape LaTeX special characters in plain text (titles, names)
+pub(crate) fn escape_latex_text(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' | '%' | '#' | '_' | '{' | '}' | '$' => {
+                output.push('\\');
+                output.push(c);
+            }
+            '\\' => output.push_str("\\textbackslash{}"),
+            '~' => output.push_str("\\textasciitilde{}"),
+            '^' => output.push_str("\\textasciicircum{}"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+// === Moodle XML ===
+
+impl Exporter {
+    /// Moodle question bank: one category per chapter, numerical questions when a
+    /// numeric answer can be extracted from the solution, essay questions otherwise.
+    async fn write_moodle(&self, book: &Book, chapters: &[Chapter], out: &mut ExportWriter) -> Result<()> {
+        out.push("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<quiz>\n").await?;
+
+        for chapter in chapters {
+            let category = format!("$course$/{}/Глава {}. {}", book.title, chapter.number, chapter.title);
+            out.push(&format!(
+                "  <question type=\"category\">\n    <category><text>{}</text></category>\n  </question>\n",
+                escape_xml(&category)
+            )).await?;
+
+            let problems = self.chapter_problems(&chapter.id).await?;
+            let solutions = self.solutions_for(&problems).await?;
+            for problem in &problems {
+                let solution = solutions.get(&problem.id);
+                out.push(&moodle_question(problem, solution.map(|s| s.content.as_str()), &self.base_url)).await?;
+            }
+        }
+
+        out.push("</quiz>\n").await
+    }
+}
+
+fn moodle_question(problem: &Problem, solution: Option<&str>, base_url: &str) -> String {
+    let name = format!("Задача {}", problem.number);
+    let text = cdata(&markdown_math_to_mathjax(&problem.content, base_url));
+    let feedback = solution
+        .map(|s| format!("    <generalfeedback format=\"html\"><text>{}</text></generalfeedback>\n", cdata(&markdown_math_to_mathjax(s, base_url))))
+        .unwrap_or_default();
+
+    match solution.and_then(extract_numeric_answer) {
+        Some(answer) => format!(
+            "  <question type=\"numerical\">\n    <name><text>{}</text></name>\n    <questiontext format=\"html\"><text>{}</text></questiontext>\n{}    <defaultgrade>1</defaultgrade>\n    <answer fraction=\"100\"><text>{}</text><tolerance>0.001</tolerance></answer>\n  </question>\n",
+            escape_xml(&name), text, feedback, answer
+        ),
+        None => format!(
+            "  <question type=\"essay\">\n    <name><text>{}</text></name>\n    <questiontext format=\"html\"><text>{}</text></questiontext>\n{}    <defaultgrade>1</defaultgrade>\n    <responseformat>editor</responseformat>\n  </question>\n",
+            escape_xml(&name), text, feedback
+        ),
+    }
+}
+
+/// Pull a single numeric final answer ("Ответ: 12", "Answer: -3.5") from solution text
+fn extract_numeric_answer(solution: &str) -> Option<f64> {
+    let re = regex!(r"(?i)(?:ответ|answer)\s*[:：]?\s*\$?\s*(?:[a-zа-я]\s*=\s*)?(-?\d+(?:[.,]\d+)?)\s*\$?\s*\.?\s*$");
+    solution
+        .lines()
+        .rev()
+        .map(|l| l.trim().trim_matches('*').trim())
+        .filter(|l| !l.is_empty())
+        .find_map(|line| re.captures(line))
+        .and_then(|c| c[1].replace(',', ".").parse().ok())
+}
+
+/// Convert `$...$` / `$$...$$` to MathJax `\(...\)` / `\[...\]` delimiters used by Moodle
+fn markdown_math_to_mathjax(text: &str, base_url: &str) -> String {
+    // Formula crops become absolute <img> tags instead of \includegraphics
+    let text = regex!(r"!\[formula\]\((/[^)\s]+)\)")
+        .replace_all(text, format!(r#"<img src="{}$1" alt="formula">"#, base_url.trim_end_matches('/')).as_str());
+    let display = markdown_math_to_latex(&text);
+    let mut output = String::with_capacity(display.len());
+    let mut open = true;
+    for c in display.chars() {
+        if c == '$' {
+            output.push_str(if open { "\\(" } else { "\\)" });
+            open = !open;
+        } else {
+            output.push(c);
+        }
+    }
+    output.replace('\n', "<br>\n")
+}
+
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// === QTI 2.1 ===
+
+const QTI_NS: &str = r#"xmlns="http://www.imsglobal.org/xsd/imsqti_v2p1" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.imsglobal.org/xsd/imsqti_v2p1 http://www.imsglobal.org/xsd/qti/qtiv2p1/imsqti_v2p1.xsd""#;
+
+/// Build a QTI 2.1 content package: `imsmanifest.xml` plus one assessment item per problem.
+/// Problems with an extractable numeric answer become auto-graded text-entry items,
+/// the rest extended-text (essay) items.
+pub fn export_qti_package(title: &str, problems: &[(Problem, Option<String>)], base_url: &str) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    let mut resources = String::new();
+    for (problem, solution) in problems {
+        let identifier = qti_identifier(&problem.id);
+        let href = format!("items/{}.xml", identifier);
+
+        zip.start_file(href.as_str(), options)?;
+        zip.write_all(qti_item(&identifier, problem, solution.as_deref(), base_url).as_bytes())?;
+
+        resources.push_str(&format!(
+            "    <resource identifier=\"{id}\" type=\"imsqti_item_xmlv2p1\" href=\"{href}\">\n      <file href=\"{href}\"/>\n    </resource>\n",
+            id = identifier,
+            href = href
+        ));
+    }
+
+    let manifest = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest xmlns="http://www.imsglobal.org/xsd/imscp_v1p1" identifier="{}">
+  <metadata>
+    <schema>QTIv2.1 Package</schema>
+    <schemaversion>1.0.0</schemaversion>
+  </metadata>
+  <organizations/>
+  <resources>
+{}  </resources>
+</manifest>
+"#,
+        qti_identifier(&format!("manifest-{}", title)),
+        resources
+    );
+    zip.start_file("imsmanifest.xml", options)?;
+    zip.write_all(manifest.as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+fn qti_item(identifier: &str, problem: &Problem, solution: Option<&str>, base_url: &str) -> String {
+    let title = escape_xml(&format!("Задача {}", problem.number));
+    let body = escape_xml(&markdown_math_to_mathjax(&problem.content, base_url).replace("<br>", ""))
+        .replace('\n', "<br/>\n");
+
+    match solution.and_then(extract_numeric_answer) {
+        Some(answer) => format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<assessmentItem {ns} identifier="{id}" title="{title}" adaptive="false" timeDependent="false">
+  <responseDeclaration identifier="RESPONSE" cardinality="single" baseType="float">
+    <correctResponse><value>{answer}</value></correctResponse>
+  </responseDeclaration>
+  <outcomeDeclaration identifier="SCORE" cardinality="single" baseType="float"/>
+  <itemBody>
+    <div>{body}</div>
+    <p><textEntryInteraction responseIdentifier="RESPONSE" expectedLength="12"/></p>
+  </itemBody>
+  <responseProcessing template="http://www.imsglobal.org/question/qti_v2p1/rptemplates/match_correct"/>
+</assessmentItem>
+"#,
+            ns = QTI_NS, id = identifier, title = title, answer = answer, body = body
+        ),
+        None => format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<assessmentItem {ns} identifier="{id}" title="{title}" adaptive="false" timeDependent="false">
+  <responseDeclaration identifier="RESPONSE" cardinality="single" baseType="string"/>
+  <outcomeDeclaration identifier="SCORE" cardinality="single" baseType="float"/>
+  <itemBody>
+    <div>{body}</div>
+    <extendedTextInteraction responseIdentifier="RESPONSE"/>
+  </itemBody>
+</assessmentItem>
+"#,
+            ns = QTI_NS, id = identifier, title = title, body = body
+        ),
     }
 }
 
+/// QTI identifiers must be XML NCNames: letters first, no colons
+fn qti_identifier(raw: &str) -> String {
+    let cleaned: String = raw
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '-' })
+        .collect();
+    format!("item-{}", cleaned)
+}
+
 /// Export statistics
 #[derive(Debug, Clone)]
 pub struct ExportStats {
@@ -425,3 +1226,190 @@ pub struct ExportStats {
     pub chapters_exported: u32,
     pub formulas_count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streamed_markdown_matches_collected_and_includes_subs() {
+        let path = std::env::temp_dir().join(format!("bookers_export_{}.db", uuid::Uuid::new_v4()));
+        let _ = std::fs::File::create(&path);
+        let db = Database::new(&format!("sqlite:{}", path.to_str().unwrap())).await.unwrap();
+        let book = Book {
+            id: "algebra-7".to_string(),
+            title: "Алгебра 7".to_string(),
+            author: None,
+            subject: None,
+            file_path: "resources/algebra-7.pdf".to_string(),
+            total_pages: 0,
+            created_at: chrono::Utc::now(),
+        };
+        db.create_book(&book).await.unwrap();
+        let chapter = Chapter {
+            id: "algebra-7:1".to_string(),
+            book_id: book.id.clone(),
+            number: 1,
+            title: "Уравнения".to_string(),
+            description: None,
+            problem_count: 0,
+            theory_count: 0,
+            created_at: chrono::Utc::now(),
+        };
+        db.create_chapter(&chapter).await.unwrap();
+
+        let mut problems = Vec::new();
+        for n in 1..=400 {
+            let id = Problem::generate_id("algebra-7", 1, &n.to_string());
+            problems.push(Problem {
+                id: format!("{}а", id),
+                chapter_id: chapter.id.clone(),
+                parent_id: Some(id.clone()),
+                number: "а".to_string(),
+                content: format!("Пункт а задачи {}", n),
+                ..Default::default()
+            });
+            problems.insert(problems.len() - 1, Problem {
+                id,
+                chapter_id: chapter.id.clone(),
+                number: n.to_string(),
+                content: format!("Решите уравнение {} $x^2 = {}$ и сделайте проверку. ", n, n * n).repeat(3),
+                has_solution: n == 7,
+                ..Default::default()
+            });
+        }
+        db.create_or_update_problems(&problems).await.unwrap();
+        let problem_id = Problem::generate_id("algebra-7", 1, "7");
+        db.create_or_update_solution(&Solution {
+            id: Solution::generate_id(&problem_id),
+            problem_id,
+            provider: "claude".to_string(),
+            content: "$x = \\pm 7$".to_string(),
+            latex_formulas: vec![],
+            is_verified: true,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }).await.unwrap();
+
+        let exporter = Exporter::new(db);
+        let collected = exporter.export_book(&book.id, ExportFormat::Markdown).await.unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&exporter.export_book(&book.id, ExportFormat::Json).await.unwrap()).unwrap();
+        assert_eq!(json["book"]["id"], "algebra-7");
+        assert_eq!(json["chapters"][0]["problems"].as_array().unwrap().len(), 400);
+        let mut rx = exporter.stream_book(book, ExportFormat::Markdown);
+        let mut chunks = 0;
+        let mut streamed = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            streamed.extend(chunk.unwrap());
+            chunks += 1;
+        }
+        assert!(chunks > 1);
+        assert_eq!(streamed, collected);
+
+        let text = String::from_utf8(collected).unwrap();
+        assert!(text.contains("**а).** Пункт а задачи 400"));
+        assert!(text.contains("**Решение:**\n\n$x = \\pm 7$"));
+        assert_eq!(text.matches("**Решение:**").count(), 1);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn converts_display_math() {
+        assert_eq!(
+            markdown_math_to_latex("Решите $$x^2 = 4$$ и $y$"),
+            r"Решите \[x^2 = 4\] и $y$"
+        );
+        assert_eq!(markdown_math_to_latex("$$a"), r"\[a\]");
+    }
+
+    #[test]
+    fn escapes_titles() {
+        assert_eq!(escape_latex_text("A & B_1 100%"), r"A \& B\_1 100\%");
+    }
+
+    #[test]
+    fn extracts_numeric_answers() {
+        assert_eq!(extract_numeric_answer("Решение...\n**Ответ: 12**"), Some(12.0));
+        assert_eq!(extract_numeric_answer("Ответ: $x = -3,5$."), Some(-3.5));
+        assert_eq!(extract_numeric_answer("Ответ: x принадлежит R"), None);
+    }
+
+    #[test]
+    fn moodle_question_types() {
+        let problem = Problem {
+            number: "7".to_string(),
+            content: "Найдите $x$".to_string(),
+            ..Default::default()
+        };
+        let numeric = moodle_question(&problem, Some("Ответ: 4"), "");
+        assert!(numeric.contains("type=\"numerical\""));
+        assert!(numeric.contains("<text>4</text>"));
+        assert!(numeric.contains(r"Найдите \(x\)"));
+
+        let essay = moodle_question(&problem, None, "");
+        assert!(essay.contains("type=\"essay\""));
+    }
+
+    #[test]
+    fn qti_package_contains_manifest_and_items() {
+        let problem = Problem {
+            id: "algebra-7:1:5".to_string(),
+            number: "5".to_string(),
+            content: "Вычислите $2 + 2$ & проверьте".to_string(),
+            ..Default::default()
+        };
+        let bytes = export_qti_package("Алгебра", &[(problem, Some("Ответ: 4".to_string()))], "").unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        assert!(archive.by_name("imsmanifest.xml").is_ok());
+
+        let mut item = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("items/item-algebra-7-1-5.xml").unwrap(), &mut item).unwrap();
+        assert!(item.contains("textEntryInteraction"));
+        assert!(item.contains("&amp; проверьте"));
+    }
+
+    #[test]
+    fn latex_problem_with_solution_for_print() {
+        let problem = Problem {
+            number: "12".to_string(),
+            content: "Решите $$x^2 = 4$$ и найдите 50% корней".to_string(),
+            ..Default::default()
+        };
+        let solution = Solution {
+            id: "s1".to_string(),
+            problem_id: "p1".to_string(),
+            provider: "claude".to_string(),
+            content: "### Шаг 1\n$$x = \\pm 2$$\n**Ответ:** $x = \\pm 2$".to_string(),
+            latex_formulas: vec![],
+            is_verified: false,
+            rating: None,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+
+        let tex = format_problem_latex(&problem, None, Some(&solution), None);
+        assert!(tex.contains(r"Решите \[x^2 = 4\] и найдите 50\% корней"));
+        assert!(tex.contains("\\paragraph{Решение.} \\textbf{Шаг 1}\n\\[x = \\pm 2\\]\n\\textbf{Ответ:} $x = \\pm 2$"));
+        assert!(!format_problem_latex(&problem, None, None, None).contains("Решение."));
+        assert_eq!(
+            markdown_to_latex(r"\input{/app/.env} a_1 ^ и $x_1$ за 5$", None),
+            r"\textbackslash{}input\{/app/.env\} a\_1 \textasciicircum{} и $x_1$ за 5\$"
+        );
+        assert_eq!(ExportFormat::from_name("pdf").map(|f| f.mime_type()), Some("application/pdf"));
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert!(matches!(ExportFormat::from_name("slides"), Some(ExportFormat::Beamer)));
+        assert!(ExportFormat::from_name("docx").is_none());
+    }
+}
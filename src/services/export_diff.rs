@@ -0,0 +1,150 @@
+//! What changed in a book between two exports. Every export run (see
+//! [`crate::services::export_history`]) records a hash of each exported
+//! problem (its text, its sub-problems and the solution that went out with
+//! it), so a handout printed last month can be compared with a later export
+//! or with the book as it is now.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::Problem;
+use crate::services::export_history::ExportRun;
+
+/// A top-level problem as it was exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotProblem {
+    pub problem_id: String,
+    pub chapter_id: String,
+    pub number: String,
+    pub content_hash: String,
+}
+
+impl SnapshotProblem {
+    pub fn new(problem: &Problem, solution: Option<&str>) -> Self {
+        Self {
+            problem_id: problem.id.clone(),
+            chapter_id: problem.chapter_id.clone(),
+            number: problem.number.clone(),
+            content_hash: content_hash(problem, solution),
+        }
+    }
+}
+
+/// SHA-256 of what an export shows of a problem
+pub fn content_hash(problem: &Problem, solution: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(problem.content.trim().as_bytes());
+    for sub in problem.sub_problems.iter().flatten() {
+        hasher.update(b"\n\x1f");
+        hasher.update(sub.number.as_bytes());
+        hasher.update(b"\x1f");
+        hasher.update(sub.content.trim().as_bytes());
+    }
+    hasher.update(b"\n\x1e");
+    hasher.update(solution.unwrap_or("").trim().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChapterCounts {
+    pub chapter_id: String,
+    pub from_problems: usize,
+    pub to_problems: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportDiff {
+    pub from: ExportRun,
+    /// `None` when compared with the book's current content
+    pub to: Option<ExportRun>,
+    /// Problem counts of every chapter in either export
+    pub chapters: Vec<ChapterCounts>,
+    pub added: Vec<SnapshotProblem>,
+    pub removed: Vec<SnapshotProblem>,
+    /// As they are in `to`
+    pub changed: Vec<SnapshotProblem>,
+    pub unchanged: usize,
+}
+
+/// Compare the problems of two exports, matched by problem id
+pub fn diff_snapshots(
+    from: ExportRun,
+    from_problems: &[SnapshotProblem],
+    to: Option<ExportRun>,
+    to_problems: &[SnapshotProblem],
+) -> ExportDiff {
+    let mut chapters: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for problem in from_problems {
+        chapters.entry(&problem.chapter_id).or_default().0 += 1;
+    }
+    for problem in to_problems {
+        chapters.entry(&problem.chapter_id).or_default().1 += 1;
+    }
+
+    let before: HashMap<&str, &SnapshotProblem> = from_problems.iter().map(|p| (p.problem_id.as_str(), p)).collect();
+    let after: HashMap<&str, &SnapshotProblem> = to_problems.iter().map(|p| (p.problem_id.as_str(), p)).collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for problem in to_problems {
+        match before.get(problem.problem_id.as_str()) {
+            None => added.push(problem.clone()),
+            Some(old) if old.content_hash != problem.content_hash => changed.push(problem.clone()),
+            Some(_) => unchanged += 1,
+        }
+    }
+    let removed = from_problems
+        .iter()
+        .filter(|p| !after.contains_key(p.problem_id.as_str()))
+        .cloned()
+        .collect();
+
+    ExportDiff {
+        from,
+        to,
+        chapters: chapters
+            .into_iter()
+            .map(|(chapter_id, (from_problems, to_problems))| ChapterCounts {
+                chapter_id: chapter_id.to_string(),
+                from_problems,
+                to_problems,
+            })
+            .collect(),
+        added,
+        removed,
+        changed,
+        unchanged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(chapter: u32, number: u32, hash: &str) -> SnapshotProblem {
+        SnapshotProblem {
+            problem_id: format!("algebra-7:{}:{}", chapter, number),
+            chapter_id: format!("algebra-7:{}", chapter),
+            number: number.to_string(),
+            content_hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn diffs_problems_and_chapter_counts() {
+        let run = ExportRun::new("algebra-7", None, "markdown");
+        let from = [snapshot(1, 1, "a"), snapshot(1, 2, "b"), snapshot(2, 1, "c")];
+        let to = [snapshot(1, 1, "a"), snapshot(1, 2, "B"), snapshot(2, 2, "d"), snapshot(3, 1, "e")];
+
+        let diff = diff_snapshots(run.clone(), &from, Some(run), &to);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.changed, vec![snapshot(1, 2, "B")]);
+        assert_eq!(diff.removed, vec![snapshot(2, 1, "c")]);
+        assert_eq!(diff.added.len(), 2);
+        let counts: Vec<(usize, usize)> = diff.chapters.iter().map(|c| (c.from_problems, c.to_problems)).collect();
+        assert_eq!(counts, vec![(2, 2), (1, 1), (0, 1)]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Export history: every book or chapter export is a run in `export_runs`
+//! with its format, filters, how long it took, and the file it produced,
+//! kept in attachment storage so what was handed out can be downloaded
+//! again as it was instead of being regenerated from today's content.
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::services::attachments::AttachmentStorage;
+use crate::services::database::Database;
+
+/// Options an export was made with
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportFilters {
+    #[serde(default)]
+    pub approved_only: bool,
+    /// Solution filter: a provider name or `verified`; any solution when unset
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub include_glossary: bool,
+    /// Problem numbers of a Beamer chapter export
+    pub problems: Option<Vec<String>>,
+}
+
+/// One export invocation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRun {
+    pub id: String,
+    pub book_id: String,
+    /// Set for chapter exports
+    pub chapter_id: Option<String>,
+    pub format: String,
+    #[serde(default)]
+    pub filters: ExportFilters,
+    /// Name the file was sent under; set once the export finished
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub size: Option<u64>,
+    /// SHA-256 of the file
+    pub checksum: Option<String>,
+    #[serde(skip_serializing)]
+    pub storage_key: Option<String>,
+    pub duration_ms: Option<u64>,
+    /// Why a streamed export broke off
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ExportRun {
+    pub fn new(book_id: &str, chapter_id: Option<&str>, format: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            book_id: book_id.to_string(),
+            chapter_id: chapter_id.map(str::to_string),
+            format: format.to_string(),
+            filters: ExportFilters::default(),
+            file_name: None,
+            content_type: None,
+            size: None,
+            checksum: None,
+            storage_key: None,
+            duration_ms: None,
+            error: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn with_filters(mut self, filters: ExportFilters) -> Self {
+        self.filters = filters;
+        self
+    }
+}
+
+/// Stored export files kept per book; older runs keep their record and
+/// problem hashes but lose the file
+pub const KEPT_EXPORT_FILES: i64 = 20;
+
+/// Store the file an export produced and complete its run. `started` is when
+/// the export began, for the recorded duration.
+pub async fn store_export_artifact(
+    db: &Database,
+    storage: &AttachmentStorage,
+    run: &mut ExportRun,
+    file_name: &str,
+    content_type: &str,
+    bytes: Vec<u8>,
+    started: Instant,
+) -> Result<()> {
+    let storage_key = describe_file(run, file_name, content_type, format!("{:x}", Sha256::digest(&bytes)), bytes.len() as u64, started);
+    storage.put(&storage_key, bytes, content_type).await?;
+    run.storage_key = Some(storage_key);
+    db.finish_export_run(run).await?;
+    prune_export_files(db, storage, &run.book_id).await;
+    Ok(())
+}
+
+/// Like [`store_export_artifact`] for a file spooled to disk while it was
+/// streamed
+pub async fn store_export_spool(
+    db: &Database,
+    storage: &AttachmentStorage,
+    run: &mut ExportRun,
+    file_name: &str,
+    content_type: &str,
+    mut spool: ExportSpool,
+    started: Instant,
+) -> Result<()> {
+    spool.file.flush().await?;
+    let checksum = format!("{:x}", std::mem::take(&mut spool.hasher).finalize());
+    let storage_key = describe_file(run, file_name, content_type, checksum.clone(), spool.size, started);
+    storage.put_file(&storage_key, &spool.path, content_type, &checksum).await?;
+    run.storage_key = Some(storage_key);
+    db.finish_export_run(run).await?;
+    prune_export_files(db, storage, &run.book_id).await;
+    Ok(())
+}
+
+/// Fill in a finished run's file details; returns its storage key
+fn describe_file(run: &mut ExportRun, file_name: &str, content_type: &str, checksum: String, size: u64, started: Instant) -> String {
+    let extension = file_name.rsplit_once('.').map_or("bin", |(_, ext)| ext);
+    run.checksum = Some(checksum);
+    run.size = Some(size);
+    run.file_name = Some(file_name.to_string());
+    run.content_type = Some(content_type.to_string());
+    run.duration_ms = Some(started.elapsed().as_millis() as u64);
+    // Flat key: local storage keeps attachments in one directory
+    format!("export-{}.{}", run.id, extension)
+}
+
+/// Delete the stored files of a book's exports beyond [`KEPT_EXPORT_FILES`]
+async fn prune_export_files(db: &Database, storage: &AttachmentStorage, book_id: &str) {
+    let old = match db.old_export_files(book_id, KEPT_EXPORT_FILES).await {
+        Ok(old) => old,
+        Err(e) => return log::warn!("Failed to list old exports of {}: {}", book_id, e),
+    };
+    for (run_id, key) in old {
+        // The key stays on the run until the file is gone, so a failed
+        // delete is retried on the next prune
+        let pruned = match storage.delete(&key).await {
+            Ok(()) => db.clear_export_file(&run_id).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = pruned {
+            log::warn!("Failed to prune export {}: {}", run_id, e);
+        }
+    }
+}
+
+/// A streamed export written to a temporary file chunk by chunk, hashed as
+/// it goes, so the whole file never sits in memory. The file is removed when
+/// the spool is dropped without being stored.
+pub struct ExportSpool {
+    path: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl ExportSpool {
+    pub async fn create() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("bookers_export_{}", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self { path, file, hasher: Sha256::new(), size: 0 })
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk).await?;
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for ExportSpool {
+    fn drop(&mut self) {
+        // Already moved into storage when the export was kept
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
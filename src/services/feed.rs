@@ -0,0 +1,145 @@
+//! Atom feed of a book's ingestion progress: problems as they are parsed
+//! and solutions as they are generated, newest first, for following a
+//! shared server from a feed reader.
+
+use chrono::{DateTime, Utc};
+
+use crate::models::{Book, Problem, Solution};
+
+/// Entries a feed has when the reader doesn't ask for a number
+pub const DEFAULT_FEED_ENTRIES: usize = 50;
+pub const MAX_FEED_ENTRIES: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEntry {
+    /// Stable across updates, so a regenerated solution shows as updated
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub updated: DateTime<Utc>,
+    /// `problem` or `solution`
+    pub category: &'static str,
+    /// Markdown with LaTeX, sent as text
+    pub content: String,
+}
+
+impl FeedEntry {
+    pub fn problem(base_url: &str, problem: &Problem) -> Self {
+        let link = problem_url(base_url, &problem.id);
+        Self {
+            id: link.clone(),
+            title: problem.display_name.clone(),
+            link,
+            updated: problem.created_at,
+            category: "problem",
+            content: problem.content.clone(),
+        }
+    }
+
+    pub fn solution(base_url: &str, solution: &Solution, problem_name: &str) -> Self {
+        let link = problem_url(base_url, &solution.problem_id);
+        Self {
+            id: format!("{}#solution-{}", link, solution.provider),
+            title: format!("Solution: {} ({})", problem_name, solution.provider),
+            link,
+            updated: solution.updated_at,
+            category: "solution",
+            content: solution.content.clone(),
+        }
+    }
+}
+
+fn problem_url(base_url: &str, problem_id: &str) -> String {
+    format!("{}/problems/{}", base_url.trim_end_matches('/'), urlencoding::encode(problem_id))
+}
+
+/// Problems and solutions merged newest first, at most `limit` of them
+pub fn merge_entries(mut entries: Vec<FeedEntry>, limit: usize) -> Vec<FeedEntry> {
+    entries.sort_by_key(|e| std::cmp::Reverse(e.updated));
+    entries.truncate(limit);
+    entries
+}
+
+/// Atom 1.0 document of a book's entries. `feed_url` is the feed's own
+/// address, used as its id.
+pub fn render_atom(book: &Book, base_url: &str, feed_url: &str, entries: &[FeedEntry]) -> String {
+    let base = base_url.trim_end_matches('/');
+    let updated = entries.iter().map(|e| e.updated).max().unwrap_or(book.created_at);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape_xml(feed_url)));
+    xml.push_str(&format!("  <title>{}: new problems and solutions</title>\n", escape_xml(&book.title)));
+    xml.push_str(&format!("  <updated>{}</updated>\n", updated.to_rfc3339()));
+    xml.push_str(&format!("  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n", escape_xml(feed_url)));
+    xml.push_str(&format!(
+        "  <link rel=\"alternate\" type=\"text/html\" href=\"{}/books/{}\"/>\n",
+        escape_xml(base),
+        escape_xml(&urlencoding::encode(&book.id))
+    ));
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", escape_xml(book.author.as_deref().unwrap_or("Bookers"))));
+    xml.push_str("  <generator>Bookers</generator>\n");
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", escape_xml(&entry.id)));
+        xml.push_str(&format!("    <title>{}</title>\n", escape_xml(&entry.title)));
+        xml.push_str(&format!("    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", escape_xml(&entry.link)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated.to_rfc3339()));
+        xml.push_str(&format!("    <category term=\"{}\"/>\n", entry.category));
+        xml.push_str(&format!("    <content type=\"text\">{}</content>\n", escape_xml(&entry.content)));
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// Text as XML character data. Control characters other than tab and line
+/// breaks (OCR leaves e.g. form feeds) can't appear in XML 1.0 even escaped,
+/// so they are dropped.
+fn escape_xml(text: &str) -> String {
+    text.chars()
+        .filter(|&c| matches!(c, '\t' | '\n' | '\r') || (c >= ' ' && !matches!(c, '\u{FFFE}' | '\u{FFFF}')))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_entries_newest_first() {
+        let book = Book {
+            id: "algebra-7".to_string(),
+            title: "Алгебра & геометрия".to_string(),
+            author: None,
+            subject: None,
+            file_path: String::new(),
+            total_pages: 0,
+            created_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let problem = Problem {
+            id: "algebra-7:1:5".to_string(),
+            display_name: "Задача 5".to_string(),
+            content: "Докажите, что $a < b$\u{c}\u{0}".to_string(),
+            created_at: DateTime::from_timestamp(1_700_000_100, 0).unwrap(),
+            ..Default::default()
+        };
+        let mut solved = FeedEntry::problem("https://books.example.org/", &problem);
+        solved.title = "Solution: Задача 5 (claude)".to_string();
+        solved.updated = DateTime::from_timestamp(1_700_000_200, 0).unwrap();
+
+        let entries = merge_entries(vec![FeedEntry::problem("https://books.example.org/", &problem), solved], 10);
+        assert_eq!(entries[0].updated.timestamp(), 1_700_000_200);
+
+        let xml = render_atom(&book, "https://books.example.org/", "https://books.example.org/feeds/books/algebra-7.atom", &entries);
+        assert!(xml.contains("<title>Алгебра &amp; геометрия: new problems and solutions</title>"));
+        assert!(xml.contains("<updated>2023-11-14T22:16:40+00:00</updated>"));
+        assert!(xml.contains("<id>https://books.example.org/problems/algebra-7%3A1%3A5</id>"));
+        assert!(xml.contains("<content type=\"text\">Докажите, что $a &lt; b$</content>"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+        assert_eq!(merge_entries(entries, 1).len(), 1);
+    }
+}
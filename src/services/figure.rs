@@ -0,0 +1,434 @@
+//! Geometry figures reconstructed from problem text (experimental). The
+//! model describes the drawing as points and what connects them, never as
+//! markup; the SVG for the viewer and the TikZ for LaTeX exports are
+//! generated here from that description.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use lazy_regex::regex_is_match;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Problem;
+use crate::services::ai_solver::AISolver;
+use crate::services::database::Database;
+use crate::services::export::escape_latex_text;
+
+const MAX_POINTS: usize = 30;
+const MAX_ITEMS: usize = 60;
+/// Largest coordinate accepted, in figure units
+const MAX_COORDINATE: f64 = 1000.0;
+const MAX_LABEL_LEN: usize = 24;
+
+const SVG_WIDTH: f64 = 400.0;
+const SVG_HEIGHT: f64 = 300.0;
+const SVG_MARGIN: f64 = 28.0;
+/// Width of the figure in exported LaTeX, in cm
+const TIKZ_WIDTH_CM: f64 = 6.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FigurePoint {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FigureCircle {
+    pub center: String,
+    pub radius: f64,
+}
+
+/// Text written along a segment, e.g. its length
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentLabel {
+    pub from: String,
+    pub to: String,
+    pub text: String,
+}
+
+/// A drawing as named points and the segments, polygons, circles and marks
+/// between them. Points are in figure units with y pointing up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FigureDescription {
+    pub points: Vec<FigurePoint>,
+    #[serde(default)]
+    pub segments: Vec<[String; 2]>,
+    #[serde(default)]
+    pub polygons: Vec<Vec<String>>,
+    #[serde(default)]
+    pub circles: Vec<FigureCircle>,
+    /// Right angles as `[A, B, C]`, the angle at B
+    #[serde(default)]
+    pub right_angles: Vec<[String; 3]>,
+    #[serde(default)]
+    pub labels: Vec<SegmentLabel>,
+}
+
+/// Figure stored for a problem. `reconstructed` ones were drawn by a model
+/// from the statement, not taken from the book, and are labelled as such.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemFigure {
+    pub problem_id: String,
+    pub provider: String,
+    pub description: FigureDescription,
+    pub reconstructed: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Ask the model for the problem's figure, check it and store it in place
+/// of any earlier one
+pub async fn reconstruct_figure(
+    db: &Database,
+    solver: &AISolver,
+    problem: &Problem,
+    provider: Option<&str>,
+) -> Result<ProblemFigure> {
+    let (provider, response) = solver.describe_figure(problem, provider).await?;
+    let description = parse_figure_response(&response)?;
+    let figure = ProblemFigure {
+        problem_id: problem.id.clone(),
+        provider,
+        description,
+        reconstructed: true,
+        created_at: Utc::now(),
+    };
+    db.save_problem_figure(&figure).await?;
+    Ok(figure)
+}
+
+/// Does the statement talk about plane figures?
+pub fn is_geometry_problem(problem: &Problem) -> bool {
+    regex_is_match!(
+        r"(?i)треугольник|окружност|круг[аеуо]?\b|угол|угла|углы|четырёхугольник|четырехугольник|трапеци|параллелограмм|ромб|квадрат|прямоугольник|биссектрис|медиан|высот[аыу]|хорд|касательн|радиус|диаметр|перпендикуляр|многоугольник|вписан|описан",
+        &problem.content
+    )
+}
+
+/// The figure in a model's answer: the first JSON object in it, checked
+pub fn parse_figure_response(response: &str) -> Result<FigureDescription> {
+    let start = response.find('{').ok_or_else(|| anyhow!("No figure description in the response"))?;
+    let end = response.rfind('}').filter(|&end| end > start).ok_or_else(|| anyhow!("Unterminated figure description"))?;
+    let figure: FigureDescription = serde_json::from_str(&response[start..=end])?;
+    figure.validate()?;
+    Ok(figure)
+}
+
+impl FigureDescription {
+    pub fn validate(&self) -> Result<()> {
+        if self.points.is_empty() || self.points.len() > MAX_POINTS {
+            bail!("A figure needs 1 to {} points", MAX_POINTS);
+        }
+        let items = self.segments.len() + self.polygons.len() + self.circles.len() + self.right_angles.len() + self.labels.len();
+        if items > MAX_ITEMS {
+            bail!("Too many elements in the figure");
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for point in &self.points {
+            if !regex_is_match!(r"^[A-Za-z][A-Za-z0-9]{0,2}'{0,2}$", &point.name) {
+                bail!("Bad point name {:?}", point.name);
+            }
+            if !names.insert(point.name.as_str()) {
+                bail!("Point {} is defined twice", point.name);
+            }
+            if !(point.x.is_finite() && point.y.is_finite() && point.x.abs() <= MAX_COORDINATE && point.y.abs() <= MAX_COORDINATE) {
+                bail!("Point {} is out of range", point.name);
+            }
+        }
+
+        let known = |name: &String| -> Result<()> {
+            if names.contains(name.as_str()) { Ok(()) } else { bail!("Unknown point {}", name) }
+        };
+        self.segments.iter().flatten().try_for_each(known)?;
+        self.right_angles.iter().flatten().try_for_each(known)?;
+        for polygon in &self.polygons {
+            if polygon.len() < 3 {
+                bail!("A polygon needs at least 3 points");
+            }
+            polygon.iter().try_for_each(known)?;
+        }
+        for circle in &self.circles {
+            known(&circle.center)?;
+            if !(circle.radius.is_finite() && circle.radius > 0.0 && circle.radius <= MAX_COORDINATE) {
+                bail!("Bad radius {}", circle.radius);
+            }
+        }
+        for label in &self.labels {
+            known(&label.from)?;
+            known(&label.to)?;
+            if label.text.chars().count() > MAX_LABEL_LEN {
+                bail!("Label {:?} is too long", label.text);
+            }
+        }
+        Ok(())
+    }
+
+    fn point_map(&self) -> HashMap<&str, (f64, f64)> {
+        self.points.iter().map(|p| (p.name.as_str(), (p.x, p.y))).collect()
+    }
+
+    /// Lines of the drawing: polygon sides and segments
+    fn edges(&self) -> Vec<(&str, &str)> {
+        let mut edges: Vec<(&str, &str)> = Vec::new();
+        for polygon in &self.polygons {
+            for (i, from) in polygon.iter().enumerate() {
+                edges.push((from, &polygon[(i + 1) % polygon.len()]));
+            }
+        }
+        edges.extend(self.segments.iter().map(|[a, b]| (a.as_str(), b.as_str())));
+        edges
+    }
+
+    /// (min x, min y, max x, max y) of the points and circles
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        let points = self.point_map();
+        let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        let mut extend = |x: f64, y: f64, r: f64| {
+            bounds = (bounds.0.min(x - r), bounds.1.min(y - r), bounds.2.max(x + r), bounds.3.max(y + r));
+        };
+        for point in &self.points {
+            extend(point.x, point.y, 0.0);
+        }
+        for circle in &self.circles {
+            let (x, y) = points[circle.center.as_str()];
+            extend(x, y, circle.radius);
+        }
+        bounds
+    }
+
+    /// Center of the points, which labels are pushed away from
+    fn centroid(&self) -> (f64, f64) {
+        let n = self.points.len() as f64;
+        let (sx, sy) = self.points.iter().fold((0.0, 0.0), |(sx, sy), p| (sx + p.x, sy + p.y));
+        (sx / n, sy / n)
+    }
+
+    /// Short text form for solver prompts
+    pub fn summary(&self) -> String {
+        let coordinate = |v: f64| format!("{}", (v * 100.0).round() / 100.0);
+        let mut parts = vec![format!(
+            "точки {}",
+            self.points
+                .iter()
+                .map(|p| format!("{}({}; {})", p.name, coordinate(p.x), coordinate(p.y)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )];
+        if !self.polygons.is_empty() {
+            parts.push(format!("многоугольники {}", self.polygons.iter().map(|p| p.concat()).collect::<Vec<_>>().join(", ")));
+        }
+        if !self.segments.is_empty() {
+            parts.push(format!("отрезки {}", self.segments.iter().map(|s| s.concat()).collect::<Vec<_>>().join(", ")));
+        }
+        for circle in &self.circles {
+            parts.push(format!("окружность с центром {} радиуса {}", circle.center, coordinate(circle.radius)));
+        }
+        for [a, b, c] in &self.right_angles {
+            parts.push(format!("прямой угол {}{}{}", a, b, c));
+        }
+        for label in &self.labels {
+            parts.push(format!("{}{} = {}", label.from, label.to, label.text));
+        }
+        format!("Чертёж (восстановлен по условию): {}.", parts.join("; "))
+    }
+
+    pub fn to_svg(&self) -> String {
+        let points = self.point_map();
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let (width, height) = ((max_x - min_x).max(1e-6), (max_y - min_y).max(1e-6));
+        let scale = ((SVG_WIDTH - 2.0 * SVG_MARGIN) / width).min((SVG_HEIGHT - 2.0 * SVG_MARGIN) / height);
+        let offset_x = (SVG_WIDTH - width * scale) / 2.0;
+        let offset_y = (SVG_HEIGHT - height * scale) / 2.0;
+        let to_svg = |(x, y): (f64, f64)| (offset_x + (x - min_x) * scale, SVG_HEIGHT - offset_y - (y - min_y) * scale);
+
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="serif" font-size="15">
+<rect width="{w}" height="{h}" fill="white"/>
+<g fill="none" stroke="black" stroke-width="1.5">
+"#,
+            w = SVG_WIDTH,
+            h = SVG_HEIGHT
+        );
+        for (from, to) in self.edges() {
+            let ((x1, y1), (x2, y2)) = (to_svg(points[from]), to_svg(points[to]));
+            let _ = writeln!(svg, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#, x1, y1, x2, y2);
+        }
+        for circle in &self.circles {
+            let (cx, cy) = to_svg(points[circle.center.as_str()]);
+            let _ = writeln!(svg, r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}"/>"#, cx, cy, circle.radius * scale);
+        }
+        for [a, b, c] in &self.right_angles {
+            let corner = to_svg(points[b.as_str()]);
+            if let Some([p, q, r]) = right_angle_mark(corner, to_svg(points[a.as_str()]), to_svg(points[c.as_str()]), 10.0) {
+                let _ = writeln!(svg, r#"<polyline points="{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}" stroke-width="1"/>"#, p.0, p.1, q.0, q.1, r.0, r.1);
+            }
+        }
+        svg.push_str("</g>\n");
+
+        let center = to_svg(self.centroid());
+        for point in &self.points {
+            let (x, y) = to_svg((point.x, point.y));
+            let (dx, dy) = away(center, (x, y), 14.0);
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{:.1}" cy="{:.1}" r="2.5"/><text x="{:.1}" y="{:.1}" text-anchor="middle" dominant-baseline="middle" font-style="italic">{}</text>"#,
+                x,
+                y,
+                x + dx,
+                y + dy,
+                escape_xml(&point.name)
+            );
+        }
+        for label in &self.labels {
+            let ((x1, y1), (x2, y2)) = (to_svg(points[label.from.as_str()]), to_svg(points[label.to.as_str()]));
+            let middle = ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+            let (dx, dy) = away(center, middle, 12.0);
+            let _ = writeln!(
+                svg,
+                r#"<text x="{:.1}" y="{:.1}" text-anchor="middle" dominant-baseline="middle" font-size="13">{}</text>"#,
+                middle.0 + dx,
+                middle.1 + dy,
+                escape_xml(&label.text)
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn to_tikz(&self) -> String {
+        let points = self.point_map();
+        // Coordinates are named by index, so point names need no escaping
+        let index: HashMap<&str, usize> = self.points.iter().enumerate().map(|(i, p)| (p.name.as_str(), i)).collect();
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let scale = TIKZ_WIDTH_CM / (max_x - min_x).max(max_y - min_y).max(1e-6);
+
+        let mut tikz = format!("\\begin{{center}}\n\\begin{{tikzpicture}}[scale={:.3}]\n", scale);
+        for (i, point) in self.points.iter().enumerate() {
+            let _ = writeln!(tikz, "\\coordinate (p{}) at ({:.3},{:.3});", i, point.x, point.y);
+        }
+        for (from, to) in self.edges() {
+            let _ = writeln!(tikz, "\\draw (p{}) -- (p{});", index[from], index[to]);
+        }
+        for circle in &self.circles {
+            let _ = writeln!(tikz, "\\draw (p{}) circle ({:.3});", index[circle.center.as_str()], circle.radius);
+        }
+        let mark = 0.06 * (max_x - min_x).max(max_y - min_y);
+        for [a, b, c] in &self.right_angles {
+            if let Some([p, q, r]) = right_angle_mark(points[b.as_str()], points[a.as_str()], points[c.as_str()], mark) {
+                let _ = writeln!(tikz, "\\draw ({:.3},{:.3}) -- ({:.3},{:.3}) -- ({:.3},{:.3});", p.0, p.1, q.0, q.1, r.0, r.1);
+            }
+        }
+        let center = self.centroid();
+        for (i, point) in self.points.iter().enumerate() {
+            let (dx, dy) = away(center, (point.x, point.y), 1.0);
+            let _ = writeln!(
+                tikz,
+                "\\fill (p{}) circle (1.2pt) node[anchor={}] {{${}$}};",
+                i,
+                tikz_anchor(dx, dy),
+                point.name.replace('\'', "^{\\prime}")
+            );
+        }
+        for label in &self.labels {
+            let _ = writeln!(
+                tikz,
+                "\\path (p{}) -- (p{}) node[midway, sloped, above] {{{}}};",
+                index[label.from.as_str()],
+                index[label.to.as_str()],
+                escape_latex_text(&label.text)
+            );
+        }
+        tikz.push_str("\\end{tikzpicture}\n\\end{center}\n");
+        tikz
+    }
+}
+
+/// Small square in the corner at `corner` between the rays to `a` and `c`
+fn right_angle_mark(corner: (f64, f64), a: (f64, f64), c: (f64, f64), size: f64) -> Option<[(f64, f64); 3]> {
+    let unit = |(x, y): (f64, f64)| {
+        let (dx, dy) = (x - corner.0, y - corner.1);
+        let length = dx.hypot(dy);
+        (length > 1e-9).then(|| (dx / length * size, dy / length * size))
+    };
+    let (u, v) = (unit(a)?, unit(c)?);
+    Some([
+        (corner.0 + u.0, corner.1 + u.1),
+        (corner.0 + u.0 + v.0, corner.1 + u.1 + v.1),
+        (corner.0 + v.0, corner.1 + v.1),
+    ])
+}
+
+/// Offset of length `distance` pointing from `center` through `point`
+fn away(center: (f64, f64), point: (f64, f64), distance: f64) -> (f64, f64) {
+    let (dx, dy) = (point.0 - center.0, point.1 - center.1);
+    let length = dx.hypot(dy);
+    if length < 1e-9 {
+        return (0.0, -distance);
+    }
+    (dx / length * distance, dy / length * distance)
+}
+
+/// TikZ anchor putting a label on the `(dx, dy)` side of its point
+fn tikz_anchor(dx: f64, dy: f64) -> &'static str {
+    let angle = dy.atan2(dx).to_degrees();
+    match angle {
+        a if (-22.5..22.5).contains(&a) => "west",
+        a if (22.5..67.5).contains(&a) => "south west",
+        a if (67.5..112.5).contains(&a) => "south",
+        a if (112.5..157.5).contains(&a) => "south east",
+        a if (-67.5..-22.5).contains(&a) => "north west",
+        a if (-112.5..-67.5).contains(&a) => "north",
+        a if (-157.5..-112.5).contains(&a) => "north east",
+        _ => "east",
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = r#"Here is the figure:
+```json
+{"points": [{"name": "A", "x": 0, "y": 0}, {"name": "B", "x": 4, "y": 0}, {"name": "C", "x": 0, "y": 3}],
+ "polygons": [["A", "B", "C"]],
+ "right_angles": [["B", "A", "C"]],
+ "labels": [{"from": "A", "to": "B", "text": "4 см"}, {"from": "B", "to": "C", "text": "<5>"}]}
+```"#;
+
+    #[test]
+    fn reads_and_draws_model_figures() {
+        let figure = parse_figure_response(TRIANGLE).unwrap();
+        assert_eq!(figure.points.len(), 3);
+        assert_eq!(figure.edges().len(), 3);
+
+        let svg = figure.to_svg();
+        assert_eq!(svg.matches("<line").count(), 3);
+        assert!(svg.contains("&lt;5&gt;") && !svg.contains("<5>"));
+
+        let tikz = figure.to_tikz();
+        assert!(tikz.contains("\\coordinate (p1) at (4.000,0.000);"));
+        assert!(tikz.contains("node[anchor=north east] {$A$}"));
+        assert!(figure.summary().contains("прямой угол BAC"));
+
+        assert!(parse_figure_response("no figure").is_err());
+        let unknown = TRIANGLE.replace(r#"["A", "B", "C"]]"#, r#"["A", "B", "D"]]"#);
+        assert!(parse_figure_response(&unknown).is_err());
+        let markup = TRIANGLE.replace(r#""name": "C""#, r#""name": "<script>""#);
+        assert!(parse_figure_response(&markup).is_err());
+    }
+
+    #[test]
+    fn detects_geometry_problems() {
+        let problem = |content: &str| Problem { content: content.to_string(), ..Default::default() };
+        assert!(is_geometry_problem(&problem("В треугольнике ABC угол C равен 90°")));
+        assert!(is_geometry_problem(&problem("Найдите радиус окружности")));
+        assert!(!is_geometry_problem(&problem("Решите уравнение $x^2 = 4$")));
+    }
+}
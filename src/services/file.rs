@@ -1,14 +1,113 @@
+//! Files derived from the books in the resources directory. Every name is
+//! built from [`artifact_key`] of the book's file name, so one PDF always
+//! maps to the same artifacts whatever handler or job asks for them:
+//!
+//! - page previews: `{preview_dir}/{key}_{page}.png`, `{key}_{page}@{dpi}dpi.png`
+//!   when rendered at another than the default resolution
+//! - OCR cache: `{ocr_cache_dir}/{key}_{page}.ocr_cache` (zstd-compressed JSON;
+//!   plain JSON written by older versions is read as is)
+//! - images cut out by OCR: `{preview_dir}/ocr_image-{provider}-{stem}-{page}-img-{n}.jpeg`
+//!
+//! EPUBs go through the same methods: each spine document is a page, its
+//! text stands in for the text layer and its first image for the preview
+//! (see [`crate::services::epub`]).
+//!
+//! Older imports also left `.jpg` previews, which are still served. Previews
+//! under any other historical name are only found after `migrate-previews`
+//! renamed them; OCR caches named after the raw file name are still read
+//! (`migrate-slugs` renames those).
+
 use log::{error, info};
+use moka::sync::Cache;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::config::{Config, RenderBackend, DEFAULT_RENDER_DPI};
+use crate::models::pdf::pdfinfo_fields;
+use crate::models::{PdfMetadata, SafeFileName};
+use crate::services::epub::{is_epub, render_chapter_preview, EpubBook};
+use crate::services::formula_fallback::{crop_formula, FormulaRegion};
+use crate::utils::slug::{artifact_key, book_id_of, book_slug, BOOK_EXTENSIONS};
+use crate::utils::CommandRunner;
+
+/// OCR cache files kept in memory; the viewer re-reads the same few pages
+/// on every page flip
+const OCR_CACHE_ENTRIES: u64 = 256;
+
+/// Parsed `pdfinfo` output kept in memory, one entry per book version
+const PDF_METADATA_ENTRIES: u64 = 1024;
+
+/// zstd level for OCR payloads; the JSON compresses well at the default
+const OCR_CACHE_ZSTD_LEVEL: i32 = 3;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Disk usage of the OCR cache
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct OcrCacheStats {
+    pub files: u64,
+    pub compressed_files: u64,
+    /// Bytes on disk
+    pub stored_bytes: u64,
+    /// Bytes of the JSON payloads once decompressed
+    pub raw_bytes: u64,
+    /// raw / stored; 1.0 for an empty or uncompressed cache
+    pub compression_ratio: f64,
+}
+
+/// A book in the resources directory: its id, used by routes and the
+/// database, and the file it was imported from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookRef {
+    pub id: String,
+    pub file: String,
+}
+
+/// Book files in the resources directory by book id, as of the directory's
+/// mtime
+#[derive(Default)]
+struct BookIndex {
+    modified: Option<SystemTime>,
+    files: HashMap<String, String>,
+}
+
+impl BookIndex {
+    fn scan(dir: &Path, modified: Option<SystemTime>) -> Self {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| BOOK_EXTENSIONS.iter().any(|ext| name.ends_with(ext)))
+            .collect();
+        // A PDF wins over an EPUB of the same book
+        names.sort_by_key(|name| BOOK_EXTENSIONS.iter().position(|ext| name.ends_with(ext)));
+
+        let mut files = HashMap::new();
+        for name in names {
+            files.entry(book_id_of(&name)).or_insert(name);
+        }
+        Self { modified, files }
+    }
+}
 
 #[derive(Clone)]
 pub struct FileService {
     resources_dir: PathBuf,
     preview_dir: PathBuf,
     ocr_cache_dir: PathBuf,
+    /// OCR cache contents by path and mtime, so files changed behind our
+    /// back are read again
+    ocr_cache: Cache<(PathBuf, SystemTime), Arc<str>>,
+    /// PDF metadata by path, mtime and size, so a replaced book is read again
+    pdf_metadata: Cache<(PathBuf, SystemTime, u64), Arc<PdfMetadata>>,
+    /// One lock per preview being rendered, so concurrent requests for the
+    /// same page wait for a single pdftoppm run
+    preview_renders: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>>,
+    render_backend: RenderBackend,
+    render_dpi: u32,
+    books: Arc<Mutex<BookIndex>>,
 }
 
 impl FileService {
@@ -17,80 +116,224 @@ impl FileService {
             resources_dir,
             preview_dir,
             ocr_cache_dir,
+            ocr_cache: Cache::new(OCR_CACHE_ENTRIES),
+            pdf_metadata: Cache::new(PDF_METADATA_ENTRIES),
+            preview_renders: Arc::default(),
+            render_backend: RenderBackend::Poppler,
+            render_dpi: DEFAULT_RENDER_DPI,
+            books: Arc::default(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            render_backend: config.render_backend,
+            render_dpi: config.render_dpi,
+            ..Self::new(
+                config.resources_dir.clone(),
+                config.preview_dir.clone(),
+                config.ocr_cache_dir.clone(),
+            )
         }
     }
 
-    pub fn get_preview_dir(&self) -> &PathBuf {
-        &self.preview_dir
+    /// The same service rendering previews at `dpi`; caches are shared
+    pub fn with_render_dpi(&self, dpi: u32) -> Self {
+        Self { render_dpi: dpi, ..self.clone() }
+    }
+
+    /// Path of a file under the resources directory, see [`resolve_within`]
+    pub fn resolve_resource(&self, file: &str) -> Result<PathBuf, String> {
+        resolve_within(&self.resources_dir, file)
+    }
+
+    /// Path of a file under the preview directory, see [`resolve_within`]
+    pub fn resolve_preview(&self, file: &str) -> Result<PathBuf, String> {
+        resolve_within(&self.preview_dir, file)
+    }
+
+    /// Book named by either its id or its file name (`algebra-7`,
+    /// `algebra-7.pdf`, `Алгебра 7.pdf`), if its file is in the resources
+    /// directory
+    pub fn resolve_book(&self, reference: &str) -> Option<BookRef> {
+        let modified = fs::metadata(&self.resources_dir).and_then(|m| m.modified()).ok();
+        let mut index = self.books.lock().unwrap_or_else(|e| e.into_inner());
+        if modified.is_none() || index.modified != modified {
+            *index = BookIndex::scan(&self.resources_dir, modified);
+        }
+
+        let id = book_id_of(reference);
+        let file = index.files.get(&id)?.clone();
+        Some(BookRef { id, file })
     }
 
-    pub fn get_resources_dir(&self) -> &PathBuf {
-        &self.resources_dir
+    /// File of the book named by `reference`, see [`Self::resolve_book`].
+    /// Unknown books fall back to the reference itself, or `{id}.pdf` for an
+    /// id, so callers get the usual "not found" errors.
+    pub fn book_file(&self, reference: &str) -> String {
+        match self.resolve_book(reference) {
+            Some(book) => book.file,
+            None if BOOK_EXTENSIONS.iter().any(|ext| reference.ends_with(ext)) => reference.to_string(),
+            None => format!("{}.pdf", reference),
+        }
     }
 
     pub fn get_pdf_page_count(&self, file: &str) -> Result<u32, String> {
-        let metadata = self.get_pdf_metadata(file)?;
-        metadata
-            .get("Pages")
-            .and_then(|p| p.parse().ok())
-            .ok_or_else(|| "Could not parse page count".to_string())
+        Ok(self.get_pdf_metadata(file)?.pages)
     }
 
-    pub fn get_pdf_metadata(&self, file: &str) -> Result<HashMap<String, String>, String> {
-        let file_path = self.resources_dir.join(file);
-        info!("Getting metadata for file: {:?}", file_path);
+    /// Metadata of a PDF (or EPUB), running pdfinfo only once per version of the file
+    pub fn get_pdf_metadata(&self, file: &str) -> Result<PdfMetadata, String> {
+        let file_path = self.resolve_resource(file)?;
+        let stat = fs::metadata(&file_path).map_err(|e| format!("Failed to read {:?}: {}", file_path, e))?;
+        let key = (file_path, stat.modified().unwrap_or(SystemTime::UNIX_EPOCH), stat.len());
+        if let Some(metadata) = self.pdf_metadata.get(&key) {
+            return Ok(metadata.as_ref().clone());
+        }
 
-        let output = Command::new("pdfinfo")
+        let fields = if is_epub(&key.0) { EpubBook::open(&key.0)?.info_fields() } else { read_pdf_info(&key.0)? };
+        let metadata = PdfMetadata::from_fields(&fields)?;
+        self.pdf_metadata.insert(key, Arc::new(metadata.clone()));
+        Ok(metadata)
+    }
+
+    /// Text layer of a PDF, one entry per page. Scanned books without a text
+    /// layer come back as empty pages; EPUBs give the text of each chapter.
+    pub fn extract_pdf_text(&self, file: &str) -> Result<Vec<(u32, String)>, String> {
+        let file_path = self.resolve_resource(file)?;
+        info!("Extracting text layer of: {:?}", file_path);
+        if is_epub(&file_path) {
+            return EpubBook::open(&file_path)?.texts();
+        }
+
+        let output = CommandRunner::new("pdftotext")
+            .arg("-layout")
+            .args(["-eol", "unix"])
             .arg(&file_path)
+            .arg("-")
             .output()
-            .map_err(|e| format!("Failed to execute pdfinfo: {}", e))?;
+            .map_err(|e| format!("Failed to execute pdftotext: {}", e))?;
 
         if !output.status.success() {
-            error!("Failed to get metadata: {:?}", output);
-            return Err("Failed to get metadata".to_string());
+            error!("Failed to extract text: {:?}", output.status);
+            return Err("Failed to extract text".to_string());
         }
 
-        let metadata_str = String::from_utf8_lossy(&output.stdout);
-        let mut metadata = HashMap::new();
+        Ok(crate::services::ingestion::split_pdftotext_pages(&String::from_utf8_lossy(&output.stdout)))
+    }
 
-        for line in metadata_str.lines() {
-            if let Some((key, value)) = line.split_once(':') {
-                metadata.insert(key.trim().to_string(), value.trim().to_string());
+    /// Existing preview of a page with its content type: the PNG, else a JPG
+    pub fn find_preview(&self, file: &str, page: u32) -> Result<Option<(PathBuf, &'static str)>, String> {
+        for (ext, content_type) in [("png", "image/png"), ("jpg", "image/jpeg")] {
+            let name = preview_file_name(file, page, self.render_dpi, ext);
+            let path = self.resolve_preview(&name)?;
+            if path.exists() {
+                return Ok(Some((path, content_type)));
             }
         }
+        Ok(None)
+    }
 
-        Ok(metadata)
+    /// Image to read a page from: its existing preview, else the path the
+    /// preview would be rendered to
+    pub fn page_image(&self, file: &str, page: u32) -> PathBuf {
+        match self.find_preview(file, page) {
+            Ok(Some((path, _))) => path,
+            _ => self.preview_dir.join(preview_file_name(file, page, self.render_dpi, "png")),
+        }
     }
 
+    /// Existing preview of a page, rendering it with pdftoppm (or taking an
+    /// EPUB chapter's image) when there is none
     pub fn generate_preview(&self, file: &str, page: u32) -> Result<PathBuf, String> {
-        let file_path = self.resources_dir.join(file);
-        let preview_path = self
-            .preview_dir
-            .join(format!("{}_{}.png", file.replace('/', "_"), page));
-
-        if !preview_path.exists() {
-            fs::create_dir_all(&self.preview_dir)
-                .map_err(|e| format!("Failed to create preview directory: {}", e))?;
-
-            let output = Command::new("pdftoppm")
-                .arg("-png")
-                .arg("-singlefile")
-                .arg("-f")
-                .arg(page.to_string())
-                .arg("-l")
-                .arg(page.to_string())
-                .arg(&file_path)
-                .arg(preview_path.with_extension("").to_string_lossy().to_string())
-                .output()
-                .map_err(|e| format!("Failed to execute pdftoppm: {}", e))?;
-
-            if !output.status.success() {
-                error!("Failed to generate PNG for preview: {:?}", output);
-                return Err("Failed to generate PNG for preview".to_string());
-            }
+        if let Some((path, _)) = self.find_preview(file, page)? {
+            return Ok(path);
+        }
+
+        let file_path = self.resolve_resource(file)?;
+        let preview_path = self.resolve_preview(&preview_file_name(file, page, self.render_dpi, "png"))?;
+
+        let render = self
+            .preview_renders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(preview_path.clone())
+            .or_default()
+            .clone();
+        let _rendering = render.lock().unwrap_or_else(|e| e.into_inner());
+        let result = self.render_preview(file, page, &file_path, &preview_path);
+        self.preview_renders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&preview_path);
+        result
+    }
+
+    /// Render a page unless a request we waited for already did. The image
+    /// is written under a temporary name of its own and renamed, so a
+    /// half-written file is never served as the preview, and renders in
+    /// another process (the CLI, a second server) can't write into it.
+    fn render_preview(&self, file: &str, page: u32, file_path: &Path, preview_path: &Path) -> Result<PathBuf, String> {
+        if let Some((path, _)) = self.find_preview(file, page)? {
+            return Ok(path);
+        }
+
+        fs::create_dir_all(&self.preview_dir)
+            .map_err(|e| format!("Failed to create preview directory: {}", e))?;
+
+        let partial_path = unique_sibling(preview_path, "partial.png");
+        if let Err(e) = self.render_page(file_path, page, self.render_dpi, &partial_path) {
+            error!("Failed to render page {} of {}: {}", page, file, e);
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+
+        fs::rename(&partial_path, preview_path).map_err(|e| {
+            let _ = fs::remove_file(&partial_path);
+            format!("Failed to save preview: {}", e)
+        })?;
+        Ok(preview_path.to_path_buf())
+    }
+
+    /// Rasterise 1-based `page` of `file_path` to a PNG at `output` with the
+    /// configured backend
+    fn render_page(&self, file_path: &Path, page: u32, dpi: u32, output: &Path) -> Result<(), String> {
+        if is_epub(file_path) {
+            return render_chapter_preview(file_path, page, output);
         }
+        match self.render_backend {
+            #[cfg(feature = "pdfium")]
+            RenderBackend::Pdfium => crate::services::pdfium::render_page_png(file_path, page, dpi, None, output),
+            RenderBackend::Poppler => render_with_pdftoppm(file_path, page, dpi, output),
+        }
+    }
 
-        Ok(preview_path)
+    /// Render only `region` of a page at `dpi`, e.g. a formula sharper than
+    /// its preview. PDFium renders in-process; pdftoppm renders the page (and
+    /// EPUBs give the chapter's image) to a temporary file which is then cropped.
+    pub fn render_region(
+        &self,
+        file: &str,
+        page: u32,
+        region: FormulaRegion,
+        dpi: u32,
+        output: &Path,
+    ) -> Result<(), String> {
+        region.validate()?;
+        let file_path = self.resolve_resource(file)?;
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+        #[cfg(feature = "pdfium")]
+        if self.render_backend == RenderBackend::Pdfium && !is_epub(&file_path) {
+            return crate::services::pdfium::render_page_png(&file_path, page, dpi, Some(region), output);
+        }
+        let page_path = unique_sibling(output, "page.png");
+        let result = self
+            .render_page(&file_path, page, dpi, &page_path)
+            .and_then(|()| crop_formula(&page_path, region, output).map_err(|e| e.to_string()));
+        let _ = fs::remove_file(&page_path);
+        result
     }
 
     pub fn save_ocr_cache(
@@ -102,7 +345,7 @@ impl FileService {
     ) -> Result<(), String> {
         let ocr_cache_path = self
             .ocr_cache_dir
-            .join(format!("{}_{}.ocr_cache", file.replace('/', "_"), page));
+            .join(ocr_cache_file_name(file, page));
 
         let ocr_cache_json = serde_json::json!([
             {
@@ -114,18 +357,683 @@ impl FileService {
         fs::create_dir_all(&self.ocr_cache_dir)
             .map_err(|e| format!("Failed to create OCR cache directory: {}", e))?;
 
-        fs::write(
-            &ocr_cache_path,
-            serde_json::to_string_pretty(&ocr_cache_json)
-                .map_err(|e| format!("Failed to serialize OCR cache: {}", e))?,
-        )
-        .map_err(|e| format!("Failed to write OCR cache: {}", e))
+        // A rewrite within the mtime resolution would keep the old key
+        if let Ok(modified) = fs::metadata(&ocr_cache_path).and_then(|m| m.modified()) {
+            self.ocr_cache.invalidate(&(ocr_cache_path.clone(), modified));
+        }
+
+        let json = serde_json::to_string_pretty(&ocr_cache_json)
+            .map_err(|e| format!("Failed to serialize OCR cache: {}", e))?;
+        // bulk::compress records the content size in the frame, which the stats read
+        let compressed = zstd::bulk::compress(json.as_bytes(), OCR_CACHE_ZSTD_LEVEL)
+            .map_err(|e| format!("Failed to compress OCR cache: {}", e))?;
+        fs::write(&ocr_cache_path, compressed).map_err(|e| format!("Failed to write OCR cache: {}", e))
     }
 
+    /// Cached OCR response of a page, also read from its legacy name
     pub fn get_ocr_cache(&self, file: &str, page: u32) -> Option<String> {
-        let ocr_cache_path = self
-            .ocr_cache_dir
-            .join(format!("{}_{}.ocr_cache", file.replace('/', "_"), page));
-        fs::read_to_string(&ocr_cache_path).ok()
+        [ocr_cache_file_name(file, page), format!("{}_{}.ocr_cache", file, page)]
+            .iter()
+            .filter_map(|name| resolve_within(&self.ocr_cache_dir, name).ok())
+            .find_map(|path| self.read_ocr_cache_file(path))
+    }
+
+    /// Path of a file under the OCR cache directory, see [`resolve_within`]
+    pub fn resolve_ocr_cache(&self, file: &str) -> Result<PathBuf, String> {
+        resolve_within(&self.ocr_cache_dir, file)
+    }
+
+    /// Existing OCR cache file of a page, under its current or legacy name
+    pub fn find_ocr_cache(&self, file: &str, page: u32) -> Option<PathBuf> {
+        [ocr_cache_file_name(file, page), format!("{}_{}.ocr_cache", file, page)]
+            .iter()
+            .filter_map(|name| self.resolve_ocr_cache(name).ok())
+            .find(|path| path.is_file())
+    }
+
+    /// Images OCR cut out of any page of `file`, as (page, file name) in
+    /// page order
+    pub fn ocr_images(&self, file: &str) -> Vec<(u32, String)> {
+        let key = artifact_key(file);
+        let stem = format!("-{}", key.strip_suffix(".pdf").unwrap_or(&key));
+        let mut images: Vec<(u32, String)> = fs::read_dir(&self.preview_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|name| {
+                // ocr_image-{provider}-{stem}-{page}-img-{n}.jpeg, read from the right
+                let rest = name.strip_prefix("ocr_image-")?.strip_suffix(".jpeg")?;
+                let (rest, index) = rest.rsplit_once("-img-")?;
+                let (prefix, page) = rest.rsplit_once('-')?;
+                index.parse::<usize>().ok()?;
+                let page = page.parse::<u32>().ok()?;
+                prefix.ends_with(&stem).then_some((page, name))
+            })
+            .collect();
+        images.sort();
+        images
+    }
+
+    fn read_ocr_cache_file(&self, path: PathBuf) -> Option<String> {
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let key = (path, modified);
+        if let Some(data) = self.ocr_cache.get(&key) {
+            return Some(data.to_string());
+        }
+        let bytes = fs::read(&key.0).ok()?;
+        let bytes = if bytes.starts_with(&ZSTD_MAGIC) {
+            zstd::decode_all(bytes.as_slice())
+                .map_err(|e| error!("Failed to decompress {:?}: {}", key.0, e))
+                .ok()?
+        } else {
+            bytes
+        };
+        let data = String::from_utf8(bytes).ok()?;
+        self.ocr_cache.insert(key, Arc::from(data.as_str()));
+        Some(data)
+    }
+
+    /// Sizes of the files in the OCR cache directory. Compressed sizes come
+    /// from the zstd frame headers, so nothing is decompressed.
+    pub fn ocr_cache_stats(&self) -> Result<OcrCacheStats, String> {
+        let entries = match fs::read_dir(&self.ocr_cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(OcrCacheStats::default().with_ratio()),
+            Err(e) => return Err(format!("Failed to read {:?}: {}", self.ocr_cache_dir, e)),
+        };
+
+        let mut stats = OcrCacheStats::default();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "ocr_cache") {
+                continue;
+            }
+            let Ok(stored) = entry.metadata().map(|m| m.len()) else {
+                continue;
+            };
+            stats.files += 1;
+            stats.stored_bytes += stored;
+            match zstd_content_size(&path) {
+                Some(raw) => {
+                    stats.compressed_files += 1;
+                    stats.raw_bytes += raw;
+                }
+                None => stats.raw_bytes += stored,
+            }
+        }
+        Ok(stats.with_ratio())
+    }
+}
+
+impl OcrCacheStats {
+    fn with_ratio(mut self) -> Self {
+        self.compression_ratio = if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f64 / self.stored_bytes as f64
+        };
+        self
+    }
+}
+
+/// Decompressed size recorded in the header of a zstd file, `None` for
+/// other files
+fn zstd_content_size(path: &Path) -> Option<u64> {
+    use std::io::Read;
+
+    let mut header = Vec::with_capacity(18);
+    fs::File::open(path).ok()?.take(18).read_to_end(&mut header).ok()?;
+    if !header.starts_with(&ZSTD_MAGIC) {
+        return None;
+    }
+    zstd::zstd_safe::get_frame_content_size(&header).ok().flatten()
+}
+
+/// Name of the rendered preview of a page (`ext` is "png" or "jpg").
+/// Previews rendered at another resolution than [`DEFAULT_RENDER_DPI`] carry
+/// it in the name, so a changed `RENDER_DPI` or book `render_dpi` renders
+/// them again instead of serving the old ones.
+pub fn preview_file_name(file: &str, page: u32, dpi: u32, ext: &str) -> String {
+    match dpi {
+        DEFAULT_RENDER_DPI => format!("{}_{}.{}", artifact_key(file), page, ext),
+        dpi => format!("{}_{}@{}dpi.{}", artifact_key(file), page, dpi, ext),
+    }
+}
+
+pub fn ocr_cache_file_name(file: &str, page: u32) -> String {
+    format!("{}_{}.ocr_cache", artifact_key(file), page)
+}
+
+/// Name of an image the OCR provider cut out of a page; the markdown it
+/// returns links to the same name under `/ocr_image/`
+pub fn ocr_image_file_name(provider: &str, file: &str, page: u32, index: usize) -> String {
+    let key = artifact_key(file);
+    let stem = key.strip_suffix(".pdf").unwrap_or(&key);
+    format!("ocr_image-{}-{}-{}-img-{}.jpeg", provider, stem, page, index)
+}
+
+/// Rename `{file}_{page}.{ext}` artifacts in `dir` that were written before
+/// file names were slugged. Returns the (old, new) names; with `dry_run`
+/// nothing is renamed. Existing targets are left alone.
+pub fn migrate_artifact_names(dir: &Path, dry_run: bool) -> Result<Vec<(String, String)>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {:?}: {}", dir, e)),
+    };
+
+    let mut renamed = Vec::new();
+    for name in entries.filter_map(|entry| entry.ok()?.file_name().into_string().ok()) {
+        let Some((stem, ext)) = name.rsplit_once('.') else {
+            continue;
+        };
+        let Some((file, page)) = stem.rsplit_once('_').filter(|(_, page)| page.parse::<u32>().is_ok()) else {
+            continue;
+        };
+        let new_name = format!("{}_{}.{}", artifact_key(file), page, ext);
+        if new_name == name {
+            continue;
+        }
+        if dir.join(&new_name).exists() {
+            log::warn!("Not renaming {} to existing {}", name, new_name);
+            continue;
+        }
+        if !dry_run {
+            fs::rename(dir.join(&name), dir.join(&new_name))
+                .map_err(|e| format!("Failed to rename {}: {}", name, e))?;
+        }
+        renamed.push((name, new_name));
+    }
+    renamed.sort();
+    Ok(renamed)
+}
+
+/// Result of [`migrate_previews`]
+#[derive(Debug, Default)]
+pub struct PreviewMigration {
+    /// (old, new) names
+    pub renamed: Vec<(String, String)>,
+    /// Files that belong to no PDF in the resources directory
+    pub orphans: Vec<String>,
+    /// Legacy files whose canonical name is already taken
+    pub conflicts: Vec<String>,
+}
+
+/// Rename the files in the preview directory from every historical scheme
+/// (`{file}_{page}.png`, `{slug}-{page}.png`, `ocr-image-…`) to the names in
+/// the module docs. Files that can't be matched to a PDF in `resources_dir`
+/// are reported as orphans and left alone, as are subdirectories.
+pub fn migrate_previews(preview_dir: &Path, resources_dir: &Path, dry_run: bool) -> Result<PreviewMigration, String> {
+    let entries = match fs::read_dir(preview_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(PreviewMigration::default()),
+        Err(e) => return Err(format!("Failed to read {:?}: {}", preview_dir, e)),
+    };
+    let books: Vec<String> = fs::read_dir(resources_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".pdf"))
+        .collect();
+
+    let mut migration = PreviewMigration::default();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Some(new_name) = canonical_preview_name(&name, &books) else {
+            migration.orphans.push(name);
+            continue;
+        };
+        if new_name == name {
+            continue;
+        }
+        if preview_dir.join(&new_name).exists() {
+            log::warn!("Not renaming {} to existing {}", name, new_name);
+            migration.conflicts.push(name);
+            continue;
+        }
+        if !dry_run {
+            fs::rename(preview_dir.join(&name), preview_dir.join(&new_name))
+                .map_err(|e| format!("Failed to rename {}: {}", name, e))?;
+        }
+        migration.renamed.push((name, new_name));
+    }
+    migration.renamed.sort();
+    migration.orphans.sort();
+    migration.conflicts.sort();
+    Ok(migration)
+}
+
+/// Canonical name of a file in the preview directory, `None` when it doesn't
+/// follow any known scheme or its PDF is gone
+fn canonical_preview_name(name: &str, books: &[String]) -> Option<String> {
+    // A book under any of the names it had in artifact names
+    let book = |key: &str| {
+        books.iter().find(|file| {
+            let stem = file.strip_suffix(".pdf").unwrap_or(file);
+            let artifact = artifact_key(file);
+            [file.as_str(), stem, &artifact, artifact.strip_suffix(".pdf").unwrap_or(&artifact), &book_slug(stem)]
+                .contains(&key)
+        })
+    };
+
+    if let Some(rest) = name.strip_prefix("ocr_image-").or_else(|| name.strip_prefix("ocr-image-")) {
+        let (head, index) = rest.rsplit_once("-img-")?;
+        let index = index.split_once('.').map_or(index, |(n, _)| n).parse().ok()?;
+        let (head, page) = head.rsplit_once('-')?;
+        let (provider, stem) = head.split_once('-')?;
+        return Some(ocr_image_file_name(provider, book(stem)?, page.parse().ok()?, index));
+    }
+
+    let (stem, ext) = name.rsplit_once('.')?;
+    let (stem, dpi) = stem
+        .rsplit_once('@')
+        .and_then(|(stem, dpi)| Some((stem, dpi.strip_suffix("dpi")?.parse().ok()?)))
+        .unwrap_or((stem, DEFAULT_RENDER_DPI));
+    [stem.rsplit_once('_'), stem.rsplit_once('-')]
+        .into_iter()
+        .flatten()
+        .find_map(|(key, page)| Some(preview_file_name(book(key)?, page.parse().ok()?, dpi, ext)))
+}
+
+/// Resolve a user-supplied relative path inside `base`. The name is checked
+/// lexically first (see [`SafeFileName`]), then the deepest existing part of
+/// the path is canonicalized so symlinks pointing out of `base` are refused
+/// too. The file itself doesn't need to exist yet.
+pub fn resolve_within(base: &Path, relative: &str) -> Result<PathBuf, String> {
+    let name = SafeFileName::try_from(relative.to_string())?;
+    let Ok(base) = canonicalize(base) else {
+        // Nothing can be linked out of a directory that doesn't exist yet
+        return Ok(base.join(name.as_str()));
+    };
+    let path = base.join(name.as_str());
+
+    let existing = path
+        .ancestors()
+        .find(|p| p.symlink_metadata().is_ok())
+        .map_or(Ok(base.clone()), canonicalize)
+        .map_err(|e| format!("Failed to resolve {:?}: {}", path, e))?;
+    if !existing.starts_with(&base) {
+        error!("Refusing path {:?} outside of {:?}", relative, base);
+        return Err(format!("Path escapes its directory: {:?}", relative));
+    }
+
+    Ok(path)
+}
+
+/// [`Path::canonicalize`] without the `\\?\` prefix it adds on Windows,
+/// which poppler's tools can't open
+fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let canonical = path.canonicalize()?;
+    if cfg!(windows)
+        && let Some(simple) = canonical.to_str().and_then(strip_verbatim_prefix)
+    {
+        return Ok(PathBuf::from(simple));
+    }
+    Ok(canonical)
+}
+
+/// `C:\books` for `\\?\C:\books`; `None` for any other path, including
+/// verbatim UNC paths that have no plain drive form
+fn strip_verbatim_prefix(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix(r"\\?\")?;
+    let drive = rest.as_bytes();
+    (drive.len() >= 3 && drive[0].is_ascii_alphabetic() && drive[1] == b':' && drive[2] == b'\\').then_some(rest)
+}
+
+/// `Key: value` fields printed by pdfinfo, or the same keys from PDFium when
+/// pdfinfo is missing and the build has it
+fn read_pdf_info(file_path: &Path) -> Result<HashMap<String, String>, String> {
+    info!("Getting metadata for file: {:?}", file_path);
+
+    let output = match CommandRunner::new("pdfinfo").arg("-isodates").arg(file_path).output() {
+        Ok(output) => output,
+        #[cfg(feature = "pdfium")]
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("pdfinfo is not installed; reading metadata with PDFium");
+            return crate::services::pdfium::metadata(file_path);
+        }
+        Err(e) => return Err(format!("Failed to execute pdfinfo: {}", e)),
+    };
+
+    if !output.status.success() {
+        error!("Failed to get metadata: {:?}", output);
+        return Err("Failed to get metadata".to_string());
+    }
+
+    Ok(pdfinfo_fields(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Render 1-based `page` to the PNG `output` with pdftoppm, or with PDFium
+/// when pdftoppm is missing and the build has it
+/// A temporary name next to `path` no other render uses, e.g.
+/// `algebra-7.pdf_3.{uuid}.partial.png`
+fn unique_sibling(path: &Path, extension: &str) -> PathBuf {
+    path.with_extension(format!("{}.{}", uuid::Uuid::new_v4().simple(), extension))
+}
+
+fn render_with_pdftoppm(pdf: &Path, page: u32, dpi: u32, output: &Path) -> Result<(), String> {
+    // pdftoppm appends the extension to the output root
+    let result = CommandRunner::new("pdftoppm")
+        .arg("-png")
+        .arg("-singlefile")
+        .args(["-r", &dpi.to_string()])
+        .arg("-f")
+        .arg(page.to_string())
+        .arg("-l")
+        .arg(page.to_string())
+        .arg(pdf)
+        .arg(output.with_extension(""))
+        .output();
+    let output = match result {
+        Ok(output) => output,
+        #[cfg(feature = "pdfium")]
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("pdftoppm is not installed; rendering page {} of {:?} with PDFium", page, pdf);
+            return crate::services::pdfium::render_page_png(pdf, page, dpi, None, output);
+        }
+        Err(e) => return Err(format!("Failed to execute pdftoppm: {}", e)),
+    };
+
+    if !output.status.success() {
+        error!("pdftoppm failed: {:?}", output);
+        return Err("Failed to generate PNG for preview".to_string());
+    }
+    Ok(())
+}
+
+/// Text layer of a single PDF page (empty for scanned pages), or the text of
+/// an EPUB's chapter
+pub fn extract_page_text(path: &Path, page: u32) -> Result<String, String> {
+    if is_epub(path) {
+        return EpubBook::open(path)?.chapter_text(page);
+    }
+    let output = CommandRunner::new("pdftotext")
+        .arg("-layout")
+        .args(["-eol", "unix"])
+        .arg("-f")
+        .arg(page.to_string())
+        .arg("-l")
+        .arg(page.to_string())
+        .arg(path)
+        .arg("-")
+        .output()
+        .map_err(|e| format!("Failed to execute pdftotext: {}", e))?;
+
+    if !output.status.success() {
+        error!("Failed to extract text of page {}: {:?}", page, output.status);
+        return Err(format!("Failed to extract text of page {}", page));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\u{c}').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("booker-resolve-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("books/sub")).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        fs::write(dir.join("books/sub/algebra.pdf"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolves_names_inside_base() {
+        let dir = temp_dir("inside");
+        let base = dir.join("books");
+        let canonical = base.canonicalize().unwrap();
+        assert_eq!(resolve_within(&base, "sub/algebra.pdf").unwrap(), canonical.join("sub/algebra.pdf"));
+        assert_eq!(resolve_within(&base, "new_1.png").unwrap(), canonical.join("new_1.png"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_traversal() {
+        let dir = temp_dir("traversal");
+        let base = dir.join("books");
+        // "..%2fsecret.txt" arrives here already percent-decoded
+        for name in ["../secret.txt", "sub/../../secret.txt", "/etc/passwd", "..", "", "..\\secret.txt", "a\0b"] {
+            assert!(resolve_within(&base, name).is_err(), "{:?} was accepted", name);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_base() {
+        let dir = temp_dir("symlink");
+        let base = dir.join("books");
+        std::os::unix::fs::symlink(dir.join("secret.txt"), base.join("link.pdf")).unwrap();
+        std::os::unix::fs::symlink(&dir, base.join("up")).unwrap();
+        assert!(resolve_within(&base, "link.pdf").is_err());
+        assert!(resolve_within(&base, "up/secret.txt").is_err());
+        assert!(resolve_within(&base, "up/missing.png").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finds_legacy_previews_and_ocr_cache() {
+        let dir = temp_dir("legacy");
+        let files = FileService::new(dir.join("books"), dir.join("previews"), dir.join("ocr_cache"));
+        fs::create_dir_all(dir.join("previews")).unwrap();
+        fs::create_dir_all(dir.join("ocr_cache")).unwrap();
+        assert!(files.find_preview("Алгебра 7.pdf", 2).unwrap().is_none());
+        assert_eq!(
+            files.page_image("Алгебра 7.pdf", 2),
+            dir.join("previews").join(preview_file_name("Алгебра 7.pdf", 2, DEFAULT_RENDER_DPI, "png"))
+        );
+
+        fs::write(dir.join("previews/Алгебра 7.pdf_2.png"), "").unwrap();
+        assert!(files.find_preview("Алгебра 7.pdf", 2).unwrap().is_none());
+
+        fs::write(dir.join("previews").join(preview_file_name("Алгебра 7.pdf", 2, DEFAULT_RENDER_DPI, "jpg")), "").unwrap();
+        let (path, content_type) = files.find_preview("Алгебра 7.pdf", 2).unwrap().unwrap();
+        assert!(path.ends_with(preview_file_name("Алгебра 7.pdf", 2, DEFAULT_RENDER_DPI, "jpg")));
+        assert_eq!(content_type, "image/jpeg");
+
+        fs::write(dir.join("previews").join(preview_file_name("Алгебра 7.pdf", 2, DEFAULT_RENDER_DPI, "png")), "").unwrap();
+        let (path, content_type) = files.find_preview("Алгебра 7.pdf", 2).unwrap().unwrap();
+        assert!(path.ends_with(preview_file_name("Алгебра 7.pdf", 2, DEFAULT_RENDER_DPI, "png")));
+        assert_eq!(content_type, "image/png");
+        // Rendered at another resolution, it is a different preview
+        assert!(files.with_render_dpi(300).find_preview("Алгебра 7.pdf", 2).unwrap().is_none());
+        let name = preview_file_name("Алгебра 7.pdf", 2, 300, "png");
+        assert!(name.ends_with("_2@300dpi.png"), "{}", name);
+        assert!(files.with_render_dpi(300).page_image("Алгебра 7.pdf", 2).ends_with(name));
+
+        fs::write(dir.join("ocr_cache/Алгебра 7.pdf_2.ocr_cache"), "[]").unwrap();
+        assert_eq!(files.get_ocr_cache("Алгебра 7.pdf", 2).as_deref(), Some("[]"));
+        files.save_ocr_cache("Алгебра 7.pdf", 2, "mistral", serde_json::json!({})).unwrap();
+        assert!(files.get_ocr_cache("Алгебра 7.pdf", 2).unwrap().contains("mistral"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ocr_cache_reads_follow_writes() {
+        let dir = temp_dir("ocr-lru");
+        let files = FileService::new(dir.join("books"), dir.join("previews"), dir.join("ocr_cache"));
+        assert!(files.get_ocr_cache("algebra.pdf", 1).is_none());
+
+        files.save_ocr_cache("algebra.pdf", 1, "mistral", serde_json::json!({"text": "first"})).unwrap();
+        assert!(files.get_ocr_cache("algebra.pdf", 1).unwrap().contains("first"));
+        files.save_ocr_cache("algebra.pdf", 1, "mistral", serde_json::json!({"text": "second"})).unwrap();
+        assert!(files.get_ocr_cache("algebra.pdf", 1).unwrap().contains("second"));
+
+        // Edited outside the service: a new mtime means a new cache key
+        let path = dir.join("ocr_cache").join(ocr_cache_file_name("algebra.pdf", 1));
+        fs::write(&path, "[\"third\"]").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(60)).unwrap();
+        assert_eq!(files.get_ocr_cache("algebra.pdf", 1).as_deref(), Some("[\"third\"]"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compresses_ocr_payloads() {
+        let dir = temp_dir("ocr-zstd");
+        let files = FileService::new(dir.join("books"), dir.join("previews"), dir.join("ocr_cache"));
+        let markdown = "Решите уравнение $x^2 - 5x + 6 = 0$. ".repeat(200);
+        files.save_ocr_cache("algebra.pdf", 1, "mistral", serde_json::json!({"pages": [{"markdown": markdown}]})).unwrap();
+        fs::write(dir.join("ocr_cache").join(ocr_cache_file_name("algebra.pdf", 2)), "[]").unwrap();
+
+        let stored = fs::read(dir.join("ocr_cache").join(ocr_cache_file_name("algebra.pdf", 1))).unwrap();
+        assert!(stored.starts_with(&ZSTD_MAGIC));
+        assert!(files.get_ocr_cache("algebra.pdf", 1).unwrap().contains(&markdown));
+        assert_eq!(files.get_ocr_cache("algebra.pdf", 2).as_deref(), Some("[]"));
+
+        let stats = files.ocr_cache_stats().unwrap();
+        assert_eq!((stats.files, stats.compressed_files), (2, 1));
+        assert_eq!(stats.stored_bytes, stored.len() as u64 + 2);
+        assert!(stats.raw_bytes > markdown.len() as u64);
+        assert!(stats.compression_ratio > 5.0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn migrates_previews_from_all_schemes() {
+        let dir = temp_dir("previews");
+        let previews = dir.join("previews");
+        fs::create_dir_all(previews.join("formulas")).unwrap();
+        fs::write(dir.join("books/Алгебра 7.pdf"), "").unwrap();
+        fs::write(dir.join("books/geometry.pdf"), "").unwrap();
+        let slug = book_slug("Алгебра 7");
+        for name in [
+            "Алгебра 7.pdf_1.png".to_string(),
+            format!("{}-2.png", slug),
+            "ocr-image-mistral-Алгебра 7-3-img-0.jpeg".to_string(),
+            "geometry.pdf_4.png".to_string(),
+            "geometry.pdf_5@300dpi.png".to_string(),
+            "geometry-4.png".to_string(),
+            "physics.pdf_1.png".to_string(),
+            "notes.txt".to_string(),
+        ] {
+            fs::write(previews.join(name), "").unwrap();
+        }
+
+        let planned = migrate_previews(&previews, &dir.join("books"), true).unwrap();
+        assert_eq!(planned.renamed.len(), 3);
+        assert!(previews.join("Алгебра 7.pdf_1.png").exists());
+
+        let done = migrate_previews(&previews, &dir.join("books"), false).unwrap();
+        for (file, page) in [("Алгебра 7.pdf", 1), ("Алгебра 7.pdf", 2), ("geometry.pdf", 4)] {
+            assert!(previews.join(preview_file_name(file, page, DEFAULT_RENDER_DPI, "png")).exists());
+        }
+        assert!(previews.join(ocr_image_file_name("mistral", "Алгебра 7.pdf", 3, 0)).exists());
+        assert_eq!(done.conflicts, vec!["geometry-4.png".to_string()]);
+        assert_eq!(done.orphans, vec!["notes.txt".to_string(), "physics.pdf_1.png".to_string()]);
+        assert!(previews.join("formulas").is_dir());
+
+        let again = migrate_previews(&previews, &dir.join("books"), false).unwrap();
+        assert!(again.renamed.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ocr_image_names_match_for_unicode_books() {
+        assert_eq!(ocr_image_file_name("mistral", "algebra.pdf", 3, 0), "ocr_image-mistral-algebra-3-img-0.jpeg");
+        let name = ocr_image_file_name("mistral", "Алгебра 7.pdf", 3, 1);
+        assert!(SafeFileName::try_from(name.clone()).is_ok());
+        assert!(!name.contains(' '));
+    }
+
+    #[test]
+    fn migrates_unicode_artifact_names() {
+        let dir = temp_dir("migrate");
+        let previews = dir.join("previews");
+        fs::create_dir_all(&previews).unwrap();
+        for name in ["Алгебра 7.pdf_3.png", "algebra.pdf_1.png", "notes.txt"] {
+            fs::write(previews.join(name), "").unwrap();
+        }
+        fs::write(dir.join("books/Алгебра 7.pdf"), "").unwrap();
+
+        let expected = preview_file_name("Алгебра 7.pdf", 3, DEFAULT_RENDER_DPI, "png");
+        let planned = migrate_artifact_names(&previews, true).unwrap();
+        assert_eq!(planned, vec![("Алгебра 7.pdf_3.png".to_string(), expected.clone())]);
+        assert!(previews.join("Алгебра 7.pdf_3.png").exists());
+
+        migrate_artifact_names(&previews, false).unwrap();
+        assert!(previews.join(&expected).exists());
+        assert!(migrate_artifact_names(&previews, false).unwrap().is_empty());
+
+        let book_id = book_slug("Алгебра 7");
+        let files = FileService::new(dir.join("books"), previews.clone(), dir.join("ocr_cache"));
+        assert_eq!(files.book_file(&book_id), "Алгебра 7.pdf");
+        assert_eq!(preview_file_name(&format!("{}.pdf", book_id), 3, DEFAULT_RENDER_DPI, "png"), expected);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn strips_verbatim_drive_prefixes_only() {
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\books\algebra.pdf"), Some(r"C:\books\algebra.pdf"));
+        assert_eq!(strip_verbatim_prefix(r"\\?\UNC\server\share"), None);
+        assert_eq!(strip_verbatim_prefix(r"C:\books"), None);
+        assert_eq!(strip_verbatim_prefix("/srv/books"), None);
+    }
+
+    #[test]
+    fn resolves_books_by_id_or_file_name() {
+        let dir = temp_dir("books");
+        let books = dir.join("books");
+        for name in ["algebra-7.pdf", "algebra-7.epub", "Геометрия 9.pdf"] {
+            fs::write(books.join(name), "").unwrap();
+        }
+        let files = FileService::new(books.clone(), dir.join("previews"), dir.join("ocr_cache"));
+
+        let algebra = BookRef { id: "algebra-7".to_string(), file: "algebra-7.pdf".to_string() };
+        assert_eq!(files.resolve_book("algebra-7"), Some(algebra.clone()));
+        assert_eq!(files.resolve_book("algebra-7.pdf"), Some(algebra.clone()));
+        assert_eq!(files.resolve_book("algebra-7.epub"), Some(algebra));
+        let geometry = files.resolve_book("Геометрия 9.pdf").unwrap();
+        assert_eq!(geometry.id, book_slug("Геометрия 9"));
+        assert_eq!(files.resolve_book(&geometry.id).unwrap().file, "Геометрия 9.pdf");
+        // Files in subdirectories aren't books of their own
+        assert_eq!(files.resolve_book("algebra"), None);
+        assert_eq!(files.book_file("algebra"), "algebra.pdf");
+        assert_eq!(files.book_file("sub/algebra.pdf"), "sub/algebra.pdf");
+
+        fs::write(books.join("physics.pdf"), "").unwrap();
+        assert_eq!(files.book_file("physics"), "physics.pdf");
+        assert!(files.resolve_book("physics").is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn epub_chapters_are_pages() {
+        let dir = temp_dir("epub");
+        let books = dir.join("books");
+        crate::services::epub::write_sample_epub(&books.join("algebra-7.epub"));
+        let files = FileService::new(books.clone(), dir.join("previews"), dir.join("ocr_cache"));
+
+        let metadata = files.get_pdf_metadata("algebra-7.epub").unwrap();
+        assert_eq!((metadata.pages, metadata.title.as_deref()), (2, Some("Алгебра 7")));
+        let texts = files.extract_pdf_text("algebra-7.epub").unwrap();
+        assert!(texts[0].1.starts_with("Глава 1\n\n171."));
+        assert_eq!(extract_page_text(&books.join("algebra-7.epub"), 2).unwrap(), "172. Figure:");
+
+        let preview = files.generate_preview("algebra-7.epub", 2).unwrap();
+        assert_eq!(preview, dir.join("previews").join(preview_file_name("algebra-7.epub", 2, DEFAULT_RENDER_DPI, "png")));
+        assert!(files.generate_preview("algebra-7.epub", 1).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn page_and_file_params_are_validated_on_extraction() {
+        let params: crate::models::PreviewParams =
+            serde_json::from_value(serde_json::json!({"file": "algebra.pdf", "page": 3})).unwrap();
+        assert_eq!((params.file.as_str(), params.page.get()), ("algebra.pdf", 3));
+        for bad in [
+            serde_json::json!({"file": "../algebra.pdf", "page": 3}),
+            serde_json::json!({"file": "algebra.pdf", "page": 0}),
+        ] {
+            assert!(serde_json::from_value::<crate::models::PreviewParams>(bad).is_err());
+        }
     }
 }
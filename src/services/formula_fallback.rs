@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use lazy_regex::regex;
+use serde::Deserialize;
+
+use crate::services::validation::validate_latex;
+
+/// Failed OCR/validation rounds before a formula may be replaced by an image
+pub const FALLBACK_AFTER_ATTEMPTS: u32 = 3;
+
+/// Resolution formula crops are rendered at, twice the previews' so small
+/// scripts stay legible
+pub const FORMULA_RENDER_DPI: u32 = 300;
+
+/// URL prefix formula crops are served under
+pub const FORMULA_IMAGE_ROUTE: &str = "/formula_image";
+
+/// Rectangle on the page image, as fractions (0.0 - 1.0) of its size
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct FormulaRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl FormulaRegion {
+    pub fn validate(&self) -> Result<(), String> {
+        let in_unit = |v: f64| (0.0..=1.0).contains(&v);
+        if !(in_unit(self.x) && in_unit(self.y) && in_unit(self.width) && in_unit(self.height)) {
+            return Err("Region coordinates must be fractions between 0 and 1".to_string());
+        }
+        if self.width <= 0.0 || self.height <= 0.0 {
+            return Err("Region must have a positive width and height".to_string());
+        }
+        if self.x + self.width > 1.0 + f64::EPSILON || self.y + self.height > 1.0 + f64::EPSILON {
+            return Err("Region extends past the page".to_string());
+        }
+        Ok(())
+    }
+
+    /// Pixel rectangle (x, y, width, height) for an image of the given size
+    pub(crate) fn to_pixels(self, image_width: u32, image_height: u32) -> (u32, u32, u32, u32) {
+        let x = (self.x * image_width as f64).floor() as u32;
+        let y = (self.y * image_height as f64).floor() as u32;
+        let width = ((self.width * image_width as f64).ceil() as u32).clamp(1, image_width - x.min(image_width - 1));
+        let height = ((self.height * image_height as f64).ceil() as u32).clamp(1, image_height - y.min(image_height - 1));
+        (x, y, width, height)
+    }
+}
+
+/// Math segments (`$...$` / `$$...$$`, delimiters included) that fail LaTeX validation.
+///
+/// Only structural errors count; misspelled commands can be fixed as text and
+/// don't warrant an image.
+pub fn invalid_formulas(content: &str) -> Vec<String> {
+    regex!(r"\$\$[^$]+\$\$|\$[^$]+\$")
+        .find_iter(content)
+        .map(|m| m.as_str().to_string())
+        .filter(|f| {
+            validate_latex(f)
+                .iter()
+                .any(|e| !e.starts_with("Possible misspelling"))
+        })
+        .collect()
+}
+
+/// Directory formula crops are written to
+pub fn formula_image_dir(preview_dir: &Path) -> PathBuf {
+    preview_dir.join("formulas")
+}
+
+/// Crop `region` out of a page image and save it as PNG
+pub fn crop_formula(page_image: &Path, region: FormulaRegion, output: &Path) -> anyhow::Result<()> {
+    let image = image::open(page_image)?;
+    let (x, y, width, height) = region.to_pixels(image.width(), image.height());
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image.crop_imm(x, y, width, height).save_with_format(output, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// File name for a problem's formula crop. Problem ids contain `:`, and the
+/// viewer's markdown renderer treats `_` as emphasis, so both become `-`.
+pub fn formula_image_name(problem_id: &str, formula: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let hash = Sha256::digest(formula.as_bytes());
+    let safe_id: String = problem_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{}-{:x}.png", safe_id, u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]))
+}
+
+/// Replace the first occurrence of `formula` with a markdown image reference
+pub fn replace_with_image(content: &str, formula: &str, image_url: &str) -> Option<String> {
+    content
+        .contains(formula)
+        .then(|| content.replacen(formula, &format!("![formula]({})", image_url), 1))
+}
+
+/// Local file behind a `![formula](/formula_image/...)` URL
+pub fn formula_image_path(preview_dir: &Path, url: &str) -> Option<PathBuf> {
+    let name = url.strip_prefix(FORMULA_IMAGE_ROUTE)?.trim_start_matches('/');
+    if name.is_empty() || name.contains("..") || name.contains('/') {
+        return None;
+    }
+    Some(formula_image_dir(preview_dir).join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_broken_formulas_only() {
+        let content = r"Вычислите $\frac{1}{2}$, $\alpha$ и $\sqrt{x$ а также $$a^{2$$";
+        assert_eq!(invalid_formulas(content), vec![r"$\sqrt{x$".to_string(), "$$a^{2$$".to_string()]);
+    }
+
+    #[test]
+    fn replaces_formula_with_image_reference() {
+        let replaced = replace_with_image(r"Решите $\sqrt{x$ = 2", r"$\sqrt{x$", "/formula_image/p.png").unwrap();
+        assert_eq!(replaced, "Решите ![formula](/formula_image/p.png) = 2");
+        assert!(replace_with_image("text", "$x$", "/u").is_none());
+    }
+
+    #[test]
+    fn region_maps_to_pixels_within_bounds() {
+        let region = FormulaRegion { x: 0.5, y: 0.9, width: 0.5, height: 0.1 };
+        assert!(region.validate().is_ok());
+        assert_eq!(region.to_pixels(200, 100), (100, 90, 100, 10));
+        assert!(FormulaRegion { x: 0.8, y: 0.0, width: 0.5, height: 0.1 }.validate().is_err());
+    }
+
+    #[test]
+    fn crops_page_image() {
+        let dir = std::env::temp_dir().join(format!("formula-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("page.png");
+        image::RgbImage::new(40, 20).save(&page).unwrap();
+
+        let output = dir.join("formulas").join(formula_image_name("algebra-7:1:5", "$x$"));
+        crop_formula(&page, FormulaRegion { x: 0.25, y: 0.5, width: 0.5, height: 0.5 }, &output).unwrap();
+        let cropped = image::open(&output).unwrap();
+        assert_eq!((cropped.width(), cropped.height()), (20, 10));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_image_urls_safely() {
+        let dir = Path::new("/previews");
+        assert_eq!(
+            formula_image_path(dir, "/formula_image/a_1.png"),
+            Some(PathBuf::from("/previews/formulas/a_1.png"))
+        );
+        assert!(formula_image_path(dir, "/formula_image/../x.png").is_none());
+        assert!(formula_image_path(dir, "https://example.com/x.png").is_none());
+    }
+}
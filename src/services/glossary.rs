@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use lazy_regex::regex;
+use serde::Serialize;
+
+use crate::models::{Problem, TheoryBlock, TheoryType};
+use crate::services::database::Database;
+use crate::services::knowledge_graph::ConceptExtractor;
+
+/// A term of a book's glossary
+#[derive(Debug, Clone, Serialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub definition: String,
+    /// `theory` (a definition block of the book) or `generated` (written by an LLM)
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chapter_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_number: Option<u32>,
+}
+
+/// Dedup key of a term: lowercase, `ё` as `е`, single spaces, no punctuation
+pub fn term_key(term: &str) -> String {
+    term.to_lowercase()
+        .replace('ё', "е")
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The term a definition block defines: its title (without a leading
+/// "Определение"), else the first emphasised phrase, else the words before
+/// "называется"/"называют"
+pub fn defined_term(block: &TheoryBlock) -> Option<String> {
+    let from_title = block.title.as_deref().map(|t| {
+        regex!(r"(?i)^\s*определение\s*\d*\s*[.:]?\s*").replace(t, "").trim().to_string()
+    });
+    if let Some(title) = from_title.filter(|t| !t.is_empty()) {
+        return Some(title);
+    }
+
+    if let Some(caps) = regex!(r"\*\*([^*]{2,60})\*\*|\*([^*]{2,60})\*").captures(&block.content) {
+        return caps.get(1).or(caps.get(2)).map(|m| m.as_str().trim().to_string());
+    }
+
+    let caps = regex!(r"(?i)(?:^|[.!?]\s+)([^.!?$]{2,60}?)\s+называ(?:ется|ются|ют)\b").captures(&block.content)?;
+    let term = caps.get(1)?.as_str().trim();
+    (term.split_whitespace().count() <= 5).then(|| term.to_string())
+}
+
+/// Glossary entries from the definition blocks of a book's chapters, deduplicated
+/// by term; the first definition in book order wins
+pub fn entries_from_theory(chapters: &[(u32, Vec<TheoryBlock>)]) -> Vec<GlossaryEntry> {
+    let mut entries: Vec<GlossaryEntry> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (chapter_number, blocks) in chapters {
+        for block in blocks.iter().filter(|b| matches!(b.block_type, TheoryType::Definition)) {
+            let Some(term) = defined_term(block) else {
+                continue;
+            };
+            let key = term_key(&term);
+            if key.is_empty() || seen.contains_key(&key) {
+                continue;
+            }
+            seen.insert(key, entries.len());
+            entries.push(GlossaryEntry {
+                term,
+                definition: block.content.trim().to_string(),
+                source: "theory".to_string(),
+                chapter_number: Some(*chapter_number),
+                page_number: block.page_number,
+            });
+        }
+    }
+    entries
+}
+
+/// Concepts mentioned in problems that the glossary doesn't define yet,
+/// most frequent first
+pub fn undefined_concepts(problems: &[Problem], entries: &[GlossaryEntry]) -> Vec<String> {
+    let extractor = ConceptExtractor::new();
+    let defined: Vec<String> = entries.iter().map(|e| term_key(&e.term)).collect();
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for problem in problems {
+        for concept in extractor.extract_concepts(&problem.content) {
+            *counts.entry(concept).or_default() += 1;
+        }
+    }
+
+    let mut concepts: Vec<(String, usize)> = counts
+        .into_iter()
+        .filter(|(concept, _)| {
+            let key = term_key(concept);
+            // "уравнение" is defined by an entry for "уравнение" or "уравнением"
+            !defined.iter().any(|d| d == &key || d.starts_with(&key))
+        })
+        .collect();
+    concepts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    concepts.into_iter().map(|(c, _)| c).collect()
+}
+
+/// Parse `term: definition` / `term — definition` lines of an LLM answer
+pub fn parse_definitions(text: &str) -> Vec<(String, String)> {
+    let line_re = regex!(r"^\s*(?:[-*•]\s*|\d+[.)]\s*)?\**([^:*—–]{2,60}?)\**\s*(?::|—|–| - )\s*(.+?)\s*$");
+    text.lines()
+        .filter_map(|line| {
+            let caps = line_re.captures(line)?;
+            Some((caps[1].trim().to_string(), caps[2].trim().to_string()))
+        })
+        .filter(|(term, definition)| !term.is_empty() && !definition.is_empty())
+        .collect()
+}
+
+/// The book's glossary: theory definitions plus stored generated ones, sorted by term
+pub async fn build_glossary(db: &Database, book_id: &str) -> anyhow::Result<Vec<GlossaryEntry>> {
+    let mut chapters = Vec::new();
+    for chapter in db.get_chapters_by_book(book_id).await? {
+        let blocks = db.get_theory_blocks_by_chapter(&chapter.id).await?;
+        chapters.push((chapter.number, blocks));
+    }
+
+    let mut entries = entries_from_theory(&chapters);
+    let mut keys: Vec<String> = entries.iter().map(|e| term_key(&e.term)).collect();
+    for entry in db.get_generated_glossary(book_id).await? {
+        let key = term_key(&entry.term);
+        if !keys.contains(&key) {
+            keys.push(key);
+            entries.push(entry);
+        }
+    }
+
+    entries.sort_by_key(|e| term_key(&e.term));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(title: Option<&str>, content: &str) -> TheoryBlock {
+        TheoryBlock {
+            id: "t".to_string(),
+            chapter_id: "c".to_string(),
+            block_num: 1,
+            title: title.map(str::to_string),
+            block_type: TheoryType::Definition,
+            content: content.to_string(),
+            latex_formulas: Vec::new(),
+            page_number: Some(5),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn finds_defined_terms() {
+        assert_eq!(defined_term(&definition(Some("Определение 3. Корень уравнения"), "...")).as_deref(), Some("Корень уравнения"));
+        assert_eq!(defined_term(&definition(None, "**Модулем** числа $a$ называют ...")).as_deref(), Some("Модулем"));
+        assert_eq!(
+            defined_term(&definition(None, "Квадратным уравнением называется уравнение вида $ax^2+bx+c=0$.")).as_deref(),
+            Some("Квадратным уравнением")
+        );
+        assert_eq!(defined_term(&definition(None, "Рассмотрим пример.")), None);
+    }
+
+    #[test]
+    fn dedups_terms_across_chapters() {
+        let chapters = vec![
+            (1, vec![definition(Some("Степень"), "Первое определение")]),
+            (2, vec![definition(Some("степень."), "Повтор"), definition(Some("Корень"), "Второе")]),
+        ];
+        let entries = entries_from_theory(&chapters);
+        let terms: Vec<(&str, Option<u32>)> = entries.iter().map(|e| (e.term.as_str(), e.chapter_number)).collect();
+        assert_eq!(terms, vec![("Степень", Some(1)), ("Корень", Some(2))]);
+    }
+
+    #[test]
+    fn parses_llm_definitions() {
+        let text = "- **логарифм**: показатель степени, в которую нужно возвести основание\n2. парабола — график функции $y = x^2$\nвступление без определения";
+        let defs = parse_definitions(text);
+        assert_eq!(defs.len(), 2);
+        assert_eq!(defs[0].0, "логарифм");
+        assert_eq!(defs[1], ("парабола".to_string(), "график функции $y = x^2$".to_string()));
+    }
+}
@@ -0,0 +1,112 @@
+//! Rendered function graphs for the viewer and exports, cached in memory by
+//! a hash of what was drawn.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::services::cache::TimedCache;
+use crate::services::plot::{render_png, render_svg, PlotSpec};
+
+/// Graphs kept; the cache is emptied when it grows past this
+const MAX_CACHED_GRAPHS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GraphFormat {
+    Svg,
+    Png,
+}
+
+impl GraphFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "svg" => Some(GraphFormat::Svg),
+            "png" => Some(GraphFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            GraphFormat::Svg => "image/svg+xml",
+            GraphFormat::Png => "image/png",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderedGraph {
+    pub format: GraphFormat,
+    pub bytes: Arc<Vec<u8>>,
+}
+
+#[derive(Clone)]
+pub struct GraphRenderService {
+    cache: TimedCache<String, Arc<Vec<u8>>>,
+}
+
+impl GraphRenderService {
+    /// Default TTL: 1 day (graphs are cheap to redraw, but pages ask often)
+    const DEFAULT_TTL: i64 = 24 * 60 * 60;
+
+    pub fn new() -> Self {
+        Self { cache: TimedCache::new(Self::DEFAULT_TTL) }
+    }
+
+    /// Hash a graph is cached under, usable as an ETag
+    pub fn graph_hash(spec: &PlotSpec, format: GraphFormat) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format.mime_type().as_bytes());
+        hasher.update(serde_json::to_string(spec).unwrap_or_default().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub async fn render(&self, spec: &PlotSpec, format: GraphFormat) -> Result<RenderedGraph> {
+        let hash = Self::graph_hash(spec, format);
+        if let Some(bytes) = self.cache.get(&hash).await {
+            return Ok(RenderedGraph { format, bytes });
+        }
+
+        let spec = spec.clone();
+        let bytes = tokio::task::spawn_blocking(move || match format {
+            GraphFormat::Svg => render_svg(&spec).map(String::into_bytes),
+            GraphFormat::Png => render_png(&spec),
+        })
+        .await??;
+        let bytes = Arc::new(bytes);
+
+        if self.cache.len().await >= MAX_CACHED_GRAPHS {
+            self.cache.clear().await;
+        }
+        self.cache.set(hash, bytes.clone()).await;
+        Ok(RenderedGraph { format, bytes })
+    }
+}
+
+impl Default for GraphRenderService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn caches_by_spec_and_format() {
+        let service = GraphRenderService::new();
+        let spec = PlotSpec::new(vec!["2x + 1".to_string()]);
+
+        let svg = service.render(&spec, GraphFormat::Svg).await.unwrap();
+        let again = service.render(&spec, GraphFormat::Svg).await.unwrap();
+        assert!(Arc::ptr_eq(&svg.bytes, &again.bytes));
+
+        let png = service.render(&spec, GraphFormat::Png).await.unwrap();
+        assert!(png.bytes.starts_with(b"\x89PNG"));
+        assert_eq!(service.cache.len().await, 2);
+
+        assert!(service.render(&PlotSpec::new(vec!["2x +".to_string()]), GraphFormat::Svg).await.is_err());
+    }
+}
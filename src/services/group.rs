@@ -0,0 +1,261 @@
+//! Groups of students ("7Б") with the assignments handed to them, and what
+//! their answers say about the group as a whole. Members are the names
+//! students submit under, compared without case; there are no accounts.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Problem;
+use crate::services::answer_key::AnswerVerdict;
+use crate::services::assignment::{latest_answers, Assignment, Submission};
+use crate::services::auto_tagger::{LocalClassifier, TagCategory};
+
+/// Topic of problems the classifier finds nothing in
+const NO_TOPIC: &str = "без темы";
+
+/// Most common mistakes listed in the analytics
+const COMMON_MISTAKE_LIMIT: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    /// Student names, sorted
+    pub members: Vec<String>,
+    pub assignment_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Group {
+    pub fn new(name: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            members: Vec::new(),
+            assignment_ids: Vec::new(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// How many members answered an assignment handed to the group
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AssignmentCompletion {
+    pub assignment_id: String,
+    pub title: String,
+    pub due_at: DateTime<Utc>,
+    /// Members who answered every problem
+    pub completed: usize,
+    /// Members who answered some but not all
+    pub partial: usize,
+    pub members: usize,
+}
+
+/// Graded answers of the group to problems of one topic
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopicMistakes {
+    pub topic: String,
+    pub answers: usize,
+    pub correct: usize,
+    pub mistakes: usize,
+    /// Share of graded answers that are wrong, 0-100
+    pub mistake_percent: f64,
+}
+
+/// The same wrong answer given by several members
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommonMistake {
+    pub problem_id: String,
+    pub number: String,
+    pub answer: String,
+    pub students: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupAnalytics {
+    pub group_id: String,
+    pub name: String,
+    pub members: usize,
+    pub assignments: Vec<AssignmentCompletion>,
+    /// Most mistaken topics first
+    pub topics: Vec<TopicMistakes>,
+    pub common_mistakes: Vec<CommonMistake>,
+    /// Members with no answer to any of the group's assignments
+    pub inactive_members: Vec<String>,
+}
+
+/// Topics of a problem for grouping mistakes: its topic and concept tags,
+/// or its subject when it has none
+pub fn problem_topics(classifier: &LocalClassifier, problem: &Problem) -> Vec<String> {
+    let tags = classifier.tag_problem(problem).tags;
+    let pick = |categories: &[TagCategory]| -> Vec<String> {
+        tags.iter().filter(|t| categories.contains(&t.category)).map(|t| t.name.clone()).collect()
+    };
+    let topics = pick(&[TagCategory::Topic, TagCategory::Concept]);
+    if !topics.is_empty() {
+        return topics;
+    }
+    let subjects = pick(&[TagCategory::Subject]);
+    if subjects.is_empty() { vec![NO_TOPIC.to_string()] } else { subjects }
+}
+
+/// Completion per assignment and mistakes per topic of the group's members.
+/// Only each member's latest answer to a problem counts; answers that
+/// couldn't be graded count towards completion but not towards mistakes.
+pub fn group_analytics(
+    group: &Group,
+    assignments: &[(Assignment, Vec<Submission>)],
+    problems: &HashMap<String, Problem>,
+) -> GroupAnalytics {
+    let members: BTreeSet<String> = group.members.iter().map(|m| m.to_lowercase()).collect();
+    let classifier = LocalClassifier::new();
+    let mut topic_cache: HashMap<&str, Vec<String>> = HashMap::new();
+
+    let mut active: BTreeSet<String> = BTreeSet::new();
+    let mut topics: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut wrong: HashMap<(&str, String), (String, usize)> = HashMap::new();
+    let mut completion = Vec::with_capacity(assignments.len());
+
+    for (assignment, submissions) in assignments {
+        let mut latest = latest_answers(submissions);
+        latest.retain(|(student, problem_id), _| {
+            members.contains(student) && assignment.problem_ids.iter().any(|id| id == problem_id)
+        });
+
+        let mut answered: HashMap<&str, usize> = HashMap::new();
+        for ((student, problem_id), submission) in &latest {
+            *answered.entry(student.as_str()).or_default() += 1;
+            active.insert(student.clone());
+            if submission.verdict == AnswerVerdict::Unknown {
+                continue;
+            }
+
+            let Some(problem) = problems.get(*problem_id) else {
+                continue;
+            };
+            let correct = submission.verdict == AnswerVerdict::Match;
+            let problem_tags = topic_cache.entry(*problem_id).or_insert_with(|| problem_topics(&classifier, problem));
+            for topic in problem_tags.iter() {
+                let entry = topics.entry(topic.clone()).or_default();
+                entry.0 += 1;
+                entry.1 += usize::from(correct);
+            }
+            if let (false, Some(answer)) = (correct, submission.answer.as_deref()) {
+                let key = answer.split_whitespace().collect::<Vec<_>>().join(" ");
+                let entry = wrong.entry((*problem_id, key.to_lowercase())).or_insert((key, 0));
+                entry.1 += 1;
+            }
+        }
+
+        let total = assignment.problem_ids.len();
+        completion.push(AssignmentCompletion {
+            assignment_id: assignment.id.clone(),
+            title: assignment.title.clone(),
+            due_at: assignment.due_at,
+            completed: answered.values().filter(|&&n| n >= total).count(),
+            partial: answered.values().filter(|&&n| n < total).count(),
+            members: members.len(),
+        });
+    }
+
+    let mut topics: Vec<TopicMistakes> = topics
+        .into_iter()
+        .map(|(topic, (answers, correct))| TopicMistakes {
+            topic,
+            answers,
+            correct,
+            mistakes: answers - correct,
+            mistake_percent: ((answers - correct) as f64 * 1000.0 / answers as f64).round() / 10.0,
+        })
+        .collect();
+    topics.sort_by(|a, b| b.mistake_percent.total_cmp(&a.mistake_percent).then(b.answers.cmp(&a.answers)));
+
+    let mut common_mistakes: Vec<CommonMistake> = wrong
+        .into_iter()
+        .filter(|(_, (_, students))| *students > 1)
+        .map(|((problem_id, _), (answer, students))| CommonMistake {
+            problem_id: problem_id.to_string(),
+            number: problems.get(problem_id).map(|p| p.number.clone()).unwrap_or_default(),
+            answer,
+            students,
+        })
+        .collect();
+    common_mistakes.sort_by(|a, b| {
+        b.students.cmp(&a.students).then_with(|| a.problem_id.cmp(&b.problem_id)).then_with(|| a.answer.cmp(&b.answer))
+    });
+    common_mistakes.truncate(COMMON_MISTAKE_LIMIT);
+
+    GroupAnalytics {
+        group_id: group.id.clone(),
+        name: group.name.clone(),
+        members: members.len(),
+        assignments: completion,
+        topics,
+        common_mistakes,
+        inactive_members: group.members.iter().filter(|m| !active.contains(&m.to_lowercase())).cloned().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::assignment::SubmissionSource;
+
+    fn problem(id: &str, content: &str) -> Problem {
+        Problem {
+            id: id.to_string(),
+            number: id.rsplit(':').next().unwrap().to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn answer(student: &str, problem_id: &str, text: &str, verdict: AnswerVerdict) -> Submission {
+        Submission {
+            id: uuid::Uuid::new_v4().to_string(),
+            assignment_id: "a".to_string(),
+            problem_id: problem_id.to_string(),
+            student_name: student.to_string(),
+            source: SubmissionSource::Typed,
+            answer: Some(text.to_string()),
+            photo_key: None,
+            verdict,
+            late: false,
+            submitted_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn summarizes_completion_and_mistakes_by_topic() {
+        let problems: HashMap<String, Problem> = [
+            problem("b:1:1", "Решите квадратное уравнение x² - 5x + 6 = 0"),
+            problem("b:1:2", "Найдите log_2 8"),
+        ]
+        .into_iter()
+        .map(|p| (p.id.clone(), p))
+        .collect();
+
+        let mut group = Group::new("7Б");
+        group.members = vec!["Аня".to_string(), "Борис".to_string(), "Вера".to_string()];
+        let assignment = Assignment::new("ДЗ", None, vec!["b:1:1".to_string(), "b:1:2".to_string()], Utc::now());
+        let submissions = vec![
+            answer("аня", "b:1:1", "x = 2", AnswerVerdict::Mismatch),
+            answer("Аня", "b:1:2", "3", AnswerVerdict::Match),
+            answer("Борис", "b:1:1", "x  =  2", AnswerVerdict::Mismatch),
+            answer("Гость", "b:1:1", "x = 2", AnswerVerdict::Mismatch),
+        ];
+
+        let analytics = group_analytics(&group, &[(assignment, submissions)], &problems);
+        let completion = &analytics.assignments[0];
+        assert_eq!((completion.completed, completion.partial, completion.members), (1, 1, 3));
+        assert_eq!(analytics.inactive_members, ["Вера"]);
+
+        let worst = &analytics.topics[0];
+        assert_eq!((worst.topic.as_str(), worst.mistakes, worst.mistake_percent), ("квадратные уравнения", 2, 100.0));
+        assert_eq!(analytics.topics.last().unwrap().topic, "логарифм");
+        assert_eq!(analytics.common_mistakes.len(), 1);
+        assert_eq!((analytics.common_mistakes[0].number.as_str(), analytics.common_mistakes[0].students), ("1", 2));
+    }
+}
@@ -0,0 +1,223 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::services::database::Database;
+use crate::services::toc_detector::TocDetector;
+
+/// Chapter/section headings recognized in every book. Each pattern is
+/// matched case-insensitively against a whole trimmed line and captures the
+/// heading number as `num` (Arabic or Roman).
+pub const DEFAULT_HEADING_PATTERNS: &[&str] = &[
+    r"^глава\s+(?P<num>\d+|[ivxlcdm]+)(?:[\s.:]|$)",
+    r"^§\s*(?P<num>\d+)(?:[\s.:]|$)",
+    r"^параграф\s+(?P<num>\d+)(?:[\s.:]|$)",
+    r"^раздел\s+(?P<num>\d+|[ivxlcdm]+)(?:[\s.:]|$)",
+    r"^chapter\s+(?P<num>\d+|[ivxlcdm]+)(?:[\s.:]|$)",
+    r"^unit\s+(?P<num>\d+)(?:[\s.:]|$)",
+];
+
+/// Lines searched for a heading at the top of a page
+const PAGE_HEAD_LINES: usize = 10;
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_DETECTOR: HeadingDetector = HeadingDetector::new(&HeadingOverrides::default())
+        .expect("default heading patterns compile");
+}
+
+/// Per-book heading patterns, added to the defaults or replacing them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeadingOverrides {
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Use only `patterns`, e.g. for a book whose "§" marks theory, not chapters
+    #[serde(default)]
+    pub replace_defaults: bool,
+}
+
+impl HeadingOverrides {
+    pub fn validate(&self) -> Result<(), String> {
+        for pattern in &self.patterns {
+            compile(pattern)?;
+        }
+        if self.replace_defaults && self.patterns.is_empty() {
+            return Err("replace_defaults needs at least one pattern".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A heading line found in page text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub number: u32,
+    /// Text after the number on the same line, e.g. the chapter title
+    pub title: String,
+}
+
+/// Recognizes chapter headings ("Глава 5", "§ 3", "Chapter IV", ...)
+pub struct HeadingDetector {
+    patterns: Vec<Regex>,
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid heading pattern '{}': {}", pattern, e))?;
+    if !regex.capture_names().any(|name| name == Some("num")) {
+        return Err(format!("Heading pattern '{}' must capture the number as (?P<num>...)", pattern));
+    }
+    Ok(regex)
+}
+
+impl HeadingDetector {
+    pub fn new(overrides: &HeadingOverrides) -> Result<Self, String> {
+        let defaults = DEFAULT_HEADING_PATTERNS.iter().copied().filter(|_| !overrides.replace_defaults);
+        let patterns = overrides
+            .patterns
+            .iter()
+            .map(String::as_str)
+            .chain(defaults)
+            .map(compile)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Detector with the default patterns only
+    pub fn defaults() -> &'static HeadingDetector {
+        &DEFAULT_DETECTOR
+    }
+
+    /// Detector for a book, falling back to the defaults when its overrides
+    /// can't be loaded
+    pub async fn for_book(db: &Database, book_id: &str) -> HeadingDetector {
+        let overrides = match db.get_heading_overrides(book_id).await {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                log::warn!("Failed to load heading patterns of {}: {}", book_id, e);
+                HeadingOverrides::default()
+            }
+        };
+        HeadingDetector::new(&overrides).unwrap_or_else(|e| {
+            log::warn!("Ignoring heading patterns of {}: {}", book_id, e);
+            HeadingDetector::new(&HeadingOverrides::default()).expect("default heading patterns compile")
+        })
+    }
+
+    pub fn match_line(&self, line: &str) -> Option<Heading> {
+        let line = line.trim();
+        self.patterns.iter().find_map(|pattern| {
+            let caps = pattern.captures(line)?;
+            let num = caps.name("num")?.as_str();
+            let number = num.parse::<u32>().ok().or_else(|| TocDetector::roman_to_arabic(num))?;
+            let title = line[caps.get(0)?.end()..]
+                .trim_start_matches(|c: char| c == '.' || c == ':' || c.is_whitespace())
+                .trim_end()
+                .to_string();
+            Some(Heading { number, title })
+        })
+    }
+
+    pub fn is_heading_line(&self, line: &str) -> bool {
+        self.match_line(line).is_some()
+    }
+
+    /// First heading among the top lines of a page, with the title taken
+    /// from the next non-empty line when the heading line has none
+    pub fn find_page_heading(&self, text: &str) -> Option<Heading> {
+        let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).take(PAGE_HEAD_LINES).collect();
+        let (idx, mut heading) = lines.iter().enumerate().find_map(|(i, l)| Some((i, self.match_line(l)?)))?;
+        if heading.title.is_empty() {
+            heading.title = lines
+                .get(idx + 1)
+                .map(|l| l.trim())
+                .filter(|l| l.chars().count() < 200)
+                .unwrap_or("Untitled Chapter")
+                .to_string();
+        }
+        Some(heading)
+    }
+
+    /// A chapter heading printed at the bottom of a page belongs to the next
+    /// page. Returns the page text without it and the heading to carry over.
+    pub fn split_trailing_heading(&self, text: &str) -> (String, Option<String>) {
+        let lines: Vec<&str> = text.lines().collect();
+        let Some(last_non_empty_idx) = lines.iter().rposition(|l| !l.trim().is_empty()) else {
+            return (String::new(), None);
+        };
+
+        if !self.is_heading_line(lines[last_non_empty_idx]) {
+            return (text.trim().to_string(), None);
+        }
+
+        let current = lines[..last_non_empty_idx].join("\n").trim().to_string();
+        let carryover = lines[last_non_empty_idx..].join("\n").trim().to_string();
+
+        if carryover.is_empty() {
+            (current, None)
+        } else {
+            (current, Some(carryover))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_trailing_chapter_header_into_carryover() {
+        let text = "702. Последняя задача.\nГлава 5. Разложение многочленов на множители";
+        let (page_text, carryover) = HeadingDetector::defaults().split_trailing_heading(text);
+
+        assert_eq!(page_text, "702. Последняя задача.");
+        assert_eq!(
+            carryover.as_deref(),
+            Some("Глава 5. Разложение многочленов на множители")
+        );
+    }
+
+    #[test]
+    fn leaves_text_intact_without_trailing_chapter_header() {
+        let text = "701. Обычная задача без заголовка главы.";
+        let (page_text, carryover) = HeadingDetector::defaults().split_trailing_heading(text);
+
+        assert_eq!(page_text, text);
+        assert!(carryover.is_none());
+    }
+
+    #[test]
+    fn matches_default_heading_styles() {
+        let detector = HeadingDetector::defaults();
+        let heading = |line: &str| detector.match_line(line).map(|h| (h.number, h.title));
+        assert_eq!(heading("Глава IV. Функции"), Some((4, "Функции".to_string())));
+        assert_eq!(heading("§ 12. Квадратный корень"), Some((12, "Квадратный корень".to_string())));
+        assert_eq!(heading("Параграф 3"), Some((3, String::new())));
+        assert_eq!(heading("CHAPTER 2: Limits"), Some((2, "Limits".to_string())));
+        assert_eq!(heading("Unit 7 Vectors"), Some((7, "Vectors".to_string())));
+        assert_eq!(heading("Глава"), None);
+        assert_eq!(heading("Главное правило 5"), None);
+        assert_eq!(heading("См. § 12"), None);
+    }
+
+    #[test]
+    fn book_overrides_extend_or_replace_defaults() {
+        let lesson = HeadingOverrides { patterns: vec![r"^урок\s+(?P<num>\d+)".to_string()], replace_defaults: false };
+        let detector = HeadingDetector::new(&lesson).unwrap();
+        assert!(detector.is_heading_line("Урок 3. Дроби"));
+        assert!(detector.is_heading_line("Глава 1"));
+
+        let only = HeadingOverrides { replace_defaults: true, ..lesson };
+        assert!(!HeadingDetector::new(&only).unwrap().is_heading_line("§ 2"));
+
+        let no_number = HeadingOverrides { patterns: vec![r"^урок\s+\d+".to_string()], replace_defaults: false };
+        assert!(no_number.validate().is_err());
+    }
+
+    #[test]
+    fn finds_heading_near_page_top() {
+        let page = "Алгебра 7\n\nГлава 2\nНеравенства\nРешите неравенство.";
+        let heading = HeadingDetector::defaults().find_page_heading(page).unwrap();
+        assert_eq!((heading.number, heading.title.as_str()), (2, "Неравенства"));
+    }
+}
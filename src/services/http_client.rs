@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::Config;
+
+lazy_static::lazy_static! {
+    static ref GLOBAL: Arc<HttpClientFactory> = Arc::new(HttpClientFactory::from_config(&Config::new()));
+}
+
+/// Idle connections kept open per host
+const MAX_IDLE_PER_HOST: usize = 8;
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Builds the HTTP clients of all providers with the same user agent and
+/// proxy settings. Clients are shared per timeout, so providers calling the
+/// same API reuse its pooled connections.
+pub struct HttpClientFactory {
+    user_agent: String,
+    /// Explicit proxy from the config (http(s) or socks5). Without one,
+    /// reqwest picks up `HTTP(S)_PROXY` itself.
+    proxy: Option<String>,
+    clients: Mutex<HashMap<Duration, reqwest::Client>>,
+}
+
+impl HttpClientFactory {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            user_agent: std::env::var("HTTP_USER_AGENT")
+                .ok()
+                .filter(|ua| !ua.is_empty())
+                .unwrap_or_else(|| format!("booker-web/{}", env!("CARGO_PKG_VERSION"))),
+            proxy: config.proxy_url.clone(),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn global() -> Arc<HttpClientFactory> {
+        GLOBAL.clone()
+    }
+
+    /// Pooled client whose requests are abandoned after `timeout`
+    pub fn client(&self, timeout: Duration) -> reqwest::Client {
+        let Ok(mut clients) = self.clients.lock() else {
+            return self.build(timeout);
+        };
+        clients.entry(timeout).or_insert_with(|| self.build(timeout)).clone()
+    }
+
+    fn build(&self, timeout: Duration) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(&self.user_agent)
+            .timeout(timeout)
+            .pool_max_idle_per_host(MAX_IDLE_PER_HOST)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT);
+
+        if let Some(proxy) = &self.proxy {
+            match reqwest::Proxy::all(proxy) {
+                Ok(proxy) => builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env())),
+                Err(e) => log::warn!(
+                    "Ignoring proxy {} ({}); SOCKS proxies need the `socks` feature",
+                    proxy,
+                    e
+                ),
+            }
+        }
+
+        builder.build().unwrap_or_else(|e| {
+            log::warn!("Failed to build HTTP client, using defaults: {}", e);
+            reqwest::Client::new()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_clients_per_timeout() {
+        let factory = HttpClientFactory {
+            user_agent: "test".to_string(),
+            proxy: Some("not a proxy url".to_string()),
+            clients: Mutex::new(HashMap::new()),
+        };
+        factory.client(Duration::from_secs(10));
+        factory.client(Duration::from_secs(10));
+        factory.client(Duration::from_secs(120));
+        assert_eq!(factory.clients.lock().unwrap().len(), 2);
+    }
+}
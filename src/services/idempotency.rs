@@ -0,0 +1,84 @@
+//! `Idempotency-Key` support for mutating requests. The first request with a
+//! key claims it and its response is stored; a retry with the same key gets
+//! that response back instead of running the handler again, so a resent
+//! batch submission doesn't start a second job.
+
+use sha2::{Digest, Sha256};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Set on responses replayed from the store
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "Idempotent-Replayed";
+
+/// Longest request body read to fingerprint a keyed request; larger keyed
+/// requests are rejected
+pub const MAX_KEYED_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+/// Longest response body stored for replay; larger responses are returned
+/// but the key is released
+pub const MAX_STORED_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+/// What a request holding a key should do
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyClaim {
+    /// First use of the key: run the request and store its response
+    New,
+    /// Another request with the key hasn't finished yet
+    InProgress,
+    /// The key was used for a different request
+    Mismatch,
+    Completed(StoredResponse),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Keys are 1-255 printable ASCII characters, e.g. a UUID
+pub fn valid_key(key: &str) -> bool {
+    (1..=255).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Identity of a request for detecting a key reused on another request
+pub fn request_fingerprint(method: &str, path: &str, query: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for part in [method.as_bytes(), path.as_bytes(), query.as_bytes()] {
+        hasher.update(part);
+        hasher.update(b"\n");
+    }
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Whether a response is kept for replay. Server errors are not: the
+/// request may succeed when retried.
+pub fn should_store(status: u16, body_len: usize) -> bool {
+    status < 500 && body_len <= MAX_STORED_RESPONSE_BYTES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_cover_method_path_and_body() {
+        let base = request_fingerprint("POST", "/api/batch/ocr", "", b"{\"book_id\":\"a\"}");
+        assert_eq!(base, request_fingerprint("POST", "/api/batch/ocr", "", b"{\"book_id\":\"a\"}"));
+        assert_ne!(base, request_fingerprint("POST", "/api/batch/ocr", "", b"{\"book_id\":\"b\"}"));
+        assert_ne!(base, request_fingerprint("PUT", "/api/batch/ocr", "", b"{\"book_id\":\"a\"}"));
+        assert_ne!(base, request_fingerprint("POST", "/api/batch/solve", "", b"{\"book_id\":\"a\"}"));
+    }
+
+    #[test]
+    fn validates_keys_and_storable_responses() {
+        assert!(valid_key("3f1c2a9e-6b7d-4e52-9a41-0c8f5d2e7b10"));
+        assert!(!valid_key(""));
+        assert!(!valid_key("two words"));
+        assert!(!valid_key(&"k".repeat(256)));
+        assert!(should_store(201, 100));
+        assert!(should_store(422, 100));
+        assert!(!should_store(503, 100));
+        assert!(!should_store(200, MAX_STORED_RESPONSE_BYTES + 1));
+    }
+}
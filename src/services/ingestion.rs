@@ -0,0 +1,308 @@
+use lazy_regex::regex;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::services::heading_detector::HeadingDetector;
+use crate::services::toc_detector::TocDetector;
+
+/// Largest page span the batch OCR endpoint accepts (`end - start`)
+const MAX_BATCH_SPAN: u32 = 100;
+
+/// Pages with fewer letters/digits than this are treated as blank
+const BLANK_PAGE_CHARS: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PageSpan {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedChapter {
+    pub chapter_id: String,
+    pub number: u32,
+    pub title: String,
+    pub start_page: u32,
+    pub end_page: u32,
+    /// `headings` (found on the pages), `toc` (printed page numbers) or `default`
+    pub source: &'static str,
+    pub confidence: f32,
+}
+
+/// A request body for `POST /api/batch/ocr`
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedBatch {
+    pub book_id: String,
+    pub start_page: u32,
+    pub end_page: u32,
+    pub chapter_id: String,
+    pub incremental: bool,
+}
+
+/// Suggested way to ingest a book, derived from its page texts
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestionPlan {
+    pub book_id: String,
+    pub total_pages: u32,
+    pub content_pages: PageSpan,
+    pub blank_pages: Vec<u32>,
+    pub toc_pages: Vec<u32>,
+    pub answer_pages: Option<PageSpan>,
+    pub chapters: Vec<PlannedChapter>,
+    pub batch_ocr: Vec<PlannedBatch>,
+    pub notes: Vec<String>,
+}
+
+fn is_blank(text: &str) -> bool {
+    text.chars().filter(|c| c.is_alphanumeric()).take(BLANK_PAGE_CHARS).count() < BLANK_PAGE_CHARS
+}
+
+fn head_lines(text: &str, n: usize) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|l| !l.is_empty()).take(n)
+}
+
+fn has_heading(text: &str, heading: &Regex) -> bool {
+    head_lines(text, 5).any(|l| heading.is_match(l))
+}
+
+fn is_toc_page(detector: &TocDetector, text: &str) -> bool {
+    let toc_heading = regex!(r"(?i)^(оглавление|содержание|contents|table of contents)\b");
+    has_heading(text, toc_heading)
+        || detector
+            .detect_toc(text)
+            .is_some_and(|toc| toc.entries.len() >= 3 && toc.confidence >= 0.3)
+}
+
+/// Build an ingestion plan from `(page_number, text)` pairs. Pages missing from
+/// `pages` are assumed to have no text available (not necessarily blank).
+pub fn analyze_pages(book_id: &str, total_pages: u32, pages: &[(u32, String)], headings: &HeadingDetector) -> IngestionPlan {
+    let detector = TocDetector::new();
+    let mut notes = Vec::new();
+
+    let with_text: Vec<&(u32, String)> = pages.iter().filter(|(p, _)| *p >= 1 && *p <= total_pages).collect();
+    let blank_pages: Vec<u32> = with_text.iter().filter(|(_, t)| is_blank(t)).map(|(p, _)| *p).collect();
+    let text_pages: Vec<&(u32, String)> = with_text.iter().copied().filter(|(_, t)| !is_blank(t)).collect();
+
+    if text_pages.is_empty() {
+        notes.push("No text layer or OCR text found; OCR a few pages to get a better plan".to_string());
+        let content = PageSpan { start: 1, end: total_pages.max(1) };
+        let chapters = vec![default_chapter(book_id, content)];
+        return IngestionPlan {
+            book_id: book_id.to_string(),
+            total_pages,
+            content_pages: content,
+            blank_pages,
+            toc_pages: Vec::new(),
+            answer_pages: None,
+            batch_ocr: plan_batches(book_id, &chapters),
+            chapters,
+            notes,
+        };
+    }
+
+    // TOC pages sit in the first fifth or the last tenth of the book
+    let front_limit = (total_pages / 5).max(3);
+    let back_limit = total_pages.saturating_sub((total_pages / 10).max(3));
+    let toc_pages: Vec<u32> = text_pages
+        .iter()
+        .filter(|(p, _)| *p <= front_limit || *p > back_limit)
+        .filter(|(_, t)| is_toc_page(&detector, t))
+        .map(|(p, _)| *p)
+        .collect();
+
+    // Answers start at a heading in the back half and run until the back
+    // matter (index, TOC) or the end of the book
+    let answers_heading = regex!(r"(?i)^(ответы|answers)\b");
+    let back_matter_heading = regex!(r"(?i)^(оглавление|содержание|предметный указатель|алфавитный указатель|список литературы|литература|index|contents|bibliography)\b");
+
+    let answers_start = text_pages
+        .iter()
+        .filter(|(p, _)| *p > total_pages / 2)
+        .find(|(_, t)| has_heading(t, answers_heading))
+        .map(|(p, _)| *p);
+    let back_matter_start = text_pages
+        .iter()
+        .filter(|(p, _)| *p > back_limit.min(answers_start.unwrap_or(u32::MAX)))
+        .find(|(p, t)| toc_pages.contains(p) || has_heading(t, back_matter_heading))
+        .map(|(p, _)| *p);
+
+    let last_text_before = |limit: u32| {
+        text_pages.iter().map(|(p, _)| *p).filter(|p| *p < limit).max()
+    };
+
+    let answer_pages = answers_start.map(|start| PageSpan {
+        start,
+        end: last_text_before(back_matter_start.unwrap_or(total_pages + 1)).unwrap_or(start).max(start),
+    });
+
+    let content_limit = answers_start.or(back_matter_start).unwrap_or(total_pages + 1);
+    let front_toc_end = toc_pages.iter().copied().filter(|p| *p <= front_limit).max().unwrap_or(0);
+    let first_text = text_pages.iter().map(|(p, _)| *p).find(|p| *p > front_toc_end);
+
+    let content_pages: Vec<(u32, String)> = text_pages
+        .iter()
+        .filter(|(p, _)| *p > front_toc_end && *p < content_limit)
+        .map(|(p, t)| (*p, t.clone()))
+        .collect();
+    let mut detected = detector.detect_chapters_from_pages(&content_pages, headings);
+
+    let mut content = PageSpan {
+        start: detected.first().map(|c| c.start_page).or(first_text).unwrap_or(1),
+        end: last_text_before(content_limit).unwrap_or(total_pages.max(1)),
+    };
+    if content.end < content.start {
+        content.end = content.start;
+    }
+
+    let chapters: Vec<PlannedChapter> = if !detected.is_empty() {
+        if let Some(last) = detected.last_mut() {
+            last.end_page = Some(content.end);
+        }
+        detected
+            .iter()
+            .map(|c| PlannedChapter {
+                chapter_id: format!("{}:{}", book_id, c.number),
+                number: c.number,
+                title: c.title.clone(),
+                start_page: c.start_page,
+                end_page: c.end_page.unwrap_or(content.end).max(c.start_page),
+                source: "headings",
+                confidence: c.confidence,
+            })
+            .collect()
+    } else if let Some(toc) = toc_pages
+        .iter()
+        .filter_map(|p| text_pages.iter().find(|(tp, _)| tp == p))
+        .filter_map(|(_, t)| detector.detect_toc(t))
+        .max_by_key(|toc| toc.entries.len())
+        .filter(|toc| toc.entries.iter().all(|e| e.page_number.is_some()))
+    {
+        notes.push("Chapters taken from the table of contents; printed page numbers may be offset from PDF pages".to_string());
+        let starts: Vec<u32> = toc.entries.iter().filter_map(|e| e.page_number).collect();
+        toc.entries
+            .iter()
+            .zip(&starts)
+            .enumerate()
+            .map(|(i, (entry, &start))| PlannedChapter {
+                chapter_id: format!("{}:{}", book_id, entry.number),
+                number: entry.number,
+                title: entry.title.clone(),
+                start_page: start,
+                end_page: starts.get(i + 1).map(|next| next.saturating_sub(1)).unwrap_or(content.end).max(start),
+                source: "toc",
+                confidence: toc.confidence,
+            })
+            .collect()
+    } else {
+        notes.push("No chapter headings found; the content is planned as a single chapter".to_string());
+        vec![default_chapter(book_id, content)]
+    };
+
+    if pages.len() < total_pages as usize {
+        notes.push(format!(
+            "Text was available for {} of {} pages",
+            pages.len(),
+            total_pages
+        ));
+    }
+
+    IngestionPlan {
+        book_id: book_id.to_string(),
+        total_pages,
+        content_pages: content,
+        blank_pages,
+        toc_pages,
+        answer_pages,
+        batch_ocr: plan_batches(book_id, &chapters),
+        chapters,
+        notes,
+    }
+}
+
+fn default_chapter(book_id: &str, content: PageSpan) -> PlannedChapter {
+    PlannedChapter {
+        chapter_id: format!("{}:1", book_id),
+        number: 1,
+        title: "Глава 1".to_string(),
+        start_page: content.start,
+        end_page: content.end,
+        source: "default",
+        confidence: 0.0,
+    }
+}
+
+/// One batch OCR request per chapter, split where a chapter is longer than a
+/// single batch allows
+fn plan_batches(book_id: &str, chapters: &[PlannedChapter]) -> Vec<PlannedBatch> {
+    let mut batches = Vec::new();
+    for chapter in chapters {
+        let mut start = chapter.start_page;
+        while start <= chapter.end_page {
+            let end = (start + MAX_BATCH_SPAN).min(chapter.end_page);
+            batches.push(PlannedBatch {
+                book_id: book_id.to_string(),
+                start_page: start,
+                end_page: end,
+                chapter_id: chapter.chapter_id.clone(),
+                incremental: true,
+            });
+            start = end + 1;
+        }
+    }
+    batches
+}
+
+/// Split `pdftotext` output into page texts (pages are separated by form feeds)
+pub fn split_pdftotext_pages(output: &str) -> Vec<(u32, String)> {
+    output
+        .split('\u{c}')
+        .enumerate()
+        .map(|(i, text)| (i as u32 + 1, text.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(n: u32) -> String {
+        format!("Решите уравнение номер {} и проверьте ответ подстановкой.", n)
+    }
+
+    #[test]
+    fn plans_content_chapters_and_answers() {
+        let mut pages = vec![
+            (1, "Алгебра 7 класс\nУчебник".to_string()),
+            (2, String::new()),
+            (3, "Оглавление\nГлава 1. Уравнения .......... 4\nГлава 2. Неравенства .......... 6".to_string()),
+            (4, format!("Глава 1\nУравнения\n{}", body(1))),
+            (5, body(2)),
+            (6, format!("Глава 2\nНеравенства\n{}", body(3))),
+            (7, body(4)),
+            (8, format!("Ответы\n1. x = 2\n2. x = 3 {}", body(5))),
+            (9, body(6)),
+            (10, "Предметный указатель\nуравнение 4, 5\nнеравенство 6, 7".to_string()),
+        ];
+        pages.sort_by_key(|(p, _)| *p);
+
+        let plan = analyze_pages("algebra", 10, &pages, HeadingDetector::defaults());
+        assert_eq!(plan.blank_pages, vec![2]);
+        assert_eq!(plan.toc_pages, vec![3]);
+        assert_eq!(plan.content_pages, PageSpan { start: 4, end: 7 });
+        assert_eq!(plan.answer_pages, Some(PageSpan { start: 8, end: 9 }));
+
+        let spans: Vec<(u32, u32, u32)> = plan.chapters.iter().map(|c| (c.number, c.start_page, c.end_page)).collect();
+        assert_eq!(spans, vec![(1, 4, 5), (2, 6, 7)]);
+        assert_eq!(plan.batch_ocr.len(), 2);
+        assert_eq!(plan.batch_ocr[1].chapter_id, "algebra:2");
+    }
+
+    #[test]
+    fn splits_long_chapters_into_batches() {
+        let pages: Vec<(u32, String)> = (1..=250).map(|p| (p, body(p))).collect();
+        let plan = analyze_pages("big", 250, &pages, HeadingDetector::defaults());
+        assert_eq!(plan.chapters.len(), 1);
+        let spans: Vec<(u32, u32)> = plan.batch_ocr.iter().map(|b| (b.start_page, b.end_page)).collect();
+        assert_eq!(spans, vec![(1, 101), (102, 202), (203, 250)]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Content issues: a note that a page, problem or solution is wrong ("OCR
+//! dropped the second half", "answer key mismatch"), with a status, an
+//! assignee and a comment thread, so cleanup can be split between
+//! maintainers. Assignees and authors are free-text names; there are no accounts.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Kind of record an issue is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueEntity {
+    Book,
+    Chapter,
+    Page,
+    Problem,
+    Solution,
+}
+
+impl IssueEntity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueEntity::Book => "book",
+            IssueEntity::Chapter => "chapter",
+            IssueEntity::Page => "page",
+            IssueEntity::Problem => "problem",
+            IssueEntity::Solution => "solution",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "book" => IssueEntity::Book,
+            "chapter" => IssueEntity::Chapter,
+            "page" => IssueEntity::Page,
+            "problem" => IssueEntity::Problem,
+            "solution" => IssueEntity::Solution,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Open,
+    InProgress,
+    Resolved,
+    WontFix,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Open => "open",
+            IssueStatus::InProgress => "in_progress",
+            IssueStatus::Resolved => "resolved",
+            IssueStatus::WontFix => "wont_fix",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "open" => IssueStatus::Open,
+            "in_progress" => IssueStatus::InProgress,
+            "resolved" => IssueStatus::Resolved,
+            "wont_fix" => IssueStatus::WontFix,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Issue {
+    pub id: String,
+    pub entity_type: IssueEntity,
+    pub entity_id: String,
+    /// Book of the record, for filtering
+    pub book_id: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub status: IssueStatus,
+    pub assignee: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueComment {
+    pub id: String,
+    pub issue_id: String,
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl IssueComment {
+    pub fn new(issue_id: &str, author: Option<String>, body: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            issue_id: issue_id.to_string(),
+            author,
+            body,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Filters of `GET /api/issues`; unset ones match everything
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IssueFilter {
+    pub status: Option<IssueStatus>,
+    pub book: Option<String>,
+    pub entity_type: Option<IssueEntity>,
+    pub entity_id: Option<String>,
+    pub assignee: Option<String>,
+}
+
+/// Comment recording a status or assignee change, `None` when nothing changed
+pub fn change_note(
+    before: &Issue,
+    status: Option<IssueStatus>,
+    assignee: Option<&Option<String>>,
+) -> Option<String> {
+    let mut changes = Vec::new();
+    if let Some(status) = status.filter(|s| *s != before.status) {
+        changes.push(format!("status: {} → {}", before.status.as_str(), status.as_str()));
+    }
+    if let Some(assignee) = assignee.filter(|a| **a != before.assignee) {
+        let name = |a: &Option<String>| a.clone().unwrap_or_else(|| "nobody".to_string());
+        changes.push(format!("assignee: {} → {}", name(&before.assignee), name(assignee)));
+    }
+    (!changes.is_empty()).then(|| changes.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_status_and_assignee_changes() {
+        let now = Utc::now();
+        let issue = Issue {
+            id: "i".to_string(),
+            entity_type: IssueEntity::Problem,
+            entity_id: "algebra-7:1:5".to_string(),
+            book_id: "algebra-7".to_string(),
+            title: "Условие обрезано".to_string(),
+            body: None,
+            status: IssueStatus::Open,
+            assignee: None,
+            created_by: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        assert_eq!(change_note(&issue, Some(IssueStatus::Open), None), None);
+        assert_eq!(
+            change_note(&issue, Some(IssueStatus::Resolved), Some(&Some("Оля".to_string()))).as_deref(),
+            Some("status: open → resolved; assignee: nobody → Оля")
+        );
+        assert_eq!(IssueStatus::parse("wont_fix"), Some(IssueStatus::WontFix));
+        assert_eq!(IssueEntity::parse("theory"), None);
+    }
+}
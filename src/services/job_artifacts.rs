@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// File attached to a finished background job (error CSV, per-page report, ...)
+#[derive(Debug, Clone, Serialize)]
+pub struct JobArtifact {
+    pub name: String,
+    pub size: u64,
+    /// Download URL
+    pub url: String,
+}
+
+/// Job ids are UUIDs and artifact names plain file names; anything else
+/// (separators, `..`, hidden files) is rejected so paths stay inside `jobs_dir`.
+fn is_safe_component(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('.')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn job_dir(jobs_dir: &Path, job_id: &str) -> Option<PathBuf> {
+    is_safe_component(job_id).then(|| jobs_dir.join(job_id))
+}
+
+/// Local file of a job artifact, if the id and name are valid
+pub fn artifact_path(jobs_dir: &Path, job_id: &str, name: &str) -> Option<PathBuf> {
+    if !is_safe_component(name) {
+        return None;
+    }
+    job_dir(jobs_dir, job_id).map(|dir| dir.join(name))
+}
+
+fn artifact_url(job_id: &str, name: &str) -> String {
+    format!("/api/jobs/{}/artifacts/{}", job_id, name)
+}
+
+/// Write an artifact for a job, replacing one with the same name
+pub fn save_artifact(jobs_dir: &Path, job_id: &str, name: &str, data: &[u8]) -> anyhow::Result<JobArtifact> {
+    let path = artifact_path(jobs_dir, job_id, name)
+        .ok_or_else(|| anyhow::anyhow!("Invalid artifact name: {}", name))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, data)?;
+
+    Ok(JobArtifact {
+        name: name.to_string(),
+        size: data.len() as u64,
+        url: artifact_url(job_id, name),
+    })
+}
+
+/// Artifacts of a job sorted by name; empty if the job has none
+pub fn list_artifacts(jobs_dir: &Path, job_id: &str) -> anyhow::Result<Vec<JobArtifact>> {
+    let Some(dir) = job_dir(jobs_dir, job_id) else {
+        return Ok(Vec::new());
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut artifacts = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        artifacts.push(JobArtifact {
+            url: artifact_url(job_id, &name),
+            name,
+            size: metadata.len(),
+        });
+    }
+    artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(artifacts)
+}
+
+/// Remove artifact directories not modified within `retention_days`.
+/// Returns the number of job directories removed.
+pub fn prune_artifacts(jobs_dir: &Path, retention_days: u32) -> usize {
+    let Ok(entries) = std::fs::read_dir(jobs_dir) else {
+        return 0;
+    };
+    let max_age = std::time::Duration::from_secs(retention_days as u64 * 24 * 3600);
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > max_age);
+        if expired && entry.path().is_dir() && std::fs::remove_dir_all(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+pub fn content_type(name: &str) -> &'static str {
+    match Path::new(name).extension().and_then(|e| e.to_str()) {
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("json") => "application/json",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("tex") => "application/x-latex",
+        Some("txt") | Some("log") => "text/plain; charset=utf-8",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// CSV document from a header and rows, quoting fields as needed
+pub fn to_csv(header: &[&str], rows: &[Vec<String>]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    let mut out = header.iter().map(|h| field(h)).collect::<Vec<_>>().join(",");
+    out.push('\n');
+    for row in rows {
+        out.push_str(&row.iter().map(|v| field(v)).collect::<Vec<_>>().join(","));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_lists_artifacts() {
+        let dir = std::env::temp_dir().join(format!("jobs-test-{}", uuid::Uuid::new_v4()));
+        let job_id = uuid::Uuid::new_v4().to_string();
+
+        let saved = save_artifact(&dir, &job_id, "errors.csv", b"page,error\n").unwrap();
+        assert_eq!(saved.url, format!("/api/jobs/{}/artifacts/errors.csv", job_id));
+        save_artifact(&dir, &job_id, "pages.json", b"[]").unwrap();
+
+        let names: Vec<String> = list_artifacts(&dir, &job_id).unwrap().into_iter().map(|a| a.name).collect();
+        assert_eq!(names, vec!["errors.csv", "pages.json"]);
+        assert!(list_artifacts(&dir, "missing").unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        let dir = Path::new("/data/jobs");
+        assert!(artifact_path(dir, "job-1", "../secret").is_none());
+        assert!(artifact_path(dir, "..", "errors.csv").is_none());
+        assert!(artifact_path(dir, "job-1", ".hidden").is_none());
+        assert_eq!(artifact_path(dir, "job-1", "errors.csv"), Some(PathBuf::from("/data/jobs/job-1/errors.csv")));
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        let csv = to_csv(&["page", "error"], &[vec!["3".to_string(), "timeout, \"retry\"".to_string()]]);
+        assert_eq!(csv, "page,error\n3,\"timeout, \"\"retry\"\"\"\n");
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use lazy_regex::regex;
+use serde::{Deserialize, Serialize};
+
+/// Russian notation missing from standard LaTeX. Defined for every book;
+/// a book's own macros override these.
+pub const DEFAULT_MACROS: &[(&str, &str)] = &[
+    (r"\tg", r"\operatorname{tg}"),
+    (r"\ctg", r"\operatorname{ctg}"),
+    (r"\arctg", r"\operatorname{arctg}"),
+    (r"\arcctg", r"\operatorname{arcctg}"),
+    (r"\cosec", r"\operatorname{cosec}"),
+    (r"\sh", r"\operatorname{sh}"),
+    (r"\ch", r"\operatorname{ch}"),
+    (r"\th", r"\operatorname{th}"),
+    (r"\cth", r"\operatorname{cth}"),
+];
+
+/// Commands a macro definition may use besides the book's own and the
+/// default macros. Anything else is refused: TeX can spell a command in many
+/// ways (`\csname input\endcsname`, `^^5cinput`), so only known math
+/// commands get through, rather than a list of dangerous ones being kept out.
+const MATH_COMMANDS: &[&str] = &[
+    // Structure and fonts
+    "frac", "dfrac", "tfrac", "sqrt", "binom", "operatorname", "mathrm", "mathbf", "mathbb", "mathcal", "mathit",
+    "mathsf", "mathscr", "boldsymbol", "text", "textbf", "textit", "textrm", "ensuremath", "displaystyle",
+    "textstyle", "scriptstyle", "mathop", "mathrel", "mathbin", "mathord", "limits", "nolimits", "overline",
+    "underline", "overrightarrow", "overleftarrow", "vec", "hat", "widehat", "bar", "tilde", "widetilde", "dot",
+    "ddot", "overset", "underset", "stackrel", "left", "right", "big", "Big", "bigg", "Bigg", "bigl", "bigr",
+    "Bigl", "Bigr", "quad", "qquad", "not",
+    // Operators and relations
+    "cdot", "cdots", "ldots", "dots", "vdots", "ddots", "times", "div", "pm", "mp", "le", "leq", "leqslant", "ge",
+    "geq", "geqslant", "ne", "neq", "approx", "equiv", "sim", "simeq", "cong", "propto", "infty", "sum", "prod",
+    "int", "iint", "oint", "lim", "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh",
+    "cosh", "tanh", "coth", "log", "ln", "lg", "exp", "min", "max", "sup", "inf", "deg", "det", "gcd", "angle",
+    "measuredangle", "triangle", "perp", "parallel", "in", "notin", "ni", "subset", "subseteq", "supset",
+    "supseteq", "cup", "cap", "setminus", "emptyset", "varnothing", "forall", "exists", "neg", "land", "lor", "to",
+    "rightarrow", "leftarrow", "leftrightarrow", "Rightarrow", "Leftarrow", "Leftrightarrow", "iff", "implies",
+    "mapsto", "circ", "prime", "partial", "nabla", "mid", "colon", "bmod", "pmod", "lbrace", "rbrace", "langle",
+    "rangle", "lfloor", "rfloor", "lceil", "rceil", "vert", "Vert",
+    // Greek
+    "alpha", "beta", "gamma", "delta", "epsilon", "varepsilon", "zeta", "eta", "theta", "vartheta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "pi", "varpi", "rho", "varrho", "sigma", "varsigma", "tau", "upsilon", "phi",
+    "varphi", "chi", "psi", "omega", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi",
+    "Psi", "Omega",
+];
+
+/// Single-character commands (`\,`, `\{`) a definition may use
+const MATH_SYMBOLS: &str = ",;:! {}|\\%&_#$";
+
+/// Packages a book's preamble may load
+const ALLOWED_PACKAGES: &[&str] = &[
+    "amsmath", "amssymb", "amsthm", "mathtools", "mathrsfs", "bm", "cancel", "xcolor", "color", "gensymb",
+    "siunitx", "icomma", "esvect", "physics", "tikz", "pgfplots", "graphicx", "enumitem",
+];
+
+/// LaTeX macros and extra preamble of a book
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookMacros {
+    /// Macro name (with backslash) to replacement, e.g. `\tg` -> `\operatorname{tg}`
+    #[serde(default)]
+    pub macros: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preamble: Option<String>,
+}
+
+impl BookMacros {
+    /// Defaults overridden by the book's own macros
+    pub fn effective(&self) -> BTreeMap<String, String> {
+        let mut macros: BTreeMap<String, String> = DEFAULT_MACROS
+            .iter()
+            .map(|(name, def)| (name.to_string(), def.to_string()))
+            .collect();
+        macros.extend(self.macros.iter().map(|(k, v)| (k.clone(), v.clone())));
+        macros
+    }
+
+    /// Combine the macros of several books (e.g. for a worksheet); earlier books win
+    pub fn merge(books: &[BookMacros]) -> BookMacros {
+        let mut merged = BookMacros::default();
+        let mut preamble_lines: Vec<&str> = Vec::new();
+        for book in books {
+            for (name, def) in &book.macros {
+                merged.macros.entry(name.clone()).or_insert_with(|| def.clone());
+            }
+            for line in book.preamble.iter().flat_map(|p| p.lines()) {
+                if !preamble_lines.contains(&line) {
+                    preamble_lines.push(line);
+                }
+            }
+        }
+        merged.preamble = (!preamble_lines.is_empty()).then(|| preamble_lines.join("\n"));
+        merged
+    }
+
+    /// Preamble lines defining the macros, to insert before `\begin{document}`.
+    /// Macros and preamble lines saved before they were checked as strictly
+    /// are left out when they no longer pass.
+    pub fn latex_preamble(&self) -> String {
+        let defined = self.defined_names();
+        let mut output = String::new();
+        for line in self.preamble.iter().flat_map(|p| p.lines()) {
+            match validate_preamble_line(line, &defined) {
+                Ok(()) => {
+                    output.push_str(line.trim());
+                    output.push('\n');
+                }
+                Err(e) => log::warn!("Leaving out preamble line: {}", e),
+            }
+        }
+        for (name, def) in self.effective() {
+            if let Err(e) = validate_macro(&name, &def, &defined) {
+                log::warn!("Leaving out macro: {}", e);
+                continue;
+            }
+            // DeclareRobustCommand also replaces existing commands (e.g. \th)
+            match arg_count(&def) {
+                0 => output.push_str(&format!("\\DeclareRobustCommand{{{}}}{{{}}}\n", name, def)),
+                n => output.push_str(&format!("\\DeclareRobustCommand{{{}}}[{}]{{{}}}\n", name, n, def)),
+            }
+        }
+        output
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let defined = self.defined_names();
+        for (name, def) in &self.macros {
+            validate_macro(name, def, &defined)?;
+        }
+        for line in self.preamble.iter().flat_map(|p| p.lines()) {
+            validate_preamble_line(line, &defined)?;
+        }
+        Ok(())
+    }
+
+    /// Command names (without backslash) the book defines, which its
+    /// definitions may use
+    fn defined_names(&self) -> BTreeSet<String> {
+        let preamble = self.preamble.iter().flat_map(|p| p.lines()).filter_map(|line| {
+            regex!(r"^\s*\\(?:newcommand|renewcommand|providecommand|DeclareRobustCommand|DeclareMathOperator)\*?\{?\\([A-Za-z]+)")
+                .captures(line)
+                .map(|c| c[1].to_string())
+        });
+        self.effective().into_keys().map(|name| name.trim_start_matches('\\').to_string()).chain(preamble).collect()
+    }
+}
+
+/// Highest `#n` parameter used in a definition
+fn arg_count(definition: &str) -> u32 {
+    regex!(r"#([1-9])")
+        .captures_iter(definition)
+        .filter_map(|c| c[1].parse().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+fn balanced_braces(text: &str) -> bool {
+    let mut depth = 0i32;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth < 0 {
+                    return false;
+                }
+            }
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+fn validate_macro(name: &str, definition: &str, defined: &BTreeSet<String>) -> Result<(), String> {
+    if !regex!(r"^\\[A-Za-z]+$").is_match(name) {
+        return Err(format!("Invalid macro name '{}': expected a backslash followed by letters", name));
+    }
+    if definition.trim().is_empty() {
+        return Err(format!("Macro {} has an empty definition", name));
+    }
+    validate_body(definition, defined).map_err(|e| format!("Macro {}: {}", name, e))
+}
+
+/// Balanced braces and only allowed commands
+fn validate_body(body: &str, defined: &BTreeSet<String>) -> Result<(), String> {
+    if !balanced_braces(body) {
+        return Err("unbalanced braces".to_string());
+    }
+    // `^^5c` is a backslash to TeX
+    if body.contains("^^") {
+        return Err("character codes (^^) are not allowed".to_string());
+    }
+    // Definitions are embedded in the viewer pages' scripts
+    if body.contains(['<', '>']) {
+        return Err("< and > are not allowed; use \\le, \\ge or \\langle, \\rangle".to_string());
+    }
+    for command in regex!(r"\\([A-Za-z]+|.)").captures_iter(body) {
+        let name = &command[1];
+        let allowed = match name.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() => MATH_COMMANDS.contains(&name) || defined.contains(name),
+            Some(c) => MATH_SYMBOLS.contains(c),
+            None => false,
+        };
+        if !allowed {
+            return Err(format!("\\{} is not allowed", name));
+        }
+    }
+    Ok(())
+}
+
+/// A preamble line is empty, a comment, `\usepackage` of an allowed package
+/// or a single definition whose body passes [`validate_body`]
+fn validate_preamble_line(line: &str, defined: &BTreeSet<String>) -> Result<(), String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('%') {
+        return Ok(());
+    }
+    let not_allowed = || format!("Preamble line not allowed: {}", line);
+    if let Some(packages) = regex!(r"^\\usepackage(?:\[[\w\s,=.-]*\])?\{([\w\s,-]+)\}$").captures(line) {
+        return match packages[1].split(',').map(str::trim).all(|p| ALLOWED_PACKAGES.contains(&p)) {
+            true => Ok(()),
+            false => Err(not_allowed()),
+        };
+    }
+    if let Some(operator) = regex!(r"^\\DeclareMathOperator\*?\{\\[A-Za-z]+\}\{([^\\{}%^]*)\}$").captures(line) {
+        return validate_body(&operator[1], defined);
+    }
+    let definition = regex!(
+        r"^\\(?:newcommand|renewcommand|providecommand|DeclareRobustCommand)\*?(?:\{\\[A-Za-z]+\}|\\[A-Za-z]+)(?:\[[1-9]\])?\{(.*)\}$"
+    );
+    match definition.captures(line) {
+        Some(definition) if balanced_braces(&definition[1]) => {
+            validate_body(&definition[1], defined).map_err(|e| format!("{}: {}", not_allowed(), e))
+        }
+        _ => Err(not_allowed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_macros_override_defaults() {
+        let book = BookMacros {
+            macros: BTreeMap::from([
+                (r"\tg".to_string(), r"\mathrm{tg}".to_string()),
+                (r"\abs".to_string(), r"\left|#1\right|".to_string()),
+            ]),
+            preamble: Some(r"\usepackage{cancel}".to_string()),
+        };
+        let preamble = book.latex_preamble();
+        assert!(preamble.starts_with("\\usepackage{cancel}\n"));
+        assert!(preamble.contains(r"\DeclareRobustCommand{\tg}{\mathrm{tg}}"));
+        assert!(preamble.contains(r"\DeclareRobustCommand{\abs}[1]{\left|#1\right|}"));
+        assert!(preamble.contains(r"\DeclareRobustCommand{\ctg}{\operatorname{ctg}}"));
+    }
+
+    #[test]
+    fn merges_books_in_order() {
+        let a = BookMacros {
+            macros: BTreeMap::from([(r"\R".to_string(), r"\mathbb{R}".to_string())]),
+            preamble: Some(r"\usepackage{cancel}".to_string()),
+        };
+        let b = BookMacros {
+            macros: BTreeMap::from([(r"\R".to_string(), r"\mathbf{R}".to_string())]),
+            preamble: Some("\\usepackage{cancel}\n\\usepackage{xcolor}".to_string()),
+        };
+        let merged = BookMacros::merge(&[a, b]);
+        assert_eq!(merged.macros[r"\R"], r"\mathbb{R}");
+        assert_eq!(merged.preamble.as_deref(), Some("\\usepackage{cancel}\n\\usepackage{xcolor}"));
+    }
+
+    #[test]
+    fn rejects_unsafe_definitions() {
+        let book = |name: &str, def: &str, preamble: &str| BookMacros {
+            macros: BTreeMap::from([(name.to_string(), def.to_string())]),
+            preamble: (!preamble.is_empty()).then(|| preamble.to_string()),
+        };
+        assert!(book(r"\tg", r"\operatorname{tg}", "").validate().is_ok());
+        assert!(book(r"\abs", r"\left|#1\right|\,\infty", "\\usepackage{cancel}\n% comment").validate().is_ok());
+        assert!(book(r"\vv", r"\vect{#1}", r"\newcommand{\vect}[1]{\overrightarrow{#1}}").validate().is_ok());
+        assert!(book("tg", r"\operatorname{tg}", "").validate().is_err());
+        assert!(book(r"\x", r"\frac{1}{2", "").validate().is_err());
+        for unsafe_def in [
+            r"\input{/etc/passwd}",
+            r"\csname input\endcsname{/app/.env}",
+            r"^^5cinput{x}",
+            r"\def\y{1}",
+            r"x</script><script>alert(1)//",
+        ] {
+            assert!(book(r"\x", unsafe_def, "").validate().is_err(), "{}", unsafe_def);
+        }
+        for unsafe_line in [
+            r"\immediate\write18{rm -rf /}",
+            r"\usepackage{x}\input{secret}",
+            r"\usepackage{shellesc}",
+            r"\newcommand{\y}{\csname input\endcsname{secret}}",
+            r"\newcommand{\y}{1} \input{secret}",
+            r"\DeclareMathOperator{\y}{\input}",
+            r"\newcommand{\y}{</script>}",
+        ] {
+            assert!(book(r"\x", "x", unsafe_line).validate().is_err(), "{}", unsafe_line);
+        }
+        // Saved before the checks, left out of documents
+        let preamble = book(r"\x", r"\csname input\endcsname", "\\usepackage{cancel}\n\\input{secret}").latex_preamble();
+        assert!(preamble.starts_with("\\usepackage{cancel}\n"));
+        assert!(!preamble.contains("input"));
+    }
+}
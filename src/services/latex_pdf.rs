@@ -0,0 +1,98 @@
+use std::path::Path;
+use std::time::Duration;
+
+use lazy_regex::regex;
+
+use crate::utils::CommandRunner;
+
+/// A runaway macro expansion (e.g. a recursive book macro) would otherwise hang
+/// the engine forever
+const TEX_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// TeX engines in order of preference: latexmk reruns until cross-references
+/// settle, tectonic is a single self-contained binary. Documents hold OCR and
+/// user-edited text, so no engine may run shell commands, and tectonic only
+/// uses packages it already has instead of downloading them.
+const ENGINES: &[(&str, &[&str])] = &[
+    ("latexmk", &["-pdf", "-latexoption=-no-shell-escape", "-interaction=nonstopmode", "-halt-on-error", "document.tex"]),
+    ("pdflatex", &["-no-shell-escape", "-interaction=nonstopmode", "-halt-on-error", "document.tex"]),
+    ("xelatex", &["-no-shell-escape", "-interaction=nonstopmode", "-halt-on-error", "document.tex"]),
+    ("tectonic", &["--only-cached", "--untrusted", "--chatter", "minimal", "document.tex"]),
+];
+
+/// Compile a LaTeX document to PDF with the first available engine. The
+/// engine may only read files next to the document, so images included from
+/// `image_dir` are copied there first; other absolute paths can't be opened.
+pub async fn compile_pdf(tex: &str, image_dir: Option<&Path>) -> anyhow::Result<Vec<u8>> {
+    let dir = std::env::temp_dir().join(format!("bookers_latex_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir)?;
+    let tex = copy_images(tex, image_dir, &dir);
+    std::fs::write(dir.join("document.tex"), tex)?;
+
+    let result = tokio::task::spawn_blocking({
+        let dir = dir.clone();
+        move || run_tex_engine(&dir)
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?;
+
+    let pdf = result.and_then(|_| Ok(std::fs::read(dir.join("document.pdf"))?));
+    let _ = std::fs::remove_dir_all(&dir);
+    pdf
+}
+
+/// `\includegraphics` of files inside `image_dir` pointed at copies in `dir`
+fn copy_images(tex: &str, image_dir: Option<&Path>, dir: &Path) -> String {
+    let Some(image_dir) = image_dir.and_then(|d| std::fs::canonicalize(d).ok()) else {
+        return tex.to_string();
+    };
+    let mut copied = 0;
+    regex!(r"(\\includegraphics(?:\[[^\]]*\])?)\{(/[^}]+)\}")
+        .replace_all(tex, |caps: &lazy_regex::Captures| {
+            let source = std::fs::canonicalize(&caps[2]).ok().filter(|path| path.starts_with(&image_dir));
+            let extension = source.as_ref().and_then(|path| path.extension()).map(|e| e.to_string_lossy().into_owned());
+            match (source, extension) {
+                (Some(source), Some(extension)) => {
+                    copied += 1;
+                    let name = format!("image{}.{}", copied, extension);
+                    match std::fs::copy(&source, dir.join(&name)) {
+                        Ok(_) => format!("{}{{{}}}", &caps[1], name),
+                        Err(e) => {
+                            log::warn!("Could not copy {} for LaTeX: {}", source.display(), e);
+                            caps[0].to_string()
+                        }
+                    }
+                }
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn run_tex_engine(dir: &Path) -> anyhow::Result<()> {
+    let mut last_error = String::from("no TeX engine found (install latexmk, pdflatex or tectonic)");
+    for (engine, args) in ENGINES {
+        let command = CommandRunner::new(engine)
+            .args(*args)
+            .current_dir(dir)
+            // Paranoid kpathsea: no absolute paths, parent directories or dot files
+            .env("openin_any", "p")
+            .env("openout_any", "p")
+            .timeout(TEX_TIMEOUT);
+        match command.output() {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => {
+                // TeX reports errors on stdout, tectonic on stderr
+                let log = [output.stdout, output.stderr].concat();
+                last_error = format!(
+                    "{} failed: {}",
+                    engine,
+                    String::from_utf8_lossy(&log).lines().rev().take(5).collect::<Vec<_>>().join(" | ")
+                );
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => last_error = e.to_string(),
+            Err(_) => continue,
+        }
+    }
+    Err(anyhow::anyhow!(last_error))
+}
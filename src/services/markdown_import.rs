@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Book, Chapter};
+use crate::services::database::Database;
+use crate::services::parser::TextbookParser;
+
+/// Markdown file to import into a book chapter
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarkdownImportRequest {
+    pub book_id: String,
+    pub chapter_num: u32,
+    /// Defaults to the first `#` heading, then to the existing chapter title
+    pub chapter_title: Option<String>,
+    pub markdown: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarkdownImportSummary {
+    pub book_id: String,
+    pub chapter_id: String,
+    pub problems_imported: usize,
+    pub sub_problems_imported: usize,
+    pub theory_blocks_imported: usize,
+    pub problem_numbers: Vec<String>,
+    /// Text outside any problem/theory heading that was not imported
+    pub unclassified: Vec<String>,
+}
+
+/// Parse markdown problems and store them under the given book/chapter.
+///
+/// The book and chapter are created when missing; an existing book keeps its
+/// metadata. Problems are upserted, so re-importing an edited file updates them.
+pub async fn import_markdown(db: &Database, request: &MarkdownImportRequest) -> anyhow::Result<MarkdownImportSummary> {
+    let parser = TextbookParser::new();
+    let result = parser.parse_markdown(&request.markdown, &request.book_id, request.chapter_num);
+
+    if db.get_book(&request.book_id).await?.is_none() {
+        db.create_book(&Book {
+            id: request.book_id.clone(),
+            title: format!("Book {}", request.book_id),
+            author: None,
+            subject: Some("Mathematics".to_string()),
+            file_path: String::new(),
+            total_pages: 0,
+            created_at: chrono::Utc::now(),
+        })
+        .await?;
+    }
+
+    let chapter_id = format!("{}:{}", request.book_id, request.chapter_num);
+    let existing = db.get_chapter(&chapter_id).await?;
+    let title = request
+        .chapter_title
+        .clone()
+        .filter(|t| !t.trim().is_empty())
+        .or_else(|| first_heading(&request.markdown))
+        .or_else(|| existing.as_ref().map(|c| c.title.clone()))
+        .unwrap_or_else(|| format!("Chapter {}", request.chapter_num));
+
+    db.create_chapter(&Chapter {
+        id: chapter_id.clone(),
+        book_id: request.book_id.clone(),
+        number: request.chapter_num,
+        title,
+        description: existing.and_then(|c| c.description),
+        problem_count: result.problems.len() as u32,
+        theory_count: result.theory_blocks.len() as u32,
+        created_at: chrono::Utc::now(),
+    })
+    .await?;
+
+    let mut sub_problems_imported = 0;
+    for problem in &result.problems {
+        db.create_problem(problem).await?;
+        if let Some(subs) = &problem.sub_problems {
+            let writes = db.create_or_update_problems(subs).await?;
+            sub_problems_imported += writes.iter().filter(|w| w.is_written()).count();
+        }
+    }
+
+    for theory in &result.theory_blocks {
+        db.create_theory_block(theory).await?;
+    }
+
+    Ok(MarkdownImportSummary {
+        book_id: request.book_id.clone(),
+        chapter_id,
+        problems_imported: result.problems.len(),
+        sub_problems_imported,
+        theory_blocks_imported: result.theory_blocks.len(),
+        problem_numbers: result.problems.iter().map(|p| p.number.clone()).collect(),
+        unclassified: result.unclassified,
+    })
+}
+
+/// Title of the first level-1 heading (`# ...`)
+fn first_heading(markdown: &str) -> Option<String> {
+    markdown
+        .lines()
+        .map(str::trim)
+        .find(|l| l.starts_with("# "))
+        .map(|l| l.trim_start_matches('#').trim().to_string())
+        .filter(|t| !t.is_empty())
+}
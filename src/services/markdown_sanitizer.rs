@@ -0,0 +1,190 @@
+use std::ops::Range;
+
+use lazy_regex::regex;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+/// Inline tags provider output may keep; anything else is shown as text
+const ALLOWED_TAGS: &[&str] = &["b", "br", "em", "i", "strong", "sub", "sup", "u"];
+
+/// Link schemes that run code when clicked
+const UNSAFE_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:", "file:"];
+
+/// Clean up provider-written markdown before it is stored or put into a
+/// page:
+///
+/// - line endings become `\n` and control characters are dropped
+/// - raw HTML other than a few attribute-free inline tags is escaped, so
+///   `<script>` shows up as text instead of running. Pages put the result
+///   into HTML as is, so this covers every `<` a browser would read as a
+///   tag, including ones markdown doesn't see as HTML (`<img/src=x>`, link
+///   titles and destinations, code fence info strings)
+/// - `javascript:`/`data:` link targets become `#`
+/// - `<` in math and code gets a space when it would start a tag
+///   (`$a<b$` -> `$a< b$`), which TeX ignores
+/// - an unterminated code fence or `$$` block is closed
+///
+/// Everything else, including the LaTeX, is left as written. Running it on
+/// its own output changes nothing.
+pub fn sanitize_markdown(content: &str) -> String {
+    let mut text: String = content
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect();
+    let trimmed = text.trim_end().len();
+    text.truncate(trimmed);
+
+    if text.lines().filter(|line| line.trim_start().starts_with("```")).count() % 2 == 1 {
+        text.push_str("\n```");
+    }
+    if text.matches("$$").count() % 2 == 1 {
+        text.push_str("\n$$");
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let options = Options::ENABLE_MATH | Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut in_code_block = false;
+    for (event, range) in Parser::new_ext(&text, options).into_offset_iter() {
+        let source = &text[range.clone()];
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::InlineHtml(tag) if is_allowed_tag(&tag) => {}
+            Event::Html(_) | Event::InlineHtml(_) => {
+                edits.push((range, source.replace('<', "&lt;")));
+            }
+            Event::InlineMath(_) | Event::DisplayMath(_) | Event::Code(_) => {
+                edits.push((range, break_tags(source)));
+            }
+            Event::Text(_) if in_code_block => {
+                edits.push((range, break_tags(source)));
+            }
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) if is_unsafe_url(&dest_url) => {
+                if let Some(at) = source.rfind(dest_url.as_ref()) {
+                    let start = range.start + at;
+                    edits.push((start..start + dest_url.len(), "#".to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Events nest (an image inside a link), so keep the first edit of an area
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut sanitized = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (range, replacement) in edits {
+        if range.start < copied {
+            continue;
+        }
+        sanitized.push_str(&text[copied..range.start]);
+        sanitized.push_str(&replacement);
+        copied = range.end;
+    }
+    sanitized.push_str(&text[copied..]);
+    escape_tag_starts(&sanitized)
+}
+
+/// `&lt;` for every `<` that starts a tag, comment or declaration other
+/// than an allowed tag
+fn escape_tag_starts(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('<') {
+        escaped.push_str(&rest[..at]);
+        let tail = &rest[at..];
+        if let Some(tag) = regex!(r"^</?[a-zA-Z]+\s*/?>").find(tail)
+            && is_allowed_tag(tag.as_str())
+        {
+            escaped.push_str(tag.as_str());
+            rest = &tail[tag.end()..];
+            continue;
+        }
+        let opens_tag = tail[1..].starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'));
+        escaped.push_str(if opens_tag { "&lt;" } else { "<" });
+        rest = &tail[1..];
+    }
+    escaped.push_str(rest);
+    escaped
+}
+
+fn is_allowed_tag(tag: &str) -> bool {
+    regex!(r"^</?([a-zA-Z]+)\s*/?>$")
+        .captures(tag.trim())
+        .is_some_and(|c| ALLOWED_TAGS.contains(&c[1].to_lowercase().as_str()))
+}
+
+fn is_unsafe_url(url: &str) -> bool {
+    let url: String = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect::<String>().to_lowercase();
+    UNSAFE_SCHEMES.iter().any(|scheme| url.starts_with(scheme))
+}
+
+/// `<` followed by a space where it would otherwise open a tag
+fn break_tags(source: &str) -> String {
+    regex!(r"<([A-Za-z/!?])").replace_all(source, "< $1").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html_but_keeps_simple_tags() {
+        assert_eq!(
+            sanitize_markdown("Ответ: x<sup>2</sup>\n\n<script>alert(1)</script>"),
+            "Ответ: x<sup>2</sup>\n\n&lt;script>alert(1)&lt;/script>"
+        );
+        assert_eq!(sanitize_markdown("a <img src=x onerror=alert(1)> b"), "a &lt;img src=x onerror=alert(1)> b");
+        assert_eq!(sanitize_markdown("<b onclick=\"x()\">bold</b>"), "&lt;b onclick=\"x()\">bold</b>");
+    }
+
+    #[test]
+    fn escapes_tags_markdown_does_not_see_as_html() {
+        for input in [
+            "<img/src=x/onerror=alert(1)>",
+            "<svg/onload=alert(1)>",
+            "[a](<x\"><img src=x onerror=alert(1)>)",
+            "[a](https://example.com \"<img src=x onerror=alert(1)>\")",
+            "```<img src=x onerror=alert(1)>\nx\n```",
+            "<!--x--><?php",
+        ] {
+            let sanitized = sanitize_markdown(input);
+            assert!(!regex!(r"<[A-Za-z/!?]").is_match(&sanitized), "{}", sanitized);
+        }
+        assert_eq!(sanitize_markdown("x <br/> <b>y</b> a < b"), "x <br/> <b>y</b> a < b");
+    }
+
+    #[test]
+    fn neutralizes_script_links() {
+        assert_eq!(sanitize_markdown("[ответ](javascript:alert(1))"), "[ответ](#)");
+        assert_eq!(sanitize_markdown("[ответ](https://example.com)"), "[ответ](https://example.com)");
+        assert_eq!(sanitize_markdown("![x](data:text/html;base64,AAAA)"), "![x](#)");
+    }
+
+    #[test]
+    fn leaves_latex_alone_except_tag_like_comparisons() {
+        let latex = r"$$\frac{a_1}{b_2} \le x^2$$ и $a < b$, $\sqrt{2}*3$";
+        assert_eq!(sanitize_markdown(latex), latex);
+        assert_eq!(sanitize_markdown("$a<b$ и `x<y`"), "$a< b$ и `x< y`");
+        assert_eq!(sanitize_markdown("```\n<script>\n```"), "```\n< script>\n```");
+    }
+
+    #[test]
+    fn normalizes_and_closes_blocks() {
+        assert_eq!(sanitize_markdown("a\r\nb\u{0}\r\n\n  "), "a\nb");
+        assert_eq!(sanitize_markdown("```python\nprint(1)"), "```python\nprint(1)\n```");
+        assert_eq!(sanitize_markdown("$$x = 1"), "$$x = 1\n$$");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        for input in [
+            "<script>alert(1)</script>\n\n$a<b$ [x](javascript:y)",
+            "x<sup>2</sup> **bold** `a<b`\n```\n<div>\n```",
+        ] {
+            let once = sanitize_markdown(input);
+            assert_eq!(sanitize_markdown(&once), once);
+        }
+    }
+}
@@ -19,3 +19,60 @@ pub mod knowledge_graph;
 pub mod auto_tagger;
 pub mod similarity;
 pub mod page_parser;
+pub mod ocr_audit;
+pub mod credentials;
+pub mod provider_registry;
+pub mod worksheet;
+pub mod book_compare;
+pub mod markdown_import;
+pub mod anki_import;
+pub mod ocr_rules;
+pub mod dehyphenate;
+pub mod formula_fallback;
+pub mod ocr_confidence;
+pub mod review;
+pub mod job_artifacts;
+pub mod ingestion;
+pub mod answer_key;
+pub mod explain;
+pub mod glossary;
+pub mod latex_macros;
+pub mod book_settings;
+pub mod book_dashboard;
+pub mod ui_preferences;
+pub mod idempotency;
+pub mod edit_version;
+pub mod http_client;
+#[cfg(feature = "pdfium")]
+pub mod pdfium;
+pub mod page_classifier;
+pub mod heading_detector;
+pub mod study_pack;
+pub mod solution_cleanup;
+pub mod markdown_sanitizer;
+pub mod templates;
+pub mod solve_cache;
+pub mod cache_bundle;
+pub mod plot;
+pub mod attachments;
+pub mod graph_render;
+pub mod figure;
+pub mod curriculum;
+pub mod epub;
+pub mod export_diff;
+pub mod export_history;
+pub mod assignment;
+pub mod group;
+pub mod digest;
+pub mod issues;
+pub mod text_search;
+pub mod share_link;
+pub mod embed;
+pub mod feed;
+pub mod calc_check;
+pub mod symbolic;
+pub mod calibration;
+pub mod anki_export;
+pub mod book_probe;
+pub mod latex_pdf;
+pub mod cost_estimate;
@@ -1,29 +1,184 @@
-use crate::config::Config;
+use crate::config::{Config, MathpixConfig, OFFLINE_ERROR};
 use crate::models::OcrError;
+use crate::services::epub::is_epub;
+use crate::services::ocr_image_file_name;
+use crate::services::http_client::HttpClientFactory;
+use crate::services::provider_registry::{self, ProviderKind};
+use crate::services::retry::{guarded, is_cancelled, CallInterrupted};
 use async_trait::async_trait;
 use base64::Engine;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{LazyLock, Mutex};
 use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Provider recorded for pages read from the book's own text: a PDF's text
+/// layer or an EPUB chapter
+pub const TEXT_LAYER_PROVIDER: &str = "pdftext";
+
+/// Local OCR with the tesseract binary; needs no API key and works offline
+pub const TESSERACT_PROVIDER: &str = "tesseract";
+
+/// Mathpix OCR, which reads formulas as LaTeX
+pub const MATHPIX_PROVIDER: &str = "mathpix";
+
+const MATHPIX_TEXT_URL: &str = "https://api.mathpix.com/v3/text";
+
+/// Why one-off page OCR has no provider to use
+pub const NO_OCR_PROVIDER: &str =
+    "No OCR provider: set MISTRAL_API_KEY or MATHPIX_APP_ID/MATHPIX_APP_KEY, or install tesseract";
+
+/// Share of letters and digits among the non-space characters of a usable
+/// text layer; fonts without a Unicode map come out as symbol soup
+const TEXT_LAYER_MIN_ALNUM_SHARE: f64 = 0.5;
+
+/// Whether a page's text layer has enough real text (at least `min_chars`
+/// letters and digits) to stand in for OCR
+pub fn is_substantial_text(text: &str, min_chars: usize) -> bool {
+    let visible = text.chars().filter(|c| !c.is_whitespace()).count();
+    let alnum = text.chars().filter(|c| c.is_alphanumeric()).count();
+    visible > 0 && alnum >= min_chars && alnum as f64 / visible as f64 >= TEXT_LAYER_MIN_ALNUM_SHARE
+}
+
+/// Text of a page and the provider that read it
+#[derive(Debug, Clone)]
+pub struct PageText {
+    pub text: String,
+    pub provider: String,
+}
+
+fn text_layer_page(text: String) -> PageText {
+    PageText { text, provider: TEXT_LAYER_PROVIDER.to_string() }
+}
 
 /// OCR Service for running OCR on images
 #[derive(Clone)]
 pub struct OcrService {
     preview_dir: PathBuf,
+    resources_dir: PathBuf,
+    timeout: Duration,
+    proxy_env: Vec<(&'static str, String)>,
+    text_layer_min_chars: usize,
+    offline: bool,
+    tesseract: TesseractOcrProvider,
+    mathpix: Option<MathpixOcrProvider>,
 }
 
 impl OcrService {
-    pub fn new(preview_dir: PathBuf) -> Self {
-        Self { preview_dir }
+    pub fn new(config: &Config) -> Self {
+        Self {
+            preview_dir: config.preview_dir.clone(),
+            resources_dir: config.resources_dir.clone(),
+            timeout: config.provider_timeout(),
+            proxy_env: config.proxy_env(),
+            text_layer_min_chars: config.text_layer_min_chars,
+            offline: config.offline,
+            tesseract: TesseractOcrProvider::from_config(config),
+            mathpix: MathpixOcrProvider::from_config(config),
+        }
+    }
+
+    /// OCR a page of a book file. Born-digital pages whose PDF text layer is
+    /// substantial (see [`is_substantial_text`]) are read from it for free;
+    /// other pages go to `provider`. In offline mode any text layer is used,
+    /// and pages without one are read by tesseract when it is installed.
+    /// EPUB pages are always read from their text.
+    pub async fn ocr_page(
+        &self,
+        file: &str,
+        page: u32,
+        image_path: &Path,
+        provider: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<PageText> {
+        let epub = is_epub(file);
+        if self.offline || self.text_layer_min_chars > 0 || epub {
+            match self.text_layer(file, page).await {
+                Ok(text) if (self.offline || epub) && !text.trim().is_empty() => return Ok(text_layer_page(text)),
+                Ok(text) if is_substantial_text(&text, self.text_layer_min_chars) => {
+                    log::info!("Page {} of {} read from its text layer", page, file);
+                    return Ok(text_layer_page(text));
+                }
+                Ok(_) => {}
+                // Not a PDF, or pdftotext is missing: only fatal without a provider
+                Err(e) if !self.offline => log::debug!("No text layer for page {} of {}: {}", page, file, e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let provider = if self.offline {
+            if !image_path.exists() || !self.tesseract.is_available() {
+                return Err(anyhow::anyhow!(
+                    "Offline mode: page {} of {} has no text layer; OCR needs tesseract or a cloud provider",
+                    page,
+                    file
+                ));
+            }
+            TESSERACT_PROVIDER
+        } else {
+            provider
+        };
+        let text = self.run_ocr_cancellable(image_path, provider, cancel).await?;
+        Ok(PageText { text, provider: provider.to_string() })
+    }
+
+    async fn text_layer(&self, file: &str, page: u32) -> anyhow::Result<String> {
+        let path = crate::services::resolve_within(&self.resources_dir, file).map_err(|e| anyhow::anyhow!(e))?;
+        tokio::task::spawn_blocking(move || crate::services::extract_page_text(&path, page))
+            .await
+            .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?
+            .map_err(|e| anyhow::anyhow!(e))
     }
     
     /// Run OCR on an image file
     pub async fn run_ocr(&self, image_path: &Path, provider: &str) -> anyhow::Result<String> {
+        self.run_ocr_cancellable(image_path, provider, &CancellationToken::new()).await
+    }
+
+    /// Run OCR, giving up when a job's `cancel` token fires. Each attempt is
+    /// limited to the configured provider timeout.
+    pub async fn run_ocr_cancellable(
+        &self,
+        image_path: &Path,
+        provider: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<String> {
+        if self.offline && provider != TESSERACT_PROVIDER {
+            return Err(anyhow::anyhow!(OFFLINE_ERROR));
+        }
+
         // Check if preview image exists
         if !image_path.exists() {
             return Err(anyhow::anyhow!("Image not found: {:?}", image_path));
         }
-        
+
+        if provider == MATHPIX_PROVIDER && self.mathpix.is_none() {
+            return Err(anyhow::anyhow!("Mathpix OCR needs MATHPIX_APP_ID and MATHPIX_APP_KEY"));
+        }
+
+        if !provider_registry::circuit_allows(ProviderKind::Ocr, provider) {
+            return Err(anyhow::anyhow!(
+                "OCR provider '{}' is temporarily disabled after repeated failures",
+                provider
+            ));
+        }
+
+        let result = match (provider, &self.mathpix) {
+            (TESSERACT_PROVIDER, _) => self.tesseract.recognize(image_path, cancel).await,
+            (MATHPIX_PROVIDER, Some(mathpix)) => mathpix.recognize(image_path, cancel).await.map(|(text, _)| text),
+            _ => self.run_ocr_script(image_path, provider, cancel).await,
+        };
+        // A cancelled call says nothing about the provider's health
+        if !result.as_ref().is_err_and(is_cancelled) {
+            provider_registry::record_outcome(ProviderKind::Ocr, provider, result.is_ok());
+        }
+        result
+    }
+
+    async fn run_ocr_script(&self, image_path: &Path, provider: &str, cancel: &CancellationToken) -> anyhow::Result<String> {        
         // Try to use venv python first
         let python_path = if std::path::Path::new(".venv/bin/python").exists() {
             ".venv/bin/python"
@@ -37,23 +192,29 @@ impl OcrService {
         let mut last_error = String::new();
 
         for attempt in 1..=MAX_ATTEMPTS {
-            let output = tokio::task::spawn_blocking({
-                let path = image_path.to_path_buf();
-                let py = python_path.to_string();
-                let prov = provider.to_string();
-                move || {
-                    std::process::Command::new(&py)
-                        .arg("ocr.py")
-                        .arg(&path)
-                        .arg("-p")
-                        .arg(&prov)
-                        .output()
-                }
-            })
+            // kill_on_drop: a timed out or cancelled attempt kills the script
+            let child = tokio::process::Command::new(python_path)
+                .arg("ocr.py")
+                .arg(image_path)
+                .arg("-p")
+                .arg(provider)
+                .envs(self.proxy_env.iter().cloned())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to run OCR: {}", e))?;
+
+            let output = guarded(
+                async { Ok(child.wait_with_output().await?) },
+                self.timeout,
+                cancel,
+            )
             .await
-            .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?;
-
-            let output = output.map_err(|e| anyhow::anyhow!("Failed to run OCR: {}", e))?;
+            .map_err(|e| match e.downcast_ref::<CallInterrupted>().copied() {
+                Some(interrupted) => e.context(format!("OCR provider '{}' {}", provider, interrupted)),
+                None => anyhow::anyhow!("Failed to run OCR: {}", e),
+            })?;
 
             if output.status.success() {
                 let text = String::from_utf8_lossy(&output.stdout);
@@ -124,6 +285,225 @@ pub trait OcrProvider: Send + Sync {
     fn provider_id(&self) -> &'static str;
 }
 
+/// Picks the provider for one-off page OCR: Mistral when its key is set (and
+/// not offline), then Mathpix when its credentials are, otherwise tesseract
+/// when it is installed
+pub fn default_ocr_provider(config: &Config) -> Option<Box<dyn OcrProvider>> {
+    if let Some(api_key) = std::env::var("MISTRAL_API_KEY").ok().filter(|k| !k.is_empty() && !config.offline) {
+        return Some(Box::new(MistralOcrProvider::new(api_key)));
+    }
+    if !config.offline
+        && let Some(mathpix) = MathpixOcrProvider::from_config(config)
+    {
+        return Some(Box::new(mathpix));
+    }
+    let tesseract = TesseractOcrProvider::from_config(config);
+    tesseract.is_available().then(|| Box::new(tesseract) as Box<dyn OcrProvider>)
+}
+
+/// OCR with a local tesseract install (`TESSERACT_PATH`, or `tesseract` on
+/// PATH) in the `TESSERACT_LANGS` languages, `rus+eng` by default. Plain text
+/// only: formulas come out as best-effort characters, not LaTeX.
+#[derive(Clone)]
+pub struct TesseractOcrProvider {
+    program: PathBuf,
+    languages: String,
+    timeout: Duration,
+}
+
+impl TesseractOcrProvider {
+    pub fn new(program: PathBuf, languages: &str, timeout: Duration) -> Self {
+        Self { program, languages: languages.to_string(), timeout }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config.tesseract_path.clone().unwrap_or_else(|| PathBuf::from(TESSERACT_PROVIDER)),
+            &config.tesseract_languages,
+            config.provider_timeout(),
+        )
+    }
+
+    /// Whether the tesseract binary can be run. Probed once per program: the
+    /// provider list and every page without an image check it.
+    pub fn is_available(&self) -> bool {
+        static PROBED: LazyLock<Mutex<HashMap<PathBuf, bool>>> = LazyLock::new(Default::default);
+        let mut probed = PROBED.lock().unwrap_or_else(|e| e.into_inner());
+        *probed.entry(self.program.clone()).or_insert_with(|| {
+            std::process::Command::new(&self.program)
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+    }
+
+    /// Text of an image, giving up when `cancel` fires or the provider timeout passes
+    pub async fn recognize(&self, image_path: &Path, cancel: &CancellationToken) -> anyhow::Result<String> {
+        // kill_on_drop: a timed out or cancelled run kills tesseract
+        let child = tokio::process::Command::new(&self.program)
+            .arg(image_path)
+            .arg("stdout")
+            .args(["-l", &self.languages])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to run tesseract ({}): {}", self.program.display(), e))?;
+
+        let output = guarded(async { Ok(child.wait_with_output().await?) }, self.timeout, cancel)
+            .await
+            .map_err(|e| match e.downcast_ref::<CallInterrupted>().copied() {
+                Some(interrupted) => e.context(format!("OCR provider '{}' {}", TESSERACT_PROVIDER, interrupted)),
+                None => anyhow::anyhow!("Failed to run tesseract: {}", e),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(anyhow::anyhow!("tesseract exited with status {}: {}", output.status, stderr));
+        }
+        Ok(clean_tesseract_text(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+#[async_trait]
+impl OcrProvider for TesseractOcrProvider {
+    async fn extract_text(
+        &self,
+        image_path: &str,
+        _file: &str,
+        _page: u32,
+    ) -> Result<(String, Value), OcrError> {
+        let text = self
+            .recognize(Path::new(image_path), &CancellationToken::new())
+            .await
+            .map_err(|e| OcrError(e.to_string()))?;
+        let result = serde_json::json!({
+            "provider": TESSERACT_PROVIDER,
+            "languages": self.languages,
+            "text": text,
+        });
+        Ok((text, result))
+    }
+
+    fn provider_id(&self) -> &'static str {
+        TESSERACT_PROVIDER
+    }
+}
+
+/// Tesseract output without trailing spaces, runs of blank lines and the
+/// form feed it ends each page with
+fn clean_tesseract_text(text: &str) -> String {
+    let mut cleaned = String::new();
+    let mut blank_run = 0;
+    for line in text.lines().map(|l| l.trim_end().trim_matches('\u{c}')) {
+        if line.trim().is_empty() {
+            blank_run += 1;
+            continue;
+        }
+        if !cleaned.is_empty() {
+            cleaned.push_str(if blank_run > 0 { "\n\n" } else { "\n" });
+        }
+        cleaned.push_str(line);
+        blank_run = 0;
+    }
+    cleaned
+}
+
+/// OCR with the Mathpix `v3/text` API. Formulas come back as LaTeX in
+/// Mathpix Markdown, asked for with the `$`/`$$` delimiters the rest of the
+/// app uses.
+#[derive(Clone)]
+pub struct MathpixOcrProvider {
+    credentials: MathpixConfig,
+    timeout: Duration,
+}
+
+impl MathpixOcrProvider {
+    pub fn new(credentials: MathpixConfig, timeout: Duration) -> Self {
+        Self { credentials, timeout }
+    }
+
+    /// None without `MATHPIX_APP_ID`/`MATHPIX_APP_KEY`
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.mathpix.clone().map(|credentials| Self::new(credentials, config.provider_timeout()))
+    }
+
+    /// Text of an image and Mathpix's full response (it carries the
+    /// `confidence` the review queue uses), giving up when `cancel` fires or
+    /// the provider timeout passes
+    pub async fn recognize(&self, image_path: &Path, cancel: &CancellationToken) -> anyhow::Result<(String, Value)> {
+        let src = crate::utils::encode_image_to_base64(&image_path.to_string_lossy())
+            .map_err(|e| anyhow::anyhow!("Failed to encode image to base64: {}", e))?;
+        let request_body = serde_json::json!({
+            "src": src,
+            "formats": ["text", "data"],
+            "data_options": { "include_latex": true },
+            "math_inline_delimiters": ["$", "$"],
+            "math_display_delimiters": ["$$", "$$"],
+            "rm_spaces": true,
+        });
+
+        let client = HttpClientFactory::global().client(self.timeout);
+        let call = async {
+            let resp = client
+                .post(MATHPIX_TEXT_URL)
+                .header("app_id", &self.credentials.app_id)
+                .header("app_key", &self.credentials.app_key)
+                .json(&request_body)
+                .send()
+                .await?;
+            let status = resp.status();
+            let body = resp.text().await?;
+            if !status.is_success() {
+                anyhow::bail!("Mathpix OCR failed, status: {}, body: {}", status, body);
+            }
+            Ok(serde_json::from_str::<Value>(&body)?)
+        };
+        let result = guarded(call, self.timeout, cancel)
+            .await
+            .map_err(|e| match e.downcast_ref::<CallInterrupted>().copied() {
+                Some(interrupted) => e.context(format!("OCR provider '{}' {}", MATHPIX_PROVIDER, interrupted)),
+                None => e,
+            })?;
+        Ok((mathpix_text(&result)?, result))
+    }
+}
+
+#[async_trait]
+impl OcrProvider for MathpixOcrProvider {
+    async fn extract_text(
+        &self,
+        image_path: &str,
+        _file: &str,
+        _page: u32,
+    ) -> Result<(String, Value), OcrError> {
+        self.recognize(Path::new(image_path), &CancellationToken::new())
+            .await
+            .map_err(|e| OcrError(e.to_string()))
+    }
+
+    fn provider_id(&self) -> &'static str {
+        MATHPIX_PROVIDER
+    }
+}
+
+/// Text of a Mathpix response. Mathpix answers some failures (an image
+/// with no text, bad credentials) with 200 and an `error` field.
+fn mathpix_text(response: &Value) -> anyhow::Result<String> {
+    if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+        let detail = response["error_info"]["message"].as_str().unwrap_or_default();
+        anyhow::bail!("Mathpix OCR error: {} {}", error, detail);
+    }
+    response
+        .get("text")
+        .and_then(|t| t.as_str())
+        .map(|t| t.trim().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Mathpix response has no text"))
+}
+
 pub struct MistralOcrProvider {
     api_key: String,
     config: Config,
@@ -149,7 +529,7 @@ impl OcrProvider for MistralOcrProvider {
         let image_base64_url = crate::utils::encode_image_to_base64(image_path)
             .map_err(|e| OcrError(format!("Failed to encode image to base64: {}", e)))?;
 
-        let client = reqwest::Client::new();
+        let client = HttpClientFactory::global().client(self.config.provider_timeout());
         let request_body = serde_json::json!({
             "document": {
                 "type": "image_url",
@@ -165,7 +545,11 @@ impl OcrProvider for MistralOcrProvider {
             .json(&request_body)
             .send()
             .await
-            .map_err(|e| OcrError(format!("Failed to send request: {}", e)))?;
+            .map_err(|e| if e.is_timeout() {
+                OcrError(format!("Request timed out after {:?}", self.config.provider_timeout()))
+            } else {
+                OcrError(format!("Failed to send request: {}", e))
+            })?;
 
         let status = resp.status();
         let text = resp
@@ -218,18 +602,10 @@ impl MistralOcrProvider {
                     continue;
                 };
 
-                let filename = std::path::Path::new(file)
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("unknown");
-
-                let img_output_path = self.config.preview_dir.join(format!(
-                    "ocr_image-{}-{}-{}-img-{}.jpeg",
-                    self.provider_id(),
-                    filename,
-                    page,
-                    img_index
-                ));
+                let img_output_path = self
+                    .config
+                    .preview_dir
+                    .join(ocr_image_file_name(self.provider_id(), file, page, img_index));
 
                 if let Err(e) = std::fs::write(&img_output_path, image_bytes) {
                     log::error!("Failed to write OCR image: {}", e);
@@ -257,14 +633,11 @@ impl MistralOcrProvider {
             .filter_map(|page_data| page_data.get("markdown").and_then(|m| m.as_str()))
             .map(|markdown| {
                 re.replace_all(markdown, |caps: &regex::Captures| {
-                    let img_index = &caps[1];
+                    let img_index = caps[1].parse().unwrap_or(0);
                     format!(
-                        "![ocr-image]({}/ocr_image/ocr_image-{}-{}-{}-img-{}.jpeg)",
+                        "![ocr-image]({}/ocr_image/{})",
                         self.config.base_url,
-                        self.provider_id(),
-                        file.replace(".pdf", ""),
-                        page,
-                        img_index
+                        ocr_image_file_name(self.provider_id(), file, page, img_index)
                     )
                 })
                 .to_string()
@@ -273,3 +646,52 @@ impl MistralOcrProvider {
             .join("\n\n")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_layers_need_enough_real_text() {
+        let page = "171. Решите уравнение x + 5 = 12. ".repeat(10);
+        assert!(is_substantial_text(&page, 200));
+        assert!(!is_substantial_text(&page, 1000));
+        assert!(!is_substantial_text("  \n\u{c}", 0));
+        // Glyphs without a Unicode mapping
+        let soup = "\u{fffd}#$% ".repeat(100) + &"а".repeat(250);
+        assert!(!is_substantial_text(&soup, 200));
+    }
+
+    #[test]
+    fn cleans_tesseract_output() {
+        let raw = "171. Решите уравнение  \nx + 5 = 12\n\n\n\nа) 7\n\u{c}";
+        assert_eq!(clean_tesseract_text(raw), "171. Решите уравнение\nx + 5 = 12\n\nа) 7");
+        assert_eq!(clean_tesseract_text("\u{c}"), "");
+    }
+
+    #[test]
+    fn reads_mathpix_responses() {
+        let ok = serde_json::json!({
+            "text": "171. Решите уравнение $x^{2}-5 x+6=0$\n",
+            "confidence": 0.97,
+        });
+        assert_eq!(mathpix_text(&ok).unwrap(), "171. Решите уравнение $x^{2}-5 x+6=0$");
+        let failed = serde_json::json!({
+            "error": "Content not found",
+            "error_info": { "id": "image_no_content", "message": "Content not found" },
+        });
+        assert!(mathpix_text(&failed).unwrap_err().to_string().contains("Content not found"));
+    }
+
+    #[tokio::test]
+    async fn missing_tesseract_is_an_error() {
+        let tesseract = TesseractOcrProvider::new(
+            PathBuf::from("booker-no-such-tesseract"),
+            "rus+eng",
+            Duration::from_secs(5),
+        );
+        assert!(!tesseract.is_available());
+        let err = tesseract.recognize(Path::new("page.png"), &CancellationToken::new()).await.unwrap_err();
+        assert!(err.to_string().contains("Failed to run tesseract"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::services::book_settings::book_file_service;
+use crate::services::background::{JobManager, JobStatus, JobType};
+use crate::services::database::Database;
+use crate::services::FileService;
+use crate::services::ocr::OcrService;
+
+/// Pages whose re-OCR similarity falls below this are flagged as diverged.
+pub const DIVERGENCE_THRESHOLD: f64 = 0.75;
+
+/// Result of re-checking a single page with a second OCR provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcrAuditEntry {
+    pub id: String,
+    pub book_id: String,
+    pub page_number: u32,
+    pub provider: String,
+    pub similarity: f64,
+    pub diverged: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Samples already-OCR'd pages and re-runs them through a second provider
+/// to catch silent regressions in the primary OCR output.
+#[derive(Clone)]
+pub struct OcrAuditor {
+    job_manager: Arc<JobManager>,
+    db: Arc<Database>,
+    config: Arc<Config>,
+}
+
+impl OcrAuditor {
+    pub fn new(job_manager: Arc<JobManager>, db: Arc<Database>, config: Arc<Config>) -> Self {
+        Self { job_manager, db, config }
+    }
+
+    /// Start an audit job. When `book_id` is `None` every book is sampled.
+    pub async fn start_audit(
+        &self,
+        book_id: Option<String>,
+        sample_size: usize,
+        provider: &str,
+    ) -> anyhow::Result<String> {
+        let job_id = self.job_manager.create_job(JobType::OcrAudit {
+            book_id: book_id.clone(),
+            sample_size,
+            provider: provider.to_string(),
+        }).await;
+
+        let auditor = self.clone();
+        let jid = job_id.clone();
+        let provider = provider.to_string();
+
+        tokio::spawn(async move {
+            auditor.run_audit(&jid, book_id, sample_size, &provider).await;
+        });
+
+        Ok(job_id)
+    }
+
+    async fn run_audit(&self, job_id: &str, book_id: Option<String>, sample_size: usize, provider: &str) {
+        let books = match book_id {
+            Some(id) => vec![id],
+            None => match self.db.list_books().await {
+                Ok(books) => books.into_iter().map(|b| b.id).collect(),
+                Err(e) => {
+                    self.job_manager.fail_job(job_id, &format!("Failed to list books: {}", e)).await;
+                    return;
+                }
+            },
+        };
+
+        let ocr_service = OcrService::new(&self.config);
+        let files = FileService::from_config(&self.config);
+        let mut audited = 0u32;
+        let mut diverged = Vec::new();
+        let mut errors = Vec::new();
+
+        for (book_idx, book_id) in books.iter().enumerate() {
+            if let Some(job) = self.job_manager.get_job(job_id).await
+                && matches!(job.status, JobStatus::Cancelled)
+            {
+                return;
+            }
+
+            let progress = book_idx as f32 / books.len() as f32 * 100.0;
+            self.job_manager.update_progress(job_id, progress, &format!("Auditing {}", book_id)).await;
+
+            let pages = match self.db.get_pages_by_book(book_id).await {
+                Ok(pages) => pages,
+                Err(e) => {
+                    errors.push(format!("{}: failed to load pages - {}", book_id, e));
+                    continue;
+                }
+            };
+
+            let mut candidates: Vec<_> = pages
+                .into_iter()
+                .filter(|p| p.ocr_text.as_deref().is_some_and(|t| !t.trim().is_empty()))
+                .collect();
+            candidates.shuffle(&mut rand::thread_rng());
+            candidates.truncate(sample_size);
+
+            let files = book_file_service(&self.db, &files, book_id).await;
+            for page in candidates {
+                let image_path = files.page_image(&files.book_file(book_id), page.page_number);
+
+                let text = match ocr_service.run_ocr(&image_path, provider).await {
+                    Ok(t) => t,
+                    Err(e) => {
+                        errors.push(format!("{} page {}: {}", book_id, page.page_number, e));
+                        continue;
+                    }
+                };
+
+                let baseline = page.ocr_text.as_deref().unwrap_or("");
+                let similarity = text_similarity(baseline, &text);
+                let entry = OcrAuditEntry {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    book_id: book_id.clone(),
+                    page_number: page.page_number,
+                    provider: provider.to_string(),
+                    similarity,
+                    diverged: similarity < DIVERGENCE_THRESHOLD,
+                    created_at: chrono::Utc::now(),
+                };
+
+                if entry.diverged {
+                    log::warn!(
+                        "OCR audit: {} page {} diverges from '{}' output (similarity {:.2})",
+                        book_id,
+                        page.page_number,
+                        provider,
+                        similarity
+                    );
+                    diverged.push(serde_json::json!({
+                        "book_id": book_id,
+                        "page_number": page.page_number,
+                        "similarity": similarity,
+                    }));
+                }
+
+                if let Err(e) = self.db.save_ocr_audit(&entry).await {
+                    errors.push(format!("{} page {}: failed to save audit - {}", book_id, page.page_number, e));
+                }
+                audited += 1;
+            }
+        }
+
+        self.job_manager.complete_job(job_id, serde_json::json!({
+            "books": books.len(),
+            "audited_pages": audited,
+            "diverged": diverged,
+            "errors": errors,
+        })).await;
+    }
+}
+
+/// Token-level Jaccard similarity between two OCR outputs (0.0 - 1.0).
+///
+/// Whitespace and case are ignored so layout differences between providers
+/// don't count as divergence.
+pub fn text_similarity(a: &str, b: &str) -> f64 {
+    let tokens = |s: &str| -> HashSet<String> {
+        s.split_whitespace().map(|t| t.to_lowercase()).collect()
+    };
+
+    let a = tokens(a);
+    let b = tokens(b);
+
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let intersection = a.intersection(&b).count();
+    let union = a.union(&b).count();
+
+    intersection as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_text_is_fully_similar() {
+        assert_eq!(text_similarity("Решите уравнение x + 1 = 2", "Решите  уравнение\nx + 1 = 2"), 1.0);
+    }
+
+    #[test]
+    fn unrelated_text_diverges() {
+        let sim = text_similarity("Найдите значение выражения", "Lorem ipsum dolor sit amet");
+        assert!(sim < DIVERGENCE_THRESHOLD);
+    }
+
+    #[test]
+    fn empty_pages_are_similar() {
+        assert_eq!(text_similarity("", "  "), 1.0);
+        assert_eq!(text_similarity("text", ""), 0.0);
+    }
+}
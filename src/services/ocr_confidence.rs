@@ -0,0 +1,134 @@
+use lazy_regex::regex;
+use serde_json::Value;
+
+use crate::services::validation::validate_latex;
+
+/// Problems below this are surfaced first for review
+pub const LOW_CONFIDENCE_THRESHOLD: f32 = 0.6;
+
+/// Page-level confidence reported by the OCR provider, when it has one.
+///
+/// Mathpix reports `confidence`/`confidence_rate`; Azure/Google report it per
+/// line or word, which is averaged. Mistral and the LLM providers report none.
+pub fn provider_confidence(payload: &Value) -> Option<f32> {
+    for key in ["confidence", "confidence_rate"] {
+        if let Some(c) = payload.get(key).and_then(|v| v.as_f64()) {
+            return Some(c.clamp(0.0, 1.0) as f32);
+        }
+    }
+
+    let mut values = Vec::new();
+    collect_confidences(payload, &mut values);
+    if values.is_empty() {
+        return None;
+    }
+    Some((values.iter().sum::<f64>() / values.len() as f64).clamp(0.0, 1.0) as f32)
+}
+
+fn collect_confidences(value: &Value, out: &mut Vec<f64>) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                match (key.as_str(), v.as_f64()) {
+                    ("confidence", Some(c)) => out.push(c),
+                    _ => collect_confidences(v, out),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_confidences(v, out)),
+        _ => {}
+    }
+}
+
+/// Heuristic confidence (0.0 - 1.0) of an OCR'd text fragment.
+///
+/// Penalizes the usual OCR failure signs: replacement/garbage characters,
+/// words mixing Cyrillic and Latin letters (`3адача`, `Зaдача`), broken LaTeX
+/// and fragments too short to be a real problem statement.
+pub fn estimate_text_confidence(text: &str) -> f32 {
+    let text = text.trim();
+    if text.is_empty() {
+        return 0.0;
+    }
+
+    let mut score = 1.0f32;
+
+    let total = text.chars().count() as f32;
+    let garbage = text
+        .chars()
+        .filter(|c| *c == '\u{FFFD}' || (c.is_control() && !c.is_whitespace()) || ('\u{E000}'..='\u{F8FF}').contains(c))
+        .count() as f32;
+    score -= (garbage / total * 10.0).min(0.5);
+
+    let words: Vec<&str> = regex!(r"\p{L}{2,}").find_iter(text).map(|m| m.as_str()).collect();
+    if !words.is_empty() {
+        let mixed = words
+            .iter()
+            .filter(|w| {
+                let cyr = w.chars().any(|c| matches!(c, 'а'..='я' | 'А'..='Я' | 'ё' | 'Ё'));
+                let lat = w.chars().any(|c| c.is_ascii_alphabetic());
+                cyr && lat
+            })
+            .count() as f32;
+        score -= (mixed / words.len() as f32 * 2.0).min(0.4);
+    }
+
+    let latex_errors = validate_latex(text)
+        .iter()
+        .filter(|e| !e.starts_with("Possible misspelling"))
+        .count() as f32;
+    score -= (latex_errors * 0.15).min(0.45);
+
+    if total < 8.0 {
+        score -= 0.2;
+    }
+
+    score.clamp(0.0, 1.0)
+}
+
+/// Combine the provider's page confidence with the text heuristic
+pub fn problem_confidence(content: &str, provider: Option<f32>) -> f32 {
+    let text = estimate_text_confidence(content);
+    match provider {
+        Some(p) => (p.clamp(0.0, 1.0) * text).clamp(0.0, 1.0),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_text_scores_high() {
+        let c = estimate_text_confidence(r"Решите уравнение $\frac{x}{2} + 3 = 7$.");
+        assert!(c > 0.95, "{}", c);
+    }
+
+    #[test]
+    fn ocr_artifacts_lower_confidence() {
+        let clean = estimate_text_confidence("Найдите значение выражения при x = 2");
+        let mixed = estimate_text_confidence("Haйдите знaчение выражения пpи x = 2");
+        let broken = estimate_text_confidence(r"Найдите значение $\frac{1}{2 + \sqrt{x$ при x = 2");
+        let garbage = estimate_text_confidence("Найдите \u{FFFD}\u{FFFD}\u{FFFD} значение");
+        assert!(mixed < clean);
+        assert!(broken < clean);
+        assert!(garbage < clean);
+        assert_eq!(estimate_text_confidence("   "), 0.0);
+    }
+
+    #[test]
+    fn reads_provider_confidence() {
+        assert_eq!(provider_confidence(&serde_json::json!({"confidence": 0.8, "text": "x"})), Some(0.8));
+        let azure = serde_json::json!({"lines": [{"confidence": 0.5}, {"words": [{"confidence": 1.0}]}]});
+        assert_eq!(provider_confidence(&azure), Some(0.75));
+        assert_eq!(provider_confidence(&serde_json::json!({"pages": [{"markdown": "x"}]})), None);
+    }
+
+    #[test]
+    fn combines_provider_and_text_scores() {
+        let text = "Решите уравнение 2x + 3 = 7";
+        assert!(problem_confidence(text, Some(0.5)) <= 0.5);
+        assert_eq!(problem_confidence(text, None), estimate_text_confidence(text));
+    }
+}
@@ -0,0 +1,162 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::services::database::Database;
+use crate::services::dehyphenate::dehyphenate;
+
+/// Regex find/replace applied to a book's OCR text before parsing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcrRule {
+    pub id: String,
+    pub book_id: String,
+    pub pattern: String,
+    /// Replacement text; `$1`/`${name}` refer to capture groups
+    pub replacement: String,
+    /// Rules run in ascending position order
+    pub position: i64,
+    pub enabled: bool,
+    pub description: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Single replacement made by a rule, for the preview diff
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleChange {
+    pub rule_id: Option<String>,
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RulePreview {
+    pub original: String,
+    pub processed: String,
+    pub changes: Vec<RuleChange>,
+}
+
+/// Compile a rule pattern, returning a readable error for invalid regexes
+pub fn compile_pattern(pattern: &str) -> anyhow::Result<Regex> {
+    Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid pattern '{}': {}", pattern, e))
+}
+
+/// Apply enabled rules in order. Rules with invalid patterns are skipped.
+pub fn apply_rules(rules: &[OcrRule], text: &str) -> String {
+    preview_rules(rules, text).processed
+}
+
+/// Apply rules and record every replacement they made
+pub fn preview_rules(rules: &[OcrRule], text: &str) -> RulePreview {
+    let mut ordered: Vec<&OcrRule> = rules.iter().filter(|r| r.enabled).collect();
+    ordered.sort_by_key(|r| r.position);
+
+    let mut processed = text.to_string();
+    let mut changes = Vec::new();
+
+    for rule in ordered {
+        let re = match compile_pattern(&rule.pattern) {
+            Ok(re) => re,
+            Err(e) => {
+                log::warn!("Skipping OCR rule {}: {}", rule.id, e);
+                continue;
+            }
+        };
+
+        for caps in re.captures_iter(&processed) {
+            let m = caps.get(0).unwrap();
+            let mut after = String::new();
+            caps.expand(&rule.replacement, &mut after);
+            if after != m.as_str() {
+                changes.push(RuleChange {
+                    rule_id: (!rule.id.is_empty()).then(|| rule.id.clone()),
+                    line: processed[..m.start()].matches('\n').count() + 1,
+                    before: m.as_str().to_string(),
+                    after,
+                });
+            }
+        }
+
+        processed = re.replace_all(&processed, rule.replacement.as_str()).into_owned();
+    }
+
+    RulePreview {
+        original: text.to_string(),
+        processed,
+        changes,
+    }
+}
+
+/// Post-process freshly OCR'd text: repair line-break hyphenation, then run
+/// the book's rules, each step unless the book's settings turn it off.
+///
+/// Failures to load rules are logged and the rules step is skipped, so a
+/// broken rules table never blocks OCR.
+pub async fn postprocess_ocr_text(db: &Database, book_id: &str, text: &str) -> String {
+    let steps = match db.get_book_settings(book_id).await {
+        Ok(settings) => settings.post_processing,
+        Err(e) => {
+            log::warn!("Failed to load settings of {}: {}", book_id, e);
+            Default::default()
+        }
+    };
+    let text = if steps.dehyphenate { dehyphenate(text) } else { text.to_string() };
+    if !steps.ocr_rules {
+        return text;
+    }
+    match db.get_ocr_rules(book_id).await {
+        Ok(rules) if !rules.is_empty() => apply_rules(&rules, &text),
+        Ok(_) => text,
+        Err(e) => {
+            log::warn!("Failed to load OCR rules for {}: {}", book_id, e);
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, position: i64, pattern: &str, replacement: &str) -> OcrRule {
+        OcrRule {
+            id: id.to_string(),
+            book_id: "algebra-7".to_string(),
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            position,
+            enabled: true,
+            description: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn applies_rules_in_position_order() {
+        let rules = vec![
+            rule("b", 2, r"33", "3"),
+            rule("a", 1, r"(\d)З", "${1}3"),
+        ];
+        assert_eq!(apply_rules(&rules, "№1З: x = 2З"), "№13: x = 23");
+
+        let mut disabled = rule("c", 0, "x", "y");
+        disabled.enabled = false;
+        assert_eq!(apply_rules(&[disabled], "x"), "x");
+    }
+
+    #[test]
+    fn preview_lists_changes_with_lines() {
+        let rules = vec![rule("lig", 1, "ﬁ", "fi")];
+        let preview = preview_rules(&rules, "first line\nﬁnd x\nﬁx");
+        assert_eq!(preview.processed, "first line\nfind x\nfix");
+        assert_eq!(preview.changes.len(), 2);
+        assert_eq!(preview.changes[0].line, 2);
+        assert_eq!(preview.changes[1].line, 3);
+        assert_eq!(preview.changes[0].after, "fi");
+    }
+
+    #[test]
+    fn invalid_patterns_are_skipped() {
+        assert!(compile_pattern("(").is_err());
+        assert_eq!(apply_rules(&[rule("bad", 1, "(", "")], "text"), "text");
+    }
+}
@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use image::GrayImage;
+
+use crate::models::PageKind;
+
+/// Longest side of the thumbnail the histogram is taken from
+const ANALYSIS_SIZE: u32 = 600;
+
+/// Border (fraction of each side) ignored for scan shadows and page edges
+const MARGIN: f64 = 0.05;
+
+/// Paper darker than this is a dark-mode page or negative scan: light ink on
+/// dark paper, measured as if inverted
+const DARK_PAPER: u8 = 128;
+
+/// A pixel is ink when it is this much darker than the paper
+const INK_CONTRAST: u8 = 40;
+
+/// Ink below this share of the page is scanner noise: the page is blank
+const BLANK_MAX_INK: f64 = 0.003;
+
+/// Pages with more ink than this are pictures, not text
+const IMAGE_MIN_INK: f64 = 0.40;
+
+/// Text is black on paper; a page where this share of pixels is mid-gray is
+/// a photo or shaded illustration
+const IMAGE_MIN_MIDTONES: f64 = 0.25;
+
+/// Narrowest gutter between two text columns, as a share of the page width
+const MIN_GUTTER: f64 = 0.015;
+
+/// A gutter column of pixels has at most this share of the ink of a text one
+const GUTTER_MAX_INK: f64 = 0.1;
+
+/// Brightness histogram summary of a page image
+#[derive(Debug, Clone, Copy)]
+pub struct PageStats {
+    /// Brightness (0-255) of the paper, the most common value; for inverted
+    /// pages, of the paper once inverted
+    pub paper: u8,
+    /// Whether the page is light ink on dark paper
+    pub inverted: bool,
+    /// Share of pixels further from the paper than `INK_CONTRAST`
+    pub ink: f64,
+    /// Share of pixels that are mid-gray rather than black or paper
+    pub midtones: f64,
+}
+
+impl PageStats {
+    pub fn kind(&self) -> PageKind {
+        if self.ink < BLANK_MAX_INK {
+            PageKind::Blank
+        } else if self.ink > IMAGE_MIN_INK || self.midtones > IMAGE_MIN_MIDTONES {
+            PageKind::ImageOnly
+        } else {
+            PageKind::Text
+        }
+    }
+}
+
+/// Histogram statistics of the page area inside the margins
+pub fn page_stats(image: &GrayImage) -> PageStats {
+    let (width, height) = image.dimensions();
+    let (mx, my) = ((width as f64 * MARGIN) as u32, (height as f64 * MARGIN) as u32);
+
+    let mut histogram = [0u64; 256];
+    for y in my..height.saturating_sub(my) {
+        for x in mx..width.saturating_sub(mx) {
+            histogram[image.get_pixel(x, y).0[0] as usize] += 1;
+        }
+    }
+
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return PageStats { paper: 255, inverted: false, ink: 0.0, midtones: 0.0 };
+    }
+
+    let mut paper = (0..256).max_by_key(|&v| histogram[v]).unwrap_or(255) as u8;
+    let inverted = paper < DARK_PAPER;
+    if inverted {
+        // Measure light-on-dark pages as their negative
+        histogram.reverse();
+        paper = 255 - paper;
+    }
+    let ink_below = paper.saturating_sub(INK_CONTRAST) as usize;
+    let ink: u64 = histogram[..ink_below].iter().sum();
+    // Mid-gray: the middle half between black and the paper
+    let midtones: u64 = histogram[(ink_below / 4)..(ink_below * 3 / 4)].iter().sum();
+
+    PageStats {
+        paper,
+        inverted,
+        ink: ink as f64 / total as f64,
+        midtones: midtones as f64 / total as f64,
+    }
+}
+
+/// Text columns of a page: two when an empty vertical gutter runs through
+/// the middle third between inked halves, one otherwise. None for blank pages.
+pub fn column_count(image: &GrayImage) -> Option<u8> {
+    let stats = page_stats(image);
+    if stats.kind() == PageKind::Blank {
+        return None;
+    }
+    let (width, height) = image.dimensions();
+    let (mx, my) = ((width as f64 * MARGIN) as u32, (height as f64 * MARGIN) as u32);
+    let ink_below = stats.paper.saturating_sub(INK_CONTRAST);
+    let is_ink = |x: u32, y: u32| {
+        let value = image.get_pixel(x, y).0[0];
+        (if stats.inverted { 255 - value } else { value }) < ink_below
+    };
+
+    let ink: Vec<usize> = (mx..width.saturating_sub(mx))
+        .map(|x| (my..height.saturating_sub(my)).filter(|&y| is_ink(x, y)).count())
+        .collect();
+    let mut sorted = ink.clone();
+    sorted.sort_unstable();
+    // Most pixel columns of a text page cross text; the upper quartile is one that does
+    let text_ink = sorted.get(sorted.len() * 3 / 4).copied().unwrap_or(0) as f64;
+    let is_gutter = |i: usize| ink[i] as f64 <= text_ink * GUTTER_MAX_INK;
+
+    let (middle_start, middle_end) = (ink.len() / 3, ink.len() * 2 / 3);
+    let (mut run, mut widest) = (0, 0);
+    for i in middle_start..middle_end {
+        run = if is_gutter(i) { run + 1 } else { 0 };
+        widest = widest.max(run);
+    }
+    let mostly_inked = |range: std::ops::Range<usize>| {
+        let len = range.len();
+        range.filter(|&i| !is_gutter(i)).count() * 2 > len
+    };
+    let two_columns = widest as f64 >= width as f64 * MIN_GUTTER
+        && mostly_inked(0..middle_start)
+        && mostly_inked(middle_end..ink.len());
+    Some(if two_columns { 2 } else { 1 })
+}
+
+fn analysis_image(path: &Path) -> Result<GrayImage, String> {
+    Ok(image::open(path)
+        .map_err(|e| format!("Failed to open {:?}: {}", path, e))?
+        .thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE)
+        .to_luma8())
+}
+
+/// Classify a rendered page image as blank, text or picture without OCR
+pub fn classify_page(path: &Path) -> Result<(PageKind, PageStats), String> {
+    let stats = page_stats(&analysis_image(path)?);
+    Ok((stats.kind(), stats))
+}
+
+/// [`column_count`] of a rendered page image
+pub fn page_columns(path: &Path) -> Result<Option<u8>, String> {
+    Ok(column_count(&analysis_image(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Off-white page with dark borders the margin should hide
+    fn page(paper: u8) -> GrayImage {
+        GrayImage::from_fn(200, 300, |x, y| {
+            if x < 4 || y < 4 || x > 195 || y > 295 { Luma([20]) } else { Luma([paper]) }
+        })
+    }
+
+    #[test]
+    fn blank_page_with_noise_and_borders() {
+        let mut image = page(235);
+        image.put_pixel(100, 100, Luma([0]));
+        assert_eq!(page_stats(&image).kind(), PageKind::Blank);
+    }
+
+    #[test]
+    fn text_lines_are_text() {
+        let mut image = page(225);
+        for line in 0..20 {
+            let y = 30 + line * 12;
+            for x in 20..180 {
+                if x % 5 != 0 {
+                    image.put_pixel(x, y, Luma([15]));
+                    image.put_pixel(x, y + 1, Luma([15]));
+                }
+            }
+        }
+        let stats = page_stats(&image);
+        assert_eq!(stats.paper, 225);
+        assert_eq!(stats.kind(), PageKind::Text, "{:?}", stats);
+    }
+
+    #[test]
+    fn light_text_on_dark_paper_is_text() {
+        let mut image = page(30);
+        for line in 0..20 {
+            let y = 30 + line * 12;
+            for x in 20..180 {
+                if x % 5 != 0 {
+                    image.put_pixel(x, y, Luma([220]));
+                    image.put_pixel(x, y + 1, Luma([220]));
+                }
+            }
+        }
+        let stats = page_stats(&image);
+        assert!(stats.inverted);
+        assert_eq!(stats.paper, 225);
+        assert_eq!(stats.kind(), PageKind::Text, "{:?}", stats);
+        assert_eq!(column_count(&image), Some(1));
+        assert_eq!(page_stats(&page(30)).kind(), PageKind::Blank);
+    }
+
+    #[test]
+    fn counts_text_columns() {
+        let lines = |image: &mut GrayImage, columns: &[(u32, u32)]| {
+            for line in 0..20 {
+                let y = 30 + line * 12;
+                for &(start, end) in columns {
+                    for x in start..end {
+                        if x % 5 != 0 {
+                            image.put_pixel(x, y, Luma([15]));
+                        }
+                    }
+                }
+            }
+        };
+        let mut single = page(230);
+        lines(&mut single, &[(20, 180)]);
+        let mut double = page(230);
+        lines(&mut double, &[(20, 92), (108, 180)]);
+        assert_eq!(column_count(&single), Some(1));
+        assert_eq!(column_count(&double), Some(2));
+        assert_eq!(column_count(&page(230)), None);
+    }
+
+    #[test]
+    fn shaded_illustration_is_image_only() {
+        let mut image = page(240);
+        for y in 40..260 {
+            for x in 30..170 {
+                image.put_pixel(x, y, Luma([60 + ((x + y) % 100) as u8]));
+            }
+        }
+        assert_eq!(page_stats(&image).kind(), PageKind::ImageOnly);
+    }
+}
@@ -1,9 +1,141 @@
+use lazy_regex::regex;
 use serde::{Deserialize, Serialize};
-use crate::models::{Problem, TheoryBlock, TheoryType};
+use crate::models::{Problem, ReviewStatus, TheoryBlock, TheoryType};
+use std::time::Duration;
+use crate::services::ai_parser::{join_split_letters, mistral_parse_json, DEFAULT_PARSE_TIMEOUT};
+use crate::services::ocr_confidence::problem_confidence;
+
+/// Prompt of [`PageContentParser`]; the page's OCR text goes between the two parts
+const PAGE_PROMPT_HEAD: &str = r#"Ты - эксперт по анализу учебников. Разбери страницу и извлеки ВСЕ элементы.
+
+ЭЛЕМЕНТЫ ДЛЯ ИЗВЛЕЧЕНИЯ:
+
+1. МЕТАДАННЫЕ СТРАНИЦЫ:
+   - Номер страницы (обычно вверху/внизу)
+   - Название главы/раздела
+   - Заголовок страницы
+
+2. ТЕОРИЯ (важно!):
+   - Определения ("Определение 1. ...")
+   - Теоремы ("Теорема 1. ..." + доказательство)
+   - Леммы, следствия
+   - Свойства, аксиомы
+   - Формулы (выделенные отдельно)
+   - Методы решения
+
+3. ПРИМЕРЫ:
+   - Примеры с решениями ("Пример 1.")
+   - Разбор задач
+
+4. ЗАДАЧИ:
+   - Номер + условие + подзадачи (а, б, в)
+
+5. РИСУНКИ/ГРАФИКИ:
+   - Описание изображений
+   - Подписи к рисункам ("Рис. 1. ...")
+   - Графики функций
+
+6. ТАБЛИЦЫ:
+   - Таблицы с данными
+
+7. ЗАМЕЧАНИЯ:
+   - Примечания, советы, предупреждения
+
+8. УПРАЖНЕНИЯ:
+   - Для самостоятельной работы
+
+ФОРМАТ ОТВЕТА (строго JSON):
+{
+  "metadata": {
+    "page_number": 15,
+    "chapter_title": "Квадратные уравнения",
+    "section_title": "Формула дискриминанта",
+    "header": "...",
+    "footer": "..."
+  },
+  "elements": [
+    {
+      "type": "theory",
+      "theory_type": "definition",
+      "title": "Квадратное уравнение",
+      "number": "1",
+      "content": "Квадратным уравнением называется...",
+      "formulas": ["ax^2 + bx + c = 0"],
+      "importance": "critical"
+    },
+    {
+      "type": "theorem", 
+      "theory_type": "theorem",
+      "title": "Теорема Виета",
+      "number": "2",
+      "content": "Если x1, x2 - корни...",
+      "formulas": ["x1 + x2 = -b/a", "x1 * x2 = c/a"],
+      "importance": "critical"
+    },
+    {
+      "type": "example",
+      "number": "1",
+      "problem": "Решить x^2 - 5x + 6 = 0",
+      "solution": "D = 25 - 24 = 1...",
+      "formulas": ["D = b^2 - 4ac"],
+      "is_solved": true
+    },
+    {
+      "type": "problem",
+      "number": "125",
+      "content": "Решите уравнение...",
+      "sub_problems": [
+        {"letter": "а", "content": "x^2 = 4"},
+        {"letter": "б", "content": "x^2 = 9"}
+      ],
+      "difficulty": 5,
+      "category": "квадратные уравнения"
+    },
+    {
+      "type": "figure",
+      "number": "1",
+      "caption": "График параболы",
+      "description": "Парабола y = x^2 с ветвями вверх...",
+      "figure_type": "graph"
+    },
+    {
+      "type": "remark",
+      "remark_type": "note", 
+      "content": "Обратите внимание..."
+    },
+    {
+      "type": "text",
+      "content": "Текстовый абзац...",
+      "is_intro": false,
+      "is_conclusion": false
+    }
+  ],
+  "stats": {
+    "problem_count": 5,
+    "theory_count": 3,
+    "example_count": 2,
+    "figure_count": 1,
+    "exercise_count": 0,
+    "total_formulas": 8
+  }
+}
+
+ВАЖНО:
+- Извлекай ВСЕ элементы в порядке их появления
+- Теория приоритетнее задач
+- Сохраняй LaTeX формулы в content и formulas
+- Если нет элемента, не включай его
+
+OCR текст:
+"#;
+const PAGE_PROMPT_TAIL: &str = r#"
+
+Верни ТОЛЬКО JSON, без markdown."#;
 
 /// Complete page content parser - extracts ALL elements from page
 pub struct PageContentParser {
     api_key: Option<String>,
+    timeout: Duration,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -186,14 +318,20 @@ pub struct PageStats {
 
 impl PageContentParser {
     pub fn new(api_key: Option<String>) -> Self {
-        Self { api_key }
+        Self { api_key, timeout: DEFAULT_PARSE_TIMEOUT }
+    }
+
+    /// Timeout of each parsing request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
     
     /// Parse complete page content
     pub async fn parse_page(&self, ocr_text: &str, page_num: Option<u32>) -> anyhow::Result<ParsedPageContent> {
         // Try AI parser first
         if let Some(ref key) = self.api_key {
-            match self.ai_parse_page(ocr_text, page_num, key).await {
+            match self.ai_parse_page(ocr_text, key).await {
                 Ok(result) => return Ok(result),
                 Err(e) => {
                     log::warn!("AI page parser failed, using regex fallback: {}", e);
@@ -206,189 +344,12 @@ impl PageContentParser {
     }
     
     /// AI-powered page parsing
-    async fn ai_parse_page(&self, text: &str, page_num: Option<u32>, api_key: &str) -> anyhow::Result<ParsedPageContent> {
-        let python_script = format!(r#"
-import json
-import re
-from mistralai import Mistral
-
-api_key = os.getenv("MISTRAL_API_KEY", "{}")
-client = Mistral(api_key=api_key)
-
-ocr_text = '''{}'''
-
-# Clean OCR
-ocr_text = re.sub(r'([а-яa-z])\n\s*\1', r'\1', ocr_text)
-ocr_text = re.sub(r'\n\s*\n+', '\n\n', ocr_text)
-
-prompt = '''Ты - эксперт по анализу учебников. Разбери страницу и извлеки ВСЕ элементы.
-
-ЭЛЕМЕНТЫ ДЛЯ ИЗВЛЕЧЕНИЯ:
-
-1. МЕТАДАННЫЕ СТРАНИЦЫ:
-   - Номер страницы (обычно вверху/внизу)
-   - Название главы/раздела
-   - Заголовок страницы
-
-2. ТЕОРИЯ (важно!):
-   - Определения ("Определение 1. ...")
-   - Теоремы ("Теорема 1. ..." + доказательство)
-   - Леммы, следствия
-   - Свойства, аксиомы
-   - Формулы (выделенные отдельно)
-   - Методы решения
-
-3. ПРИМЕРЫ:
-   - Примеры с решениями ("Пример 1.")
-   - Разбор задач
-
-4. ЗАДАЧИ:
-   - Номер + условие + подзадачи (а, б, в)
-
-5. РИСУНКИ/ГРАФИКИ:
-   - Описание изображений
-   - Подписи к рисункам ("Рис. 1. ...")
-   - Графики функций
-
-6. ТАБЛИЦЫ:
-   - Таблицы с данными
-
-7. ЗАМЕЧАНИЯ:
-   - Примечания, советы, предупреждения
-
-8. УПРАЖНЕНИЯ:
-   - Для самостоятельной работы
-
-ФОРМАТ ОТВЕТА (строго JSON):
-{{
-  "metadata": {{
-    "page_number": 15,
-    "chapter_title": "Квадратные уравнения",
-    "section_title": "Формула дискриминанта",
-    "header": "...",
-    "footer": "..."
-  }},
-  "elements": [
-    {{
-      "type": "theory",
-      "theory_type": "definition",
-      "title": "Квадратное уравнение",
-      "number": "1",
-      "content": "Квадратным уравнением называется...",
-      "formulas": ["ax^2 + bx + c = 0"],
-      "importance": "critical"
-    }},
-    {{
-      "type": "theorem", 
-      "theory_type": "theorem",
-      "title": "Теорема Виета",
-      "number": "2",
-      "content": "Если x1, x2 - корни...",
-      "formulas": ["x1 + x2 = -b/a", "x1 * x2 = c/a"],
-      "importance": "critical"
-    }},
-    {{
-      "type": "example",
-      "number": "1",
-      "problem": "Решить x^2 - 5x + 6 = 0",
-      "solution": "D = 25 - 24 = 1...",
-      "formulas": ["D = b^2 - 4ac"],
-      "is_solved": true
-    }},
-    {{
-      "type": "problem",
-      "number": "125",
-      "content": "Решите уравнение...",
-      "sub_problems": [
-        {{"letter": "а", "content": "x^2 = 4"}},
-        {{"letter": "б", "content": "x^2 = 9"}}
-      ],
-      "difficulty": 5,
-      "category": "квадратные уравнения"
-    }},
-    {{
-      "type": "figure",
-      "number": "1",
-      "caption": "График параболы",
-      "description": "Парабола y = x^2 с ветвями вверх...",
-      "figure_type": "graph"
-    }},
-    {{
-      "type": "remark",
-      "remark_type": "note", 
-      "content": "Обратите внимание..."
-    }},
-    {{
-      "type": "text",
-      "content": "Текстовый абзац...",
-      "is_intro": false,
-      "is_conclusion": false
-    }}
-  ],
-  "stats": {{
-    "problem_count": 5,
-    "theory_count": 3,
-    "example_count": 2,
-    "figure_count": 1,
-    "exercise_count": 0,
-    "total_formulas": 8
-  }}
-}}
-
-ВАЖНО:
-- Извлекай ВСЕ элементы в порядке их появления
-- Теория приоритетнее задач
-- Сохраняй LaTeX формулы в content и formulas
-- Если нет элемента, не включай его
-
-OCR текст:
-''' + ocr_text + '''
-
-Верни ТОЛЬКО JSON, без markdown.'''
-
-try:
-    import os
-    response = client.chat.complete(
-        model="mistral-large-latest",
-        messages=[{{"role": "user", "content": prompt}}],
-        temperature=0.1,
-        max_tokens=8000
-    )
-    
-    result_text = response.choices[0].message.content.strip()
-    result_text = re.sub(r'^```json\s*', '', result_text)
-    result_text = re.sub(r'^```\s*', '', result_text)
-    result_text = re.sub(r'```\s*$', '', result_text)
-    result_text = result_text.strip()
-    
-    data = json.loads(result_text)
-    print(json.dumps(data, ensure_ascii=False))
-    
-except Exception as e:
-    print(json.dumps({{"error": str(e)}}, ensure_ascii=False))
-    raise
-"#, api_key, text.replace("'''", "'''"));
-
-        let output = std::process::Command::new("python3")
-            .arg("-c")
-            .arg(&python_script)
-            .env("MISTRAL_API_KEY", api_key)
-            .output()
-            .map_err(|e| anyhow::anyhow!("Failed to run Python: {}", e))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("AI parsing failed: {}", stderr));
-        }
-
-        let result: ParsedPageContent = serde_json::from_str(&stdout)
-            .map_err(|e| anyhow::anyhow!("Failed to parse AI response: {}. Output: {}", e, stdout))?;
-
-        Ok(result)
+    async fn ai_parse_page(&self, text: &str, api_key: &str) -> anyhow::Result<ParsedPageContent> {
+        let ocr_text = regex!(r"\n\s*\n+").replace_all(&join_split_letters(text), "\n\n").into_owned();
+        let prompt = [PAGE_PROMPT_HEAD, &ocr_text, PAGE_PROMPT_TAIL].concat();
+        mistral_parse_json(api_key, &prompt, 0.1, self.timeout).await
     }
-    
+
     /// Regex-based fallback parser
     fn regex_parse_page(&self, text: &str, page_num: Option<u32>) -> ParsedPageContent {
         let mut elements = Vec::new();
@@ -848,14 +809,19 @@ pub fn convert_to_models(
     for elem in parsed.elements {
         match elem {
             PageElement::Problem(p) => {
-                let problem_id = format!("{}:{}:{}", book_id, chapter_num, p.number);
+                let number = Problem::normalize_number(&p.number);
                 problems.push(Problem {
-                    id: problem_id,
+                    id: Problem::generate_id(book_id, chapter_num, &number),
                     chapter_id: format!("{}:{}", book_id, chapter_num),
                     page_id: None,
                     parent_id: None,
-                    number: p.number.clone(),
-                    display_name: format!("Задача {}", p.number),
+                    display_name: format!("Задача {}", number),
+                    number,
+                    confidence: Some(problem_confidence(&p.content, None)),
+                    review_status: ReviewStatus::Unreviewed,
+                    review_note: None,
+                    reference_answer: None,
+                    version: 1,
                     content: p.content,
                     latex_formulas: p.formulas,
                     page_number: None,
@@ -1,4 +1,4 @@
-use crate::models::problem::{Problem, TheoryBlock, TheoryType};
+use crate::models::problem::{Problem, ReviewStatus, TheoryBlock, TheoryType};
 use chrono::Utc;
 use lazy_regex::regex;
 use regex::Regex;
@@ -205,6 +205,109 @@ impl TextbookParser {
         }
     }
 
+    /// Parse a hand-written markdown file.
+    ///
+    /// Unlike OCR text only headings open problems (`## Задача 5`,
+    /// `## Упражнение 3`, `## №12`), so numbered lists inside a problem stay
+    /// part of its content. Sub-problems are lines starting with `а)`, `б)`...
+    /// and theory blocks are headings such as `## Теорема 1`.
+    pub fn parse_markdown(&self, text: &str, book_id: &str, chapter_num: u32) -> ParseResult {
+        let heading_re = regex!(
+            r"(?i)^\s*#{1,6}\s*(?:Задача|Упражнение|Problem|Exercise)?\s*[№#]?\s*(\d+(?:\.\d+)*)\s*[:.)]?\s*(.*)$"
+        );
+
+        let mut problems = Vec::new();
+        let mut theory_blocks = Vec::new();
+        let mut unclassified = Vec::new();
+
+        let mut current_problem: Option<ProblemBuilder> = None;
+        let mut current_theory: Option<TheoryBuilder> = None;
+        let mut current_unclassified = String::new();
+        let mut theory_counter = 0u32;
+
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if trimmed.starts_with('#') {
+                if let Some(pb) = current_problem.take() {
+                    problems.push(pb.build(book_id, chapter_num));
+                }
+                if let Some(tb) = current_theory.take() {
+                    theory_blocks.push(tb.build(book_id, chapter_num));
+                }
+
+                if let Some(caps) = heading_re.captures(trimmed) {
+                    current_problem = Some(ProblemBuilder::new(caps[1].to_string(), caps[2].trim()));
+                } else if let Some((theory_type, title)) =
+                    self.detect_theory_start(trimmed.trim_start_matches('#'))
+                {
+                    theory_counter += 1;
+                    current_theory = Some(TheoryBuilder::new(theory_counter, theory_type, title));
+                } else {
+                    // Any other heading (chapter title, section) closes the current block
+                    if !current_unclassified.is_empty() {
+                        unclassified.push(std::mem::take(&mut current_unclassified));
+                    }
+                    current_unclassified.push_str(trimmed);
+                    current_unclassified.push('\n');
+                }
+                continue;
+            }
+
+            if let Some(ref mut pb) = current_problem {
+                let item = trimmed.trim_start_matches(['-', '*']).trim_start();
+                if let Some(letter) = self.detect_sub_problem(item) {
+                    pb.start_sub_problem(letter, item);
+                } else if pb.current_sub.is_some() {
+                    pb.add_line_to_sub(trimmed);
+                } else {
+                    pb.add_line(trimmed);
+                }
+            } else if let Some(ref mut tb) = current_theory {
+                tb.add_line(trimmed);
+            } else {
+                current_unclassified.push_str(trimmed);
+                current_unclassified.push('\n');
+            }
+        }
+
+        if let Some(pb) = current_problem {
+            problems.push(pb.build(book_id, chapter_num));
+        }
+        if let Some(tb) = current_theory {
+            theory_blocks.push(tb.build(book_id, chapter_num));
+        }
+        if !current_unclassified.is_empty() {
+            unclassified.push(current_unclassified);
+        }
+
+        let problems = problems
+            .into_iter()
+            .map(|mut p| {
+                p.content = p.content.trim().to_string();
+                p.latex_formulas = p.extract_formulas();
+                p
+            })
+            .collect();
+
+        let theory_blocks = theory_blocks
+            .into_iter()
+            .map(|mut t| {
+                t.latex_formulas = extract_formulas(&t.content);
+                t
+            })
+            .collect();
+
+        ParseResult {
+            problems,
+            theory_blocks,
+            unclassified,
+        }
+    }
+
     /// Detect if line starts a problem and extract problem number
     fn detect_problem_start(&self, line: &str) -> Option<String> {
         for pattern in &self.problem_patterns {
@@ -302,6 +405,11 @@ impl SubProblemBuilder {
             continues_to_page: None,
             is_cross_page: false,
             is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
         }
     }
 }
@@ -353,7 +461,14 @@ impl ProblemBuilder {
 
     fn build(mut self, book_id: &str, chapter_num: u32) -> Problem {
         self.finish_sub_problems();
-        let id = Problem::generate_id(book_id, chapter_num, &self.number);
+        let number = Problem::normalize_number(&self.number);
+        let id = Problem::generate_id(book_id, chapter_num, &number);
+
+        let letters: Vec<&str> = self.sub_problems.iter().map(|s| s.letter.as_str()).collect();
+        let letters = Problem::normalize_sub_letters(&letters);
+        for (sub, letter) in self.sub_problems.iter_mut().zip(letters) {
+            sub.letter = letter;
+        }
 
         let sub_problems = if self.sub_problems.is_empty() {
             None
@@ -371,8 +486,8 @@ impl ProblemBuilder {
             chapter_id: format!("{}:{}", book_id, chapter_num),
             page_id: None,
             parent_id: None,
-            number: self.number.clone(),
-            display_name: format!("Problem {}", self.number),
+            display_name: format!("Problem {}", number),
+            number,
             content: self.content,
             latex_formulas: vec![],
             page_number: self.page_number,
@@ -385,6 +500,11 @@ impl ProblemBuilder {
             continues_to_page: None,
             is_cross_page: false,
             is_bookmarked: false,
+            confidence: None,
+            review_status: ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
         }
     }
 }
@@ -507,4 +627,41 @@ mod tests {
         assert_eq!(result.problems[0].number, "1");
         assert_eq!(result.problems[1].number, "2");
     }
+
+    #[test]
+    fn test_parse_markdown() {
+        let parser = TextbookParser::new();
+        let text = r#"
+# Линейные уравнения
+
+## Задача 5
+Решите уравнения:
+а) $2x + 3 = 7$
+б) $5 - x = 1$
+
+## Теорема 1: О корнях
+Линейное уравнение имеет не более одного корня.
+
+## Задача 6. Найдите значение выражения
+1. при $x = 2$
+2. при $x = -1$
+"#;
+
+        let result = parser.parse_markdown(text, "algebra-7", 2);
+
+        assert_eq!(result.problems.len(), 2);
+        assert_eq!(result.problems[0].id, "algebra-7:2:5");
+        assert_eq!(result.problems[0].content, "Решите уравнения:");
+        let subs = result.problems[0].sub_problems.as_ref().unwrap();
+        assert_eq!(subs.len(), 2);
+        assert_eq!(subs[1].number, "б");
+
+        // Numbered list items stay inside the problem
+        assert_eq!(result.problems[1].number, "6");
+        assert!(result.problems[1].content.starts_with("Найдите значение выражения"));
+        assert!(result.problems[1].content.contains("2. при $x = -1$"));
+
+        assert_eq!(result.theory_blocks.len(), 1);
+        assert_eq!(result.unclassified, vec!["# Линейные уравнения\n".to_string()]);
+    }
 }
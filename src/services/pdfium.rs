@@ -0,0 +1,93 @@
+//! PDF pages and metadata through PDFium, for machines without poppler's
+//! `pdftoppm`/`pdfinfo` (typically Windows). Built with `--features pdfium`;
+//! the PDFium library (`pdfium.dll`, `libpdfium.so`, ...) is loaded from
+//! next to the executable, else from the system library path.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use pdfium_render::prelude::*;
+
+use crate::services::formula_fallback::FormulaRegion;
+
+/// PDFium must not be initialised twice at the same time
+static PDFIUM: Mutex<()> = Mutex::new(());
+
+fn with_document<T>(pdf: &Path, f: impl FnOnce(&PdfDocument) -> Result<T, String>) -> Result<T, String> {
+    let _guard = PDFIUM.lock().unwrap_or_else(|e| e.into_inner());
+    let local = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(Pdfium::pdfium_platform_library_name_at_path(exe.parent()?)));
+    let bindings = match local.map(Pdfium::bind_to_library) {
+        Some(Ok(bindings)) => Ok(bindings),
+        _ => Pdfium::bind_to_system_library(),
+    }
+    .map_err(|e| format!("Failed to load the PDFium library: {}", e))?;
+    let pdfium = Pdfium::new(bindings);
+    let document = pdfium
+        .load_pdf_from_file(pdf, None)
+        .map_err(|e| format!("Failed to open {:?}: {}", pdf, e))?;
+    f(&document)
+}
+
+/// Render 1-based `page` of `pdf` at `dpi` to a PNG at `output`, only its
+/// `region` when given
+pub fn render_page_png(
+    pdf: &Path,
+    page: u32,
+    dpi: u32,
+    region: Option<FormulaRegion>,
+    output: &Path,
+) -> Result<(), String> {
+    with_document(pdf, |document| {
+        let index = page
+            .checked_sub(1)
+            .and_then(|i| PdfPageIndex::try_from(i).ok())
+            .ok_or_else(|| format!("Invalid page {}", page))?;
+        let pdf_page = document
+            .pages()
+            .get(index)
+            .map_err(|e| format!("Failed to open page {}: {}", page, e))?;
+        let bitmap = pdf_page
+            .render_with_config(&PdfRenderConfig::new().scale_page_by_factor(dpi as f32 / 72.0))
+            .map_err(|e| format!("Failed to render page {}: {}", page, e))?;
+        let mut image = bitmap.as_image();
+        if let Some(region) = region {
+            let (x, y, width, height) = region.to_pixels(image.width(), image.height());
+            image = image.crop_imm(x, y, width, height);
+        }
+        image
+            .save_with_format(output, image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to save page {}: {}", page, e))
+    })
+}
+
+/// Metadata under the keys `pdfinfo` prints (`Pages`, `Title`, `Page size`,
+/// ...); dates keep the PDF's `D:YYYYMMDDHHmmSS` form
+pub fn metadata(pdf: &Path) -> Result<HashMap<String, String>, String> {
+    with_document(pdf, |document| {
+        let mut metadata = HashMap::from([("Pages".to_string(), document.pages().len().to_string())]);
+        for (key, tag) in [
+            ("Title", PdfDocumentMetadataTagType::Title),
+            ("Author", PdfDocumentMetadataTagType::Author),
+            ("Subject", PdfDocumentMetadataTagType::Subject),
+            ("Keywords", PdfDocumentMetadataTagType::Keywords),
+            ("Creator", PdfDocumentMetadataTagType::Creator),
+            ("Producer", PdfDocumentMetadataTagType::Producer),
+            ("CreationDate", PdfDocumentMetadataTagType::CreationDate),
+            ("ModDate", PdfDocumentMetadataTagType::ModificationDate),
+        ] {
+            if let Some(value) = document.metadata().get(tag) {
+                metadata.insert(key.to_string(), value.value().to_string());
+            }
+        }
+        if let Ok(page) = document.pages().first() {
+            metadata.insert(
+                "Page size".to_string(),
+                format!("{} x {} pts", page.width().value, page.height().value),
+            );
+        }
+        Ok(metadata)
+    })
+}
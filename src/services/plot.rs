@@ -0,0 +1,676 @@
+//! Function plots for "постройте график" problems. A [`PlotSpec`] lists
+//! right-hand sides of `y = f(x)` as written in the book (`x^2 - 4x + 3`,
+//! `\frac{1}{x}`, `2\sin x`), which are parsed here and drawn to an SVG.
+
+use anyhow::{anyhow, bail, Result};
+use lazy_regex::regex;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::models::Problem;
+
+/// Curves on one plot
+pub const MAX_PLOT_FUNCTIONS: usize = 5;
+
+const PLOT_WIDTH: u32 = 640;
+const PLOT_HEIGHT: u32 = 480;
+/// Points sampled per curve
+const PLOT_SAMPLES: usize = 800;
+/// Longest formula accepted, in characters
+pub const MAX_FORMULA_CHARS: usize = 256;
+/// Parser recursion a formula may need: a bracket, fraction or function
+/// call takes two levels, a sign or exponent one. The evaluator recurses as
+/// deep, so unbounded nesting would overflow the stack.
+const MAX_NESTING: usize = 64;
+
+fn default_x_min() -> f64 {
+    -10.0
+}
+
+fn default_x_max() -> f64 {
+    10.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlotSpec {
+    /// Right-hand sides of `y = f(x)`
+    pub functions: Vec<String>,
+    #[serde(default = "default_x_min")]
+    pub x_min: f64,
+    #[serde(default = "default_x_max")]
+    pub x_max: f64,
+    /// Fitted to the curves when unset
+    #[serde(default)]
+    pub y_min: Option<f64>,
+    #[serde(default)]
+    pub y_max: Option<f64>,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl PlotSpec {
+    fn parse(&self) -> Result<Vec<Expr>> {
+        if self.functions.is_empty() || self.functions.len() > MAX_PLOT_FUNCTIONS {
+            bail!("A plot needs 1 to {} functions", MAX_PLOT_FUNCTIONS);
+        }
+        if !(self.x_min.is_finite() && self.x_max.is_finite() && self.x_min < self.x_max) {
+            bail!("x_min must be below x_max");
+        }
+        if let (Some(min), Some(max)) = (self.y_min, self.y_max)
+            && !(min.is_finite() && max.is_finite() && min < max)
+        {
+            bail!("y_min must be below y_max");
+        }
+        self.functions
+            .iter()
+            .map(|f| parse_function(f).map_err(|e| anyhow!("Can't read {:?}: {}", f, e)))
+            .collect()
+    }
+}
+
+/// Plot of every function of `spec` as an SVG document
+pub fn render_svg(spec: &PlotSpec) -> Result<String> {
+    let plot = sample(spec)?;
+    let mut svg = String::new();
+    draw(SVGBackend::with_string(&mut svg, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area(), spec, &plot, true)?;
+    Ok(svg)
+}
+
+/// Plot of `spec` as a PNG image. Bitmaps have no font to draw with, so
+/// this one has the grid, axes and curves but no numbers, title or legend.
+pub fn render_png(spec: &PlotSpec) -> Result<Vec<u8>> {
+    let plot = sample(spec)?;
+    let mut pixels = vec![0u8; (PLOT_WIDTH * PLOT_HEIGHT * 3) as usize];
+    draw(BitMapBackend::with_buffer(&mut pixels, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area(), spec, &plot, false)?;
+    let image = image::RgbImage::from_raw(PLOT_WIDTH, PLOT_HEIGHT, pixels)
+        .ok_or_else(|| anyhow!("Plot buffer has the wrong size"))?;
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Curves of a spec and the y range they are shown in
+struct Sampled {
+    curves: Vec<Vec<(f64, f64)>>,
+    y_min: f64,
+    y_max: f64,
+}
+
+fn sample(spec: &PlotSpec) -> Result<Sampled> {
+    let functions = spec.parse()?;
+    let step = (spec.x_max - spec.x_min) / PLOT_SAMPLES as f64;
+    let curves: Vec<Vec<(f64, f64)>> = functions
+        .iter()
+        .map(|f| (0..=PLOT_SAMPLES).map(|i| spec.x_min + step * i as f64).map(|x| (x, f.eval(x))).collect())
+        .collect();
+
+    let (y_min, y_max) = match (spec.y_min, spec.y_max) {
+        (Some(min), Some(max)) => (min, max),
+        (min, max) => {
+            let (fit_min, fit_max) = fit_range(curves.iter().flatten().map(|&(_, y)| y));
+            (min.unwrap_or(fit_min), max.unwrap_or(fit_max))
+        }
+    };
+    if y_min >= y_max {
+        bail!("y_min must be below y_max");
+    }
+    Ok(Sampled { curves, y_min, y_max })
+}
+
+fn draw<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, spec: &PlotSpec, plot: &Sampled, labels: bool) -> Result<()> {
+    let draw_error = |e: &dyn std::fmt::Display| anyhow!("Failed to draw plot: {}", e);
+    let (y_min, y_max) = (plot.y_min, plot.y_max);
+
+    root.fill(&WHITE).map_err(|e| draw_error(&e))?;
+    let mut builder = ChartBuilder::on(&root);
+    builder.margin(12);
+    if labels {
+        builder
+            .caption(spec.title.as_deref().unwrap_or(""), ("sans-serif", 18))
+            .x_label_area_size(30)
+            .y_label_area_size(40);
+    }
+    let mut chart = builder
+        .build_cartesian_2d(spec.x_min..spec.x_max, y_min..y_max)
+        .map_err(|e| draw_error(&e))?;
+    let mut mesh = chart.configure_mesh();
+    mesh.light_line_style(WHITE);
+    if !labels {
+        mesh.disable_axes().x_label_formatter(&|_| String::new()).y_label_formatter(&|_| String::new());
+    }
+    mesh.draw().map_err(|e| draw_error(&e))?;
+
+    // Axes through the origin when it is in view
+    if (y_min..=y_max).contains(&0.0) {
+        chart
+            .draw_series(LineSeries::new([(spec.x_min, 0.0), (spec.x_max, 0.0)], BLACK.stroke_width(1)))
+            .map_err(|e| draw_error(&e))?;
+    }
+    if (spec.x_min..=spec.x_max).contains(&0.0) {
+        chart
+            .draw_series(LineSeries::new([(0.0, y_min), (0.0, y_max)], BLACK.stroke_width(1)))
+            .map_err(|e| draw_error(&e))?;
+    }
+
+    let span = y_max - y_min;
+    for (i, (curve, label)) in plot.curves.iter().zip(&spec.functions).enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        // Split at gaps and asymptotes, so 1/x isn't joined across x = 0
+        let mut segments: Vec<Vec<(f64, f64)>> = vec![Vec::new()];
+        for &(x, y) in curve {
+            let visible = y.is_finite() && y > y_min - span && y < y_max + span;
+            match segments.last_mut() {
+                Some(segment) if visible => segment.push((x, y)),
+                Some(segment) if !segment.is_empty() => segments.push(Vec::new()),
+                _ => {}
+            }
+        }
+        for (n, segment) in segments.into_iter().filter(|s| s.len() > 1).enumerate() {
+            let series = chart
+                .draw_series(LineSeries::new(segment, color.stroke_width(2)))
+                .map_err(|e| draw_error(&e))?;
+            if n == 0 && labels {
+                series
+                    .label(format!("y = {}", label))
+                    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+            }
+        }
+    }
+    if labels {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .map_err(|e| draw_error(&e))?;
+    }
+    root.present().map_err(|e| draw_error(&e))?;
+    Ok(())
+}
+
+/// y range showing the bulk of the values and the x axis: outliers near
+/// asymptotes are cut off and a margin added
+fn fit_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let mut values: Vec<f64> = values.filter(|y| y.is_finite()).collect();
+    if values.is_empty() {
+        return (-10.0, 10.0);
+    }
+    values.sort_by(f64::total_cmp);
+    let at = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+    let (low, high) = (at(0.02).min(0.0), at(0.98).max(0.0));
+    if high - low < 1e-9 {
+        return (low - 1.0, high + 1.0);
+    }
+    let margin = (high - low) * 0.1;
+    (low - margin, high + margin)
+}
+
+impl PlotSpec {
+    /// Plot of `functions` over the default range
+    pub fn new(functions: Vec<String>) -> Self {
+        Self { functions, x_min: default_x_min(), x_max: default_x_max(), y_min: None, y_max: None, title: None }
+    }
+}
+
+/// `2x + 1` from `y = 2x + 1` or `f(x) = 2x + 1`; anything else as is
+pub fn function_body(expression: &str) -> &str {
+    match regex!(r"^\s*(?:y|f\s*\(\s*x\s*\))\s*=").find(expression) {
+        Some(m) => expression[m.end()..].trim(),
+        None => expression.trim(),
+    }
+}
+
+/// Readable functions written as `y = ...` or `f(x) = ...` in a problem or
+/// solution text, in order and without repeats
+pub fn graph_expressions(text: &str) -> Vec<String> {
+    let mut functions: Vec<String> = Vec::new();
+    for caps in regex!(r"(?:\by|f\s*\(\s*x\s*\))\s*=\s*([^=;$\n]+)").captures_iter(text) {
+        let rhs = caps[1].trim().trim_end_matches(['.', ',']).trim();
+        // "y = 2x + 1, x ∈ [0; 5]" leaves the domain behind a comma
+        let rhs = rhs.split(", ").next().unwrap_or(rhs).trim();
+        if !rhs.is_empty() && !functions.iter().any(|f| f == rhs) && parse_function(rhs).is_ok() {
+            functions.push(rhs.to_string());
+        }
+    }
+    functions
+}
+
+/// Functions to plot for a problem that asks for a graph: the `y = ...`
+/// formulas of its statement that can be read. `None` for other problems.
+pub fn plot_request(problem: &Problem) -> Option<PlotSpec> {
+    let text = problem.content.to_lowercase();
+    if !(text.contains("постро") && text.contains("график")) {
+        return None;
+    }
+
+    let mut functions: Vec<String> = Vec::new();
+    let formulas = problem.latex_formulas.iter().map(String::as_str).chain(std::iter::once(problem.content.as_str()));
+    for function in formulas.flat_map(graph_expressions) {
+        if !functions.contains(&function) {
+            functions.push(function);
+        }
+    }
+    functions.truncate(MAX_PLOT_FUNCTIONS);
+    (!functions.is_empty()).then(|| PlotSpec::new(functions))
+}
+
+// === Formula parsing ===
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(f64),
+    X,
+    /// Letters other than `x`, only in algebraic formulas
+    Var(char),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Call(fn(f64) -> f64, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, x: f64) -> f64 {
+        self.eval_with(&|name| if name == 'x' { x } else { f64::NAN })
+    }
+
+    fn eval_with(&self, value: &dyn Fn(char) -> f64) -> f64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::X => value('x'),
+            Expr::Var(name) => value(*name),
+            Expr::Neg(a) => -a.eval_with(value),
+            Expr::Add(a, b) => a.eval_with(value) + b.eval_with(value),
+            Expr::Sub(a, b) => a.eval_with(value) - b.eval_with(value),
+            Expr::Mul(a, b) => a.eval_with(value) * b.eval_with(value),
+            Expr::Div(a, b) => a.eval_with(value) / b.eval_with(value),
+            Expr::Pow(a, b) => pow(a.eval_with(value), b.eval_with(value)),
+            Expr::Call(f, a) => f(a.eval_with(value)),
+        }
+    }
+
+    fn collect_variables(&self, names: &mut BTreeSet<char>) {
+        match self {
+            Expr::Num(_) => {}
+            Expr::X => {
+                names.insert('x');
+            }
+            Expr::Var(name) => {
+                names.insert(*name);
+            }
+            Expr::Neg(a) | Expr::Call(_, a) => a.collect_variables(names),
+            Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) | Expr::Div(a, b) | Expr::Pow(a, b) => {
+                a.collect_variables(names);
+                b.collect_variables(names);
+            }
+        }
+    }
+}
+
+/// A formula in the notation plots accept, for code that evaluates
+/// expressions from problems rather than drawing them
+#[derive(Debug, Clone)]
+pub(crate) struct Formula(Expr);
+
+impl Formula {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        parse_function(input).map(Self)
+    }
+
+    /// Like [`Formula::parse`], but any single Latin letter other than `e`
+    /// is a variable: `(a - b)(a + b)`, `2\pi r`
+    pub(crate) fn parse_algebraic(input: &str) -> Result<Self> {
+        parse_formula(input, true).map(Self)
+    }
+
+    pub(crate) fn eval(&self, x: f64) -> f64 {
+        self.0.eval(x)
+    }
+
+    /// Value with every variable taken from `value`
+    pub(crate) fn eval_with(&self, value: &dyn Fn(char) -> f64) -> f64 {
+        self.0.eval_with(value)
+    }
+
+    /// False for plain arithmetic like `2,5 \cdot 4 - 3`
+    pub(crate) fn uses_x(&self) -> bool {
+        self.variables().contains(&'x')
+    }
+
+    pub(crate) fn variables(&self) -> BTreeSet<char> {
+        let mut names = BTreeSet::new();
+        self.0.collect_variables(&mut names);
+        names
+    }
+}
+
+/// `powf`, except that odd roots of negative numbers are real:
+/// `x^(1/3)` is defined left of 0 as it is at school
+fn pow(base: f64, exponent: f64) -> f64 {
+    if base < 0.0 && exponent.fract() != 0.0 {
+        let inverse = 1.0 / exponent;
+        if (inverse.round() - inverse).abs() < 1e-9 && inverse.round() as i64 % 2 != 0 {
+            return -(-base).powf(exponent);
+        }
+    }
+    base.powf(exponent)
+}
+
+fn function(name: &str) -> Option<fn(f64) -> f64> {
+    Some(match name {
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" | "tg" => f64::tan,
+        "cot" | "ctg" => |x: f64| 1.0 / x.tan(),
+        "arcsin" => f64::asin,
+        "arccos" => f64::acos,
+        "arctan" | "arctg" => f64::atan,
+        "sqrt" => f64::sqrt,
+        "abs" => f64::abs,
+        "ln" => f64::ln,
+        "lg" | "log" => f64::log10,
+        "exp" => f64::exp,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(char),
+}
+
+fn tokenize(input: &str, variables: bool) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek() {
+                    // A comma is the decimal separator of Russian books
+                    if d.is_ascii_digit() || d == '.' || (d == ',' && number.chars().all(|c| c.is_ascii_digit())) {
+                        number.push(if d == ',' { '.' } else { d });
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Num(number.parse().map_err(|_| anyhow!("bad number {}", number))?));
+            }
+            '\\' | 'a'..='z' | 'A'..='Z' | 'π' => {
+                chars.next();
+                if c == 'π' {
+                    tokens.push(Token::Ident("pi".to_string()));
+                    continue;
+                }
+                let mut name = if c == '\\' { String::new() } else { c.to_string() };
+                while let Some(&l) = chars.peek().filter(|l| l.is_ascii_alphabetic()) {
+                    name.push(l);
+                    chars.next();
+                }
+                match name.as_str() {
+                    "cdot" | "times" => tokens.push(Token::Op('*')),
+                    "div" => tokens.push(Token::Op('/')),
+                    "left" | "right" | "" => {}
+                    _ => tokens.extend(split_identifier(&name, variables)?),
+                }
+            }
+            '+' | '-' | '−' | '*' | '·' | '×' | '/' | ':' | '^' | '(' | ')' | '{' | '}' | '[' | ']' | '|' => {
+                chars.next();
+                tokens.push(Token::Op(match c {
+                    '−' => '-',
+                    '·' | '×' => '*',
+                    ':' => '/',
+                    '{' | '[' => '(',
+                    '}' | ']' => ')',
+                    c => c,
+                }));
+            }
+            _ => bail!("unexpected {:?}", c),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Letters run together, as in `2xsinx`: known names are taken greedily,
+/// anything else must be single `x`s (or any single letters with `variables`)
+fn split_identifier(name: &str, variables: bool) -> Result<Vec<Token>> {
+    const NAMES: [&str; 18] = [
+        "arcsin", "arccos", "arctan", "arctg", "sqrt", "frac", "sin", "cos", "tan", "cot", "ctg", "abs", "exp",
+        "log", "tg", "ln", "lg", "pi",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = name;
+    while !rest.is_empty() {
+        if let Some(known) = NAMES.iter().find(|n| rest.starts_with(*n)) {
+            tokens.push(Token::Ident(known.to_string()));
+            rest = &rest[known.len()..];
+        } else if let Some(tail) = rest.strip_prefix(['x', 'e']) {
+            tokens.push(Token::Ident(rest[..1].to_string()));
+            rest = tail;
+        } else if variables && rest.as_bytes()[0].is_ascii_alphabetic() {
+            tokens.push(Token::Ident(rest[..1].to_string()));
+            rest = &rest[1..];
+        } else {
+            bail!("unknown name {}", rest);
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, op: char) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: char) -> Result<()> {
+        if self.eat(op) { Ok(()) } else { bail!("expected {:?}", op) }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut left = self.term()?;
+        loop {
+            if self.eat('+') {
+                left = Expr::Add(Box::new(left), Box::new(self.term()?));
+            } else if self.eat('-') {
+                left = Expr::Sub(Box::new(left), Box::new(self.term()?));
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            if self.eat('*') {
+                left = Expr::Mul(Box::new(left), Box::new(self.unary()?));
+            } else if self.eat('/') {
+                left = Expr::Div(Box::new(left), Box::new(self.unary()?));
+            } else if matches!(self.peek(), Some(Token::Num(_) | Token::Ident(_)) | Some(Token::Op('('))) {
+                // Implicit multiplication: 2x, 3(x + 1), x sin x
+                left = Expr::Mul(Box::new(left), Box::new(self.power()?));
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+
+    /// Runs `parse` one nesting level deeper
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        if self.depth >= MAX_NESTING {
+            bail!("nested more than {} levels deep", MAX_NESTING);
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        self.nested(Self::signed)
+    }
+
+    fn signed(&mut self) -> Result<Expr> {
+        if self.eat('-') {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    /// `-x^2` is `-(x^2)`, and `2^-x` is allowed
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(Expr::Pow(Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        self.nested(Self::operand)
+    }
+
+    fn operand(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Op('(')) => {
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            Some(Token::Op('|')) => {
+                let inner = self.expr()?;
+                self.expect('|')?;
+                Ok(Expr::Call(f64::abs, Box::new(inner)))
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "x" => Ok(Expr::X),
+                var if var.len() == 1 && var != "e" => Ok(Expr::Var(var.chars().next().unwrap_or('x'))),
+                "e" => Ok(Expr::Num(std::f64::consts::E)),
+                "pi" => Ok(Expr::Num(std::f64::consts::PI)),
+                "frac" => {
+                    let numerator = self.atom()?;
+                    let denominator = self.atom()?;
+                    Ok(Expr::Div(Box::new(numerator), Box::new(denominator)))
+                }
+                name => {
+                    let f = function(name).ok_or_else(|| anyhow!("unknown function {}", name))?;
+                    // sin x^2 is sin(x^2); sin^2 x is not supported
+                    Ok(Expr::Call(f, Box::new(self.power()?)))
+                }
+            },
+            Some(Token::Op(op)) => bail!("unexpected {:?}", op),
+            None => bail!("unexpected end"),
+        }
+    }
+}
+
+fn parse_function(input: &str) -> Result<Expr> {
+    parse_formula(input, false)
+}
+
+fn parse_formula(input: &str, variables: bool) -> Result<Expr> {
+    if input.chars().count() > MAX_FORMULA_CHARS {
+        bail!("longer than {} characters", MAX_FORMULA_CHARS);
+    }
+    let mut parser = Parser { tokens: tokenize(input, variables)?, pos: 0, depth: 0 };
+    let expr = parser.expr()?;
+    if let Some(token) = parser.peek() {
+        bail!("unexpected {:?}", token);
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(formula: &str, x: f64) -> f64 {
+        parse_function(formula).unwrap().eval(x)
+    }
+
+    #[test]
+    fn parses_school_and_latex_notation() {
+        assert_eq!(at("x^2 - 4x + 3", 1.0), 0.0);
+        assert_eq!(at("-x^2", 3.0), -9.0);
+        assert_eq!(at("2(x+1)", 2.0), 6.0);
+        assert_eq!(at("\\frac{1}{x}", 4.0), 0.25);
+        assert_eq!(at("\\sqrt{x} + |x - 5|", 4.0), 3.0);
+        assert_eq!(at("0,5x − 1", 4.0), 1.0);
+        assert_eq!(at("x^{1/3}", -8.0), -2.0);
+        assert_eq!(at("3 \\cdot 2^x", 2.0), 12.0);
+        assert!((at("2\\sin x", std::f64::consts::FRAC_PI_2) - 2.0).abs() < 1e-12);
+        assert!((at("xsinx", std::f64::consts::FRAC_PI_2) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert!(parse_function("x +").is_err());
+        assert!(parse_function("z^2").is_err());
+        assert!(parse_function("(x").is_err());
+        assert!(parse_function(&format!("{}x{}", "(".repeat(5000), ")".repeat(5000))).is_err());
+        assert!(parse_function(&"-".repeat(200)).is_err());
+        assert!(parse_function(&format!("{}x{}", "(".repeat(20), ")".repeat(20))).is_ok());
+    }
+
+    #[test]
+    fn graph_problems_get_plots() {
+        let problem = Problem {
+            content: "Постройте график функции $y = x^2 - 4x + 3$. Найдите нули функции.".to_string(),
+            latex_formulas: vec!["y = x^2 - 4x + 3".to_string()],
+            ..Default::default()
+        };
+        let spec = plot_request(&problem).unwrap();
+        assert_eq!(spec.functions, vec!["x^2 - 4x + 3"]);
+
+        let svg = render_svg(&PlotSpec { functions: vec!["1/x".into(), "x".into()], ..spec }).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("y = 1/x"));
+
+        let other = Problem { content: "Решите уравнение $y = 2$".to_string(), ..Default::default() };
+        assert!(plot_request(&other).is_none());
+        let bad = PlotSpec { functions: vec![], x_min: 0.0, x_max: 1.0, y_min: None, y_max: None, title: None };
+        assert!(render_svg(&bad).is_err());
+    }
+
+    #[test]
+    fn finds_functions_in_solutions_and_renders_png() {
+        let solution = "Прямая $y = 2x + 1$ пересекает параболу f(x) = x^2 - 2; снова y = 2x + 1.";
+        assert_eq!(graph_expressions(solution), vec!["2x + 1", "x^2 - 2"]);
+        assert_eq!(function_body(" y = 2x+1"), "2x+1");
+        assert_eq!(function_body("x^2"), "x^2");
+
+        let png = render_png(&PlotSpec { title: Some("ignored".into()), ..PlotSpec::new(vec!["x^2 - 2".into()]) }).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+}
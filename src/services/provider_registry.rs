@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::services::credentials::{MaskedKey, ProviderCredentials};
+use crate::services::{TesseractOcrProvider, MATHPIX_PROVIDER, TESSERACT_PROVIDER};
+use crate::services::retry::CircuitBreaker;
+
+/// Consecutive failures before a provider's circuit opens
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit rejects calls before a trial request
+const RESET_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref BREAKERS: Mutex<HashMap<String, CircuitBreaker>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Ocr,
+    Solve,
+}
+
+impl ProviderKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::Ocr => "ocr",
+            ProviderKind::Solve => "solve",
+        }
+    }
+}
+
+/// Static capability metadata for a provider
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderCapabilities {
+    pub id: &'static str,
+    pub kind: ProviderKind,
+    pub display_name: &'static str,
+    pub model: Option<&'static str>,
+    pub vision: bool,
+    pub streaming: bool,
+    /// Largest `max_tokens` a request may ask for
+    pub max_tokens: Option<u32>,
+    /// Models a solve request may pick instead of `model`
+    pub models: &'static [&'static str],
+    /// Highest sampling temperature the API accepts
+    pub max_temperature: Option<f32>,
+    /// Approximate USD cost per 1k output tokens (None for per-page pricing)
+    pub cost_per_1k_tokens: Option<f64>,
+    /// Approximate USD cost per OCR'd page (None for per-token pricing)
+    pub cost_per_page: Option<f64>,
+    /// Env var that must be set for the provider to be usable
+    pub env_var: &'static str,
+}
+
+/// Provider entry as exposed by GET /api/providers
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderInfo {
+    #[serde(flatten)]
+    pub capabilities: ProviderCapabilities,
+    pub configured: bool,
+    pub circuit_state: String,
+    pub recent_failures: u32,
+    pub keys: Vec<MaskedKey>,
+}
+
+pub const PROVIDERS: &[ProviderCapabilities] = &[
+    ProviderCapabilities {
+        id: "openai",
+        kind: ProviderKind::Solve,
+        display_name: "OpenAI GPT-4o",
+        model: Some("gpt-4o"),
+        vision: true,
+        streaming: true,
+        max_tokens: Some(16384),
+        models: &["gpt-4o", "gpt-4o-mini", "gpt-4.1", "gpt-4.1-mini"],
+        max_temperature: Some(2.0),
+        cost_per_1k_tokens: Some(0.01),
+        cost_per_page: None,
+        env_var: "OPENAI_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "claude",
+        kind: ProviderKind::Solve,
+        display_name: "Claude 3.5 Sonnet",
+        model: Some("claude-3-5-sonnet-20241022"),
+        vision: true,
+        streaming: true,
+        max_tokens: Some(8192),
+        models: &[
+            "claude-3-5-sonnet-20241022",
+            "claude-3-5-haiku-20241022",
+            "claude-3-7-sonnet-20250219",
+            "claude-sonnet-4-20250514",
+            "claude-opus-4-20250514",
+        ],
+        max_temperature: Some(1.0),
+        cost_per_1k_tokens: Some(0.015),
+        cost_per_page: None,
+        env_var: "ANTHROPIC_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "mistral",
+        kind: ProviderKind::Solve,
+        display_name: "Mistral Large",
+        model: Some("mistral-large-latest"),
+        vision: false,
+        streaming: true,
+        max_tokens: Some(8192),
+        models: &["mistral-large-latest", "mistral-medium-latest", "mistral-small-latest"],
+        max_temperature: Some(1.0),
+        cost_per_1k_tokens: Some(0.006),
+        cost_per_page: None,
+        env_var: "MISTRAL_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "mistral",
+        kind: ProviderKind::Ocr,
+        display_name: "Mistral OCR",
+        model: Some("mistral-ocr-latest"),
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: Some(0.001),
+        env_var: "MISTRAL_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "kimi",
+        kind: ProviderKind::Ocr,
+        display_name: "Kimi Vision",
+        model: None,
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: None,
+        env_var: "KIMI_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "mathpix",
+        kind: ProviderKind::Ocr,
+        display_name: "Mathpix",
+        model: None,
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: Some(0.002),
+        // With MATHPIX_APP_ID; configured when both are set
+        env_var: "MATHPIX_APP_KEY",
+    },
+    ProviderCapabilities {
+        id: "azure",
+        kind: ProviderKind::Ocr,
+        display_name: "Azure Document Intelligence",
+        model: None,
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: Some(0.0015),
+        env_var: "AZURE_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "google",
+        kind: ProviderKind::Ocr,
+        display_name: "Google Document AI",
+        model: None,
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: Some(0.0015),
+        env_var: "GOOGLE_PROJECT_ID",
+    },
+    ProviderCapabilities {
+        id: "openai",
+        kind: ProviderKind::Ocr,
+        display_name: "OpenAI GPT-4o Vision",
+        model: Some("gpt-4o"),
+        vision: true,
+        streaming: false,
+        max_tokens: Some(4096),
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: Some(0.01),
+        cost_per_page: None,
+        env_var: "OPENAI_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "claude",
+        kind: ProviderKind::Ocr,
+        display_name: "Claude Vision",
+        model: Some("claude-3-5-sonnet-20241022"),
+        vision: true,
+        streaming: false,
+        max_tokens: Some(4096),
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: Some(0.015),
+        cost_per_page: None,
+        env_var: "ANTHROPIC_API_KEY",
+    },
+    ProviderCapabilities {
+        id: "tesseract",
+        kind: ProviderKind::Ocr,
+        display_name: "Tesseract (local)",
+        model: None,
+        vision: true,
+        streaming: false,
+        max_tokens: None,
+        models: &[],
+        max_temperature: None,
+        cost_per_1k_tokens: None,
+        cost_per_page: Some(0.0),
+        // No key; configured when the binary runs
+        env_var: "TESSERACT_PATH",
+    },
+];
+
+/// Capabilities of a provider of the given kind
+pub fn capabilities(kind: ProviderKind, id: &str) -> Option<&'static ProviderCapabilities> {
+    PROVIDERS.iter().find(|p| p.kind == kind && p.id == id)
+}
+
+fn breaker_key(kind: ProviderKind, id: &str) -> String {
+    format!("{}:{}", kind.as_str(), id)
+}
+
+/// Whether the provider's circuit currently allows a call
+pub fn circuit_allows(kind: ProviderKind, id: &str) -> bool {
+    let mut breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    breakers
+        .entry(breaker_key(kind, id))
+        .or_insert_with(|| CircuitBreaker::new(FAILURE_THRESHOLD, RESET_TIMEOUT))
+        .can_execute()
+}
+
+/// Record the outcome of a provider call
+pub fn record_outcome(kind: ProviderKind, id: &str, success: bool) {
+    let mut breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    let breaker = breakers
+        .entry(breaker_key(kind, id))
+        .or_insert_with(|| CircuitBreaker::new(FAILURE_THRESHOLD, RESET_TIMEOUT));
+    if success {
+        breaker.record_success();
+    } else {
+        breaker.record_failure();
+        if breaker.is_open() {
+            log::warn!("Circuit opened for {} provider '{}'", kind.as_str(), id);
+        }
+    }
+}
+
+/// All known providers with configuration and circuit-breaker state
+pub fn list_providers(kind: Option<ProviderKind>) -> Vec<ProviderInfo> {
+    let credentials = ProviderCredentials::global();
+    // Probed before taking the lock every provider call goes through
+    let tesseract = TesseractOcrProvider::from_config(&Config::new()).is_available();
+    let breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+
+    PROVIDERS
+        .iter()
+        .filter(|p| kind.is_none_or(|k| k == p.kind))
+        .map(|p| {
+            let breaker = breakers.get(&breaker_key(p.kind, p.id));
+            let configured = match p.kind {
+                ProviderKind::Solve => credentials.has_provider(p.id),
+                ProviderKind::Ocr if p.id == TESSERACT_PROVIDER => tesseract,
+                ProviderKind::Ocr if p.id == MATHPIX_PROVIDER => Config::new().mathpix.is_some(),
+                ProviderKind::Ocr => std::env::var(p.env_var).is_ok_and(|v| !v.is_empty()),
+            };
+            ProviderInfo {
+                capabilities: p.clone(),
+                configured,
+                circuit_state: breaker.map(|b| b.state_name()).unwrap_or("closed").to_string(),
+                recent_failures: breaker.map(|b| b.failures()).unwrap_or(0),
+                keys: match p.kind {
+                    ProviderKind::Solve => credentials.masked(p.id),
+                    ProviderKind::Ocr => Vec::new(),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuit_opens_after_repeated_failures() {
+        let id = "test-provider";
+        assert!(circuit_allows(ProviderKind::Solve, id));
+        for _ in 0..FAILURE_THRESHOLD {
+            record_outcome(ProviderKind::Solve, id, false);
+        }
+        assert!(!circuit_allows(ProviderKind::Solve, id));
+        // OCR breakers are tracked separately
+        assert!(circuit_allows(ProviderKind::Ocr, id));
+    }
+
+    #[test]
+    fn lists_providers_by_kind() {
+        let ocr = list_providers(Some(ProviderKind::Ocr));
+        assert!(ocr.iter().all(|p| p.capabilities.kind == ProviderKind::Ocr));
+        assert!(ocr.iter().any(|p| p.capabilities.id == "mathpix"));
+    }
+}
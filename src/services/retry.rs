@@ -192,4 +192,90 @@ impl CircuitBreaker {
     pub fn is_open(&self) -> bool {
         self.state == CircuitState::Open
     }
+
+    /// Current state as a lowercase label ("closed", "open", "half_open")
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+/// Why a provider call was abandoned before it finished
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallInterrupted {
+    TimedOut(Duration),
+    Cancelled,
+}
+
+impl std::fmt::Display for CallInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallInterrupted::TimedOut(after) => write!(f, "timed out after {:?}", after),
+            CallInterrupted::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for CallInterrupted {}
+
+/// Run a provider call, giving up when it outlives `timeout` or the job is
+/// cancelled. The call's future is dropped, which aborts in-flight requests
+/// and kills child processes spawned with `kill_on_drop`.
+pub async fn guarded<T>(
+    call: impl std::future::Future<Output = anyhow::Result<T>>,
+    timeout: Duration,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> anyhow::Result<T> {
+    tokio::select! {
+        _ = cancel.cancelled() => Err(CallInterrupted::Cancelled.into()),
+        result = tokio::time::timeout(timeout, call) => {
+            result.unwrap_or_else(|_| Err(CallInterrupted::TimedOut(timeout).into()))
+        }
+    }
+}
+
+/// Whether an error is a timeout, ours or one reported by reqwest
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(cause.downcast_ref::<CallInterrupted>(), Some(CallInterrupted::TimedOut(_)))
+            || cause.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout)
+    })
+}
+
+/// Whether an error comes from job cancellation
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<CallInterrupted>(), Some(CallInterrupted::Cancelled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn guarded_call_times_out_or_is_cancelled() {
+        let never = || async {
+            sleep(Duration::from_secs(60)).await;
+            anyhow::Ok(())
+        };
+
+        let err = guarded(never(), Duration::from_millis(20), &CancellationToken::new()).await.unwrap_err();
+        assert!(is_timeout(&err));
+        assert_eq!(err.to_string(), "timed out after 20ms");
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = guarded(never(), Duration::from_secs(60), &cancel).await.unwrap_err();
+        assert!(is_cancelled(&err) && !is_timeout(&err));
+
+        let ok = guarded(async { anyhow::Ok(7) }, Duration::from_secs(1), &CancellationToken::new()).await;
+        assert_eq!(ok.unwrap(), 7);
+    }
 }
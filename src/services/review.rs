@@ -0,0 +1,83 @@
+use crate::models::ReviewStatus;
+
+/// Characters of page OCR shown around a problem in review batches
+const SNIPPET_CONTEXT: usize = 300;
+
+/// Check a reviewer's decision against the problem's current state
+pub fn validate_decision(current: ReviewStatus, next: ReviewStatus, note: Option<&str>) -> Result<(), String> {
+    if !current.can_transition_to(next) {
+        return Err(format!(
+            "Cannot change review status from {} to {}",
+            current.as_str(),
+            next.as_str()
+        ));
+    }
+    if next == ReviewStatus::NeedsFix && note.is_none_or(|n| n.trim().is_empty()) {
+        return Err("A note describing what needs fixing is required".to_string());
+    }
+    Ok(())
+}
+
+/// Part of the page OCR text around where `content` starts, so a reviewer can
+/// compare the parsed problem against its source without loading the page.
+/// Falls back to the start of the page when the problem can't be located.
+pub fn ocr_snippet(ocr_text: &str, content: &str) -> String {
+    let chars: Vec<char> = ocr_text.chars().collect();
+    let needle: String = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("")
+        .chars()
+        .take(40)
+        .collect();
+
+    let found = (!needle.is_empty())
+        .then(|| ocr_text.find(&needle))
+        .flatten()
+        .map(|byte_idx| ocr_text[..byte_idx].chars().count());
+
+    let (start, end) = match found {
+        Some(at) => (
+            at.saturating_sub(SNIPPET_CONTEXT),
+            (at + content.chars().count() + SNIPPET_CONTEXT).min(chars.len()),
+        ),
+        None => (0, (2 * SNIPPET_CONTEXT).min(chars.len())),
+    };
+
+    let mut snippet: String = chars[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_centers_on_problem() {
+        let filler = "Теория. ".repeat(100);
+        let page = format!("{}№ 12. Решите уравнение $x^2 = 4$.\n{}", filler, filler);
+        let snippet = ocr_snippet(&page, "№ 12. Решите уравнение $x^2 = 4$.");
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("№ 12. Решите уравнение"));
+        assert!(snippet.chars().count() < page.chars().count());
+
+        let short = ocr_snippet("Короткая страница", "Не найдено");
+        assert_eq!(short, "Короткая страница");
+    }
+
+    #[test]
+    fn needs_fix_requires_note() {
+        use ReviewStatus::*;
+        assert!(validate_decision(Unreviewed, Approved, None).is_ok());
+        assert!(validate_decision(Unreviewed, NeedsFix, Some("  ")).is_err());
+        assert!(validate_decision(Unreviewed, NeedsFix, Some("typo in formula")).is_ok());
+        assert!(validate_decision(Approved, Approved, None).is_err());
+    }
+}
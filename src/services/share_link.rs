@@ -0,0 +1,157 @@
+//! Expiring public links to a problem, a chapter summary or a worksheet.
+//!
+//! A link's token is `{id}.{expires}.{signature}`, the signature being the
+//! HMAC-SHA256 of id and expiry under the deployment's share secret, so the
+//! expiry can't be pushed back. The link itself is stored with its target;
+//! deleting it revokes the token before it expires.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::services::attachments::hmac_sha256;
+
+/// Lifetime of a link created without a `ttl`
+pub const DEFAULT_TTL_SECS: u64 = 7 * 24 * 3600;
+/// Longest lifetime a link may be given
+pub const MAX_TTL_SECS: u64 = 90 * 24 * 3600;
+
+/// What a link shows
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum ShareTarget {
+    /// A problem with its solution
+    Problem(String),
+    /// A chapter's theory and problem list
+    Chapter(String),
+    Worksheet(String),
+}
+
+impl ShareTarget {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ShareTarget::Problem(_) => "problem",
+            ShareTarget::Chapter(_) => "chapter",
+            ShareTarget::Worksheet(_) => "worksheet",
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            ShareTarget::Problem(id) | ShareTarget::Chapter(id) | ShareTarget::Worksheet(id) => id,
+        }
+    }
+
+    pub fn from_parts(kind: &str, id: String) -> Option<Self> {
+        match kind {
+            "problem" => Some(ShareTarget::Problem(id)),
+            "chapter" => Some(ShareTarget::Chapter(id)),
+            "worksheet" => Some(ShareTarget::Worksheet(id)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    pub id: String,
+    pub target: ShareTarget,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    /// A link expiring after `ttl_secs`, capped at [`MAX_TTL_SECS`]. The
+    /// expiry is kept to whole seconds, as it is in the token.
+    pub fn new(target: ShareTarget, ttl_secs: u64) -> Self {
+        let now = Utc::now();
+        let expires = now.timestamp() + ttl_secs.min(MAX_TTL_SECS) as i64;
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            target,
+            expires_at: DateTime::from_timestamp(expires, 0).unwrap_or(now),
+            created_at: now,
+        }
+    }
+
+    pub fn token(&self, secret: &[u8]) -> String {
+        let expires = self.expires_at.timestamp();
+        format!("{}.{}.{}", self.id, expires, URL_SAFE_NO_PAD.encode(signature(secret, &self.id, expires)))
+    }
+
+    pub fn url(&self, secret: &[u8]) -> String {
+        format!("/s/{}", self.token(secret))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// Malformed or signed with another secret
+    Invalid,
+    Expired,
+}
+
+/// Id of the link a token was issued for, when its signature holds and its
+/// expiry hasn't passed. Whether the link still exists (or was revoked) is
+/// up to the caller.
+pub fn verify_token<'a>(token: &'a str, secret: &[u8], now: DateTime<Utc>) -> Result<&'a str, TokenError> {
+    let mut parts = token.splitn(3, '.');
+    let (Some(id), Some(expires), Some(given)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(TokenError::Invalid);
+    };
+    let expires_at: i64 = expires.parse().map_err(|_| TokenError::Invalid)?;
+    let expected = URL_SAFE_NO_PAD.encode(signature(secret, id, expires_at));
+    // Compared without stopping at the first difference
+    let same = expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0;
+    if !same {
+        return Err(TokenError::Invalid);
+    }
+    if now.timestamp() >= expires_at {
+        return Err(TokenError::Expired);
+    }
+    Ok(id)
+}
+
+/// The target is left out so renaming a book keeps its links working; it
+/// is read from the stored link
+fn signature(secret: &[u8], id: &str, expires: i64) -> [u8; 32] {
+    hmac_sha256(secret, format!("{}\n{}", id, expires).as_bytes())
+}
+
+/// Random secret for deployments that don't set `SHARE_LINK_SECRET`
+pub fn generate_secret() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_signed_with_their_expiry() {
+        let link = ShareLink::new(ShareTarget::Problem("algebra-7:1:5".to_string()), 3600);
+        let token = link.token(b"secret");
+        let now = Utc::now();
+
+        assert_eq!(verify_token(&token, b"secret", now), Ok(link.id.as_str()));
+        assert_eq!(verify_token(&token, b"other secret", now), Err(TokenError::Invalid));
+        assert_eq!(verify_token(&token, b"secret", link.expires_at), Err(TokenError::Expired));
+
+        let expires = link.expires_at.timestamp();
+        let extended = token.replacen(&expires.to_string(), &(expires + 86400).to_string(), 1);
+        assert_eq!(verify_token(&extended, b"secret", now), Err(TokenError::Invalid));
+        assert_eq!(verify_token("not-a-token", b"secret", now), Err(TokenError::Invalid));
+    }
+
+    #[test]
+    fn ttl_is_capped() {
+        let link = ShareLink::new(ShareTarget::Worksheet("w1".to_string()), u64::MAX / 2);
+        assert!(link.expires_at <= Utc::now() + chrono::Duration::seconds(MAX_TTL_SECS as i64 + 1));
+    }
+}
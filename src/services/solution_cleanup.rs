@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+
+use crate::models::problem::Solution;
+
+/// Solutions at least this similar (character trigram Jaccard) are treated
+/// as the same answer
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.9;
+
+/// Canonical provider name: `Claude`, `anthropic` and `claude-3-opus` are
+/// all `claude`. Unknown names are only lowercased.
+pub fn canonical_provider(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    if name == "anthropic" || name.starts_with("claude") {
+        "claude".to_string()
+    } else if name.starts_with("openai") || name.starts_with("gpt") || name.starts_with("chatgpt") {
+        "openai".to_string()
+    } else if name.starts_with("mistral") {
+        "mistral".to_string()
+    } else {
+        name
+    }
+}
+
+/// Character trigrams of the content with case and whitespace normalized
+fn trigrams(content: &str) -> HashSet<[char; 3]> {
+    let chars: Vec<char> = content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .collect();
+    chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+/// Similarity of two solution texts, 0.0 to 1.0
+pub fn content_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let union = a.union(&b).count();
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveReason {
+    /// Another run of the same provider (under any of its names)
+    SameProvider,
+    NearDuplicate { similarity: f64 },
+}
+
+impl ArchiveReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveReason::SameProvider => "same_provider",
+            ArchiveReason::NearDuplicate { .. } => "near_duplicate",
+        }
+    }
+}
+
+/// A redundant solution and the one kept in its place
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedSolution {
+    pub solution_id: String,
+    pub problem_id: String,
+    pub provider: String,
+    pub kept_id: String,
+    pub reason: ArchiveReason,
+}
+
+/// A kept solution whose provider is stored under a non-canonical name
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderRename {
+    pub solution_id: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CleanupPlan {
+    pub solutions_scanned: usize,
+    pub problems_affected: usize,
+    pub archive: Vec<ArchivedSolution>,
+    pub renames: Vec<ProviderRename>,
+}
+
+/// Verified first, then by rating, then the most recently updated
+fn rank(solution: &Solution) -> (bool, Option<u8>, chrono::DateTime<chrono::Utc>) {
+    (solution.is_verified, solution.rating, solution.updated_at)
+}
+
+/// Decide which solutions of each problem to keep. The best one always
+/// stays; a later one is archived when it repeats a kept solution's provider
+/// or content. Verified solutions are never archived.
+pub fn plan_cleanup(solutions: &[Solution], threshold: f64) -> CleanupPlan {
+    let mut by_problem: BTreeMap<&str, Vec<&Solution>> = BTreeMap::new();
+    for solution in solutions {
+        by_problem.entry(solution.problem_id.as_str()).or_default().push(solution);
+    }
+
+    let mut plan = CleanupPlan { solutions_scanned: solutions.len(), ..Default::default() };
+    for (_, mut group) in by_problem {
+        group.sort_by_key(|s| std::cmp::Reverse(rank(s)));
+
+        let mut kept: Vec<&Solution> = Vec::new();
+        let archived_before = plan.archive.len();
+        for solution in group {
+            let provider = canonical_provider(&solution.provider);
+            let redundant = if solution.is_verified {
+                None
+            } else if let Some(k) = kept.iter().find(|k| canonical_provider(&k.provider) == provider) {
+                Some((*k, ArchiveReason::SameProvider))
+            } else {
+                kept.iter().find_map(|k| {
+                    let similarity = content_similarity(&k.content, &solution.content);
+                    (similarity >= threshold).then_some((*k, ArchiveReason::NearDuplicate { similarity }))
+                })
+            };
+
+            match redundant {
+                Some((keeper, reason)) => plan.archive.push(ArchivedSolution {
+                    solution_id: solution.id.clone(),
+                    problem_id: solution.problem_id.clone(),
+                    provider: solution.provider.clone(),
+                    kept_id: keeper.id.clone(),
+                    reason,
+                }),
+                None => kept.push(solution),
+            }
+        }
+
+        // Provider names are unique per problem: a canonical name goes to the
+        // best kept solution under it. Only verified solutions can share one
+        // (others were archived above), and those keep their alias.
+        let mut claimed: Vec<String> = kept.iter().map(|k| k.provider.clone()).collect();
+        for solution in &kept {
+            let canonical = canonical_provider(&solution.provider);
+            if solution.provider != canonical && !claimed.contains(&canonical) {
+                claimed.push(canonical.clone());
+                plan.renames.push(ProviderRename {
+                    solution_id: solution.id.clone(),
+                    from: solution.provider.clone(),
+                    to: canonical,
+                });
+            }
+        }
+
+        if plan.archive.len() > archived_before {
+            plan.problems_affected += 1;
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution(id: &str, provider: &str, content: &str, verified: bool, rating: Option<u8>) -> Solution {
+        Solution {
+            id: id.to_string(),
+            problem_id: "algebra-7:1:5".to_string(),
+            provider: provider.to_string(),
+            content: content.to_string(),
+            latex_formulas: vec![],
+            is_verified: verified,
+            rating,
+            generation: None,
+            prompt_hash: None,
+            version: 1,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn canonicalizes_provider_aliases() {
+        assert_eq!(canonical_provider("Claude-3-Opus"), "claude");
+        assert_eq!(canonical_provider("anthropic"), "claude");
+        assert_eq!(canonical_provider("gpt-4o"), "openai");
+        assert_eq!(canonical_provider(" Manual "), "manual");
+    }
+
+    #[test]
+    fn keeps_best_and_archives_reruns_and_near_duplicates() {
+        let answer = "Раскроем скобки: $x^2 + 2x + 1 = 0$, откуда $(x+1)^2 = 0$ и $x = -1$.";
+        let solutions = vec![
+            solution("a", "claude", "Решим через дискриминант: $D = 0$, значит $x = -1$.", false, Some(3)),
+            solution("b", "Claude-3", answer, false, Some(5)),
+            solution("c", "openai", &format!("{}  ", answer.replace("Раскроем", "раскроем")), false, None),
+            solution("d", "mistral", "Графический способ: парабола касается оси в точке $-1$.", true, None),
+        ];
+
+        let plan = plan_cleanup(&solutions, DEFAULT_SIMILARITY_THRESHOLD);
+        let archived: Vec<(&str, &str, &str)> = plan
+            .archive
+            .iter()
+            .map(|a| (a.solution_id.as_str(), a.kept_id.as_str(), a.reason.as_str()))
+            .collect();
+        assert_eq!(archived, vec![("a", "b", "same_provider"), ("c", "b", "near_duplicate")]);
+        assert_eq!(plan.problems_affected, 1);
+        // "b" outranks "a", so it stays and takes over the canonical claude name
+        assert_eq!(plan.renames.len(), 1);
+        assert_eq!((plan.renames[0].solution_id.as_str(), plan.renames[0].to.as_str()), ("b", "claude"));
+    }
+
+    #[test]
+    fn never_archives_verified_solutions() {
+        let solutions = vec![
+            solution("a", "claude", "x = 2", true, Some(5)),
+            solution("b", "claude", "x = 2", true, None),
+        ];
+        assert!(plan_cleanup(&solutions, DEFAULT_SIMILARITY_THRESHOLD).archive.is_empty());
+
+        // Only one of two verified aliases can take the canonical name
+        let aliases = vec![
+            solution("a", "claude-3", "x = 2", true, Some(5)),
+            solution("b", "claude_sonnet", "x = 2", true, None),
+        ];
+        let plan = plan_cleanup(&aliases, DEFAULT_SIMILARITY_THRESHOLD);
+        let renamed: Vec<&str> = plan.renames.iter().map(|r| r.solution_id.as_str()).collect();
+        assert_eq!(renamed, vec!["a"]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::problem::GenerationParams;
+use crate::services::ai_solver::SOLUTION_PROMPT_VERSION;
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Hash of everything that goes into a solve request. Two requests with the
+/// same key send the provider the same prompt with the same parameters, so
+/// the stored answer of one can be returned for the other.
+pub fn solve_cache_key(provider: &str, problem_content: &str, context: &str, params: &GenerationParams) -> String {
+    let mut hasher = Sha256::new();
+    for part in [
+        SOLUTION_PROMPT_VERSION.to_string().as_str(),
+        provider,
+        &params.model,
+        &params.temperature.to_string(),
+        &params.max_tokens.to_string(),
+        problem_content.trim(),
+        context.trim(),
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn record_hit() {
+    HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_miss() {
+    MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Solve cache lookups since the server started
+#[derive(Debug, Clone, Serialize)]
+pub struct SolveCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    /// Stored solutions that can be served from the cache
+    pub cached_solutions: u64,
+}
+
+pub fn cache_stats(cached_solutions: u64) -> SolveCacheStats {
+    let hits = HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    let lookups = hits + misses;
+    SolveCacheStats {
+        hits,
+        misses,
+        hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        cached_solutions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(model: &str, temperature: f32) -> GenerationParams {
+        GenerationParams { model: model.to_string(), temperature, max_tokens: 4096 }
+    }
+
+    #[test]
+    fn key_changes_with_any_prompt_input() {
+        let base = solve_cache_key("claude", "Решите: $x^2 = 4$", "Теорема Виета", &params("claude-3-5-sonnet-20241022", 0.3));
+        assert_eq!(base, solve_cache_key("claude", " Решите: $x^2 = 4$\n", "Теорема Виета", &params("claude-3-5-sonnet-20241022", 0.3)));
+
+        for other in [
+            solve_cache_key("openai", "Решите: $x^2 = 4$", "Теорема Виета", &params("claude-3-5-sonnet-20241022", 0.3)),
+            solve_cache_key("claude", "Решите: $x^2 = 9$", "Теорема Виета", &params("claude-3-5-sonnet-20241022", 0.3)),
+            solve_cache_key("claude", "Решите: $x^2 = 4$", "", &params("claude-3-5-sonnet-20241022", 0.3)),
+            solve_cache_key("claude", "Решите: $x^2 = 4$", "Теорема Виета", &params("claude-3-5-haiku-20241022", 0.3)),
+            solve_cache_key("claude", "Решите: $x^2 = 4$", "Теорема Виета", &params("claude-3-5-sonnet-20241022", 0.7)),
+        ] {
+            assert_ne!(base, other);
+        }
+    }
+}
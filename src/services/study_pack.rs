@@ -0,0 +1,258 @@
+use serde::Serialize;
+
+use crate::models::problem::{Chapter, Problem, ProblemHint, TheoryBlock, TheoryType};
+
+/// Problems picked for a study pack
+pub const PACK_PROBLEMS: usize = 10;
+
+/// Longest theory summary, in characters
+const SUMMARY_CHARS: usize = 300;
+
+/// Most formulas listed under "key formulas"
+const MAX_KEY_FORMULAS: usize = 20;
+
+/// Everything needed to study a chapter, assembled in one call
+#[derive(Debug, Clone, Serialize)]
+pub struct StudyPack {
+    pub chapter_id: String,
+    pub book_id: String,
+    pub chapter_number: u32,
+    pub chapter_title: String,
+    pub theory: Vec<TheorySummary>,
+    pub key_formulas: Vec<String>,
+    pub problems: Vec<PackProblem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TheorySummary {
+    pub id: String,
+    pub block_type: TheoryType,
+    pub title: Option<String>,
+    pub summary: String,
+    pub page_number: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackProblem {
+    pub id: String,
+    pub number: String,
+    pub display_name: String,
+    pub content: String,
+    pub difficulty: Option<u8>,
+    pub page_number: Option<u32>,
+    pub hints: Vec<PackHint>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackHint {
+    pub hint_level: u8,
+    pub content: String,
+}
+
+/// First sentences of a block, cut at `SUMMARY_CHARS`
+pub fn summarize(content: &str) -> String {
+    let text = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= SUMMARY_CHARS {
+        return text;
+    }
+
+    let cut: String = text.chars().take(SUMMARY_CHARS).collect();
+    // Prefer ending on a sentence, unless that drops most of the text
+    match cut.rfind(". ") {
+        Some(end) if cut[..end].chars().count() > SUMMARY_CHARS / 2 => cut[..=end].to_string(),
+        _ => format!("{}…", cut.trim_end()),
+    }
+}
+
+/// Theory worth re-reading before practice; proofs and worked examples are
+/// left to the full chapter
+pub fn summarize_theory(blocks: &[TheoryBlock]) -> Vec<TheorySummary> {
+    blocks
+        .iter()
+        .filter(|b| !matches!(b.block_type, TheoryType::Proof | TheoryType::Example))
+        .map(|b| TheorySummary {
+            id: b.id.clone(),
+            block_type: b.block_type.clone(),
+            title: b.title.clone(),
+            summary: summarize(&b.content),
+            page_number: b.page_number,
+        })
+        .collect()
+}
+
+/// Formulas stated in the chapter's theory, formula and theorem blocks
+/// first, without repeats or one-symbol fragments like `$x$`
+pub fn key_formulas(blocks: &[TheoryBlock]) -> Vec<String> {
+    let rank = |t: &TheoryType| match t {
+        TheoryType::Formula => 0,
+        TheoryType::Theorem | TheoryType::Property => 1,
+        TheoryType::Definition => 2,
+        _ => 3,
+    };
+    let mut ordered: Vec<&TheoryBlock> = blocks.iter().collect();
+    ordered.sort_by_key(|b| rank(&b.block_type));
+
+    let mut seen = std::collections::HashSet::new();
+    ordered
+        .iter()
+        .flat_map(|b| b.latex_formulas.iter())
+        .map(|f| f.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|f| f.chars().filter(|c| !c.is_whitespace()).count() > 2)
+        .filter(|f| seen.insert(f.clone()))
+        .take(MAX_KEY_FORMULAS)
+        .collect()
+}
+
+/// Position of a problem in the book: page, then numeric problem number
+fn book_order(problem: &Problem) -> (u32, u32, String) {
+    let number = problem.number.chars().take_while(char::is_ascii_digit).collect::<String>();
+    (
+        problem.page_number.unwrap_or(u32::MAX),
+        number.parse().unwrap_or(u32::MAX),
+        problem.number.clone(),
+    )
+}
+
+/// Up to `count` problems spread evenly from easiest to hardest. Problems
+/// without a difficulty are ranked after rated ones in book order, which in
+/// most textbooks also goes from easy to hard.
+pub fn pick_representative(problems: &[Problem], count: usize) -> Vec<&Problem> {
+    let mut sorted: Vec<&Problem> = problems.iter().filter(|p| p.parent_id.is_none()).collect();
+    sorted.sort_by_key(|p| (p.difficulty.is_none(), p.difficulty, book_order(p)));
+
+    if sorted.len() <= count {
+        return sorted;
+    }
+    (0..count).map(|i| sorted[i * sorted.len() / count]).collect()
+}
+
+pub fn build_study_pack(
+    chapter: &Chapter,
+    theory: &[TheoryBlock],
+    problems: &[(&Problem, Vec<ProblemHint>)],
+) -> StudyPack {
+    StudyPack {
+        chapter_id: chapter.id.clone(),
+        book_id: chapter.book_id.clone(),
+        chapter_number: chapter.number,
+        chapter_title: chapter.title.clone(),
+        theory: summarize_theory(theory),
+        key_formulas: key_formulas(theory),
+        problems: problems
+            .iter()
+            .map(|(p, hints)| PackProblem {
+                id: p.id.clone(),
+                number: p.number.clone(),
+                display_name: p.display_name.clone(),
+                content: p.content.clone(),
+                difficulty: p.difficulty,
+                page_number: p.page_number,
+                hints: hints
+                    .iter()
+                    .map(|h| PackHint { hint_level: h.hint_level, content: h.content.clone() })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// Markdown handout of a study pack; formulas stay LaTeX
+pub fn render_markdown(pack: &StudyPack) -> String {
+    let mut md = format!("# Глава {}. {}\n\n", pack.chapter_number, pack.chapter_title);
+
+    if !pack.theory.is_empty() {
+        md.push_str("## Теория\n\n");
+        for block in &pack.theory {
+            match &block.title {
+                Some(title) => md.push_str(&format!("- **{}** ({}): {}\n", title, block.block_type.as_str(), block.summary)),
+                None => md.push_str(&format!("- *{}*: {}\n", block.block_type.as_str(), block.summary)),
+            }
+        }
+        md.push('\n');
+    }
+
+    if !pack.key_formulas.is_empty() {
+        md.push_str("## Ключевые формулы\n\n");
+        for formula in &pack.key_formulas {
+            md.push_str(&format!("- ${}$\n", formula));
+        }
+        md.push('\n');
+    }
+
+    if !pack.problems.is_empty() {
+        md.push_str("## Задачи\n\n");
+        for problem in &pack.problems {
+            md.push_str(&format!("### {}", problem.display_name));
+            if let Some(difficulty) = problem.difficulty {
+                md.push_str(&format!(" (сложность {})", difficulty));
+            }
+            md.push_str(&format!("\n\n{}\n\n", problem.content.trim()));
+            for hint in &problem.hints {
+                md.push_str(&format!("> **Подсказка {}:** {}\n\n", hint.hint_level, hint.content.trim().replace('\n', "\n> ")));
+            }
+        }
+    }
+
+    md.trim_end().to_string() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(number: u32, difficulty: Option<u8>) -> Problem {
+        Problem {
+            id: format!("algebra-7:1:{}", number),
+            chapter_id: "algebra-7:1".to_string(),
+            number: number.to_string(),
+            display_name: format!("Задача {}", number),
+            content: format!("{}. Решите уравнение", number),
+            page_number: Some(10 + number / 10),
+            difficulty,
+            created_at: chrono::Utc::now(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn picks_problems_across_difficulties() {
+        let problems: Vec<Problem> = (1..=30).map(|n| problem(n, Some((n % 5) as u8 + 1))).collect();
+        let picked = pick_representative(&problems, 10);
+        assert_eq!(picked.len(), 10);
+        let difficulties: Vec<u8> = picked.iter().filter_map(|p| p.difficulty).collect();
+        assert_eq!(difficulties, vec![1, 1, 2, 2, 3, 3, 4, 4, 5, 5]);
+
+        // Unrated problems are sampled in book order, not by the text sort of their numbers
+        let unrated: Vec<Problem> = (1..=20).map(|n| problem(n, None)).collect();
+        let numbers: Vec<&str> = pick_representative(&unrated, 4).iter().map(|p| p.number.as_str()).collect();
+        assert_eq!(numbers, vec!["1", "6", "11", "16"]);
+    }
+
+    #[test]
+    fn key_formulas_prefer_formula_blocks_and_skip_repeats() {
+        let block = |block_type, formulas: &[&str]| TheoryBlock {
+            id: "algebra-7:1:T:1".to_string(),
+            chapter_id: "algebra-7:1".to_string(),
+            block_num: 1,
+            title: None,
+            block_type,
+            content: String::new(),
+            latex_formulas: formulas.iter().map(|f| f.to_string()).collect(),
+            page_number: None,
+            created_at: chrono::Utc::now(),
+        };
+        let blocks = [
+            block(TheoryType::Explanation, &["x", "a^2 - b^2"]),
+            block(TheoryType::Formula, &["(a+b)^2 = a^2 + 2ab + b^2", "a^2  -  b^2"]),
+        ];
+        assert_eq!(key_formulas(&blocks), vec!["(a+b)^2 = a^2 + 2ab + b^2", "a^2 - b^2"]);
+    }
+
+    #[test]
+    fn summary_ends_on_a_sentence() {
+        let long = format!("{}. {}", "Первое предложение ".repeat(10).trim(), "Второе ".repeat(40));
+        let summary = summarize(&long);
+        assert!(summary.ends_with("предложение."), "{}", summary);
+        assert_eq!(summarize("Короткий   текст"), "Короткий текст");
+    }
+}
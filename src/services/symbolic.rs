@@ -0,0 +1,92 @@
+//! Symbolic equivalence of algebraic answers: `(x-2)(x+2)` and `x^2 - 4`,
+//! `\frac{1}{2}` and `0,5`. Both sides are parsed with the plot formula
+//! parser and compared at a set of sample points over their variables, which
+//! decides equivalence of the polynomial and rational expressions school
+//! books answer with, without a CAS.
+
+use lazy_regex::regex;
+
+use crate::services::plot::Formula;
+
+/// Points both answers are compared at
+const SAMPLES: usize = 12;
+/// Points that must give finite values on both sides for a verdict
+const MIN_VALID_SAMPLES: usize = 5;
+const RELATIVE_TOLERANCE: f64 = 1e-9;
+
+/// Whether two answers are the same expression. None when either is not a
+/// formula (words, several values, a system) or too few sample points are in
+/// both domains.
+pub fn equivalent(a: &str, b: &str) -> Option<bool> {
+    let (a, b) = (Formula::parse_algebraic(&prepare(a)?).ok()?, Formula::parse_algebraic(&prepare(b)?).ok()?);
+    let mut variables = a.variables();
+    variables.extend(b.variables());
+    let variables: Vec<char> = variables.into_iter().collect();
+
+    let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut valid = 0;
+    for _ in 0..SAMPLES {
+        // Positive, non-integer values keep roots and logarithms defined and
+        // make accidental agreement unlikely
+        let values: Vec<f64> = variables.iter().map(|_| 0.5 + 2.5 * next_unit(&mut seed)).collect();
+        let value = |name: char| variables.iter().position(|&v| v == name).map_or(f64::NAN, |i| values[i]);
+        let (left, right) = (a.eval_with(&value), b.eval_with(&value));
+        if !left.is_finite() || !right.is_finite() {
+            continue;
+        }
+        if (left - right).abs() > RELATIVE_TOLERANCE * left.abs().max(right.abs()).max(1.0) {
+            return Some(false);
+        }
+        valid += 1;
+    }
+    (valid >= MIN_VALID_SAMPLES).then_some(true)
+}
+
+/// Answer text as a formula: no math delimiters, final full stop or
+/// assignment (`x = 4` is compared as `4`), superscript powers spelled out.
+/// None for several values (`x_1 = 3, x_2 = 5`, `3; 5`) and equations, which
+/// would otherwise be compared by one of their parts.
+fn prepare(answer: &str) -> Option<String> {
+    let answer = answer.replace('$', "").replace('²', "^2").replace('³', "^3").replace("\\dfrac", "\\frac");
+    let answer = answer.trim().trim_end_matches(['.', ';']).trim();
+    // A comma between digits is a decimal comma
+    if answer.contains(';') || regex!(r"\D,|,\D").is_match(answer) {
+        return None;
+    }
+    let value = match answer.split_once('=') {
+        Some((name, value)) if regex!(r"^[A-Za-z](?:_\{?\w+\}?)?$").is_match(name.trim()) => value,
+        Some(_) => return None,
+        None => answer,
+    };
+    (!value.contains('=')).then(|| value.trim().to_string())
+}
+
+/// Deterministic xorshift in [0, 1), so verdicts don't change between runs
+fn next_unit(state: &mut u64) -> f64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_expressions_at_sample_points() {
+        assert_eq!(equivalent("(x-2)(x+2)", "x² − 4"), Some(true));
+        assert_eq!(equivalent("$\\frac{1}{2}$", "0,5"), Some(true));
+        assert_eq!(equivalent("y = (a + b)^2", "a^2 + 2ab + b^2"), Some(true));
+        assert_eq!(equivalent("\\frac{x^2 - 1}{x - 1}", "x + 1"), Some(true));
+        assert_eq!(equivalent("(x-2)^2", "x^2 - 4"), Some(false));
+        assert_eq!(equivalent("a + b", "a - b"), Some(false));
+        // Not formulas
+        assert_eq!(equivalent("корней нет", "0"), None);
+        assert_eq!(equivalent("а) 3; б) 0,5", "3"), None);
+        assert_eq!(equivalent("x_1 = 3, x_2 = 5", "x = 5"), None);
+        assert_eq!(equivalent("3, 5", "5"), None);
+        assert_eq!(equivalent("2x = 10", "5"), None);
+        assert_eq!(equivalent("x_1 = 2,5", "2,5"), Some(true));
+    }
+}
@@ -0,0 +1,229 @@
+//! Page templates: the bundled `templates/` directory with a deployment's
+//! theme on top. A theme directory (`THEME_DIR`) may hold
+//!
+//! - `templates/`: files replacing the bundled templates of the same name
+//! - `theme.json`: CSS variables (`{"bg-primary": "#1e1e2e", ...}`) served
+//!   as `/theme.css`, which every page loads after its own styles
+//!
+//! With `TEMPLATE_RELOAD=1` edited templates are picked up on the next page
+//! view instead of at the next restart.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+use lazy_regex::regex;
+use tera::{Context, Tera, Value};
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::services::markdown_sanitizer::sanitize_markdown;
+
+/// Bundled templates, relative to the working directory
+pub const TEMPLATES_DIR: &str = "templates";
+
+pub struct Templates {
+    tera: RwLock<Tera>,
+    dirs: Vec<PathBuf>,
+    theme_dir: Option<PathBuf>,
+    reload: bool,
+    /// Newest template mtime when `tera` was built
+    loaded: Mutex<Option<SystemTime>>,
+}
+
+impl Templates {
+    pub fn from_config(config: &Config) -> tera::Result<Self> {
+        let theme_dir = config.theme_dir.clone();
+        let mut dirs = vec![PathBuf::from(TEMPLATES_DIR)];
+        dirs.extend(theme_dir.as_ref().map(|dir| dir.join("templates")));
+        let loaded = newest_mtime(&dirs);
+        Ok(Self {
+            tera: RwLock::new(build(&dirs)?),
+            dirs,
+            theme_dir,
+            reload: config.template_reload,
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    pub fn render(&self, name: &str, context: &Context) -> tera::Result<String> {
+        if self.reload {
+            self.reload_if_changed();
+        }
+        self.tera.read().unwrap_or_else(|e| e.into_inner()).render(name, context)
+    }
+
+    /// Rebuild the templates when a file changed since they were loaded. A
+    /// template that fails to parse is logged and the previous set kept, so
+    /// a typo doesn't take the pages down.
+    fn reload_if_changed(&self) {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        let newest = newest_mtime(&self.dirs);
+        if newest == *loaded {
+            return;
+        }
+        *loaded = newest;
+        match build(&self.dirs) {
+            Ok(tera) => {
+                log::info!("Templates changed, reloaded");
+                *self.tera.write().unwrap_or_else(|e| e.into_inner()) = tera;
+            }
+            Err(e) => log::error!("Failed to reload templates, keeping the previous ones: {:?}", e),
+        }
+    }
+
+    /// `:root` rule with the theme's CSS variables; empty without a theme
+    pub fn theme_css(&self) -> String {
+        let Some(path) = self.theme_dir.as_ref().map(|dir| dir.join("theme.json")) else {
+            return String::new();
+        };
+        let variables: BTreeMap<String, String> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid theme {:?}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        theme_css(&variables)
+    }
+}
+
+fn theme_css(variables: &BTreeMap<String, String>) -> String {
+    let mut css = String::from(":root {\n");
+    for (name, value) in variables {
+        let name = name.trim_start_matches("--");
+        // Values end up inside a <link>ed stylesheet: no way out of the declaration
+        if !regex!(r"^[A-Za-z0-9_-]+$").is_match(name) || value.contains([';', '{', '}', '<']) {
+            log::warn!("Ignoring theme variable {:?}", name);
+            continue;
+        }
+        css.push_str(&format!("    --{}: {};\n", name, value.trim()));
+    }
+    css.push_str("}\n");
+    css
+}
+
+/// Templates of `dirs` by path relative to their directory; later
+/// directories override earlier ones
+fn template_files(dirs: &[PathBuf]) -> Vec<(PathBuf, Option<String>)> {
+    let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in dirs {
+        for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(dir) else {
+                continue;
+            };
+            let name = relative.to_string_lossy().replace('\\', "/");
+            files.insert(name, entry.path().to_path_buf());
+        }
+    }
+    files.into_iter().map(|(name, path)| (path, Some(name))).collect()
+}
+
+fn newest_mtime(dirs: &[PathBuf]) -> Option<SystemTime> {
+    dirs.iter()
+        .flat_map(|dir| WalkDir::new(dir).into_iter().filter_map(Result::ok))
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+}
+
+fn build(dirs: &[PathBuf]) -> tera::Result<Tera> {
+    let mut tera = Tera::default();
+    tera.add_template_files(template_files(dirs))?;
+    register_filters(&mut tera);
+    Ok(tera)
+}
+
+fn register_filters(tera: &mut Tera) {
+    // Register markdown filter
+    tera.register_filter("markdown", |value: &Value, _args: &HashMap<String, Value>| {
+        let text = value.as_str().unwrap_or("");
+        // Simple markdown to HTML conversion
+        let html = text
+            .replace("**", "<strong>")
+            .replace("*", "<em>")
+            .replace("`", "<code>")
+            .replace("\n\n", "</p><p>")
+            .replace("\n", "<br>");
+        Ok(Value::String(format!("<p>{}</p>", html)))
+    });
+
+    // Provider-written content goes through the sanitizer before `| safe`
+    tera.register_filter("sanitize_markdown", |value: &Value, _args: &HashMap<String, Value>| {
+        Ok(Value::String(sanitize_markdown(value.as_str().unwrap_or(""))))
+    });
+
+    // JSON for a `<script>` block: `</script>` in a value can't end the block
+    tera.register_filter("script_json", |value: &Value, _args: &HashMap<String, Value>| {
+        Ok(Value::String(script_json(value)))
+    });
+
+    // Register truncate filter
+    tera.register_filter("truncate", |value: &Value, args: &HashMap<String, Value>| {
+        let text = value.as_str().unwrap_or("");
+        let length = args.get("length").and_then(|v| v.as_i64()).unwrap_or(100) as usize;
+        if text.len() > length {
+            Ok(Value::String(format!("{}...", &text[..length])))
+        } else {
+            Ok(Value::String(text.to_string()))
+        }
+    });
+}
+
+/// `value` as JSON with `<`, `>` and `&` escaped, safe to embed in a script
+fn script_json(value: &Value) -> String {
+    value
+        .to_string()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_json_cannot_close_the_script() {
+        let value = serde_json::json!({ r"\x": "x</script><script>alert(1)//" });
+        let json = script_json(&value);
+        assert!(!json.contains('<'));
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn theme_templates_override_bundled_ones() {
+        let dir = std::env::temp_dir().join(format!("booker-templates-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (bundled, theme) = (dir.join("bundled"), dir.join("theme"));
+        std::fs::create_dir_all(bundled.join("textbook")).unwrap();
+        std::fs::create_dir_all(&theme).unwrap();
+        std::fs::write(bundled.join("index.html"), "bundled index").unwrap();
+        std::fs::write(bundled.join("textbook/page.html"), "bundled page").unwrap();
+        std::fs::write(theme.join("index.html"), "themed {{ name }}").unwrap();
+
+        let tera = build(&[bundled, theme]).unwrap();
+        let mut context = Context::new();
+        context.insert("name", "index");
+        assert_eq!(tera.render("index.html", &context).unwrap(), "themed index");
+        assert_eq!(tera.render("textbook/page.html", &context).unwrap(), "bundled page");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn theme_css_skips_unsafe_variables() {
+        let variables = BTreeMap::from([
+            ("--bg-primary".to_string(), "#1e1e2e".to_string()),
+            ("accent".to_string(), "rgb(37, 99, 235)".to_string()),
+            ("x".to_string(), "red; } body { display: none".to_string()),
+            ("a b".to_string(), "red".to_string()),
+        ]);
+        assert_eq!(
+            theme_css(&variables),
+            ":root {\n    --bg-primary: #1e1e2e;\n    --accent: rgb(37, 99, 235);\n}\n"
+        );
+    }
+}
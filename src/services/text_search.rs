@@ -0,0 +1,108 @@
+//! Full-text search over problem, theory and solution text, backed by the
+//! SQLite FTS5 indexes `problems_fts`, `theory_fts` and `solutions_fts`
+//! (see `Database::ensure_search_index`).
+
+use serde::{Deserialize, Serialize};
+
+/// Marks around matched terms in [`SearchHit::snippet`]
+pub const SNIPPET_START: &str = "<mark>";
+pub const SNIPPET_END: &str = "</mark>";
+
+/// What FTS5's `snippet()` puts around matches: private-use characters, so
+/// the text can be HTML-escaped before they become [`SNIPPET_START`] and
+/// [`SNIPPET_END`]
+pub const RAW_SNIPPET_START: char = '\u{E002}';
+pub const RAW_SNIPPET_END: char = '\u{E003}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    Problem,
+    Theory,
+    Solution,
+}
+
+impl SearchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchKind::Problem => "problem",
+            SearchKind::Theory => "theory",
+            SearchKind::Solution => "solution",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub kind: SearchKind,
+    /// Problem, theory block or solution id
+    pub id: String,
+    /// The problem a solution belongs to; the problem itself for problems
+    pub problem_id: Option<String>,
+    pub chapter_id: String,
+    /// Problem display name, theory block title or solution provider
+    pub title: Option<String>,
+    /// HTML-escaped text around the matches, matched terms between [`SNIPPET_START`] and [`SNIPPET_END`]
+    pub snippet: String,
+    /// BM25 score; lower is a better match
+    pub rank: f64,
+}
+
+/// FTS5 query for what a user typed: every word must appear, the last one
+/// may be a prefix still being typed. Words are quoted so FTS5 operators
+/// and punctuation (`-`, `^`, `:`, `"`) in the input are taken literally.
+/// `None` when there is nothing to search for.
+pub fn match_expression(input: &str) -> Option<String> {
+    let words: Vec<String> = input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("\"{}\"", w))
+        .collect();
+    let last = words.len().checked_sub(1)?;
+    Some(
+        words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| if i == last { format!("{}*", w) } else { w.clone() })
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// A `snippet()` result as HTML: the indexed text escaped, only the matches marked
+pub fn mark_snippet(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            RAW_SNIPPET_START => html.push_str(SNIPPET_START),
+            RAW_SNIPPET_END => html.push_str(SNIPPET_END),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_words_and_prefixes_the_last() {
+        assert_eq!(match_expression("квадратное урав").as_deref(), Some("\"квадратное\" \"урав\"*"));
+        assert_eq!(match_expression("x^2 - NEAR(\"y\")").as_deref(), Some("\"x\" \"2\" \"NEAR\" \"y\"*"));
+        assert_eq!(match_expression("  -- "), None);
+    }
+
+    #[test]
+    fn escapes_snippet_text_but_not_marks() {
+        assert_eq!(
+            mark_snippet("<img src=x onerror=alert(1)> \u{E002}x<y\u{E003}"),
+            "&lt;img src=x onerror=alert(1)&gt; <mark>x&lt;y</mark>"
+        );
+    }
+}
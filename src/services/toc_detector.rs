@@ -1,6 +1,7 @@
 use regex::Regex;
 use crate::models::{Chapter, Book};
 use crate::services::database::Database;
+use crate::services::heading_detector::HeadingDetector;
 use anyhow::Result;
 
 /// Table of Contents detector
@@ -93,51 +94,27 @@ impl TocDetector {
         Some(DetectedToc { entries, confidence })
     }
 
-    /// Detect chapters from page-by-page OCR text
-    pub fn detect_chapters_from_pages(&self, pages: &[(u32, String)]) -> Vec<ChapterDetection> {
+    /// Detect chapters from page-by-page OCR text, using the default heading
+    /// patterns or a book's own (see [`HeadingDetector`])
+    pub fn detect_chapters_from_pages(&self, pages: &[(u32, String)], headings: &HeadingDetector) -> Vec<ChapterDetection> {
         let mut chapters = Vec::new();
         let mut last_number = 0;
 
-        // Pattern for chapter headers on pages
-        let chapter_patterns = vec![
-            Regex::new(r"(?i)^\s*глава\s+(\d+)").ok(),
-            Regex::new(r"(?i)^\s*глава\s+([IVXLC]+)").ok(),
-            Regex::new(r"(?i)^\s*§\s*(\d+)").ok(),
-            Regex::new(r"(?i)^\s*раздел\s+(\d+)").ok(),
-            Regex::new(r"(?i)^\s*chapter\s+(\d+)").ok(),
-        ];
-
         for (page_num, text) in pages {
-            let first_lines: String = text.lines().take(10).collect::<Vec<_>>().join("\n");
-
-            for pattern_opt in &chapter_patterns {
-                if let Some(pattern) = pattern_opt {
-                    if let Some(caps) = pattern.captures(&first_lines) {
-                        let num_str = caps.get(1).map(|m| m.as_str()).unwrap_or("0");
-                        
-                        let number = if let Ok(n) = num_str.parse::<u32>() {
-                            n
-                        } else {
-                            Self::roman_to_arabic(num_str).unwrap_or(0)
-                        };
-
-                        // Only accept if number is sequential
-                        if number > last_number || number == 1 {
-                            // Extract title from next lines
-                            let title = self.extract_chapter_title(text, caps.get(0).unwrap().end());
-
-                            chapters.push(ChapterDetection {
-                                number,
-                                title,
-                                start_page: *page_num,
-                                end_page: None,
-                                confidence: 0.9,
-                            });
-                            last_number = number;
-                        }
-                        break;
-                    }
-                }
+            let Some(heading) = headings.find_page_heading(text) else {
+                continue;
+            };
+
+            // Only accept if number is sequential
+            if heading.number > last_number || heading.number == 1 {
+                chapters.push(ChapterDetection {
+                    number: heading.number,
+                    title: heading.title,
+                    start_page: *page_num,
+                    end_page: None,
+                    confidence: 0.9,
+                });
+                last_number = heading.number;
             }
         }
 
@@ -151,28 +128,8 @@ impl TocDetector {
         chapters
     }
 
-    /// Extract chapter title from text after header
-    fn extract_chapter_title(&self, text: &str, start_pos: usize) -> String {
-        let remaining = &text[start_pos..];
-        
-        // Take first non-empty line as title
-        for line in remaining.lines() {
-            let line = line.trim();
-            if !line.is_empty() && line.len() < 200 {
-                // Clean up the title
-                return line
-                    .trim_start_matches('.')
-                    .trim_start_matches(':')
-                    .trim()
-                    .to_string();
-            }
-        }
-
-        "Untitled Chapter".to_string()
-    }
-
     /// Convert Roman numerals to Arabic
-    fn roman_to_arabic(roman: &str) -> Option<u32> {
+    pub(crate) fn roman_to_arabic(roman: &str) -> Option<u32> {
         let roman = roman.to_uppercase();
         let mut result = 0;
         let mut prev_value = 0;
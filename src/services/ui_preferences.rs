@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+/// Profile of requests without a `booker_profile` cookie; a single-user
+/// deployment keeps everything here, so every device shares it
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Cookie naming the preference profile of a browser
+pub const PROFILE_COOKIE: &str = "booker_profile";
+
+pub const MIN_FONT_SIZE: u32 = 12;
+pub const MAX_FONT_SIZE: u32 = 24;
+
+/// How the pages look for one profile. Stored server-side so a second
+/// device or a cleared browser gets the same settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiPreferences {
+    pub theme: Theme,
+    pub formula_renderer: FormulaRenderer,
+    /// Base text size in pixels; pages scale relative to 16
+    pub font_size: u32,
+}
+
+impl Default for UiPreferences {
+    fn default() -> Self {
+        Self { theme: Theme::System, formula_renderer: FormulaRenderer::Katex, font_size: 16 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+    /// Follow the browser's `prefers-color-scheme`
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormulaRenderer {
+    /// KaTeX HTML output
+    Katex,
+    /// KaTeX MathML output, rendered by the browser and read by screen readers
+    Mathml,
+    /// LaTeX source left as typed
+    Source,
+}
+
+impl UiPreferences {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&self.font_size) {
+            return Err(format!("font_size must be between {} and {}", MIN_FONT_SIZE, MAX_FONT_SIZE));
+        }
+        Ok(())
+    }
+}
+
+/// Profile name from a cookie value: letters, digits, `-` and `_`, at most
+/// 64 characters; anything else means [`DEFAULT_PROFILE`]
+pub fn profile_name(cookie: Option<&str>) -> String {
+    cookie
+        .map(str::trim)
+        .filter(|name| {
+            !name.is_empty()
+                && name.len() <= 64
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .unwrap_or(DEFAULT_PROFILE)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_documents_keep_defaults() {
+        let preferences: UiPreferences = serde_json::from_str(r#"{"theme": "dark"}"#).unwrap();
+        assert_eq!(preferences.theme, Theme::Dark);
+        assert_eq!(preferences.formula_renderer, FormulaRenderer::Katex);
+        assert_eq!(preferences.font_size, 16);
+        assert!(serde_json::from_str::<UiPreferences>(r#"{"theme": "sepia"}"#).is_err());
+        assert!(UiPreferences { font_size: 40, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn profile_names_fall_back_to_default() {
+        assert_eq!(profile_name(Some("kitchen-tablet")), "kitchen-tablet");
+        assert_eq!(profile_name(None), DEFAULT_PROFILE);
+        assert_eq!(profile_name(Some("")), DEFAULT_PROFILE);
+        assert_eq!(profile_name(Some("../x")), DEFAULT_PROFILE);
+    }
+}
@@ -295,7 +295,7 @@ pub fn validate_batch_import(problems: &[Problem], chapter_id: &str) -> Validati
     // Check for duplicates within batch
     let mut seen_numbers = std::collections::HashSet::new();
     for problem in problems {
-        let key = format!("{}:{}", chapter_id, problem.number);
+        let key = format!("{}:{}", chapter_id, Problem::normalize_number(&problem.number));
         if !seen_numbers.insert(key.clone()) {
             result.add_error(
                 "BATCH_DUPLICATE",
@@ -375,6 +375,11 @@ mod tests {
             continues_to_page: None,
             is_cross_page: false,
             is_bookmarked: false,
+            confidence: None,
+            review_status: crate::models::ReviewStatus::Unreviewed,
+            review_note: None,
+            reference_answer: None,
+            version: 1,
         }
     }
 }
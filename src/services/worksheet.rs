@@ -0,0 +1,263 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::models::Problem;
+use crate::services::calibration::apply_calibrations;
+use crate::services::database::Database;
+use crate::services::export::{escape_latex_text, markdown_to_latex};
+use crate::services::latex_macros::BookMacros;
+use crate::services::latex_pdf::compile_pdf;
+
+/// Stored worksheet: the selected problems in their randomized order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Worksheet {
+    pub id: String,
+    pub title: String,
+    pub problem_ids: Vec<String>,
+    pub seed: u64,
+    pub include_answer_key: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Constraints used to pick problems when explicit IDs are not given
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorksheetConstraints {
+    pub chapter_id: Option<String>,
+    pub book_id: Option<String>,
+    pub count: Option<usize>,
+    pub min_difficulty: Option<u8>,
+    pub max_difficulty: Option<u8>,
+    /// Only pick problems that already have a solution (for the answer key)
+    pub only_solved: Option<bool>,
+}
+
+/// Rendered worksheet document
+pub struct RenderedWorksheet {
+    pub data: Vec<u8>,
+    /// "pdf" when a TeX engine was available, "tex" otherwise
+    pub extension: &'static str,
+}
+
+impl RenderedWorksheet {
+    pub fn mime_type(&self) -> &'static str {
+        match self.extension {
+            "pdf" => "application/pdf",
+            _ => "application/x-latex",
+        }
+    }
+}
+
+pub struct WorksheetGenerator {
+    db: Database,
+    /// Formula crops are included from here
+    preview_dir: PathBuf,
+}
+
+impl WorksheetGenerator {
+    pub fn new(db: Database, config: &Config) -> Self {
+        Self { db, preview_dir: config.preview_dir.clone() }
+    }
+
+    /// Pick candidate problem IDs matching the constraints
+    pub async fn select_problems(&self, constraints: &WorksheetConstraints) -> anyhow::Result<Vec<String>> {
+        let chapter_ids: Vec<String> = match (&constraints.chapter_id, &constraints.book_id) {
+            (Some(chapter_id), _) => vec![chapter_id.clone()],
+            (None, Some(book_id)) => self.db.get_chapters_by_book(book_id).await?
+                .into_iter()
+                .map(|c| c.id)
+                .collect(),
+            (None, None) => return Err(anyhow::anyhow!("chapter_id or book_id is required")),
+        };
+
+        // Difficulty bounds apply to how hard problems proved, where known
+        let calibrations = self.db.get_calibrations(constraints.book_id.as_deref()).await?;
+        let mut candidates = Vec::new();
+        for chapter_id in chapter_ids {
+            let mut problems = self.db.get_problems_by_chapter(&chapter_id).await?;
+            apply_calibrations(&mut problems, &calibrations);
+            for problem in problems {
+                if constraints.only_solved.unwrap_or(false) && !problem.has_solution {
+                    continue;
+                }
+                if let Some(min) = constraints.min_difficulty
+                    && problem.difficulty.is_none_or(|d| d < min)
+                {
+                    continue;
+                }
+                if let Some(max) = constraints.max_difficulty
+                    && problem.difficulty.is_none_or(|d| d > max)
+                {
+                    continue;
+                }
+                candidates.push(problem.id);
+            }
+        }
+
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.truncate(constraints.count.unwrap_or(10));
+        Ok(candidates)
+    }
+
+    /// Create and store a worksheet; problem order is shuffled with a stored seed
+    pub async fn create(&self, title: &str, problem_ids: Vec<String>, include_answer_key: bool) -> anyhow::Result<Worksheet> {
+        let seed: u64 = rand::random();
+        let mut ordered = problem_ids;
+        ordered.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let worksheet = Worksheet {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            problem_ids: ordered,
+            seed,
+            include_answer_key,
+            created_at: chrono::Utc::now(),
+        };
+        self.db.save_worksheet(&worksheet).await?;
+        Ok(worksheet)
+    }
+
+    /// Render the worksheet: questions first, answer key on separate pages
+    pub async fn render(&self, worksheet: &Worksheet) -> anyhow::Result<RenderedWorksheet> {
+        let mut problems = Vec::new();
+        for id in &worksheet.problem_ids {
+            if let Some(problem) = self.db.get_problem_with_subs(id).await? {
+                let solution = self.db.get_solution_for_problem(id).await?.map(|s| s.content);
+                problems.push((problem, solution));
+            }
+        }
+
+        // Problems may come from several books; their macros are merged
+        let mut book_ids: Vec<String> = Vec::new();
+        for (problem, _) in &problems {
+            if let Some(chapter) = self.db.get_chapter(&problem.chapter_id).await?
+                && !book_ids.contains(&chapter.book_id)
+            {
+                book_ids.push(chapter.book_id);
+            }
+        }
+        let mut book_macros = Vec::new();
+        for book_id in &book_ids {
+            book_macros.push(self.db.get_book_macros(book_id).await?);
+        }
+        let macros = BookMacros::merge(&book_macros).latex_preamble();
+
+        let tex = render_latex(&worksheet.title, &macros, &problems, worksheet.include_answer_key, Some(&self.preview_dir));
+
+        match compile_pdf(&tex, Some(&self.preview_dir)).await {
+            Ok(pdf) => Ok(RenderedWorksheet { data: pdf, extension: "pdf" }),
+            Err(e) => {
+                log::warn!("Worksheet PDF compilation unavailable, returning LaTeX source: {}", e);
+                Ok(RenderedWorksheet { data: tex.into_bytes(), extension: "tex" })
+            }
+        }
+    }
+}
+
+fn render_latex(
+    title: &str,
+    macros: &str,
+    problems: &[(Problem, Option<String>)],
+    include_answer_key: bool,
+    preview_dir: Option<&Path>,
+) -> String {
+    let to_latex = |text: &str| markdown_to_latex(text, preview_dir);
+    let mut output = String::from(r"\documentclass[12pt]{article}
+\usepackage[utf8]{inputenc}
+\usepackage[russian]{babel}
+\usepackage{amsmath,amssymb}
+\usepackage{graphicx}
+\usepackage{enumitem}
+\usepackage[a4paper,margin=2cm]{geometry}
+\pagestyle{plain}
+");
+    output.push_str(macros);
+    output.push_str("\n\\begin{document}\n");
+    output.push_str(&format!("\\begin{{center}}\\Large\\textbf{{{}}}\\end{{center}}\n", escape_latex_text(title)));
+    output.push_str("\\noindent Имя: \\rule{6cm}{0.4pt} \\hfill Дата: \\rule{3cm}{0.4pt}\n\\vspace{1em}\n\n");
+
+    output.push_str("\\begin{enumerate}[label=\\textbf{\\arabic*.}]\n");
+    for (problem, _) in problems {
+        output.push_str(&format!("\\item {}\n", to_latex(&problem.content)));
+        if let Some(subs) = &problem.sub_problems {
+            output.push_str("\\begin{enumerate}[label=\\alph*)]\n");
+            for sub in subs {
+                output.push_str(&format!("\\item {}\n", to_latex(&sub.content)));
+            }
+            output.push_str("\\end{enumerate}\n");
+        }
+        output.push_str("\\vspace{2.5cm}\n");
+    }
+    output.push_str("\\end{enumerate}\n");
+
+    if include_answer_key {
+        output.push_str("\n\\newpage\n\\begin{center}\\Large\\textbf{Ответы}\\end{center}\n");
+        output.push_str("\\begin{enumerate}[label=\\textbf{\\arabic*.}]\n");
+        for (problem, solution) in problems {
+            let answer = solution
+                .as_deref()
+                .map(|s| to_latex(answer_excerpt(s)))
+                .unwrap_or_else(|| "---".to_string());
+            output.push_str(&format!("\\item (№{}) {}\n", escape_latex_text(&problem.number), answer));
+        }
+        output.push_str("\\end{enumerate}\n");
+    }
+
+    output.push_str("\\end{document}\n");
+    output
+}
+
+/// Final answer line of a solution ("Ответ: ..."), or the whole text if there is none
+fn answer_excerpt(solution: &str) -> &str {
+    solution
+        .lines()
+        .rev()
+        .map(|l| l.trim().trim_matches('*').trim())
+        .find(|l| {
+            let lower = l.to_lowercase();
+            lower.starts_with("ответ") || lower.starts_with("answer")
+        })
+        .unwrap_or(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answer_key_on_separate_page() {
+        let problem = Problem {
+            number: "3".to_string(),
+            content: "Решите $$x^2=9$$".to_string(),
+            ..Default::default()
+        };
+        let tex = render_latex("Контрольная", "", &[(problem, Some("x = ±3\n**Ответ: $x = \\pm 3$**".to_string()))], true, None);
+
+        let key_start = tex.find("\\newpage").expect("answer key page break");
+        assert!(tex[..key_start].contains(r"\[x^2=9\]"));
+        assert!(tex[key_start..].contains(r"Ответ: $x = \pm 3$"));
+    }
+
+    #[test]
+    fn escapes_prose_in_problem_text() {
+        let problem = Problem {
+            number: "1".to_string(),
+            content: r"a_1 50% & \input{x} при $a_1 > 0$".to_string(),
+            ..Default::default()
+        };
+        let tex = render_latex("Тест", "", &[(problem, None)], false, None);
+        assert!(tex.contains(r"a\_1 50\% \& \textbackslash{}input\{x\} при $a_1 > 0$"), "{}", tex);
+        assert!(!tex.contains(r"\input{x}"));
+    }
+
+    #[test]
+    fn no_answer_key_when_disabled() {
+        let tex = render_latex("Тест", &BookMacros::default().latex_preamble(), &[], false, None);
+        assert!(!tex.contains("Ответы"));
+        assert!(tex.find(r"\DeclareRobustCommand{\tg}").unwrap() < tex.find(r"\begin{document}").unwrap());
+    }
+}
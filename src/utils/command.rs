@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Stderr characters kept in the debug log
+const LOGGED_STDERR_CHARS: usize = 500;
+
+/// How often a running command is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static DEFAULT_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(120);
+
+lazy_static::lazy_static! {
+    static ref STATS: Mutex<HashMap<String, CommandStats>> = Mutex::new(HashMap::new());
+    static ref PROGRAM_PATHS: Mutex<HashMap<String, PathBuf>> = Mutex::new(HashMap::new());
+}
+
+/// Invocation counters of one external binary, exposed by `GET /api/metrics`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandStats {
+    pub calls: u64,
+    /// Non-zero exits, spawn errors and timeouts
+    pub failures: u64,
+    pub timeouts: u64,
+    pub total_ms: u64,
+}
+
+/// Counters per binary, keyed by program name
+pub fn command_stats() -> HashMap<String, CommandStats> {
+    STATS.lock().map(|s| s.clone()).unwrap_or_default()
+}
+
+/// Timeout for commands that don't set their own (`COMMAND_TIMEOUT_SECS`)
+pub fn set_default_timeout(timeout: Duration) {
+    DEFAULT_TIMEOUT_SECS.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+/// Run `program` from `path` instead of looking it up on PATH, e.g. a
+/// poppler install outside PATH on Windows (`PDFTOPPM_PATH`, ...)
+pub fn set_program_path(program: &str, path: PathBuf) {
+    if let Ok(mut paths) = PROGRAM_PATHS.lock() {
+        paths.insert(program.to_string(), path);
+    }
+}
+
+fn program_path(program: &str) -> PathBuf {
+    PROGRAM_PATHS
+        .lock()
+        .ok()
+        .and_then(|paths| paths.get(program).cloned())
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+fn record(program: &str, elapsed: Duration, failed: bool, timed_out: bool) {
+    if let Ok(mut stats) = STATS.lock() {
+        let entry = stats.entry(program.to_string()).or_default();
+        entry.calls += 1;
+        entry.failures += u64::from(failed);
+        entry.timeouts += u64::from(timed_out);
+        entry.total_ms += elapsed.as_millis() as u64;
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+/// Runs an external binary with a timeout, logging the invocation at debug
+/// level and counting failures per binary. A command that outlives its
+/// timeout is killed and reported as `io::ErrorKind::TimedOut`.
+pub struct CommandRunner {
+    program: String,
+    args: Vec<OsString>,
+    current_dir: Option<PathBuf>,
+    envs: Vec<(OsString, OsString)>,
+    timeout: Option<Duration>,
+}
+
+impl CommandRunner {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            current_dir: None,
+            envs: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|a| a.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.envs.push((key.as_ref().to_os_string(), value.as_ref().to_os_string()));
+        self
+    }
+
+    /// Override the default timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run to completion (blocking) and collect stdout/stderr
+    pub fn output(self) -> io::Result<Output> {
+        let timeout = self
+            .timeout
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_TIMEOUT_SECS.load(Ordering::Relaxed)));
+        let started = Instant::now();
+
+        let result = self.run(timeout);
+        let elapsed = started.elapsed();
+        let args: Vec<String> = self.args.iter().map(|a| a.to_string_lossy().into_owned()).collect();
+
+        match &result {
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                log::debug!(
+                    "{} {:?} exited with {} in {:?}; stderr: {}",
+                    self.program,
+                    args,
+                    output.status,
+                    elapsed,
+                    truncate(stderr.trim(), LOGGED_STDERR_CHARS)
+                );
+                record(&self.program, elapsed, !output.status.success(), false);
+            }
+            Err(e) => {
+                log::debug!("{} {:?} failed after {:?}: {}", self.program, args, elapsed, e);
+                record(&self.program, elapsed, true, e.kind() == io::ErrorKind::TimedOut);
+            }
+        }
+        result
+    }
+
+    fn run(&self, timeout: Duration) -> io::Result<Output> {
+        let mut command = Command::new(program_path(&self.program));
+        command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+
+        let mut child = command.spawn()?;
+
+        // Drain the pipes on threads so a chatty command can't block on a full pipe
+        let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                buf
+            })
+        };
+        let stdout = read_pipe(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+        let stderr = read_pipe(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} timed out after {:?}", self.program, timeout),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_output_and_counts_failures() {
+        let output = CommandRunner::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "out\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "err\n");
+        assert_eq!(output.status.code(), Some(3));
+        assert!(command_stats()["sh"].failures >= 1);
+    }
+
+    #[test]
+    fn runs_configured_program_path() {
+        set_program_path("booker-test-echo", PathBuf::from("echo"));
+        let output = CommandRunner::new("booker-test-echo").arg("hi").output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+        assert_eq!(command_stats()["booker-test-echo"].calls, 1);
+    }
+
+    #[test]
+    fn kills_command_after_timeout() {
+        let started = Instant::now();
+        let err = CommandRunner::new("sleep").arg("5").timeout(Duration::from_millis(100)).output().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(command_stats()["sleep"].timeouts >= 1);
+    }
+}
@@ -1,3 +1,9 @@
+pub mod command;
+pub mod page_range;
+pub mod slug;
+
+pub use command::CommandRunner;
+
 use base64::{engine::general_purpose, Engine as _};
 use std::fs;
 
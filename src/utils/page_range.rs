@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+
+/// Pages one selection may name. Ranges are checked against it before they
+/// are expanded, since `1-4000000000` is valid when the page count is unknown.
+pub const MAX_SELECTED_PAGES: u32 = 10_000;
+
+/// Parse a page selection such as `1-10,15,20-e,odd` into sorted page numbers.
+///
+/// Parts are separated by commas:
+/// - `N`: a single page; `e` is the last page
+/// - `A-B`: an inclusive range, `B` may be `e`
+/// - `odd` / `even`: every odd/even page of the document
+/// - `A-B/odd`, `A-B/even`: only the odd/even pages of a range
+///
+/// `total_pages` of 0 means the page count is unknown; `e`, `odd` and `even`
+/// need it and are rejected then. Selections over [`MAX_SELECTED_PAGES`]
+/// pages are rejected.
+pub fn parse_page_ranges(spec: &str, total_pages: u32) -> Result<BTreeSet<u32>, String> {
+    let mut pages = BTreeSet::new();
+    let mut selected: u32 = 0;
+
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (range, parity) = match part.split_once('/') {
+            Some((range, parity)) => (range.trim(), Some(parse_parity(parity.trim())?)),
+            None => match parse_parity(part) {
+                Ok(parity) => ("1-e", Some(parity)),
+                Err(_) => (part, None),
+            },
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_page(start, total_pages)?, parse_page(end, total_pages)?),
+            None => {
+                let page = parse_page(range, total_pages)?;
+                (page, page)
+            }
+        };
+        if start > end {
+            return Err(format!("Invalid page range '{}': start is after end", part));
+        }
+        if total_pages > 0 && end > total_pages {
+            return Err(format!("Page {} is past the last page ({})", end, total_pages));
+        }
+        selected = selected.saturating_add(end - start + 1);
+        if selected > MAX_SELECTED_PAGES {
+            return Err(format!("More than {} pages selected", MAX_SELECTED_PAGES));
+        }
+
+        pages.extend((start..=end).filter(|p| parity.is_none_or(|odd| (p % 2 == 1) == odd)));
+    }
+
+    if pages.is_empty() {
+        return Err(format!("No pages selected by '{}'", spec));
+    }
+    Ok(pages)
+}
+
+/// `true` for odd pages, `false` for even ones
+fn parse_parity(text: &str) -> Result<bool, String> {
+    match text {
+        "odd" => Ok(true),
+        "even" => Ok(false),
+        _ => Err(format!("Unknown page filter '{}': expected odd or even", text)),
+    }
+}
+
+fn parse_page(text: &str, total_pages: u32) -> Result<u32, String> {
+    match text.trim() {
+        "e" if total_pages > 0 => Ok(total_pages),
+        "e" => Err("The last page ('e') is unknown for this document".to_string()),
+        number => match number.parse::<u32>() {
+            Ok(0) => Err("Page numbers start at 1".to_string()),
+            Ok(page) => Ok(page),
+            Err(_) => Err(format!("Invalid page number '{}'", number)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(spec: &str, total: u32) -> Vec<u32> {
+        parse_page_ranges(spec, total).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn parses_ranges_singles_and_parity() {
+        assert_eq!(pages("1-3, 5,8-e", 9), vec![1, 2, 3, 5, 8, 9]);
+        assert_eq!(pages("even", 7), vec![2, 4, 6]);
+        assert_eq!(pages("odd,4", 5), vec![1, 3, 4, 5]);
+        assert_eq!(pages("10-e/odd", 14), vec![11, 13]);
+        assert_eq!(pages("2-4", 0), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_selections() {
+        for (spec, total) in [("5-3", 10), ("0", 10), ("1-e", 0), ("odd", 0), ("12", 10), ("1-x", 10), ("1-4/third", 10), ("", 10), ("1-4000000000", 0)] {
+            assert!(parse_page_ranges(spec, total).is_err(), "{:?} was accepted", spec);
+        }
+    }
+}
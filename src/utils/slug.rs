@@ -0,0 +1,161 @@
+use sha2::{Digest, Sha256};
+
+/// Hex characters of the name hash appended to transliterated slugs
+const HASH_CHARS: usize = 8;
+
+/// Extensions of the book files in the resources directory, preferred first
+pub const BOOK_EXTENSIONS: &[&str] = &[".pdf", ".epub"];
+
+/// Latin spelling of a Cyrillic letter (lowercase, simplified GOST 7.79-2000 B)
+fn cyrillic_to_latin(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' => "g",
+        'д' => "d",
+        'е' => "e",
+        'ё' => "yo",
+        'ж' => "zh",
+        'з' => "z",
+        'и' => "i",
+        'й' => "j",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' => "u",
+        'ф' => "f",
+        'х' => "h",
+        'ц' => "c",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shh",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'э' => "e",
+        'ю' => "yu",
+        'я' => "ya",
+        'і' => "i",
+        'ї' => "yi",
+        'є' => "ye",
+        'ґ' => "g",
+        _ => return None,
+    })
+}
+
+/// Lowercase ASCII rendering of `text`; Cyrillic is transliterated and any
+/// other non-ASCII character is dropped
+pub fn transliterate(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii() {
+            output.push(c);
+        } else if let Some(latin) = cyrillic_to_latin(c) {
+            output.push_str(latin);
+        }
+    }
+    output
+}
+
+/// Names made only of ASCII letters, digits, `-`, `_` and inner dots are
+/// used as they are
+fn is_slug(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Stable, URL- and filesystem-safe id for a book named `name` (usually the
+/// PDF file stem). ASCII names are kept unchanged so existing ids stay
+/// valid; anything else is transliterated and gets a short hash of the
+/// original name, so "Алгебра 7" and "Алгебра-7" don't collide.
+/// Slugs map to themselves.
+pub fn book_slug(name: &str) -> String {
+    if is_slug(name) {
+        return name.to_string();
+    }
+
+    let mut slug = String::new();
+    for c in transliterate(name).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+    let hash = &hash[..HASH_CHARS];
+    if slug.is_empty() {
+        format!("book-{}", hash)
+    } else {
+        format!("{}-{}", slug, hash)
+    }
+}
+
+/// Id of the book named by `reference`, which is either the id itself or
+/// the book's file name: `algebra-7` and `algebra-7.pdf` both give `algebra-7`
+pub fn book_id_of(reference: &str) -> String {
+    let name = reference.trim();
+    let stem = BOOK_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext).filter(|stem| !stem.is_empty()))
+        .unwrap_or(name);
+    book_slug(stem)
+}
+
+/// Key of a resources file (relative path with extension, `/` or `\`
+/// separated) in artifact names such as `{key}_{page}.png`. The stem is
+/// slugged and the extension kept, so `Алгебра 7.pdf` and
+/// `{book_slug("Алгебра 7")}.pdf` share their previews.
+pub fn artifact_key(file: &str) -> String {
+    let flat = file.replace(['/', '\\'], "_");
+    match flat.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            format!("{}.{}", book_slug(stem), ext)
+        }
+        _ => book_slug(&flat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ascii_names_and_slugs_unicode_ones() {
+        assert_eq!(book_slug("algebra_7.v2"), "algebra_7.v2");
+        let slug = book_slug("Алгебра 7 класс (Мордкович)");
+        assert!(slug.starts_with("algebra-7-klass-mordkovich-"), "{}", slug);
+        assert_eq!(slug.len(), "algebra-7-klass-mordkovich-".len() + HASH_CHARS);
+        assert_eq!(book_slug(&slug), slug);
+        assert_ne!(book_slug("Алгебра 7"), book_slug("Алгебра-7"));
+        assert!(book_slug("代数").starts_with("book-"));
+    }
+
+    #[test]
+    fn artifact_keys_match_for_file_and_slug() {
+        assert_eq!(artifact_key("algebra.pdf"), "algebra.pdf");
+        assert_eq!(artifact_key("sub/algebra.pdf"), "sub_algebra.pdf");
+        assert_eq!(artifact_key(r"sub\algebra.pdf"), "sub_algebra.pdf");
+        let key = artifact_key("Геометрия 9.pdf");
+        assert!(key.starts_with("geometriya-9-") && key.ends_with(".pdf"), "{}", key);
+        assert_eq!(artifact_key(&format!("{}.pdf", book_slug("Геометрия 9"))), key);
+    }
+
+    #[test]
+    fn book_ids_from_ids_and_file_names() {
+        assert_eq!(book_id_of("algebra-7"), "algebra-7");
+        assert_eq!(book_id_of("algebra-7.pdf"), "algebra-7");
+        assert_eq!(book_id_of("algebra-7.epub"), "algebra-7");
+        assert_eq!(book_id_of("Алгебра 7.pdf"), book_slug("Алгебра 7"));
+        assert_eq!(book_id_of(&book_slug("Алгебра 7")), book_slug("Алгебра 7"));
+    }
+}